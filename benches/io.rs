@@ -51,6 +51,7 @@ fn bench_sign(c: &mut Criterion, name: &str, fixture: &str) {
             use std::io::Write;
 
             let settings_str = std::fs::read_to_string(fixture_path("test_settings.json")).unwrap();
+            #[allow(deprecated)]
             let settings = Settings::from_string(&settings_str, "json").unwrap();
 
             let context = c2pa::Context::new()
@@ -74,19 +75,20 @@ fn bench_sign(c: &mut Criterion, name: &str, fixture: &str) {
             let native_format = asset.media_type().to_mime();
             let is_bmff = matches!(asset.structure().container, asset_io::ContainerKind::Bmff);
 
+            #[allow(deprecated)]
             let signer = Settings::signer().unwrap();
 
-            if builder.needs_placeholder(&native_format) {
+            if builder.needs_placeholder(native_format) {
                 let (structure, mut output_file, signed_jumbf) = if is_bmff {
                     let mut placeholder_bmff = BmffHash::new("jumbf manifest", "sha256", None);
                     placeholder_bmff.set_default_exclusions();
                     placeholder_bmff.add_place_holder_hash().unwrap();
                     builder
-                        .add_assertion(&format!("{}.v3", BmffHash::LABEL), &placeholder_bmff)
+                        .add_assertion(format!("{}.v3", BmffHash::LABEL), &placeholder_bmff)
                         .unwrap();
 
                     let mut placeholder_jumbf = builder.placeholder("application/c2pa").unwrap();
-                    placeholder_jumbf.extend(std::iter::repeat(0u8).take(256 * 1024));
+                    placeholder_jumbf.extend(std::iter::repeat_n(0u8, 256 * 1024));
 
                     let updates = Updates::new()
                         .set_jumbf(placeholder_jumbf.clone())
@@ -120,7 +122,7 @@ fn bench_sign(c: &mut Criterion, name: &str, fixture: &str) {
                     output_file.flush().unwrap();
                     output_file.seek(std::io::SeekFrom::Start(0)).unwrap();
                     builder
-                        .update_hash_from_stream(&native_format, &mut output_file)
+                        .update_hash_from_stream(native_format, &mut output_file)
                         .unwrap();
                     let signed_jumbf = builder.sign_embeddable("application/c2pa").unwrap();
                     (structure, output_file, signed_jumbf)
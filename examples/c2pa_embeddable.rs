@@ -55,10 +55,10 @@ fn sign_bmff<R: Read + Seek, W: Read + Write + Seek>(
     let mut placeholder = BmffHash::new("jumbf manifest", HASH_ALG, None);
     placeholder.set_default_exclusions();
     placeholder.add_place_holder_hash()?;
-    builder.add_assertion(&format!("{}.v3", BmffHash::LABEL), &placeholder)?;
+    builder.add_assertion(format!("{}.v3", BmffHash::LABEL), &placeholder)?;
 
     let mut placeholder_jumbf = builder.placeholder("application/c2pa")?;
-    placeholder_jumbf.extend(std::iter::repeat(0u8).take(BMFF_PLACEHOLDER_PADDING));
+    placeholder_jumbf.extend(std::iter::repeat_n(0u8, BMFF_PLACEHOLDER_PADDING));
 
     let updates = Updates::new()
         .set_jumbf(placeholder_jumbf)
@@ -128,7 +128,7 @@ fn sign_bmff<R: Read + Seek, W: Read + Write + Seek>(
         .definition
         .assertions
         .retain(|a| !a.label.contains(BmffHash::LABEL));
-    builder.add_assertion(&format!("{}.v3", BmffHash::LABEL), &bmff_hash)?;
+    builder.add_assertion(format!("{}.v3", BmffHash::LABEL), &bmff_hash)?;
 
     let signed_jumbf = builder.sign_embeddable("application/c2pa")?;
     Ok((structure, signed_jumbf))
@@ -186,7 +186,7 @@ fn sign_box_hash<R: Read + Seek, W: Read + Write + Seek>(
 ) -> Result<SignResult, Error> {
     builder.add_assertion(BoxHash::LABEL, &BoxHash { boxes: Vec::new() })?;
     let mut placeholder_jumbf = builder.placeholder("application/c2pa")?;
-    placeholder_jumbf.extend(std::iter::repeat(0u8).take(BOX_HASH_PADDING));
+    placeholder_jumbf.extend(std::iter::repeat_n(0u8, BOX_HASH_PADDING));
 
     let updates = Updates::new()
         .set_jumbf(placeholder_jumbf)
@@ -327,7 +327,7 @@ fn main() -> Result<(), Error> {
 
     let t = Instant::now();
 
-    let (structure, signed_jumbf) = if builder.needs_placeholder(&native_format) {
+    let (structure, signed_jumbf) = if builder.needs_placeholder(native_format) {
         if is_bmff {
             sign_bmff(&mut asset, &mut builder, &mut output)?
         } else {
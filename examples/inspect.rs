@@ -29,65 +29,52 @@ fn main() -> asset_io::Result<()> {
     println!("  Segments: {}", asset.structure().segments.len());
 
     // Check for XMP (loads lazily only if present)
-    match asset.xmp()? {
-        Some(xmp) => {
-            println!("\n✓ Found XMP metadata ({} bytes)", xmp.len());
-            // Print first 100 chars
-            let preview =
-                std::str::from_utf8(&xmp[..xmp.len().min(100)]).unwrap_or("<binary data>");
-            println!("  Preview: {}...", preview);
-        }
-        None => {}
+    if let Some(xmp) = asset.xmp()? {
+        println!("\n✓ Found XMP metadata ({} bytes)", xmp.len());
+        // Print first 100 chars
+        let preview = std::str::from_utf8(&xmp[..xmp.len().min(100)]).unwrap_or("<binary data>");
+        println!("  Preview: {}...", preview);
     }
 
     // Check for JUMBF (loads and assembles only if present)
-    match asset.jumbf()? {
-        Some(jumbf) => {
-            println!("\n✓ Found JUMBF data ({} bytes)", jumbf.len());
-        }
-        None => {}
+    if let Some(jumbf) = asset.jumbf()? {
+        println!("\n✓ Found JUMBF data ({} bytes)", jumbf.len());
     }
 
     // Check for EXIF metadata
-    match asset.exif_info()? {
-        Some(info) => {
-            println!("\n✓ Found EXIF metadata");
-            println!("  Preview: {}", info);
-            // Show individual fields if present
-            if let Some(ref make) = info.make {
-                println!("  Make: {}", make);
-            }
-            if let Some(ref model) = info.model {
-                println!("  Model: {}", model);
-            }
-            if let Some(ref dt) = info.date_time_original.or(info.date_time) {
-                println!("  DateTime: {}", dt);
-            }
-            if let Some(ref software) = info.software {
-                println!("  Software: {}", software);
-            }
-            if let Some(orientation) = info.orientation {
-                println!("  Orientation: {}", orientation);
-            }
+    if let Some(info) = asset.exif_info()? {
+        println!("\n✓ Found EXIF metadata");
+        println!("  Preview: {}", info);
+        // Show individual fields if present
+        if let Some(ref make) = info.make {
+            println!("  Make: {}", make);
+        }
+        if let Some(ref model) = info.model {
+            println!("  Model: {}", model);
+        }
+        if let Some(ref dt) = info.date_time_original.or(info.date_time) {
+            println!("  DateTime: {}", dt);
+        }
+        if let Some(ref software) = info.software {
+            println!("  Software: {}", software);
+        }
+        if let Some(orientation) = info.orientation {
+            println!("  Orientation: {}", orientation);
         }
-        None => {}
     }
 
     // Check for embedded thumbnail (from EXIF)
-    match asset.read_embedded_thumbnail()? {
-        Some(thumb) => {
-            let dims = match (thumb.width, thumb.height) {
-                (Some(w), Some(h)) => format!("{}x{}", w, h),
-                _ => "unknown dimensions".to_string(),
-            };
-            println!(
-                "\n✓ Found embedded thumbnail ({:?}, {}, {} bytes)",
-                thumb.format,
-                dims,
-                thumb.data.len()
-            );
-        }
-        None => {}
+    if let Some(thumb) = asset.read_embedded_thumbnail()? {
+        let dims = match (thumb.width, thumb.height) {
+            (Some(w), Some(h)) => format!("{}x{}", w, h),
+            _ => "unknown dimensions".to_string(),
+        };
+        println!(
+            "\n✓ Found embedded thumbnail ({:?}, {}, {} bytes)",
+            thumb.format,
+            dims,
+            thumb.data.len()
+        );
     }
 
     // Show segment breakdown
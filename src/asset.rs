@@ -6,11 +6,12 @@
 use crate::{
     containers::ContainerKind,
     detect_container,
-    error::Result,
+    error::{Error, Result},
     get_handler,
     processing_writer::{ProcessChunkFn, ReadChunkFn},
+    segment::DEFAULT_CHUNK_SIZE,
     structure::Structure,
-    Updates,
+    Limits, Updates,
 };
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom, Write};
@@ -45,6 +46,8 @@ pub struct Asset<R: Read + Seek> {
     source: R,
     structure: Structure,
     handler: Handler,
+    /// Processing chunk size used when [`Updates`] does not set one
+    pub(crate) chunk_size: usize,
 }
 
 // Handler enum and implementation are generated by register_formats! macro in lib.rs
@@ -59,19 +62,8 @@ impl Asset<File> {
     }
 
     /// Create an Asset from an owned File
-    pub fn from_file(mut file: File) -> Result<Self> {
-        file.seek(SeekFrom::Start(0))?;
-        let container = detect_container(&mut file)?;
-        file.seek(SeekFrom::Start(0))?;
-
-        let handler = get_handler(container)?;
-        let structure = handler.parse(&mut file)?;
-
-        Ok(Asset {
-            source: file,
-            structure,
-            handler,
-        })
+    pub fn from_file(file: File) -> Result<Self> {
+        AssetBuilder::new().from_source(file)
     }
 
     /// Open a media file with memory mapping for zero-copy operations
@@ -124,45 +116,7 @@ impl Asset<File> {
     /// ```
     #[cfg(feature = "memory-mapped")]
     pub unsafe fn open_with_mmap<P: AsRef<Path>>(path: P) -> Result<Self> {
-        #[allow(unused_imports)] // FileExt trait is used for lock_shared() method
-        use fs2::FileExt;
-
-        let path = path.as_ref();
-
-        // Open read-only (best we can do for safety)
-        let file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(false)
-            .open(path)?;
-
-        // Get initial file metadata
-        let metadata = file.metadata()?;
-        let initial_size = metadata.len();
-
-        // Acquire advisory shared lock (allows multiple concurrent readers)
-        // This prevents cooperative writers but doesn't guarantee safety
-        file.lock_shared()?;
-
-        // Verify file size hasn't changed (basic race condition check)
-        let current_size = file.metadata()?.len();
-        if current_size != initial_size {
-            file.unlock()?;
-            return Err(crate::Error::InvalidFormat(
-                "File size changed during open (file may be unstable)".into(),
-            ));
-        }
-
-        // Create memory map (unsafe operation)
-        let mmap = memmap2::Mmap::map(&file)?;
-
-        // Create asset from file
-        // Note: Advisory lock remains held as long as file handle exists
-        let mut asset = Self::from_file(file)?;
-
-        // Attach memory map for zero-copy access
-        asset.structure.set_mmap(mmap);
-
-        Ok(asset)
+        AssetBuilder::new().memory_mapped(true).open(path)
     }
 }
 
@@ -187,19 +141,8 @@ impl<R: Read + Seek> Asset<R> {
     /// # Ok(())
     /// # }
     /// ```
-    pub fn from_source(mut source: R) -> Result<Self> {
-        source.seek(SeekFrom::Start(0))?;
-        let container = detect_container(&mut source)?;
-        source.seek(SeekFrom::Start(0))?;
-
-        let handler = get_handler(container)?;
-        let structure = handler.parse(&mut source)?;
-
-        Ok(Asset {
-            source,
-            structure,
-            handler,
-        })
+    pub fn from_source(source: R) -> Result<Self> {
+        AssetBuilder::new().from_source(source)
     }
 
     /// Get the detected format
@@ -213,15 +156,98 @@ impl<R: Read + Seek> Asset<R> {
     }
 
     /// Get XMP metadata (loads lazily, assembles extended parts if present)
+    ///
+    /// Returns `None` for an XMP packet over the size limit, which a lenient
+    /// parse accepts (see [`AssetBuilder::strict`]).
     pub fn xmp(&mut self) -> Result<Option<Vec<u8>>> {
+        if self
+            .handler
+            .limits()
+            .oversized_xmp(&self.structure)
+            .is_some()
+        {
+            return Ok(None);
+        }
         self.handler.read_xmp(&self.structure, &mut self.source)
     }
 
-    /// Get JUMBF data (loads and assembles lazily)
+    /// Get the C2PA manifest store JUMBF data (loads and assembles lazily)
+    ///
+    /// Other JUMBF boxes are available through [`jumbf_boxes`](Self::jumbf_boxes)
+    /// and [`jumbf_box`](Self::jumbf_box).
     pub fn jumbf(&mut self) -> Result<Option<Vec<u8>>> {
         self.handler.read_jumbf(&self.structure, &mut self.source)
     }
 
+    /// Parse the C2PA manifest store into a JUMBF box tree
+    ///
+    /// Returns `None` if the asset has no JUMBF data. Fails if the data is not
+    /// a well-formed JUMBF superbox (a placeholder written before signing, for
+    /// example).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::Asset;
+    ///
+    /// # fn main() -> asset_io::Result<()> {
+    /// let mut asset = Asset::open("signed.jpg")?;
+    /// if let Some(store) = asset.manifest_store()? {
+    ///     println!("{} manifests", store.manifests().count());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn manifest_store(&mut self) -> Result<Option<crate::JumbfSuperBox>> {
+        let max_depth = self.handler.limits().max_box_depth;
+        self.jumbf()?
+            .map(|data| crate::JumbfSuperBox::parse_with_depth(&data, max_depth))
+            .transpose()
+    }
+
+    /// Get the label of the active C2PA manifest (the last one in the store)
+    ///
+    /// The label identifies the manifest, such as `urn:c2pa:...` or a bare UUID.
+    pub fn active_manifest_label(&mut self) -> Result<Option<String>> {
+        Ok(self.manifest_store()?.and_then(|store| {
+            store
+                .active_manifest()
+                .and_then(|m| m.label().map(str::to_owned))
+        }))
+    }
+
+    /// List every JUMBF box with its label and content type
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::Asset;
+    ///
+    /// # fn main() -> asset_io::Result<()> {
+    /// let asset = Asset::open("image.jpg")?;
+    /// for jumbf in asset.jumbf_boxes() {
+    ///     println!("{:?} (C2PA: {})", jumbf.label, jumbf.c2pa);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn jumbf_boxes(&self) -> Vec<crate::JumbfBox> {
+        self.structure.jumbf_boxes()
+    }
+
+    /// Get the data of the JUMBF box with the given description label
+    ///
+    /// The label `"c2pa"` selects the C2PA manifest store, like [`jumbf`](Self::jumbf).
+    pub fn jumbf_box(&mut self, label: &str) -> Result<Option<Vec<u8>>> {
+        match self.structure.jumbf_index_by_label(label) {
+            Some(index) => self
+                .handler
+                .read_jumbf_box(&self.structure, &mut self.source, index)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
     /// Extract an embedded thumbnail if available
     ///
     /// Many image formats include pre-rendered thumbnails for quick preview:
@@ -314,11 +340,143 @@ impl<R: Read + Seek> Asset<R> {
             .read_exif_info(&self.structure, &mut self.source)
     }
 
+    /// Get technical media properties (dimensions, duration, codecs, audio format)
+    ///
+    /// Properties are read from the container's own headers; fields the format
+    /// does not record are `None`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use asset_io::Asset;
+    ///
+    /// let mut asset = Asset::open("photo.jpg")?;
+    /// let info = asset.media_info()?;
+    /// println!("{:?}x{:?}", info.width, info.height);
+    /// # Ok::<(), asset_io::Error>(())
+    /// ```
+    pub fn media_info(&mut self) -> Result<crate::MediaInfo> {
+        self.handler
+            .read_media_info(&self.structure, &mut self.source)
+    }
+
+    /// Get the capture time, normalized across metadata sources
+    ///
+    /// Sources are checked in a fixed order and the first that parses wins:
+    /// EXIF `DateTimeOriginal` (+ `OffsetTimeOriginal`), XMP `photoshop:DateCreated`
+    /// then `xmp:CreateDate`, BMFF `mvhd` creation time, QuickTime
+    /// `com.apple.quicktime.creationdate`, then RIFF `IDIT`/`ICRD`.
+    /// [`CaptureTime::source`](crate::CaptureTime::source) reports which one was used.
+    ///
+    /// Sources whose features are disabled are skipped, as are EXIF blocks that
+    /// cannot be parsed.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use asset_io::Asset;
+    ///
+    /// let mut asset = Asset::open("photo.jpg")?;
+    /// if let Some(time) = asset.capture_time()? {
+    ///     println!("{} via {:?}", time, time.source);
+    /// }
+    /// # Ok::<(), asset_io::Error>(())
+    /// ```
+    pub fn capture_time(&mut self) -> Result<Option<crate::CaptureTime>> {
+        // Unreadable EXIF falls through to the other sources
+        #[cfg(feature = "exif")]
+        if let Ok(Some(exif)) = self.exif_info() {
+            let time = exif.date_time_original.as_deref().and_then(|dt| {
+                crate::CaptureTime::from_exif(dt, exif.offset_time_original.as_deref())
+            });
+            if time.is_some() {
+                return Ok(time);
+            }
+        }
+
+        #[cfg(feature = "xmp")]
+        if let Some(xmp) = self.xmp()?.as_deref().and_then(crate::MiniXmp::from_bytes) {
+            let values = xmp.get_many(&["photoshop:DateCreated", "xmp:CreateDate"]);
+            let sources = [
+                crate::CaptureTimeSource::XmpDateCreated,
+                crate::CaptureTimeSource::XmpCreateDate,
+            ];
+            for (value, source) in values.iter().zip(sources) {
+                let time = value
+                    .as_deref()
+                    .and_then(|v| crate::CaptureTime::from_iso8601(v, source));
+                if time.is_some() {
+                    return Ok(time);
+                }
+            }
+        }
+
+        #[cfg(feature = "bmff")]
+        if self.structure.container == ContainerKind::Bmff {
+            let seconds = crate::containers::bmff_io::read_mvhd_creation_time(
+                &self.structure,
+                &mut self.source,
+            )?;
+            if let Some(time) = seconds.and_then(crate::CaptureTime::from_mp4_seconds) {
+                return Ok(Some(time));
+            }
+            let metadata = self.quicktime_metadata()?;
+            let time = metadata
+                .as_ref()
+                .and_then(|m| m.creation_date())
+                .and_then(|d| {
+                    crate::CaptureTime::from_iso8601(
+                        d,
+                        crate::CaptureTimeSource::QuickTimeCreationDate,
+                    )
+                });
+            if time.is_some() {
+                return Ok(time);
+            }
+        }
+
+        #[cfg(feature = "riff")]
+        if let Handler::Riff(riff) = &self.handler {
+            let dates = riff.read_dates(&self.structure, &mut self.source)?;
+            return Ok(dates.iter().find_map(|(id, value)| match id {
+                b"IDIT" => crate::CaptureTime::from_idit(value),
+                _ => crate::CaptureTime::from_iso8601(value, crate::CaptureTimeSource::RiffIcrd),
+            }));
+        }
+
+        Ok(None)
+    }
+
     /// Get the file structure
     pub fn structure(&self) -> &Structure {
         &self.structure
     }
 
+    /// Problems found while parsing (see [`AssetBuilder::strict`])
+    pub fn diagnostics(&self) -> &[crate::Diagnostic] {
+        self.structure.diagnostics()
+    }
+
+    /// Check the asset against its container specification
+    ///
+    /// Nothing is modified. The report starts with the [`diagnostics`](Self::diagnostics)
+    /// recorded while parsing, followed by container-specific checks:
+    ///
+    /// - JPEG: marker sequence, duplicate XMP, extended XMP GUID and MD5
+    /// - PNG: chunk CRCs and chunk ordering
+    /// - BMFF: `ftyp` position, `stco`/`co64` targets inside `mdat`, `iloc` extents
+    ///   inside the file, duplicate C2PA manifest boxes
+    /// - RIFF: the RIFF size, padding bytes, WebP `VP8X` flags against the chunks present
+    ///
+    /// Fails only if the source cannot be read; conformance problems are errors
+    /// in the report.
+    pub fn validate(&mut self) -> Result<crate::ValidationReport> {
+        let mut report = crate::ValidationReport {
+            diagnostics: self.structure.diagnostics().to_vec(),
+        };
+        self.handler
+            .validate(&self.structure, &mut self.source, &mut report)?;
+        Ok(report)
+    }
+
     /// Get mutable access to the file structure
     ///
     /// This is useful for advanced operations like attaching memory maps
@@ -397,75 +555,11 @@ impl<R: Read + Seek> Asset<R> {
     {
         use crate::segment::DEFAULT_CHUNK_SIZE;
 
-        let chunk_size = updates.processing.effective_chunk_size();
-        let exclude_segments = &updates.processing.exclude_segments;
-        let _exclusion_mode = updates.processing.exclusion_mode;
+        let chunk_size = updates.processing.effective_chunk_size(self.chunk_size);
+        let ranges = processing_ranges(&self.structure, updates);
 
         self.source.seek(SeekFrom::Start(0))?;
 
-        // Build list of segment indices to exclude
-        let excluded_indices: Vec<Option<usize>> = exclude_segments
-            .iter()
-            .filter_map(|kind| {
-                self.structure
-                    .segments
-                    .iter()
-                    .position(|s| s.is_type(*kind))
-            })
-            .map(Some)
-            .collect();
-
-        // Calculate ranges to process (everything except excluded segments)
-        let mut ranges = Vec::new();
-        let mut last_end = 0u64;
-
-        // For BMFF, always exclude ftyp box (per BmffHash spec)
-        #[cfg(feature = "bmff")]
-        if self.structure.container == crate::ContainerKind::Bmff {
-            // ftyp box end is at the start of the first metadata box
-            // For C2PA segments with 2 ranges, ranges[1] has the full UUID box offset
-            let ftyp_end = self
-                .structure
-                .segments
-                .iter()
-                .flat_map(|s| s.ranges.iter())
-                .map(|r| r.offset)
-                .min()
-                .unwrap_or(24); // Default if no segments
-            last_end = ftyp_end;
-        }
-
-        for (idx, segment) in self.structure.segments.iter().enumerate() {
-            let is_excluded = excluded_indices.contains(&Some(idx));
-
-            if is_excluded {
-                // Use format-specific exclusion range (e.g., entire box for BMFF)
-                let (exclude_start, exclude_size) =
-                    if let Some(kind) = exclude_segments.iter().find(|k| segment.is_type(**k)) {
-                        self.structure
-                            .exclusion_range_for_segment(*kind)
-                            .unwrap_or_else(|| {
-                                let loc = segment.location();
-                                (loc.offset, loc.size)
-                            })
-                    } else {
-                        let loc = segment.location();
-                        (loc.offset, loc.size)
-                    };
-
-                // Add range before this excluded segment
-                if exclude_start > last_end {
-                    ranges.push((last_end, exclude_start - last_end));
-                }
-                last_end = exclude_start + exclude_size;
-            }
-        }
-
-        // Add final range after last exclusion
-        if last_end < self.structure.total_size {
-            ranges.push((last_end, self.structure.total_size - last_end));
-        }
-
         // Process each range with overlapped I/O
         // We use double-buffering: read next chunk while processing current
         let buffer_size = chunk_size.min(DEFAULT_CHUNK_SIZE);
@@ -561,7 +655,7 @@ impl<R: Read + Seek> Asset<R> {
 
         let chunk_size = updates
             .processing
-            .effective_chunk_size()
+            .effective_chunk_size(self.chunk_size)
             .max(DEFAULT_CHUNK_SIZE);
         let exclude_segments = &updates.processing.exclude_segments;
         let _exclusion_mode = updates.processing.exclusion_mode;
@@ -570,8 +664,8 @@ impl<R: Read + Seek> Asset<R> {
         let mut ranges = Vec::new();
         let mut last_end = 0u64;
 
-        for segment in self.structure.segments.iter() {
-            let is_excluded = exclude_segments.iter().any(|kind| segment.is_type(*kind));
+        for (index, segment) in self.structure.segments.iter().enumerate() {
+            let is_excluded = self.structure.is_excluded(index, exclude_segments);
 
             if is_excluded {
                 // For BMFF, use the format-specific exclusion range that includes the entire box
@@ -700,7 +794,7 @@ impl<R: Read + Seek> Asset<R> {
 
         let chunk_size = updates
             .processing
-            .effective_chunk_size()
+            .effective_chunk_size(self.chunk_size)
             .max(DEFAULT_CHUNK_SIZE);
         let exclude_segments = &updates.processing.exclude_segments;
 
@@ -835,14 +929,14 @@ impl<R: Read + Seek> Asset<R> {
 
         // Check if mmap is available
         if !self.structure.has_mmap() {
-            return Err(crate::Error::InvalidFormat(
+            return Err(crate::Error::Unsupported(
                 "No memory map available - use open_with_mmap()".into(),
             ));
         }
 
         let chunk_size = updates
             .processing
-            .effective_chunk_size()
+            .effective_chunk_size(self.chunk_size)
             .max(DEFAULT_CHUNK_SIZE);
         let exclude_segments = &updates.processing.exclude_segments;
 
@@ -850,8 +944,8 @@ impl<R: Read + Seek> Asset<R> {
         let mut hash_ranges: Vec<ByteRange> = Vec::new();
         let mut last_end = 0u64;
 
-        for segment in self.structure.segments.iter() {
-            let is_excluded = exclude_segments.iter().any(|kind| segment.is_type(*kind));
+        for (index, segment) in self.structure.segments.iter().enumerate() {
+            let is_excluded = self.structure.is_excluded(index, exclude_segments);
 
             if is_excluded {
                 let loc = segment.location();
@@ -978,7 +1072,7 @@ impl<R: Read + Seek> Asset<R> {
 
         let chunk_size = updates
             .processing
-            .effective_chunk_size()
+            .effective_chunk_size(self.chunk_size)
             .max(DEFAULT_CHUNK_SIZE);
         let exclude_segments = &updates.processing.exclude_segments;
 
@@ -1101,6 +1195,136 @@ impl<R: Read + Seek> Asset<R> {
         Ok(hashes.into_iter().map(|(_, h)| h).collect())
     }
 
+    /// Compute C2PA BmffHash Merkle maps for all mdat boxes in one streaming pass
+    ///
+    /// Each top-level mdat box is split into blocks as `blocks` says, starting 16 bytes
+    /// into the box (the first 16 bytes belong to the main hash), and every block is
+    /// hashed with `H`, which must produce 32-byte digests (e.g. SHA-256). The result
+    /// holds the leaf hashes, block sizes, `count`, `local_id` and root of each mdat,
+    /// plus the hash of the init segment.
+    ///
+    /// [`MerkleBlocks::Chunks`](crate::MerkleBlocks::Chunks) loads the moov box, which
+    /// may be at most [`AssetBuilder::max_segment_size`] bytes.
+    ///
+    /// Returns [`Error::UnsupportedFormat`](crate::Error::UnsupportedFormat) for non-BMFF
+    /// assets. Requires the `bmff` and `parallel` features.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{Asset, MerkleBlocks};
+    /// use sha2::Sha256;
+    ///
+    /// # fn main() -> asset_io::Result<()> {
+    /// let mut asset = Asset::open("video.mp4")?;
+    /// let merkle = asset.bmff_merkle_maps::<Sha256>(MerkleBlocks::Fixed(1024 * 1024))?;
+    /// for map in &merkle.maps {
+    ///     println!("mdat {}: {} leaves", map.local_id, map.count);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(all(feature = "bmff", feature = "parallel"))]
+    pub fn bmff_merkle_maps<H>(
+        &mut self,
+        blocks: crate::MerkleBlocks,
+    ) -> Result<crate::BmffMerkleMaps>
+    where
+        H: sha2::Digest<OutputSize = sha2::digest::consts::U32> + Default,
+    {
+        if self.structure.container != ContainerKind::Bmff {
+            return Err(crate::Error::UnsupportedFormat(None));
+        }
+        crate::containers::bmff_io::bmff_merkle_maps::<R, H>(
+            &mut self.source,
+            blocks,
+            self.handler.limits(),
+        )
+    }
+
+    /// Read the payload of a box from this asset's BMFF box tree
+    ///
+    /// The box must come from [`Structure::bmff_tree`](crate::Structure::bmff_tree)
+    /// of this asset. See [`BmffBox::read_payload`](crate::BmffBox::read_payload);
+    /// the payload may be at most [`AssetBuilder::max_segment_size`] bytes.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::Asset;
+    ///
+    /// # fn main() -> asset_io::Result<()> {
+    /// let mut asset = Asset::open("video.mp4")?;
+    /// let ftyp = asset.structure().bmff_tree().and_then(|t| t.find("/ftyp")).cloned();
+    /// if let Some(ftyp) = ftyp {
+    ///     let payload = asset.read_bmff_payload(&ftyp)?;
+    ///     println!("major brand: {}", String::from_utf8_lossy(&payload[..4]));
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "bmff")]
+    pub fn read_bmff_payload(&mut self, bmff_box: &crate::BmffBox) -> Result<Vec<u8>> {
+        bmff_box.read_payload(&mut self.source, self.structure.max_segment_size())
+    }
+
+    /// Read QuickTime / MP4 descriptive metadata
+    ///
+    /// Collects the user data atoms and iTunes items in `moov/udta` and the `mdta`
+    /// keys in `moov/meta`. Returns `None` for non-BMFF assets and for files
+    /// without a `moov` box. Requires the `bmff` feature.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{Asset, QuickTimeKey};
+    ///
+    /// # fn main() -> asset_io::Result<()> {
+    /// let mut asset = Asset::open("video.mov")?;
+    /// if let Some(metadata) = asset.quicktime_metadata()? {
+    ///     println!("title: {:?}", metadata.text(&QuickTimeKey::TITLE));
+    ///     println!("location: {:?}", metadata.location());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "bmff")]
+    pub fn quicktime_metadata(&mut self) -> Result<Option<crate::QuickTimeMetadata>> {
+        if self.structure.container != ContainerKind::Bmff {
+            return Ok(None);
+        }
+        crate::containers::bmff_io::read_quicktime_metadata(
+            &self.structure,
+            &mut self.source,
+            self.handler.limits(),
+        )
+    }
+
+    /// Read WAV `bext`, `iXML` and `LIST/INFO` metadata
+    ///
+    /// Returns `None` for assets other than WAV. Requires the `riff` feature.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{Asset, WavMetadata};
+    ///
+    /// # fn main() -> asset_io::Result<()> {
+    /// let mut asset = Asset::open("take.wav")?;
+    /// if let Some(metadata) = asset.wav_metadata()? {
+    ///     if let Some(bext) = &metadata.bext {
+    ///         println!("originator: {}", bext.originator);
+    ///     }
+    ///     println!("title: {:?}", metadata.info(&WavMetadata::TITLE));
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "riff")]
+    pub fn wav_metadata(&mut self) -> Result<Option<crate::WavMetadata>> {
+        crate::containers::riff_io::RiffIO::read_wav_metadata(&self.structure)
+    }
+
     /// Write to a writer with updates
     ///
     /// This writes the asset with the specified updates (e.g., new JUMBF, XMP).
@@ -1142,6 +1366,44 @@ impl<R: Read + Seek> Asset<R> {
         Ok(dest_structure)
     }
 
+    /// Write to a sequential writer with updates
+    ///
+    /// Like [`write`](Self::write), but the destination only needs [`Write`], so
+    /// output can go straight into a socket, an HTTP response body or a
+    /// compressor. BMFF files whose `moov` box is too large to rewrite in memory
+    /// need [`write`](Self::write) and fail here with
+    /// [`Error::Unsupported`](crate::Error::Unsupported).
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use asset_io::*;
+    /// # use std::io::Write;
+    /// # fn example() -> Result<()> {
+    /// let mut asset = Asset::open("input.jpg")?;
+    /// let updates = Updates::new().set_jumbf(vec![1, 2, 3]);
+    ///
+    /// let mut stdout = std::io::stdout().lock();
+    /// asset.write_stream(&mut stdout, &updates)?;
+    /// stdout.flush()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_stream<W: Write>(
+        &mut self,
+        writer: &mut W,
+        updates: &Updates,
+    ) -> Result<Structure> {
+        let dest_structure = self
+            .handler
+            .calculate_updated_structure(&self.structure, updates)?;
+
+        self.source.seek(SeekFrom::Start(0))?;
+        self.handler
+            .write_stream(&self.structure, &mut self.source, writer, updates)?;
+
+        Ok(dest_structure)
+    }
+
     /// Write to a writer with updates and optional data processing callback
     ///
     /// This performs a streaming write where data can be processed (e.g., hashed)
@@ -1225,6 +1487,99 @@ impl<R: Read + Seek> Asset<R> {
 
         Ok(dest_structure)
     }
+
+    /// Write with processing and compute BmffHash Merkle maps in the same pass
+    ///
+    /// Behaves like [`write_with_processing`](Self::write_with_processing) (the processor
+    /// still sees every chunk, including [`MdatChunk`](crate::MdatChunk)s), while the mdat
+    /// data is also split into `blocks` and hashed with `H`. Only the init
+    /// segment is read back from `writer` afterwards, so large media data is never
+    /// re-read. The maps match [`bmff_merkle_maps`](Self::bmff_merkle_maps) on the output.
+    ///
+    /// When the JUMBF is not excluded from processing, mdat data is hashed as ordinary
+    /// content, and mdat boxes streamed past the segment size limit are also sent as
+    /// [`MdatChunk`](crate::MdatChunk)s, which plain `write_with_processing` does not do.
+    /// Processors that hash every chunk should skip chunks with an [`id`](crate::ProcessChunk::id).
+    ///
+    /// Requires the `bmff` and `parallel` features.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{Asset, ExclusionMode, MerkleBlocks, SegmentKind, Updates};
+    /// use sha2::{Digest, Sha256};
+    /// use std::fs::OpenOptions;
+    ///
+    /// # fn main() -> asset_io::Result<()> {
+    /// let mut asset = Asset::open("video.mp4")?;
+    /// let mut output = OpenOptions::new()
+    ///     .read(true)
+    ///     .write(true)
+    ///     .create(true)
+    ///     .truncate(true)
+    ///     .open("signed.mp4")?;
+    ///
+    /// let updates = Updates::new()
+    ///     .set_jumbf(vec![0u8; 20000])
+    ///     .exclude_from_processing(vec![SegmentKind::Jumbf], ExclusionMode::DataOnly);
+    ///
+    /// let mut hasher = Sha256::new();
+    /// let (structure, merkle) = asset.write_with_merkle_maps::<_, Sha256, _>(
+    ///     &mut output,
+    ///     &updates,
+    ///     MerkleBlocks::Chunks,
+    ///     &mut |chunk: &dyn asset_io::ProcessChunk| {
+    ///         if chunk.id().is_none() {
+    ///             hasher.update(chunk.data());
+    ///         }
+    ///         Ok(())
+    ///     },
+    /// )?;
+    /// println!("{} merkle maps", merkle.maps.len());
+    /// # let _ = structure;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(all(feature = "bmff", feature = "parallel"))]
+    pub fn write_with_merkle_maps<W, H, F>(
+        &mut self,
+        writer: &mut W,
+        updates: &Updates,
+        blocks: crate::MerkleBlocks,
+        processor: &mut F,
+    ) -> Result<(Structure, crate::BmffMerkleMaps)>
+    where
+        W: Read + Write + Seek,
+        H: sha2::Digest<OutputSize = sha2::digest::consts::U32> + Default,
+        F: ProcessChunkFn,
+    {
+        use crate::containers::bmff_io::{bmff_init_hash, MerkleMapBuilder};
+
+        if self.structure.container != ContainerKind::Bmff {
+            return Err(crate::Error::UnsupportedFormat(None));
+        }
+
+        let mut builder =
+            MerkleMapBuilder::<H>::new(blocks, &mut self.source, self.handler.limits())?;
+        let mut updates = updates.clone();
+        updates.processing.mdat_chunks = true;
+        let mut merkle_processor = |chunk: &dyn crate::ProcessChunk| {
+            if let Some(id) = chunk.id() {
+                builder.update(id, chunk.data());
+            }
+            processor(chunk)
+        };
+        let structure = self.write_with_processing(writer, &updates, &mut merkle_processor)?;
+
+        let init_hash = bmff_init_hash::<W, H>(writer)?;
+        Ok((
+            structure,
+            crate::BmffMerkleMaps {
+                init_hash,
+                maps: builder.finish(),
+            },
+        ))
+    }
 }
 
 // In-place update methods (require Read + Write + Seek)
@@ -1277,8 +1632,8 @@ impl<R: Read + Write + Seek> Asset<R> {
             SegmentKind::Xmp => self.structure.xmp_index(),
             // EXIF not yet fully implemented in Structure
             _ => {
-                return Err(Error::InvalidFormat(format!(
-                    "In-place updates not supported for {:?}",
+                return Err(Error::Unsupported(format!(
+                    "In-place updates of {:?} segments",
                     kind
                 )))
             }
@@ -1292,7 +1647,7 @@ impl<R: Read + Write + Seek> Asset<R> {
 
         // Validate size
         if new_data.len() as u64 > total_capacity {
-            return Err(Error::InvalidFormat(format!(
+            return Err(Error::CapacityExceeded(format!(
                 "New data ({} bytes) exceeds capacity ({} bytes)",
                 new_data.len(),
                 total_capacity
@@ -1378,20 +1733,348 @@ impl Asset<File> {
     }
 }
 
+/// Byte ranges visited by [`read_with_processing`](Asset::read_with_processing)
+///
+/// Everything except the segments excluded by `updates` (and the `ftyp`
+/// box for BMFF), as `(offset, size)` pairs in file order.
+pub(crate) fn processing_ranges(structure: &Structure, updates: &Updates) -> Vec<(u64, u64)> {
+    let exclude_segments = &updates.processing.exclude_segments;
+    // Build list of segment indices to exclude
+    let excluded_indices: Vec<Option<usize>> = exclude_segments
+        .iter()
+        .filter_map(|kind| {
+            (0..structure.segments.len())
+                .find(|&i| structure.is_excluded(i, std::slice::from_ref(kind)))
+        })
+        .map(Some)
+        .collect();
+
+    // Calculate ranges to process (everything except excluded segments)
+    let mut ranges = Vec::new();
+    let mut last_end = 0u64;
+
+    // For BMFF, always exclude ftyp box (per BmffHash spec)
+    #[cfg(feature = "bmff")]
+    if structure.container == crate::ContainerKind::Bmff {
+        // ftyp box end is at the start of the first metadata box
+        // For C2PA segments with 2 ranges, ranges[1] has the full UUID box offset
+        let ftyp_end = structure
+            .segments
+            .iter()
+            .flat_map(|s| s.ranges.iter())
+            .map(|r| r.offset)
+            .min()
+            .unwrap_or(24); // Default if no segments
+        last_end = ftyp_end;
+    }
+
+    for (idx, segment) in structure.segments.iter().enumerate() {
+        let is_excluded = excluded_indices.contains(&Some(idx));
+
+        if is_excluded {
+            // Use format-specific exclusion range (e.g., entire box for BMFF)
+            let (exclude_start, exclude_size) =
+                if let Some(kind) = exclude_segments.iter().find(|k| segment.is_type(**k)) {
+                    structure
+                        .exclusion_range_for_segment(*kind)
+                        .unwrap_or_else(|| {
+                            let loc = segment.location();
+                            (loc.offset, loc.size)
+                        })
+                } else {
+                    let loc = segment.location();
+                    (loc.offset, loc.size)
+                };
+
+            // Add range before this excluded segment
+            if exclude_start > last_end {
+                ranges.push((last_end, exclude_start - last_end));
+            }
+            last_end = exclude_start + exclude_size;
+        }
+    }
+
+    // Add final range after last exclusion
+    if last_end < structure.total_size {
+        ranges.push((last_end, structure.total_size - last_end));
+    }
+
+    ranges
+}
+
 /// Builder for creating assets with custom options
+///
+/// Configures the limits the parser enforces, strictness, memory mapping, the
+/// processing chunk size and, optionally, the container or media type to use
+/// instead of detecting one. [`open`](Self::open) and
+/// [`from_source`](Self::from_source) apply the same options;
+/// [`Asset::open`] and [`Asset::from_source`] use the defaults.
+///
+/// # Example
+///
+/// ```no_run
+/// use asset_io::AssetBuilder;
+///
+/// # fn main() -> asset_io::Result<()> {
+/// // Tight limits for untrusted uploads
+/// let mut asset = AssetBuilder::new()
+///     .max_segment_size(16 * 1024 * 1024)
+///     .max_box_depth(16)
+///     .max_segment_count(10_000)
+///     .max_xmp_size(1024 * 1024)
+///     .open("upload.jpg")?;
+///
+/// if let Some(xmp) = asset.xmp()? {
+///     println!("XMP: {} bytes", xmp.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
 pub struct AssetBuilder {
-    // Future: Add options like memory mapping, buffer sizes, etc.
+    limits: Limits,
+    #[cfg(feature = "memory-mapped")]
+    memory_mapped: bool,
+    chunk_size: usize,
+    container: Option<ContainerKind>,
+    media_type: Option<crate::MediaType>,
 }
 
 impl AssetBuilder {
-    /// Create a new builder
+    /// Create a new builder with the default options
     pub fn new() -> Self {
-        Self {}
+        Self {
+            limits: Limits::default(),
+            #[cfg(feature = "memory-mapped")]
+            memory_mapped: false,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            container: None,
+            media_type: None,
+        }
+    }
+
+    /// Set the largest segment or box read into memory at once (default 256 MB)
+    ///
+    /// Applies to metadata such as JUMBF, EXIF and BMFF `moov` boxes. Media data is
+    /// streamed and not subject to this limit.
+    pub fn max_segment_size(mut self, size: u64) -> Self {
+        self.limits.max_segment_size = size;
+        self
+    }
+
+    /// Set the deepest nesting of BMFF boxes and JUMBF superboxes (default 32)
+    pub fn max_box_depth(mut self, depth: usize) -> Self {
+        self.limits.max_box_depth = depth;
+        self
+    }
+
+    /// Set the most segments an asset may have (default 1,000,000)
+    pub fn max_segment_count(mut self, count: usize) -> Self {
+        self.limits.max_segment_count = count;
+        self
+    }
+
+    /// Set the largest XMP packet, including JPEG extended XMP (default 100 MB)
+    pub fn max_xmp_size(mut self, size: u64) -> Self {
+        self.limits.max_xmp_size = size;
+        self
+    }
+
+    /// Set whether parsing is strict (default `true`)
+    ///
+    /// A strict parse fails on anything it cannot accept. A lenient parse records
+    /// recoverable damage in [`Asset::diagnostics`] and keeps going where it can:
+    ///
+    /// - a JPEG without EOI, or truncated before it, keeps what is there; writes
+    ///   append the EOI
+    /// - a RIFF chunk or PNG chunk running past the end of the file is dropped
+    ///   and writes correct the sizes
+    /// - a BMFF box with an impossible size, e.g. running past its parent, is
+    ///   dropped along with the boxes after it in that parent
+    /// - an XMP packet over [`max_xmp_size`](Self::max_xmp_size) is ignored, so
+    ///   [`Asset::xmp`] returns `None`, and the packet is copied unchanged on write
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{AssetBuilder, Updates};
+    ///
+    /// # fn main() -> asset_io::Result<()> {
+    /// let mut asset = AssetBuilder::new().strict(false).open("upload.jpg")?;
+    /// for diagnostic in asset.diagnostics() {
+    ///     println!("{}", diagnostic);
+    /// }
+    ///
+    /// // Writing produces a repaired file
+    /// let mut output = std::fs::File::create("repaired.jpg")?;
+    /// asset.write(&mut output, &Updates::default())?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.limits.strict = strict;
+        self
+    }
+
+    /// Memory map files opened with [`open`](Self::open) (default `false`)
+    ///
+    /// See [`Asset::open_with_mmap`] for how the file is mapped. Has no effect on
+    /// [`from_source`](Self::from_source).
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified while the asset is alive; see
+    /// [`Asset::open_with_mmap`].
+    #[cfg(feature = "memory-mapped")]
+    pub unsafe fn memory_mapped(mut self, memory_mapped: bool) -> Self {
+        self.memory_mapped = memory_mapped;
+        self
+    }
+
+    /// Set the processing chunk size used when [`Updates`] does not set one (default 64 KB)
+    pub fn chunk_size(mut self, size: usize) -> Self {
+        self.chunk_size = size.max(1);
+        self
+    }
+
+    /// Parse as `container` instead of detecting the container from the header
+    pub fn container(mut self, container: ContainerKind) -> Self {
+        self.container = Some(container);
+        self
+    }
+
+    /// Report `media_type` instead of the one found while parsing
+    ///
+    /// Unless [`container`](Self::container) is also set, the source is parsed as
+    /// the media type's container.
+    pub fn media_type(mut self, media_type: crate::MediaType) -> Self {
+        self.media_type = Some(media_type);
+        self
     }
 
     /// Open an asset with the configured options
     pub fn open<P: AsRef<Path>>(self, path: P) -> Result<Asset<File>> {
-        Asset::open(path)
+        #[cfg(feature = "memory-mapped")]
+        if self.memory_mapped {
+            return self.open_mapped(path.as_ref());
+        }
+        self.from_source(File::open(path)?)
+    }
+
+    /// Create an asset from a source with the configured options
+    ///
+    /// The source will be seeked to position 0 before parsing.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{AssetBuilder, ContainerKind};
+    /// use std::io::Cursor;
+    ///
+    /// # fn main() -> asset_io::Result<()> {
+    /// let data = std::fs::read("image.jpg")?;
+    /// let asset = AssetBuilder::new()
+    ///     .container(ContainerKind::Jpeg)
+    ///     .max_segment_count(1000)
+    ///     .from_source(Cursor::new(data))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_source<R: Read + Seek>(self, mut source: R) -> Result<Asset<R>> {
+        let (structure, handler) = self.parse(&mut source)?;
+        Ok(Asset {
+            source,
+            structure,
+            handler,
+            chunk_size: self.chunk_size,
+        })
+    }
+
+    /// Detect the container and parse the source with these options
+    pub(crate) fn parse<R: Read + Seek>(&self, source: &mut R) -> Result<(Structure, Handler)> {
+        source.seek(SeekFrom::Start(0))?;
+        let container = match (self.container, self.media_type) {
+            (Some(container), Some(media_type)) if media_type.container() != container => {
+                return Err(Error::InvalidFormat(format!(
+                    "{} is not stored in a {:?} container",
+                    media_type, container
+                )));
+            }
+            (Some(container), _) => container,
+            (None, Some(media_type)) => media_type.container(),
+            (None, None) => {
+                let container = detect_container(source)?;
+                source.seek(SeekFrom::Start(0))?;
+                container
+            }
+        };
+
+        let handler = get_handler(container)?.with_limits(self.limits);
+        let mut structure = handler.parse(source)?;
+        if let Some(media_type) = self.media_type {
+            structure.media_type = media_type;
+        }
+        if let Some((offset, size)) = self.limits.oversized_xmp(&structure) {
+            let media_type = Some(structure.media_type);
+            self.limits
+                .recover(
+                    &mut structure,
+                    "XMP",
+                    Error::LimitExceeded {
+                        offset,
+                        reason: format!(
+                            "XMP too large: {} bytes (max {} bytes)",
+                            size, self.limits.max_xmp_size
+                        ),
+                    },
+                )
+                .map_err(|e| e.in_container(container, media_type))?;
+        }
+
+        Ok((structure, handler))
+    }
+
+    /// Open and memory map a file (see [`Asset::open_with_mmap`])
+    #[cfg(feature = "memory-mapped")]
+    fn open_mapped(self, path: &Path) -> Result<Asset<File>> {
+        #[allow(unused_imports)] // FileExt trait is used for lock_shared() method
+        use fs2::FileExt;
+
+        // Open read-only (best we can do for safety)
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(false)
+            .open(path)?;
+
+        // Get initial file metadata
+        let metadata = file.metadata()?;
+        let initial_size = metadata.len();
+
+        // Acquire advisory shared lock (allows multiple concurrent readers)
+        // This prevents cooperative writers but doesn't guarantee safety
+        file.lock_shared()?;
+
+        // Verify file size hasn't changed (basic race condition check)
+        let current_size = file.metadata()?.len();
+        if current_size != initial_size {
+            file.unlock()?;
+            return Err(crate::Error::InvalidFormat(
+                "File size changed during open (file may be unstable)".into(),
+            ));
+        }
+
+        // Create memory map (unsafe operation). Safety: the caller of
+        // `memory_mapped` guarantees the file is not modified while mapped.
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+
+        // Create asset from file
+        // Note: Advisory lock remains held as long as file handle exists
+        let mut asset = self.from_source(file)?;
+
+        // Attach memory map for zero-copy access
+        asset.structure.set_mmap(mmap);
+
+        Ok(asset)
     }
 }
 
@@ -0,0 +1,1387 @@
+//! Async asset handling over tokio `AsyncRead + AsyncSeek` sources
+//!
+//! The container code is synchronous, so [`AsyncAsset`] runs it over the blocks
+//! of the source fetched so far. An operation that needs a block it does not
+//! have stops; the missing blocks are fetched with async I/O and the operation
+//! runs again. The final run reads the same bytes as the sync API, so offsets
+//! and [`Structure`] results are identical, and no run ever waits on I/O.
+//!
+//! Writes run the same way against a recording destination. Media data, which
+//! the writers copy verbatim, is not fetched: reads of it are recorded, and the
+//! recorded plan is then replayed with async I/O, copying media data from the
+//! source straight to the destination and passing the chunks to the processor,
+//! if any, on the way.
+
+#[cfg(feature = "bmff")]
+use crate::detect_container;
+use crate::{
+    asset::processing_ranges,
+    containers::ContainerKind,
+    error::Result,
+    segment::{Segment, SegmentKind, DEFAULT_CHUNK_SIZE},
+    structure::Structure,
+    AssetBuilder, Error, Handler, Limits, ProcessChunk, Updates,
+};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of the blocks fetched from the source
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Most blocks fetched ahead of a miss that continues the previous fetch
+const MAX_READ_AHEAD: u64 = 64;
+
+/// Largest top-level BMFF box fetched before parsing
+#[cfg(feature = "bmff")]
+const MAX_PREFETCH_BOX: u64 = 16 * 1024 * 1024;
+
+/// Smallest read of media data that is recorded rather than fetched
+///
+/// Smaller reads are box or chunk headers, which writers interpret rather than
+/// copy. A read finishing the media range the previous recorded read was
+/// copying is recorded at any size.
+const MIN_RECORDED_READ: usize = 4096;
+
+/// A chunk passed to the processor of an async operation
+///
+/// The owned counterpart of the chunks sync processors receive, with the same
+/// [`ProcessChunk`] context: the mdat index and size flag for BMFF media data
+/// (see [`MdatChunk`](crate::MdatChunk)), or the segment of a boundary marker.
+#[derive(Debug, Clone, Default)]
+pub struct AsyncChunk {
+    data: Vec<u8>,
+    id: Option<usize>,
+    large_size: Option<bool>,
+    segment: Option<Segment>,
+}
+
+impl AsyncChunk {
+    /// Take the chunk data
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// The context of `chunk`, without its data
+    fn context(chunk: &dyn ProcessChunk) -> Self {
+        Self {
+            data: Vec::new(),
+            id: chunk.id(),
+            large_size: chunk.large_size(),
+            segment: chunk.segment().cloned(),
+        }
+    }
+
+    /// This chunk's context with `data`
+    fn with_data(&self, data: Vec<u8>) -> Self {
+        Self {
+            data,
+            id: self.id,
+            large_size: self.large_size,
+            segment: self.segment.clone(),
+        }
+    }
+
+    /// Whether data with the context of `other` can be passed as part of this chunk
+    fn continues(&self, other: &Self) -> bool {
+        self.segment.is_none()
+            && other.segment.is_none()
+            && self.id == other.id
+            && self.large_size == other.large_size
+    }
+}
+
+impl ProcessChunk for AsyncChunk {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn id(&self) -> Option<usize> {
+        self.id
+    }
+
+    fn large_size(&self) -> Option<bool> {
+        self.large_size
+    }
+
+    fn segment(&self) -> Option<&Segment> {
+        self.segment.as_ref()
+    }
+}
+
+/// A media asset backed by an async source
+///
+/// The async counterpart of [`Asset`](crate::Asset). The container code runs
+/// over blocks of the source fetched with async I/O, and runs again after
+/// fetching anything it missed, so no operation blocks the runtime. Writes copy
+/// media data from the source to the destination without holding it in memory.
+/// An operation whose future is dropped leaves the asset usable.
+///
+/// # Example
+///
+/// ```no_run
+/// use asset_io::{AsyncAsset, Updates};
+///
+/// # async fn example() -> asset_io::Result<()> {
+/// let mut asset = AsyncAsset::open("image.jpg").await?;
+///
+/// if let Some(xmp) = asset.xmp().await? {
+///     println!("XMP: {} bytes", xmp.len());
+/// }
+///
+/// let updates = Updates::new().set_xmp(b"<new>metadata</new>".to_vec());
+/// asset.write_to("output.jpg", &updates).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncAsset<R: AsyncRead + AsyncSeek + Unpin> {
+    source: R,
+    blocks: Blocks,
+    structure: Structure,
+    handler: Handler,
+}
+
+impl AsyncAsset<tokio::fs::File> {
+    /// Open a media file from a path
+    ///
+    /// The media type is automatically detected from the file header.
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = tokio::fs::File::open(path).await?;
+        Self::from_source(file).await
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncAsset<R> {
+    /// Create an asset from an async source (media type auto-detected)
+    ///
+    /// The source is parsed exactly as by [`Asset::from_source`](crate::Asset::from_source).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::AsyncAsset;
+    /// use std::io::Cursor;
+    ///
+    /// # async fn example() -> asset_io::Result<()> {
+    /// let jpeg_data = tokio::fs::read("image.jpg").await?;
+    /// let asset = AsyncAsset::from_source(Cursor::new(jpeg_data)).await?;
+    /// println!("{} segments", asset.structure().segments().len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_source(mut source: R) -> Result<Self> {
+        let builder = AssetBuilder::new();
+        let len = source.seek(SeekFrom::End(0)).await?;
+        let mut blocks = Blocks::new(len);
+
+        #[cfg(feature = "bmff")]
+        if matches!(
+            retry(&mut blocks, &mut source, |reader| detect_container(reader)).await?,
+            Ok(ContainerKind::Bmff)
+        ) {
+            blocks.fetch_boxes(&mut source).await?;
+        }
+
+        let (structure, handler) =
+            retry(&mut blocks, &mut source, |reader| builder.parse(reader)).await??;
+        // Writes record media data reads instead of fetching them
+        blocks.forget(&media_ranges(&structure, handler.limits()));
+
+        Ok(Self {
+            source,
+            blocks,
+            structure,
+            handler,
+        })
+    }
+
+    /// Get the detected format
+    pub fn container(&self) -> ContainerKind {
+        self.structure.container
+    }
+
+    /// Get the media type of this asset
+    pub fn media_type(&self) -> crate::MediaType {
+        self.structure.media_type
+    }
+
+    /// Get the structure
+    pub fn structure(&self) -> &Structure {
+        &self.structure
+    }
+
+    /// Get XMP metadata (assembles extended parts if present)
+    ///
+    /// Returns `None` for an XMP packet over the size limit, like
+    /// [`Asset::xmp`](crate::Asset::xmp).
+    pub async fn xmp(&mut self) -> Result<Option<Vec<u8>>> {
+        if self
+            .handler
+            .limits()
+            .oversized_xmp(&self.structure)
+            .is_some()
+        {
+            return Ok(None);
+        }
+        let Self {
+            source,
+            blocks,
+            structure,
+            handler,
+        } = self;
+        retry(blocks, source, |reader| handler.read_xmp(structure, reader)).await?
+    }
+
+    /// Get the C2PA manifest store JUMBF data
+    pub async fn jumbf(&mut self) -> Result<Option<Vec<u8>>> {
+        let Self {
+            source,
+            blocks,
+            structure,
+            handler,
+        } = self;
+        retry(blocks, source, |reader| {
+            handler.read_jumbf(structure, reader)
+        })
+        .await?
+    }
+
+    /// Write to an async destination with updates
+    ///
+    /// Returns the destination structure, identical to the one returned by
+    /// [`Asset::write`](crate::Asset::write), along with the flushed destination.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{AsyncAsset, Updates};
+    /// use std::io::Cursor;
+    ///
+    /// # async fn example() -> asset_io::Result<()> {
+    /// let mut asset = AsyncAsset::open("image.jpg").await?;
+    /// let updates = Updates::new().set_jumbf(vec![1, 2, 3]);
+    ///
+    /// let (structure, output) = asset.write(Cursor::new(Vec::new()), &updates).await?;
+    /// println!("wrote {} bytes", output.into_inner().len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn write<W>(&mut self, writer: W, updates: &Updates) -> Result<(Structure, W)>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin,
+    {
+        let dest_structure = self
+            .handler
+            .calculate_updated_structure(&self.structure, updates)?;
+        let mut dest = Seekable::new(writer).await?;
+        let ops = self
+            .plan(dest.pos, |handler, structure, source, planner| {
+                handler.write(structure, source, &mut PlanSink(planner), updates)
+            })
+            .await?;
+        self.replay(
+            ops,
+            &mut dest,
+            &mut |_| async { Ok(()) },
+            DEFAULT_CHUNK_SIZE,
+        )
+        .await?;
+
+        let mut writer = dest.writer;
+        writer.flush().await?;
+        Ok((dest_structure, writer))
+    }
+
+    /// Write to an async sequential destination with updates
+    ///
+    /// The async counterpart of [`Asset::write_stream`](crate::Asset::write_stream):
+    /// the destination only needs [`AsyncWrite`], e.g. an HTTP response body.
+    pub async fn write_stream<W>(&mut self, writer: W, updates: &Updates) -> Result<(Structure, W)>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let dest_structure = self
+            .handler
+            .calculate_updated_structure(&self.structure, updates)?;
+        let ops = self
+            .plan(0, |handler, structure, source, planner| {
+                handler.write_stream(structure, source, &mut PlanSink(planner), updates)
+            })
+            .await?;
+        let mut dest = Sequential { writer, pos: 0 };
+        self.replay(
+            ops,
+            &mut dest,
+            &mut |_| async { Ok(()) },
+            DEFAULT_CHUNK_SIZE,
+        )
+        .await?;
+
+        let mut writer = dest.writer;
+        writer.flush().await?;
+        Ok((dest_structure, writer))
+    }
+
+    /// Write to a file path with updates using `tokio::fs`
+    ///
+    /// Returns the destination structure which can be used for subsequent
+    /// in-place updates via [`Structure::update_segment`].
+    pub async fn write_to<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        updates: &Updates,
+    ) -> Result<Structure> {
+        let output = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .await?;
+        let (structure, _) = self.write(output, updates).await?;
+        Ok(structure)
+    }
+
+    /// Write with updates, passing the written data to an async processor
+    ///
+    /// The async counterpart of
+    /// [`Asset::write_with_processing`](crate::Asset::write_with_processing): the
+    /// processor receives the same data, in the same order and with the same
+    /// [`ProcessChunk`] context (mdat chunks and segment boundaries), although
+    /// chunk sizes may differ. Any `Err` from the processor stops the write and
+    /// is returned here.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{AsyncAsset, ExclusionMode, ProcessChunk, SegmentKind, Updates};
+    /// use std::io::Cursor;
+    ///
+    /// # async fn example() -> asset_io::Result<()> {
+    /// let mut asset = AsyncAsset::open("video.mp4").await?;
+    /// let updates = Updates::new()
+    ///     .set_jumbf(vec![0; 1024])
+    ///     .exclude_from_processing(vec![SegmentKind::Jumbf], ExclusionMode::DataOnly);
+    ///
+    /// let mut mdat_bytes = 0;
+    /// asset
+    ///     .write_with_processing(Cursor::new(Vec::new()), &updates, |chunk| {
+    ///         if chunk.id().is_some() {
+    ///             mdat_bytes += chunk.data().len();
+    ///         }
+    ///         async { Ok(()) }
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn write_with_processing<W, F, Fut>(
+        &mut self,
+        writer: W,
+        updates: &Updates,
+        mut processor: F,
+    ) -> Result<(Structure, W)>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin,
+        F: FnMut(AsyncChunk) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let dest_structure = self
+            .handler
+            .calculate_updated_structure(&self.structure, updates)?;
+        let mut dest = Seekable::new(writer).await?;
+        let ops = self
+            .plan(dest.pos, |handler, structure, source, planner| {
+                let mut record = |chunk: &dyn ProcessChunk| {
+                    planner.borrow_mut().process(chunk);
+                    Ok(())
+                };
+                handler.write_with_processor(
+                    structure,
+                    source,
+                    &mut PlanSink(planner),
+                    updates,
+                    &mut record,
+                )
+            })
+            .await?;
+        let chunk_size = updates.processing.effective_chunk_size(DEFAULT_CHUNK_SIZE);
+        self.replay(ops, &mut dest, &mut processor, chunk_size)
+            .await?;
+
+        let mut writer = dest.writer;
+        writer.flush().await?;
+        Ok((dest_structure, writer))
+    }
+
+    /// Read and process the asset with an async processor
+    ///
+    /// The async counterpart of
+    /// [`Asset::read_with_processing`](crate::Asset::read_with_processing): the
+    /// processor receives the same chunks, in the same order, with the same
+    /// exclusions. Any `Err` from the processor stops reading and is returned
+    /// here.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{AsyncAsset, ExclusionMode, ProcessChunk, SegmentKind, Updates};
+    ///
+    /// # async fn example() -> asset_io::Result<()> {
+    /// let mut asset = AsyncAsset::open("signed.jpg").await?;
+    /// let updates = Updates::new()
+    ///     .exclude_from_processing(vec![SegmentKind::Jumbf], ExclusionMode::DataOnly);
+    ///
+    /// let mut total = 0;
+    /// asset
+    ///     .read_with_processing(&updates, |chunk| {
+    ///         total += chunk.data().len();
+    ///         async { Ok(()) }
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn read_with_processing<F, Fut>(
+        &mut self,
+        updates: &Updates,
+        mut processor: F,
+    ) -> Result<()>
+    where
+        F: FnMut(AsyncChunk) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let ranges = processing_ranges(&self.structure, updates);
+        let buffer_size = updates
+            .processing
+            .effective_chunk_size(DEFAULT_CHUNK_SIZE)
+            .min(DEFAULT_CHUNK_SIZE) as u64;
+
+        for (offset, size) in ranges {
+            self.source.seek(SeekFrom::Start(offset)).await?;
+            let mut remaining = size;
+            while remaining > 0 {
+                let mut data = vec![0u8; remaining.min(buffer_size) as usize];
+                self.source.read_exact(&mut data).await?;
+                remaining -= data.len() as u64;
+                processor(AsyncChunk {
+                    data,
+                    ..Default::default()
+                })
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a write, fetching everything it reads except media data
+    ///
+    /// `write` runs the sync writer over the fetched blocks, with the recording
+    /// destination built on the given planner. The destination starts at `start`.
+    async fn plan<F>(&mut self, start: u64, mut write: F) -> Result<Vec<Op>>
+    where
+        F: FnMut(&Handler, &Structure, &mut BlockReader<'_>, &RefCell<Planner>) -> Result<()>,
+    {
+        let mut media = media_ranges(&self.structure, self.handler.limits());
+        loop {
+            let planner = RefCell::new(Planner::new(media.clone(), start));
+            let result = write(
+                &self.handler,
+                &self.structure,
+                &mut self.blocks.reader(Some(&planner)),
+                &planner,
+            );
+            let mut planner = planner.into_inner();
+            // A run stopped by a miss never got to write its last read
+            if self.blocks.missing.is_empty() {
+                planner.end_read();
+            }
+            if planner.rejected.is_empty() && self.blocks.missing.is_empty() {
+                return result.map(|()| planner.ops);
+            }
+            // Fetch media data the writer did not copy verbatim
+            media.retain(|range| !planner.rejected.contains(range));
+            self.blocks.fetch_missing(&mut self.source).await?;
+        }
+    }
+
+    /// Carry out a recorded write
+    async fn replay<D, F, Fut>(
+        &mut self,
+        ops: Vec<Op>,
+        dest: &mut D,
+        processor: &mut F,
+        chunk_size: usize,
+    ) -> Result<()>
+    where
+        D: Destination,
+        F: FnMut(AsyncChunk) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        for op in ops {
+            let mut pos = op.pos;
+            if op.pieces.is_empty() {
+                if let Some(chunk) = &op.chunk {
+                    processor(chunk.with_data(Vec::new())).await?;
+                }
+                continue;
+            }
+            for piece in op.pieces {
+                match piece {
+                    Piece::Bytes(data) => {
+                        emit(dest, &mut pos, &op.chunk, processor, data).await?;
+                    }
+                    Piece::Source { offset, len } => {
+                        self.source.seek(SeekFrom::Start(offset)).await?;
+                        let mut remaining = len;
+                        while remaining > 0 {
+                            let mut data = vec![0u8; remaining.min(chunk_size as u64) as usize];
+                            self.source.read_exact(&mut data).await?;
+                            remaining -= data.len() as u64;
+                            emit(dest, &mut pos, &op.chunk, processor, data).await?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write `data` at `pos` if the op writes, and pass it on if the op processes it
+async fn emit<D, F, Fut>(
+    dest: &mut D,
+    pos: &mut Option<u64>,
+    chunk: &Option<AsyncChunk>,
+    processor: &mut F,
+    data: Vec<u8>,
+) -> Result<()>
+where
+    D: Destination,
+    F: FnMut(AsyncChunk) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    if let Some(at) = pos {
+        dest.write_at(*at, &data).await?;
+        *at += data.len() as u64;
+    }
+    if let Some(chunk) = chunk {
+        processor(chunk.with_data(data)).await?;
+    }
+    Ok(())
+}
+
+/// Run `f` over the fetched blocks, fetching what it misses, until it misses nothing
+async fn retry<R, T, F>(blocks: &mut Blocks, source: &mut R, mut f: F) -> Result<T>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+    F: FnMut(&mut BlockReader<'_>) -> T,
+{
+    loop {
+        let result = f(&mut blocks.reader(None));
+        if blocks.missing.is_empty() {
+            return Ok(result);
+        }
+        blocks.fetch_missing(source).await?;
+    }
+}
+
+/// Source ranges of media data as `(start, end)`, in file order
+///
+/// Image data segments and segments over the segment size limit, which writers
+/// only ever stream, less any other segment stored inside them (such as HEIF
+/// items in `mdat`).
+fn media_ranges(structure: &Structure, limits: &Limits) -> Vec<(u64, u64)> {
+    let (images, others): (Vec<&Segment>, Vec<&Segment>) =
+        structure.segments.iter().partition(|segment| {
+            segment.is_type(SegmentKind::ImageData)
+                || (segment.is_type(SegmentKind::Other)
+                    && segment.location().size > limits.max_segment_size)
+        });
+    let ranges = |segments: Vec<&Segment>| {
+        let mut ranges: Vec<(u64, u64)> = segments
+            .iter()
+            .flat_map(|segment| segment.ranges.iter())
+            .map(|range| (range.offset, range.offset + range.size))
+            .collect();
+        ranges.sort_unstable();
+        ranges
+    };
+    let others = ranges(others);
+
+    let mut media = Vec::new();
+    for (mut start, end) in ranges(images) {
+        for &(other_start, other_end) in &others {
+            if other_start >= end {
+                break;
+            }
+            if other_end > start {
+                if other_start > start {
+                    media.push((start, other_start));
+                }
+                start = start.max(other_end);
+            }
+        }
+        if start < end {
+            media.push((start, end));
+        }
+    }
+    media
+}
+
+/// The blocks of the source fetched so far
+struct Blocks {
+    /// Source length
+    len: u64,
+    blocks: HashMap<u64, Vec<u8>>,
+    /// Blocks the last run needed but did not have
+    missing: BTreeSet<u64>,
+    /// Blocks to fetch ahead of the next miss that continues the last fetch
+    read_ahead: u64,
+    /// Block following the last fetch
+    next: u64,
+}
+
+impl Blocks {
+    fn new(len: u64) -> Self {
+        Self {
+            len,
+            blocks: HashMap::new(),
+            missing: BTreeSet::new(),
+            read_ahead: 0,
+            next: 0,
+        }
+    }
+
+    /// Start a run over the fetched blocks
+    fn reader<'a>(&'a mut self, planner: Option<&'a RefCell<Planner>>) -> BlockReader<'a> {
+        self.missing.clear();
+        BlockReader {
+            blocks: self,
+            pos: 0,
+            planner,
+        }
+    }
+
+    /// Copy the bytes at `offset` into `buf`, noting the blocks missing for it
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> bool {
+        let mut done = 0;
+        let mut complete = true;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let index = pos / BLOCK_SIZE;
+            let start = (pos % BLOCK_SIZE) as usize;
+            let len = (BLOCK_SIZE as usize - start).min(buf.len() - done);
+            match self.blocks.get(&index) {
+                Some(block) => {
+                    if complete {
+                        buf[done..done + len].copy_from_slice(&block[start..start + len]);
+                    }
+                }
+                None => {
+                    self.missing.insert(index);
+                    complete = false;
+                }
+            }
+            done += len;
+        }
+        complete
+    }
+
+    /// Fetch the missing blocks, reading further ahead while misses are sequential
+    async fn fetch_missing<R: AsyncRead + AsyncSeek + Unpin>(
+        &mut self,
+        source: &mut R,
+    ) -> Result<()> {
+        let count = self.len.div_ceil(BLOCK_SIZE);
+        let mut missing = std::mem::take(&mut self.missing).into_iter().peekable();
+        while let Some(first) = missing.next() {
+            let mut end = first + 1;
+            while missing.next_if_eq(&end).is_some() {
+                end += 1;
+            }
+            self.read_ahead = if first == self.next {
+                (self.read_ahead * 2).clamp(1, MAX_READ_AHEAD)
+            } else {
+                0
+            };
+            let limit = (end + self.read_ahead).min(count);
+            while end < limit && !self.blocks.contains_key(&end) {
+                end += 1;
+            }
+            self.fetch(source, first, end).await?;
+        }
+        Ok(())
+    }
+
+    /// Fetch the blocks covering `offset..offset + len` that are not fetched yet
+    #[cfg(feature = "bmff")]
+    async fn fetch_range<R: AsyncRead + AsyncSeek + Unpin>(
+        &mut self,
+        source: &mut R,
+        offset: u64,
+        len: u64,
+    ) -> Result<()> {
+        let end = (offset + len).min(self.len).div_ceil(BLOCK_SIZE);
+        let mut index = offset / BLOCK_SIZE;
+        while index < end {
+            if self.blocks.contains_key(&index) {
+                index += 1;
+                continue;
+            }
+            let first = index;
+            while index < end && !self.blocks.contains_key(&index) {
+                index += 1;
+            }
+            self.fetch(source, first, index).await?;
+        }
+        Ok(())
+    }
+
+    /// Fetch blocks `first..end`
+    async fn fetch<R: AsyncRead + AsyncSeek + Unpin>(
+        &mut self,
+        source: &mut R,
+        first: u64,
+        end: u64,
+    ) -> Result<()> {
+        let start = first * BLOCK_SIZE;
+        let mut data = vec![0u8; ((end * BLOCK_SIZE).min(self.len) - start) as usize];
+        source.seek(SeekFrom::Start(start)).await?;
+        source.read_exact(&mut data).await?;
+        for (index, block) in (first..).zip(data.chunks(BLOCK_SIZE as usize)) {
+            self.blocks.insert(index, block.to_vec());
+        }
+        self.next = end;
+        Ok(())
+    }
+
+    /// Fetch the top-level boxes of a BMFF file, except media data
+    ///
+    /// Parsing walks every top-level box; fetching them first saves a run per
+    /// box for fragmented files.
+    #[cfg(feature = "bmff")]
+    async fn fetch_boxes<R: AsyncRead + AsyncSeek + Unpin>(
+        &mut self,
+        source: &mut R,
+    ) -> Result<()> {
+        let mut offset = 0u64;
+        while offset + 8 <= self.len {
+            let mut header = [0u8; 16];
+            let available = (self.len - offset).min(16) as usize;
+            self.fetch_range(source, offset, available as u64).await?;
+            self.read_at(offset, &mut header[..available]);
+
+            let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+            let (size, header_size) = match size {
+                0 => (self.len - offset, 8),
+                1 if available == 16 => (u64::from_be_bytes(header[8..16].try_into().unwrap()), 16),
+                1 => break,
+                size => (size, 8),
+            };
+            if size < header_size {
+                break;
+            }
+            if &header[4..8] != b"mdat" && size <= MAX_PREFETCH_BOX {
+                self.fetch_range(source, offset, size).await?;
+            }
+            offset = offset.saturating_add(size);
+        }
+        Ok(())
+    }
+
+    /// Drop blocks of media data, keeping those holding box or chunk headers
+    fn forget(&mut self, media: &[(u64, u64)]) {
+        for &(start, end) in media {
+            for index in start / BLOCK_SIZE + 1..end / BLOCK_SIZE {
+                self.blocks.remove(&index);
+            }
+        }
+    }
+}
+
+/// The error a run stops with when it needs a block not fetched yet
+fn not_fetched() -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, "source data not fetched yet")
+}
+
+/// A sync reader over the fetched blocks
+struct BlockReader<'a> {
+    blocks: &'a mut Blocks,
+    pos: u64,
+    /// Records reads of media data during a write
+    planner: Option<&'a RefCell<Planner>>,
+}
+
+impl Read for BlockReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = (buf.len() as u64).min(self.blocks.len.saturating_sub(self.pos)) as usize;
+        let buf = &mut buf[..len];
+        let recorded = match self.planner {
+            Some(planner) => planner.borrow_mut().read(self.pos, buf)?,
+            None => Vec::new(),
+        };
+
+        // Fill everything that was not recorded
+        let mut filled = 0;
+        let mut complete = true;
+        for span in recorded.iter().chain([&Span {
+            start: len,
+            end: len,
+            source: 0,
+        }]) {
+            complete &= self
+                .blocks
+                .read_at(self.pos + filled as u64, &mut buf[filled..span.start]);
+            filled = span.end;
+        }
+        if !complete {
+            return Err(not_fetched());
+        }
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for BlockReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_position(pos, self.pos, self.blocks.len)?;
+        Ok(self.pos)
+    }
+}
+
+/// The position `pos` resolves to, from `current` in a stream of `len` bytes
+fn seek_position(pos: SeekFrom, current: u64, len: u64) -> io::Result<u64> {
+    match pos {
+        SeekFrom::Start(offset) => Some(offset),
+        SeekFrom::End(delta) => len.checked_add_signed(delta),
+        SeekFrom::Current(delta) => current.checked_add_signed(delta),
+    }
+    .ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+/// Part of a read buffer left unfilled, holding source data from `source`
+#[derive(Clone, Copy)]
+struct Span {
+    start: usize,
+    end: usize,
+    source: u64,
+}
+
+/// A read of media data left unfilled, to be copied from the source on replay
+struct RecordedRead {
+    /// Address and length of the reader's buffer
+    addr: usize,
+    len: usize,
+    spans: Vec<Span>,
+    /// Parts of the spans written to the destination, as merged buffer ranges
+    written: Vec<(usize, usize)>,
+}
+
+impl RecordedRead {
+    fn note_written(&mut self, start: usize, end: usize) {
+        match self.written.last_mut() {
+            Some(last) if last.1 == start => last.1 = end,
+            _ => {
+                self.written.push((start, end));
+                self.written.sort_unstable();
+                let mut merged: Vec<(usize, usize)> = Vec::with_capacity(self.written.len());
+                for &(start, end) in &self.written {
+                    match merged.last_mut() {
+                        Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                        _ => merged.push((start, end)),
+                    }
+                }
+                self.written = merged;
+            }
+        }
+    }
+
+    fn all_written(&self) -> bool {
+        let written: usize = self.written.iter().map(|(start, end)| end - start).sum();
+        let recorded: usize = self.spans.iter().map(|span| span.end - span.start).sum();
+        written == recorded
+    }
+}
+
+/// Data of a recorded op: bytes the writer produced, or a range of the source
+#[derive(PartialEq)]
+enum Piece {
+    Bytes(Vec<u8>),
+    Source { offset: u64, len: u64 },
+}
+
+impl Piece {
+    fn len(&self) -> u64 {
+        match self {
+            Piece::Bytes(data) => data.len() as u64,
+            Piece::Source { len, .. } => *len,
+        }
+    }
+}
+
+/// A recorded step of a write
+///
+/// Its data is passed to the processor with the context of `chunk`, if set,
+/// and written to the destination at `pos`, if set.
+struct Op {
+    chunk: Option<AsyncChunk>,
+    pos: Option<u64>,
+    pieces: Vec<Piece>,
+}
+
+impl Op {
+    fn len(&self) -> u64 {
+        self.pieces.iter().map(Piece::len).sum()
+    }
+
+    /// Whether `next` can be carried out as part of this op
+    fn continues(&self, next: &Op) -> bool {
+        let chunk = match (&self.chunk, &next.chunk) {
+            (None, None) => true,
+            (Some(chunk), Some(next)) => chunk.continues(next),
+            _ => false,
+        };
+        let pos = match (self.pos, next.pos) {
+            (None, None) => true,
+            (Some(pos), Some(next)) => pos + self.len() == next,
+            _ => false,
+        };
+        chunk && pos
+    }
+
+    fn extend(&mut self, pieces: Vec<Piece>) {
+        for piece in pieces {
+            match (self.pieces.last_mut(), piece) {
+                (Some(Piece::Bytes(data)), Piece::Bytes(more)) => data.extend_from_slice(&more),
+                (
+                    Some(Piece::Source { offset, len }),
+                    Piece::Source {
+                        offset: next,
+                        len: more,
+                    },
+                ) if *offset + *len == next => *len += more,
+                (_, piece) => self.pieces.push(piece),
+            }
+        }
+    }
+}
+
+/// Records a write for replay with async I/O
+///
+/// Reads of media data are left unfilled. Writes and processor chunks whose
+/// data lies in such a buffer are recorded as copies from the source, so a
+/// read must reach the destination before the next one; otherwise its media
+/// range is rejected and fetched for the next run.
+struct Planner {
+    /// Media data ranges whose reads are recorded, as `(start, end)`
+    media: Vec<(u64, u64)>,
+    /// Media ranges the writer did not copy verbatim
+    rejected: Vec<(u64, u64)>,
+    /// The last recorded read, until all of it is written
+    read: Option<RecordedRead>,
+    /// Source position following the last recorded read
+    next_source: Option<u64>,
+    ops: Vec<Op>,
+    /// Destination position and length
+    pos: u64,
+    len: u64,
+}
+
+impl Planner {
+    fn new(media: Vec<(u64, u64)>, start: u64) -> Self {
+        Self {
+            media,
+            rejected: Vec::new(),
+            read: None,
+            next_source: None,
+            ops: Vec::new(),
+            pos: start,
+            len: start,
+        }
+    }
+
+    /// Note a read of `buf.len()` bytes at `pos`, returning the spans left unfilled
+    fn read(&mut self, pos: u64, buf: &[u8]) -> io::Result<Vec<Span>> {
+        let end = pos + buf.len() as u64;
+        let mut spans = Vec::new();
+        let first = self
+            .media
+            .partition_point(|&(_, media_end)| media_end <= pos);
+        for &(start, stop) in &self.media[first..] {
+            if start >= end {
+                break;
+            }
+            let start = start.max(pos);
+            spans.push(Span {
+                start: (start - pos) as usize,
+                end: (stop.min(end) - pos) as usize,
+                source: start,
+            });
+        }
+        let recorded: usize = spans.iter().map(|span| span.end - span.start).sum();
+        if recorded < MIN_RECORDED_READ && !self.is_tail(pos, end) {
+            spans.clear();
+        }
+
+        // A new read into the buffer of the last one ends it
+        let addr = buf.as_ptr() as usize;
+        let reuses = self
+            .read
+            .as_ref()
+            .is_some_and(|read| addr < read.addr + read.len && read.addr < addr + buf.len());
+        if (reuses || !spans.is_empty()) && !self.end_read() {
+            return Err(not_fetched());
+        }
+        if let Some(last) = spans.last() {
+            self.next_source = Some(last.source + (last.end - last.start) as u64);
+            self.read = Some(RecordedRead {
+                addr,
+                len: buf.len(),
+                spans: spans.clone(),
+                written: Vec::new(),
+            });
+        }
+        Ok(spans)
+    }
+
+    /// Whether `pos..end` finishes a media range the last recorded read was copying
+    fn is_tail(&self, pos: u64, end: u64) -> bool {
+        self.next_source == Some(pos)
+            && self
+                .media
+                .iter()
+                .any(|&(start, stop)| start < pos && stop == end)
+    }
+
+    /// End the last recorded read, rejecting its media ranges if it was not
+    /// all written
+    fn end_read(&mut self) -> bool {
+        let Some(read) = self.read.take() else {
+            return true;
+        };
+        if read.all_written() {
+            return true;
+        }
+        for span in &read.spans {
+            if let Some(&range) = self
+                .media
+                .iter()
+                .find(|&&(start, end)| start <= span.source && span.source < end)
+            {
+                if !self.rejected.contains(&range) {
+                    self.rejected.push(range);
+                }
+            }
+        }
+        false
+    }
+
+    /// Split `data` into bytes and copies from the source, noting what gets written
+    fn pieces(&mut self, data: &[u8], written: bool) -> Vec<Piece> {
+        let addr = data.as_ptr() as usize;
+        let end = addr + data.len();
+        let mut pieces = Vec::new();
+        let mut at = addr;
+        if let Some(read) = &mut self.read {
+            let mut copied = Vec::new();
+            for span in &read.spans {
+                let start = (read.addr + span.start).max(at);
+                let stop = (read.addr + span.end).min(end);
+                if start >= stop {
+                    continue;
+                }
+                if start > at {
+                    pieces.push(Piece::Bytes(data[at - addr..start - addr].to_vec()));
+                }
+                pieces.push(Piece::Source {
+                    offset: span.source + (start - read.addr - span.start) as u64,
+                    len: (stop - start) as u64,
+                });
+                copied.push((start - read.addr, stop - read.addr));
+                at = stop;
+            }
+            if written {
+                for (start, stop) in copied {
+                    read.note_written(start, stop);
+                }
+                if read.all_written() {
+                    self.read = None;
+                }
+            }
+        }
+        if at < end {
+            pieces.push(Piece::Bytes(data[at - addr..].to_vec()));
+        }
+        pieces
+    }
+
+    /// Record data passed to the processor
+    fn process(&mut self, chunk: &dyn ProcessChunk) {
+        let pieces = self.pieces(chunk.data(), false);
+        self.push(Op {
+            chunk: Some(AsyncChunk::context(chunk)),
+            pos: None,
+            pieces,
+        });
+    }
+
+    /// Record data written to the destination
+    fn write(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let pieces = self.pieces(data, true);
+        let pos = self.pos;
+        self.pos += data.len() as u64;
+        self.len = self.len.max(self.pos);
+
+        // Data is usually processed right before it is written
+        if let Some(mut last) = self.ops.pop() {
+            if last.chunk.is_some() && last.pos.is_none() && last.pieces == pieces {
+                last.pos = Some(pos);
+                self.push(last);
+                return;
+            }
+            self.ops.push(last);
+        }
+        self.push(Op {
+            chunk: None,
+            pos: Some(pos),
+            pieces,
+        });
+    }
+
+    /// Add `op`, merging it into the last one when it continues it
+    fn push(&mut self, op: Op) {
+        match self.ops.last_mut() {
+            Some(last) if last.continues(&op) => last.extend(op.pieces),
+            _ => self.ops.push(op),
+        }
+    }
+
+    /// Read back written bytes at the destination position
+    fn read_back(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = (buf.len() as u64).min(self.len.saturating_sub(self.pos)) as usize;
+        let (start, end) = (self.pos, self.pos + len as u64);
+        let buf = &mut buf[..len];
+        buf.fill(0);
+        // Later writes win
+        for op in &self.ops {
+            let Some(mut at) = op.pos else {
+                continue;
+            };
+            for piece in &op.pieces {
+                let (from, to) = (at.max(start), (at + piece.len()).min(end));
+                if from < to {
+                    match piece {
+                        Piece::Bytes(data) => buf[(from - start) as usize..(to - start) as usize]
+                            .copy_from_slice(&data[(from - at) as usize..(to - at) as usize]),
+                        Piece::Source { .. } => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::Unsupported,
+                                "reading back media data during an async write",
+                            ))
+                        }
+                    }
+                }
+                at += piece.len();
+            }
+        }
+        self.pos = end;
+        Ok(len)
+    }
+}
+
+/// The recording destination of a planned write
+struct PlanSink<'a>(&'a RefCell<Planner>);
+
+impl Write for PlanSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for PlanSink<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read_back(buf)
+    }
+}
+
+impl Seek for PlanSink<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let mut planner = self.0.borrow_mut();
+        planner.pos = seek_position(pos, planner.pos, planner.len)?;
+        Ok(planner.pos)
+    }
+}
+
+/// Where a replayed write goes
+trait Destination {
+    /// Write `data` at `pos`
+    async fn write_at(&mut self, pos: u64, data: &[u8]) -> Result<()>;
+}
+
+/// A seekable async destination
+struct Seekable<W> {
+    writer: W,
+    pos: u64,
+}
+
+impl<W: AsyncWrite + AsyncSeek + Unpin> Seekable<W> {
+    async fn new(mut writer: W) -> Result<Self> {
+        let pos = writer.stream_position().await?;
+        Ok(Self { writer, pos })
+    }
+}
+
+impl<W: AsyncWrite + AsyncSeek + Unpin> Destination for Seekable<W> {
+    async fn write_at(&mut self, pos: u64, data: &[u8]) -> Result<()> {
+        if pos != self.pos {
+            self.writer.seek(SeekFrom::Start(pos)).await?;
+        }
+        self.writer.write_all(data).await?;
+        self.pos = pos + data.len() as u64;
+        Ok(())
+    }
+}
+
+/// A sequential async destination
+struct Sequential<W> {
+    writer: W,
+    pos: u64,
+}
+
+impl<W: AsyncWrite + Unpin> Destination for Sequential<W> {
+    async fn write_at(&mut self, pos: u64, data: &[u8]) -> Result<()> {
+        if pos != self.pos {
+            return Err(Error::Unsupported(
+                "write_stream destination cannot seek".into(),
+            ));
+        }
+        self.writer.write_all(data).await?;
+        self.pos += data.len() as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{fixture_bytes, P1000708};
+    use std::io::Cursor;
+
+    /// Blocks holding all of `data`
+    fn blocks(data: &[u8]) -> Blocks {
+        let mut blocks = Blocks::new(data.len() as u64);
+        for (index, block) in (0..).zip(data.chunks(BLOCK_SIZE as usize)) {
+            blocks.blocks.insert(index, block.to_vec());
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_copied_media_is_recorded_as_source_range() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+        let mut blocks = blocks(&data);
+        let planner = RefCell::new(Planner::new(vec![(100, 20_000)], 0));
+        let mut reader = blocks.reader(Some(&planner));
+        let mut sink = PlanSink(&planner);
+
+        let mut header = [0u8; 100];
+        reader.read_exact(&mut header).unwrap();
+        sink.write_all(&header).unwrap();
+        let mut buffer = vec![0u8; 8192];
+        for _ in 0..2 {
+            reader.read_exact(&mut buffer).unwrap();
+            sink.write_all(&buffer).unwrap();
+        }
+        // The tail of the media range is recorded at any size
+        let mut tail = vec![0u8; 20_000 - 100 - 2 * 8192];
+        reader.read_exact(&mut tail).unwrap();
+        sink.write_all(&tail).unwrap();
+
+        let mut planner = planner.into_inner();
+        assert!(planner.end_read());
+        assert!(planner.rejected.is_empty());
+        assert_eq!(planner.ops.len(), 1);
+        assert!(
+            planner.ops[0].pieces
+                == vec![
+                    Piece::Bytes(data[..100].to_vec()),
+                    Piece::Source {
+                        offset: 100,
+                        len: 19_900
+                    }
+                ]
+        );
+    }
+
+    #[test]
+    fn test_media_not_copied_verbatim_is_rejected() {
+        let data = vec![7u8; 10_000];
+        let mut blocks = blocks(&data);
+        let planner = RefCell::new(Planner::new(vec![(0, 10_000)], 0));
+        let mut reader = blocks.reader(Some(&planner));
+
+        let mut buffer = vec![0u8; 10_000];
+        reader.read_exact(&mut buffer).unwrap();
+        // Recorded, so left unfilled
+        assert!(buffer.iter().all(|&byte| byte == 0));
+        PlanSink(&planner).write_all(&buffer[..5000]).unwrap();
+
+        let mut planner = planner.into_inner();
+        assert!(!planner.end_read());
+        assert_eq!(planner.rejected, vec![(0, 10_000)]);
+    }
+
+    #[test]
+    fn test_missing_blocks_are_noted() {
+        let mut blocks = Blocks::new(4 * BLOCK_SIZE);
+        blocks.blocks.insert(0, vec![1u8; BLOCK_SIZE as usize]);
+        let mut reader = blocks.reader(None);
+        let mut buffer = vec![0u8; 3 * BLOCK_SIZE as usize];
+        let error = reader.read_exact(&mut buffer).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(blocks.missing, BTreeSet::from([1, 2]));
+    }
+
+    #[tokio::test]
+    async fn test_write_does_not_fetch_media_data() {
+        let data = fixture_bytes(P1000708).unwrap();
+        let mut asset = AsyncAsset::from_source(Cursor::new(data.clone()))
+            .await
+            .unwrap();
+        let updates = Updates::new().set_jumbf(vec![1u8; 100]);
+        let (_, output) = asset
+            .write(Cursor::new(Vec::new()), &updates)
+            .await
+            .unwrap();
+
+        let mut expected = Cursor::new(Vec::new());
+        crate::Asset::from_source(Cursor::new(data.clone()))
+            .unwrap()
+            .write(&mut expected, &updates)
+            .unwrap();
+        assert!(output.into_inner() == expected.into_inner());
+
+        let fetched: usize = asset.blocks.blocks.values().map(Vec::len).sum();
+        assert!(
+            fetched < data.len() / 4,
+            "{fetched} of {} bytes",
+            data.len()
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! Normalized capture timestamps
+//!
+//! Photos and videos record when they were captured in different places and
+//! formats. `Asset::capture_time()` checks them in one fixed order and returns
+//! the first that parses:
+//!
+//! 1. EXIF `DateTimeOriginal`, with `OffsetTimeOriginal` when present
+//! 2. XMP `photoshop:DateCreated`, then `xmp:CreateDate`
+//! 3. BMFF `mvhd` `creation_time` (seconds since 1904-01-01 UTC)
+//! 4. QuickTime `com.apple.quicktime.creationdate`
+//! 5. RIFF `IDIT` (AVI), then `ICRD` (LIST INFO)
+//!
+//! # Example
+//!
+//! ```no_run
+//! use asset_io::Asset;
+//!
+//! # fn main() -> asset_io::Result<()> {
+//! let mut asset = Asset::open("clip.mov")?;
+//! if let Some(time) = asset.capture_time()? {
+//!     println!("{} (from {:?}, timezone known: {})", time, time.source, time.has_timezone());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+
+/// Seconds from 1904-01-01 (the BMFF epoch) to 1970-01-01
+#[cfg(feature = "bmff")]
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Where a [`CaptureTime`] was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureTimeSource {
+    /// EXIF `DateTimeOriginal` (with `OffsetTimeOriginal` when present)
+    ExifDateTimeOriginal,
+    /// XMP `photoshop:DateCreated`
+    XmpDateCreated,
+    /// XMP `xmp:CreateDate`
+    XmpCreateDate,
+    /// BMFF `mvhd` `creation_time`
+    MvhdCreationTime,
+    /// QuickTime `com.apple.quicktime.creationdate`
+    QuickTimeCreationDate,
+    /// RIFF `IDIT` chunk (AVI)
+    RiffIdit,
+    /// RIFF `ICRD` entry in LIST INFO
+    RiffIcrd,
+}
+
+/// A capture date and time, as recorded by the asset
+///
+/// The fields hold the recorded wall-clock time. When the source recorded a
+/// timezone, `utc_offset_minutes` holds it; otherwise the time is local to an
+/// unknown zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureTime {
+    /// Year (e.g. 2024)
+    pub year: i32,
+    /// Month (1-12)
+    pub month: u8,
+    /// Day of month (1-31)
+    pub day: u8,
+    /// Hour (0-23)
+    pub hour: u8,
+    /// Minute (0-59)
+    pub minute: u8,
+    /// Second (0-60, allowing a leap second)
+    pub second: u8,
+    /// Fractional second in nanoseconds
+    pub nanosecond: u32,
+    /// Offset from UTC in minutes, if the timezone is known
+    pub utc_offset_minutes: Option<i32>,
+    /// Where the value came from
+    pub source: CaptureTimeSource,
+    /// The value as recorded, before normalization
+    pub raw: String,
+}
+
+impl CaptureTime {
+    /// True if the source recorded a timezone (or is defined to be UTC)
+    pub fn has_timezone(&self) -> bool {
+        self.utc_offset_minutes.is_some()
+    }
+
+    /// Seconds since 1970-01-01 UTC
+    ///
+    /// Times without a known timezone are treated as UTC, which keeps mixed
+    /// libraries in a stable order but may be off by the local offset.
+    pub fn unix_timestamp(&self) -> i64 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let local =
+            days * 86_400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+        local - self.utc_offset_minutes.unwrap_or(0) as i64 * 60
+    }
+
+    /// Parse EXIF `DateTimeOriginal` ("YYYY:MM:DD HH:MM:SS") with an optional
+    /// `OffsetTimeOriginal` ("+HH:MM")
+    #[cfg(feature = "exif")]
+    pub(crate) fn from_exif(date_time: &str, offset: Option<&str>) -> Option<Self> {
+        let s = date_time.trim();
+        let b = s.as_bytes();
+        if b.len() < 19 || b[4] != b':' || b[7] != b':' || b[13] != b':' || b[16] != b':' {
+            return None;
+        }
+        let mut time = Self::from_parts(
+            s.get(0..4)?.parse().ok()?,
+            s.get(5..7)?.parse().ok()?,
+            s.get(8..10)?.parse().ok()?,
+            s.get(11..13)?.parse().ok()?,
+            s.get(14..16)?.parse().ok()?,
+            s.get(17..19)?.parse().ok()?,
+            CaptureTimeSource::ExifDateTimeOriginal,
+            s,
+        )?;
+        time.utc_offset_minutes = offset.and_then(parse_utc_offset);
+        if let Some(offset) = offset {
+            time.raw = format!("{} {}", s, offset.trim());
+        }
+        Some(time)
+    }
+
+    /// Parse an ISO 8601 date or date-time, as used by XMP, QuickTime and RIFF `ICRD`
+    ///
+    /// Accepts `YYYY`, `YYYY-MM`, `YYYY-MM-DD` and `YYYY-MM-DDThh:mm[:ss[.s+]]`
+    /// (with `T` or a space), followed by `Z`, `±hh:mm` or `±hhmm`.
+    #[cfg(any(feature = "xmp", feature = "bmff", feature = "riff"))]
+    pub(crate) fn from_iso8601(value: &str, source: CaptureTimeSource) -> Option<Self> {
+        let s = value.trim();
+        let num = |range: std::ops::Range<usize>| -> Option<u32> {
+            let part = s.get(range)?;
+            part.bytes()
+                .all(|c| c.is_ascii_digit())
+                .then(|| part.parse().ok())?
+        };
+        let sep = |at: usize, c: u8| s.as_bytes().get(at) == Some(&c);
+
+        let year = num(0..4)? as i32;
+        let mut pos = 4;
+        let (mut month, mut day) = (1, 1);
+        if sep(4, b'-') {
+            month = num(5..7)?;
+            pos = 7;
+            if sep(7, b'-') {
+                day = num(8..10)?;
+                pos = 10;
+            }
+        }
+
+        let (mut hour, mut minute, mut second, mut nanosecond) = (0, 0, 0, 0);
+        let mut utc_offset = None;
+        if pos == 10 && (sep(10, b'T') || sep(10, b' ')) {
+            hour = num(11..13)?;
+            if !sep(13, b':') {
+                return None;
+            }
+            minute = num(14..16)?;
+            pos = 16;
+            if sep(16, b':') {
+                second = num(17..19)?;
+                pos = 19;
+                if sep(19, b'.') || sep(19, b',') {
+                    let digits = s[20..].bytes().take_while(|c| c.is_ascii_digit()).count();
+                    let frac: String = s[20..20 + digits].chars().take(9).collect();
+                    nanosecond = format!("{:0<9}", frac).parse().ok()?;
+                    pos = 20 + digits;
+                }
+            }
+            let rest = &s[pos..];
+            if !rest.is_empty() {
+                utc_offset = Some(parse_utc_offset(rest)?);
+            }
+        } else if pos != s.len() {
+            return None;
+        }
+
+        let mut time = Self::from_parts(year, month, day, hour, minute, second, source, s)?;
+        time.nanosecond = nanosecond;
+        time.utc_offset_minutes = utc_offset;
+        Some(time)
+    }
+
+    /// Convert an `mvhd` `creation_time` (seconds since 1904-01-01 UTC)
+    ///
+    /// Returns `None` for 0, which means the time was not set.
+    #[cfg(feature = "bmff")]
+    pub(crate) fn from_mp4_seconds(seconds: u64) -> Option<Self> {
+        if seconds == 0 {
+            return None;
+        }
+        let unix = i64::try_from(seconds).ok()? - MP4_EPOCH_OFFSET;
+        let (year, month, day) = civil_from_days(unix.div_euclid(86_400));
+        let secs = unix.rem_euclid(86_400) as u32;
+        let mut time = Self::from_parts(
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            CaptureTimeSource::MvhdCreationTime,
+            &seconds.to_string(),
+        )?;
+        time.utc_offset_minutes = Some(0);
+        Some(time)
+    }
+
+    /// Parse a RIFF `IDIT` value in C `asctime` form ("Mon Jun 10 14:23:05 2019"),
+    /// falling back to ISO 8601
+    #[cfg(feature = "riff")]
+    pub(crate) fn from_idit(value: &str) -> Option<Self> {
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        let s = value.trim_end_matches('\0').trim();
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if let [_, month, day, time, year] = fields.as_slice() {
+            let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as u32 + 1;
+            let hms: Vec<&str> = time.split(':').collect();
+            if hms.len() != 3 {
+                return None;
+            }
+            return Self::from_parts(
+                year.parse().ok()?,
+                month,
+                day.parse().ok()?,
+                hms[0].parse().ok()?,
+                hms[1].parse().ok()?,
+                hms[2].parse().ok()?,
+                CaptureTimeSource::RiffIdit,
+                s,
+            );
+        }
+        Self::from_iso8601(s, CaptureTimeSource::RiffIdit)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[cfg(any(feature = "exif", feature = "xmp", feature = "bmff", feature = "riff"))]
+    fn from_parts(
+        year: i32,
+        month: u32,
+        day: u32,
+        hour: u32,
+        minute: u32,
+        second: u32,
+        source: CaptureTimeSource,
+        raw: &str,
+    ) -> Option<Self> {
+        let valid = (1..=12).contains(&month)
+            && (1..=31).contains(&day)
+            && hour < 24
+            && minute < 60
+            && second <= 60;
+        // EXIF uses all-zero dates for "unknown"
+        if !valid || year == 0 {
+            return None;
+        }
+        Some(Self {
+            year,
+            month: month as u8,
+            day: day as u8,
+            hour: hour as u8,
+            minute: minute as u8,
+            second: second as u8,
+            nanosecond: 0,
+            utc_offset_minutes: None,
+            source,
+            raw: raw.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for CaptureTime {
+    /// ISO 8601, with the UTC offset when known
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )?;
+        match self.utc_offset_minutes {
+            Some(0) => write!(f, "Z"),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let offset = offset.abs();
+                write!(f, "{}{:02}:{:02}", sign, offset / 60, offset % 60)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parse `Z`, `±hh:mm`, `±hhmm` or `±hh` into minutes east of UTC
+#[cfg(any(feature = "exif", feature = "xmp", feature = "bmff", feature = "riff"))]
+fn parse_utc_offset(s: &str) -> Option<i32> {
+    let s = s.trim();
+    if s == "Z" || s == "z" {
+        return Some(0);
+    }
+    let sign = match s.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits: String = s[1..].chars().filter(|&c| c != ':').collect();
+    if !digits.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes): (i32, i32) = match digits.len() {
+        2 => (digits.parse().ok()?, 0),
+        4 => (digits[..2].parse().ok()?, digits[2..].parse().ok()?),
+        _ => return None,
+    };
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    let year = year as i64 - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Proleptic Gregorian date for a count of days since 1970-01-01
+#[cfg(feature = "bmff")]
+fn civil_from_days(days: i64) -> (i32, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year as i32, month, day)
+}
+
+#[cfg(all(
+    test,
+    any(feature = "exif", feature = "xmp", feature = "bmff", feature = "riff")
+))]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "exif")]
+    #[test]
+    fn test_exif_with_offset() {
+        let t = CaptureTime::from_exif("2023:07:14 09:30:05", Some("-07:00")).unwrap();
+        assert_eq!((t.year, t.month, t.day), (2023, 7, 14));
+        assert_eq!((t.hour, t.minute, t.second), (9, 30, 5));
+        assert_eq!(t.utc_offset_minutes, Some(-420));
+        assert_eq!(t.to_string(), "2023-07-14T09:30:05-07:00");
+        assert_eq!(t.unix_timestamp(), 1_689_352_205);
+
+        let local = CaptureTime::from_exif("2023:07:14 09:30:05", None).unwrap();
+        assert!(!local.has_timezone());
+        assert!(CaptureTime::from_exif("0000:00:00 00:00:00", None).is_none());
+    }
+
+    #[cfg(feature = "xmp")]
+    #[test]
+    fn test_iso8601_forms() {
+        let src = CaptureTimeSource::XmpCreateDate;
+        let t = CaptureTime::from_iso8601("2019-06-10T14:23:05.25+02:00", src).unwrap();
+        assert_eq!(t.nanosecond, 250_000_000);
+        assert_eq!(t.utc_offset_minutes, Some(120));
+
+        // QuickTime writes the offset without a colon
+        let t = CaptureTime::from_iso8601("2019-06-10T14:23:05-0700", src).unwrap();
+        assert_eq!(t.utc_offset_minutes, Some(-420));
+
+        let t = CaptureTime::from_iso8601("2019-06-10T14:23Z", src).unwrap();
+        assert_eq!((t.minute, t.utc_offset_minutes), (23, Some(0)));
+
+        let t = CaptureTime::from_iso8601("2019-06-10", src).unwrap();
+        assert_eq!((t.day, t.hour), (10, 0));
+        assert!(!t.has_timezone());
+
+        assert!(CaptureTime::from_iso8601("June 2019", src).is_none());
+        assert!(CaptureTime::from_iso8601("2019-13-01", src).is_none());
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_mp4_epoch() {
+        // 2001-01-01T00:00:00Z
+        let t = CaptureTime::from_mp4_seconds(3_061_152_000).unwrap();
+        assert_eq!((t.year, t.month, t.day, t.hour), (2001, 1, 1, 0));
+        assert_eq!(t.unix_timestamp(), 978_307_200);
+        assert!(t.has_timezone());
+        assert!(CaptureTime::from_mp4_seconds(0).is_none());
+    }
+
+    #[cfg(feature = "riff")]
+    #[test]
+    fn test_idit() {
+        let t = CaptureTime::from_idit("Mon Jun 10 14:23:05 2019\n\0").unwrap();
+        assert_eq!((t.year, t.month, t.day, t.hour), (2019, 6, 10, 14));
+        assert_eq!(t.source, CaptureTimeSource::RiffIdit);
+        assert!(!t.has_timezone());
+    }
+}
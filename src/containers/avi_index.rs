@@ -0,0 +1,240 @@
+//! AVI index offset tracking (legacy `idx1` and OpenDML `indx`/`ix##`)
+//!
+//! AVI files carry indexes that point at the media data:
+//!
+//! - `idx1`: the legacy index after `LIST movi`. Its offsets are normally
+//!   relative to the `movi` list type, so they survive moving `movi` as a
+//!   whole, but some writers store absolute file offsets instead.
+//! - `indx`: OpenDML super indexes inside each `LIST strl` of `LIST hdrl`.
+//!   Each entry holds the absolute file offset of an `ix##` standard index.
+//! - `ix##`: OpenDML standard indexes, whose entries are relative to a 64-bit
+//!   absolute base offset. Files over 1 GB hold most of them in `RIFF AVIX`
+//!   extension chunks following the main `RIFF AVI ` chunk.
+//!
+//! Parsing records the file position of every field that holds an absolute
+//! offset, so that writes can rewrite them when the data they point to moves.
+//!
+//! Reference: OpenDML AVI File Format Extensions 1.02
+
+use crate::{
+    error::{Error, Result},
+    Limits,
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// `bIndexType` of an index of indexes (super index)
+const AVI_INDEX_OF_INDEXES: u8 = 0x00;
+/// `bIndexType` of an index of chunks (standard index)
+const AVI_INDEX_OF_CHUNKS: u8 = 0x01;
+
+/// Size of the common `indx`/`ix##` header up to the entries
+const INDEX_HEADER_SIZE: usize = 24;
+
+/// Offset of `qwBaseOffset` in standard index data
+const BASE_OFFSET_FIELD: u64 = 12;
+
+/// A field in the file holding an absolute file offset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OffsetField {
+    /// File position of the field
+    pub(crate) pos: u64,
+    /// Field width in bytes (4 for `idx1`, 8 for OpenDML)
+    pub(crate) width: u8,
+}
+
+/// Absolute offset fields of an AVI file, sorted by position
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AviIndex {
+    pub(crate) fields: Vec<OffsetField>,
+}
+
+impl AviIndex {
+    /// Scan the indexes of an AVI file
+    ///
+    /// `hdrl` and `movi` are the offsets of the `LIST hdrl` and `LIST movi`
+    /// chunks and `idx1` the offset of the `idx1` chunk, as found at the top level.
+    /// The `LIST hdrl` is read into memory, up to the segment size limit; one
+    /// too small to hold its list type is an [`Error::InvalidSegment`].
+    pub(crate) fn scan<R: Read + Seek>(
+        source: &mut R,
+        hdrl: Option<u64>,
+        movi: Option<u64>,
+        idx1: Option<u64>,
+        file_len: u64,
+        limits: &Limits,
+    ) -> Result<Self> {
+        let mut fields = Vec::new();
+        if let Some(hdrl) = hdrl {
+            let data = read_chunk(source, hdrl, limits)?;
+            if data.len() < 4 {
+                return Err(Error::InvalidSegment {
+                    offset: hdrl,
+                    reason: "LIST hdrl is too small to hold its list type".into(),
+                });
+            }
+            // Skip the list type
+            scan_list(source, &data[4..], hdrl + 12, file_len, &mut fields)?;
+        }
+        if let (Some(movi), Some(idx1)) = (movi, idx1) {
+            idx1_fields(source, movi, idx1, &mut fields)?;
+        }
+        fields.sort_by_key(|f| f.pos);
+        fields.dedup();
+        Ok(Self { fields })
+    }
+
+    /// Fields inside `[start, end)`
+    pub(crate) fn fields_in(&self, start: u64, end: u64) -> &[OffsetField] {
+        let from = self.fields.partition_point(|f| f.pos < start);
+        let to = self.fields.partition_point(|f| f.pos < end);
+        &self.fields[from..to]
+    }
+}
+
+/// Copy `size` bytes from `offset`, passing each offset field in that range through `map`
+pub(crate) fn copy_patched<R: Read + Seek, W: Write>(
+    source: &mut R,
+    writer: &mut W,
+    offset: u64,
+    size: u64,
+    fields: &[OffsetField],
+    map: impl Fn(u64) -> u64,
+) -> Result<()> {
+    source.seek(SeekFrom::Start(offset))?;
+    let mut pos = offset;
+    for field in fields {
+        std::io::copy(&mut source.by_ref().take(field.pos - pos), writer)?;
+        if field.width == 8 {
+            let value = source.read_u64::<LittleEndian>()?;
+            writer.write_u64::<LittleEndian>(map(value))?;
+        } else {
+            let value = map(source.read_u32::<LittleEndian>()? as u64);
+            let value = u32::try_from(value).map_err(|_| {
+                Error::CapacityExceeded("idx1 offset no longer fits in 32 bits".into())
+            })?;
+            writer.write_u32::<LittleEndian>(value)?;
+        }
+        pos = field.pos + field.width as u64;
+    }
+    std::io::copy(&mut source.by_ref().take(offset + size - pos), writer)?;
+    Ok(())
+}
+
+/// Read the data of the chunk at `offset`, within the segment size limit
+fn read_chunk<R: Read + Seek>(source: &mut R, offset: u64, limits: &Limits) -> Result<Vec<u8>> {
+    source.seek(SeekFrom::Start(offset + 4))?;
+    let size = source.read_u32::<LittleEndian>()? as u64;
+    limits.check_segment_size(offset, size, "AVI header list")?;
+    let mut data = vec![0u8; size as usize];
+    source.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Walk the sub-chunks of a list held in memory, starting at file position `base`
+fn scan_list<R: Read + Seek>(
+    source: &mut R,
+    data: &[u8],
+    base: u64,
+    file_len: u64,
+    fields: &mut Vec<OffsetField>,
+) -> Result<()> {
+    let mut pos = 0usize;
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let size = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let body = &data[pos + 8..(pos + 8 + size).min(data.len())];
+        let body_pos = base + pos as u64 + 8;
+        if id == b"LIST" && body.len() >= 4 {
+            scan_list(source, &body[4..], body_pos + 4, file_len, fields)?;
+        } else if id == b"indx" && body.len() >= INDEX_HEADER_SIZE {
+            indx_fields(source, body, body_pos, file_len, fields)?;
+        }
+        pos += 8 + size + size % 2;
+    }
+    Ok(())
+}
+
+/// Offset fields of an `indx` chunk, and of the standard indexes it points to
+fn indx_fields<R: Read + Seek>(
+    source: &mut R,
+    body: &[u8],
+    body_pos: u64,
+    file_len: u64,
+    fields: &mut Vec<OffsetField>,
+) -> Result<()> {
+    let longs_per_entry = u16::from_le_bytes([body[0], body[1]]) as usize;
+    let index_type = body[3];
+    let entries = u32::from_le_bytes(body[4..8].try_into().unwrap()) as usize;
+
+    match index_type {
+        AVI_INDEX_OF_INDEXES if longs_per_entry == 4 => {
+            for i in 0..entries {
+                let at = INDEX_HEADER_SIZE + i * 16;
+                let Some(entry) = body.get(at..at + 8) else {
+                    break;
+                };
+                fields.push(OffsetField {
+                    pos: body_pos + at as u64,
+                    width: 8,
+                });
+                // The standard index the entry points to: [ix##][size][header]
+                let ix = u64::from_le_bytes(entry.try_into().unwrap());
+                if ix == 0 || ix + 8 + INDEX_HEADER_SIZE as u64 > file_len {
+                    continue;
+                }
+                source.seek(SeekFrom::Start(ix + 8 + 3))?;
+                if source.read_u8()? == AVI_INDEX_OF_CHUNKS {
+                    fields.push(OffsetField {
+                        pos: ix + 8 + BASE_OFFSET_FIELD,
+                        width: 8,
+                    });
+                }
+            }
+        }
+        AVI_INDEX_OF_CHUNKS => fields.push(OffsetField {
+            pos: body_pos + BASE_OFFSET_FIELD,
+            width: 8,
+        }),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Check how `idx1` offsets are stored, recording them when they are absolute
+fn idx1_fields<R: Read + Seek>(
+    source: &mut R,
+    movi: u64,
+    idx1: u64,
+    fields: &mut Vec<OffsetField>,
+) -> Result<()> {
+    source.seek(SeekFrom::Start(idx1 + 4))?;
+    let entries = source.read_u32::<LittleEndian>()? as u64 / 16;
+    if entries == 0 {
+        return Ok(());
+    }
+    let mut entry = [0u8; 16];
+    source.read_exact(&mut entry)?;
+    let chunk_id = &entry[0..4];
+    let offset = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+
+    let id_at = |source: &mut R, pos: u64| -> Result<bool> {
+        let mut id = [0u8; 4];
+        source.seek(SeekFrom::Start(pos))?;
+        Ok(source.read_exact(&mut id).is_ok() && id == chunk_id)
+    };
+    // Relative offsets count from the `movi` list type
+    if id_at(source, movi + 8 + offset)? {
+        return Ok(());
+    }
+    if id_at(source, offset)? {
+        log::debug!("idx1 uses absolute offsets ({} entries)", entries);
+        fields.extend((0..entries).map(|i| OffsetField {
+            pos: idx1 + 8 + i * 16 + 8,
+            width: 4,
+        }));
+    } else {
+        log::warn!("idx1 offsets point at neither movi-relative nor absolute chunks");
+    }
+    Ok(())
+}
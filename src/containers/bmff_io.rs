@@ -4,29 +4,31 @@
 //!
 //! Reference: ISO/IEC 14496-12:2022
 
-use super::{ContainerIO, ContainerKind};
+use super::{
+    bmff_tree::{BmffBox, BmffTree, BoxId},
+    heif_meta::{HeifMeta, MetaEdit, C2PA_CONTENT_TYPE, XMP_CONTENT_TYPE},
+    quicktime_meta::{self, QuickTimeMetadata},
+    ContainerIO, ContainerKind,
+};
 use crate::{
+    diagnostic::{Diagnostic, Severity, ValidationReport},
     error::{Error, Result},
     processing_writer::MdatChunk,
-    segment::{ByteRange, Segment, SegmentKind},
+    segment::{ByteRange, LazyData, Segment, SegmentKind, SegmentMetadata},
     structure::Structure,
-    MediaType, Updates,
+    Candidate, Confidence, Limits, MediaType, MetadataUpdate, Updates,
 };
 use atree::{Arena, Token};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::{
     collections::HashMap,
-    io::{Read, Seek, SeekFrom, Write},
+    io::{BufRead, Read, Seek, SeekFrom, Write},
 };
 
 // BMFF constants
 const HEADER_SIZE: u64 = 8; // 4 byte type + 4 byte size
 const HEADER_SIZE_LARGE: u64 = 16; // 4 byte type + 4 byte size + 8 byte large size
 
-// Maximum size for a single buffer allocation to prevent OOM attacks
-// Set to 256MB - reasonable for legitimate BMFF boxes while preventing abuse
-const MAX_BOX_ALLOCATION: u64 = 256 * 1024 * 1024;
-
 const C2PA_UUID: [u8; 16] = [
     0xd8, 0xfe, 0xc3, 0xd6, 0x1b, 0x0e, 0x48, 0x3c, 0x92, 0x97, 0x58, 0x28, 0x87, 0x7e, 0xc4, 0x81,
 ];
@@ -231,6 +233,7 @@ pub(crate) struct BoxInfo {
     user_type: Option<Vec<u8>>,
     version: Option<u8>,
     flags: Option<u32>,
+    large_size: bool,
 }
 
 fn read_box_header_ext<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<(u8, u32)> {
@@ -239,6 +242,17 @@ fn read_box_header_ext<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<(u8, u
     Ok((version, flags))
 }
 
+/// Whether the `meta` box whose payload starts at the reader's position is a
+/// QuickTime container, which starts with its `hdlr` child instead of version
+/// and flags
+fn is_quicktime_meta<R: Read + Seek + ?Sized>(reader: &mut R) -> Result<bool> {
+    let pos = reader.stream_position()?;
+    let mut peek = [0u8; 8];
+    let found = reader.read_exact(&mut peek).is_ok() && &peek[4..8] == b"hdlr";
+    reader.seek(SeekFrom::Start(pos))?;
+    Ok(found)
+}
+
 fn write_box_header_ext<W: Write>(w: &mut W, v: u8, f: u32) -> Result<u64> {
     w.write_u8(v)?;
     w.write_u24::<BigEndian>(f)?;
@@ -368,173 +382,319 @@ fn path_from_token(bmff_tree: &Arena<BoxInfo>, current_node_token: &Token) -> Re
 }
 
 /// Build a tree structure representing the BMFF box hierarchy
+///
+/// Damaged boxes end the walk of their parent and are reported as errors in
+/// `diagnostics`: an unreadable header, a size smaller than the header or a
+/// box running past its parent.
 pub(crate) fn build_bmff_tree<R: Read + Seek + ?Sized>(
     reader: &mut R,
     end: u64,
     bmff_tree: &mut Arena<BoxInfo>,
     current_node: &Token,
     bmff_path_map: &mut HashMap<String, Vec<Token>>,
+    max_depth: usize,
+    diagnostics: &mut Vec<Diagnostic>,
 ) -> Result<()> {
     let start = reader.stream_position()?;
+    if max_depth == 0 {
+        return Err(Error::LimitExceeded {
+            offset: start,
+            reason: "BMFF boxes nested too deeply".into(),
+        });
+    }
+    let parent_path = path_from_token(bmff_tree, current_node)?;
 
     let mut current = start;
     while current < end {
         // Get box header.
-        let header = BoxHeaderLite::read(reader)
-            .map_err(|err| Error::InvalidFormat(format!("Bad BMFF: {}", err)))?;
+        let header = match BoxHeaderLite::read(reader) {
+            Ok(header) => header,
+            Err(_) => {
+                diagnostics.push(Diagnostic::error(
+                    current,
+                    parent_path.clone(),
+                    "Truncated box header",
+                ));
+                break;
+            }
+        };
+        let path = format!("{}/{}", parent_path.trim_end_matches('/'), header.fourcc);
 
         // Break if size zero BoxHeader
         let s = header.size;
         if s == 0 {
             break;
         }
+        let header_size = if header.large_size {
+            HEADER_SIZE_LARGE
+        } else {
+            HEADER_SIZE
+        };
+        if s < header_size {
+            diagnostics.push(Diagnostic::error(
+                current,
+                path,
+                format!("Box size {} is smaller than its header", s),
+            ));
+            break;
+        }
 
         // Reject boxes whose size would overflow u64 or extend past the parent boundary.
         // `current` equals `box_start(...)` since BoxHeaderLite::read() consumes exactly
         // HEADER_SIZE / HEADER_SIZE_LARGE bytes and box_start subtracts that back out.
-        let box_end = match current.checked_add(s) {
-            Some(e) => e,
-            None => break, // malformed: size overflows u64
-        };
+        let box_end = current.saturating_add(s);
         if box_end > end {
-            break; // box claims to extend past our boundary
+            diagnostics.push(Diagnostic::error(
+                current,
+                path,
+                format!("Box size {} runs past its parent (ends at {})", s, end),
+            ));
+            break;
         }
 
-        // Match and parse the supported atom boxes.
-        match header.name {
-            BoxType::UuidBox => {
-                let start = box_start(reader, header.large_size)?;
+        // Report errors at this box, unless they already name a box inside it
+        let fourcc = header.fourcc.clone();
+        add_box(
+            reader,
+            header,
+            bmff_tree,
+            current_node,
+            bmff_path_map,
+            max_depth,
+            diagnostics,
+        )
+        .map_err(|e| {
+            e.at(
+                current,
+                indexed_path(bmff_tree, current_node, &fourcc, current),
+            )
+        })?;
+        current = reader.stream_position()?;
+    }
+
+    Ok(())
+}
+
+/// Add a box whose header was just read to the tree, with its children
+fn add_box<R: Read + Seek + ?Sized>(
+    reader: &mut R,
+    header: BoxHeaderLite,
+    bmff_tree: &mut Arena<BoxInfo>,
+    current_node: &Token,
+    bmff_path_map: &mut HashMap<String, Vec<Token>>,
+    max_depth: usize,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<()> {
+    let s = header.size;
+    // Match and parse the supported atom boxes.
+    match header.name {
+        BoxType::UuidBox => {
+            let start = box_start(reader, header.large_size)?;
+
+            let mut extended_type = [0u8; 16]; // 16 bytes of UUID
+            reader.read_exact(&mut extended_type)?;
+
+            let (version, flags) = read_box_header_ext(reader)?;
+
+            let b = BoxInfo {
+                path: header.fourcc.clone(),
+                offset: start,
+                size: s,
+                box_type: BoxType::UuidBox,
+                parent: Some(*current_node),
+                user_type: Some(extended_type.to_vec()),
+                version: Some(version),
+                flags: Some(flags),
+                large_size: header.large_size,
+            };
 
-                let mut extended_type = [0u8; 16]; // 16 bytes of UUID
-                reader.read_exact(&mut extended_type)?;
+            let new_token = current_node.append(bmff_tree, b);
 
-                let (version, flags) = read_box_header_ext(reader)?;
+            let path = path_from_token(bmff_tree, &new_token)?;
+            add_token_to_cache(bmff_path_map, path, new_token);
 
-                let b = BoxInfo {
+            // position seek pointer
+            skip_bytes_to(reader, start + s)?;
+        }
+        // container box types
+        BoxType::MoovBox
+        | BoxType::TrakBox
+        | BoxType::MdiaBox
+        | BoxType::MinfBox
+        | BoxType::StblBox
+        | BoxType::MoofBox
+        | BoxType::TrafBox
+        | BoxType::EdtsBox
+        | BoxType::UdtaBox
+        | BoxType::DinfBox
+        | BoxType::TrefBox
+        | BoxType::TregBox
+        | BoxType::MvexBox
+        | BoxType::MfraBox
+        | BoxType::MetaBox
+        | BoxType::SchiBox => {
+            let start = box_start(reader, header.large_size)?;
+
+            let full_box = FULL_BOX_TYPES.contains(&header.fourcc.as_str())
+                && !(header.name == BoxType::MetaBox && is_quicktime_meta(reader)?);
+            let b = if full_box {
+                let (version, flags) = read_box_header_ext(reader)?; // box extensions
+                BoxInfo {
                     path: header.fourcc.clone(),
                     offset: start,
                     size: s,
-                    box_type: BoxType::UuidBox,
+                    box_type: header.name,
                     parent: Some(*current_node),
-                    user_type: Some(extended_type.to_vec()),
+                    user_type: None,
                     version: Some(version),
                     flags: Some(flags),
-                };
-
-                let new_token = current_node.append(bmff_tree, b);
-
-                let path = path_from_token(bmff_tree, &new_token)?;
-                add_token_to_cache(bmff_path_map, path, new_token);
-
-                // position seek pointer
-                skip_bytes_to(reader, start + s)?;
-            }
-            // container box types
-            BoxType::MoovBox
-            | BoxType::TrakBox
-            | BoxType::MdiaBox
-            | BoxType::MinfBox
-            | BoxType::StblBox
-            | BoxType::MoofBox
-            | BoxType::TrafBox
-            | BoxType::EdtsBox
-            | BoxType::UdtaBox
-            | BoxType::DinfBox
-            | BoxType::TrefBox
-            | BoxType::TregBox
-            | BoxType::MvexBox
-            | BoxType::MfraBox
-            | BoxType::MetaBox
-            | BoxType::SchiBox => {
-                let start = box_start(reader, header.large_size)?;
-
-                let b = if FULL_BOX_TYPES.contains(&header.fourcc.as_str()) {
-                    let (version, flags) = read_box_header_ext(reader)?; // box extensions
-                    BoxInfo {
-                        path: header.fourcc.clone(),
-                        offset: start,
-                        size: s,
-                        box_type: header.name,
-                        parent: Some(*current_node),
-                        user_type: None,
-                        version: Some(version),
-                        flags: Some(flags),
-                    }
-                } else {
-                    BoxInfo {
-                        path: header.fourcc.clone(),
-                        offset: start,
-                        size: s,
-                        box_type: header.name,
-                        parent: Some(*current_node),
-                        user_type: None,
-                        version: None,
-                        flags: None,
-                    }
-                };
+                    large_size: header.large_size,
+                }
+            } else {
+                BoxInfo {
+                    path: header.fourcc.clone(),
+                    offset: start,
+                    size: s,
+                    box_type: header.name,
+                    parent: Some(*current_node),
+                    user_type: None,
+                    version: None,
+                    flags: None,
+                    large_size: header.large_size,
+                }
+            };
 
-                let new_token = bmff_tree.new_node(b);
-                current_node
-                    .append_node(bmff_tree, new_token)
-                    .map_err(|_err| Error::InvalidFormat("Bad BMFF Graph".to_string()))?;
+            let new_token = bmff_tree.new_node(b);
+            current_node
+                .append_node(bmff_tree, new_token)
+                .map_err(|_err| Error::InvalidFormat("Bad BMFF Graph".to_string()))?;
+
+            let path = path_from_token(bmff_tree, &new_token)?;
+            add_token_to_cache(bmff_path_map, path, new_token);
+
+            // consume all sub-boxes
+            let mut current = reader.stream_position()?;
+            let end = start + s;
+            while current < end {
+                build_bmff_tree(
+                    reader,
+                    end,
+                    bmff_tree,
+                    &new_token,
+                    bmff_path_map,
+                    max_depth - 1,
+                    diagnostics,
+                )?;
+                current = reader.stream_position()?;
+            }
 
-                let path = path_from_token(bmff_tree, &new_token)?;
-                add_token_to_cache(bmff_path_map, path, new_token);
+            // position seek pointer
+            skip_bytes_to(reader, start + s)?;
+        }
+        _ => {
+            let start = box_start(reader, header.large_size)?;
 
-                // consume all sub-boxes
-                let mut current = reader.stream_position()?;
-                let end = start + s;
-                while current < end {
-                    build_bmff_tree(reader, end, bmff_tree, &new_token, bmff_path_map)?;
-                    current = reader.stream_position()?;
+            let b = if FULL_BOX_TYPES.contains(&header.fourcc.as_str()) {
+                let (version, flags) = read_box_header_ext(reader)?; // box extensions
+                BoxInfo {
+                    path: header.fourcc.clone(),
+                    offset: start,
+                    size: s,
+                    box_type: header.name,
+                    parent: Some(*current_node),
+                    user_type: None,
+                    version: Some(version),
+                    flags: Some(flags),
+                    large_size: header.large_size,
                 }
+            } else {
+                BoxInfo {
+                    path: header.fourcc.clone(),
+                    offset: start,
+                    size: s,
+                    box_type: header.name,
+                    parent: Some(*current_node),
+                    user_type: None,
+                    version: None,
+                    flags: None,
+                    large_size: header.large_size,
+                }
+            };
 
-                // position seek pointer
-                skip_bytes_to(reader, start + s)?;
-            }
-            _ => {
-                let start = box_start(reader, header.large_size)?;
-
-                let b = if FULL_BOX_TYPES.contains(&header.fourcc.as_str()) {
-                    let (version, flags) = read_box_header_ext(reader)?; // box extensions
-                    BoxInfo {
-                        path: header.fourcc.clone(),
-                        offset: start,
-                        size: s,
-                        box_type: header.name,
-                        parent: Some(*current_node),
-                        user_type: None,
-                        version: Some(version),
-                        flags: Some(flags),
-                    }
-                } else {
-                    BoxInfo {
-                        path: header.fourcc.clone(),
-                        offset: start,
-                        size: s,
-                        box_type: header.name,
-                        parent: Some(*current_node),
-                        user_type: None,
-                        version: None,
-                        flags: None,
-                    }
-                };
-
-                let new_token = current_node.append(bmff_tree, b);
+            let new_token = current_node.append(bmff_tree, b);
 
-                let path = path_from_token(bmff_tree, &new_token)?;
-                add_token_to_cache(bmff_path_map, path, new_token);
+            let path = path_from_token(bmff_tree, &new_token)?;
+            add_token_to_cache(bmff_path_map, path, new_token);
 
-                // position seek pointer
-                skip_bytes_to(reader, start + s)?;
-            }
+            // position seek pointer
+            skip_bytes_to(reader, start + s)?;
         }
-        current = reader.stream_position()?;
     }
-
     Ok(())
 }
 
+/// Path of the box of type `fourcc` at `offset` under `parent`, e.g. `/moov/trak[1]/mdia`
+///
+/// `[n]` marks the n-th (from zero) of several boxes of the same type and is
+/// omitted for the first.
+fn indexed_path(bmff_tree: &Arena<BoxInfo>, parent: &Token, fourcc: &str, offset: u64) -> String {
+    let info = &bmff_tree[*parent].data;
+    let prefix = match info.parent {
+        Some(grandparent) => indexed_path(bmff_tree, &grandparent, &info.path, info.offset),
+        None => String::new(),
+    };
+    let index = parent
+        .children(bmff_tree)
+        .filter(|child| child.data.path == fourcc && child.data.offset < offset)
+        .count();
+    if index == 0 {
+        format!("{}/{}", prefix, fourcc)
+    } else {
+        format!("{}/{}[{}]", prefix, fourcc, index)
+    }
+}
+
+/// End of the last top-level box found by [`build_bmff_tree`]
+fn parsed_end(bmff_tree: &Arena<BoxInfo>, root: &Token) -> u64 {
+    root.children_tokens(bmff_tree)
+        .last()
+        .map(|token| bmff_tree[token].data.offset + bmff_tree[token].data.size)
+        .unwrap_or(0)
+}
+
+/// Copy the children of `token` into the public box tree, depth-first
+fn add_to_public_tree(
+    bmff_tree: &Arena<BoxInfo>,
+    token: Token,
+    parent: Option<BoxId>,
+    tree: &mut BmffTree,
+) {
+    for child in token.children_tokens(bmff_tree) {
+        let info = &bmff_tree[child].data;
+        let header_size = if info.large_size {
+            HEADER_SIZE_LARGE
+        } else {
+            HEADER_SIZE
+        };
+        let user_type = info
+            .user_type
+            .as_deref()
+            .and_then(|uuid| <[u8; 16]>::try_from(uuid).ok());
+        let id = tree.push(
+            parent,
+            BmffBox::new(
+                info.path.clone(),
+                info.offset,
+                info.size,
+                header_size,
+                user_type,
+                info.version.zip(info.flags),
+            ),
+        );
+        add_to_public_tree(bmff_tree, child, Some(id), tree);
+    }
+}
+
 /// Adjust chunk offsets (stco/co64) in a BMFF file after modifying the UUID region.
 ///
 /// When adding, removing, or replacing XMP or C2PA boxes after ftyp, moov/mdat shift;
@@ -560,10 +720,19 @@ pub fn bmff_adjust_chunk_offsets<R: Read + Write + Seek>(stream: &mut R, delta:
         user_type: None,
         version: None,
         flags: None,
+        large_size: false,
     };
     let (mut bmff_tree, root_token) = Arena::with_data(root_box);
     let mut bmff_map: HashMap<String, Vec<Token>> = HashMap::new();
-    build_bmff_tree(stream, size, &mut bmff_tree, &root_token, &mut bmff_map)?;
+    build_bmff_tree(
+        stream,
+        size,
+        &mut bmff_tree,
+        &root_token,
+        &mut bmff_map,
+        Limits::default().max_box_depth,
+        &mut Vec::new(),
+    )?;
 
     adjust_chunk_offsets(stream, &bmff_tree, &bmff_map, delta)
 }
@@ -601,11 +770,10 @@ fn adjust_chunk_offsets<W: Read + Write + Seek>(
             for _ in 0..entry_count {
                 let entry_pos = output.stream_position()?;
                 let offset = output.read_u32::<BigEndian>()?;
-                let new_offset = if adjust > 0 {
-                    offset.saturating_add(adjust as u32)
-                } else {
-                    offset.saturating_sub((-adjust) as u32)
-                };
+                // The box cannot grow here; in-memory rewrites promote it to co64 instead
+                let new_offset = u32::try_from(offset as i64 + adjust).map_err(|_| {
+                    Error::CapacityExceeded("Chunk offset overflow in stco box".into())
+                })?;
                 // Seek back to the start of this entry and overwrite it in place.
                 output.seek(SeekFrom::Start(entry_pos))?;
                 output.write_u32::<BigEndian>(new_offset)?;
@@ -649,6 +817,37 @@ fn adjust_chunk_offsets<W: Read + Write + Seek>(
     Ok(())
 }
 
+/// Change in size of the XMP and C2PA `uuid` boxes written after ftyp
+///
+/// With `xmp_as_item`, new XMP goes into a HEIF item instead of a `uuid` box.
+fn uuid_region_delta(
+    bmff_tree: &Arena<BoxInfo>,
+    existing_xmp_token: Option<Token>,
+    existing_c2pa_token: Option<Token>,
+    updates: &Updates,
+    xmp_as_item: bool,
+) -> i64 {
+    let existing_size = |token: Option<Token>| token.map(|t| bmff_tree[t].data.size).unwrap_or(0);
+
+    // Kept boxes are moved up front rather than copied twice
+    let original_size = existing_size(existing_xmp_token) + existing_size(existing_c2pa_token);
+    let new_size = {
+        let xmp = match &updates.xmp {
+            MetadataUpdate::Set(_) if xmp_as_item => 0,
+            MetadataUpdate::Set(data) => calculate_xmp_box_size(data),
+            MetadataUpdate::Remove => 0,
+            MetadataUpdate::Keep => existing_size(existing_xmp_token),
+        };
+        let c2pa = match &updates.jumbf {
+            MetadataUpdate::Set(data) => calculate_c2pa_box_size(data, "manifest"),
+            MetadataUpdate::Remove => 0,
+            MetadataUpdate::Keep => existing_size(existing_c2pa_token),
+        };
+        xmp + c2pa
+    };
+    new_size as i64 - original_size as i64
+}
+
 /// Get all UUID box tokens from the map.
 /// UUID boxes can be at root ("/uuid") or inside meta ("/meta/uuid") in HEIC/HEIF.
 fn uuid_tokens_from_map(
@@ -684,14 +883,19 @@ fn get_uuid_token(
 
 /// Detect media type from ftyp box
 fn detect_media_type_from_ftyp(major_brand: &[u8]) -> MediaType {
-    match major_brand {
-        b"heic" | b"heix" | b"heim" | b"heis" => MediaType::Heic,
-        b"avif" | b"avis" => MediaType::Avif,
-        b"mif1" | b"msf1" => MediaType::Heif,
-        b"isom" | b"mp41" | b"mp42" => MediaType::Mp4Video,
-        b"M4A " | b"M4B " => MediaType::Mp4Audio,
-        b"qt  " => MediaType::QuickTime,
-        _ => MediaType::Mp4Video, // Default fallback
+    media_type_for_brand(major_brand).unwrap_or(MediaType::Mp4Video) // Default fallback
+}
+
+/// Media type an ftyp brand names, if it is one we know
+fn media_type_for_brand(brand: &[u8]) -> Option<MediaType> {
+    match brand {
+        b"heic" | b"heix" | b"heim" | b"heis" => Some(MediaType::Heic),
+        b"avif" | b"avis" => Some(MediaType::Avif),
+        b"mif1" | b"msf1" => Some(MediaType::Heif),
+        b"isom" | b"mp41" | b"mp42" => Some(MediaType::Mp4Video),
+        b"M4A " | b"M4B " => Some(MediaType::Mp4Audio),
+        b"qt  " => Some(MediaType::QuickTime),
+        _ => None,
     }
 }
 
@@ -819,12 +1023,374 @@ fn collect_fragments<R: Read + Seek>(
     Ok(fragments)
 }
 
+/// Offset within an mdat box where Merkle-hashed data begins
+///
+/// Matches the BmffHash exclusion `/mdat` with subset offset 16: the first 16 bytes
+/// of each mdat box go into the main hash, everything after them into the Merkle tree.
+#[cfg(feature = "parallel")]
+const MDAT_MERKLE_OFFSET: u64 = 16;
+
+/// How mdat data is split into Merkle leaves
+#[cfg(feature = "parallel")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MerkleBlocks {
+    /// Blocks of this many bytes; the last block of each mdat may be shorter
+    /// (BmffHash `fixed_block_size`)
+    Fixed(u64),
+    /// One block per media chunk listed in the moov `stco`/`co64` tables
+    /// (BmffHash `variable_block_sizes`)
+    ///
+    /// Chunks start where their offsets fall inside the mdat box, and the first
+    /// block starts 16 bytes into the box. An mdat without chunk offsets, such as
+    /// the media data of each fragment in a fragmented file, is a single block.
+    Chunks,
+}
+
+/// Merkle map for a single mdat box (C2PA BmffHash `MerkleMap`)
+///
+/// Leaves are the hashes of consecutive blocks of the mdat box, starting 16 bytes
+/// into the box. Exactly one of `fixed_block_size` and `variable_block_sizes` is set,
+/// depending on the [`MerkleBlocks`] the map was built with.
+#[cfg(feature = "parallel")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BmffMerkleMap {
+    /// Index of the mdat box among all top-level mdat boxes (0, 1, 2, ...)
+    pub local_id: usize,
+    /// Number of leaves
+    pub count: usize,
+    /// Size of each hashed block in bytes (the last block may be shorter)
+    pub fixed_block_size: Option<u64>,
+    /// Size of every hashed block in bytes, in file order
+    pub variable_block_sizes: Option<Vec<u64>>,
+    /// Leaf hashes in file order
+    pub leaves: Vec<[u8; 32]>,
+    /// Merkle root, with odd nodes promoted as BmffHash defines
+    /// (see [`MerkleOddNode::Promote`](crate::MerkleOddNode::Promote))
+    pub root: [u8; 32],
+}
+
+/// Merkle maps for all mdat boxes plus the init segment hash
+#[cfg(feature = "parallel")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BmffMerkleMaps {
+    /// Hash of the init segment: every top-level box before the first `moof` or
+    /// `mdat`, excluding any C2PA `uuid` box (so re-signing does not change it)
+    pub init_hash: [u8; 32],
+    /// One map per mdat box that has data past the first 16 bytes
+    pub maps: Vec<BmffMerkleMap>,
+}
+
+/// Leaf sizes of the mdat box being hashed
+#[cfg(feature = "parallel")]
+enum BlockSizes {
+    Fixed(u64),
+    /// Planned sizes of the blocks of each mdat box, by mdat id
+    Chunks(Vec<Vec<u64>>),
+}
+
+/// The mdat box a [`MerkleMapBuilder`] is currently hashing
+#[cfg(feature = "parallel")]
+struct CurrentMap<H> {
+    id: usize,
+    hasher: H,
+    filled: u64,
+    leaves: Vec<[u8; 32]>,
+    sizes: Vec<u64>,
+}
+
+/// Incrementally splits mdat data into blocks and hashes them
+///
+/// Fed with the same `(mdat id, bytes)` pairs that [`MdatChunk`] carries, so the
+/// read path and the write path produce identical maps.
+#[cfg(feature = "parallel")]
+pub(crate) struct MerkleMapBuilder<H> {
+    blocks: BlockSizes,
+    current: Option<CurrentMap<H>>,
+    maps: Vec<BmffMerkleMap>,
+}
+
+#[cfg(feature = "parallel")]
+impl<H> MerkleMapBuilder<H>
+where
+    H: sha2::Digest<OutputSize = sha2::digest::consts::U32> + Default,
+{
+    /// Start a builder; `source` is read for chunk offsets with [`MerkleBlocks::Chunks`]
+    ///
+    /// Block boundaries are relative to each mdat box, so the same builder serves any
+    /// output that copies the source's mdat boxes unchanged.
+    pub(crate) fn new<R: Read + Seek + ?Sized>(
+        blocks: MerkleBlocks,
+        source: &mut R,
+        limits: &Limits,
+    ) -> Result<Self> {
+        let blocks = match blocks {
+            MerkleBlocks::Fixed(0) => {
+                return Err(Error::InvalidFormat(
+                    "Merkle block size must be greater than zero".into(),
+                ))
+            }
+            MerkleBlocks::Fixed(size) => BlockSizes::Fixed(size),
+            MerkleBlocks::Chunks => BlockSizes::Chunks(mdat_chunk_sizes(source, limits)?),
+        };
+        Ok(Self {
+            blocks,
+            current: None,
+            maps: Vec::new(),
+        })
+    }
+
+    /// Add mdat bytes for the box with the given id
+    pub(crate) fn update(&mut self, id: usize, mut data: &[u8]) {
+        if self.current.as_ref().map(|current| current.id) != Some(id) {
+            self.finish_current();
+            self.current = Some(CurrentMap {
+                id,
+                hasher: H::new(),
+                filled: 0,
+                leaves: Vec::new(),
+                sizes: Vec::new(),
+            });
+        }
+        let Some(current) = self.current.as_mut() else {
+            return;
+        };
+        while !data.is_empty() {
+            // Data past the planned chunks all goes into the last block
+            let block_size = match &self.blocks {
+                BlockSizes::Fixed(size) => *size,
+                BlockSizes::Chunks(sizes) => sizes
+                    .get(id)
+                    .and_then(|sizes| sizes.get(current.leaves.len()))
+                    .copied()
+                    .unwrap_or(u64::MAX),
+            };
+            let take = (block_size - current.filled).min(data.len() as u64) as usize;
+            current.hasher.update(&data[..take]);
+            current.filled += take as u64;
+            data = &data[take..];
+            if current.filled == block_size {
+                current.finish_leaf();
+            }
+        }
+    }
+
+    fn finish_current(&mut self) {
+        if let Some(mut current) = self.current.take() {
+            if current.filled > 0 {
+                current.finish_leaf();
+            }
+            if !current.leaves.is_empty() {
+                let (fixed_block_size, variable_block_sizes) = match self.blocks {
+                    BlockSizes::Fixed(size) => (Some(size), None),
+                    BlockSizes::Chunks(_) => (None, Some(current.sizes)),
+                };
+                self.maps.push(BmffMerkleMap {
+                    local_id: current.id,
+                    count: current.leaves.len(),
+                    fixed_block_size,
+                    variable_block_sizes,
+                    root: crate::MerkleTree::<H>::new(
+                        &current.leaves,
+                        crate::MerkleOddNode::Promote,
+                    )
+                    .root(),
+                    leaves: current.leaves,
+                });
+            }
+        }
+    }
+
+    /// Finish the last block and return one map per mdat box
+    pub(crate) fn finish(mut self) -> Vec<BmffMerkleMap> {
+        self.finish_current();
+        self.maps
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<H> CurrentMap<H>
+where
+    H: sha2::Digest<OutputSize = sha2::digest::consts::U32> + Default,
+{
+    fn finish_leaf(&mut self) {
+        self.leaves
+            .push(finalize_32(std::mem::take(&mut self.hasher)));
+        self.sizes.push(std::mem::take(&mut self.filled));
+    }
+}
+
+/// Sizes of the media chunks in each top-level mdat box, by mdat id
+///
+/// Blocks cover each mdat from 16 bytes into the box to its end, split at every
+/// chunk offset from the moov `stco`/`co64` tables that falls inside that range.
+#[cfg(feature = "parallel")]
+fn mdat_chunk_sizes<R>(source: &mut R, limits: &Limits) -> Result<Vec<Vec<u64>>>
+where
+    R: Read + Seek + ?Sized,
+{
+    let file_size = source.seek(SeekFrom::End(0))?;
+    let mut mdats = Vec::new();
+    let mut moov = None;
+    let mut pos = 0u64;
+    while pos + HEADER_SIZE <= file_size {
+        source.seek(SeekFrom::Start(pos))?;
+        let header = BoxHeaderLite::read(source)?;
+        if header.size < HEADER_SIZE {
+            break;
+        }
+        let box_end = pos.saturating_add(header.size).min(file_size);
+        match header.name {
+            BoxType::MdatBox => mdats.push((pos + MDAT_MERKLE_OFFSET, box_end)),
+            BoxType::MoovBox if moov.is_none() => moov = Some((pos, box_end - pos)),
+            _ => {}
+        }
+        pos = box_end;
+    }
+
+    let mut offsets = Vec::new();
+    if let Some((offset, size)) = moov {
+        limits.check_segment_size(offset, size, "moov box")?;
+        let mut data = vec![0u8; size as usize];
+        source.seek(SeekFrom::Start(offset))?;
+        source.read_exact(&mut data)?;
+        for table in chunk_offset_tables(&data)? {
+            offsets.extend(table.offsets(&data));
+        }
+    }
+    offsets.sort_unstable();
+    offsets.dedup();
+
+    Ok(mdats
+        .into_iter()
+        .map(|(start, end)| {
+            let first = offsets.partition_point(|&o| o <= start);
+            let last = offsets.partition_point(|&o| o < end);
+            let mut sizes = Vec::new();
+            let mut block_start = start;
+            for &offset in &offsets[first..last] {
+                sizes.push(offset - block_start);
+                block_start = offset;
+            }
+            if block_start < end {
+                sizes.push(end - block_start);
+            }
+            sizes
+        })
+        .collect())
+}
+
+#[cfg(feature = "parallel")]
+fn finalize_32<H: sha2::Digest<OutputSize = sha2::digest::consts::U32>>(hasher: H) -> [u8; 32] {
+    hasher.finalize().into()
+}
+
+/// Hash the init segment of a BMFF stream (see [`BmffMerkleMaps::init_hash`])
+#[cfg(feature = "parallel")]
+pub(crate) fn bmff_init_hash<R, H>(source: &mut R) -> Result<[u8; 32]>
+where
+    R: Read + Seek + ?Sized,
+    H: sha2::Digest<OutputSize = sha2::digest::consts::U32> + Default,
+{
+    let file_size = source.seek(SeekFrom::End(0))?;
+    let mut hasher = H::new();
+    let mut pos = 0u64;
+    let mut buffer = vec![0u8; crate::segment::DEFAULT_CHUNK_SIZE];
+
+    while pos + HEADER_SIZE <= file_size {
+        source.seek(SeekFrom::Start(pos))?;
+        let header = BoxHeaderLite::read(source)?;
+        if header.size < HEADER_SIZE || matches!(header.name, BoxType::MdatBox | BoxType::MoofBox) {
+            break;
+        }
+
+        let is_c2pa = header.name == BoxType::UuidBox && {
+            let mut uuid = [0u8; 16];
+            source.read_exact(&mut uuid)?;
+            uuid == C2PA_UUID
+        };
+
+        if !is_c2pa {
+            source.seek(SeekFrom::Start(pos))?;
+            let mut remaining = header.size.min(file_size - pos);
+            while remaining > 0 {
+                let n = remaining.min(buffer.len() as u64) as usize;
+                source.read_exact(&mut buffer[..n])?;
+                hasher.update(&buffer[..n]);
+                remaining -= n as u64;
+            }
+        }
+        pos += header.size;
+    }
+
+    Ok(finalize_32(hasher))
+}
+
+/// Compute Merkle maps for every top-level mdat box in one streaming pass
+#[cfg(feature = "parallel")]
+pub(crate) fn bmff_merkle_maps<R, H>(
+    source: &mut R,
+    blocks: MerkleBlocks,
+    limits: &Limits,
+) -> Result<BmffMerkleMaps>
+where
+    R: Read + Seek + ?Sized,
+    H: sha2::Digest<OutputSize = sha2::digest::consts::U32> + Default,
+{
+    let mut builder = MerkleMapBuilder::<H>::new(blocks, source, limits)?;
+    let init_hash = bmff_init_hash::<R, H>(source)?;
+
+    let file_size = source.seek(SeekFrom::End(0))?;
+    let mut pos = 0u64;
+    let mut mdat_id = 0usize;
+    let mut buffer = vec![0u8; crate::segment::DEFAULT_CHUNK_SIZE];
+
+    while pos + HEADER_SIZE <= file_size {
+        source.seek(SeekFrom::Start(pos))?;
+        let header = BoxHeaderLite::read(source)?;
+        if header.size < HEADER_SIZE {
+            break;
+        }
+        let box_end = pos.saturating_add(header.size).min(file_size);
+
+        if header.name == BoxType::MdatBox {
+            let start = pos + MDAT_MERKLE_OFFSET;
+            if start < box_end {
+                source.seek(SeekFrom::Start(start))?;
+                let mut remaining = box_end - start;
+                while remaining > 0 {
+                    let n = remaining.min(buffer.len() as u64) as usize;
+                    source.read_exact(&mut buffer[..n])?;
+                    builder.update(mdat_id, &buffer[..n]);
+                    remaining -= n as u64;
+                }
+            }
+            mdat_id += 1;
+        }
+        pos = box_end;
+    }
+
+    Ok(BmffMerkleMaps {
+        init_hash,
+        maps: builder.finish(),
+    })
+}
+
 /// BMFF container I/O implementation
-pub struct BmffIO;
+pub struct BmffIO {
+    limits: Limits,
+}
 
 impl BmffIO {
     pub fn new() -> Self {
-        Self
+        Self {
+            limits: Limits::default(),
+        }
+    }
+
+    /// Replace the limits enforced while parsing and reading
+    pub(crate) fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
     }
 
     #[allow(dead_code)]
@@ -918,6 +1484,7 @@ impl BmffIO {
             user_type: None,
             version: None,
             flags: None,
+            large_size: false,
         };
 
         let (mut bmff_tree, root_token) = Arena::with_data(root_box);
@@ -929,6 +1496,8 @@ impl BmffIO {
             &mut bmff_tree,
             &root_token,
             &mut bmff_map,
+            Limits::default().max_box_depth,
+            &mut Vec::new(),
         )?;
 
         // Look for moof boxes
@@ -982,23 +1551,55 @@ impl BmffIO {
             user_type: None,
             version: None,
             flags: None,
+            large_size: false,
         };
 
         let (mut bmff_tree, root_token) = Arena::with_data(root_box);
         let mut bmff_map: HashMap<String, Vec<Token>> = HashMap::new();
 
         // Build layout of the BMFF structure
+        let mut diagnostics = Vec::new();
         build_bmff_tree(
             source,
             file_size,
             &mut bmff_tree,
             &root_token,
             &mut bmff_map,
+            self.limits.max_box_depth,
+            &mut diagnostics,
         )?;
 
         // Create structure
         let mut structure = Structure::new(ContainerKind::Bmff, media_type);
-        structure.total_size = file_size;
+        for diagnostic in diagnostics {
+            if diagnostic.severity == Severity::Error {
+                // A lenient parse says how it recovered
+                let reason = if self.limits.strict {
+                    diagnostic.message
+                } else {
+                    format!(
+                        "{}; it and anything after it are dropped",
+                        diagnostic.message
+                    )
+                };
+                self.limits.recover(
+                    &mut structure,
+                    &diagnostic.path,
+                    Error::InvalidSegment {
+                        offset: diagnostic.offset,
+                        reason,
+                    },
+                )?;
+            } else {
+                structure.add_diagnostic(diagnostic);
+            }
+        }
+        // Writes copy the parsed boxes only, dropping damaged trailing data
+        structure.total_size = parsed_end(&bmff_tree, &root_token);
+
+        let mut public_tree = BmffTree::default();
+        add_to_public_tree(&bmff_tree, root_token, None, &mut public_tree);
+        structure.set_bmff_tree(public_tree);
 
         // Find XMP UUID boxes (at /uuid or /meta/uuid in HEIC)
         for uuid_token in uuid_tokens_from_map(&bmff_map) {
@@ -1033,28 +1634,28 @@ impl BmffIO {
                     let version_flags_offset = box_offset + 8 + 16 + 4;
                     source.seek(SeekFrom::Start(version_flags_offset))?;
 
-                    // Read purpose string (null-terminated)
+                    // Read purpose string (null-terminated), which must end before
+                    // the merkle offset and within the segment size limit
+                    let purpose_room = box_size
+                        .saturating_sub(8 + 16 + 4 + 8)
+                        .min(self.limits.max_segment_size);
                     let mut purpose_bytes = Vec::new();
-                    loop {
-                        let mut byte = [0u8; 1];
-                        if source.read_exact(&mut byte).is_err() {
-                            break;
-                        }
-                        if byte[0] == 0 {
-                            break; // Found null terminator
-                        }
-                        purpose_bytes.push(byte[0]);
-                        // Sanity check: purpose string shouldn't be longer than 256 bytes
-                        if purpose_bytes.len() > 256 {
-                            break;
-                        }
+                    std::io::BufReader::new(source.by_ref().take(purpose_room))
+                        .read_until(0, &mut purpose_bytes)?;
+                    if purpose_bytes.pop() != Some(0) {
+                        self.limits.recover(
+                            &mut structure,
+                            "uuid/c2pa",
+                            Error::InvalidSegment {
+                                offset: box_offset,
+                                reason: "C2PA box purpose is not null-terminated".into(),
+                            },
+                        )?;
+                        continue;
                     }
 
-                    // Skip merkle offset (8 bytes)
-                    source.seek(SeekFrom::Current(8))?;
-
-                    // Current position is start of JUMBF data
-                    let data_offset = source.stream_position()?;
+                    // Skip merkle offset (8 bytes); the JUMBF data follows
+                    let data_offset = version_flags_offset + purpose_bytes.len() as u64 + 1 + 8;
                     let header_overhead = data_offset - box_offset;
                     let data_size = box_size.saturating_sub(header_overhead);
 
@@ -1076,6 +1677,41 @@ impl BmffIO {
             }
         }
 
+        // Find item-based XMP and C2PA in the top-level meta box (HEIF).
+        // The meta box itself is kept loaded so that calculate_updated_structure
+        // can predict how it changes on write.
+        let mut diagnostics = Vec::new();
+        let heif_meta = read_heif_meta(
+            source,
+            &bmff_tree,
+            &bmff_map,
+            &self.limits,
+            &mut diagnostics,
+        )?;
+        for diagnostic in diagnostics {
+            structure.add_diagnostic(diagnostic);
+        }
+        if let Some((meta_offset, meta)) = heif_meta {
+            let uuid_xmp = structure.xmp_index();
+            structure.add_segment(
+                Segment::new(
+                    meta_offset,
+                    meta.len(),
+                    SegmentKind::Other,
+                    Some("meta".into()),
+                )
+                .with_data(LazyData::Loaded(meta.bytes().to_vec())),
+            );
+            add_heif_item_segments(&mut structure, &meta, meta_offset)?;
+            // A uuid XMP box takes precedence, as it is what gets rewritten on write
+            if uuid_xmp.is_some() {
+                *structure.xmp_index_mut() = uuid_xmp;
+            }
+        }
+
+        // QuickTime metadata lives in moov/udta and moov/meta
+        add_moov_segments(&mut structure, source, &bmff_tree, &bmff_map, &self.limits)?;
+
         // Find mdat boxes (media data) for V3 Merkle hashing
         // These contain the actual media content (video/audio/image data)
         // NOTE: These are tracked separately from metadata (XMP/JUMBF) and should NOT
@@ -1129,100 +1765,277 @@ impl BmffIO {
 
         Ok(structure)
     }
-}
-
-impl Default for BmffIO {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl ContainerIO for BmffIO {
-    fn container_type() -> ContainerKind {
-        ContainerKind::Bmff
-    }
-
-    fn supported_media_types() -> &'static [MediaType] {
-        Self::supported_media_types()
-    }
-
-    fn extensions() -> &'static [&'static str] {
-        Self::extensions()
-    }
-
-    fn mime_types() -> &'static [&'static str] {
-        Self::mime_types()
-    }
 
-    fn detect(header: &[u8]) -> Option<ContainerKind> {
-        Self::detect(header)
-    }
-
-    fn parse<R: Read + Seek>(&self, source: &mut R) -> Result<Structure> {
-        self.parse_impl(source)
-    }
-
-    fn read_xmp<R: Read + Seek>(
+    /// Check box order, chunk offsets, item locations and C2PA boxes
+    fn validate_impl<R: Read + Seek>(
         &self,
-        _structure: &Structure,
+        structure: &Structure,
         source: &mut R,
-    ) -> Result<Option<Vec<u8>>> {
-        let Some(index) = _structure.xmp_index() else {
-            return Ok(None);
+        report: &mut ValidationReport,
+    ) -> Result<()> {
+        let Some(tree) = structure.bmff_tree() else {
+            return Ok(());
         };
+        let file_len = source.seek(SeekFrom::End(0))?;
 
-        let segment = &_structure.segments()[index];
-        if !segment.is_xmp() {
-            return Ok(None);
+        match tree.top_level().next() {
+            Some(first) if first.box_type() == "ftyp" => {}
+            Some(first) => report.error(first.offset(), first.path(), "ftyp must be the first box"),
+            None => report.error(0, "/", "No boxes found"),
+        }
+        for ftyp in tree.find_all("/ftyp").skip(1) {
+            report.error(ftyp.offset(), ftyp.path(), "Duplicate ftyp box");
+        }
+        if structure.total_size < file_len {
+            report.error(
+                structure.total_size,
+                "/",
+                format!(
+                    "{} bytes after the last complete box",
+                    file_len - structure.total_size
+                ),
+            );
+        }
+
+        // Sample data referenced by the chunk offset tables must lie in an mdat
+        let mdat: Vec<ByteRange> = tree
+            .top_level()
+            .filter(|b| b.box_type() == "mdat")
+            .map(|b| ByteRange::new(b.payload_offset(), b.payload_size()))
+            .collect();
+        let in_mdat = |offset: u64| {
+            mdat.iter()
+                .any(|r| offset >= r.offset && offset < r.end_offset())
+        };
+        for chunk_table in tree
+            .iter()
+            .filter(|b| matches!(b.box_type(), "stco" | "co64"))
+        {
+            if chunk_table.payload_size() > self.limits.max_segment_size {
+                report.warning(
+                    chunk_table.offset(),
+                    chunk_table.path(),
+                    "Chunk offset table too large to check",
+                );
+                continue;
+            }
+            let payload = chunk_table.read_payload(source, self.limits.max_segment_size)?;
+            let entry_size = if chunk_table.box_type() == "co64" {
+                8
+            } else {
+                4
+            };
+            let Some(count) = payload
+                .get(..4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            else {
+                report.error(
+                    chunk_table.offset(),
+                    chunk_table.path(),
+                    "Truncated chunk offset table",
+                );
+                continue;
+            };
+            let entries = &payload[4..];
+            if entries.len() / entry_size < count {
+                report.error(
+                    chunk_table.offset(),
+                    chunk_table.path(),
+                    format!(
+                        "Chunk offset table has room for fewer than {} entries",
+                        count
+                    ),
+                );
+                continue;
+            }
+            let outside: Vec<u64> = entries
+                .chunks_exact(entry_size)
+                .take(count)
+                .map(|e| {
+                    e.iter()
+                        .fold(0u64, |value, byte| (value << 8) | *byte as u64)
+                })
+                .filter(|offset| !in_mdat(*offset))
+                .collect();
+            if let Some(first) = outside.first() {
+                report.error(
+                    chunk_table.offset(),
+                    chunk_table.path(),
+                    format!(
+                        "{} of {} chunk offsets lie outside mdat (first: {})",
+                        outside.len(),
+                        count,
+                        first
+                    ),
+                );
+            }
         }
 
-        // Read XMP data from ranges
-        if segment.ranges.len() == 1 {
-            let range = segment.ranges[0];
-            if range.size > MAX_BOX_ALLOCATION {
-                return Err(Error::InvalidFormat(format!(
-                    "XMP data size too large: {} bytes (max: {} bytes)",
-                    range.size, MAX_BOX_ALLOCATION
-                )));
+        // Items located by iloc must lie in an mdat, or in meta for idat
+        if let Some(meta_box) = tree.find("/meta") {
+            if meta_box.size() <= self.limits.max_segment_size {
+                source.seek(SeekFrom::Start(meta_box.offset()))?;
+                let mut bytes = vec![0u8; meta_box.size() as usize];
+                source.read_exact(&mut bytes)?;
+                if let Some(meta) = heif_meta_with_items(bytes) {
+                    for item in meta.items() {
+                        let Some(ranges) = meta.item_ranges(item.id, meta_box.offset()) else {
+                            continue;
+                        };
+                        let inside = |r: &ByteRange, area: &ByteRange| {
+                            r.offset >= area.offset && r.end_offset() <= area.end_offset()
+                        };
+                        let meta_range = ByteRange::new(meta_box.offset(), meta_box.size());
+                        if let Some(range) = ranges
+                            .iter()
+                            .find(|r| !inside(r, &meta_range) && !mdat.iter().any(|m| inside(r, m)))
+                        {
+                            report.error(
+                                range.offset,
+                                "/meta/iloc",
+                                format!("Item {} data lies outside mdat", item.id),
+                            );
+                        }
+                    }
+                }
             }
-            source.seek(SeekFrom::Start(range.offset))?;
-            let mut xmp_data = vec![0u8; range.size as usize];
-            source.read_exact(&mut xmp_data)?;
-            return Ok(Some(xmp_data));
         }
 
-        Ok(None)
+        // Only one C2PA manifest store is allowed; Merkle boxes may repeat
+        for jumbf in structure
+            .segments
+            .iter()
+            .filter(|s| s.is_jumbf() && s.path.as_deref() == Some("uuid/c2pa/manifest"))
+            .skip(1)
+        {
+            report.error(
+                jumbf.span().offset,
+                "/uuid",
+                "Duplicate C2PA manifest store box",
+            );
+        }
+
+        Ok(())
     }
+}
 
-    fn read_jumbf<R: Read + Seek>(
+impl Default for BmffIO {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContainerIO for BmffIO {
+    fn container_type() -> ContainerKind {
+        ContainerKind::Bmff
+    }
+
+    fn supported_media_types() -> &'static [MediaType] {
+        Self::supported_media_types()
+    }
+
+    fn extensions() -> &'static [&'static str] {
+        Self::extensions()
+    }
+
+    fn mime_types() -> &'static [&'static str] {
+        Self::mime_types()
+    }
+
+    fn detect(header: &[u8]) -> Option<ContainerKind> {
+        Self::detect(header)
+    }
+
+    fn sniff(header: &[u8]) -> Vec<Candidate> {
+        if Self::detect(header).is_none() {
+            return Vec::new();
+        }
+        // The major brand names the media type; compatible brands (after the
+        // minor version) name others the file also conforms to
+        let mut candidates: Vec<Candidate> = Vec::new();
+        let major = header.get(8..12).and_then(media_type_for_brand);
+        if let Some(media_type) = major {
+            candidates.push(Candidate::new(media_type, Confidence::High));
+        }
+        let ftyp_size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let end = ftyp_size.min(header.len());
+        for brand in header.get(16..end).unwrap_or_default().chunks_exact(4) {
+            if let Some(media_type) = media_type_for_brand(brand) {
+                if !candidates.iter().any(|c| c.media_type == media_type) {
+                    candidates.push(Candidate::new(media_type, Confidence::Medium));
+                }
+            }
+        }
+        // Parsing falls back to MP4 video for unknown major brands
+        if major.is_none()
+            && !candidates
+                .iter()
+                .any(|c| c.media_type == MediaType::Mp4Video)
+        {
+            candidates.push(Candidate::new(MediaType::Mp4Video, Confidence::Low));
+        }
+        candidates
+    }
+
+    fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    fn parse<R: Read + Seek>(&self, source: &mut R) -> Result<Structure> {
+        self.parse_impl(source)
+    }
+
+    fn read_xmp<R: Read + Seek>(
         &self,
-        structure: &Structure,
+        _structure: &Structure,
         source: &mut R,
     ) -> Result<Option<Vec<u8>>> {
-        if structure.jumbf_indices().is_empty() {
+        let Some(index) = _structure.xmp_index() else {
+            return Ok(None);
+        };
+
+        let segment = &_structure.segments()[index];
+        if !segment.is_xmp() {
             return Ok(None);
         }
 
-        // For BMFF, we need to parse the C2PA UUID box structure
-        // The JUMBF data is inside the C2PA UUID box
-        for &index in structure.jumbf_indices() {
-            let segment = &structure.segments()[index];
-            if !segment.is_jumbf() {
-                continue;
-            }
+        // uuid boxes hold XMP in one range; items may have several extents
+        read_segment_ranges(source, segment, &self.limits).map(Some)
+    }
 
-            if !segment.ranges.is_empty() {
-                // ranges[0] is the JUMBF data range (data_offset, data_size)
-                // For BMFF parse we have 2 ranges: [data, full_box]; for calculate_updated_structure same
-                let range = segment.ranges[0];
-                source.seek(SeekFrom::Start(range.offset))?;
-                let mut jumbf_data = vec![0u8; range.size as usize];
-                source.read_exact(&mut jumbf_data)?;
-                return Ok(Some(jumbf_data));
-            }
+    fn read_jumbf<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+    ) -> Result<Option<Vec<u8>>> {
+        let Some(index) = structure.c2pa_jumbf_index() else {
+            return Ok(None);
+        };
+        self.read_jumbf_box(structure, source, index).map(Some)
+    }
+
+    fn read_jumbf_box<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        index: usize,
+    ) -> Result<Vec<u8>> {
+        // For BMFF, the JUMBF data is inside the C2PA UUID box or a HEIF item
+        let segment = &structure.segments()[index];
+        if is_heif_item(segment) {
+            // Item extents are concatenated
+            return read_segment_ranges(source, segment, &self.limits);
         }
 
-        Ok(None)
+        // ranges[0] is the JUMBF data range (data_offset, data_size)
+        // For BMFF parse we have 2 ranges: [data, full_box]; for calculate_updated_structure same
+        let range = segment.ranges[0];
+        self.limits
+            .check_segment_size(range.offset, range.size, "JUMBF box")?;
+        source.seek(SeekFrom::Start(range.offset))?;
+        let mut jumbf_data = vec![0u8; range.size as usize];
+        source.read_exact(&mut jumbf_data)?;
+        Ok(jumbf_data)
     }
 
     fn write<R: Read + Seek, W: Read + Write + Seek>(
@@ -1232,7 +2045,43 @@ impl ContainerIO for BmffIO {
         writer: &mut W,
         updates: &Updates,
     ) -> Result<()> {
-        use crate::MetadataUpdate;
+        let fallback_delta = write_boxes(source, writer, updates, true, &self.limits)?;
+
+        // A moov box too large to rewrite in memory is patched in the output
+        if fallback_delta != 0 {
+            writer.flush()?;
+            bmff_adjust_chunk_offsets(writer, fallback_delta)?;
+        }
+        Ok(())
+    }
+
+    fn write_stream<R: Read + Seek, W: Write>(
+        &self,
+        _structure: &Structure,
+        source: &mut R,
+        writer: &mut W,
+        updates: &Updates,
+    ) -> Result<()> {
+        write_boxes(source, writer, updates, false, &self.limits)?;
+        Ok(())
+    }
+
+    fn write_with_processor<R: Read + Seek, W: Read + Write + Seek, F>(
+        &self,
+        _structure: &Structure,
+        source: &mut R,
+        writer: &mut W,
+        updates: &Updates,
+        processor: &mut F,
+    ) -> Result<()>
+    where
+        F: crate::ProcessChunkFn,
+    {
+        use crate::processing_writer::ProcessingWriter;
+
+        let exclude_segments = &updates.processing.exclude_segments;
+        let _exclusion_mode = updates.processing.exclusion_mode;
+        let should_exclude_jumbf = exclude_segments.contains(&SegmentKind::Jumbf);
 
         source.seek(SeekFrom::Start(0))?;
 
@@ -1250,6 +2099,7 @@ impl ContainerIO for BmffIO {
             user_type: None,
             version: None,
             flags: None,
+            large_size: false,
         };
 
         let (mut bmff_tree, root_token) = Arena::with_data(root_box);
@@ -1260,7 +2110,11 @@ impl ContainerIO for BmffIO {
             &mut bmff_tree,
             &root_token,
             &mut bmff_map,
+            self.limits.max_box_depth,
+            &mut Vec::new(),
         )?;
+        // Copy the parsed boxes only, dropping damaged trailing data
+        let file_size = parsed_end(&bmff_tree, &root_token);
 
         // Find ftyp box (required to be first)
         let ftyp_token = bmff_map
@@ -1297,19 +2151,85 @@ impl ContainerIO for BmffIO {
                 .unwrap_or(false)
         });
 
+        // HEIF files keep XMP as an item in the top-level meta box, and movies keep
+        // chunk offsets in moov. Both are rewritten whenever their contents change or
+        // the data they point to moves.
+        let heif_meta =
+            read_heif_meta(source, &bmff_tree, &bmff_map, &self.limits, &mut Vec::new())?;
+        let write_xmp_uuid = write_xmp && heif_meta.is_none();
+        let kept_xmp = existing_xmp_token.map(|t| bmff_tree[t].data.offset);
+        let kept_c2pa = existing_c2pa_token.map(|t| bmff_tree[t].data.offset);
+        let uuid_delta = uuid_region_delta(
+            &bmff_tree,
+            existing_xmp_token,
+            existing_c2pa_token,
+            updates,
+            heif_meta.is_some(),
+        );
+        let rewrites = plan_box_rewrites(
+            source,
+            &bmff_tree,
+            &bmff_map,
+            heif_meta.as_ref(),
+            updates,
+            ftyp_end,
+            uuid_delta,
+            &self.limits,
+        )?;
+
+        // Wrap writer in ProcessingWriter
+        let mut pw = ProcessingWriter::new(writer, processor);
+
+        // BMFF V3 hashing: For each non-excluded top-level box the hash covers:
+        //   8-byte big-endian output offset  ||  box content minus any subset exclusions
+        // mdat is partially excluded (bytes 16+) and those bytes go to the Merkle tree instead.
+        let use_bmff_v2 = should_exclude_jumbf;
+
+        // Collect top-level offsets of boxes that are included in the main hash.
+        // Fully-excluded boxes (ftyp, C2PA UUID, mfra, free, skip) are omitted.
+        // mdat is kept because it is only partially excluded (subset offset 16).
+        let mut top_level_offsets = Vec::new();
+        if use_bmff_v2 {
+            for (path, tokens) in &bmff_map {
+                if path.starts_with('/') && path.matches('/').count() == 1 && path.len() > 1 {
+                    for token in tokens {
+                        let box_info = &bmff_tree[*token];
+                        let offset = box_info.data.offset;
+
+                        // These box types are fully excluded from the V3 hash.
+                        let is_ftyp = path == "/ftyp";
+                        let is_mfra = path == "/mfra";
+                        let is_free = path == "/free";
+                        let is_skip = path == "/skip";
+                        let is_c2pa_uuid = path == "/uuid"
+                            && box_info
+                                .data
+                                .user_type
+                                .as_ref()
+                                .map(|uuid| uuid.as_slice() == C2PA_UUID)
+                                .unwrap_or(false);
+
+                        if !is_ftyp && !is_mfra && !is_free && !is_skip && !is_c2pa_uuid {
+                            top_level_offsets.push(offset);
+                        }
+                    }
+                }
+            }
+            top_level_offsets.sort();
+            top_level_offsets.dedup();
+        }
+
         // Simple strategy: Copy up to ftyp end, insert/skip UUIDs, copy rest
         source.seek(SeekFrom::Start(0))?;
 
-        // Copy ftyp box
-        if ftyp_end > MAX_BOX_ALLOCATION {
-            return Err(Error::InvalidFormat(format!(
-                "ftyp box size too large: {} bytes (max: {} bytes)",
-                ftyp_end, MAX_BOX_ALLOCATION
-            )));
-        }
+        // ftyp is fully excluded from the hash (not in top_level_offsets).
         let mut buffer = vec![0u8; ftyp_end as usize];
         source.read_exact(&mut buffer)?;
-        writer.write_all(&buffer)?;
+        pw.set_exclude_mode(true);
+        pw.write_all(&buffer)?;
+        pw.set_exclude_mode(false);
+
+        let xmp_box_start = ftyp_end;
 
         // ===== C2PA Spec Compliance: Box Ordering =====
         // Per C2PA spec A.5.1: XMP UUID boxes SHALL be placed immediately after ftyp
@@ -1317,52 +2237,92 @@ impl ContainerIO for BmffIO {
         // Order: ftyp → XMP (optional) → C2PA (optional) → other boxes
 
         // Write new XMP UUID if needed
-        if write_xmp {
+        if write_xmp_uuid {
             if let MetadataUpdate::Set(ref xmp_data) = updates.xmp {
-                write_xmp_box(writer, xmp_data)?;
+                // V3: hash = output_offset || box_content. Emit the offset then let
+                // the box content flow through the processor naturally (no exclude_mode).
+                if use_bmff_v2 {
+                    let out_offset = pw.stream_position()?;
+                    pw.process_offset(out_offset)?;
+                }
+                write_xmp_box(&mut pw, xmp_data)?;
             }
-        } else if !remove_xmp {
+        } else if !write_xmp && !remove_xmp {
             // Keep existing XMP
             if let Some(token) = existing_xmp_token {
                 let box_info = &bmff_tree[token].data;
-                if box_info.size > MAX_BOX_ALLOCATION {
-                    return Err(Error::InvalidFormat(format!(
-                        "XMP box size too large: {} bytes (max: {} bytes)",
-                        box_info.size, MAX_BOX_ALLOCATION
-                    )));
+                if box_info.size > self.limits.max_segment_size {
+                    return Err(Error::LimitExceeded {
+                        offset: box_info.offset,
+                        reason: format!(
+                            "XMP box size too large: {} bytes (max: {} bytes)",
+                            box_info.size, self.limits.max_segment_size
+                        ),
+                    });
                 }
                 source.seek(SeekFrom::Start(box_info.offset))?;
                 let mut box_data = vec![0u8; box_info.size as usize];
                 source.read_exact(&mut box_data)?;
-                writer.write_all(&box_data)?;
+
+                // V3: emit offset then let box content through (no exclude_mode).
+                if use_bmff_v2 {
+                    let out_offset = pw.stream_position()?;
+                    pw.process_offset(out_offset)?;
+                }
+                pw.write_all(&box_data)?;
             }
         }
 
+        // Calculate C2PA box start position (for future use if needed)
+        let _c2pa_box_start = if write_xmp_uuid {
+            if let MetadataUpdate::Set(ref xmp_data) = updates.xmp {
+                xmp_box_start + calculate_xmp_box_size(xmp_data)
+            } else {
+                xmp_box_start
+            }
+        } else if let Some(token) = existing_xmp_token {
+            let box_info = &bmff_tree[token].data;
+            box_info.offset + box_info.size
+        } else {
+            xmp_box_start
+        };
+
         // Write new C2PA UUID if needed
         if write_jumbf {
             if let MetadataUpdate::Set(ref jumbf_data) = updates.jumbf {
-                write_c2pa_box(writer, jumbf_data, "manifest", &[], 0)?;
+                // For C2PA: Always exclude from hash (required by BmffHash spec)
+                // For V2: We still exclude it entirely, don't hash offset
+                pw.set_exclude_mode(true);
+                write_c2pa_box(&mut pw, jumbf_data, "manifest", &[], 0)?;
+                pw.set_exclude_mode(false);
             }
         } else if !remove_jumbf {
             // Keep existing C2PA
             if let Some(token) = existing_c2pa_token {
                 let box_info = &bmff_tree[token].data;
-                if box_info.size > MAX_BOX_ALLOCATION {
-                    return Err(Error::InvalidFormat(format!(
-                        "C2PA box size too large: {} bytes (max: {} bytes)",
-                        box_info.size, MAX_BOX_ALLOCATION
-                    )));
+                if box_info.size > self.limits.max_segment_size {
+                    return Err(Error::LimitExceeded {
+                        offset: box_info.offset,
+                        reason: format!(
+                            "C2PA box size too large: {} bytes (max: {} bytes)",
+                            box_info.size, self.limits.max_segment_size
+                        ),
+                    });
                 }
                 source.seek(SeekFrom::Start(box_info.offset))?;
                 let mut box_data = vec![0u8; box_info.size as usize];
                 source.read_exact(&mut box_data)?;
-                writer.write_all(&box_data)?;
+
+                // Exclude C2PA UUID box (always excluded, even in V2)
+                pw.set_exclude_mode(true);
+                pw.write_all(&box_data)?;
+                pw.set_exclude_mode(false);
             }
         }
 
-        // Copy remaining boxes (skip existing UUID boxes)
-        source.seek(SeekFrom::Start(ftyp_end))?;
+        // Copy remaining boxes (skip already-handled UUIDs)
         let mut current_pos = ftyp_end;
+        let mut next_mdat_id = 0usize;
 
         while current_pos < file_size {
             // Read box header to determine if we should skip it
@@ -1375,36 +2335,206 @@ impl ContainerIO for BmffIO {
                 source.read_exact(&mut uuid_bytes)?;
                 source.seek(SeekFrom::Start(box_start))?; // Reset for potential copy
 
-                (uuid_bytes == XMP_UUID && (write_xmp || remove_xmp))
-                    || (uuid_bytes == C2PA_UUID && (write_jumbf || remove_jumbf))
+                // Boxes that were kept have already been moved up front
+                (uuid_bytes == XMP_UUID && (write_xmp || remove_xmp || Some(box_start) == kept_xmp))
+                    || (uuid_bytes == C2PA_UUID
+                        && (write_jumbf || remove_jumbf || Some(box_start) == kept_c2pa))
             } else {
                 false
             };
 
-            if should_skip {
+            let replacement = rewrites.replacements.get(&box_start);
+            if let Some(bytes) = rewrites.insertions.get(&box_start) {
+                // V3: the relocated moov is an ordinary top-level box
+                if use_bmff_v2 {
+                    let out_offset = pw.stream_position()?;
+                    pw.process_offset(out_offset)?;
+                }
+                pw.write_all(bytes)?;
+            }
+
+            if should_skip || rewrites.moved.contains(&box_start) {
                 // Skip this box
                 source.seek(SeekFrom::Start(box_start + header.size))?;
+            } else if let Some(bytes) = replacement {
+                // V3: rewritten boxes are ordinary top-level boxes
+                if use_bmff_v2 && top_level_offsets.contains(&box_start) {
+                    let out_offset = pw.stream_position()?;
+                    pw.process_offset(out_offset)?;
+                }
+                pw.write_all(bytes)?;
             } else {
-                // Copy this box - use streaming for large boxes (mdat, moov, etc.)
+                // Copy this box - use streaming for large boxes
                 source.seek(SeekFrom::Start(box_start))?;
 
-                if header.size > MAX_BOX_ALLOCATION {
+                // Check if this is a top-level box (for V2 hashing)
+                let is_top_level = use_bmff_v2 && top_level_offsets.contains(&box_start);
+
+                // Determine if this box should be excluded from hash
+                let is_excluded_box = matches!(
+                    header.name,
+                    BoxType::MfraBox | BoxType::FreeBox | BoxType::UnknownBox(0x736b6970)
+                );
+
+                if header.size > self.limits.max_segment_size {
                     // Stream large boxes (e.g., mdat) without loading into memory
-                    // This is crucial for large video files where mdat can be gigabytes
                     let mut remaining = header.size;
                     let mut buffer = vec![0u8; 8 * 1024 * 1024]; // 8MB buffer
-
-                    while remaining > 0 {
+                    let is_mdat = header.name == BoxType::MdatBox;
+                    let mdat_id = if is_mdat {
+                        let id = next_mdat_id;
+                        next_mdat_id += 1;
+                        id
+                    } else {
+                        0
+                    };
+                    let mdat_large_size = header.large_size;
+
+                    // V3 hash: emit output offset for non-excluded top-level boxes.
+                    // The box content (or the first 16 bytes for mdat) then flows through
+                    // the processor without exclude_mode for the main hash.
+                    if is_top_level && !is_excluded_box {
+                        let out_offset = pw.stream_position()?;
+                        pw.process_offset(out_offset)?;
+                    }
+
+                    // For mdat: bytes 0-15 go into the main hash; bytes 16+ go to the
+                    // Merkle tree (via process_chunk, excluded from main hash).
+                    // For all other non-excluded top-level boxes: all content goes to
+                    // the main hash (no exclude_mode).
+                    let mut main_hash_remaining: usize =
+                        if is_mdat && is_top_level && !is_excluded_box {
+                            16
+                        } else {
+                            0
+                        };
+
+                    let mut box_pos = 0u64;
+
+                    while remaining > 0 {
                         let to_read = remaining.min(buffer.len() as u64) as usize;
                         source.read_exact(&mut buffer[..to_read])?;
-                        writer.write_all(&buffer[..to_read])?;
+
+                        if is_excluded_box {
+                            pw.set_exclude_mode(true);
+                            pw.write_all(&buffer[..to_read])?;
+                            pw.set_exclude_mode(false);
+                        } else if is_mdat && is_top_level {
+                            // Split the buffer at the 16-byte boundary.
+                            if main_hash_remaining > 0 {
+                                let to_main = main_hash_remaining.min(to_read);
+                                pw.write_all(&buffer[..to_main])?; // → main hash
+                                main_hash_remaining -= to_main;
+
+                                if to_main < to_read {
+                                    let rest = &buffer[to_main..to_read];
+                                    pw.set_exclude_mode(true);
+                                    pw.process_chunk(MdatChunk {
+                                        id: mdat_id,
+                                        data: rest,
+                                        large_size: mdat_large_size,
+                                    })?;
+                                    pw.write_all(rest)?;
+                                    pw.set_exclude_mode(false);
+                                }
+                            } else {
+                                // Entirely past the 16-byte main-hash region → Merkle only.
+                                pw.set_exclude_mode(true);
+                                pw.process_chunk(MdatChunk {
+                                    id: mdat_id,
+                                    data: &buffer[..to_read],
+                                    large_size: mdat_large_size,
+                                })?;
+                                pw.write_all(&buffer[..to_read])?;
+                                pw.set_exclude_mode(false);
+                            }
+                        } else if is_mdat && updates.processing.mdat_chunks {
+                            // Not V3: mdat is hashed normally, but Merkle map builders
+                            // still need bytes 16+ as mdat chunks.
+                            let skip = 16u64.saturating_sub(box_pos).min(to_read as u64) as usize;
+                            if skip < to_read {
+                                pw.process_chunk(MdatChunk {
+                                    id: mdat_id,
+                                    data: &buffer[skip..to_read],
+                                    large_size: mdat_large_size,
+                                })?;
+                            }
+                            pw.write_all(&buffer[..to_read])?;
+                        } else {
+                            // Non-mdat box: write all content through the processor.
+                            pw.write_all(&buffer[..to_read])?;
+                        }
+
+                        box_pos += to_read as u64;
                         remaining -= to_read as u64;
                     }
                 } else {
                     // Small boxes can be loaded for efficiency
                     let mut box_data = vec![0u8; header.size as usize];
                     source.read_exact(&mut box_data)?;
-                    writer.write_all(&box_data)?;
+
+                    // V3 small-box path.
+                    //
+                    // mdat: emit offset, write first 16 bytes to main hash, then
+                    // send bytes 16+ to the Merkle tree (excluded from main hash).
+                    //
+                    // Other non-excluded top-level boxes: emit offset then write all
+                    // content through the processor (no exclude_mode).
+                    let mdat_v2_top_done = if header.name == BoxType::MdatBox {
+                        let mdat_id = next_mdat_id;
+                        next_mdat_id += 1;
+                        // Always provide mdat chunks (even outside V2 mode) so that a
+                        // processor can observe them if needed.
+                        let merkle_start = 16.min(box_data.len());
+                        let merkle_data = &box_data[merkle_start..];
+
+                        if is_top_level && !is_excluded_box {
+                            let out_offset = pw.stream_position()?;
+                            pw.process_offset(out_offset)?;
+                            // First 16 bytes → main hash.
+                            pw.write_all(&box_data[..merkle_start])?;
+                            // Bytes 16+ → Merkle tree, excluded from main hash.
+                            if !merkle_data.is_empty() {
+                                pw.set_exclude_mode(true);
+                                pw.process_chunk(MdatChunk {
+                                    id: mdat_id,
+                                    data: merkle_data,
+                                    large_size: header.large_size,
+                                })?;
+                                pw.write_all(merkle_data)?;
+                                pw.set_exclude_mode(false);
+                            }
+                            true
+                        } else {
+                            if !merkle_data.is_empty() {
+                                pw.process_chunk(MdatChunk {
+                                    id: mdat_id,
+                                    data: merkle_data,
+                                    large_size: header.large_size,
+                                })?;
+                            }
+                            false
+                        }
+                    } else {
+                        false
+                    };
+
+                    if !mdat_v2_top_done {
+                        if is_top_level && !is_excluded_box {
+                            // V3: emit offset then let all content flow through the processor.
+                            let out_offset = pw.stream_position()?;
+                            pw.process_offset(out_offset)?;
+                            pw.write_all(&box_data)?;
+                        } else if is_excluded_box {
+                            // Fully-excluded boxes (mfra, free, skip) are not hashed.
+                            pw.set_exclude_mode(true);
+                            pw.write_all(&box_data)?;
+                            pw.set_exclude_mode(false);
+                        } else {
+                            // Normal copy (V1 style, or non-top-level boxes)
+                            pw.write_all(&box_data)?;
+                        }
+                    }
                 }
             }
 
@@ -1412,710 +2542,1527 @@ impl ContainerIO for BmffIO {
             source.seek(SeekFrom::Start(current_pos))?;
         }
 
-        // Compute chunk offset delta: new UUID region size - original UUID region size
-        let original_size: u64 = {
-            let xmp = if write_xmp || remove_xmp {
-                existing_xmp_token
-                    .map(|t| bmff_tree[t].data.size)
-                    .unwrap_or(0)
-            } else {
-                0
-            };
-            let c2pa = if write_jumbf || remove_jumbf {
-                existing_c2pa_token
-                    .map(|t| bmff_tree[t].data.size)
-                    .unwrap_or(0)
-            } else {
-                0
-            };
-            xmp + c2pa
-        };
-        let new_size: u64 = {
-            let xmp = match &updates.xmp {
-                MetadataUpdate::Set(data) => calculate_xmp_box_size(data),
-                MetadataUpdate::Remove => 0,
-                MetadataUpdate::Keep => existing_xmp_token
-                    .map(|t| bmff_tree[t].data.size)
-                    .unwrap_or(0),
-            };
-            let c2pa = match &updates.jumbf {
-                MetadataUpdate::Set(data) => calculate_c2pa_box_size(data, "manifest"),
-                MetadataUpdate::Remove => 0,
-                MetadataUpdate::Keep => existing_c2pa_token
-                    .map(|t| bmff_tree[t].data.size)
-                    .unwrap_or(0),
-            };
-            xmp + c2pa
-        };
-        let delta = new_size as i64 - original_size as i64;
-        if delta != 0 {
-            writer.flush()?;
-            adjust_chunk_offsets(writer, &bmff_tree, &bmff_map, delta)?;
+        // A moov box too large to rewrite in memory is patched in the output
+        if rewrites.fallback_delta != 0 {
+            pw.flush()?;
+            bmff_adjust_chunk_offsets(pw.get_mut(), rewrites.fallback_delta)?;
         }
 
         Ok(())
     }
 
-    fn write_with_processor<R: Read + Seek, W: Read + Write + Seek, F>(
+    fn calculate_updated_structure(
         &self,
-        _structure: &Structure,
-        source: &mut R,
-        writer: &mut W,
+        source_structure: &Structure,
         updates: &Updates,
-        processor: &mut F,
-    ) -> Result<()>
-    where
-        F: crate::ProcessChunkFn,
-    {
-        use crate::processing_writer::ProcessingWriter;
-        use crate::MetadataUpdate;
-
-        let exclude_segments = &updates.processing.exclude_segments;
-        let _exclusion_mode = updates.processing.exclusion_mode;
-        let should_exclude_jumbf = exclude_segments.contains(&SegmentKind::Jumbf);
-
-        source.seek(SeekFrom::Start(0))?;
+    ) -> Result<Structure> {
+        crate::jumbf::reject_labeled_boxes(updates, ContainerKind::Bmff)?;
 
-        // Get file size
-        let file_size = source.seek(SeekFrom::End(0))?;
-        source.seek(SeekFrom::Start(0))?;
+        let mut new_structure =
+            Structure::new(source_structure.container, source_structure.media_type);
 
-        // Parse BMFF structure to find insertion points
-        let root_box = BoxInfo {
-            path: "".to_string(),
-            offset: 0,
-            size: file_size,
-            box_type: BoxType::Empty,
-            parent: None,
-            user_type: None,
-            version: None,
-            flags: None,
+        let has_path = |seg: &Segment, prefix: &str| {
+            seg.path.as_deref().is_some_and(|p| p.starts_with(prefix))
         };
 
-        let (mut bmff_tree, root_token) = Arena::with_data(root_box);
-        let mut bmff_map: HashMap<String, Vec<Token>> = HashMap::new();
-        build_bmff_tree(
-            source,
-            file_size,
-            &mut bmff_tree,
-            &root_token,
-            &mut bmff_map,
-        )?;
-
-        // Find ftyp box (required to be first)
-        let ftyp_token = bmff_map
-            .get("/ftyp")
-            .and_then(|v| v.first())
-            .ok_or_else(|| Error::InvalidFormat("Missing ftyp box".to_string()))?;
-        let ftyp_info = &bmff_tree[*ftyp_token].data;
-        let ftyp_end = ftyp_info.offset + ftyp_info.size;
-
-        // Determine what to do with XMP and JUMBF
-        let write_xmp = matches!(updates.xmp, MetadataUpdate::Set(_));
-        let write_jumbf = matches!(updates.jumbf, MetadataUpdate::Set(_));
-        let remove_xmp = matches!(updates.xmp, MetadataUpdate::Remove);
-        let remove_jumbf = matches!(updates.jumbf, MetadataUpdate::Remove);
+        // Existing uuid boxes, as (box range, data range). The XMP box is
+        // header + UUID + data; the C2PA segment records its full box in ranges[1].
+        let existing_xmp = source_structure
+            .segments
+            .iter()
+            .find(|seg| seg.is_xmp() && has_path(seg, "uuid/xmp"))
+            .map(|seg| {
+                let data = seg.location();
+                (ByteRange::new(data.offset - 24, data.size + 24), data)
+            });
+        let existing_c2pa = source_structure
+            .segments
+            .iter()
+            .find(|seg| seg.is_jumbf() && has_path(seg, "uuid/c2pa"))
+            .map(|seg| {
+                let data = seg.ranges[0];
+                (seg.ranges.get(1).copied().unwrap_or(data), data)
+            });
 
-        // Find existing UUID boxes (at /uuid or /meta/uuid in HEIC)
-        let existing_xmp_token = uuid_tokens_from_map(&bmff_map).find(|&token| {
-            let box_info = &bmff_tree[token];
-            box_info
-                .data
-                .user_type
-                .as_ref()
-                .map(|uuid| uuid.as_slice() == XMP_UUID)
-                .unwrap_or(false)
+        // Top-level meta box with HEIF items (loaded at parse time)
+        let meta_segment = source_structure
+            .segments
+            .iter()
+            .find(|seg| seg.path.as_deref() == Some("meta"));
+        let heif_meta = meta_segment.and_then(|seg| {
+            let meta = heif_meta_with_items(seg.data.get()?.to_vec())?;
+            Some((seg.location().offset, meta))
         });
 
-        let existing_c2pa_token = uuid_tokens_from_map(&bmff_map).find(|&token| {
-            let box_info = &bmff_tree[token];
-            box_info
-                .data
-                .user_type
-                .as_ref()
-                .map(|uuid| uuid.as_slice() == C2PA_UUID)
-                .unwrap_or(false)
-        });
+        // New uuid boxes, as (box size, data offset within the box)
+        let xmp_box = match &updates.xmp {
+            MetadataUpdate::Set(_) if heif_meta.is_some() => None,
+            MetadataUpdate::Set(data) => Some((calculate_xmp_box_size(data), 24)),
+            MetadataUpdate::Remove => None,
+            MetadataUpdate::Keep => existing_xmp.map(|(full, _)| (full.size, 24)),
+        };
+        let jumbf_box = match &updates.jumbf {
+            MetadataUpdate::Set(data) => {
+                let size = calculate_c2pa_box_size(data, "manifest");
+                Some((size, size - data.len() as u64))
+            }
+            MetadataUpdate::Remove => None,
+            MetadataUpdate::Keep => {
+                existing_c2pa.map(|(full, data)| (full.size, data.offset - full.offset))
+            }
+        };
 
-        // Wrap writer in ProcessingWriter
-        let mut pw = ProcessingWriter::new(writer, processor);
+        // The write path inserts uuid boxes right after ftyp. Infer where ftyp ends
+        // from the earliest uuid or meta box; otherwise use typical ftyp sizes.
+        let ftyp_end = existing_xmp
+            .iter()
+            .chain(existing_c2pa.iter())
+            .map(|(full, _)| full.offset)
+            .chain(heif_meta.iter().map(|(offset, _)| *offset))
+            .min()
+            .unwrap_or(match source_structure.media_type {
+                // No segments in source - use typical ftyp size for each format
+                // These are empirically determined from common files
+                crate::MediaType::QuickTime => 20u64, // QuickTime .mov files
+                crate::MediaType::Heif => 24u64,      // HEIF images
+                crate::MediaType::Heic => 24u64,      // HEIC images
+                crate::MediaType::Avif => 32u64,      // AVIF images (larger ftyp!)
+                crate::MediaType::Mp4Audio => 32u64,  // M4A audio files
+                _ => 24u64,                           // Generic MP4 default
+            });
 
-        // BMFF V3 hashing: For each non-excluded top-level box the hash covers:
-        //   8-byte big-endian output offset  ||  box content minus any subset exclusions
-        // mdat is partially excluded (bytes 16+) and those bytes go to the Merkle tree instead.
-        let use_bmff_v2 = should_exclude_jumbf;
+        let mut current_offset = ftyp_end;
 
-        // Collect top-level offsets of boxes that are included in the main hash.
-        // Fully-excluded boxes (ftyp, C2PA UUID, mfra, free, skip) are omitted.
-        // mdat is kept because it is only partially excluded (subset offset 16).
-        let mut top_level_offsets = Vec::new();
-        if use_bmff_v2 {
-            for (path, tokens) in &bmff_map {
-                if path.starts_with('/') && path.matches('/').count() == 1 && path.len() > 1 {
-                    for token in tokens {
-                        let box_info = &bmff_tree[*token];
-                        let offset = box_info.data.offset;
+        // Add XMP UUID box if present
+        if let Some((size, data_start)) = xmp_box {
+            new_structure.add_segment(Segment::with_ranges(
+                vec![ByteRange::new(
+                    current_offset + data_start,
+                    size - data_start,
+                )],
+                SegmentKind::Xmp,
+                Some("uuid/xmp".to_string()),
+            )?);
+            current_offset += size;
+        }
 
-                        // These box types are fully excluded from the V3 hash.
-                        let is_ftyp = path == "/ftyp";
-                        let is_mfra = path == "/mfra";
-                        let is_free = path == "/free";
-                        let is_skip = path == "/skip";
-                        let is_c2pa_uuid = path == "/uuid"
-                            && box_info
-                                .data
-                                .user_type
-                                .as_ref()
-                                .map(|uuid| uuid.as_slice() == C2PA_UUID)
-                                .unwrap_or(false);
+        // Add C2PA UUID box if present
+        if let Some((size, data_start)) = jumbf_box {
+            // Store TWO ranges:
+            // 1. JUMBF data range (for reading/writing manifest with update_segment)
+            // 2. Full C2PA UUID box range (for hash exclusions per BmffHash spec)
+            new_structure.add_segment(Segment::with_ranges(
+                vec![
+                    ByteRange::new(current_offset + data_start, size - data_start), // JUMBF data
+                    ByteRange::new(current_offset, size),                           // Full UUID box
+                ],
+                SegmentKind::Jumbf,
+                Some("uuid/c2pa".to_string()),
+            )?);
+            current_offset += size;
+        }
 
-                        if !is_ftyp && !is_mfra && !is_free && !is_skip && !is_c2pa_uuid {
-                            top_level_offsets.push(offset);
-                        }
+        // Every existing uuid box is either rewritten, moved up front, or removed
+        let removed: Vec<ByteRange> = existing_xmp
+            .iter()
+            .chain(existing_c2pa.iter())
+            .map(|(full, _)| *full)
+            .collect();
+        let removed_size: u64 = removed.iter().map(|r| r.size).sum();
+        let uuid_delta = (current_offset - ftyp_end) as i64 - removed_size as i64;
+
+        // Rewritten meta and moov boxes change size in place
+        let meta_rewrite = match &heif_meta {
+            Some((meta_src, meta)) => {
+                let (bytes, _) = meta.rewrite(&heif_meta_edit(updates), *meta_src, uuid_delta)?;
+                Some((*meta_src, meta.len(), bytes))
+            }
+            None => None,
+        };
+        let meta_delta = meta_rewrite
+            .as_ref()
+            .map_or(0, |(_, len, bytes)| bytes.len() as i64 - *len as i64);
+
+        // QuickTime edits replace moov/udta and moov/meta (loaded at parse time)
+        let find_segment = |path: &str| {
+            source_structure
+                .segments
+                .iter()
+                .find(|seg| seg.path.as_deref() == Some(path))
+        };
+        let moov_src = find_segment("moov").map(|seg| seg.location());
+        let mut moov_children = Vec::new();
+        if moov_src.is_some() {
+            let mut old_boxes = Vec::new();
+            for path in ["moov/udta", "moov/meta"] {
+                let old = find_segment(path);
+                let bytes = match old {
+                    Some(seg) => Some(seg.data.get().ok_or_else(|| Error::LimitExceeded {
+                        offset: seg.location().offset,
+                        reason: format!("{} box too large to edit", path),
+                    })?),
+                    None => None,
+                };
+                old_boxes.push((path, old.map(|seg| seg.location()), bytes));
+            }
+            let (udta, meta) = if updates.quicktime.is_empty() {
+                (
+                    old_boxes[0].2.map(<[u8]>::to_vec),
+                    old_boxes[1].2.map(<[u8]>::to_vec),
+                )
+            } else {
+                quicktime_meta::apply_edits(old_boxes[0].2, old_boxes[1].2, &updates.quicktime)?
+            };
+            for ((path, old, _), new) in old_boxes.into_iter().zip([udta, meta]) {
+                moov_children.push((path, old, new));
+            }
+
+            // Location removal also strips ©xyz from the udta of every track
+            let remove_location = updates
+                .quicktime
+                .contains(&quicktime_meta::QuickTimeEdit::RemoveLocation);
+            for seg in source_structure
+                .segments
+                .iter()
+                .filter(|seg| seg.path.as_deref() == Some("moov/trak/udta"))
+            {
+                let new = match (seg.data.get(), remove_location) {
+                    (Some(bytes), true) => quicktime_meta::strip_track_location(bytes)?,
+                    (Some(bytes), false) => bytes.to_vec(),
+                    (None, false) => continue,
+                    (None, true) => {
+                        return Err(Error::LimitExceeded {
+                            offset: seg.location().offset,
+                            reason: "moov/trak/udta box too large to edit".into(),
+                        })
                     }
-                }
+                };
+                moov_children.push(("moov/trak/udta", Some(seg.location()), Some(new)));
             }
-            top_level_offsets.sort();
-            top_level_offsets.dedup();
         }
+        // Size changes inside moov, as (source offset, delta)
+        let mut moov_changes: Vec<(u64, i64)> = moov_children
+            .iter()
+            .filter_map(|(_, old, new)| {
+                let old = (*old)?;
+                Some((
+                    old.offset,
+                    new.as_ref().map_or(0, |b| b.len() as i64) - old.size as i64,
+                ))
+            })
+            .collect();
+        let appended_size: i64 = moov_children
+            .iter()
+            .filter(|(_, old, _)| old.is_none())
+            .filter_map(|(_, _, new)| new.as_ref().map(|b| b.len() as i64))
+            .sum();
 
-        // Simple strategy: Copy up to ftyp end, insert/skip UUIDs, copy rest
-        source.seek(SeekFrom::Start(0))?;
-
-        // ftyp is fully excluded from the hash (not in top_level_offsets).
-        let mut buffer = vec![0u8; ftyp_end as usize];
-        source.read_exact(&mut buffer)?;
-        pw.set_exclude_mode(true);
-        pw.write_all(&buffer)?;
-        pw.set_exclude_mode(false);
-
-        let xmp_box_start = ftyp_end;
-
-        // ===== C2PA Spec Compliance: Box Ordering =====
-        // Per C2PA spec A.5.1: XMP UUID boxes SHALL be placed immediately after ftyp
-        // and BEFORE any C2PA UUID boxes. This ensures proper manifest discovery.
-        // Order: ftyp → XMP (optional) → C2PA (optional) → other boxes
+        // Chunk offset tables, summarized at parse time
+        let chunk_tables: Vec<(&Segment, u32, u64)> = source_structure
+            .segments
+            .iter()
+            .filter_map(|seg| {
+                let (count, max) = seg.metadata.as_ref()?.as_chunk_offsets()?;
+                Some((seg, count, max))
+            })
+            .collect();
 
-        // Write new XMP UUID if needed
-        if write_xmp {
-            if let MetadataUpdate::Set(ref xmp_data) = updates.xmp {
-                // V3: hash = output_offset || box_content. Emit the offset then let
-                // the box content flow through the processor naturally (no exclude_mode).
-                if use_bmff_v2 {
-                    let out_offset = pw.stream_position()?;
-                    pw.process_offset(out_offset)?;
-                }
-                write_xmp_box(&mut pw, xmp_data)?;
+        // Faststart moves moov right before the first mdat that precedes it
+        let relocate_before = source_structure
+            .segments
+            .iter()
+            .filter(|seg| has_path(seg, "mdat#"))
+            .map(|seg| {
+                let data = seg.location();
+                // Same header size rule as parse_impl
+                let header_size = if data.size + 8 > u32::MAX as u64 {
+                    16
+                } else {
+                    8
+                };
+                data.offset - header_size
+            })
+            .min()
+            .filter(|mdat| updates.faststart && moov_src.is_some_and(|m| *mdat < m.offset));
+
+        // Other boxes keep their place relative to the boxes that are copied through
+        let meta_src = meta_rewrite.as_ref().map(|(offset, _, _)| *offset);
+        let offset_with_moov_delta = |src: u64, moov_delta: i64| -> u64 {
+            let removed_before: u64 = removed
+                .iter()
+                .filter(|r| r.offset < src)
+                .map(|r| r.size)
+                .sum();
+            let mut out = (current_offset + (src - ftyp_end - removed_before)) as i64;
+            if meta_src.is_some_and(|m| m < src) {
+                out += meta_delta;
             }
-        } else if !remove_xmp {
-            // Keep existing XMP
-            if let Some(token) = existing_xmp_token {
-                let box_info = &bmff_tree[token].data;
-                if box_info.size > MAX_BOX_ALLOCATION {
-                    return Err(Error::InvalidFormat(format!(
-                        "XMP box size too large: {} bytes (max: {} bytes)",
-                        box_info.size, MAX_BOX_ALLOCATION
-                    )));
+            if let Some(moov) = moov_src {
+                if moov.offset < src {
+                    out += moov_delta;
                 }
-                source.seek(SeekFrom::Start(box_info.offset))?;
-                let mut box_data = vec![0u8; box_info.size as usize];
-                source.read_exact(&mut box_data)?;
-
-                // V3: emit offset then let box content through (no exclude_mode).
-                if use_bmff_v2 {
-                    let out_offset = pw.stream_position()?;
-                    pw.process_offset(out_offset)?;
+                if relocate_before.is_some_and(|mdat| mdat <= src) && src < moov.offset {
+                    out += moov.size as i64 + moov_delta;
                 }
-                pw.write_all(&box_data)?;
             }
-        }
+            out as u64
+        };
 
-        // Calculate C2PA box start position (for future use if needed)
-        let _c2pa_box_start = if write_xmp {
-            if let MetadataUpdate::Set(ref xmp_data) = updates.xmp {
-                xmp_box_start + calculate_xmp_box_size(xmp_data)
-            } else {
-                xmp_box_start
+        // stco tables whose offsets overflow are promoted to co64, which grows moov
+        // and moves the data after it, so repeat until nothing else overflows
+        let mut promoted = vec![false; chunk_tables.len()];
+        let moov_delta = loop {
+            let moov_delta = moov_changes.iter().map(|(_, d)| d).sum::<i64>()
+                + appended_size
+                + chunk_tables
+                    .iter()
+                    .zip(&promoted)
+                    .filter(|(_, p)| **p)
+                    .map(|((_, count, _), _)| *count as i64 * 4)
+                    .sum::<i64>();
+            let mut changed = false;
+            for ((seg, _, max), promoted) in chunk_tables.iter().zip(promoted.iter_mut()) {
+                if !*promoted
+                    && has_path(seg, "moov/trak/mdia/minf/stbl/stco")
+                    && offset_with_moov_delta(*max, moov_delta) > u32::MAX as u64
+                {
+                    *promoted = true;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break moov_delta;
             }
-        } else if let Some(token) = existing_xmp_token {
-            let box_info = &bmff_tree[token].data;
-            box_info.offset + box_info.size
-        } else {
-            xmp_box_start
         };
+        for ((seg, count, _), promoted) in chunk_tables.iter().zip(&promoted) {
+            if *promoted {
+                moov_changes.push((seg.location().offset, *count as i64 * 4));
+            }
+        }
+        let output_offset = |src: u64| offset_with_moov_delta(src, moov_delta);
+
+        if let Some((meta_src, _, bytes)) = meta_rewrite {
+            let meta_out = output_offset(meta_src);
+            let uuid_xmp = new_structure.xmp_index();
+            if let Some(new_meta) = heif_meta_with_items(bytes.clone()) {
+                new_structure.add_segment(
+                    Segment::new(
+                        meta_out,
+                        new_meta.len(),
+                        SegmentKind::Other,
+                        Some("meta".into()),
+                    )
+                    .with_data(LazyData::Loaded(bytes)),
+                );
+                add_heif_item_segments(&mut new_structure, &new_meta, meta_out)?;
+            }
+            if uuid_xmp.is_some() {
+                *new_structure.xmp_index_mut() = uuid_xmp;
+            }
+        }
 
-        // Write new C2PA UUID if needed
-        if write_jumbf {
-            if let MetadataUpdate::Set(ref jumbf_data) = updates.jumbf {
-                // For C2PA: Always exclude from hash (required by BmffHash spec)
-                // For V2: We still exclude it entirely, don't hash offset
-                pw.set_exclude_mode(true);
-                write_c2pa_box(&mut pw, jumbf_data, "manifest", &[], 0)?;
-                pw.set_exclude_mode(false);
+        if let Some(moov) = moov_src {
+            let new_moov_size = (moov.size as i64 + moov_delta) as u64;
+            let moov_out = match relocate_before {
+                Some(mdat) => output_offset(mdat) - new_moov_size,
+                None => output_offset(moov.offset),
+            };
+            new_structure.add_segment(Segment::new(
+                moov_out,
+                new_moov_size,
+                SegmentKind::Other,
+                Some("moov".into()),
+            ));
+
+            // Boxes inside moov move by the size changes before them; new metadata
+            // boxes are appended in order
+            let moved_within = |offset: u64| -> u64 {
+                let delta: i64 = moov_changes
+                    .iter()
+                    .filter(|(o, _)| *o < offset)
+                    .map(|(_, d)| d)
+                    .sum();
+                (moov_out as i64 + (offset - moov.offset) as i64 + delta) as u64
+            };
+            let mut appended_at = moved_within(moov.offset + moov.size);
+            for (path, old, new) in &moov_children {
+                let Some(bytes) = new else {
+                    continue;
+                };
+                let out = match old {
+                    Some(old) => moved_within(old.offset),
+                    None => {
+                        let out = appended_at;
+                        appended_at += bytes.len() as u64;
+                        out
+                    }
+                };
+                new_structure.add_segment(
+                    Segment::new(
+                        out,
+                        bytes.len() as u64,
+                        SegmentKind::Other,
+                        Some((*path).into()),
+                    )
+                    .with_data(LazyData::Loaded(bytes.clone())),
+                );
             }
-        } else if !remove_jumbf {
-            // Keep existing C2PA
-            if let Some(token) = existing_c2pa_token {
-                let box_info = &bmff_tree[token].data;
-                if box_info.size > MAX_BOX_ALLOCATION {
-                    return Err(Error::InvalidFormat(format!(
-                        "C2PA box size too large: {} bytes (max: {} bytes)",
-                        box_info.size, MAX_BOX_ALLOCATION
-                    )));
-                }
-                source.seek(SeekFrom::Start(box_info.offset))?;
-                let mut box_data = vec![0u8; box_info.size as usize];
-                source.read_exact(&mut box_data)?;
 
-                // Exclude C2PA UUID box (always excluded, even in V2)
-                pw.set_exclude_mode(true);
-                pw.write_all(&box_data)?;
-                pw.set_exclude_mode(false);
+            for ((seg, count, max), promoted) in chunk_tables.iter().zip(&promoted) {
+                let old = seg.location();
+                let (path, size) = match promoted {
+                    true => (
+                        "moov/trak/mdia/minf/stbl/co64".to_string(),
+                        old.size + *count as u64 * 4,
+                    ),
+                    false => (seg.path.clone().unwrap_or_default(), old.size),
+                };
+                new_structure.add_segment(
+                    Segment::new(
+                        moved_within(old.offset),
+                        size,
+                        SegmentKind::Other,
+                        Some(path),
+                    )
+                    .with_metadata(SegmentMetadata::ChunkOffsets {
+                        entry_count: *count,
+                        max_offset: output_offset(*max),
+                    }),
+                );
             }
         }
 
-        // Copy remaining boxes (skip already-handled UUIDs)
-        let mut current_pos = ftyp_end;
-        let mut next_mdat_id = 0usize;
+        // Media data is copied through unchanged
+        for seg in source_structure
+            .segments
+            .iter()
+            .filter(|seg| has_path(seg, "mdat#"))
+        {
+            let data = seg.location();
+            new_structure.add_segment(Segment::with_ranges(
+                vec![ByteRange::new(output_offset(data.offset), data.size)],
+                SegmentKind::ImageData,
+                Some(format!("mdat#{}", new_structure.segments.len())),
+            )?);
+        }
 
-        while current_pos < file_size {
-            // Read box header to determine if we should skip it
-            let box_start = current_pos;
-            let header = BoxHeaderLite::read(source)?;
+        new_structure.total_size =
+            (source_structure.total_size as i64 + uuid_delta + meta_delta + moov_delta)
+                .try_into()
+                .map_err(|_| Error::InvalidFormat("Invalid updated file size".into()))?;
+        Ok(new_structure)
+    }
 
-            // Check if this is a UUID box we already wrote
-            let should_skip = if header.name == BoxType::UuidBox {
-                let mut uuid_bytes = [0u8; 16];
-                source.read_exact(&mut uuid_bytes)?;
-                source.seek(SeekFrom::Start(box_start))?; // Reset for potential copy
+    #[cfg(feature = "exif")]
+    fn read_embedded_thumbnail_info<R: Read + Seek>(
+        &self,
+        _structure: &Structure,
+        source: &mut R,
+    ) -> Result<Option<crate::thumbnail::EmbeddedThumbnailInfo>> {
+        // HEIF/HEIC thumbnail extraction
+        // Thumbnails are stored as separate items with 'thmb' reference to the primary item
+        extract_heif_thumbnail_info(source)
+    }
 
-                (uuid_bytes == XMP_UUID && (write_xmp || remove_xmp))
-                    || (uuid_bytes == C2PA_UUID && (write_jumbf || remove_jumbf))
-            } else {
-                false
-            };
+    #[cfg(feature = "exif")]
+    fn read_exif_info<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+    ) -> Result<Option<crate::tiff::ExifInfo>> {
+        use std::io::SeekFrom;
 
-            if should_skip {
-                // Skip this box
-                source.seek(SeekFrom::Start(box_start + header.size))?;
+        // Find EXIF segment
+        let exif_segment = structure
+            .segments()
+            .iter()
+            .find(|s| s.is_type(SegmentKind::Exif));
+
+        let segment = match exif_segment {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        // Read the EXIF data
+        let location = segment.location();
+        if location.size > self.limits.max_segment_size {
+            return Err(Error::LimitExceeded {
+                offset: location.offset,
+                reason: format!(
+                    "EXIF data size too large: {} bytes (max: {} bytes)",
+                    location.size, self.limits.max_segment_size
+                ),
+            });
+        }
+        source.seek(SeekFrom::Start(location.offset))?;
+        let mut data = vec![0u8; location.size as usize];
+        source.read_exact(&mut data)?;
+
+        // HEIF: Exif item has 4-byte tiff_header_offset prefix, then "Exif\0\0", then TIFF
+        // The 4 bytes are typically 0x00000006 (offset to TIFF data from start of Exif block)
+        let exif_data = if data.len() > 10 && &data[4..10] == b"Exif\0\0" {
+            &data[10..]
+        } else if data.len() > 4 {
+            // Some files may have different structure, try to find TIFF header
+            if data[4..].starts_with(b"II") || data[4..].starts_with(b"MM") {
+                &data[4..]
             } else {
-                // Copy this box - use streaming for large boxes
-                source.seek(SeekFrom::Start(box_start))?;
+                &data
+            }
+        } else {
+            return Ok(None);
+        };
 
-                // Check if this is a top-level box (for V2 hashing)
-                let is_top_level = use_bmff_v2 && top_level_offsets.contains(&box_start);
+        crate::tiff::parse_exif_info(exif_data)
+    }
 
-                // Determine if this box should be excluded from hash
-                let is_excluded_box = matches!(
-                    header.name,
-                    BoxType::MfraBox | BoxType::FreeBox | BoxType::UnknownBox(0x736b6970)
-                );
+    fn read_media_info<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+    ) -> Result<crate::MediaInfo> {
+        read_bmff_media_info(structure, source)
+    }
 
-                if header.size > MAX_BOX_ALLOCATION {
-                    // Stream large boxes (e.g., mdat) without loading into memory
-                    let mut remaining = header.size;
-                    let mut buffer = vec![0u8; 8 * 1024 * 1024]; // 8MB buffer
-                    let is_mdat = header.name == BoxType::MdatBox;
-                    let mdat_id = if is_mdat {
-                        let id = next_mdat_id;
-                        next_mdat_id += 1;
-                        id
-                    } else {
-                        0
-                    };
-                    let mdat_large_size = header.large_size;
+    fn validate<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        report: &mut ValidationReport,
+    ) -> Result<()> {
+        self.validate_impl(structure, source, report)
+    }
 
-                    // V3 hash: emit output offset for non-excluded top-level boxes.
-                    // The box content (or the first 16 bytes for mdat) then flows through
-                    // the processor without exclude_mode for the main hash.
-                    if is_top_level && !is_excluded_box {
-                        let out_offset = pw.stream_position()?;
-                        pw.process_offset(out_offset)?;
-                    }
+    fn exclusion_range_for_segment(structure: &Structure, kind: SegmentKind) -> Option<(u64, u64)> {
+        let segment = match kind {
+            SegmentKind::Jumbf => structure
+                .c2pa_jumbf_index()
+                .map(|i| &structure.segments()[i]),
+            SegmentKind::Xmp => structure.xmp_index().map(|i| &structure.segments()[i]),
+            _ => None,
+        }?;
 
-                    // For mdat: bytes 0-15 go into the main hash; bytes 16+ go to the
-                    // Merkle tree (via process_chunk, excluded from main hash).
-                    // For all other non-excluded top-level boxes: all content goes to
-                    // the main hash (no exclude_mode).
-                    let mut main_hash_remaining: usize =
-                        if is_mdat && is_top_level && !is_excluded_box {
-                            16
-                        } else {
-                            0
-                        };
+        // For BMFF C2PA segments, ranges[1] contains the full UUID box (for hash exclusions)
+        // ranges[0] contains just the JUMBF data (for reading/writing)
+        if kind == SegmentKind::Jumbf && segment.ranges.len() >= 2 && !is_heif_item(segment) {
+            let full_box_range = &segment.ranges[1];
+            Some((full_box_range.offset, full_box_range.size))
+        } else {
+            // For XMP or if only one range exists, use the first range
+            let location = segment.location();
+            Some((location.offset, location.size))
+        }
+    }
+}
 
-                    while remaining > 0 {
-                        let to_read = remaining.min(buffer.len() as u64) as usize;
-                        source.read_exact(&mut buffer[..to_read])?;
+// ============================================================================
+// HEIF Item Metadata
+// ============================================================================
 
-                        if is_excluded_box {
-                            pw.set_exclude_mode(true);
-                            pw.write_all(&buffer[..to_read])?;
-                            pw.set_exclude_mode(false);
-                        } else if is_mdat && is_top_level {
-                            // Split the buffer at the 16-byte boundary.
-                            if main_hash_remaining > 0 {
-                                let to_main = main_hash_remaining.min(to_read);
-                                pw.write_all(&buffer[..to_main])?; // → main hash
-                                main_hash_remaining -= to_main;
+/// Read the top-level `meta` box, if it describes HEIF items
+///
+/// Returns the box offset and parsed contents. A box over the segment size
+/// limit fails when strict and is otherwise skipped with a diagnostic;
+/// malformed boxes are ignored so that the rest of the file can still be handled.
+fn read_heif_meta<R: Read + Seek>(
+    source: &mut R,
+    bmff_tree: &Arena<BoxInfo>,
+    bmff_map: &HashMap<String, Vec<Token>>,
+    limits: &Limits,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<Option<(u64, HeifMeta)>> {
+    let Some(token) = bmff_map.get("/meta").and_then(|v| v.first()) else {
+        return Ok(None);
+    };
+    let info = &bmff_tree[*token].data;
+    if let Err(error) = limits.check_segment_size(info.offset, info.size, "HEIF meta box") {
+        if limits.strict {
+            return Err(error.at(info.offset, "/meta"));
+        }
+        diagnostics.push(Diagnostic::error(
+            info.offset,
+            "/meta",
+            format!("{}; its items are ignored", error.reason()),
+        ));
+        return Ok(None);
+    }
+    source.seek(SeekFrom::Start(info.offset))?;
+    let mut bytes = vec![0u8; info.size as usize];
+    source.read_exact(&mut bytes)?;
+    Ok(heif_meta_with_items(bytes).map(|meta| (info.offset, meta)))
+}
 
-                                if to_main < to_read {
-                                    let rest = &buffer[to_main..to_read];
-                                    pw.set_exclude_mode(true);
-                                    pw.process_chunk(MdatChunk {
-                                        id: mdat_id,
-                                        data: rest,
-                                        large_size: mdat_large_size,
-                                    })?;
-                                    pw.write_all(rest)?;
-                                    pw.set_exclude_mode(false);
-                                }
-                            } else {
-                                // Entirely past the 16-byte main-hash region → Merkle only.
-                                pw.set_exclude_mode(true);
-                                pw.process_chunk(MdatChunk {
-                                    id: mdat_id,
-                                    data: &buffer[..to_read],
-                                    large_size: mdat_large_size,
-                                })?;
-                                pw.write_all(&buffer[..to_read])?;
-                                pw.set_exclude_mode(false);
-                            }
-                        } else {
-                            // Non-mdat box: write all content through the processor.
-                            pw.write_all(&buffer[..to_read])?;
-                        }
+fn heif_meta_with_items(bytes: Vec<u8>) -> Option<HeifMeta> {
+    match HeifMeta::parse(bytes) {
+        Ok(meta) if meta.has_items() => Some(meta),
+        Ok(_) => None,
+        Err(e) => {
+            log::debug!("ignoring unparseable meta box: {}", e);
+            None
+        }
+    }
+}
 
-                        remaining -= to_read as u64;
-                    }
-                } else {
-                    // Small boxes can be loaded for efficiency
-                    let mut box_data = vec![0u8; header.size as usize];
-                    source.read_exact(&mut box_data)?;
+/// Item edits implied by `updates` for files that store metadata as HEIF items
+///
+/// New XMP is written as an item; any existing C2PA items are dropped whenever the
+/// manifest store is replaced or removed, since the new one goes in a `uuid` box.
+fn heif_meta_edit(updates: &Updates) -> MetaEdit<'_> {
+    MetaEdit {
+        remove_xmp: matches!(updates.xmp, MetadataUpdate::Remove),
+        remove_c2pa: !matches!(updates.jumbf, MetadataUpdate::Keep),
+        set_xmp: match &updates.xmp {
+            MetadataUpdate::Set(data) => Some(data.as_slice()),
+            _ => None,
+        },
+    }
+}
 
-                    // V3 small-box path.
-                    //
-                    // mdat: emit offset, write first 16 bytes to main hash, then
-                    // send bytes 16+ to the Merkle tree (excluded from main hash).
-                    //
-                    // Other non-excluded top-level boxes: emit offset then write all
-                    // content through the processor (no exclude_mode).
-                    let mdat_v2_top_done = if header.name == BoxType::MdatBox {
-                        let mdat_id = next_mdat_id;
-                        next_mdat_id += 1;
-                        // Always provide mdat chunks (even outside V2 mode) so that a
-                        // processor can observe them if needed.
-                        let merkle_start = 16.min(box_data.len());
-                        let merkle_data = &box_data[merkle_start..];
+/// Add segments for the XMP and C2PA items of a HEIF `meta` box
+fn add_heif_item_segments(
+    structure: &mut Structure,
+    meta: &HeifMeta,
+    meta_offset: u64,
+) -> Result<()> {
+    for (content_type, kind, path) in [
+        (XMP_CONTENT_TYPE, SegmentKind::Xmp, "meta/item/xmp"),
+        (C2PA_CONTENT_TYPE, SegmentKind::Jumbf, "meta/item/c2pa"),
+    ] {
+        for id in meta.items_with_content_type(content_type) {
+            if let Some(ranges) = meta.item_ranges(id, meta_offset) {
+                structure.add_segment(Segment::with_ranges(ranges, kind, Some(path.to_string()))?);
+            }
+        }
+    }
+    Ok(())
+}
 
-                        if is_top_level && !is_excluded_box {
-                            let out_offset = pw.stream_position()?;
-                            pw.process_offset(out_offset)?;
-                            // First 16 bytes → main hash.
-                            pw.write_all(&box_data[..merkle_start])?;
-                            // Bytes 16+ → Merkle tree, excluded from main hash.
-                            if !merkle_data.is_empty() {
-                                pw.set_exclude_mode(true);
-                                pw.process_chunk(MdatChunk {
-                                    id: mdat_id,
-                                    data: merkle_data,
-                                    large_size: header.large_size,
-                                })?;
-                                pw.write_all(merkle_data)?;
-                                pw.set_exclude_mode(false);
-                            }
-                            true
-                        } else {
-                            if !merkle_data.is_empty() {
-                                pw.process_chunk(MdatChunk {
-                                    id: mdat_id,
-                                    data: merkle_data,
-                                    large_size: header.large_size,
-                                })?;
-                            }
-                            false
-                        }
-                    } else {
-                        false
+/// True for segments parsed from HEIF items rather than `uuid` boxes
+fn is_heif_item(segment: &Segment) -> bool {
+    segment
+        .path
+        .as_deref()
+        .is_some_and(|p| p.starts_with("meta/item/"))
+}
+
+/// Read all ranges of a segment into one buffer
+fn read_segment_ranges<R: Read + Seek>(
+    source: &mut R,
+    segment: &Segment,
+    limits: &Limits,
+) -> Result<Vec<u8>> {
+    let total: u64 = segment.ranges.iter().map(|r| r.size).sum();
+    if total > limits.max_segment_size {
+        return Err(Error::LimitExceeded {
+            offset: segment.location().offset,
+            reason: format!(
+                "{} data size too large: {} bytes (max: {} bytes)",
+                segment.kind.as_str(),
+                total,
+                limits.max_segment_size
+            ),
+        });
+    }
+    let mut data = Vec::with_capacity(total as usize);
+    for range in &segment.ranges {
+        source.seek(SeekFrom::Start(range.offset))?;
+        let start = data.len();
+        data.resize(start + range.size as usize, 0);
+        source.read_exact(&mut data[start..])?;
+    }
+    Ok(data)
+}
+
+// ============================================================================
+// Media Info
+// ============================================================================
+
+/// Read technical properties from the movie boxes (`mvhd`, per-track `tkhd`,
+/// `mdhd`, `hdlr`, `stsd`) and the HEIF item properties (`ispe`, `pixi`)
+fn read_bmff_media_info<R: Read + Seek>(
+    structure: &Structure,
+    source: &mut R,
+) -> Result<crate::MediaInfo> {
+    let mut info = crate::MediaInfo::default();
+
+    let heif_meta = structure
+        .segments
+        .iter()
+        .find(|seg| seg.path.as_deref() == Some("meta"))
+        .and_then(|seg| heif_meta_with_items(seg.data.get()?.to_vec()));
+    if let Some(meta) = heif_meta {
+        let props = meta.primary_image_properties()?;
+        info.codec = props
+            .item_type
+            .map(|t| String::from_utf8_lossy(&t).into_owned());
+        info.width = props.width;
+        info.height = props.height;
+        info.bit_depth = props.bits_per_channel.first().map(|&b| b as u16);
+        if !props.bits_per_channel.is_empty() {
+            info.components = Some(props.bits_per_channel.len() as u8);
+        }
+    }
+
+    let Some(tree) = structure.bmff_tree() else {
+        return Ok(info);
+    };
+
+    if let Some(mvhd) = tree.find("/moov/mvhd") {
+        let payload = mvhd.read_payload(source, structure.max_segment_size())?;
+        if let Some((timescale, duration)) = timescale_and_duration(&payload, mvhd.version()) {
+            info.timescale = Some(timescale);
+            info.duration = crate::media_info::duration_from_units(duration, timescale);
+        }
+    }
+
+    for trak in tree.find_all("/moov/trak") {
+        info.tracks.push(read_track_info(
+            tree,
+            trak,
+            source,
+            structure.max_segment_size(),
+        )?);
+    }
+
+    if let Some(video) = info.tracks.iter().find(|t| t.width.is_some_and(|w| w > 0)) {
+        if info.width.is_none() {
+            info.width = video.width;
+            info.height = video.height;
+        }
+    }
+    if let Some(audio) = info.tracks.iter().find(|t| t.sample_rate.is_some()) {
+        info.channels = audio.channels;
+        info.sample_rate = audio.sample_rate;
+    }
+    if info.codec.is_none() {
+        info.codec = info.tracks.iter().find_map(|t| t.codec.clone());
+    }
+    Ok(info)
+}
+
+/// `mvhd` `creation_time`, in seconds since 1904-01-01 UTC
+pub(crate) fn read_mvhd_creation_time<R: Read + Seek>(
+    structure: &Structure,
+    source: &mut R,
+) -> Result<Option<u64>> {
+    let Some(mvhd) = structure.bmff_tree().and_then(|t| t.find("/moov/mvhd")) else {
+        return Ok(None);
+    };
+    let payload = mvhd.read_payload(source, structure.max_segment_size())?;
+    Ok(if mvhd.version() == Some(1) {
+        payload
+            .get(0..8)
+            .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+    } else {
+        payload
+            .get(0..4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()) as u64)
+    })
+}
+
+/// Timescale and duration from an `mvhd` or `mdhd` payload (after version/flags)
+fn timescale_and_duration(payload: &[u8], version: Option<u8>) -> Option<(u32, u64)> {
+    let be32 = |at: usize| {
+        Some(u32::from_be_bytes(
+            payload.get(at..at + 4)?.try_into().ok()?,
+        ))
+    };
+    if version == Some(1) {
+        // creation_time(8) + modification_time(8) + timescale(4) + duration(8)
+        let duration = u64::from_be_bytes(payload.get(20..28)?.try_into().ok()?);
+        Some((be32(16)?, duration))
+    } else {
+        // creation_time(4) + modification_time(4) + timescale(4) + duration(4)
+        Some((be32(8)?, be32(12)? as u64))
+    }
+}
+
+fn read_track_info<R: Read + Seek>(
+    tree: &BmffTree,
+    trak: &BmffBox,
+    source: &mut R,
+    max_size: u64,
+) -> Result<crate::TrackInfo> {
+    let mut track = crate::TrackInfo::default();
+    let mdia = tree.child(trak, "mdia");
+
+    if let Some(tkhd) = tree.child(trak, "tkhd") {
+        let payload = tkhd.read_payload(source, max_size)?;
+        // Times and duration are 8 bytes in version 1; the track id follows the two times.
+        // Width and height (16.16 fixed point) close the box after layer, volume and matrix.
+        let (id_at, size_at) = if tkhd.version() == Some(1) {
+            (16, 84)
+        } else {
+            (8, 72)
+        };
+        if let Some(id) = payload.get(id_at..id_at + 4) {
+            track.id = u32::from_be_bytes(id.try_into().unwrap());
+        }
+        if let Some(size) = payload.get(size_at..size_at + 8) {
+            let width = u32::from_be_bytes(size[0..4].try_into().unwrap()) >> 16;
+            let height = u32::from_be_bytes(size[4..8].try_into().unwrap()) >> 16;
+            if width > 0 && height > 0 {
+                track.width = Some(width);
+                track.height = Some(height);
+            }
+        }
+    }
+
+    if let Some(mdhd) = mdia.and_then(|m| tree.child(m, "mdhd")) {
+        let payload = mdhd.read_payload(source, max_size)?;
+        if let Some((timescale, duration)) = timescale_and_duration(&payload, mdhd.version()) {
+            track.timescale = Some(timescale);
+            track.duration = crate::media_info::duration_from_units(duration, timescale);
+        }
+    }
+
+    if let Some(hdlr) = mdia.and_then(|m| tree.child(m, "hdlr")) {
+        // pre_defined(4) + handler_type(4)
+        let payload = hdlr.read_payload(source, max_size)?;
+        if let Some(handler) = payload.get(4..8) {
+            track.handler = String::from_utf8_lossy(handler).into_owned();
+        }
+    }
+
+    let stsd = mdia
+        .and_then(|m| tree.child(m, "minf"))
+        .and_then(|m| tree.child(m, "stbl"))
+        .and_then(|s| tree.child(s, "stsd"));
+    if let Some(stsd) = stsd {
+        // entry_count(4), then the first sample entry: size(4) + format(4)
+        // + reserved(6) + data_reference_index(2) + format-specific fields
+        let payload = stsd.read_payload(source, max_size)?;
+        if let Some(format) = payload.get(8..12) {
+            track.codec = Some(String::from_utf8_lossy(format).into_owned());
+        }
+        let entry = payload.get(20..).unwrap_or(&[]);
+        let be16 = |at: usize| {
+            entry
+                .get(at..at + 2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        };
+        match track.handler.as_str() {
+            // reserved(8) + channelcount(2) + samplesize(2) + pre_defined(2)
+            // + reserved(2) + samplerate(16.16)
+            "soun" => {
+                track.channels = be16(8);
+                track.sample_rate = be16(16).map(|r| r as u32);
+            }
+            // pre_defined(2) + reserved(2) + pre_defined(12) + width(2) + height(2)
+            "vide" if track.width.is_none() => {
+                track.width = be16(16).map(|w| w as u32);
+                track.height = be16(18).map(|h| h as u32);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(track)
+}
+
+// ============================================================================
+// QuickTime Metadata and Box Rewrites
+// ============================================================================
+
+/// Read the `moov/udta` and `moov/meta` metadata of a parsed file
+///
+/// Returns `None` when the file has no `moov` box.
+pub(crate) fn read_quicktime_metadata<R: Read + Seek>(
+    structure: &Structure,
+    source: &mut R,
+    limits: &Limits,
+) -> Result<Option<QuickTimeMetadata>> {
+    if !structure
+        .segments
+        .iter()
+        .any(|seg| seg.path.as_deref() == Some("moov"))
+    {
+        return Ok(None);
+    }
+    let mut load = |path: &str| -> Result<Option<Vec<u8>>> {
+        match structure
+            .segments
+            .iter()
+            .find(|seg| seg.path.as_deref() == Some(path))
+        {
+            Some(seg) => match seg.data.get() {
+                Some(data) => Ok(Some(data.to_vec())),
+                None => read_segment_ranges(source, seg, limits).map(Some),
+            },
+            None => Ok(None),
+        }
+    };
+    let udta = load("moov/udta")?;
+    let meta = load("moov/meta")?;
+    quicktime_meta::parse(udta.as_deref(), meta.as_deref()).map(Some)
+}
+
+/// Add segments for `moov` and its metadata children, loading the metadata
+fn add_moov_segments<R: Read + Seek>(
+    structure: &mut Structure,
+    source: &mut R,
+    bmff_tree: &Arena<BoxInfo>,
+    bmff_map: &HashMap<String, Vec<Token>>,
+    limits: &Limits,
+) -> Result<()> {
+    let Some(moov) = bmff_map.get("/moov").and_then(|v| v.first()) else {
+        return Ok(());
+    };
+    let moov = &bmff_tree[*moov].data;
+    structure.add_segment(Segment::new(
+        moov.offset,
+        moov.size,
+        SegmentKind::Other,
+        Some("moov".into()),
+    ));
+    // The first moov/udta and moov/meta, and the udta of every track
+    for (map_path, count) in [
+        ("/moov/udta", 1),
+        ("/moov/meta", 1),
+        ("/moov/trak/udta", usize::MAX),
+    ] {
+        for token in bmff_map.get(map_path).into_iter().flatten().take(count) {
+            let info = &bmff_tree[*token].data;
+            let mut segment = Segment::new(
+                info.offset,
+                info.size,
+                SegmentKind::Other,
+                Some(map_path[1..].into()),
+            );
+            if info.size <= limits.max_segment_size {
+                source.seek(SeekFrom::Start(info.offset))?;
+                let mut data = vec![0u8; info.size as usize];
+                source.read_exact(&mut data)?;
+                segment = segment.with_data(LazyData::Loaded(data));
+            }
+            structure.add_segment(segment);
+        }
+    }
+
+    // Chunk offset tables, summarized so updated layouts can predict stco overflow
+    for name in ["stco", "co64"] {
+        let map_path = format!("/moov/trak/mdia/minf/stbl/{}", name);
+        for token in bmff_map.get(&map_path).into_iter().flatten() {
+            let info = &bmff_tree[*token].data;
+            let large = name == "co64";
+            let header_size = if info.size > u32::MAX as u64 {
+                HEADER_SIZE_LARGE
+            } else {
+                HEADER_SIZE
+            };
+            source.seek(SeekFrom::Start(info.offset + header_size + 4))?;
+            let entry_count = source.read_u32::<BigEndian>()?;
+            let entry_size = if large { 8 } else { 4 };
+            let table_size = entry_count as u64 * entry_size;
+            if header_size + 8 + table_size > info.size {
+                return Err(Error::InvalidFormat(format!("Invalid {} box", name)));
+            }
+            // Scan in fixed-size chunks so a huge table never lands in memory
+            let mut buffer = vec![0u8; crate::segment::DEFAULT_CHUNK_SIZE];
+            let mut remaining = table_size;
+            let mut max_offset = 0;
+            while remaining > 0 {
+                let len = remaining.min(buffer.len() as u64) as usize;
+                source.read_exact(&mut buffer[..len])?;
+                for entry in buffer[..len].chunks_exact(entry_size as usize) {
+                    let offset = match large {
+                        true => u64::from_be_bytes(entry.try_into().unwrap()),
+                        false => u32::from_be_bytes(entry.try_into().unwrap()) as u64,
                     };
+                    max_offset = max_offset.max(offset);
+                }
+                remaining -= len as u64;
+            }
+            structure.add_segment(
+                Segment::new(
+                    info.offset,
+                    info.size,
+                    SegmentKind::Other,
+                    Some(format!("moov/trak/mdia/minf/stbl/{}", name)),
+                )
+                .with_metadata(SegmentMetadata::ChunkOffsets {
+                    entry_count,
+                    max_offset,
+                }),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// How source offsets move in the output
+///
+/// Each entry shifts every offset at or after its threshold.
+#[derive(Clone, Default)]
+struct OffsetShifts(Vec<(u64, i64)>);
+
+impl OffsetShifts {
+    fn add(&mut self, threshold: u64, delta: i64) {
+        if delta != 0 {
+            self.0.push((threshold, delta));
+        }
+    }
+
+    fn map(&self, offset: u64) -> u64 {
+        let delta: i64 = self
+            .0
+            .iter()
+            .filter(|(threshold, _)| offset >= *threshold)
+            .map(|(_, delta)| delta)
+            .sum();
+        (offset as i64 + delta) as u64
+    }
+
+    fn total(&self) -> i64 {
+        self.0.iter().map(|(_, delta)| delta).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Copy the boxes of `source` to `writer`, applying `updates`
+///
+/// Returns the stco/co64 shift still to patch in the output, when moov is too
+/// large to rewrite in memory. Without `patch_output` (a sequential writer),
+/// that case fails before anything is written.
+fn write_boxes<R: Read + Seek, W: Write>(
+    source: &mut R,
+    writer: &mut W,
+    updates: &Updates,
+    patch_output: bool,
+    limits: &Limits,
+) -> Result<i64> {
+    source.seek(SeekFrom::Start(0))?;
+
+    // Get file size
+    let file_size = source.seek(SeekFrom::End(0))?;
+    source.seek(SeekFrom::Start(0))?;
+
+    // Parse BMFF structure to find insertion points
+    let root_box = BoxInfo {
+        path: "".to_string(),
+        offset: 0,
+        size: file_size,
+        box_type: BoxType::Empty,
+        parent: None,
+        user_type: None,
+        version: None,
+        flags: None,
+        large_size: false,
+    };
 
-                    if !mdat_v2_top_done {
-                        if is_top_level && !is_excluded_box {
-                            // V3: emit offset then let all content flow through the processor.
-                            let out_offset = pw.stream_position()?;
-                            pw.process_offset(out_offset)?;
-                            pw.write_all(&box_data)?;
-                        } else if is_excluded_box {
-                            // Fully-excluded boxes (mfra, free, skip) are not hashed.
-                            pw.set_exclude_mode(true);
-                            pw.write_all(&box_data)?;
-                            pw.set_exclude_mode(false);
-                        } else {
-                            // Normal copy (V1 style, or non-top-level boxes)
-                            pw.write_all(&box_data)?;
-                        }
-                    }
-                }
+    let (mut bmff_tree, root_token) = Arena::with_data(root_box);
+    let mut bmff_map: HashMap<String, Vec<Token>> = HashMap::new();
+    build_bmff_tree(
+        source,
+        file_size,
+        &mut bmff_tree,
+        &root_token,
+        &mut bmff_map,
+        limits.max_box_depth,
+        &mut Vec::new(),
+    )?;
+    // Copy the parsed boxes only, dropping damaged trailing data
+    let file_size = parsed_end(&bmff_tree, &root_token);
+
+    // Find ftyp box (required to be first)
+    let ftyp_token = bmff_map
+        .get("/ftyp")
+        .and_then(|v| v.first())
+        .ok_or_else(|| Error::InvalidFormat("Missing ftyp box".to_string()))?;
+    let ftyp_info = &bmff_tree[*ftyp_token].data;
+    let ftyp_end = ftyp_info.offset + ftyp_info.size;
+
+    // Determine what to do with XMP and JUMBF
+    let write_xmp = matches!(updates.xmp, MetadataUpdate::Set(_));
+    let write_jumbf = matches!(updates.jumbf, MetadataUpdate::Set(_));
+    let remove_xmp = matches!(updates.xmp, MetadataUpdate::Remove);
+    let remove_jumbf = matches!(updates.jumbf, MetadataUpdate::Remove);
+
+    // Find existing UUID boxes (at /uuid or /meta/uuid in HEIC)
+    let existing_xmp_token = uuid_tokens_from_map(&bmff_map).find(|&token| {
+        let box_info = &bmff_tree[token];
+        box_info
+            .data
+            .user_type
+            .as_ref()
+            .map(|uuid| uuid.as_slice() == XMP_UUID)
+            .unwrap_or(false)
+    });
+
+    let existing_c2pa_token = uuid_tokens_from_map(&bmff_map).find(|&token| {
+        let box_info = &bmff_tree[token];
+        box_info
+            .data
+            .user_type
+            .as_ref()
+            .map(|uuid| uuid.as_slice() == C2PA_UUID)
+            .unwrap_or(false)
+    });
+
+    // HEIF files keep XMP as an item in the top-level meta box, and movies keep
+    // chunk offsets in moov. Both are rewritten whenever their contents change or
+    // the data they point to moves.
+    let heif_meta = read_heif_meta(source, &bmff_tree, &bmff_map, limits, &mut Vec::new())?;
+    let write_xmp_uuid = write_xmp && heif_meta.is_none();
+    let kept_xmp = existing_xmp_token.map(|t| bmff_tree[t].data.offset);
+    let kept_c2pa = existing_c2pa_token.map(|t| bmff_tree[t].data.offset);
+    let uuid_delta = uuid_region_delta(
+        &bmff_tree,
+        existing_xmp_token,
+        existing_c2pa_token,
+        updates,
+        heif_meta.is_some(),
+    );
+    let rewrites = plan_box_rewrites(
+        source,
+        &bmff_tree,
+        &bmff_map,
+        heif_meta.as_ref(),
+        updates,
+        ftyp_end,
+        uuid_delta,
+        limits,
+    )?;
+    if rewrites.fallback_delta != 0 && !patch_output {
+        return Err(Error::Unsupported(
+            "moov box too large to rewrite in memory; write to a seekable destination".into(),
+        ));
+    }
+
+    // Simple strategy: Copy up to ftyp end, insert/skip UUIDs, copy rest
+    source.seek(SeekFrom::Start(0))?;
+
+    // Copy ftyp box
+    if ftyp_end > limits.max_segment_size {
+        return Err(Error::LimitExceeded {
+            offset: 0,
+            reason: format!(
+                "ftyp box size too large: {} bytes (max: {} bytes)",
+                ftyp_end, limits.max_segment_size
+            ),
+        });
+    }
+    let mut buffer = vec![0u8; ftyp_end as usize];
+    source.read_exact(&mut buffer)?;
+    writer.write_all(&buffer)?;
+
+    // ===== C2PA Spec Compliance: Box Ordering =====
+    // Per C2PA spec A.5.1: XMP UUID boxes SHALL be placed immediately after ftyp
+    // and BEFORE any C2PA UUID boxes. This ensures proper manifest discovery.
+    // Order: ftyp → XMP (optional) → C2PA (optional) → other boxes
+
+    // Write new XMP UUID if needed
+    if write_xmp_uuid {
+        if let MetadataUpdate::Set(ref xmp_data) = updates.xmp {
+            write_xmp_box(writer, xmp_data)?;
+        }
+    } else if !write_xmp && !remove_xmp {
+        // Keep existing XMP
+        if let Some(token) = existing_xmp_token {
+            let box_info = &bmff_tree[token].data;
+            if box_info.size > limits.max_segment_size {
+                return Err(Error::LimitExceeded {
+                    offset: box_info.offset,
+                    reason: format!(
+                        "XMP box size too large: {} bytes (max: {} bytes)",
+                        box_info.size, limits.max_segment_size
+                    ),
+                });
             }
+            source.seek(SeekFrom::Start(box_info.offset))?;
+            let mut box_data = vec![0u8; box_info.size as usize];
+            source.read_exact(&mut box_data)?;
+            writer.write_all(&box_data)?;
+        }
+    }
 
-            current_pos = box_start + header.size;
-            source.seek(SeekFrom::Start(current_pos))?;
+    // Write new C2PA UUID if needed
+    if write_jumbf {
+        if let MetadataUpdate::Set(ref jumbf_data) = updates.jumbf {
+            write_c2pa_box(writer, jumbf_data, "manifest", &[], 0)?;
+        }
+    } else if !remove_jumbf {
+        // Keep existing C2PA
+        if let Some(token) = existing_c2pa_token {
+            let box_info = &bmff_tree[token].data;
+            if box_info.size > limits.max_segment_size {
+                return Err(Error::LimitExceeded {
+                    offset: box_info.offset,
+                    reason: format!(
+                        "C2PA box size too large: {} bytes (max: {} bytes)",
+                        box_info.size, limits.max_segment_size
+                    ),
+                });
+            }
+            source.seek(SeekFrom::Start(box_info.offset))?;
+            let mut box_data = vec![0u8; box_info.size as usize];
+            source.read_exact(&mut box_data)?;
+            writer.write_all(&box_data)?;
         }
+    }
 
-        // Compute chunk offset delta and patch stco/co64 when UUID region changed
-        let original_size: u64 = {
-            let xmp = if write_xmp || remove_xmp {
-                existing_xmp_token
-                    .map(|t| bmff_tree[t].data.size)
-                    .unwrap_or(0)
-            } else {
-                0
-            };
-            let c2pa = if write_jumbf || remove_jumbf {
-                existing_c2pa_token
-                    .map(|t| bmff_tree[t].data.size)
-                    .unwrap_or(0)
-            } else {
-                0
-            };
-            xmp + c2pa
-        };
-        let new_size: u64 = {
-            let xmp = match &updates.xmp {
-                MetadataUpdate::Set(data) => calculate_xmp_box_size(data),
-                MetadataUpdate::Remove => 0,
-                MetadataUpdate::Keep => existing_xmp_token
-                    .map(|t| bmff_tree[t].data.size)
-                    .unwrap_or(0),
-            };
-            let c2pa = match &updates.jumbf {
-                MetadataUpdate::Set(data) => calculate_c2pa_box_size(data, "manifest"),
-                MetadataUpdate::Remove => 0,
-                MetadataUpdate::Keep => existing_c2pa_token
-                    .map(|t| bmff_tree[t].data.size)
-                    .unwrap_or(0),
-            };
-            xmp + c2pa
+    // Copy remaining boxes (skip existing UUID boxes)
+    source.seek(SeekFrom::Start(ftyp_end))?;
+    let mut current_pos = ftyp_end;
+
+    while current_pos < file_size {
+        // Read box header to determine if we should skip it
+        let box_start = current_pos;
+        let header = BoxHeaderLite::read(source)?;
+
+        // Check if this is a UUID box we already wrote
+        let should_skip = if header.name == BoxType::UuidBox {
+            let mut uuid_bytes = [0u8; 16];
+            source.read_exact(&mut uuid_bytes)?;
+            source.seek(SeekFrom::Start(box_start))?; // Reset for potential copy
+
+            // Boxes that were kept have already been moved up front
+            (uuid_bytes == XMP_UUID && (write_xmp || remove_xmp || Some(box_start) == kept_xmp))
+                || (uuid_bytes == C2PA_UUID
+                    && (write_jumbf || remove_jumbf || Some(box_start) == kept_c2pa))
+        } else {
+            false
         };
-        let delta = new_size as i64 - original_size as i64;
-        if delta != 0 {
-            pw.flush()?;
-            bmff_adjust_chunk_offsets(pw.get_mut(), delta)?;
+
+        let replacement = rewrites.replacements.get(&box_start);
+        if let Some(bytes) = rewrites.insertions.get(&box_start) {
+            writer.write_all(bytes)?;
         }
 
-        Ok(())
+        if should_skip || rewrites.moved.contains(&box_start) {
+            // Skip this box
+            source.seek(SeekFrom::Start(box_start + header.size))?;
+        } else if let Some(bytes) = replacement {
+            writer.write_all(bytes)?;
+        } else {
+            // Copy this box - use streaming for large boxes (mdat, moov, etc.)
+            source.seek(SeekFrom::Start(box_start))?;
+
+            if header.size > limits.max_segment_size {
+                // Stream large boxes (e.g., mdat) without loading into memory
+                // This is crucial for large video files where mdat can be gigabytes
+                let mut remaining = header.size;
+                let mut buffer = vec![0u8; 8 * 1024 * 1024]; // 8MB buffer
+
+                while remaining > 0 {
+                    let to_read = remaining.min(buffer.len() as u64) as usize;
+                    source.read_exact(&mut buffer[..to_read])?;
+                    writer.write_all(&buffer[..to_read])?;
+                    remaining -= to_read as u64;
+                }
+            } else {
+                // Small boxes can be loaded for efficiency
+                let mut box_data = vec![0u8; header.size as usize];
+                source.read_exact(&mut box_data)?;
+                writer.write_all(&box_data)?;
+            }
+        }
+
+        current_pos = box_start + header.size;
+        source.seek(SeekFrom::Start(current_pos))?;
     }
 
-    fn calculate_updated_structure(
-        &self,
-        source_structure: &Structure,
-        updates: &Updates,
-    ) -> Result<Structure> {
-        use crate::MetadataUpdate;
+    Ok(rewrites.fallback_delta)
+}
 
-        let mut new_structure =
-            Structure::new(source_structure.container, source_structure.media_type);
+/// Top-level boxes that are rebuilt in memory during a write
+struct BoxRewrites {
+    /// New box bytes, keyed by the box offset in the source
+    replacements: HashMap<u64, Vec<u8>>,
+    /// Boxes written before the source box at the key offset (relocated moov)
+    insertions: HashMap<u64, Vec<u8>>,
+    /// Source offsets of boxes that were moved elsewhere
+    moved: Vec<u64>,
+    /// Uniform stco/co64 shift to patch after writing, when moov is too large
+    /// to rewrite in memory
+    fallback_delta: i64,
+}
 
-        // Calculate sizes for new UUID boxes
-        let xmp_box_size = match &updates.xmp {
-            MetadataUpdate::Set(data) => Some(8 + 16 + data.len()), // header + UUID + data
-            MetadataUpdate::Remove => None,
-            MetadataUpdate::Keep => {
-                // Find existing XMP segment size
-                source_structure
-                    .xmp_index()
-                    .and_then(|idx| source_structure.segments().get(idx))
-                    .map(|seg| {
-                        let data_size: u64 = seg.ranges.iter().map(|r| r.size).sum();
-                        (8 + 16 + data_size) as usize // Reconstruct full box size
-                    })
-            }
-        };
+/// Rebuild the HEIF meta box and the moov box as needed by `updates`
+///
+/// `uuid_delta` is the size change of the uuid region inserted after ftyp.
+/// Chunk offsets in moov are patched for every box that moves.
+#[allow(clippy::too_many_arguments)]
+fn plan_box_rewrites<R: Read + Seek>(
+    source: &mut R,
+    bmff_tree: &Arena<BoxInfo>,
+    bmff_map: &HashMap<String, Vec<Token>>,
+    heif_meta: Option<&(u64, HeifMeta)>,
+    updates: &Updates,
+    ftyp_end: u64,
+    uuid_delta: i64,
+    limits: &Limits,
+) -> Result<BoxRewrites> {
+    let mut replacements = HashMap::new();
+    let mut shifts = OffsetShifts::default();
+    shifts.add(ftyp_end, uuid_delta);
+
+    if let Some((offset, meta)) = heif_meta {
+        let (bytes, _) = meta.rewrite(&heif_meta_edit(updates), *offset, uuid_delta)?;
+        shifts.add(offset + meta.len(), bytes.len() as i64 - meta.len() as i64);
+        replacements.insert(*offset, bytes);
+    }
 
-        let jumbf_box_size = match &updates.jumbf {
-            MetadataUpdate::Set(data) => {
-                // C2PA box: header + UUID + version/flags + purpose + null + merkle_offset + data
-                Some(8 + 16 + 4 + "manifest".len() + 1 + 8 + data.len())
+    let mut insertions = HashMap::new();
+    let mut moved = Vec::new();
+    let mut fallback_delta = 0;
+    if let Some(token) = bmff_map.get("/moov").and_then(|v| v.first()) {
+        let moov = &bmff_tree[*token].data;
+        let edits = &updates.quicktime;
+        // Faststart moves moov right before the first mdat that precedes it
+        let relocate_before = bmff_map
+            .get("/mdat")
+            .and_then(|v| v.iter().map(|t| bmff_tree[*t].data.offset).min())
+            .filter(|mdat| updates.faststart && *mdat < moov.offset);
+        if moov.size > limits.max_segment_size {
+            if !edits.is_empty() || relocate_before.is_some() {
+                return Err(Error::LimitExceeded {
+                    offset: moov.offset,
+                    reason: format!(
+                        "moov box size too large to rewrite: {} bytes (max: {} bytes)",
+                        moov.size, limits.max_segment_size
+                    ),
+                });
             }
-            MetadataUpdate::Remove => None,
-            MetadataUpdate::Keep => {
-                // Find existing JUMBF segment size
-                source_structure
-                    .jumbf_indices()
-                    .first()
-                    .and_then(|&idx| source_structure.segments().get(idx))
-                    .map(|seg| {
-                        let data_size: u64 = seg.ranges.iter().map(|r| r.size).sum();
-                        (8 + 16 + 4 + "manifest".len() + 1 + 8) + data_size as usize
-                    })
+            fallback_delta = shifts.total();
+        } else if !edits.is_empty() || !shifts.is_empty() || relocate_before.is_some() {
+            source.seek(SeekFrom::Start(moov.offset))?;
+            let mut bytes = vec![0u8; moov.size as usize];
+            source.read_exact(&mut bytes)?;
+            let mut new_moov = quicktime_meta::edit_moov(&bytes, edits)?;
+            let moov_end = moov.offset + moov.size;
+            // Promote stco tables that overflow until the moov size settles, since
+            // each promotion grows moov and moves the data after it
+            let shifts = loop {
+                let mut moov_shifts = shifts.clone();
+                match relocate_before {
+                    Some(mdat) => {
+                        moov_shifts.add(mdat, new_moov.len() as i64);
+                        moov_shifts.add(moov_end, -(moov.size as i64));
+                    }
+                    None => moov_shifts.add(moov_end, new_moov.len() as i64 - moov.size as i64),
+                }
+                let overflowing = overflowing_stco(&new_moov, &moov_shifts)?;
+                if overflowing.is_empty() {
+                    break moov_shifts;
+                }
+                new_moov = promote_stco(&new_moov, &overflowing)?;
+            };
+            patch_chunk_offsets(&mut new_moov, &shifts)?;
+            match relocate_before {
+                Some(mdat) => {
+                    insertions.insert(mdat, new_moov);
+                    moved.push(moov.offset);
+                }
+                None => {
+                    replacements.insert(moov.offset, new_moov);
+                }
             }
-        };
-
-        // Start with ftyp box (assume it exists and comes first)
-        // For BMFF, XMP/JUMBF boxes are written right after ftyp
-        // If source has metadata segments, infer ftyp size from first metadata segment's offset
-        // Only consider XMP/JUMBF/EXIF segments, not ImageData (mdat boxes)
-        // Otherwise, we need to make an educated guess based on typical sizes
-        let ftyp_end = source_structure
-            .segments
-            .iter()
-            .filter(|seg| {
-                // Only metadata segments that appear near the start
-                seg.is_xmp() || seg.is_jumbf() || seg.is_exif()
-            })
-            // Use the minimum offset across ALL ranges, not just range[0] (location()).
-            // For C2PA JUMBF segments, range[0] is the JUMBF data (inside the UUID box) and
-            // range[1] is the full UUID box — using range[0].offset gives a value that is 45
-            // bytes too large, shifting every downstream offset in calculate_updated_structure.
-            .map(|seg| seg.ranges.iter().map(|r| r.offset).min().unwrap_or(0))
-            .min() // Get the earliest metadata segment
-            .unwrap_or(match source_structure.media_type {
-                // No segments in source - use typical ftyp size for each format
-                // These are empirically determined from common files
-                crate::MediaType::QuickTime => 20u64, // QuickTime .mov files
-                crate::MediaType::Heif => 24u64,      // HEIF images
-                crate::MediaType::Heic => 24u64,      // HEIC images
-                crate::MediaType::Avif => 32u64,      // AVIF images (larger ftyp!)
-                crate::MediaType::Mp4Audio => 32u64,  // M4A audio files
-                _ => 24u64,                           // Generic MP4 default
-            });
-
-        let mut current_offset = ftyp_end;
-
-        // Add XMP UUID box if present
-        if let Some(size) = xmp_box_size {
-            let data_offset = current_offset + 8 + 16; // Skip header + UUID
-            let data_size = size - 8 - 16;
-            new_structure.add_segment(Segment::with_ranges(
-                vec![ByteRange::new(data_offset, data_size as u64)],
-                SegmentKind::Xmp,
-                Some("uuid/xmp".to_string()),
-            )?);
-            current_offset += size as u64;
         }
+    }
 
-        // Add C2PA UUID box if present
-        if let Some(size) = jumbf_box_size {
-            // Data starts after: header + UUID + version/flags + purpose + null + merkle_offset
-            let data_offset = current_offset + 8 + 16 + 4 + "manifest".len() as u64 + 1 + 8;
-            let data_size = size - (8 + 16 + 4 + "manifest".len() + 1 + 8);
+    Ok(BoxRewrites {
+        replacements,
+        insertions,
+        moved,
+        fallback_delta,
+    })
+}
 
-            // Store TWO ranges:
-            // 1. JUMBF data range (for reading/writing manifest with update_segment)
-            // 2. Full C2PA UUID box range (for hash exclusions per BmffHash spec)
-            new_structure.add_segment(Segment::with_ranges(
-                vec![
-                    ByteRange::new(data_offset, data_size as u64), // JUMBF data
-                    ByteRange::new(current_offset, size as u64),   // Full UUID box
-                ],
-                SegmentKind::Jumbf,
-                Some("uuid/c2pa".to_string()),
-            )?);
-            current_offset += size as u64;
+/// Split `data[start..end]` into boxes, as (fourcc, offset, header length, end)
+fn child_boxes(data: &[u8], start: usize, end: usize) -> Vec<([u8; 4], usize, usize, usize)> {
+    let mut children = Vec::new();
+    let mut pos = start;
+    while pos + 8 <= end {
+        let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as u64;
+        let fourcc: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        let (size, header_len) = match size {
+            0 => ((end - pos) as u64, 8),
+            1 if pos + 16 <= end => (
+                u64::from_be_bytes(data[pos + 8..pos + 16].try_into().unwrap()),
+                16,
+            ),
+            s => (s, 8),
+        };
+        if size < header_len as u64 || pos as u64 + size > end as u64 {
+            break;
         }
+        let box_end = pos + size as usize;
+        children.push((fourcc, pos, header_len, box_end));
+        pos = box_end;
+    }
+    children
+}
 
-        // Add remaining boxes size (moov, mdat, etc.)
-        // The remaining file size minus what was already accounted for (ftyp + metadata)
-        // Only consider metadata segments (XMP/JUMBF/EXIF), not ImageData (mdat boxes)
-        let metadata_end_in_source = source_structure
-            .segments
-            .iter()
-            .rfind(|seg| seg.is_xmp() || seg.is_jumbf() || seg.is_exif())
-            .map(|seg| {
-                let loc = seg.location();
-                loc.offset + loc.size
-            })
-            .unwrap_or(ftyp_end);
-
-        let remaining_size = source_structure
-            .total_size
-            .saturating_sub(metadata_end_in_source);
-        current_offset += remaining_size;
+/// Container boxes between moov and the chunk offset tables
+const CHUNK_OFFSET_PATH: [&[u8; 4]; 4] = [b"trak", b"mdia", b"minf", b"stbl"];
+
+/// A stco or co64 box inside an in-memory moov box
+struct ChunkOffsetTable {
+    /// True for co64
+    large: bool,
+    /// Offset of the box within moov
+    box_start: usize,
+    /// Offset of the first entry within moov
+    entries: usize,
+    count: usize,
+}
 
-        new_structure.total_size = current_offset;
-        Ok(new_structure)
+impl ChunkOffsetTable {
+    fn entry_size(&self) -> usize {
+        if self.large {
+            8
+        } else {
+            4
+        }
     }
 
-    #[cfg(feature = "exif")]
-    fn read_embedded_thumbnail_info<R: Read + Seek>(
-        &self,
-        _structure: &Structure,
-        source: &mut R,
-    ) -> Result<Option<crate::thumbnail::EmbeddedThumbnailInfo>> {
-        // HEIF/HEIC thumbnail extraction
-        // Thumbnails are stored as separate items with 'thmb' reference to the primary item
-        extract_heif_thumbnail_info(source)
+    fn offsets<'a>(&self, moov: &'a [u8]) -> impl Iterator<Item = u64> + 'a {
+        let (entries, size, large) = (self.entries, self.entry_size(), self.large);
+        (0..self.count).map(move |i| {
+            let entry = &moov[entries + i * size..entries + (i + 1) * size];
+            if large {
+                u64::from_be_bytes(entry.try_into().unwrap())
+            } else {
+                u32::from_be_bytes(entry.try_into().unwrap()) as u64
+            }
+        })
     }
+}
 
-    #[cfg(feature = "exif")]
-    fn read_exif_info<R: Read + Seek>(
-        &self,
-        structure: &Structure,
-        source: &mut R,
-    ) -> Result<Option<crate::tiff::ExifInfo>> {
-        use std::io::SeekFrom;
+/// Find every stco/co64 box in an in-memory moov box
+fn chunk_offset_tables(moov: &[u8]) -> Result<Vec<ChunkOffsetTable>> {
+    let Some(&(_, moov_start, moov_header, moov_end)) = child_boxes(moov, 0, moov.len()).first()
+    else {
+        return Ok(Vec::new());
+    };
 
-        // Find EXIF segment
-        let exif_segment = structure
-            .segments()
-            .iter()
-            .find(|s| s.is_type(SegmentKind::Exif));
+    let mut tables = Vec::new();
+    let mut stack = vec![(moov_start + moov_header, moov_end, 0usize)];
+    while let Some((start, end, depth)) = stack.pop() {
+        for (fourcc, pos, header_len, box_end) in child_boxes(moov, start, end) {
+            let depth_path = CHUNK_OFFSET_PATH.get(depth);
+            if depth_path.is_some_and(|name| &fourcc == *name) {
+                stack.push((pos + header_len, box_end, depth + 1));
+            } else if depth == CHUNK_OFFSET_PATH.len() && (&fourcc == b"stco" || &fourcc == b"co64")
+            {
+                // version/flags, then entry count
+                let payload = pos + header_len;
+                if payload + 8 > box_end {
+                    continue;
+                }
+                let table = ChunkOffsetTable {
+                    large: &fourcc == b"co64",
+                    box_start: pos,
+                    entries: payload + 8,
+                    count: u32::from_be_bytes(moov[payload + 4..payload + 8].try_into().unwrap())
+                        as usize,
+                };
+                if table.entries + table.count * table.entry_size() > box_end {
+                    return Err(Error::InvalidFormat(format!(
+                        "Invalid {} box",
+                        String::from_utf8_lossy(&fourcc)
+                    )));
+                }
+                tables.push(table);
+            }
+        }
+    }
+    tables.sort_by_key(|t| t.box_start);
+    Ok(tables)
+}
 
-        let segment = match exif_segment {
-            Some(s) => s,
-            None => return Ok(None),
-        };
+/// Start offsets of the stco boxes whose entries no longer fit in 32 bits
+fn overflowing_stco(moov: &[u8], shifts: &OffsetShifts) -> Result<Vec<usize>> {
+    Ok(chunk_offset_tables(moov)?
+        .into_iter()
+        .filter(|t| !t.large && t.offsets(moov).any(|o| shifts.map(o) > u32::MAX as u64))
+        .map(|t| t.box_start)
+        .collect())
+}
 
-        // Read the EXIF data
-        let location = segment.location();
-        if location.size > MAX_BOX_ALLOCATION {
-            return Err(Error::InvalidFormat(format!(
-                "EXIF data size too large: {} bytes (max: {} bytes)",
-                location.size, MAX_BOX_ALLOCATION
-            )));
-        }
-        source.seek(SeekFrom::Start(location.offset))?;
-        let mut data = vec![0u8; location.size as usize];
-        source.read_exact(&mut data)?;
+/// Serialize a box, keeping a 16-byte header if the original had one
+fn box_with_header(fourcc: &[u8; 4], header_len: usize, payload: &[u8]) -> Result<Vec<u8>> {
+    let size = (header_len + payload.len()) as u64;
+    let mut out = Vec::with_capacity(size as usize);
+    if header_len == HEADER_SIZE_LARGE as usize {
+        out.write_u32::<BigEndian>(1)?;
+        out.extend_from_slice(fourcc);
+        out.write_u64::<BigEndian>(size)?;
+    } else {
+        let size = u32::try_from(size).map_err(|_| {
+            Error::CapacityExceeded(format!("{} box too large", String::from_utf8_lossy(fourcc)))
+        })?;
+        out.write_u32::<BigEndian>(size)?;
+        out.extend_from_slice(fourcc);
+    }
+    out.extend_from_slice(payload);
+    Ok(out)
+}
 
-        // HEIF: Exif item has 4-byte tiff_header_offset prefix, then "Exif\0\0", then TIFF
-        // The 4 bytes are typically 0x00000006 (offset to TIFF data from start of Exif block)
-        let exif_data = if data.len() > 10 && &data[4..10] == b"Exif\0\0" {
-            &data[10..]
-        } else if data.len() > 4 {
-            // Some files may have different structure, try to find TIFF header
-            if data[4..].starts_with(b"II") || data[4..].starts_with(b"MM") {
-                &data[4..]
+/// Convert the stco boxes starting at `targets` into co64 boxes
+///
+/// Entries keep their values; the enclosing trak/mdia/minf/stbl and moov boxes
+/// are resized to match.
+fn promote_stco(moov: &[u8], targets: &[usize]) -> Result<Vec<u8>> {
+    fn rebuild(
+        data: &[u8],
+        (fourcc, start, header_len, end): ([u8; 4], usize, usize, usize),
+        depth: usize,
+        targets: &[usize],
+    ) -> Result<Vec<u8>> {
+        let mut payload = Vec::with_capacity(end - start);
+        for child in child_boxes(data, start + header_len, end) {
+            let (child_fourcc, pos, child_header, child_end) = child;
+            if CHUNK_OFFSET_PATH
+                .get(depth)
+                .is_some_and(|name| &child_fourcc == *name)
+            {
+                payload.extend(rebuild(data, child, depth + 1, targets)?);
+            } else if targets.contains(&pos) {
+                let body = &data[pos + child_header..child_end];
+                let count = u32::from_be_bytes(body[4..8].try_into().unwrap()) as usize;
+                let mut co64 = Vec::with_capacity(8 + count * 8);
+                co64.extend_from_slice(&body[..8]); // version/flags, entry count
+                for entry in body[8..8 + count * 4].chunks_exact(4) {
+                    let offset = u32::from_be_bytes(entry.try_into().unwrap()) as u64;
+                    co64.write_u64::<BigEndian>(offset)?;
+                }
+                payload.extend(box_with_header(b"co64", HEADER_SIZE as usize, &co64)?);
             } else {
-                &data
+                payload.extend_from_slice(&data[pos..child_end]);
             }
-        } else {
-            return Ok(None);
-        };
-
-        crate::tiff::parse_exif_info(exif_data)
+        }
+        box_with_header(&fourcc, header_len, &payload)
     }
 
-    fn exclusion_range_for_segment(structure: &Structure, kind: SegmentKind) -> Option<(u64, u64)> {
-        let segment = match kind {
-            SegmentKind::Jumbf => structure
-                .c2pa_jumbf_index()
-                .map(|i| &structure.segments()[i]),
-            SegmentKind::Xmp => structure.xmp_index().map(|i| &structure.segments()[i]),
-            _ => None,
-        }?;
+    match child_boxes(moov, 0, moov.len()).first() {
+        Some(&moov_box) => rebuild(moov, moov_box, 0, targets),
+        None => Ok(moov.to_vec()),
+    }
+}
 
-        // For BMFF C2PA segments, ranges[1] contains the full UUID box (for hash exclusions)
-        // ranges[0] contains just the JUMBF data (for reading/writing)
-        if kind == SegmentKind::Jumbf && segment.ranges.len() >= 2 {
-            let full_box_range = &segment.ranges[1];
-            Some((full_box_range.offset, full_box_range.size))
-        } else {
-            // For XMP or if only one range exists, use the first range
-            let location = segment.location();
-            Some((location.offset, location.size))
+/// Rewrite the stco/co64 entries of an in-memory moov box through `shifts`
+fn patch_chunk_offsets(moov: &mut [u8], shifts: &OffsetShifts) -> Result<()> {
+    for table in chunk_offset_tables(moov)? {
+        let new_offsets: Vec<u64> = table.offsets(moov).map(|o| shifts.map(o)).collect();
+        let size = table.entry_size();
+        for (i, offset) in new_offsets.into_iter().enumerate() {
+            let entry = &mut moov[table.entries + i * size..table.entries + (i + 1) * size];
+            if table.large {
+                entry.copy_from_slice(&offset.to_be_bytes());
+            } else {
+                let offset = u32::try_from(offset).map_err(|_| {
+                    Error::CapacityExceeded("Chunk offset overflow in stco box".into())
+                })?;
+                entry.copy_from_slice(&offset.to_be_bytes());
+            }
         }
     }
+    Ok(())
 }
 
 // ============================================================================
@@ -2604,4 +4551,216 @@ mod tests {
 
         assert_eq!(BmffIO::detect(&data), Some(ContainerKind::Bmff));
     }
+
+    fn test_box(fourcc: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        box_with_header(fourcc, HEADER_SIZE as usize, payload).unwrap()
+    }
+
+    /// moov with one track whose stco holds `offsets`, followed by a udta box
+    fn test_moov(offsets: &[u32]) -> Vec<u8> {
+        let mut stco = vec![0, 0, 0, 0];
+        stco.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+        for offset in offsets {
+            stco.extend_from_slice(&offset.to_be_bytes());
+        }
+        let stbl = test_box(b"stbl", &test_box(b"stco", &stco));
+        let trak = test_box(b"trak", &test_box(b"mdia", &test_box(b"minf", &stbl)));
+        let mut children = trak;
+        children.extend(test_box(b"udta", b"data"));
+        test_box(b"moov", &children)
+    }
+
+    #[test]
+    fn test_stco_promoted_to_co64_on_overflow() {
+        let moov = test_moov(&[1000, u32::MAX - 10]);
+        let mut shifts = OffsetShifts::default();
+        shifts.add(0, 100);
+
+        let overflowing = overflowing_stco(&moov, &shifts).unwrap();
+        assert_eq!(overflowing.len(), 1);
+        let mut promoted = promote_stco(&moov, &overflowing).unwrap();
+        assert_eq!(promoted.len(), moov.len() + 8);
+        assert!(overflowing_stco(&promoted, &shifts).unwrap().is_empty());
+
+        patch_chunk_offsets(&mut promoted, &shifts).unwrap();
+        let tables = chunk_offset_tables(&promoted).unwrap();
+        assert_eq!(tables.len(), 1);
+        assert!(tables[0].large);
+        let offsets: Vec<u64> = tables[0].offsets(&promoted).collect();
+        assert_eq!(offsets, vec![1100, u32::MAX as u64 + 90]);
+        // Boxes after the table are intact
+        assert!(promoted.ends_with(&test_box(b"udta", b"data")));
+
+        // Without promotion the 32-bit table cannot be patched
+        let mut unpatched = moov.clone();
+        assert!(patch_chunk_offsets(&mut unpatched, &shifts).is_err());
+    }
+
+    #[test]
+    fn test_chunk_offset_summary_spans_read_buffers() {
+        // More entries than fit in one read buffer, with the largest near the end
+        let mut offsets: Vec<u32> = (0..40_000).collect();
+        offsets[30_000] = 5_000_000;
+        let mut stco = vec![0, 0, 0, 0];
+        stco.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+        for offset in &offsets {
+            stco.extend_from_slice(&offset.to_be_bytes());
+        }
+        let stbl = test_box(b"stbl", &test_box(b"stco", &stco));
+        let trak = test_box(b"trak", &test_box(b"mdia", &test_box(b"minf", &stbl)));
+        let mut data = test_box(b"ftyp", b"isom\0\0\0\0isom");
+        data.extend(test_box(b"moov", &trak));
+
+        let structure = BmffIO::new()
+            .parse(&mut std::io::Cursor::new(&data))
+            .unwrap();
+        let summary = structure
+            .segments
+            .iter()
+            .find_map(|segment| match segment.metadata {
+                Some(SegmentMetadata::ChunkOffsets {
+                    entry_count,
+                    max_offset,
+                }) => Some((entry_count, max_offset)),
+                _ => None,
+            });
+        assert_eq!(summary, Some((40_000, 5_000_000)));
+    }
+
+    #[test]
+    fn test_updated_structure_predicts_co64_promotion() {
+        let moov = test_moov(&[1000, u32::MAX - 10]);
+        let moov_offset = 24u64;
+        let stco_offset = moov_offset + 8 * 5;
+        let stco_size = 8 + 8 + 8;
+
+        let mut source = Structure::new(ContainerKind::Bmff, MediaType::Mp4Video);
+        source.add_segment(Segment::new(
+            moov_offset,
+            moov.len() as u64,
+            SegmentKind::Other,
+            Some("moov".into()),
+        ));
+        source.add_segment(
+            Segment::new(
+                stco_offset,
+                stco_size,
+                SegmentKind::Other,
+                Some("moov/trak/mdia/minf/stbl/stco".into()),
+            )
+            .with_metadata(SegmentMetadata::ChunkOffsets {
+                entry_count: 2,
+                max_offset: u32::MAX as u64 - 10,
+            }),
+        );
+        source.total_size = u32::MAX as u64;
+
+        let jumbf = vec![0u8; 100];
+        let updates = Updates::new().set_jumbf(jumbf.clone());
+        let updated = BmffIO::new()
+            .calculate_updated_structure(&source, &updates)
+            .unwrap();
+
+        let c2pa_size = calculate_c2pa_box_size(&jumbf, "manifest");
+        let table = updated
+            .segments
+            .iter()
+            .find(|seg| seg.path.as_deref() == Some("moov/trak/mdia/minf/stbl/co64"))
+            .unwrap();
+        assert_eq!(table.location().offset, stco_offset + c2pa_size);
+        assert_eq!(table.location().size, stco_size + 8);
+        assert_eq!(updated.total_size, source.total_size + c2pa_size + 8);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_merkle_map_builder_is_independent_of_chunking() {
+        use sha2::Sha256;
+
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+
+        let builder = || {
+            MerkleMapBuilder::<Sha256>::new(
+                MerkleBlocks::Fixed(1000),
+                &mut std::io::empty(),
+                &Limits::default(),
+            )
+        };
+        let mut whole = builder().unwrap();
+        whole.update(0, &data);
+        whole.update(1, &data[..10]);
+        let whole = whole.finish();
+
+        let mut pieces = builder().unwrap();
+        for chunk in data.chunks(333) {
+            pieces.update(0, chunk);
+        }
+        pieces.update(1, &data[..10]);
+        let pieces = pieces.finish();
+
+        assert_eq!(whole, pieces);
+        assert_eq!(whole.len(), 2);
+        assert_eq!(whole[0].count, 10);
+        assert_eq!(whole[1].local_id, 1);
+        assert_eq!(whole[1].count, 1);
+        assert_eq!(whole[0].fixed_block_size, Some(1000));
+        let zero = MerkleMapBuilder::<Sha256>::new(
+            MerkleBlocks::Fixed(0),
+            &mut std::io::empty(),
+            &Limits::default(),
+        );
+        assert!(zero.is_err());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_merkle_map_blocks_follow_media_chunks() {
+        use sha2::{Digest, Sha256};
+
+        let moov = |offsets: &[u32]| {
+            let mut stco = vec![0, 0, 0, 0];
+            stco.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+            for offset in offsets {
+                stco.extend_from_slice(&offset.to_be_bytes());
+            }
+            let stbl = test_box(b"stbl", &test_box(b"stco", &stco));
+            test_box(
+                b"moov",
+                &test_box(b"trak", &test_box(b"mdia", &test_box(b"minf", &stbl))),
+            )
+        };
+        let payload: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let ftyp = test_box(b"ftyp", b"isom\0\0\0\0isom");
+        let mdat = (ftyp.len() + moov(&[0; 3]).len()) as u32;
+
+        // The first chunk starts inside the 16 bytes that go to the main hash
+        let mut data = ftyp;
+        data.extend(moov(&[mdat + 8, mdat + 108, mdat + 508]));
+        data.extend(test_box(b"mdat", &payload));
+        data.extend(test_box(b"mdat", &payload[..100]));
+
+        let mut source = std::io::Cursor::new(&data);
+        let sizes = mdat_chunk_sizes(&mut source, &Limits::default()).unwrap();
+        assert_eq!(sizes, vec![vec![92, 400, 500], vec![92]]);
+
+        let maps =
+            bmff_merkle_maps::<_, Sha256>(&mut source, MerkleBlocks::Chunks, &Limits::default())
+                .unwrap()
+                .maps;
+        assert_eq!(maps.len(), 2);
+        assert_eq!(maps[0].fixed_block_size, None);
+        assert_eq!(maps[0].variable_block_sizes, Some(vec![92, 400, 500]));
+        let leaf = |bytes: &[u8]| -> [u8; 32] { Sha256::digest(bytes).into() };
+        assert_eq!(
+            maps[0].leaves,
+            vec![
+                leaf(&payload[8..100]),
+                leaf(&payload[100..500]),
+                leaf(&payload[500..])
+            ]
+        );
+        // An mdat without chunk offsets is a single block
+        assert_eq!(maps[1].count, 1);
+        assert_eq!(maps[1].leaves, vec![leaf(&payload[8..100])]);
+    }
 }
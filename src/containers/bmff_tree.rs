@@ -0,0 +1,286 @@
+//! Read-only view of the BMFF box hierarchy
+//!
+//! [`BmffTree`] is built while parsing a BMFF file and kept on the
+//! [`Structure`](crate::Structure), so format-specific checks (track handlers,
+//! brands, edit lists) can walk the boxes without reparsing. Box contents are not
+//! held in memory; use [`BmffBox::read_payload`] to load them on demand.
+
+use crate::error::{Error, Result};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Index of a box within its [`BmffTree`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BoxId(usize);
+
+/// A single box in a [`BmffTree`]
+#[derive(Debug, Clone)]
+pub struct BmffBox {
+    id: BoxId,
+    box_type: String,
+    path: String,
+    offset: u64,
+    size: u64,
+    header_size: u64,
+    user_type: Option<[u8; 16]>,
+    version: Option<u8>,
+    flags: Option<u32>,
+    parent: Option<BoxId>,
+    children: Vec<BoxId>,
+}
+
+impl BmffBox {
+    /// A box to add to a tree with [`BmffTree::push`]
+    pub(crate) fn new(
+        box_type: String,
+        offset: u64,
+        size: u64,
+        header_size: u64,
+        user_type: Option<[u8; 16]>,
+        version_flags: Option<(u8, u32)>,
+    ) -> Self {
+        Self {
+            id: BoxId(0),
+            box_type,
+            path: String::new(),
+            offset,
+            size,
+            header_size,
+            user_type,
+            version: version_flags.map(|(v, _)| v),
+            flags: version_flags.map(|(_, f)| f),
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// This box's id within its tree
+    pub fn id(&self) -> BoxId {
+        self.id
+    }
+
+    /// Four-character box type, e.g. `"moov"`
+    pub fn box_type(&self) -> &str {
+        &self.box_type
+    }
+
+    /// Path from the root, e.g. `"/moov/trak/mdia/hdlr"`
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Offset of the box (its size field) in the file
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Size of the whole box, header included
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Size of the size/type header (8, or 16 with a 64-bit size)
+    pub fn header_size(&self) -> u64 {
+        self.header_size
+    }
+
+    /// Extended type of a `uuid` box
+    pub fn user_type(&self) -> Option<&[u8; 16]> {
+        self.user_type.as_ref()
+    }
+
+    /// Version of a full box
+    pub fn version(&self) -> Option<u8> {
+        self.version
+    }
+
+    /// Flags of a full box
+    pub fn flags(&self) -> Option<u32> {
+        self.flags
+    }
+
+    /// Parent box, or `None` for top-level boxes
+    pub fn parent(&self) -> Option<BoxId> {
+        self.parent
+    }
+
+    /// Child boxes in file order (empty for leaf boxes)
+    pub fn children(&self) -> &[BoxId] {
+        &self.children
+    }
+
+    /// Offset of the payload: the bytes after the header, the `uuid` extended type
+    /// and the full-box version/flags
+    pub fn payload_offset(&self) -> u64 {
+        let mut offset = self.offset + self.header_size;
+        if self.user_type.is_some() {
+            offset += 16;
+        }
+        if self.version.is_some() {
+            offset += 4;
+        }
+        offset.min(self.offset + self.size)
+    }
+
+    /// Size of the payload (see [`payload_offset`](Self::payload_offset))
+    pub fn payload_size(&self) -> u64 {
+        self.offset + self.size - self.payload_offset()
+    }
+
+    /// Read the payload from `source`, which must be the file the tree was built from
+    ///
+    /// For container boxes the payload holds the children. Fails if the payload
+    /// is larger than `max_size` bytes, e.g.
+    /// [`Structure::max_segment_size`](crate::Structure::max_segment_size).
+    pub fn read_payload<R: Read + Seek>(&self, source: &mut R, max_size: u64) -> Result<Vec<u8>> {
+        let size = self.payload_size();
+        if size > max_size {
+            return Err(Error::LimitExceeded {
+                offset: self.offset,
+                reason: format!(
+                    "{} box payload too large: {} bytes (max: {} bytes)",
+                    self.box_type, size, max_size
+                ),
+            });
+        }
+        source.seek(SeekFrom::Start(self.payload_offset()))?;
+        let mut payload = vec![0u8; size as usize];
+        source.read_exact(&mut payload)?;
+        Ok(payload)
+    }
+}
+
+/// The box hierarchy of a BMFF file
+///
+/// # Example
+///
+/// ```no_run
+/// use asset_io::Asset;
+///
+/// # fn main() -> asset_io::Result<()> {
+/// let mut asset = Asset::open("video.mp4")?;
+/// let handlers: Vec<_> = match asset.structure().bmff_tree() {
+///     Some(tree) => tree.find_all("/moov/trak/mdia/hdlr").cloned().collect(),
+///     None => Vec::new(),
+/// };
+/// for hdlr in handlers {
+///     let payload = asset.read_bmff_payload(&hdlr)?;
+///     println!("track handler: {}", String::from_utf8_lossy(&payload[4..8]));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BmffTree {
+    boxes: Vec<BmffBox>,
+    top_level: Vec<BoxId>,
+}
+
+impl BmffTree {
+    /// Add a box as the last child of `parent` (or at the top level) and return its id
+    pub(crate) fn push(&mut self, parent: Option<BoxId>, mut entry: BmffBox) -> BoxId {
+        let id = BoxId(self.boxes.len());
+        entry.id = id;
+        entry.parent = parent;
+        entry.path = match parent {
+            Some(parent) => format!("{}/{}", self.boxes[parent.0].path, entry.box_type),
+            None => format!("/{}", entry.box_type),
+        };
+        match parent {
+            Some(parent) => self.boxes[parent.0].children.push(id),
+            None => self.top_level.push(id),
+        }
+        self.boxes.push(entry);
+        id
+    }
+
+    /// Number of boxes in the tree
+    pub fn len(&self) -> usize {
+        self.boxes.len()
+    }
+
+    /// True if the tree has no boxes
+    pub fn is_empty(&self) -> bool {
+        self.boxes.is_empty()
+    }
+
+    /// Look up a box by id
+    pub fn get(&self, id: BoxId) -> Option<&BmffBox> {
+        self.boxes.get(id.0)
+    }
+
+    /// All boxes, depth-first in file order
+    pub fn iter(&self) -> impl Iterator<Item = &BmffBox> {
+        self.boxes.iter()
+    }
+
+    /// Top-level boxes in file order
+    pub fn top_level(&self) -> impl Iterator<Item = &BmffBox> {
+        self.top_level.iter().map(|id| &self.boxes[id.0])
+    }
+
+    /// The first box at `path`, e.g. `"/moov/mvhd"`
+    pub fn find(&self, path: &str) -> Option<&BmffBox> {
+        self.boxes.iter().find(|b| b.path == path)
+    }
+
+    /// Every box at `path` in file order, e.g. all `"/moov/trak"` boxes
+    pub fn find_all<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a BmffBox> {
+        self.boxes.iter().filter(move |b| b.path == path)
+    }
+
+    /// Parent of a box
+    pub fn parent(&self, bmff_box: &BmffBox) -> Option<&BmffBox> {
+        bmff_box.parent.and_then(|id| self.get(id))
+    }
+
+    /// Children of a box in file order
+    pub fn children<'a>(&'a self, bmff_box: &'a BmffBox) -> impl Iterator<Item = &'a BmffBox> {
+        bmff_box.children.iter().map(|id| &self.boxes[id.0])
+    }
+
+    /// The first child of a box with the given type
+    pub fn child(&self, bmff_box: &BmffBox, box_type: &str) -> Option<&BmffBox> {
+        bmff_box
+            .children
+            .iter()
+            .map(|id| &self.boxes[id.0])
+            .find(|b| b.box_type == box_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_navigation_and_payload() {
+        let mut tree = BmffTree::default();
+        let moov = tree.push(None, BmffBox::new("moov".into(), 0, 32, 8, None, None));
+        let trak = tree.push(
+            Some(moov),
+            BmffBox::new("trak".into(), 8, 24, 8, None, None),
+        );
+        let hdlr = tree.push(
+            Some(trak),
+            BmffBox::new("hdlr".into(), 16, 16, 8, None, Some((0, 0))),
+        );
+
+        assert_eq!(tree.len(), 3);
+        let found = tree.find("/moov/trak/hdlr").unwrap();
+        assert_eq!(found.id(), hdlr);
+        assert_eq!(tree.parent(found).unwrap().id(), trak);
+        assert_eq!(
+            tree.child(tree.get(moov).unwrap(), "trak").unwrap().id(),
+            trak
+        );
+        assert_eq!(tree.top_level().count(), 1);
+
+        // Payload skips the header and version/flags
+        assert_eq!(found.payload_offset(), 28);
+        let data: Vec<u8> = (0..32).collect();
+        let payload = found.read_payload(&mut Cursor::new(&data), 1024).unwrap();
+        assert_eq!(payload, vec![28, 29, 30, 31]);
+        assert!(found.read_payload(&mut Cursor::new(&data), 3).is_err());
+    }
+}
@@ -0,0 +1,522 @@
+//! Container handlers registered at runtime
+//!
+//! The built-in containers are fixed at compile time by `register_containers!`.
+//! Other crates add their own formats by implementing [`CustomContainerIO`] and
+//! calling [`register_container`]; `Asset::open` and friends then detect and
+//! dispatch to them like any built-in container.
+
+use std::{
+    fmt,
+    io::{Read, Seek, Write},
+    sync::{Arc, RwLock},
+};
+
+use super::ContainerKind;
+use crate::{
+    error::{Error, Result},
+    jumbf::reject_labeled_boxes,
+    processing_writer::ProcessingWriter,
+    segment::{ByteRange, ExclusionMode, SegmentKind},
+    structure::Structure,
+    Candidate, Confidence, Limits, MediaInfo, MediaType, ProcessChunkFn, Updates, ValidationReport,
+};
+
+/// A source that can be read and seeked, as a trait object
+///
+/// Implemented for every `Read + Seek` type.
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek + ?Sized> ReadSeek for T {}
+
+/// Identity of a runtime-registered container format
+///
+/// Declare one as a `static` so that [`ContainerKind::Custom`] and
+/// [`MediaType::Custom`] can refer to it.
+///
+/// # Example
+///
+/// ```
+/// use asset_io::CustomFormat;
+///
+/// static ACME: CustomFormat =
+///     CustomFormat::new("acme", &["application/x-acme"], &["acme"]);
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomFormat {
+    /// Unique name of the format, e.g. "acme"
+    pub name: &'static str,
+    /// MIME types, primary first (`application/octet-stream` if empty)
+    pub mime_types: &'static [&'static str],
+    /// File extensions (without dot), primary first (`""` if empty)
+    pub extensions: &'static [&'static str],
+}
+
+impl CustomFormat {
+    /// Create a format description
+    pub const fn new(
+        name: &'static str,
+        mime_types: &'static [&'static str],
+        extensions: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            mime_types,
+            extensions,
+        }
+    }
+}
+
+impl fmt::Debug for CustomFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.name)
+    }
+}
+
+/// Container I/O for a format registered at runtime
+///
+/// The object-safe counterpart of the built-in container handlers. Sources and
+/// writers are passed as trait objects; wrap them in `&mut` to use them with
+/// generic helpers such as [`Structure::read_range`].
+///
+/// Structures returned by `parse` and `calculate_updated_structure` should use
+/// `ContainerKind::Custom(self.format())` and `MediaType::Custom(self.format())`.
+pub trait CustomContainerIO: Send + Sync {
+    /// The format this handler reads and writes
+    fn format(&self) -> &'static CustomFormat;
+
+    /// Whether the first bytes of a file are in this format
+    ///
+    /// Gets up to [`SNIFF_LEN`](crate::SNIFF_LEN) bytes.
+    fn detect(&self, header: &[u8]) -> bool;
+
+    /// Parse the file structure, locating XMP and JUMBF segments
+    ///
+    /// `limits` are those the asset was opened with: check sizes against them
+    /// before reading into memory, and report recoverable damage through
+    /// [`Limits::recover`] so that strict parses fail on it.
+    fn parse(&self, source: &mut dyn ReadSeek, limits: &Limits) -> Result<Structure>;
+
+    /// Write the file with updates applied, front to back
+    ///
+    /// [`Updates::xmp`] and [`Updates::jumbf`] say what to do with the metadata.
+    fn write(
+        &self,
+        structure: &Structure,
+        source: &mut dyn ReadSeek,
+        writer: &mut dyn Write,
+        updates: &Updates,
+    ) -> Result<()>;
+
+    /// The structure `write` would produce, without writing
+    fn calculate_updated_structure(
+        &self,
+        source_structure: &Structure,
+        updates: &Updates,
+    ) -> Result<Structure>;
+
+    /// The range to exclude from a C2PA data hash for a segment kind
+    ///
+    /// With [`ExclusionMode::DataOnly`] this is the segment data alone; with
+    /// [`ExclusionMode::EntireSegment`] it also covers the format's headers
+    /// around it. Without a range, the segment's location is excluded.
+    fn exclusion_range_for_segment(
+        &self,
+        structure: &Structure,
+        kind: SegmentKind,
+        mode: ExclusionMode,
+    ) -> Option<(u64, u64)>;
+
+    /// Read the XMP packet
+    ///
+    /// The default concatenates the ranges of the XMP segment.
+    fn read_xmp(
+        &self,
+        structure: &Structure,
+        source: &mut dyn ReadSeek,
+    ) -> Result<Option<Vec<u8>>> {
+        structure
+            .xmp_index()
+            .map(|index| read_ranges(structure, source, index))
+            .transpose()
+    }
+
+    /// Read the C2PA manifest store JUMBF
+    ///
+    /// The default concatenates the ranges of the C2PA JUMBF segment.
+    fn read_jumbf(
+        &self,
+        structure: &Structure,
+        source: &mut dyn ReadSeek,
+    ) -> Result<Option<Vec<u8>>> {
+        structure
+            .c2pa_jumbf_index()
+            .map(|index| read_ranges(structure, source, index))
+            .transpose()
+    }
+
+    /// Read technical media properties
+    ///
+    /// The default reports nothing.
+    fn read_media_info(
+        &self,
+        _structure: &Structure,
+        _source: &mut dyn ReadSeek,
+    ) -> Result<MediaInfo> {
+        Ok(MediaInfo::default())
+    }
+
+    /// Check the asset against the format specification
+    ///
+    /// The default finds nothing wrong.
+    fn validate(
+        &self,
+        _structure: &Structure,
+        _source: &mut dyn ReadSeek,
+        _report: &mut ValidationReport,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Concatenate the ranges of the segment at `index`
+fn read_ranges(
+    structure: &Structure,
+    mut source: &mut dyn ReadSeek,
+    index: usize,
+) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for range in &structure.segments()[index].ranges {
+        data.extend(structure.read_range(&mut source, *range)?);
+    }
+    Ok(data)
+}
+
+/// A registered handler and the media type slice its container reports
+struct Registered {
+    io: Arc<dyn CustomContainerIO>,
+    media_types: &'static [MediaType],
+}
+
+static REGISTRY: RwLock<Vec<Registered>> = RwLock::new(Vec::new());
+
+/// Media type slices handed out so far, one per format name
+static INTERNED: RwLock<Vec<(&'static str, &'static [MediaType])>> = RwLock::new(Vec::new());
+
+/// The `'static` media type slice of a format, leaked the first time its name is seen
+///
+/// Later registrations under the same name get the first slice back, so
+/// re-registering a format does not leak again.
+fn interned_media_types(format: &'static CustomFormat) -> &'static [MediaType] {
+    let find = |interned: &[(&'static str, &'static [MediaType])]| {
+        interned
+            .iter()
+            .find(|(name, _)| *name == format.name)
+            .map(|(_, slice)| *slice)
+    };
+    if let Some(slice) = find(&INTERNED.read().unwrap_or_else(|e| e.into_inner())) {
+        return slice;
+    }
+    let mut interned = INTERNED.write().unwrap_or_else(|e| e.into_inner());
+    if let Some(slice) = find(&interned) {
+        return slice;
+    }
+    let slice: &'static [MediaType] = Box::leak(Box::new([MediaType::Custom(format)]));
+    interned.push((format.name, slice));
+    slice
+}
+
+/// Register a handler for a format the crate does not support itself
+///
+/// Registered handlers are tried before the built-in ones, so a handler can
+/// claim files that would otherwise be read as, say, generic MP4. A handler
+/// registered earlier under the same format name is replaced.
+///
+/// # Example
+///
+/// ```no_run
+/// use asset_io::{register_container, Asset, CustomContainerIO};
+///
+/// # fn register(acme_io: impl CustomContainerIO + 'static) -> asset_io::Result<()> {
+/// register_container(acme_io);
+/// let asset = Asset::open("drawing.acme")?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn register_container(handler: impl CustomContainerIO + 'static) {
+    let format = handler.format();
+    let media_types = interned_media_types(format);
+    let mut registry = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+    registry.retain(|r| r.io.format().name != format.name);
+    registry.insert(
+        0,
+        Registered {
+            io: Arc::new(handler),
+            media_types,
+        },
+    );
+}
+
+/// Remove the handler registered for a format name
+///
+/// Returns whether one was registered.
+pub fn unregister_container(name: &str) -> bool {
+    let mut registry = REGISTRY.write().unwrap_or_else(|e| e.into_inner());
+    let len = registry.len();
+    registry.retain(|r| r.io.format().name != name);
+    registry.len() != len
+}
+
+/// Run `f` over the registered handlers, most recent first
+fn with_registry<T>(f: impl FnOnce(&[Registered]) -> T) -> T {
+    f(&REGISTRY.read().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// The custom container whose handler detects `header`, if any
+pub(crate) fn detect(header: &[u8]) -> Option<ContainerKind> {
+    with_registry(|registry| {
+        registry
+            .iter()
+            .find(|r| r.io.detect(header))
+            .map(|r| ContainerKind::Custom(r.io.format()))
+    })
+}
+
+/// Media types of the custom containers whose handlers detect `header`
+pub(crate) fn sniff(header: &[u8]) -> Vec<Candidate> {
+    with_registry(|registry| {
+        registry
+            .iter()
+            .filter(|r| r.io.detect(header))
+            .map(|r| Candidate::new(MediaType::Custom(r.io.format()), Confidence::High))
+            .collect()
+    })
+}
+
+/// The registered format matching `predicate`, if any
+pub(crate) fn find_format(
+    predicate: impl Fn(&CustomFormat) -> bool,
+) -> Option<&'static CustomFormat> {
+    with_registry(|registry| {
+        registry
+            .iter()
+            .map(|r| r.io.format())
+            .find(|format| predicate(format))
+    })
+}
+
+/// Media types a custom container holds (none if it is not registered)
+pub(crate) fn media_types(format: &CustomFormat) -> &'static [MediaType] {
+    with_registry(|registry| {
+        registry
+            .iter()
+            .find(|r| r.io.format().name == format.name)
+            .map_or(&[][..], |r| r.media_types)
+    })
+}
+
+/// Handler for a custom container
+pub(crate) fn handler(format: &CustomFormat) -> Result<CustomHandler> {
+    with_registry(|registry| {
+        registry
+            .iter()
+            .find(|r| r.io.format().name == format.name)
+            .map(|r| CustomHandler {
+                io: Arc::clone(&r.io),
+                limits: Limits::default(),
+            })
+    })
+    .ok_or_else(|| Error::Unsupported(format!("No handler registered for {}", format.name)))
+}
+
+/// A registered handler with the limits of the asset using it
+pub(crate) struct CustomHandler {
+    pub(crate) io: Arc<dyn CustomContainerIO>,
+    pub(crate) limits: Limits,
+}
+
+impl CustomHandler {
+    pub(crate) fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Write through the handler, which only knows about XMP and the C2PA JUMBF
+    pub(crate) fn write(
+        &self,
+        structure: &Structure,
+        source: &mut dyn ReadSeek,
+        writer: &mut dyn Write,
+        updates: &Updates,
+    ) -> Result<()> {
+        reject_labeled_boxes(updates, ContainerKind::Custom(self.io.format()))?;
+        self.io.write(structure, source, writer, updates)
+    }
+
+    /// Write through the handler, keeping the excluded segments of the output
+    /// away from `processor`
+    ///
+    /// The handler only sees a plain writer, so the exclusions are worked out
+    /// from the updated structure and applied by output position.
+    pub(crate) fn write_with_processor<W: Write, F: ProcessChunkFn>(
+        &self,
+        structure: &Structure,
+        source: &mut dyn ReadSeek,
+        writer: &mut W,
+        updates: &Updates,
+        processor: &mut F,
+    ) -> Result<()> {
+        let dest = self.calculate_updated_structure(structure, updates)?;
+        let mode = updates.processing.exclusion_mode;
+        let excluded = updates
+            .processing
+            .exclude_segments
+            .iter()
+            .filter_map(|kind| {
+                self.io
+                    .exclusion_range_for_segment(&dest, *kind, mode)
+                    .map(|(offset, size)| ByteRange::new(offset, size))
+                    .or_else(|| {
+                        // Fallback: use the segment's raw location
+                        dest.segments()
+                            .iter()
+                            .find(|s| s.is_type(*kind))
+                            .map(|s| s.location())
+                    })
+            })
+            .collect();
+        let mut writer = ExcludingWriter {
+            inner: ProcessingWriter::new(writer, processor),
+            excluded,
+            position: 0,
+        };
+        self.write(structure, source, &mut writer, updates)
+    }
+
+    /// Calculate the updated structure, which only knows about XMP and the C2PA JUMBF
+    pub(crate) fn calculate_updated_structure(
+        &self,
+        source_structure: &Structure,
+        updates: &Updates,
+    ) -> Result<Structure> {
+        reject_labeled_boxes(updates, ContainerKind::Custom(self.io.format()))?;
+        self.io
+            .calculate_updated_structure(source_structure, updates)
+    }
+}
+
+/// Processing writer that switches exclude mode on and off at fixed output ranges
+struct ExcludingWriter<'a, W: Write, F: ProcessChunkFn> {
+    inner: ProcessingWriter<'a, W, F>,
+    excluded: Vec<ByteRange>,
+    position: u64,
+}
+
+impl<W: Write, F: ProcessChunkFn> Write for ExcludingWriter<'_, W, F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let position = self.position;
+        let excluded = self
+            .excluded
+            .iter()
+            .any(|r| r.offset <= position && position < r.offset + r.size);
+        // Stop at the next range edge so each write is wholly in or out
+        let len = self
+            .excluded
+            .iter()
+            .flat_map(|r| [r.offset, r.offset + r.size])
+            .filter(|&edge| edge > position)
+            .min()
+            .map_or(buf.len(), |edge| buf.len().min((edge - position) as usize));
+        self.inner.set_exclude_mode(excluded);
+        self.inner.write_all(&buf[..len])?;
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FORMAT: CustomFormat =
+        CustomFormat::new("unit-test", &["application/x-unit-test"], &["utst"]);
+
+    struct TestIO;
+
+    impl CustomContainerIO for TestIO {
+        fn format(&self) -> &'static CustomFormat {
+            &FORMAT
+        }
+
+        fn detect(&self, header: &[u8]) -> bool {
+            header.starts_with(b"UTST")
+        }
+
+        fn parse(&self, _source: &mut dyn ReadSeek, _limits: &Limits) -> Result<Structure> {
+            Ok(Structure::new(
+                ContainerKind::Custom(&FORMAT),
+                MediaType::Custom(&FORMAT),
+            ))
+        }
+
+        fn write(
+            &self,
+            _structure: &Structure,
+            _source: &mut dyn ReadSeek,
+            _writer: &mut dyn Write,
+            _updates: &Updates,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn calculate_updated_structure(
+            &self,
+            _source_structure: &Structure,
+            _updates: &Updates,
+        ) -> Result<Structure> {
+            self.parse(&mut std::io::empty(), &Limits::default())
+        }
+
+        fn exclusion_range_for_segment(
+            &self,
+            _structure: &Structure,
+            _kind: SegmentKind,
+            _mode: ExclusionMode,
+        ) -> Option<(u64, u64)> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_register_and_unregister() {
+        register_container(TestIO);
+        let media_types = ContainerKind::Custom(&FORMAT).supported_media_types();
+        register_container(TestIO);
+        let kind = ContainerKind::Custom(&FORMAT);
+        // Registering again reuses the media type slice
+        assert!(std::ptr::eq(media_types, kind.supported_media_types()));
+        assert_eq!(detect(b"UTST0001"), Some(kind));
+        assert_eq!(kind.supported_media_types(), [MediaType::Custom(&FORMAT)]);
+        assert_eq!(ContainerKind::from_extension("UTST"), Some(kind));
+        assert_eq!(
+            MediaType::from_mime("application/x-unit-test"),
+            Some(MediaType::Custom(&FORMAT))
+        );
+
+        assert!(unregister_container("unit-test"));
+        assert!(!unregister_container("unit-test"));
+        assert_eq!(detect(b"UTST0001"), None);
+        assert!(handler(&FORMAT).is_err());
+    }
+
+    #[test]
+    fn test_format_without_mime_types_or_extensions() {
+        static BARE: CustomFormat = CustomFormat::new("bare", &[], &[]);
+        let media_type = MediaType::Custom(&BARE);
+        assert_eq!(media_type.to_mime(), "application/octet-stream");
+        assert_eq!(media_type.to_extension(), "");
+    }
+}
@@ -0,0 +1,1231 @@
+//! HEIF item metadata (`meta` box) parsing and rewriting
+//!
+//! HEIF files store metadata such as XMP as *items* rather than `uuid` boxes:
+//! an `infe` entry in `iinf` declares the item (type `mime`, content type
+//! `application/rdf+xml` for XMP) and an `iloc` entry gives its extents, either
+//! as file offsets (construction method 0) or relative to the `idat` box
+//! (construction method 1).
+//!
+//! The `meta` box is small, so it is parsed and rewritten in memory. Rewriting
+//! keeps every child box byte-for-byte except `iinf`, `iloc`, `iref` and `idat`,
+//! which are re-serialized only when they change.
+//!
+//! Reference: ISO/IEC 14496-12:2022 §8.11, ISO/IEC 23008-12 Annex A
+
+use crate::{
+    error::{Error, Result},
+    segment::ByteRange,
+};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Read};
+
+/// Content type of XMP items
+pub(crate) const XMP_CONTENT_TYPE: &str = "application/rdf+xml";
+
+/// Content type of C2PA manifest store items
+pub(crate) const C2PA_CONTENT_TYPE: &str = "application/c2pa";
+
+/// A child box of `meta`, as a byte range within the `meta` box
+#[derive(Debug, Clone, Copy)]
+struct Child {
+    fourcc: [u8; 4],
+    start: usize,
+    end: usize,
+}
+
+/// An `infe` entry
+#[derive(Debug, Clone)]
+pub(crate) struct ItemInfo {
+    pub(crate) id: u32,
+    pub(crate) item_type: [u8; 4],
+    pub(crate) content_type: Option<String>,
+    /// The complete `infe` box, copied unchanged when the item is kept
+    raw: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Extent {
+    index: u64,
+    offset: u64,
+    length: u64,
+}
+
+/// An `iloc` entry
+#[derive(Debug, Clone)]
+struct ItemLocation {
+    id: u32,
+    construction_method: u8,
+    data_reference_index: u16,
+    base_offset: u64,
+    extents: Vec<Extent>,
+}
+
+#[derive(Debug, Clone)]
+struct Iloc {
+    version: u8,
+    flags: u32,
+    offset_size: u8,
+    length_size: u8,
+    base_offset_size: u8,
+    index_size: u8,
+    items: Vec<ItemLocation>,
+}
+
+/// `ispe` and `pixi` properties of the primary item, from `iprp`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ImageProperties {
+    /// Item type of the primary item, e.g. `hvc1`, `av01` or `grid`
+    pub(crate) item_type: Option<[u8; 4]>,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    /// Bits per channel, one entry per channel
+    pub(crate) bits_per_channel: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+struct IrefEntry {
+    ref_type: [u8; 4],
+    from: u32,
+    to: Vec<u32>,
+}
+
+/// Changes to apply to the items of a `meta` box
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct MetaEdit<'a> {
+    /// Remove existing XMP items
+    pub(crate) remove_xmp: bool,
+    /// Remove existing C2PA items
+    pub(crate) remove_c2pa: bool,
+    /// Add a new XMP item stored in `idat`
+    pub(crate) set_xmp: Option<&'a [u8]>,
+}
+
+impl MetaEdit<'_> {
+    fn is_empty(&self) -> bool {
+        !self.remove_xmp && !self.remove_c2pa && self.set_xmp.is_none()
+    }
+}
+
+/// A parsed top-level `meta` box
+#[derive(Debug, Clone)]
+pub(crate) struct HeifMeta {
+    bytes: Vec<u8>,
+    /// Box header plus version/flags (when `meta` is a full box)
+    header_len: usize,
+    children: Vec<Child>,
+    iinf: Option<(u8, u32, Vec<ItemInfo>)>,
+    iloc: Option<Iloc>,
+    iref: Option<(u8, u32, Vec<IrefEntry>)>,
+    /// Range of the `idat` payload within `bytes`
+    idat: Option<(usize, usize)>,
+    primary: Option<u32>,
+}
+
+impl HeifMeta {
+    /// Parse a complete `meta` box (header included)
+    pub(crate) fn parse(bytes: Vec<u8>) -> Result<Self> {
+        if bytes.len() < 8 || &bytes[4..8] != b"meta" {
+            return Err(Error::InvalidFormat("Not a meta box".into()));
+        }
+        let box_header = if u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) == 1 {
+            16
+        } else {
+            8
+        };
+        // ISO meta is a full box; QuickTime meta is a plain container whose
+        // first child (hdlr) starts right after the header.
+        let header_len =
+            if bytes.len() >= box_header + 8 && &bytes[box_header + 4..box_header + 8] == b"hdlr" {
+                box_header
+            } else {
+                box_header + 4
+            };
+
+        let mut meta = HeifMeta {
+            bytes,
+            header_len,
+            children: Vec::new(),
+            iinf: None,
+            iloc: None,
+            iref: None,
+            idat: None,
+            primary: None,
+        };
+
+        let mut pos = header_len;
+        while pos + 8 <= meta.bytes.len() {
+            let size = u32::from_be_bytes(meta.bytes[pos..pos + 4].try_into().unwrap()) as usize;
+            let mut fourcc = [0u8; 4];
+            fourcc.copy_from_slice(&meta.bytes[pos + 4..pos + 8]);
+            let end = match size {
+                0 => meta.bytes.len(),
+                1 => {
+                    return Err(Error::InvalidFormat(
+                        "Large-size boxes are not supported inside meta".into(),
+                    ))
+                }
+                s if s < 8 || pos + s > meta.bytes.len() => {
+                    return Err(Error::InvalidFormat(format!(
+                        "Invalid '{}' box size {} in meta",
+                        String::from_utf8_lossy(&fourcc),
+                        s
+                    )))
+                }
+                s => pos + s,
+            };
+            meta.children.push(Child {
+                fourcc,
+                start: pos,
+                end,
+            });
+            pos = end;
+        }
+
+        for child in meta.children.clone() {
+            let body = &meta.bytes[child.start + 8..child.end];
+            match &child.fourcc {
+                b"iinf" => meta.iinf = Some(parse_iinf(body)?),
+                b"iloc" => meta.iloc = Some(parse_iloc(body)?),
+                b"iref" => meta.iref = Some(parse_iref(body)?),
+                b"idat" => meta.idat = Some((child.start + 8, child.end)),
+                b"pitm" => {
+                    let mut r = Cursor::new(body);
+                    let version = r.read_u8()?;
+                    r.read_u24::<BigEndian>()?;
+                    meta.primary = Some(if version == 0 {
+                        r.read_u16::<BigEndian>()? as u32
+                    } else {
+                        r.read_u32::<BigEndian>()?
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(meta)
+    }
+
+    /// True if this `meta` box describes items (has both `iinf` and `iloc`)
+    pub(crate) fn has_items(&self) -> bool {
+        self.iinf.is_some() && self.iloc.is_some()
+    }
+
+    /// Size of the `meta` box in bytes
+    pub(crate) fn len(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    /// The raw `meta` box
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The item entries declared in `iinf`
+    pub(crate) fn items(&self) -> &[ItemInfo] {
+        self.iinf
+            .as_ref()
+            .map(|(_, _, i)| i.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Ids of `mime` items with the given content type
+    pub(crate) fn items_with_content_type<'a>(
+        &'a self,
+        content_type: &'a str,
+    ) -> impl Iterator<Item = u32> + 'a {
+        self.items()
+            .iter()
+            .filter(move |i| {
+                &i.item_type == b"mime" && i.content_type.as_deref() == Some(content_type)
+            })
+            .map(|i| i.id)
+    }
+
+    /// Image properties of the primary item
+    ///
+    /// Properties are found through the `ipma` associations of the primary item.
+    /// Without a primary item or associations, the largest `ispe` and the first
+    /// `pixi` are used.
+    pub(crate) fn primary_image_properties(&self) -> Result<ImageProperties> {
+        let mut props = ImageProperties {
+            item_type: self
+                .primary
+                .and_then(|id| self.items().iter().find(|i| i.id == id))
+                .map(|i| i.item_type),
+            ..Default::default()
+        };
+        let Some(iprp) = self.children.iter().find(|c| &c.fourcc == b"iprp") else {
+            return Ok(props);
+        };
+
+        let mut properties = Vec::new();
+        let mut associations = Vec::new();
+        for (fourcc, body) in sub_boxes(&self.bytes[iprp.start + 8..iprp.end])? {
+            match &fourcc {
+                b"ipco" => properties = sub_boxes(body)?,
+                b"ipma" => associations = parse_ipma(body)?,
+                _ => {}
+            }
+        }
+
+        // Property indices are 1-based; 0 means "no property"
+        let associated: Vec<&([u8; 4], &[u8])> = match self
+            .primary
+            .and_then(|id| associations.iter().find(|(item, _)| *item == id))
+        {
+            Some((_, indices)) => indices
+                .iter()
+                .filter_map(|&i| properties.get((i as usize).checked_sub(1)?))
+                .collect(),
+            None => properties.iter().collect(),
+        };
+
+        for (fourcc, body) in associated {
+            match fourcc {
+                // version/flags(4) + image_width(4) + image_height(4)
+                b"ispe" if body.len() >= 12 => {
+                    let w = u32::from_be_bytes(body[4..8].try_into().unwrap());
+                    let h = u32::from_be_bytes(body[8..12].try_into().unwrap());
+                    let area = |w: Option<u32>, h: Option<u32>| {
+                        w.unwrap_or(0) as u64 * h.unwrap_or(0) as u64
+                    };
+                    if area(Some(w), Some(h)) > area(props.width, props.height) {
+                        props.width = Some(w);
+                        props.height = Some(h);
+                    }
+                }
+                // version/flags(4) + num_channels(1) + bits_per_channel(1 each)
+                b"pixi" if body.len() >= 5 && props.bits_per_channel.is_empty() => {
+                    let count = body[4] as usize;
+                    if let Some(bits) = body.get(5..5 + count) {
+                        props.bits_per_channel = bits.to_vec();
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(props)
+    }
+
+    /// Absolute file ranges of an item, given the file offset of this `meta` box
+    ///
+    /// Returns `None` for unknown items and for construction methods other than
+    /// file offsets and `idat`.
+    pub(crate) fn item_ranges(&self, id: u32, meta_offset: u64) -> Option<Vec<ByteRange>> {
+        let loc = self.iloc.as_ref()?.items.iter().find(|l| l.id == id)?;
+        if loc.data_reference_index != 0 {
+            return None;
+        }
+        let base = match loc.construction_method {
+            0 => loc.base_offset,
+            1 => meta_offset + self.idat?.0 as u64 + loc.base_offset,
+            _ => return None,
+        };
+        let ranges: Vec<ByteRange> = loc
+            .extents
+            .iter()
+            .map(|e| ByteRange::new(base + e.offset, e.length))
+            .collect();
+        if ranges.is_empty() || ranges.iter().any(|r| r.size == 0) {
+            return None;
+        }
+        Some(ranges)
+    }
+
+    /// Rewrite the `meta` box for the output file
+    ///
+    /// `meta_offset` is the offset of this box in the source file. File-offset
+    /// extents before it move by `shift_before`; extents after it additionally move
+    /// by the change in size of the `meta` box itself.
+    ///
+    /// A replaced or removed item loses its `iinf` and `iloc` entries wherever its
+    /// data lives. New XMP always goes in `idat`; an old copy stored in `mdat`
+    /// (construction method 0) stays there unreferenced, since dropping it would
+    /// move every extent after it.
+    ///
+    /// Returns the new box and the offset of the new XMP data within it (if any).
+    pub(crate) fn rewrite(
+        &self,
+        edit: &MetaEdit<'_>,
+        meta_offset: u64,
+        shift_before: i64,
+    ) -> Result<(Vec<u8>, Option<u64>)> {
+        if edit.is_empty() && shift_before == 0 {
+            return Ok((self.bytes.clone(), None));
+        }
+
+        // The meta size depends on iloc field widths, which depend on the shifted
+        // offsets, which depend on the meta size. Iterate until stable.
+        let mut growth = 0i64;
+        for _ in 0..4 {
+            let (bytes, xmp_offset) = self.rewrite_with(edit, meta_offset, shift_before, growth)?;
+            let new_growth = bytes.len() as i64 - self.bytes.len() as i64;
+            if new_growth == growth {
+                return Ok((bytes, xmp_offset));
+            }
+            growth = new_growth;
+        }
+        Err(Error::InvalidFormat(
+            "meta box layout did not converge".into(),
+        ))
+    }
+
+    fn rewrite_with(
+        &self,
+        edit: &MetaEdit<'_>,
+        meta_offset: u64,
+        shift_before: i64,
+        growth: i64,
+    ) -> Result<(Vec<u8>, Option<u64>)> {
+        let meta_end = meta_offset + self.len();
+        let shift_after = shift_before + growth;
+
+        let removed: Vec<u32> = self
+            .items()
+            .iter()
+            .filter(|i| {
+                (edit.remove_xmp || edit.set_xmp.is_some())
+                    && i.content_type.as_deref() == Some(XMP_CONTENT_TYPE)
+                    || edit.remove_c2pa && i.content_type.as_deref() == Some(C2PA_CONTENT_TYPE)
+            })
+            .map(|i| i.id)
+            .collect();
+
+        let items_changed = !removed.is_empty() || edit.set_xmp.is_some();
+
+        let new_id = self
+            .items()
+            .iter()
+            .map(|i| i.id)
+            .chain(self.iloc.iter().flat_map(|l| l.items.iter().map(|i| i.id)))
+            .max()
+            .unwrap_or(0)
+            .checked_add(1)
+            .ok_or_else(|| Error::CapacityExceeded("no item ID left for the XMP item".into()))?;
+
+        // idat payload and the iloc entries that point into it
+        let old_idat: &[u8] = self.idat.map(|(s, e)| &self.bytes[s..e]).unwrap_or(&[]);
+        let mut iloc = self.iloc.clone();
+        let mut idat = old_idat.to_vec();
+        let mut idat_changed = false;
+        let mut xmp_in_idat = None;
+
+        if let Some(iloc) = iloc.as_mut() {
+            iloc.items.retain(|l| !removed.contains(&l.id));
+
+            let removed_from_idat = self.iloc.as_ref().is_some_and(|l| {
+                l.items
+                    .iter()
+                    .any(|i| removed.contains(&i.id) && i.construction_method == 1)
+            });
+            if removed_from_idat {
+                if let Some(compacted) = compact_idat(old_idat, &mut iloc.items) {
+                    idat = compacted;
+                    idat_changed = true;
+                }
+            }
+
+            for loc in iloc.items.iter_mut() {
+                if loc.construction_method != 0 || loc.data_reference_index != 0 {
+                    continue;
+                }
+                let base = loc.base_offset;
+                let shift_for = |offset: u64| {
+                    if base + offset >= meta_end {
+                        shift_after
+                    } else {
+                        shift_before
+                    }
+                };
+                let first = shift_for(loc.extents.first().map_or(0, |e| e.offset));
+                let uniform = loc.extents.iter().all(|e| shift_for(e.offset) == first);
+                if loc.extents.is_empty() || base != 0 && uniform {
+                    loc.base_offset = shift_offset(base, first)?;
+                } else {
+                    // Extents on both sides of the meta box cannot share a base
+                    // offset, so each one is shifted on its own
+                    for extent in loc.extents.iter_mut() {
+                        extent.offset =
+                            shift_offset(base + extent.offset, shift_for(extent.offset))?;
+                    }
+                    loc.base_offset = 0;
+                }
+            }
+
+            if let Some(xmp) = edit.set_xmp {
+                xmp_in_idat = Some(idat.len() as u64);
+                iloc.items.push(ItemLocation {
+                    id: new_id,
+                    construction_method: 1,
+                    data_reference_index: 0,
+                    base_offset: 0,
+                    extents: vec![Extent {
+                        index: 0,
+                        offset: idat.len() as u64,
+                        length: xmp.len() as u64,
+                    }],
+                });
+                idat.extend_from_slice(xmp);
+                idat_changed = true;
+            }
+        }
+
+        // iinf
+        let iinf = self.iinf.as_ref().map(|(version, flags, items)| {
+            let mut entries: Vec<Vec<u8>> = items
+                .iter()
+                .filter(|i| !removed.contains(&i.id))
+                .map(|i| i.raw.clone())
+                .collect();
+            if edit.set_xmp.is_some() {
+                entries.push(build_xmp_infe(new_id));
+            }
+            (*version, *flags, entries)
+        });
+
+        // iref: drop references to removed items, link the new XMP item to the primary item
+        let mut iref = self.iref.clone();
+        if items_changed {
+            let refs = iref.get_or_insert((0, 0, Vec::new()));
+            for entry in refs.2.iter_mut() {
+                entry.to.retain(|id| !removed.contains(id));
+            }
+            refs.2
+                .retain(|e| !removed.contains(&e.from) && !e.to.is_empty());
+            if let (Some(_), Some(primary)) = (edit.set_xmp, self.primary) {
+                refs.2.push(IrefEntry {
+                    ref_type: *b"cdsc",
+                    from: new_id,
+                    to: vec![primary],
+                });
+            }
+            if refs.2.is_empty() && self.iref.is_none() {
+                iref = None;
+            }
+        }
+
+        // Assemble the new meta box
+        let mut out = Vec::with_capacity(self.bytes.len() + idat.len());
+        out.extend_from_slice(&[0, 0, 0, 0]);
+        out.extend_from_slice(b"meta");
+        if self.header_len > 8 {
+            let vf_start = self.header_len - 4;
+            out.extend_from_slice(&self.bytes[vf_start..self.header_len]);
+        }
+
+        let mut wrote_iref = false;
+        let mut wrote_idat = false;
+        let mut idat_payload_offset = None;
+        for child in &self.children {
+            match &child.fourcc {
+                b"iinf" if items_changed => {
+                    let (version, flags, entries) = iinf.as_ref().expect("iinf parsed");
+                    write_iinf(&mut out, *version, *flags, entries)?;
+                }
+                b"iloc" => {
+                    write_iloc(&mut out, iloc.as_ref().expect("iloc parsed"))?;
+                }
+                b"iref" if items_changed => {
+                    wrote_iref = true;
+                    if let Some((version, flags, entries)) = &iref {
+                        write_iref(&mut out, *version, *flags, entries)?;
+                    }
+                }
+                b"idat" if idat_changed => {
+                    wrote_idat = true;
+                    idat_payload_offset = Some(out.len() as u64 + 8);
+                    write_box(&mut out, b"idat", &idat)?;
+                }
+                b"idat" => {
+                    wrote_idat = true;
+                    idat_payload_offset = Some(out.len() as u64 + 8);
+                    out.extend_from_slice(&self.bytes[child.start..child.end]);
+                }
+                _ => out.extend_from_slice(&self.bytes[child.start..child.end]),
+            }
+        }
+        if items_changed && !wrote_iref {
+            if let Some((version, flags, entries)) = &iref {
+                write_iref(&mut out, *version, *flags, entries)?;
+            }
+        }
+        if idat_changed && !wrote_idat {
+            idat_payload_offset = Some(out.len() as u64 + 8);
+            write_box(&mut out, b"idat", &idat)?;
+        }
+
+        let size = u32::try_from(out.len())
+            .map_err(|_| Error::CapacityExceeded("meta box too large".into()))?;
+        out[0..4].copy_from_slice(&size.to_be_bytes());
+
+        let xmp_offset = match (xmp_in_idat, idat_payload_offset) {
+            (Some(rel), Some(idat_start)) => Some(idat_start + rel),
+            _ => None,
+        };
+        Ok((out, xmp_offset))
+    }
+}
+
+fn shift_offset(offset: u64, shift: i64) -> Result<u64> {
+    offset
+        .checked_add_signed(shift)
+        .ok_or_else(|| Error::InvalidFormat("iloc offset out of range after shift".into()))
+}
+
+/// Rebuild `idat` with only the data referenced by the remaining entries
+///
+/// Returns `None` (leave `idat` unchanged) if any extent cannot be relocated.
+fn compact_idat(old: &[u8], items: &mut [ItemLocation]) -> Option<Vec<u8>> {
+    let mut new = Vec::with_capacity(old.len());
+    let mut relocated = Vec::new();
+    for loc in items.iter().filter(|l| l.construction_method == 1) {
+        let mut extents = Vec::with_capacity(loc.extents.len());
+        for e in &loc.extents {
+            let start = usize::try_from(loc.base_offset.checked_add(e.offset)?).ok()?;
+            let end = start.checked_add(usize::try_from(e.length).ok()?)?;
+            if e.length == 0 || end > old.len() {
+                return None;
+            }
+            extents.push(Extent {
+                index: e.index,
+                offset: new.len() as u64,
+                length: e.length,
+            });
+            new.extend_from_slice(&old[start..end]);
+        }
+        relocated.push(extents);
+    }
+    for (loc, extents) in items
+        .iter_mut()
+        .filter(|l| l.construction_method == 1)
+        .zip(relocated)
+    {
+        loc.base_offset = 0;
+        loc.extents = extents;
+    }
+    Some(new)
+}
+
+fn read_cstring<R: Read>(r: &mut R) -> Result<String> {
+    let mut bytes = Vec::new();
+    loop {
+        match r.read_u8() {
+            Ok(0) => break,
+            Ok(b) => bytes.push(b),
+            // Some writers omit the final terminator
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn read_sized<R: Read>(r: &mut R, size: u8) -> Result<u64> {
+    match size {
+        0 => Ok(0),
+        4 => Ok(r.read_u32::<BigEndian>()? as u64),
+        8 => Ok(r.read_u64::<BigEndian>()?),
+        _ => Err(Error::InvalidFormat(format!(
+            "Invalid iloc size specifier: {}",
+            size
+        ))),
+    }
+}
+
+fn write_sized(out: &mut Vec<u8>, size: u8, value: u64) -> Result<()> {
+    match size {
+        0 => {}
+        4 => out.write_u32::<BigEndian>(value as u32)?,
+        _ => out.write_u64::<BigEndian>(value)?,
+    }
+    Ok(())
+}
+
+/// Smallest iloc field width (0, 4 or 8) that holds `value`, but no smaller than `current`
+fn widen(current: u8, value: u64) -> u8 {
+    let needed = if value == 0 {
+        0
+    } else if value <= u32::MAX as u64 {
+        4
+    } else {
+        8
+    };
+    current.max(needed)
+}
+
+/// Split a run of boxes into (fourcc, payload) pairs
+fn sub_boxes(bytes: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= bytes.len() {
+        let size = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let fourcc: [u8; 4] = bytes[pos + 4..pos + 8].try_into().unwrap();
+        let end = match size {
+            0 => bytes.len(),
+            s if s < 8 || pos + s > bytes.len() => {
+                return Err(Error::InvalidFormat(format!(
+                    "Invalid '{}' box size {} in iprp",
+                    String::from_utf8_lossy(&fourcc),
+                    s
+                )))
+            }
+            s => pos + s,
+        };
+        boxes.push((fourcc, &bytes[pos + 8..end]));
+        pos = end;
+    }
+    Ok(boxes)
+}
+
+/// Parse `ipma` into (item id, 1-based property indices)
+fn parse_ipma(body: &[u8]) -> Result<Vec<(u32, Vec<u16>)>> {
+    let mut r = Cursor::new(body);
+    let version = r.read_u8()?;
+    let flags = r.read_u24::<BigEndian>()?;
+    let count = r.read_u32::<BigEndian>()?;
+
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let id = if version < 1 {
+            r.read_u16::<BigEndian>()? as u32
+        } else {
+            r.read_u32::<BigEndian>()?
+        };
+        let association_count = r.read_u8()?;
+        let mut indices = Vec::with_capacity(association_count as usize);
+        for _ in 0..association_count {
+            // The top bit is the "essential" flag
+            let index = if flags & 1 != 0 {
+                r.read_u16::<BigEndian>()? & 0x7fff
+            } else {
+                (r.read_u8()? & 0x7f) as u16
+            };
+            indices.push(index);
+        }
+        entries.push((id, indices));
+    }
+    Ok(entries)
+}
+
+fn parse_iinf(body: &[u8]) -> Result<(u8, u32, Vec<ItemInfo>)> {
+    let mut r = Cursor::new(body);
+    let version = r.read_u8()?;
+    let flags = r.read_u24::<BigEndian>()?;
+    let count = if version == 0 {
+        r.read_u16::<BigEndian>()? as u32
+    } else {
+        r.read_u32::<BigEndian>()?
+    };
+
+    let mut items = Vec::new();
+    for _ in 0..count {
+        let start = r.position() as usize;
+        if start + 8 > body.len() {
+            break;
+        }
+        let size = r.read_u32::<BigEndian>()? as usize;
+        let mut fourcc = [0u8; 4];
+        r.read_exact(&mut fourcc)?;
+        if size < 8 || start + size > body.len() {
+            return Err(Error::InvalidFormat("Invalid infe box size".into()));
+        }
+        let raw = &body[start..start + size];
+        if &fourcc == b"infe" {
+            items.push(parse_infe(raw)?);
+        }
+        r.set_position((start + size) as u64);
+    }
+
+    Ok((version, flags, items))
+}
+
+fn parse_infe(raw: &[u8]) -> Result<ItemInfo> {
+    let mut r = Cursor::new(&raw[8..]);
+    let version = r.read_u8()?;
+    r.read_u24::<BigEndian>()?; // flags
+
+    let (id, item_type, content_type) = if version >= 2 {
+        let id = if version == 2 {
+            r.read_u16::<BigEndian>()? as u32
+        } else {
+            r.read_u32::<BigEndian>()?
+        };
+        r.read_u16::<BigEndian>()?; // item_protection_index
+        let mut item_type = [0u8; 4];
+        r.read_exact(&mut item_type)?;
+        let _name = read_cstring(&mut r)?;
+        let content_type = if &item_type == b"mime" {
+            Some(read_cstring(&mut r)?)
+        } else {
+            None
+        };
+        (id, item_type, content_type)
+    } else {
+        // Versions 0 and 1 always describe MIME content
+        let id = r.read_u16::<BigEndian>()? as u32;
+        r.read_u16::<BigEndian>()?; // item_protection_index
+        let _name = read_cstring(&mut r)?;
+        (id, *b"mime", Some(read_cstring(&mut r)?))
+    };
+
+    Ok(ItemInfo {
+        id,
+        item_type,
+        content_type,
+        raw: raw.to_vec(),
+    })
+}
+
+fn parse_iloc(body: &[u8]) -> Result<Iloc> {
+    let mut r = Cursor::new(body);
+    let version = r.read_u8()?;
+    let flags = r.read_u24::<BigEndian>()?;
+    let sizes1 = r.read_u8()?;
+    let sizes2 = r.read_u8()?;
+    let offset_size = sizes1 >> 4;
+    let length_size = sizes1 & 0x0F;
+    let base_offset_size = sizes2 >> 4;
+    let index_size = if version >= 1 { sizes2 & 0x0F } else { 0 };
+
+    let count = if version < 2 {
+        r.read_u16::<BigEndian>()? as u32
+    } else {
+        r.read_u32::<BigEndian>()?
+    };
+
+    let mut items = Vec::with_capacity(count.min(4096) as usize);
+    for _ in 0..count {
+        let id = if version < 2 {
+            r.read_u16::<BigEndian>()? as u32
+        } else {
+            r.read_u32::<BigEndian>()?
+        };
+        let construction_method = if version >= 1 {
+            (r.read_u16::<BigEndian>()? & 0x0F) as u8
+        } else {
+            0
+        };
+        let data_reference_index = r.read_u16::<BigEndian>()?;
+        let base_offset = read_sized(&mut r, base_offset_size)?;
+        let extent_count = r.read_u16::<BigEndian>()?;
+        let mut extents = Vec::with_capacity(extent_count as usize);
+        for _ in 0..extent_count {
+            let index = if index_size > 0 {
+                read_sized(&mut r, index_size)?
+            } else {
+                0
+            };
+            let offset = read_sized(&mut r, offset_size)?;
+            let length = read_sized(&mut r, length_size)?;
+            extents.push(Extent {
+                index,
+                offset,
+                length,
+            });
+        }
+        items.push(ItemLocation {
+            id,
+            construction_method,
+            data_reference_index,
+            base_offset,
+            extents,
+        });
+    }
+
+    Ok(Iloc {
+        version,
+        flags,
+        offset_size,
+        length_size,
+        base_offset_size,
+        index_size,
+        items,
+    })
+}
+
+fn parse_iref(body: &[u8]) -> Result<(u8, u32, Vec<IrefEntry>)> {
+    let mut r = Cursor::new(body);
+    let version = r.read_u8()?;
+    let flags = r.read_u24::<BigEndian>()?;
+
+    let mut entries = Vec::new();
+    while (r.position() as usize) + 8 <= body.len() {
+        let start = r.position() as usize;
+        let size = r.read_u32::<BigEndian>()? as usize;
+        let mut ref_type = [0u8; 4];
+        r.read_exact(&mut ref_type)?;
+        if size < 8 || start + size > body.len() {
+            return Err(Error::InvalidFormat("Invalid iref entry size".into()));
+        }
+        let read_id = |r: &mut Cursor<&[u8]>| -> Result<u32> {
+            Ok(if version == 0 {
+                r.read_u16::<BigEndian>()? as u32
+            } else {
+                r.read_u32::<BigEndian>()?
+            })
+        };
+        let from = read_id(&mut r)?;
+        let count = r.read_u16::<BigEndian>()?;
+        let mut to = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            to.push(read_id(&mut r)?);
+        }
+        entries.push(IrefEntry { ref_type, from, to });
+        r.set_position((start + size) as u64);
+    }
+
+    Ok((version, flags, entries))
+}
+
+fn write_box(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) -> Result<()> {
+    let size = u32::try_from(payload.len() + 8)
+        .map_err(|_| Error::CapacityExceeded("box too large".into()))?;
+    out.write_u32::<BigEndian>(size)?;
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(payload);
+    Ok(())
+}
+
+fn build_xmp_infe(id: u32) -> Vec<u8> {
+    let mut body = Vec::new();
+    if id <= u16::MAX as u32 {
+        body.extend_from_slice(&[2, 0, 0, 0]);
+        body.extend_from_slice(&(id as u16).to_be_bytes());
+    } else {
+        body.extend_from_slice(&[3, 0, 0, 0]);
+        body.extend_from_slice(&id.to_be_bytes());
+    }
+    body.extend_from_slice(&0u16.to_be_bytes()); // item_protection_index
+    body.extend_from_slice(b"mime");
+    body.push(0); // empty item_name
+    body.extend_from_slice(XMP_CONTENT_TYPE.as_bytes());
+    body.push(0);
+
+    let mut out = Vec::with_capacity(body.len() + 8);
+    out.extend_from_slice(&((body.len() + 8) as u32).to_be_bytes());
+    out.extend_from_slice(b"infe");
+    out.extend_from_slice(&body);
+    out
+}
+
+fn write_iinf(out: &mut Vec<u8>, version: u8, flags: u32, entries: &[Vec<u8>]) -> Result<()> {
+    let version = if entries.len() > u16::MAX as usize {
+        version.max(1)
+    } else {
+        version
+    };
+    let mut body = Vec::new();
+    body.write_u8(version)?;
+    body.write_u24::<BigEndian>(flags)?;
+    if version == 0 {
+        body.write_u16::<BigEndian>(entries.len() as u16)?;
+    } else {
+        body.write_u32::<BigEndian>(entries.len() as u32)?;
+    }
+    for entry in entries {
+        body.extend_from_slice(entry);
+    }
+    write_box(out, b"iinf", &body)
+}
+
+fn write_iloc(out: &mut Vec<u8>, iloc: &Iloc) -> Result<()> {
+    let mut version = iloc.version;
+    if iloc.items.iter().any(|i| i.construction_method != 0) {
+        version = version.max(1);
+    }
+    if iloc.items.iter().any(|i| i.id > u16::MAX as u32) || iloc.items.len() > u16::MAX as usize {
+        version = 2;
+    }
+
+    let (mut offset_size, mut length_size, mut base_offset_size) =
+        (iloc.offset_size, iloc.length_size, iloc.base_offset_size);
+    for item in &iloc.items {
+        base_offset_size = widen(base_offset_size, item.base_offset);
+        for e in &item.extents {
+            offset_size = widen(offset_size, e.offset);
+            length_size = widen(length_size, e.length);
+        }
+    }
+    let index_size = if version >= 1 { iloc.index_size } else { 0 };
+
+    let mut body = Vec::new();
+    body.write_u8(version)?;
+    body.write_u24::<BigEndian>(iloc.flags)?;
+    body.write_u8((offset_size << 4) | length_size)?;
+    body.write_u8((base_offset_size << 4) | index_size)?;
+    if version < 2 {
+        body.write_u16::<BigEndian>(iloc.items.len() as u16)?;
+    } else {
+        body.write_u32::<BigEndian>(iloc.items.len() as u32)?;
+    }
+    for item in &iloc.items {
+        if version < 2 {
+            body.write_u16::<BigEndian>(item.id as u16)?;
+        } else {
+            body.write_u32::<BigEndian>(item.id)?;
+        }
+        if version >= 1 {
+            body.write_u16::<BigEndian>(item.construction_method as u16)?;
+        }
+        body.write_u16::<BigEndian>(item.data_reference_index)?;
+        write_sized(&mut body, base_offset_size, item.base_offset)?;
+        body.write_u16::<BigEndian>(item.extents.len() as u16)?;
+        for e in &item.extents {
+            if index_size > 0 {
+                write_sized(&mut body, index_size, e.index)?;
+            }
+            write_sized(&mut body, offset_size, e.offset)?;
+            write_sized(&mut body, length_size, e.length)?;
+        }
+    }
+    write_box(out, b"iloc", &body)
+}
+
+fn write_iref(out: &mut Vec<u8>, version: u8, flags: u32, entries: &[IrefEntry]) -> Result<()> {
+    let wide = entries
+        .iter()
+        .any(|e| e.from > u16::MAX as u32 || e.to.iter().any(|&t| t > u16::MAX as u32));
+    let version = if wide { 1 } else { version };
+
+    let mut body = Vec::new();
+    body.write_u8(version)?;
+    body.write_u24::<BigEndian>(flags)?;
+    for entry in entries {
+        let id_size = if version == 0 { 2 } else { 4 };
+        let size = 8 + id_size + 2 + id_size * entry.to.len();
+        body.write_u32::<BigEndian>(size as u32)?;
+        body.extend_from_slice(&entry.ref_type);
+        let write_id = |body: &mut Vec<u8>, id: u32| -> Result<()> {
+            if version == 0 {
+                body.write_u16::<BigEndian>(id as u16)?;
+            } else {
+                body.write_u32::<BigEndian>(id)?;
+            }
+            Ok(())
+        };
+        write_id(&mut body, entry.from)?;
+        body.write_u16::<BigEndian>(entry.to.len() as u16)?;
+        for &to in &entry.to {
+            write_id(&mut body, to)?;
+        }
+    }
+    write_box(out, b"iref", &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a minimal HEIF meta box: hdlr, pitm (item 1), iinf (hvc1 item 1),
+    /// iloc (version 0, item 1 at file offset `image_offset`)
+    fn make_meta(image_offset: u32, image_len: u32) -> Vec<u8> {
+        make_meta_with(image_offset, image_len, &[])
+    }
+
+    /// [`make_meta`] with extra child boxes appended
+    fn make_meta_with(image_offset: u32, image_len: u32, extra: &[u8]) -> Vec<u8> {
+        make_meta_for(1, &[(image_offset, image_len)], extra)
+    }
+
+    /// A meta box whose image item has the given ID and file extents
+    fn make_meta_for(item_id: u16, extents: &[(u32, u32)], extra: &[u8]) -> Vec<u8> {
+        let id = item_id.to_be_bytes();
+        let mut children = Vec::new();
+
+        let mut hdlr = vec![0, 0, 0, 0, 0, 0, 0, 0];
+        hdlr.extend_from_slice(b"pict");
+        hdlr.extend_from_slice(&[0u8; 13]);
+        write_box(&mut children, b"hdlr", &hdlr).unwrap();
+
+        write_box(&mut children, b"pitm", &[0, 0, 0, 0, id[0], id[1]]).unwrap();
+
+        let mut infe = vec![2, 0, 0, 0, id[0], id[1], 0, 0];
+        infe.extend_from_slice(b"hvc1");
+        infe.push(0);
+        let mut iinf = vec![0, 0, 0, 0, 0, 1];
+        write_box(&mut iinf, b"infe", &infe).unwrap();
+        write_box(&mut children, b"iinf", &iinf).unwrap();
+
+        let mut iloc = vec![0, 0, 0, 0, 0x44, 0x00, 0, 1, id[0], id[1], 0, 0];
+        iloc.extend_from_slice(&(extents.len() as u16).to_be_bytes());
+        for (offset, len) in extents {
+            iloc.extend_from_slice(&offset.to_be_bytes());
+            iloc.extend_from_slice(&len.to_be_bytes());
+        }
+        write_box(&mut children, b"iloc", &iloc).unwrap();
+        children.extend_from_slice(extra);
+
+        let mut body = vec![0, 0, 0, 0];
+        body.extend_from_slice(&children);
+        let mut meta = Vec::new();
+        write_box(&mut meta, b"meta", &body).unwrap();
+        meta
+    }
+
+    #[test]
+    fn test_add_xmp_item_and_read_back() {
+        let meta = HeifMeta::parse(make_meta(1000, 50)).unwrap();
+        assert!(meta.has_items());
+        assert_eq!(meta.items_with_content_type(XMP_CONTENT_TYPE).count(), 0);
+
+        let xmp = b"<x:xmpmeta/>";
+        let edit = MetaEdit {
+            set_xmp: Some(xmp),
+            ..Default::default()
+        };
+        let (bytes, xmp_offset) = meta.rewrite(&edit, 100, 0).unwrap();
+        let growth = bytes.len() as u64 - meta.len();
+
+        let rewritten = HeifMeta::parse(bytes.clone()).unwrap();
+        let ids: Vec<u32> = rewritten
+            .items_with_content_type(XMP_CONTENT_TYPE)
+            .collect();
+        assert_eq!(ids, vec![2]);
+
+        let ranges = rewritten.item_ranges(2, 100).unwrap();
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].offset, 100 + xmp_offset.unwrap());
+        let start = xmp_offset.unwrap() as usize;
+        assert_eq!(&bytes[start..start + xmp.len()], xmp);
+
+        // The image item after the meta box moved by the growth of meta
+        let image = rewritten.item_ranges(1, 100).unwrap();
+        assert_eq!(image[0].offset, 1000 + growth);
+
+        // The new item is linked to the primary item
+        let iref = rewritten.iref.as_ref().unwrap();
+        assert_eq!(iref.2[0].ref_type, *b"cdsc");
+        assert_eq!((iref.2[0].from, iref.2[0].to.clone()), (2, vec![1]));
+    }
+
+    #[test]
+    fn test_replace_and_remove_xmp_item() {
+        let meta = HeifMeta::parse(make_meta(1000, 50)).unwrap();
+        let original_len = meta.len();
+        let first = MetaEdit {
+            set_xmp: Some(b"first xmp packet"),
+            ..Default::default()
+        };
+        let (bytes, _) = meta.rewrite(&first, 0, 0).unwrap();
+        let with_xmp = HeifMeta::parse(bytes).unwrap();
+
+        let second = MetaEdit {
+            set_xmp: Some(b"second"),
+            ..Default::default()
+        };
+        let (bytes, _) = with_xmp.rewrite(&second, 0, 0).unwrap();
+        let replaced = HeifMeta::parse(bytes).unwrap();
+        assert_eq!(
+            replaced.items_with_content_type(XMP_CONTENT_TYPE).count(),
+            1
+        );
+        // Old XMP data was compacted out of idat
+        let (s, e) = replaced.idat.unwrap();
+        assert_eq!(e - s, b"second".len());
+
+        let remove = MetaEdit {
+            remove_xmp: true,
+            ..Default::default()
+        };
+        let (bytes, _) = replaced.rewrite(&remove, 0, 0).unwrap();
+        let removed = HeifMeta::parse(bytes).unwrap();
+        assert_eq!(removed.items_with_content_type(XMP_CONTENT_TYPE).count(), 0);
+        assert!(removed.iref.as_ref().unwrap().2.is_empty());
+        // Each rewrite moved the image data by the change in meta size
+        assert_eq!(
+            removed.item_ranges(1, 0).unwrap()[0].offset,
+            1000 + removed.len() - original_len
+        );
+    }
+
+    #[test]
+    fn test_shift_only_preserves_other_boxes() {
+        let original = make_meta(1000, 50);
+        let meta = HeifMeta::parse(original.clone()).unwrap();
+
+        let (same, _) = meta.rewrite(&MetaEdit::default(), 24, 0).unwrap();
+        assert_eq!(same, original);
+
+        let (shifted, _) = meta.rewrite(&MetaEdit::default(), 24, 40).unwrap();
+        assert_eq!(shifted.len(), original.len());
+        let shifted = HeifMeta::parse(shifted).unwrap();
+        assert_eq!(shifted.item_ranges(1, 64).unwrap()[0].offset, 1040);
+    }
+
+    #[test]
+    fn test_shift_extents_on_both_sides_of_meta() {
+        // One extent before the meta box at 100, one after it
+        let meta = HeifMeta::parse(make_meta_for(1, &[(20, 30), (1000, 50)], &[])).unwrap();
+        let edit = MetaEdit {
+            set_xmp: Some(b"<x:xmpmeta/>"),
+            ..Default::default()
+        };
+        let (bytes, _) = meta.rewrite(&edit, 100, 8).unwrap();
+        let growth = bytes.len() as u64 - meta.len();
+
+        let rewritten = HeifMeta::parse(bytes).unwrap();
+        let image = rewritten.item_ranges(1, 108).unwrap();
+        assert_eq!(image[0], ByteRange::new(28, 30));
+        assert_eq!(image[1], ByteRange::new(1008 + growth, 50));
+    }
+
+    #[test]
+    fn test_new_item_id_beyond_u16() {
+        let meta = HeifMeta::parse(make_meta_for(u16::MAX, &[(1000, 50)], &[])).unwrap();
+        let edit = MetaEdit {
+            set_xmp: Some(b"<x:xmpmeta/>"),
+            ..Default::default()
+        };
+        let (bytes, _) = meta.rewrite(&edit, 0, 0).unwrap();
+        let rewritten = HeifMeta::parse(bytes).unwrap();
+        let ids: Vec<u32> = rewritten
+            .items_with_content_type(XMP_CONTENT_TYPE)
+            .collect();
+        assert_eq!(ids, vec![u16::MAX as u32 + 1]);
+        assert_eq!(rewritten.iloc.as_ref().unwrap().version, 2);
+        assert!(rewritten.item_ranges(u16::MAX as u32 + 1, 0).is_some());
+
+        // No ID is left after u32::MAX
+        let mut full = HeifMeta::parse(make_meta(1000, 50)).unwrap();
+        full.iloc.as_mut().unwrap().items[0].id = u32::MAX;
+        assert!(matches!(
+            full.rewrite(&edit, 0, 0),
+            Err(Error::CapacityExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_primary_image_properties() {
+        // ipco: a thumbnail-sized ispe (1), the primary ispe (2) and pixi (3)
+        let mut ipco = Vec::new();
+        let mut small = vec![0, 0, 0, 0];
+        small.extend_from_slice(&320u32.to_be_bytes());
+        small.extend_from_slice(&240u32.to_be_bytes());
+        write_box(&mut ipco, b"ispe", &small).unwrap();
+        let mut large = vec![0, 0, 0, 0];
+        large.extend_from_slice(&4032u32.to_be_bytes());
+        large.extend_from_slice(&3024u32.to_be_bytes());
+        write_box(&mut ipco, b"ispe", &large).unwrap();
+        write_box(&mut ipco, b"pixi", &[0, 0, 0, 0, 3, 10, 10, 10]).unwrap();
+
+        // ipma: item 1 -> properties 2 (essential) and 3
+        let ipma = [0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 2, 0x82, 3];
+        let mut iprp_body = Vec::new();
+        write_box(&mut iprp_body, b"ipco", &ipco).unwrap();
+        write_box(&mut iprp_body, b"ipma", &ipma).unwrap();
+        let mut iprp = Vec::new();
+        write_box(&mut iprp, b"iprp", &iprp_body).unwrap();
+
+        let meta = HeifMeta::parse(make_meta_with(1000, 50, &iprp)).unwrap();
+        let props = meta.primary_image_properties().unwrap();
+        assert_eq!(props.item_type, Some(*b"hvc1"));
+        assert_eq!((props.width, props.height), (Some(4032), Some(3024)));
+        assert_eq!(props.bits_per_channel, vec![10, 10, 10]);
+
+        // Without iprp only the item type is known
+        let bare = HeifMeta::parse(make_meta(1000, 50)).unwrap();
+        let props = bare.primary_image_properties().unwrap();
+        assert_eq!(props.width, None);
+        assert_eq!(props.item_type, Some(*b"hvc1"));
+    }
+}
@@ -3,9 +3,9 @@
 use super::{ContainerIO, ContainerKind};
 use crate::{
     error::{Error, Result},
-    segment::{ByteRange, Segment, SegmentKind},
+    segment::{ByteRange, Segment, SegmentKind, SegmentMetadata},
     structure::Structure,
-    Updates,
+    Candidate, Confidence, Limits, MetadataUpdate, Updates, ValidationReport,
 };
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{copy, Read, Seek, SeekFrom, Write};
@@ -35,6 +35,18 @@ fn marker_label(marker: u8) -> &'static str {
         0xDA => "SOS",
         0xDB => "DQT",
         0xC0 => "SOF0",
+        0xC1 => "SOF1",
+        0xC2 => "SOF2",
+        0xC3 => "SOF3",
+        0xC5 => "SOF5",
+        0xC6 => "SOF6",
+        0xC7 => "SOF7",
+        0xC9 => "SOF9",
+        0xCA => "SOF10",
+        0xCB => "SOF11",
+        0xCD => "SOF13",
+        0xCE => "SOF14",
+        0xCF => "SOF15",
         0xC4 => "DHT",
         0xDD => "DRI",
         0xFE => "COM",
@@ -59,12 +71,22 @@ fn marker_label(marker: u8) -> &'static str {
 }
 
 /// JPEG container I/O implementation
-pub struct JpegIO;
+pub struct JpegIO {
+    limits: Limits,
+}
 
 impl JpegIO {
     /// Create a new JPEG I/O implementation
     pub fn new() -> Self {
-        Self
+        Self {
+            limits: Limits::default(),
+        }
+    }
+
+    /// Replace the limits enforced while parsing and reading
+    pub(crate) fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
     }
 
     /// Formats this handler supports
@@ -100,6 +122,7 @@ impl JpegIO {
 
     /// Extract XMP data from JPEG file (handles extended XMP with multi-segment assembly)
     pub fn read_xmp_impl<R: Read + Seek>(
+        &self,
         structure: &crate::structure::Structure,
         source: &mut R,
     ) -> Result<Option<Vec<u8>>> {
@@ -116,7 +139,7 @@ impl JpegIO {
         if let Some(meta) = &segment.metadata {
             if let Some((guid, chunk_offsets, total_size)) = meta.as_jpeg_extended_xmp() {
                 // JPEG Extended XMP - reassemble from parts
-                return Self::reassemble_extended_xmp(
+                return self.reassemble_extended_xmp(
                     source,
                     &segment.ranges,
                     guid,
@@ -150,6 +173,7 @@ impl JpegIO {
 
     /// Reassemble JPEG Extended XMP from multiple parts using chunk offsets
     fn reassemble_extended_xmp<R: Read + Seek>(
+        &self,
         source: &mut R,
         ranges: &[ByteRange],
         _guid: &str,
@@ -157,14 +181,12 @@ impl JpegIO {
         total_size: u32,
     ) -> Result<Option<Vec<u8>>> {
         // Validate total_size to prevent DOS attacks
-        const MAX_XMP_SIZE: u32 = 100 * 1024 * 1024; // 100 MB
-        if total_size > MAX_XMP_SIZE {
-            return Err(Error::InvalidSegment {
-                offset: 0,
+        if total_size as u64 > self.limits.max_xmp_size {
+            return Err(Error::LimitExceeded {
+                offset: ranges.first().map_or(0, |range| range.offset),
                 reason: format!(
-                    "Extended XMP too large: {} bytes (max {} MB)",
-                    total_size,
-                    MAX_XMP_SIZE / (1024 * 1024)
+                    "Extended XMP too large: {} bytes (max {} bytes)",
+                    total_size, self.limits.max_xmp_size
                 ),
             });
         }
@@ -202,44 +224,29 @@ impl JpegIO {
         Ok(Some(extended_xmp))
     }
 
-    /// Extract JUMBF data from JPEG file (handles JPEG XT headers and multi-segment assembly)
+    /// Extract the C2PA manifest store from JPEG file (handles JPEG XT headers and multi-segment assembly)
     pub fn read_jumbf_impl<R: Read + Seek>(
+        &self,
         structure: &crate::structure::Structure,
         source: &mut R,
     ) -> Result<Option<Vec<u8>>> {
-        if structure.jumbf_indices().is_empty() {
+        let Some(index) = structure.c2pa_jumbf_index() else {
             return Ok(None);
-        }
+        };
 
         let mut result = Vec::new();
 
-        // Extract all JUMBF segments and concatenate them
-        // Note: The parser already skips JPEG XT headers when creating ranges,
+        // The parser already skips JPEG XT headers when creating ranges,
         // so we just read the raw JUMBF data directly from the ranges
-        for &index in structure.jumbf_indices() {
-            let segment = &structure.segments()[index];
-            if !segment.is_jumbf() {
-                continue;
-            }
+        for range in &structure.segments()[index].ranges {
+            // Validate size to prevent memory exhaustion attacks
+            self.limits
+                .check_segment_size(range.offset, range.size, "JUMBF range")?;
 
-            for range in &segment.ranges {
-                // Validate size to prevent memory exhaustion attacks
-                if range.size > crate::segment::MAX_SEGMENT_SIZE {
-                    return Err(crate::Error::InvalidSegment {
-                        offset: range.offset,
-                        reason: format!(
-                            "JUMBF range too large: {} bytes (max {} MB)",
-                            range.size,
-                            crate::segment::MAX_SEGMENT_SIZE / (1024 * 1024)
-                        ),
-                    });
-                }
-
-                source.seek(SeekFrom::Start(range.offset))?;
-                let mut buf = vec![0u8; range.size as usize];
-                source.read_exact(&mut buf)?;
-                result.extend_from_slice(&buf);
-            }
+            source.seek(SeekFrom::Start(range.offset))?;
+            let mut buf = vec![0u8; range.size as usize];
+            source.read_exact(&mut buf)?;
+            result.extend_from_slice(&buf);
         }
 
         Ok(if result.is_empty() {
@@ -251,12 +258,25 @@ impl JpegIO {
 
     /// Fast single-pass parser
     fn parse_impl<R: Read + Seek>(&self, source: &mut R) -> Result<Structure> {
+        let mut at = (0, SOI);
+        self.parse_markers(source, &mut at)
+            .map_err(|e| e.at(at.0, marker_label(at.1)))
+    }
+
+    /// Parse the marker segments, keeping `at` on the offset and marker being parsed
+    fn parse_markers<R: Read + Seek>(
+        &self,
+        source: &mut R,
+        at: &mut (u64, u8),
+    ) -> Result<Structure> {
         let mut structure = Structure::new(ContainerKind::Jpeg, crate::MediaType::Jpeg);
 
         // Check SOI marker
         if source.read_u8()? != 0xFF || source.read_u8()? != SOI {
             return Err(Error::InvalidFormat("Not a JPEG file".into()));
         }
+        let file_len = source.seek(SeekFrom::End(0))?;
+        source.seek(SeekFrom::Start(2))?;
 
         structure.add_segment(Segment::new(
             0,
@@ -267,10 +287,31 @@ impl JpegIO {
 
         let mut offset = 2u64;
 
+        // Segment index of each JUMBF box by JPEG XT box instance number (En),
+        // so interleaved continuation packets join the right box
+        let mut jumbf_instances: Vec<(u16, usize)> = Vec::new();
+
         loop {
+            self.limits.check_segment_count(&structure)?;
+
+            // A file that ends before EOI keeps the segments found so far
+            if offset + 2 > file_len {
+                self.limits.recover(
+                    &mut structure,
+                    marker_label(EOI),
+                    Error::Truncated {
+                        offset,
+                        reason: "File ends before the EOI marker".into(),
+                    },
+                )?;
+                structure.total_size = offset;
+                break;
+            }
+
             // Read marker
             let marker_prefix = source.read_u8()?;
             let marker = source.read_u8()?;
+            *at = (offset, marker);
 
             if marker_prefix != 0xFF {
                 return Err(Error::InvalidSegment {
@@ -284,6 +325,33 @@ impl JpegIO {
                 continue;
             }
 
+            // A segment that runs past the end of the file is dropped, with
+            // everything after it
+            if !matches!(marker, EOI | RST0..=RST7) {
+                let end = if offset + 4 <= file_len {
+                    let size = source.read_u16::<BigEndian>()? as u64;
+                    source.seek(SeekFrom::Current(-2))?;
+                    offset + 2 + size
+                } else {
+                    offset + 4
+                };
+                if end > file_len {
+                    self.limits.recover(
+                        &mut structure,
+                        marker_label(marker),
+                        Error::Truncated {
+                            offset,
+                            reason: format!(
+                                "{} segment runs past the end of the file",
+                                marker_label(marker)
+                            ),
+                        },
+                    )?;
+                    structure.total_size = offset;
+                    break;
+                }
+            }
+
             match marker {
                 EOI => {
                     structure.add_segment(Segment::new(
@@ -305,8 +373,26 @@ impl JpegIO {
                     let skip = size.saturating_sub(2);
                     source.seek(SeekFrom::Current(skip as i64))?;
 
-                    // Find end of image data (scan for FFD9)
-                    let image_end = find_eoi(source)?;
+                    // Find end of image data (scan for FFD9). Without one the
+                    // image data runs to the end of the file.
+                    let Some(image_end) = find_eoi(source)? else {
+                        self.limits.recover(
+                            &mut structure,
+                            marker_label(EOI),
+                            Error::Truncated {
+                                offset: file_len,
+                                reason: "EOI marker not found".into(),
+                            },
+                        )?;
+                        structure.add_segment(Segment::new(
+                            sos_start,
+                            file_len - sos_start,
+                            SegmentKind::ImageData,
+                            Some(marker_label(SOS).to_string()),
+                        ));
+                        structure.total_size = file_len;
+                        break;
+                    };
 
                     // ImageData includes SOS marker + header + compressed data
                     // This makes writing easier - just copy the whole thing
@@ -425,9 +511,8 @@ impl JpegIO {
                                                     total_size,
                                                 });
                                         }
-                                        #[cfg(feature = "exif")]
-                                        Some(crate::SegmentMetadata::Thumbnail(_)) => {
-                                            // XMP segment shouldn't have thumbnail metadata, but handle gracefully
+                                        Some(_) => {
+                                            // XMP segment shouldn't have other metadata, but handle gracefully
                                         }
                                     }
                                 }
@@ -446,7 +531,6 @@ impl JpegIO {
                             source.seek(SeekFrom::Current(remaining as i64))?;
                         }
                     } else {
-                        // Check for EXIF segment
                         // Check for EXIF segment
                         if sig_buf.len() >= EXIF_SIGNATURE.len()
                             && &sig_buf[..EXIF_SIGNATURE.len()] == EXIF_SIGNATURE
@@ -526,21 +610,6 @@ impl JpegIO {
                                 Some(marker_label(APP1).to_string()),
                             ));
                         }
-
-                        // If not XMP or EXIF, treat as Other APP1 segment
-                        if sig_buf.len() < EXIF_SIGNATURE.len()
-                            || &sig_buf[..EXIF_SIGNATURE.len()] != EXIF_SIGNATURE
-                        {
-                            // Other APP1 segment (probably EXIF)
-                            let remaining = (data_size as usize) - sig_buf.len();
-                            source.seek(SeekFrom::Current(remaining as i64))?;
-                            structure.add_segment(Segment::new(
-                                segment_start,
-                                size + 2,
-                                SegmentKind::Other,
-                                Some(marker_label(APP1).to_string()),
-                            ));
-                        }
                     }
 
                     offset += 2 + size;
@@ -597,36 +666,42 @@ impl JpegIO {
                             (data_start, data_size)
                         };
 
-                        // Extract sequence number (only for JPEG XT format)
-                        let seq_num = if is_jpeg_xt && bytes_to_read >= 8 {
-                            u32::from_be_bytes([header[4], header[5], header[6], header[7]])
+                        // Extract box instance and sequence numbers (only for JPEG XT format)
+                        let (instance, seq_num) = if is_jpeg_xt && bytes_to_read >= 8 {
+                            (
+                                u16::from_be_bytes([header[2], header[3]]),
+                                u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+                            )
                         } else {
-                            1 // Raw JUMBF always treated as first/only segment
+                            (0, 1) // Raw JUMBF always treated as first/only segment
                         };
 
-                        // Check if this is a continuation of the previous JUMBF segment
-                        let mut is_continuation = false;
-                        if seq_num > 1 {
-                            if let Some(last_segment) = structure.segments.last_mut() {
-                                if last_segment.is_jumbf() {
-                                    // Add this range to the existing JUMBF
-                                    last_segment
-                                        .ranges
-                                        .push(ByteRange::new(jumbf_data_offset, jumbf_data_size));
-                                    is_continuation = true;
+                        // Check if this is a continuation of an earlier packet of the same box
+                        let previous = jumbf_instances
+                            .iter()
+                            .rev()
+                            .find(|(en, _)| *en == instance)
+                            .map(|&(_, index)| index);
+                        match previous {
+                            Some(index) if seq_num > 1 => {
+                                // Add this range to the existing JUMBF
+                                structure.segments[index]
+                                    .ranges
+                                    .push(ByteRange::new(jumbf_data_offset, jumbf_data_size));
+                            }
+                            _ => {
+                                // New JUMBF segment
+                                if is_jpeg_xt {
+                                    jumbf_instances.push((instance, structure.segments.len()));
                                 }
+                                structure.add_segment(Segment::with_ranges(
+                                    vec![ByteRange::new(jumbf_data_offset, jumbf_data_size)],
+                                    SegmentKind::Jumbf,
+                                    Some(marker_label(APP11).to_string()),
+                                )?);
                             }
                         }
 
-                        if !is_continuation {
-                            // New JUMBF segment
-                            structure.add_segment(Segment::with_ranges(
-                                vec![ByteRange::new(jumbf_data_offset, jumbf_data_size)],
-                                SegmentKind::Jumbf,
-                                Some(marker_label(APP11).to_string()),
-                            )?);
-                        }
-
                         // Skip remaining JUMBF data
                         let remaining = data_size - bytes_to_read as u64;
                         source.seek(SeekFrom::Current(remaining as i64))?;
@@ -675,6 +750,180 @@ impl JpegIO {
     }
 }
 
+impl JpegIO {
+    /// Check the marker sequence, duplicate XMP and extended XMP consistency
+    fn validate_impl<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        report: &mut ValidationReport,
+    ) -> Result<()> {
+        let segments = structure.segments();
+        let path_starts_with = |segment: &Segment, prefix: &str| {
+            segment
+                .path
+                .as_deref()
+                .is_some_and(|path| path.starts_with(prefix))
+        };
+
+        // Exactly one frame header, before the scan, with application data ahead of it
+        let mut frames = segments.iter().filter(|s| path_starts_with(s, "SOF"));
+        let frame = frames.next();
+        for extra in frames {
+            report.error(
+                extra.location().offset,
+                extra.path.clone().unwrap_or_default(),
+                "Multiple frame headers",
+            );
+        }
+        let scan = segments.iter().find(|s| s.is_type(SegmentKind::ImageData));
+        match (frame, scan) {
+            (None, Some(scan)) => report.error(
+                scan.location().offset,
+                marker_label(SOS),
+                "Scan without a frame header (SOFn)",
+            ),
+            (None, None) => report.error(2, "SOF", "No frame header (SOFn)"),
+            _ => {}
+        }
+        if let Some(frame) = frame {
+            let after_frame = segments
+                .iter()
+                .skip_while(|s| s.location().offset <= frame.location().offset);
+            for segment in after_frame.filter(|s| path_starts_with(s, "APP")) {
+                report.warning(
+                    segment.location().offset,
+                    segment.path.clone().unwrap_or_default(),
+                    "Application segment after the frame header",
+                );
+            }
+        }
+
+        // Data after EOI
+        let file_len = source.seek(SeekFrom::End(0))?;
+        if segments.last().is_some_and(|s| path_starts_with(s, "EOI"))
+            && file_len > structure.total_size
+        {
+            report.warning(
+                structure.total_size,
+                marker_label(EOI),
+                format!("{} bytes after EOI", file_len - structure.total_size),
+            );
+        }
+
+        let mut xmp = segments.iter().filter(|s| s.is_xmp());
+        let main = xmp.next();
+        for duplicate in xmp {
+            report.error(
+                duplicate.location().offset,
+                marker_label(APP1),
+                "Duplicate XMP packet",
+            );
+        }
+        if let Some(main) = main {
+            self.validate_extended_xmp(main, source, report)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the main XMP points to the extended XMP by GUID, and that the
+    /// GUID is the MD5 of the extended XMP
+    fn validate_extended_xmp<R: Read + Seek>(
+        &self,
+        main: &Segment,
+        source: &mut R,
+        report: &mut ValidationReport,
+    ) -> Result<()> {
+        let range = main.ranges[0];
+        let path = marker_label(APP1);
+        if range.size > self.limits.max_xmp_size {
+            return Ok(());
+        }
+        source.seek(SeekFrom::Start(range.offset))?;
+        let mut main_xmp = vec![0u8; range.size as usize];
+        source.read_exact(&mut main_xmp)?;
+        let declared = extended_xmp_guid(&main_xmp);
+
+        let Some((guid, chunk_offsets, total_size)) = main
+            .metadata
+            .as_ref()
+            .and_then(|m| m.as_jpeg_extended_xmp())
+        else {
+            if let Some(declared) = declared {
+                report.error(
+                    range.offset,
+                    path,
+                    format!(
+                        "HasExtendedXMP is {} but there is no extended XMP",
+                        declared
+                    ),
+                );
+            }
+            return Ok(());
+        };
+        let extended = &main.ranges[1..];
+        let offset = extended[0].offset;
+
+        match declared {
+            None => report.error(
+                range.offset,
+                path,
+                "Extended XMP without xmpNote:HasExtendedXMP in the main XMP",
+            ),
+            Some(declared) if !declared.eq_ignore_ascii_case(guid) => report.error(
+                range.offset,
+                path,
+                format!(
+                    "HasExtendedXMP is {} but the extended XMP GUID is {}",
+                    declared, guid
+                ),
+            ),
+            _ => {}
+        }
+
+        if chunk_offsets.len() != extended.len() {
+            report.error(
+                offset,
+                path,
+                "Extended XMP parts disagree on GUID or total size",
+            );
+            return Ok(());
+        }
+        let covered: u64 = extended.iter().map(|r| r.size).sum();
+        if covered != total_size as u64 {
+            report.error(
+                offset,
+                path,
+                format!(
+                    "Extended XMP parts hold {} of {} bytes",
+                    covered, total_size
+                ),
+            );
+            return Ok(());
+        }
+        if total_size as u64 > self.limits.max_xmp_size {
+            return Ok(());
+        }
+
+        if let Some(data) =
+            self.reassemble_extended_xmp(source, &main.ranges, guid, chunk_offsets, total_size)?
+        {
+            let digest = format!("{:032X}", md5::compute(&data));
+            if !digest.eq_ignore_ascii_case(guid) {
+                report.error(
+                    offset,
+                    path,
+                    format!(
+                        "Extended XMP GUID {} is not its MD5 digest ({})",
+                        guid, digest
+                    ),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
 impl Default for JpegIO {
     fn default() -> Self {
         Self::new()
@@ -706,6 +955,21 @@ impl ContainerIO for JpegIO {
         }
     }
 
+    fn sniff(header: &[u8]) -> Vec<Candidate> {
+        // SOI followed by the first marker; SOI alone is a weaker signature
+        match header {
+            [0xFF, 0xD8, 0xFF, ..] => {
+                vec![Candidate::new(crate::MediaType::Jpeg, Confidence::High)]
+            }
+            [0xFF, 0xD8, ..] => vec![Candidate::new(crate::MediaType::Jpeg, Confidence::Medium)],
+            _ => Vec::new(),
+        }
+    }
+
+    fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
     fn parse<R: Read + Seek>(&self, source: &mut R) -> Result<Structure> {
         self.parse_impl(source)
     }
@@ -715,7 +979,7 @@ impl ContainerIO for JpegIO {
         structure: &Structure,
         source: &mut R,
     ) -> Result<Option<Vec<u8>>> {
-        Self::read_xmp_impl(structure, source)
+        self.read_xmp_impl(structure, source)
     }
 
     fn read_jumbf<R: Read + Seek>(
@@ -723,10 +987,10 @@ impl ContainerIO for JpegIO {
         structure: &Structure,
         source: &mut R,
     ) -> Result<Option<Vec<u8>>> {
-        Self::read_jumbf_impl(structure, source)
+        self.read_jumbf_impl(structure, source)
     }
 
-    fn write<R: Read + Seek, W: Write>(
+    fn write_stream<R: Read + Seek, W: Write>(
         &self,
         structure: &Structure,
         source: &mut R,
@@ -735,6 +999,8 @@ impl ContainerIO for JpegIO {
     ) -> Result<()> {
         // Calculate the destination structure first - this tells us exactly what to write
         let dest_structure = self.calculate_updated_structure(structure, updates)?;
+        let jumbf_plan = jumbf_plan(structure, updates)?;
+        let mut planned_jumbf = jumbf_plan.iter().enumerate();
 
         source.seek(SeekFrom::Start(0))?;
 
@@ -743,7 +1009,7 @@ impl ContainerIO for JpegIO {
         writer.write_u8(SOI)?;
 
         // Iterate through destination structure and write each segment
-        for dest_segment in &dest_structure.segments {
+        for (index, dest_segment) in dest_structure.segments.iter().enumerate() {
             match dest_segment {
                 seg if seg.is_type(SegmentKind::Header) => {
                     // Already wrote SOI
@@ -849,38 +1115,10 @@ impl ContainerIO for JpegIO {
                 }
 
                 seg if seg.is_jumbf() => {
-                    // Write JUMBF based on updates
-                    match &updates.jumbf {
-                        crate::MetadataUpdate::Set(new_jumbf) => {
-                            write_jumbf_segments(writer, new_jumbf)?;
-                        }
-                        crate::MetadataUpdate::Keep => {
-                            // Read existing JUMBF data and re-write it
-                            // (This ensures consistent JPEG XT formatting)
-                            if let Some(source_seg) =
-                                structure.segments.iter().find(|s| s.is_jumbf())
-                            {
-                                // Read the JUMBF data (without JPEG XT headers)
-                                let total_size: u64 =
-                                    source_seg.ranges.iter().map(|r| r.size).sum();
-                                let mut jumbf_data = vec![0u8; total_size as usize];
-                                let mut offset = 0;
-
-                                for range in &source_seg.ranges {
-                                    source.seek(SeekFrom::Start(range.offset))?;
-                                    source.read_exact(
-                                        &mut jumbf_data[offset..offset + range.size as usize],
-                                    )?;
-                                    offset += range.size as usize;
-                                }
-
-                                // Re-write with proper JPEG XT formatting
-                                write_jumbf_segments(writer, &jumbf_data)?;
-                            }
-                        }
-                        crate::MetadataUpdate::Remove => {
-                            // Skip - segment not in destination
-                        }
+                    // Destination JUMBF segments follow the plan in order
+                    if let Some((n, jumbf)) = planned_jumbf.next() {
+                        let data = jumbf.load(self, structure, source)?;
+                        write_jumbf_segments(writer, &data, jumbf_instance(n))?;
                     }
                 }
 
@@ -907,11 +1145,8 @@ impl ContainerIO for JpegIO {
 
                 _ => {
                     // Copy other segments from source
-                    // Find corresponding source segment by kind and path
-                    if let Some(source_seg) = structure
-                        .segments
-                        .iter()
-                        .find(|s| s.kind == dest_segment.kind && s.path == dest_segment.path)
+                    if let Some(source_seg) =
+                        copied_source(structure, &dest_structure.segments[..index], dest_segment)
                     {
                         let location = source_seg.location();
                         source.seek(SeekFrom::Start(location.offset))?;
@@ -947,13 +1182,15 @@ impl ContainerIO for JpegIO {
 
         // Calculate the destination structure first
         let dest_structure = self.calculate_updated_structure(structure, updates)?;
+        let jumbf_plan = jumbf_plan(structure, updates)?;
+        let mut planned_jumbf = jumbf_plan.iter().enumerate();
 
         source.seek(SeekFrom::Start(0))?;
 
         // Iterate through destination structure and write each segment.
         // A boundary signal is emitted once at the top of each iteration so no
         // arm needs to handle boundary signalling directly.
-        for dest_segment in &dest_structure.segments {
+        for (index, dest_segment) in dest_structure.segments.iter().enumerate() {
             pw.begin_segment(dest_segment)?;
 
             match dest_segment {
@@ -1047,59 +1284,21 @@ impl ContainerIO for JpegIO {
                     // Handle JUMBF based on exclusion mode:
                     // - DataOnly: Include headers in hash, exclude only data
                     // - EntireSegment: Exclude entire segment including headers
-                    match &updates.jumbf {
-                        crate::MetadataUpdate::Set(new_jumbf) => {
-                            if data_only_mode {
-                                write_jumbf_with_exclusion(
-                                    &mut pw,
-                                    new_jumbf,
-                                    should_exclude_jumbf,
-                                )?;
-                            } else {
-                                if should_exclude_jumbf {
-                                    pw.set_exclude_mode(true);
-                                }
-                                write_jumbf_segments(&mut pw, new_jumbf)?;
-                                if should_exclude_jumbf {
-                                    pw.set_exclude_mode(false);
-                                }
+                    // Only the C2PA manifest store is excluded.
+                    if let Some((n, jumbf)) = planned_jumbf.next() {
+                        let data = jumbf.load(self, structure, source)?;
+                        let exclude = should_exclude_jumbf && jumbf.c2pa;
+                        if data_only_mode {
+                            write_jumbf_with_exclusion(&mut pw, &data, exclude, jumbf_instance(n))?;
+                        } else {
+                            if exclude {
+                                pw.set_exclude_mode(true);
                             }
-                        }
-                        crate::MetadataUpdate::Keep => {
-                            if let Some(source_seg) =
-                                structure.segments.iter().find(|s| s.is_jumbf())
-                            {
-                                let total_size: u64 =
-                                    source_seg.ranges.iter().map(|r| r.size).sum();
-                                let mut jumbf_data = vec![0u8; total_size as usize];
-                                let mut offset = 0;
-
-                                for range in &source_seg.ranges {
-                                    source.seek(SeekFrom::Start(range.offset))?;
-                                    source.read_exact(
-                                        &mut jumbf_data[offset..offset + range.size as usize],
-                                    )?;
-                                    offset += range.size as usize;
-                                }
-
-                                if data_only_mode {
-                                    write_jumbf_with_exclusion(
-                                        &mut pw,
-                                        &jumbf_data,
-                                        should_exclude_jumbf,
-                                    )?;
-                                } else {
-                                    if should_exclude_jumbf {
-                                        pw.set_exclude_mode(true);
-                                    }
-                                    write_jumbf_segments(&mut pw, &jumbf_data)?;
-                                    if should_exclude_jumbf {
-                                        pw.set_exclude_mode(false);
-                                    }
-                                }
+                            write_jumbf_segments(&mut pw, &data, jumbf_instance(n))?;
+                            if exclude {
+                                pw.set_exclude_mode(false);
                             }
                         }
-                        crate::MetadataUpdate::Remove => {}
                     }
                 }
 
@@ -1122,10 +1321,8 @@ impl ContainerIO for JpegIO {
                 }
 
                 _ => {
-                    if let Some(source_seg) = structure
-                        .segments
-                        .iter()
-                        .find(|s| s.kind == dest_segment.kind && s.path == dest_segment.path)
+                    if let Some(source_seg) =
+                        copied_source(structure, &dest_structure.segments[..index], dest_segment)
                     {
                         let location = source_seg.location();
                         source.seek(SeekFrom::Start(location.offset))?;
@@ -1147,8 +1344,6 @@ impl ContainerIO for JpegIO {
         // NOTE: This logic must stay in sync with write()
         // This calculates where segments will be WITHOUT actually writing
         // The segment iteration and decision logic mirrors write() exactly
-        use crate::MetadataUpdate;
-
         let mut dest_structure = Structure::new(ContainerKind::Jpeg, source_structure.media_type);
         let mut current_offset = 2u64; // Start after SOI marker
 
@@ -1162,6 +1357,7 @@ impl ContainerIO for JpegIO {
 
         let mut xmp_written = false;
         let mut jumbf_written = false;
+        let jumbf_plan = jumbf_plan(source_structure, updates)?;
 
         // Track if file has existing XMP/JUMBF
         let has_xmp = source_structure.segments.iter().any(|s| s.is_xmp());
@@ -1210,85 +1406,15 @@ impl ContainerIO for JpegIO {
                 }
 
                 segment if segment.is_jumbf() => {
-                    match &updates.jumbf {
-                        MetadataUpdate::Keep => {
-                            // Keep existing JUMBF - calculate all APP11 segments
-                            // IMPORTANT: Use total size from ALL ranges, not just first one!
-                            let jumbf_data_size: u64 = segment.ranges.iter().map(|r| r.size).sum();
-
-                            // Calculate how many APP11 segments needed
-                            let mut remaining = jumbf_data_size;
-                            let mut ranges = Vec::new();
-
-                            const JPEG_XT_FIRST_OVERHEAD: usize = 8;
-                            const JPEG_XT_CONT_OVERHEAD: usize = 16;
-                            const MAX_DATA: usize = MAX_MARKER_SIZE - JPEG_XT_CONT_OVERHEAD;
-
-                            let mut seg_num = 0;
-                            while remaining > 0 {
-                                let data_in_segment = remaining.min(MAX_DATA as u64);
-                                let overhead = if seg_num == 0 {
-                                    JPEG_XT_FIRST_OVERHEAD
-                                } else {
-                                    JPEG_XT_CONT_OVERHEAD
-                                };
-                                let segment_size = 2 + 2 + overhead as u64 + data_in_segment; // FF EB + length + overhead + data
-
-                                ranges.push(ByteRange::new(
-                                    current_offset + 4 + overhead as u64,
-                                    data_in_segment,
-                                ));
-                                current_offset += segment_size;
-                                remaining -= data_in_segment;
-                                seg_num += 1;
-                            }
-
-                            dest_structure.add_segment_with_ranges(
-                                SegmentKind::Jumbf,
-                                ranges,
-                                segment.path.clone(),
-                            )?;
-                            jumbf_written = true;
-                        }
-                        MetadataUpdate::Set(new_jumbf) if !jumbf_written => {
-                            // Write new JUMBF - calculate all APP11 segments
-                            let jumbf_size = new_jumbf.len() as u64;
-                            let mut remaining = jumbf_size;
-                            let mut ranges = Vec::new();
-
-                            const JPEG_XT_FIRST_OVERHEAD: usize = 8;
-                            const JPEG_XT_CONT_OVERHEAD: usize = 16;
-                            const MAX_DATA: usize = MAX_MARKER_SIZE - JPEG_XT_CONT_OVERHEAD;
-
-                            let mut seg_num = 0;
-                            while remaining > 0 {
-                                let data_in_segment = remaining.min(MAX_DATA as u64);
-                                let overhead = if seg_num == 0 {
-                                    JPEG_XT_FIRST_OVERHEAD
-                                } else {
-                                    JPEG_XT_CONT_OVERHEAD
-                                };
-                                let segment_size = 2 + 2 + overhead as u64 + data_in_segment;
-
-                                ranges.push(ByteRange::new(
-                                    current_offset + 4 + overhead as u64,
-                                    data_in_segment,
-                                ));
-                                current_offset += segment_size;
-                                remaining -= data_in_segment;
-                                seg_num += 1;
-                            }
-
-                            dest_structure.add_segment_with_ranges(
-                                SegmentKind::Jumbf,
-                                ranges,
-                                Some(marker_label(APP11).to_string()),
-                            )?;
-                            jumbf_written = true;
-                        }
-                        MetadataUpdate::Remove | MetadataUpdate::Set(_) => {
-                            // Skip this segment
-                        }
+                    // All JUMBF boxes are written together where the first one was
+                    if !jumbf_written {
+                        current_offset = add_jumbf_segments(
+                            &mut dest_structure,
+                            source_structure,
+                            &jumbf_plan,
+                            current_offset,
+                        )?;
+                        jumbf_written = true;
                     }
                 }
 
@@ -1352,64 +1478,13 @@ impl ContainerIO for JpegIO {
                         }
 
                         if !jumbf_written && !has_jumbf {
-                            if let MetadataUpdate::Set(new_jumbf) = &updates.jumbf {
-                                // If data is already in APP11 format, write directly;
-                                // otherwise wrap in JPEG XT format
-                                let is_already_app11 = new_jumbf.len() >= 2
-                                    && new_jumbf[0] == 0xFF
-                                    && new_jumbf[1] == APP11;
-
-                                if is_already_app11 {
-                                    // Data already has APP11 markers - treat as opaque blob
-                                    // The write function will handle it correctly
-                                    // For structure calculation, we just need ONE segment encompassing all the data
-                                    dest_structure.add_segment(Segment::new(
-                                        current_offset,
-                                        new_jumbf.len() as u64,
-                                        SegmentKind::Jumbf,
-                                        Some(marker_label(APP11).to_string()),
-                                    ));
-                                    current_offset += new_jumbf.len() as u64;
-                                } else {
-                                    // Raw JUMBF - need to wrap in JPEG XT
-                                    let jumbf_size = new_jumbf.len() as u64;
-                                    let mut remaining = jumbf_size;
-                                    let mut ranges = Vec::new();
-
-                                    const JPEG_XT_FIRST_OVERHEAD: usize = 8;
-                                    const JPEG_XT_CONT_OVERHEAD: usize = 16;
-                                    const MAX_DATA: usize = MAX_MARKER_SIZE
-                                        - JPEG_XT_FIRST_OVERHEAD
-                                        - JPEG_XT_CONT_OVERHEAD;
-
-                                    let mut seg_num = 0;
-                                    while remaining > 0 {
-                                        let data_in_segment = remaining.min(MAX_DATA as u64);
-                                        let overhead = if seg_num == 0 {
-                                            JPEG_XT_FIRST_OVERHEAD
-                                        } else {
-                                            JPEG_XT_CONT_OVERHEAD
-                                        };
-                                        let segment_size =
-                                            2 + 2 + overhead as u64 + data_in_segment;
-
-                                        ranges.push(ByteRange::new(
-                                            current_offset + 4 + overhead as u64,
-                                            data_in_segment,
-                                        ));
-                                        current_offset += segment_size;
-                                        remaining -= data_in_segment;
-                                        seg_num += 1;
-                                    }
-
-                                    dest_structure.add_segment_with_ranges(
-                                        SegmentKind::Jumbf,
-                                        ranges,
-                                        Some(marker_label(APP11).to_string()),
-                                    )?;
-                                }
-                                jumbf_written = true;
-                            }
+                            current_offset = add_jumbf_segments(
+                                &mut dest_structure,
+                                source_structure,
+                                &jumbf_plan,
+                                current_offset,
+                            )?;
+                            jumbf_written = true;
                         }
                     }
 
@@ -1426,6 +1501,21 @@ impl ContainerIO for JpegIO {
             }
         }
 
+        // Repair a source that ends without EOI
+        if !dest_structure
+            .segments
+            .iter()
+            .any(|s| s.path.as_deref() == Some("EOI"))
+        {
+            dest_structure.add_segment(Segment::new(
+                current_offset,
+                2,
+                SegmentKind::Other,
+                Some(marker_label(EOI).to_string()),
+            ));
+            current_offset += 2;
+        }
+
         dest_structure.total_size = current_offset;
         Ok(dest_structure)
     }
@@ -1486,6 +1576,47 @@ impl ContainerIO for JpegIO {
         crate::tiff::parse_exif_info(exif_data)
     }
 
+    fn read_media_info<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+    ) -> Result<crate::MediaInfo> {
+        let mut info = crate::MediaInfo::default();
+
+        // Frame header: FF Cn + length(2) + precision(1) + height(2) + width(2) + components(1)
+        let sof = structure
+            .segments()
+            .iter()
+            .find(|s| s.path.as_deref().is_some_and(|p| p.starts_with("SOF")));
+        let Some(sof) = sof else {
+            return Ok(info);
+        };
+        source.seek(SeekFrom::Start(sof.location().offset))?;
+        let mut header = [0u8; 10];
+        source.read_exact(&mut header)?;
+        if header[0] != 0xFF {
+            return Err(Error::InvalidFormat("Invalid JPEG frame header".into()));
+        }
+
+        info.bit_depth = Some(header[4] as u16);
+        info.height = Some(u16::from_be_bytes([header[5], header[6]]) as u32);
+        info.width = Some(u16::from_be_bytes([header[7], header[8]]) as u32);
+        info.components = Some(header[9]);
+        // SOF2/6/10/14 are the progressive processes
+        info.progressive = Some(matches!(header[1], 0xC2 | 0xC6 | 0xCA | 0xCE));
+        info.codec = Some("jpeg".to_string());
+        Ok(info)
+    }
+
+    fn validate<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        report: &mut ValidationReport,
+    ) -> Result<()> {
+        self.validate_impl(structure, source, report)
+    }
+
     fn exclusion_range_for_segment(structure: &Structure, kind: SegmentKind) -> Option<(u64, u64)> {
         let segment = match kind {
             SegmentKind::Jumbf => structure
@@ -1506,8 +1637,9 @@ impl ContainerIO for JpegIO {
 // Helper functions
 
 /// Find End of Image marker (FFD9)
-/// Properly handles byte stuffing in JPEG compressed data
-fn find_eoi<R: Read + Seek>(source: &mut R) -> Result<u64> {
+/// Properly handles byte stuffing in JPEG compressed data.
+/// Returns `None` if the data ends without one.
+fn find_eoi<R: Read + Seek>(source: &mut R) -> Result<Option<u64>> {
     const BUFFER_SIZE: usize = 8192;
     let mut buffer = vec![0u8; BUFFER_SIZE];
     let mut prev_was_ff = false;
@@ -1517,7 +1649,7 @@ fn find_eoi<R: Read + Seek>(source: &mut R) -> Result<u64> {
     loop {
         let n = source.read(&mut buffer)?;
         if n == 0 {
-            return Err(Error::InvalidFormat("EOI marker not found".into()));
+            return Ok(None);
         }
 
         for (i, &byte) in buffer[..n].iter().enumerate() {
@@ -1525,7 +1657,7 @@ fn find_eoi<R: Read + Seek>(source: &mut R) -> Result<u64> {
                 if byte == EOI {
                     // Found EOI! Return position of the FF
                     let eoi_pos = start_pos + total_read + i as u64 - 1;
-                    return Ok(eoi_pos);
+                    return Ok(Some(eoi_pos));
                 } else if byte == 0x00 {
                     // Stuffed byte - the FF was part of data, not a marker
                     prev_was_ff = false;
@@ -1545,6 +1677,38 @@ fn find_eoi<R: Read + Seek>(source: &mut R) -> Result<u64> {
     }
 }
 
+/// The GUID in the `xmpNote:HasExtendedXMP` property of a main XMP packet
+///
+/// Accepts both the attribute (`xmpNote:HasExtendedXMP="..."`) and the element
+/// (`<xmpNote:HasExtendedXMP>...</xmpNote:HasExtendedXMP>`) forms.
+fn extended_xmp_guid(xmp: &[u8]) -> Option<String> {
+    const PROPERTY: &[u8] = b"HasExtendedXMP";
+    let start = xmp.windows(PROPERTY.len()).position(|w| w == PROPERTY)? + PROPERTY.len();
+    let rest = &xmp[start..];
+    let value = rest
+        .iter()
+        .position(|&b| b == b'"' || b == b'\'' || b == b'>')?
+        + 1;
+    let guid = rest.get(value..value + 32)?;
+    guid.iter()
+        .all(u8::is_ascii_hexdigit)
+        .then(|| String::from_utf8_lossy(guid).into_owned())
+}
+
+/// The source segment a copied destination segment comes from
+///
+/// Matched by kind, path and occurrence, so that repeated markers (DQT, DHT, ...)
+/// are each copied from their own source segment.
+fn copied_source<'a>(
+    structure: &'a Structure,
+    preceding: &[Segment],
+    dest_segment: &Segment,
+) -> Option<&'a Segment> {
+    let same = |s: &&Segment| s.kind == dest_segment.kind && s.path == dest_segment.path;
+    let occurrence = preceding.iter().filter(same).count();
+    structure.segments.iter().filter(same).nth(occurrence)
+}
+
 /// Write XMP as APP1 segment(s), splitting if needed for large XMP
 fn write_xmp_segment<W: Write>(writer: &mut W, xmp: &[u8]) -> Result<()> {
     const MAIN_XMP_MAX: usize = MAX_MARKER_SIZE - XMP_SIGNATURE.len() - 2;
@@ -1627,8 +1791,181 @@ fn write_xmp_segment<W: Write>(writer: &mut W, xmp: &[u8]) -> Result<()> {
     Ok(())
 }
 
+/// JPEG XT box instance number (En) of the `n`th JUMBF box written
+fn jumbf_instance(n: usize) -> u16 {
+    0x0211u16.wrapping_add(n as u16)
+}
+
+/// Where the data of an output JUMBF box comes from
+enum JumbfData<'a> {
+    /// The JUMBF segment at this index in the source structure
+    Keep(usize),
+    /// New data from the updates
+    New(&'a [u8]),
+}
+
+/// A JUMBF box of the output, in write order
+struct PlannedJumbf<'a> {
+    data: JumbfData<'a>,
+    /// Whether this is the C2PA manifest store
+    c2pa: bool,
+}
+
+impl PlannedJumbf<'_> {
+    /// Read or borrow the box data
+    fn load<R: Read + Seek>(
+        &self,
+        handler: &JpegIO,
+        structure: &Structure,
+        source: &mut R,
+    ) -> Result<std::borrow::Cow<'_, [u8]>> {
+        Ok(match self.data {
+            JumbfData::Keep(index) => handler.read_jumbf_box(structure, source, index)?.into(),
+            JumbfData::New(data) => data.into(),
+        })
+    }
+}
+
+/// Decide which JUMBF boxes the output holds
+///
+/// Source boxes keep their order; the C2PA manifest store follows `updates.jumbf`
+/// and labeled boxes their label updates. New boxes are appended.
+fn jumbf_plan<'a>(structure: &Structure, updates: &'a Updates) -> Result<Vec<PlannedJumbf<'a>>> {
+    let c2pa_index = structure.c2pa_jumbf_index();
+    let mut plan = Vec::new();
+    let mut replaced = Vec::new();
+
+    for &index in structure.jumbf_indices() {
+        let c2pa = c2pa_index == Some(index);
+        let label = structure.segments[index]
+            .jumbf_description()
+            .and_then(|(label, _)| label);
+        let update = match label {
+            _ if c2pa => Some(&updates.jumbf),
+            Some(label) => updates.jumbf_box_update(label),
+            None => None,
+        };
+        let data = match update {
+            None | Some(MetadataUpdate::Keep) => JumbfData::Keep(index),
+            Some(MetadataUpdate::Remove) => continue,
+            // Boxes sharing a replaced label collapse into the new one
+            Some(MetadataUpdate::Set(_)) if !c2pa && replaced.contains(&label) => continue,
+            Some(MetadataUpdate::Set(data)) => {
+                replaced.push(label);
+                JumbfData::New(data)
+            }
+        };
+        plan.push(PlannedJumbf { data, c2pa });
+    }
+
+    if c2pa_index.is_none() {
+        if let MetadataUpdate::Set(data) = &updates.jumbf {
+            plan.push(PlannedJumbf {
+                data: JumbfData::New(data),
+                c2pa: true,
+            });
+        }
+    }
+    for (label, update) in &updates.jumbf_boxes {
+        let MetadataUpdate::Set(data) = update else {
+            continue;
+        };
+        if crate::jumbf::describe(data).and_then(|(l, _)| l).as_deref() != Some(label.as_str()) {
+            return Err(Error::InvalidFormat(format!(
+                "JUMBF box data must start with a description box labeled '{}'",
+                label
+            )));
+        }
+        if structure.jumbf_index_by_label(label).is_none() {
+            plan.push(PlannedJumbf {
+                data: JumbfData::New(data),
+                c2pa: false,
+            });
+        }
+    }
+    Ok(plan)
+}
+
+/// Add the planned JUMBF boxes to `dest` at `offset`, returning the offset after them
+fn add_jumbf_segments(
+    dest: &mut Structure,
+    source: &Structure,
+    plan: &[PlannedJumbf],
+    mut offset: u64,
+) -> Result<u64> {
+    for jumbf in plan {
+        let (head, size, metadata) = match jumbf.data {
+            JumbfData::Keep(index) => {
+                let segment = &source.segments[index];
+                let size = segment.ranges.iter().map(|r| r.size).sum();
+                (&[][..], size, segment.metadata.clone())
+            }
+            JumbfData::New(data) => (
+                data,
+                data.len() as u64,
+                crate::jumbf::describe(data).map(|(label, content_type)| {
+                    SegmentMetadata::JumbfDescription {
+                        label,
+                        content_type,
+                    }
+                }),
+            ),
+        };
+        let (ranges, end) = jumbf_layout(head, size, offset);
+        let mut segment = Segment::with_ranges(
+            ranges,
+            SegmentKind::Jumbf,
+            Some(marker_label(APP11).to_string()),
+        )?;
+        segment.metadata = metadata;
+        if jumbf.c2pa {
+            dest.set_c2pa_jumbf_index(dest.segments.len());
+        }
+        dest.add_segment(segment);
+        offset = end;
+    }
+    Ok(offset)
+}
+
+/// Ranges of JUMBF data written by [`write_jumbf_segments`] at `offset`, and the offset after them
+///
+/// `head` is the start of the data, used to recognise data that is already
+/// formatted as APP11 segments or JPEG XT packets.
+fn jumbf_layout(head: &[u8], size: u64, mut offset: u64) -> (Vec<ByteRange>, u64) {
+    if head.len() >= 2 && head[0] == 0xFF && head[1] == APP11 {
+        // Data already has APP11 markers - treat as opaque blob
+        return (vec![ByteRange::new(offset, size)], offset + size);
+    }
+
+    let (first_overhead, cont_overhead, max_data) = if head.len() >= 2 && &head[0..2] == b"JP" {
+        // JPEG XT packets only get marker + length
+        (0, 0, MAX_MARKER_SIZE as u64 - 2)
+    } else {
+        // JP + En + Z, plus LBox + TBox repeated in continuations
+        (8, 16, MAX_MARKER_SIZE as u64 - 16)
+    };
+
+    let mut ranges = Vec::new();
+    let mut remaining = size;
+    while remaining > 0 {
+        let data_in_segment = remaining.min(max_data);
+        let overhead = if ranges.is_empty() {
+            first_overhead
+        } else {
+            cont_overhead
+        };
+        ranges.push(ByteRange::new(offset + 4 + overhead, data_in_segment));
+        offset += 4 + overhead + data_in_segment; // FF EB + length + overhead + data
+        remaining -= data_in_segment;
+    }
+    (ranges, offset)
+}
+
 /// Write JUMBF data as one or more APP11 segments
-fn write_jumbf_segments<W: Write>(writer: &mut W, jumbf: &[u8]) -> Result<()> {
+///
+/// `instance` is the JPEG XT box instance number (En), which must differ
+/// between the JUMBF boxes of a file.
+fn write_jumbf_segments<W: Write>(writer: &mut W, jumbf: &[u8], instance: u16) -> Result<()> {
     // Check if the JUMBF data is already in APP11 segment format (complete with FF EB marker)
     // (This happens when the caller provides pre-formatted APP11 segments)
     let is_complete_app11 = jumbf.len() >= 2 && jumbf[0] == 0xFF && jumbf[1] == APP11;
@@ -1683,7 +2020,7 @@ fn write_jumbf_segments<W: Write>(writer: &mut W, jumbf: &[u8]) -> Result<()> {
 
         // JPEG XT header
         writer.write_all(b"JP")?; // CI: JPEG extensions marker
-        writer.write_u16::<BigEndian>(0x0211)?; // En: Box Instance Number
+        writer.write_u16::<BigEndian>(instance)?; // En: Box Instance Number
         writer.write_u32::<BigEndian>((seg_num + 1) as u32)?; // Z: Packet sequence
 
         // For continuation segments, repeat LBox and TBox
@@ -1713,6 +2050,7 @@ fn write_jumbf_with_exclusion<W: Write, F>(
     pw: &mut crate::processing_writer::ProcessingWriter<'_, W, F>,
     jumbf: &[u8],
     should_exclude: bool,
+    instance: u16,
 ) -> Result<()>
 where
     F: crate::ProcessChunkFn,
@@ -1773,7 +2111,7 @@ where
 
         // Write JPEG XT header (always included in hash)
         pw.write_all(b"JP")?;
-        pw.write_u16::<BigEndian>(0x0211)?;
+        pw.write_u16::<BigEndian>(instance)?;
         pw.write_u32::<BigEndian>((seg_num + 1) as u32)?;
 
         // For continuation segments, LBox+TBox header is also included in hash
@@ -1815,4 +2153,56 @@ mod tests {
         assert_eq!(structure.total_size, 4);
         assert_eq!(structure.segments.len(), 2); // Header + EOI
     }
+
+    #[test]
+    fn test_jpeg_parse_other_app1() {
+        // SOI, an APP1 that is neither EXIF nor XMP, EOI
+        let mut data = vec![0xFF, 0xD8, 0xFF, APP1, 0x00, 0x08];
+        data.extend_from_slice(b"Other\0");
+        data.extend_from_slice(&[0xFF, 0xD9]);
+
+        let structure = JpegIO::new().parse(&mut Cursor::new(data)).unwrap();
+        assert_eq!(structure.segments.len(), 3);
+        assert_eq!(structure.segments[1].location(), ByteRange::new(2, 10));
+        assert_eq!(structure.total_size, 14);
+    }
+
+    #[test]
+    fn test_extended_xmp_guid() {
+        let guid = "0123456789ABCDEF0123456789ABCDEF";
+        let attribute = format!(r#"<rdf:Description xmpNote:HasExtendedXMP="{}"/>"#, guid);
+        let element = format!("<xmpNote:HasExtendedXMP>{}</xmpNote:HasExtendedXMP>", guid);
+        assert_eq!(
+            extended_xmp_guid(attribute.as_bytes()).as_deref(),
+            Some(guid)
+        );
+        assert_eq!(extended_xmp_guid(element.as_bytes()).as_deref(), Some(guid));
+        assert_eq!(extended_xmp_guid(b"<x:xmpmeta/>"), None);
+        assert_eq!(
+            extended_xmp_guid(br#"xmpNote:HasExtendedXMP="not-a-guid""#),
+            None
+        );
+    }
+
+    #[test]
+    fn test_jpeg_write_keeps_repeated_markers() {
+        // SOI, two DHT segments of different sizes, SOS + scan data, EOI
+        let data = vec![
+            0xFF, 0xD8, 0xFF, 0xC4, 0x00, 0x05, 0x01, 0x02, 0x03, 0xFF, 0xC4, 0x00, 0x03, 0x09,
+            0xFF, 0xDA, 0x00, 0x02, 0x11, 0x22, 0xFF, 0xD9,
+        ];
+        let handler = JpegIO::new();
+        let structure = handler.parse(&mut Cursor::new(&data)).unwrap();
+
+        let mut output = Cursor::new(Vec::new());
+        handler
+            .write(
+                &structure,
+                &mut Cursor::new(&data),
+                &mut output,
+                &Updates::new(),
+            )
+            .unwrap();
+        assert_eq!(output.into_inner(), data);
+    }
 }
@@ -3,7 +3,9 @@
 //! Each container format (JPEG, PNG, BMFF, etc.) has an I/O implementation that knows how to
 //! parse and write that specific file structure.
 
-use crate::{error::Result, structure::Structure, MediaType, Updates};
+use crate::{
+    error::Result, structure::Structure, Candidate, Limits, MediaType, Updates, ValidationReport,
+};
 use std::io::{Read, Seek, Write};
 
 /// Container format - defines how a file is structured on disk
@@ -31,6 +33,9 @@ pub enum ContainerKind {
     /// RIFF container (Resource Interchange File Format: WebP, WAV, AVI)
     #[cfg(feature = "riff")]
     Riff,
+
+    /// Container added at runtime with [`register_container`](crate::register_container)
+    Custom(&'static CustomFormat),
 }
 
 /// Trait for container-specific I/O operations
@@ -65,6 +70,16 @@ pub trait ContainerIO: Send + Sync {
     where
         Self: Sized;
 
+    /// Media types the given header could hold, with how sure each guess is
+    ///
+    /// Returns an empty list if the header is not in this container.
+    fn sniff(header: &[u8]) -> Vec<Candidate>
+    where
+        Self: Sized;
+
+    /// Limits this I/O implementation enforces while parsing and reading
+    fn limits(&self) -> &Limits;
+
     /// Parse file structure in single pass
     ///
     /// This discovers all segments, XMP, and JUMBF locations without
@@ -77,14 +92,31 @@ pub trait ContainerIO: Send + Sync {
     /// This streams from the source to destination, applying updates
     /// without loading the entire file into memory.
     ///
-    /// For BMFF, the writer must support Read + Write + Seek (e.g. `File`) so that
-    /// chunk offset tables (stco/co64) can be patched after UUID region changes.
+    /// The default implementation calls `write_stream`. BMFF overrides it so that
+    /// chunk offset tables (stco/co64) in a moov box too large to rewrite in
+    /// memory can be patched in the output afterwards.
     fn write<R: Read + Seek, W: Read + Write + Seek>(
         &self,
         structure: &Structure,
         source: &mut R,
         writer: &mut W,
         updates: &Updates,
+    ) -> Result<()> {
+        self.write_stream(structure, source, writer, updates)
+    }
+
+    /// Write file with updates to a sequential destination
+    ///
+    /// Like `write`, but the destination is only written front to back, so it
+    /// can be a socket, an HTTP body or a compressor. Fails where a container
+    /// has to patch its output after writing (a BMFF moov box too large to
+    /// rewrite in memory).
+    fn write_stream<R: Read + Seek, W: Write>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        writer: &mut W,
+        updates: &Updates,
     ) -> Result<()>;
 
     /// Write file with processor callback for single-pass processing
@@ -202,7 +234,7 @@ pub trait ContainerIO: Send + Sync {
         source: &mut R,
     ) -> Result<Option<Vec<u8>>>;
 
-    /// Read JUMBF data from file (container-specific)
+    /// Read the C2PA manifest store JUMBF from file (container-specific)
     ///
     /// This handles container-specific details like JPEG XT headers,
     /// multi-segment assembly, etc.
@@ -212,6 +244,19 @@ pub trait ContainerIO: Send + Sync {
         source: &mut R,
     ) -> Result<Option<Vec<u8>>>;
 
+    /// Read the JUMBF box of the segment at `index`
+    ///
+    /// The default concatenates the segment's ranges, which hold only JUMBF data
+    /// in containers that strip their own headers at parse time.
+    fn read_jumbf_box<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        index: usize,
+    ) -> Result<Vec<u8>> {
+        read_segment_ranges(self.limits(), structure, source, index)
+    }
+
     /// Read embedded thumbnail location from container-specific metadata
     ///
     /// Some containers embed pre-rendered thumbnails in their metadata:
@@ -244,6 +289,31 @@ pub trait ContainerIO: Send + Sync {
         source: &mut R,
     ) -> Result<Option<crate::tiff::ExifInfo>>;
 
+    /// Read technical media properties (dimensions, duration, codecs, audio format)
+    ///
+    /// Each container reads its own headers:
+    /// - JPEG: SOFn frame header
+    /// - PNG: IHDR chunk
+    /// - BMFF: mvhd/tkhd/mdhd/stsd for movies, ispe/pixi for HEIF images
+    /// - RIFF: VP8X/VP8/VP8L (WebP), `fmt ` (WAV), avih (AVI)
+    fn read_media_info<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+    ) -> Result<crate::MediaInfo>;
+
+    /// Check the asset against its container specification
+    ///
+    /// Adds what is wrong to `report` without modifying anything. Errors in the
+    /// report are conformance failures; only failures to read the source are
+    /// returned as `Err`.
+    fn validate<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        report: &mut ValidationReport,
+    ) -> Result<()>;
+
     /// Calculate the C2PA exclusion range for a segment kind
     ///
     /// This encapsulates container-specific details about what bytes must be excluded
@@ -262,6 +332,27 @@ pub trait ContainerIO: Send + Sync {
         Self: Sized;
 }
 
+/// Concatenate the ranges of the JUMBF segment at `index`
+fn read_segment_ranges<R: Read + Seek>(
+    limits: &Limits,
+    structure: &Structure,
+    source: &mut R,
+    index: usize,
+) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for range in &structure.segments()[index].ranges {
+        // Validate size to prevent memory exhaustion attacks
+        limits.check_segment_size(range.offset, data.len() as u64 + range.size, "JUMBF box")?;
+        source.seek(std::io::SeekFrom::Start(range.offset))?;
+        source.by_ref().take(range.size).read_to_end(&mut data)?;
+    }
+    Ok(data)
+}
+
+pub(crate) mod custom;
+
+use custom::CustomFormat;
+
 // ContainerKind I/O modules - pub(crate) so register_containers! macro can access them
 #[cfg(feature = "jpeg")]
 pub(crate) mod jpeg_io;
@@ -272,9 +363,24 @@ pub(crate) mod png_io;
 #[cfg(feature = "bmff")]
 pub mod bmff_io;
 
+#[cfg(feature = "bmff")]
+pub(crate) mod bmff_tree;
+
+#[cfg(feature = "bmff")]
+pub(crate) mod heif_meta;
+
+#[cfg(feature = "bmff")]
+pub(crate) mod quicktime_meta;
+
+#[cfg(feature = "riff")]
+pub(crate) mod avi_index;
+
 #[cfg(feature = "riff")]
 pub(crate) mod riff_io;
 
+#[cfg(feature = "riff")]
+pub(crate) mod wav_meta;
+
 // ============================================================================
 // ContainerKind Registration Macro
 // ============================================================================
@@ -282,9 +388,10 @@ pub(crate) mod riff_io;
 /// Register all supported container formats in one place
 ///
 /// This macro generates:
-/// - Handler enum for internal use (zero-cost dispatch)
+/// - Handler enum for internal use (zero-cost dispatch), plus a `Custom`
+///   variant for handlers registered at runtime (see [`custom`])
 /// - Handler implementation with container delegation
-/// - detect_container() function
+/// - detect_container() and sniff_media_types() functions
 /// - get_handler() function  
 /// - Extension and MIME type lookup
 /// - ContainerKind methods for MIME types and extensions
@@ -301,20 +408,87 @@ macro_rules! register_containers {
                 $(#[$meta])*
                 $variant($module::$io),
             )*
+            Custom(custom::CustomHandler),
         }
 
         // Generate Handler implementation - delegates to specific I/O implementations
         impl Handler {
+            /// The container this handler reads and writes
             #[allow(unreachable_patterns)]
-            pub(crate) fn parse<R: std::io::Read + std::io::Seek>(&self, source: &mut R) -> $crate::Result<$crate::Structure> {
+            pub(crate) fn container(&self) -> ContainerKind {
                 match self {
                     $(
                         $(#[$meta])*
-                        Handler::$variant(h) => h.parse(source),
+                        Handler::$variant(_) => $module::$io::container_type(),
+                    )*
+                    Handler::Custom(h) => ContainerKind::Custom(h.io.format()),
+                }
+            }
+
+            /// Add the container, and the media type once parsed, to an error
+            fn in_context<T>(
+                &self,
+                media_type: Option<$crate::MediaType>,
+                result: $crate::Result<T>,
+            ) -> $crate::Result<T> {
+                result.map_err(|e| e.in_container(self.container(), media_type))
+            }
+
+            /// Replace the limits the handler enforces
+            #[allow(unreachable_patterns)]
+            pub(crate) fn with_limits(self, limits: $crate::Limits) -> Self {
+                match self {
+                    $(
+                        $(#[$meta])*
+                        Handler::$variant(h) => Handler::$variant(h.with_limits(limits)),
                     )*
+                    Handler::Custom(h) => Handler::Custom(h.with_limits(limits)),
                 }
             }
 
+            #[allow(unreachable_patterns)]
+            pub(crate) fn limits(&self) -> &$crate::Limits {
+                match self {
+                    $(
+                        $(#[$meta])*
+                        Handler::$variant(h) => h.limits(),
+                    )*
+                    Handler::Custom(h) => &h.limits,
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            pub(crate) fn parse<R: std::io::Read + std::io::Seek>(&self, source: &mut R) -> $crate::Result<$crate::Structure> {
+                let mut structure = self.in_context(None, match self {
+                    $(
+                        $(#[$meta])*
+                        Handler::$variant(h) => h.parse(source),
+                    )*
+                    Handler::Custom(h) => h.io.parse(source, &h.limits),
+                })?;
+                structure.set_limits(*self.limits());
+                let media_type = Some(structure.media_type);
+                self.in_context(media_type, self.limits().check_segment_count(&structure))?;
+                self.in_context(media_type, $crate::jumbf::describe_segments(&mut structure, source))?;
+                Ok(structure)
+            }
+
+            #[allow(unreachable_patterns)]
+            pub(crate) fn validate<R: std::io::Read + std::io::Seek>(
+                &self,
+                structure: &$crate::Structure,
+                source: &mut R,
+                report: &mut $crate::ValidationReport,
+            ) -> $crate::Result<()> {
+                self.in_context(Some(structure.media_type), match self {
+                    $(
+                        $(#[$meta])*
+                        Handler::$variant(h) => h.validate(structure, source, report),
+                    )*
+                    Handler::Custom(h) => h.io.validate(structure, source, report),
+                })
+            }
+
             #[allow(unreachable_patterns)]
             pub(crate) fn write<R: std::io::Read + std::io::Seek, W: std::io::Read + std::io::Write + std::io::Seek>(
                 &self,
@@ -323,12 +497,30 @@ macro_rules! register_containers {
                 writer: &mut W,
                 updates: &$crate::Updates,
             ) -> $crate::Result<()> {
-                match self {
+                self.in_context(Some(structure.media_type), match self {
                     $(
                         $(#[$meta])*
                         Handler::$variant(h) => h.write(structure, source, writer, updates),
                     )*
-                }
+                    Handler::Custom(h) => h.write(structure, source, writer, updates),
+                })
+            }
+
+            #[allow(unreachable_patterns)]
+            pub(crate) fn write_stream<R: std::io::Read + std::io::Seek, W: std::io::Write>(
+                &self,
+                structure: &$crate::Structure,
+                source: &mut R,
+                writer: &mut W,
+                updates: &$crate::Updates,
+            ) -> $crate::Result<()> {
+                self.in_context(Some(structure.media_type), match self {
+                    $(
+                        $(#[$meta])*
+                        Handler::$variant(h) => h.write_stream(structure, source, writer, updates),
+                    )*
+                    Handler::Custom(h) => h.write(structure, source, writer, updates),
+                })
             }
 
             #[allow(unreachable_patterns)]
@@ -343,12 +535,13 @@ macro_rules! register_containers {
             where
                 F: $crate::ProcessChunkFn,
             {
-                match self {
+                self.in_context(Some(structure.media_type), match self {
                     $(
                         $(#[$meta])*
                         Handler::$variant(h) => h.write_with_processor(structure, source, writer, updates, processor),
                     )*
-                }
+                    Handler::Custom(h) => h.write_with_processor(structure, source, writer, updates, processor),
+                })
             }
 
             #[allow(unreachable_patterns)]
@@ -357,12 +550,15 @@ macro_rules! register_containers {
                 source_structure: &$crate::Structure,
                 updates: &$crate::Updates,
             ) -> $crate::Result<$crate::Structure> {
-                match self {
+                let mut structure = self.in_context(Some(source_structure.media_type), match self {
                     $(
                         $(#[$meta])*
                         Handler::$variant(h) => h.calculate_updated_structure(source_structure, updates),
                     )*
-                }
+                    Handler::Custom(h) => h.calculate_updated_structure(source_structure, updates),
+                })?;
+                structure.set_limits(*self.limits());
+                Ok(structure)
             }
 
             #[allow(unreachable_patterns)]
@@ -371,12 +567,13 @@ macro_rules! register_containers {
                 structure: &$crate::Structure,
                 source: &mut R,
             ) -> $crate::Result<Option<Vec<u8>>> {
-                match self {
+                self.in_context(Some(structure.media_type), match self {
                     $(
                         $(#[$meta])*
                         Handler::$variant(h) => h.read_xmp(structure, source),
                     )*
-                }
+                    Handler::Custom(h) => h.io.read_xmp(structure, source),
+                })
             }
 
             #[allow(unreachable_patterns)]
@@ -385,12 +582,29 @@ macro_rules! register_containers {
                 structure: &$crate::Structure,
                 source: &mut R,
             ) -> $crate::Result<Option<Vec<u8>>> {
-                match self {
+                self.in_context(Some(structure.media_type), match self {
                     $(
                         $(#[$meta])*
                         Handler::$variant(h) => h.read_jumbf(structure, source),
                     )*
-                }
+                    Handler::Custom(h) => h.io.read_jumbf(structure, source),
+                })
+            }
+
+            #[allow(unreachable_patterns)]
+            pub(crate) fn read_jumbf_box<R: std::io::Read + std::io::Seek>(
+                &self,
+                structure: &$crate::Structure,
+                source: &mut R,
+                index: usize,
+            ) -> $crate::Result<Vec<u8>> {
+                self.in_context(Some(structure.media_type), match self {
+                    $(
+                        $(#[$meta])*
+                        Handler::$variant(h) => h.read_jumbf_box(structure, source, index),
+                    )*
+                    Handler::Custom(h) => read_segment_ranges(&h.limits, structure, source, index),
+                })
             }
 
             #[cfg(feature = "exif")]
@@ -400,12 +614,13 @@ macro_rules! register_containers {
                 structure: &$crate::Structure,
                 source: &mut R,
             ) -> $crate::Result<Option<$crate::thumbnail::EmbeddedThumbnailInfo>> {
-                match self {
+                self.in_context(Some(structure.media_type), match self {
                     $(
                         $(#[$meta])*
                         Handler::$variant(h) => h.read_embedded_thumbnail_info(structure, source),
                     )*
-                }
+                    Handler::Custom(_) => Ok(None),
+                })
             }
 
             #[cfg(feature = "exif")]
@@ -415,12 +630,28 @@ macro_rules! register_containers {
                 structure: &$crate::Structure,
                 source: &mut R,
             ) -> $crate::Result<Option<$crate::tiff::ExifInfo>> {
-                match self {
+                self.in_context(Some(structure.media_type), match self {
                     $(
                         $(#[$meta])*
                         Handler::$variant(h) => h.read_exif_info(structure, source),
                     )*
-                }
+                    Handler::Custom(_) => Ok(None),
+                })
+            }
+
+            #[allow(unreachable_patterns)]
+            pub(crate) fn read_media_info<R: std::io::Read + std::io::Seek>(
+                &self,
+                structure: &$crate::Structure,
+                source: &mut R,
+            ) -> $crate::Result<$crate::MediaInfo> {
+                self.in_context(Some(structure.media_type), match self {
+                    $(
+                        $(#[$meta])*
+                        Handler::$variant(h) => h.read_media_info(structure, source),
+                    )*
+                    Handler::Custom(h) => h.io.read_media_info(structure, source),
+                })
             }
 
             #[allow(unreachable_patterns)]
@@ -434,6 +665,9 @@ macro_rules! register_containers {
                         $(#[$meta])*
                         Handler::$variant(_) => $module::$io::exclusion_range_for_segment(structure, kind),
                     )*
+                    Handler::Custom(h) => {
+                        h.io.exclusion_range_for_segment(structure, kind, $crate::ExclusionMode::DataOnly)
+                    }
                 }
             }
         }
@@ -445,14 +679,20 @@ macro_rules! register_containers {
             use std::io::SeekFrom;
 
             source.seek(SeekFrom::Start(0))?;
-            let mut header = [0u8; 16];
-            let n = source.read(&mut header)?;
-            let header = &header[..n];
+            let mut header = Vec::with_capacity($crate::SNIFF_LEN);
+            source.take($crate::SNIFF_LEN as u64).read_to_end(&mut header)?;
+            let n = header.len();
+            let header = header.as_slice();
 
             if n < 2 {
                 return Err($crate::Error::InvalidFormat("File too small".into()));
             }
 
+            // Registered handlers come first so they can claim files of built-in containers
+            if let Some(container) = custom::detect(header) {
+                return Ok(container);
+            }
+
             $(
                 $(#[$meta])*
                 if let Some(container) = $module::$io::detect(header) {
@@ -460,7 +700,18 @@ macro_rules! register_containers {
                 }
             )*
 
-            Err($crate::Error::UnsupportedFormat)
+            // Say what the file is when it is a format we know but don't read
+            Err($crate::Error::UnsupportedFormat($crate::detect::known_format(header).copied()))
+        }
+
+        /// Media types every handler finds the header could hold
+        pub(crate) fn sniff_media_types(header: &[u8]) -> Vec<Candidate> {
+            let mut candidates = custom::sniff(header);
+            $(
+                $(#[$meta])*
+                candidates.extend($module::$io::sniff(header));
+            )*
+            candidates
         }
 
         /// Get handler for a container
@@ -470,36 +721,38 @@ macro_rules! register_containers {
                     $(#[$meta])*
                     ContainerKind::$variant => Ok(Handler::$variant($module::$io::new())),
                 )*
+                ContainerKind::Custom(format) => Ok(Handler::Custom(custom::handler(format)?)),
             }
         }
 
-        /// Detect container from file extension
-        #[allow(dead_code)]
-        pub fn detect_from_extension(ext: &str) -> Option<ContainerKind> {
-            let ext_lower = ext.to_lowercase();
-            $(
-                $(#[$meta])*
-                if $module::$io::extensions().contains(&ext_lower.as_str()) {
-                    return Some($module::$io::container_type());
-                }
-            )*
-            None
-        }
-
-        /// Detect container from MIME type
-        #[allow(dead_code)]
-        pub fn detect_from_mime(mime: &str) -> Option<ContainerKind> {
-            $(
-                $(#[$meta])*
-                if $module::$io::mime_types().iter().any(|m| m.eq_ignore_ascii_case(mime)) {
-                    return Some($module::$io::container_type());
-                }
-            )*
-            None
-        }
-
         // Generate ContainerKind methods
         impl ContainerKind {
+            /// Look up the container for a file extension (with or without dot, any case)
+            pub fn from_extension(ext: &str) -> Option<ContainerKind> {
+                let ext = ext.trim_start_matches('.');
+                $(
+                    $(#[$meta])*
+                    if $module::$io::extensions().iter().any(|e| e.eq_ignore_ascii_case(ext)) {
+                        return Some($module::$io::container_type());
+                    }
+                )*
+                custom::find_format(|f| f.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+                    .map(ContainerKind::Custom)
+            }
+
+            /// Look up the container for a MIME type (any case, parameters ignored)
+            pub fn from_mime(mime: &str) -> Option<ContainerKind> {
+                let mime = mime.split(';').next().unwrap_or_default().trim();
+                $(
+                    $(#[$meta])*
+                    if $module::$io::mime_types().iter().any(|m| m.eq_ignore_ascii_case(mime)) {
+                        return Some($module::$io::container_type());
+                    }
+                )*
+                custom::find_format(|f| f.mime_types.iter().any(|m| m.eq_ignore_ascii_case(mime)))
+                    .map(ContainerKind::Custom)
+            }
+
             /// Get the primary MIME type for this container
             ///
             /// Returns the most common/primary MIME type for this container.
@@ -523,6 +776,7 @@ macro_rules! register_containers {
                         $(#[$meta])*
                         ContainerKind::$variant => $module::$io::supported_media_types(),
                     )*
+                    ContainerKind::Custom(format) => custom::media_types(format),
                 }
             }
 
@@ -535,6 +789,7 @@ macro_rules! register_containers {
                         $(#[$meta])*
                         ContainerKind::$variant => $module::$io::mime_types(),
                     )*
+                    ContainerKind::Custom(format) => format.mime_types,
                 }
             }
 
@@ -547,6 +802,7 @@ macro_rules! register_containers {
                         $(#[$meta])*
                         ContainerKind::$variant => $module::$io::extensions(),
                     )*
+                    ContainerKind::Custom(format) => format.extensions,
                 }
             }
         }
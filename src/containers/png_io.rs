@@ -5,7 +5,7 @@ use crate::{
     error::{Error, Result},
     segment::{ByteRange, Segment, SegmentKind, DEFAULT_CHUNK_SIZE},
     structure::Structure,
-    Updates,
+    Candidate, Confidence, Limits, Updates, ValidationReport,
 };
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{Read, Seek, SeekFrom, Write};
@@ -49,12 +49,22 @@ fn chunk_label(chunk_type: &[u8; 4]) -> &'static str {
 }
 
 /// PNG container I/O implementation
-pub struct PngIO;
+pub struct PngIO {
+    limits: Limits,
+}
 
 impl PngIO {
     /// Create a new PNG I/O implementation
     pub fn new() -> Self {
-        Self
+        Self {
+            limits: Limits::default(),
+        }
+    }
+
+    /// Replace the limits enforced while parsing and reading
+    pub(crate) fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
     }
 
     /// Formats this handler supports
@@ -114,51 +124,41 @@ impl PngIO {
 
     /// Extract JUMBF data from PNG file (direct data from caBX chunks, no headers to strip)
     pub fn read_jumbf_impl<R: Read + Seek>(
+        &self,
         structure: &crate::structure::Structure,
         source: &mut R,
     ) -> Result<Option<Vec<u8>>> {
-        if structure.jumbf_indices().is_empty() {
+        let Some(index) = structure.c2pa_jumbf_index() else {
             return Ok(None);
-        }
+        };
 
-        let mut result = Vec::new();
+        // PNG stores JUMBF directly in caBX chunks - no format-specific headers to strip
+        let location = structure.segments()[index].location();
 
-        // For now, extract all JUMBF segments and concatenate them
-        // TODO: Filter by C2PA-specific JUMBF if needed
-        for &index in structure.jumbf_indices() {
-            let segment = &structure.segments()[index];
-            if segment.is_jumbf() {
-                // PNG stores JUMBF directly in caBX chunks - no format-specific headers to strip
-                let location = segment.location();
+        // Validate size to prevent memory exhaustion attacks
+        self.limits
+            .check_segment_size(location.offset, location.size, "JUMBF segment")?;
 
-                // Validate size to prevent memory exhaustion attacks
-                if location.size > crate::segment::MAX_SEGMENT_SIZE {
-                    return Err(Error::InvalidSegment {
-                        offset: location.offset,
-                        reason: format!(
-                            "JUMBF segment too large: {} bytes (max {} MB)",
-                            location.size,
-                            crate::segment::MAX_SEGMENT_SIZE / (1024 * 1024)
-                        ),
-                    });
-                }
-
-                source.seek(SeekFrom::Start(location.offset))?;
-                let mut buf = vec![0u8; location.size as usize];
-                source.read_exact(&mut buf)?;
-                result.extend_from_slice(&buf);
-            }
-        }
+        source.seek(SeekFrom::Start(location.offset))?;
+        let mut buf = vec![0u8; location.size as usize];
+        source.read_exact(&mut buf)?;
 
-        Ok(if result.is_empty() {
-            None
-        } else {
-            Some(result)
-        })
+        Ok(if buf.is_empty() { None } else { Some(buf) })
     }
 
     /// Fast single-pass parser
     fn parse_impl<R: Read + Seek>(&self, source: &mut R) -> Result<Structure> {
+        let mut at = (0, *b"PNGh");
+        self.parse_chunks(source, &mut at)
+            .map_err(|e| e.at(at.0, String::from_utf8_lossy(&at.1)))
+    }
+
+    /// Parse the chunks, keeping `at` on the offset and type of the chunk being parsed
+    fn parse_chunks<R: Read + Seek>(
+        &self,
+        source: &mut R,
+        at: &mut (u64, [u8; 4]),
+    ) -> Result<Structure> {
         let mut structure = Structure::new(ContainerKind::Png, crate::MediaType::Png);
 
         // Check PNG signature
@@ -175,24 +175,59 @@ impl PngIO {
             Some("PNGh".to_string()),
         ));
 
+        let file_len = source.seek(SeekFrom::End(0))?;
+        source.seek(SeekFrom::Start(8))?;
+
         let mut offset = 8u64;
         let mut found_iend = false;
 
         loop {
+            self.limits.check_segment_count(&structure)?;
+            if offset >= file_len {
+                break;
+            }
+
+            // A chunk that runs past the end of the file is dropped, with
+            // everything after it
+            if offset + 12 > file_len {
+                self.limits.recover(
+                    &mut structure,
+                    "PNG",
+                    Error::Truncated {
+                        offset,
+                        reason: "Truncated chunk header at the end of the file".into(),
+                    },
+                )?;
+                break;
+            }
+
             // Read chunk length
-            let chunk_len = match source.read_u32::<BigEndian>() {
-                Ok(len) => len as u64,
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e.into()),
-            };
+            let chunk_len = source.read_u32::<BigEndian>()? as u64;
 
             // Read chunk type
             let mut chunk_type = [0u8; 4];
             source.read_exact(&mut chunk_type)?;
+            *at = (offset, chunk_type);
 
             let chunk_start = offset;
             let data_offset = offset + 8; // After length (4) + type (4)
 
+            if data_offset + chunk_len + 4 > file_len {
+                let path = String::from_utf8_lossy(&chunk_type).into_owned();
+                self.limits.recover(
+                    &mut structure,
+                    &path,
+                    Error::Truncated {
+                        offset,
+                        reason: format!(
+                            "{} chunk runs past the end of the file ({} bytes declared)",
+                            path, chunk_len
+                        ),
+                    },
+                )?;
+                break;
+            }
+
             // Validate chunk length to prevent allocation attacks
             if chunk_len > 0x7FFFFFFF {
                 return Err(Error::InvalidSegment {
@@ -341,18 +376,66 @@ impl PngIO {
         }
 
         if !found_iend {
-            return Err(Error::InvalidFormat("PNG file missing IEND chunk".into()));
+            self.limits.recover(
+                &mut structure,
+                "IEND",
+                Error::Truncated {
+                    offset,
+                    reason: "PNG file missing IEND chunk".into(),
+                },
+            )?;
+            structure.total_size = offset;
         }
 
         Ok(structure)
     }
 
+    /// Add segments for new XMP and JUMBF chunks inserted before IEND
+    ///
+    /// Returns the offset after them.
+    fn add_new_metadata(
+        dest_structure: &mut Structure,
+        mut current_offset: u64,
+        updates: &Updates,
+        add_xmp: bool,
+        add_jumbf: bool,
+    ) -> u64 {
+        if let (true, crate::MetadataUpdate::Set(new_xmp)) = (add_xmp, &updates.xmp) {
+            // New XMP chunk - iTXt header adds 22 bytes
+            let xmp_size = new_xmp.len() as u64;
+            let chunk_data_size = ITXT_XMP_HEADER_SIZE + xmp_size;
+            dest_structure.add_segment(Segment::new(
+                current_offset + 8 + ITXT_XMP_HEADER_SIZE,
+                xmp_size,
+                SegmentKind::Xmp,
+                Some("iTXt".to_string()),
+            ));
+            current_offset += 8 + chunk_data_size + 4;
+        }
+
+        if let (true, crate::MetadataUpdate::Set(new_jumbf)) = (add_jumbf, &updates.jumbf) {
+            let jumbf_size = new_jumbf.len() as u64;
+            dest_structure.add_segment(Segment::new(
+                current_offset + 8,
+                jumbf_size,
+                SegmentKind::Jumbf,
+                Some("caBX".to_string()),
+            ));
+            current_offset += 8 + jumbf_size + 4;
+        }
+
+        current_offset
+    }
+
     /// Calculate CRC32 for PNG chunk
     fn calculate_crc(chunk_type: &[u8], data: &[u8]) -> u32 {
-        let mut crc = 0xFFFFFFFF_u32;
+        let crc = Self::update_crc(0xFFFFFFFF_u32, chunk_type);
+        Self::update_crc(crc, data) ^ 0xFFFFFFFF
+    }
 
-        // Process chunk type
-        for &byte in chunk_type {
+    /// Feed `bytes` into a running CRC32 (start at `0xFFFFFFFF`, finish by inverting)
+    fn update_crc(mut crc: u32, bytes: &[u8]) -> u32 {
+        for &byte in bytes {
             crc ^= byte as u32;
             for _ in 0..8 {
                 if crc & 1 != 0 {
@@ -362,20 +445,125 @@ impl PngIO {
                 }
             }
         }
+        crc
+    }
 
-        // Process data
-        for &byte in data {
-            crc ^= byte as u32;
-            for _ in 0..8 {
-                if crc & 1 != 0 {
-                    crc = (crc >> 1) ^ 0xEDB88320;
-                } else {
-                    crc >>= 1;
+    /// Check chunk CRCs and chunk ordering
+    fn validate_impl<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        report: &mut ValidationReport,
+    ) -> Result<()> {
+        let file_len = source.seek(SeekFrom::End(0))?;
+        let end = structure.total_size.min(file_len);
+        let mut chunks: Vec<(u64, [u8; 4])> = Vec::new();
+        let mut buffer = vec![0u8; DEFAULT_CHUNK_SIZE];
+
+        let mut offset = PNG_SIGNATURE.len() as u64;
+        while offset + 12 <= end {
+            source.seek(SeekFrom::Start(offset))?;
+            let chunk_len = source.read_u32::<BigEndian>()? as u64;
+            let mut chunk_type = [0u8; 4];
+            source.read_exact(&mut chunk_type)?;
+            if offset + 12 + chunk_len > end {
+                break;
+            }
+
+            let mut crc = Self::update_crc(0xFFFFFFFF_u32, &chunk_type);
+            let mut remaining = chunk_len;
+            while remaining > 0 {
+                let n = remaining.min(buffer.len() as u64) as usize;
+                source.read_exact(&mut buffer[..n])?;
+                crc = Self::update_crc(crc, &buffer[..n]);
+                remaining -= n as u64;
+            }
+            let stored = source.read_u32::<BigEndian>()?;
+            if stored != crc ^ 0xFFFFFFFF {
+                report.error(
+                    offset,
+                    String::from_utf8_lossy(&chunk_type),
+                    format!(
+                        "CRC mismatch: stored {:08X}, computed {:08X}",
+                        stored,
+                        crc ^ 0xFFFFFFFF
+                    ),
+                );
+            }
+            chunks.push((offset, chunk_type));
+            offset += 12 + chunk_len;
+        }
+
+        if chunks.last().is_some_and(|(_, t)| t == b"IEND") && file_len > structure.total_size {
+            report.warning(
+                structure.total_size,
+                "IEND",
+                format!("{} bytes after IEND", file_len - structure.total_size),
+            );
+        }
+
+        Self::validate_order(&chunks, report);
+        Ok(())
+    }
+
+    /// Check chunk ordering and multiplicity rules of the PNG specification
+    fn validate_order(chunks: &[(u64, [u8; 4])], report: &mut ValidationReport) {
+        // Chunks that may appear at most once
+        const SINGLE: &[&[u8; 4]] = &[
+            b"IHDR", b"PLTE", b"IEND", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"bKGD",
+            b"hIST", b"tRNS", b"pHYs", b"tIME", b"eXIf", b"caBX",
+        ];
+        // Chunks that must come before the first IDAT
+        const BEFORE_IDAT: &[&[u8; 4]] = &[
+            b"PLTE", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"bKGD", b"hIST", b"tRNS",
+            b"pHYs", b"sPLT",
+        ];
+        // Chunks that must come before PLTE
+        const BEFORE_PLTE: &[&[u8; 4]] = &[b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB"];
+        // Chunks that must come after PLTE
+        const AFTER_PLTE: &[&[u8; 4]] = &[b"tRNS", b"bKGD", b"hIST"];
+
+        let label = |t: &[u8; 4]| String::from_utf8_lossy(t).into_owned();
+        let position = |t: &[u8; 4]| chunks.iter().position(|(_, c)| c == t);
+
+        match chunks.first() {
+            Some((offset, t)) if t != b"IHDR" => {
+                report.error(*offset, label(t), "IHDR must be the first chunk")
+            }
+            _ => {}
+        }
+
+        let first_idat = position(b"IDAT");
+        let plte = position(b"PLTE");
+        if first_idat.is_none() {
+            report.error(8, "IDAT", "No IDAT chunk");
+        }
+        let mut idat_ended = false;
+        for (i, (offset, t)) in chunks.iter().enumerate() {
+            if SINGLE.contains(&t) && chunks[..i].iter().any(|(_, c)| c == t) {
+                report.error(*offset, label(t), format!("Duplicate {} chunk", label(t)));
+            }
+            if BEFORE_IDAT.contains(&t) && first_idat.is_some_and(|idat| i > idat) {
+                report.error(*offset, label(t), format!("{} after IDAT", label(t)));
+            }
+            if BEFORE_PLTE.contains(&t) && plte.is_some_and(|plte| i > plte) {
+                report.error(*offset, label(t), format!("{} after PLTE", label(t)));
+            }
+            if AFTER_PLTE.contains(&t) && plte.is_some_and(|plte| i < plte) {
+                report.error(*offset, label(t), format!("{} before PLTE", label(t)));
+            }
+            if t == b"IDAT" {
+                if idat_ended {
+                    report.error(*offset, "IDAT", "IDAT chunks are not consecutive");
                 }
+            } else if first_idat.is_some_and(|idat| i > idat) {
+                idat_ended = true;
             }
         }
 
-        crc ^ 0xFFFFFFFF
+        if let (Some(iccp), Some(_)) = (position(b"iCCP"), position(b"sRGB")) {
+            report.warning(chunks[iccp].0, "iCCP", "Both iCCP and sRGB are present");
+        }
     }
 
     /// Efficiently copy bytes from source to writer using chunked I/O
@@ -518,6 +706,17 @@ impl ContainerIO for PngIO {
         }
     }
 
+    fn sniff(header: &[u8]) -> Vec<Candidate> {
+        match Self::detect(header) {
+            Some(_) => vec![Candidate::new(crate::MediaType::Png, Confidence::High)],
+            None => Vec::new(),
+        }
+    }
+
+    fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
     fn parse<R: Read + Seek>(&self, source: &mut R) -> Result<Structure> {
         self.parse_impl(source)
     }
@@ -535,10 +734,10 @@ impl ContainerIO for PngIO {
         structure: &Structure,
         source: &mut R,
     ) -> Result<Option<Vec<u8>>> {
-        Self::read_jumbf_impl(structure, source)
+        self.read_jumbf_impl(structure, source)
     }
 
-    fn write<R: Read + Seek, W: Write>(
+    fn write_stream<R: Read + Seek, W: Write>(
         &self,
         structure: &Structure,
         source: &mut R,
@@ -668,6 +867,9 @@ impl ContainerIO for PngIO {
                         source.seek(SeekFrom::Start(location.offset))?;
                         Self::copy_bytes(source, writer, location.size)?;
                         other_index += 1;
+                    } else if dest_segment.path.as_deref() == Some("IEND") {
+                        // Repair a source that ends without IEND
+                        Self::write_chunk(writer, b"IEND", &[])?;
                     }
                 }
             }
@@ -829,6 +1031,9 @@ impl ContainerIO for PngIO {
                         source.seek(SeekFrom::Start(location.offset))?;
                         Self::copy_bytes(source, &mut pw, location.size)?;
                         other_index += 1;
+                    } else if dest_segment.path.as_deref() == Some("IEND") {
+                        // Repair a source that ends without IEND
+                        Self::write_chunk(&mut pw, b"IEND", &[])?;
                     }
                 }
             }
@@ -847,6 +1052,8 @@ impl ContainerIO for PngIO {
         // The segment iteration and decision logic mirrors write() exactly
         use crate::MetadataUpdate;
 
+        crate::jumbf::reject_labeled_boxes(updates, ContainerKind::Png)?;
+
         let mut dest_structure = Structure::new(ContainerKind::Png, source_structure.media_type);
         let mut current_offset = PNG_SIGNATURE.len() as u64;
 
@@ -860,6 +1067,7 @@ impl ContainerIO for PngIO {
 
         let mut xmp_written = false;
         let mut jumbf_written = false;
+        let mut found_iend = false;
 
         // Track if file has existing metadata
         let has_xmp = source_structure.segments.iter().any(|s| s.is_xmp());
@@ -971,37 +1179,14 @@ impl ContainerIO for PngIO {
                 segment => {
                     // Check if this is IEND - write new metadata before it
                     if segment.path.as_deref() == Some("IEND") {
-                        if !xmp_written && !has_xmp {
-                            if let MetadataUpdate::Set(new_xmp) = &updates.xmp {
-                                // New XMP chunk - iTXt header adds 22 bytes
-                                let xmp_size = new_xmp.len() as u64;
-                                let chunk_data_size = ITXT_XMP_HEADER_SIZE + xmp_size;
-                                let chunk_size = 8 + chunk_data_size + 4;
-                                dest_structure.add_segment(Segment::new(
-                                    current_offset + 8 + ITXT_XMP_HEADER_SIZE,
-                                    xmp_size,
-                                    SegmentKind::Xmp,
-                                    Some("iTXt".to_string()),
-                                ));
-                                current_offset += chunk_size;
-                                xmp_written = true;
-                            }
-                        }
-
-                        if !jumbf_written && !has_jumbf {
-                            if let MetadataUpdate::Set(new_jumbf) = &updates.jumbf {
-                                let jumbf_size = new_jumbf.len() as u64;
-                                let chunk_size = 8 + jumbf_size + 4;
-                                dest_structure.add_segment(Segment::new(
-                                    current_offset + 8,
-                                    jumbf_size,
-                                    SegmentKind::Jumbf,
-                                    Some("caBX".to_string()),
-                                ));
-                                current_offset += chunk_size;
-                                jumbf_written = true;
-                            }
-                        }
+                        current_offset = Self::add_new_metadata(
+                            &mut dest_structure,
+                            current_offset,
+                            updates,
+                            !xmp_written && !has_xmp,
+                            !jumbf_written && !has_jumbf,
+                        );
+                        found_iend = true;
                     }
 
                     // Copy other chunks as-is
@@ -1021,6 +1206,24 @@ impl ContainerIO for PngIO {
             }
         }
 
+        // Repair a source that ends without IEND
+        if !found_iend {
+            current_offset = Self::add_new_metadata(
+                &mut dest_structure,
+                current_offset,
+                updates,
+                !xmp_written && !has_xmp,
+                !jumbf_written && !has_jumbf,
+            );
+            dest_structure.add_segment(Segment::new(
+                current_offset,
+                12,
+                SegmentKind::Other,
+                Some("IEND".to_string()),
+            ));
+            current_offset += 12;
+        }
+
         dest_structure.total_size = current_offset;
         Ok(dest_structure)
     }
@@ -1071,6 +1274,54 @@ impl ContainerIO for PngIO {
         crate::tiff::parse_exif_info(&data)
     }
 
+    fn read_media_info<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+    ) -> Result<crate::MediaInfo> {
+        let mut info = crate::MediaInfo::default();
+
+        let ihdr = structure
+            .segments()
+            .iter()
+            .find(|s| s.path.as_deref() == Some("IHDR"));
+        let Some(ihdr) = ihdr else {
+            return Ok(info);
+        };
+
+        // Segment covers length + type + data; IHDR data is
+        // width(4) + height(4) + bit depth + color type + compression + filter + interlace
+        source.seek(SeekFrom::Start(ihdr.location().offset + 8))?;
+        let mut data = [0u8; 13];
+        source.read_exact(&mut data)?;
+
+        let color_type = data[9];
+        info.width = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]));
+        info.height = Some(u32::from_be_bytes([data[4], data[5], data[6], data[7]]));
+        info.bit_depth = Some(data[8] as u16);
+        info.color_type = Some(color_type);
+        info.components = match color_type {
+            0 => Some(1), // grayscale
+            2 => Some(3), // RGB
+            3 => Some(1), // palette index
+            4 => Some(2), // grayscale + alpha
+            6 => Some(4), // RGBA
+            _ => None,
+        };
+        info.progressive = Some(data[12] == 1);
+        info.codec = Some("png".to_string());
+        Ok(info)
+    }
+
+    fn validate<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        report: &mut ValidationReport,
+    ) -> Result<()> {
+        self.validate_impl(structure, source, report)
+    }
+
     fn exclusion_range_for_segment(structure: &Structure, kind: SegmentKind) -> Option<(u64, u64)> {
         let segment = match kind {
             SegmentKind::Jumbf => structure
@@ -1270,6 +1521,9 @@ mod tests {
         let result = handler.parse(&mut source);
 
         assert!(result.is_err());
-        assert!(matches!(result.unwrap_err(), Error::InvalidFormat(_)));
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::InvalidFormat(_)
+        ));
     }
 }
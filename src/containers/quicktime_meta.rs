@@ -0,0 +1,1019 @@
+//! QuickTime / MP4 descriptive metadata (`moov/udta` and `moov/meta`)
+//!
+//! Movies carry descriptive metadata in three places:
+//!
+//! - iTunes-style items in `moov/udta/meta/ilst`, keyed by four-character code
+//!   (`©nam`, `©ART`, `©day`, `covr`, ...) or by `----` freeform atoms
+//! - QuickTime `mdta` items in `moov/meta`, keyed by reverse-DNS names listed in
+//!   `keys` (`com.apple.quicktime.creationdate`, ...)
+//! - plain `moov/udta` child atoms (`©xyz`, `©day`, ...) holding QuickTime
+//!   international text
+//!
+//! Tracks may also carry a `©xyz` atom in `moov/trak/udta`; only location
+//! removal touches those.
+//!
+//! The `udta` and `meta` boxes are small, so they are parsed and rebuilt in
+//! memory. Atoms that are not edited are copied byte-for-byte.
+//!
+//! Reference: QuickTime File Format Specification, "Metadata"
+
+use crate::error::{Error, Result};
+
+/// `mdta` key for the ISO 6709 location string written by Apple devices
+pub const QUICKTIME_LOCATION_KEY: &str = "com.apple.quicktime.location.ISO6709";
+
+/// `mdta` key for the capture date written by Apple devices
+pub const QUICKTIME_CREATION_DATE_KEY: &str = "com.apple.quicktime.creationdate";
+
+/// Prefix shared by all Apple location-related `mdta` keys
+const LOCATION_KEY_PREFIX: &str = "com.apple.quicktime.location.";
+
+/// `udta` atom holding an ISO 6709 location string
+const UDTA_LOCATION: [u8; 4] = *b"\xa9xyz";
+
+/// Language code for "undetermined" (packed ISO 639-2/T `und`)
+const LANGUAGE_UND: u16 = 0x55c4;
+
+// Well-known data types (QuickTime File Format, table "Well-known data types")
+const TYPE_UTF8: u32 = 1;
+const TYPE_JPEG: u32 = 13;
+const TYPE_PNG: u32 = 14;
+const TYPE_BE_SIGNED: u32 = 21;
+const TYPE_BE_UNSIGNED: u32 = 22;
+
+/// Identifies one QuickTime / MP4 metadata entry
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum QuickTimeKey {
+    /// iTunes-style item in `moov/udta/meta/ilst`, e.g. `©nam`
+    Item([u8; 4]),
+    /// iTunes freeform (`----`) item, identified by its mean and name
+    Freeform {
+        /// Reverse-DNS namespace, e.g. `com.apple.iTunes`
+        mean: String,
+        /// Item name within the namespace
+        name: String,
+    },
+    /// QuickTime `mdta` item in `moov/meta`, e.g. `com.apple.quicktime.creationdate`
+    Mdta(String),
+    /// Plain `moov/udta` child atom, e.g. `©xyz`
+    Udta([u8; 4]),
+}
+
+impl QuickTimeKey {
+    /// Title (`©nam`)
+    pub const TITLE: Self = Self::Item(*b"\xa9nam");
+    /// Artist (`©ART`)
+    pub const ARTIST: Self = Self::Item(*b"\xa9ART");
+    /// Release or recording date (`©day`)
+    pub const DATE: Self = Self::Item(*b"\xa9day");
+    /// Cover art (`covr`)
+    pub const COVER: Self = Self::Item(*b"covr");
+
+    /// Convenience constructor for [`QuickTimeKey::Mdta`]
+    pub fn mdta(name: impl Into<String>) -> Self {
+        Self::Mdta(name.into())
+    }
+
+    /// True for keys that describe where the movie was recorded
+    pub fn is_location(&self) -> bool {
+        match self {
+            Self::Mdta(name) => name.starts_with(LOCATION_KEY_PREFIX),
+            Self::Udta(fourcc) => *fourcc == UDTA_LOCATION,
+            _ => false,
+        }
+    }
+}
+
+/// Value of a QuickTime / MP4 metadata entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuickTimeValue {
+    /// UTF-8 text
+    Text(String),
+    /// Big-endian integer
+    Integer(i64),
+    /// JPEG image (e.g. cover art)
+    Jpeg(Vec<u8>),
+    /// PNG image (e.g. cover art)
+    Png(Vec<u8>),
+    /// Any other data, with its well-known type code (0 for untyped `udta` atoms)
+    Data {
+        /// Well-known data type
+        type_code: u32,
+        /// Raw value bytes
+        data: Vec<u8>,
+    },
+}
+
+impl QuickTimeValue {
+    /// The text, if this is a text value
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Self::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn from_data(type_code: u32, data: &[u8]) -> Self {
+        match type_code {
+            TYPE_UTF8 => Self::Text(String::from_utf8_lossy(data).into_owned()),
+            TYPE_JPEG => Self::Jpeg(data.to_vec()),
+            TYPE_PNG => Self::Png(data.to_vec()),
+            TYPE_BE_SIGNED | TYPE_BE_UNSIGNED if matches!(data.len(), 1 | 2 | 3 | 4 | 8) => {
+                let mut value = if type_code == TYPE_BE_SIGNED && data[0] & 0x80 != 0 {
+                    -1i64
+                } else {
+                    0
+                };
+                for &b in data {
+                    value = (value << 8) | b as i64;
+                }
+                Self::Integer(value)
+            }
+            _ => Self::Data {
+                type_code,
+                data: data.to_vec(),
+            },
+        }
+    }
+
+    fn to_data(&self) -> (u32, Vec<u8>) {
+        match self {
+            Self::Text(text) => (TYPE_UTF8, text.as_bytes().to_vec()),
+            Self::Integer(value) => match i32::try_from(*value) {
+                Ok(v) => (TYPE_BE_SIGNED, v.to_be_bytes().to_vec()),
+                Err(_) => (TYPE_BE_SIGNED, value.to_be_bytes().to_vec()),
+            },
+            Self::Jpeg(data) => (TYPE_JPEG, data.clone()),
+            Self::Png(data) => (TYPE_PNG, data.clone()),
+            Self::Data { type_code, data } => (*type_code, data.clone()),
+        }
+    }
+}
+
+/// Descriptive metadata read from a movie's `moov/udta` and `moov/meta` boxes
+///
+/// Entries are listed in file order. A key can occur more than once (e.g. several
+/// `covr` images).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuickTimeMetadata {
+    entries: Vec<(QuickTimeKey, QuickTimeValue)>,
+}
+
+impl QuickTimeMetadata {
+    /// All entries in file order
+    pub fn entries(&self) -> &[(QuickTimeKey, QuickTimeValue)] {
+        &self.entries
+    }
+
+    /// The first value stored under `key`
+    pub fn get(&self, key: &QuickTimeKey) -> Option<&QuickTimeValue> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// The first text value stored under `key`
+    pub fn text(&self, key: &QuickTimeKey) -> Option<&str> {
+        self.get(key).and_then(QuickTimeValue::as_text)
+    }
+
+    /// The ISO 6709 recording location, from `mdta` keys or the `©xyz` atom
+    pub fn location(&self) -> Option<&str> {
+        self.text(&QuickTimeKey::mdta(QUICKTIME_LOCATION_KEY))
+            .or_else(|| self.text(&QuickTimeKey::Udta(UDTA_LOCATION)))
+    }
+
+    /// The `com.apple.quicktime.creationdate` value
+    pub fn creation_date(&self) -> Option<&str> {
+        self.text(&QuickTimeKey::mdta(QUICKTIME_CREATION_DATE_KEY))
+    }
+
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True if there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// A change to QuickTime metadata, applied in the order given to [`Updates`](crate::Updates)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum QuickTimeEdit {
+    /// Replace all values of a key with one value
+    Set(QuickTimeKey, QuickTimeValue),
+    /// Remove all values of a key
+    Remove(QuickTimeKey),
+    /// Remove every location-related entry
+    RemoveLocation,
+}
+
+// ============================================================================
+// Box helpers
+// ============================================================================
+
+/// A box within an in-memory buffer
+#[derive(Debug, Clone, Copy)]
+struct RawBox<'a> {
+    fourcc: [u8; 4],
+    /// The whole box, header included
+    bytes: &'a [u8],
+    header_len: usize,
+}
+
+impl<'a> RawBox<'a> {
+    fn payload(&self) -> &'a [u8] {
+        &self.bytes[self.header_len..]
+    }
+}
+
+/// Split a buffer into consecutive boxes
+fn split_boxes(data: &[u8]) -> Result<Vec<RawBox<'_>>> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as u64;
+        let mut fourcc = [0u8; 4];
+        fourcc.copy_from_slice(&data[pos + 4..pos + 8]);
+        let (size, header_len) = match size {
+            0 => ((data.len() - pos) as u64, 8),
+            1 if pos + 16 <= data.len() => (
+                u64::from_be_bytes(data[pos + 8..pos + 16].try_into().unwrap()),
+                16,
+            ),
+            s => (s, 8),
+        };
+        if size < header_len as u64 || pos as u64 + size > data.len() as u64 {
+            return Err(Error::InvalidFormat(format!(
+                "Invalid '{}' box size {}",
+                String::from_utf8_lossy(&fourcc),
+                size
+            )));
+        }
+        let end = pos + size as usize;
+        boxes.push(RawBox {
+            fourcc,
+            bytes: &data[pos..end],
+            header_len,
+        });
+        pos = end;
+    }
+    Ok(boxes)
+}
+
+fn make_box(fourcc: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 8);
+    out.extend_from_slice(&((payload.len() + 8) as u32).to_be_bytes());
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(payload);
+    out
+}
+
+fn make_full_box(fourcc: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut body = vec![0u8; 4];
+    body.extend_from_slice(payload);
+    make_box(fourcc, &body)
+}
+
+fn make_hdlr(handler_type: &[u8; 4]) -> Vec<u8> {
+    let mut payload = vec![0u8; 4]; // pre_defined
+    payload.extend_from_slice(handler_type);
+    if handler_type == b"mdir" {
+        payload.extend_from_slice(b"appl"); // iTunes writes its manufacturer code
+        payload.extend_from_slice(&[0u8; 8]);
+    } else {
+        payload.extend_from_slice(&[0u8; 12]);
+    }
+    payload.push(0); // empty name
+    make_full_box(b"hdlr", &payload)
+}
+
+/// Offset of the first child of a `meta` box, which is a full box in ISO files and
+/// a plain container in QuickTime files
+fn meta_children_offset(meta: &RawBox<'_>) -> usize {
+    let payload = meta.payload();
+    if payload.len() >= 8 && &payload[4..8] == b"hdlr" {
+        meta.header_len
+    } else {
+        meta.header_len + 4
+    }
+}
+
+/// Parse the first `data` atom of an item
+fn parse_data_atom(item_payload: &[u8]) -> Result<Option<QuickTimeValue>> {
+    for child in split_boxes(item_payload)? {
+        if &child.fourcc == b"data" {
+            let payload = child.payload();
+            if payload.len() < 8 {
+                return Err(Error::InvalidFormat("Truncated data atom".into()));
+            }
+            let type_code = u32::from_be_bytes(payload[0..4].try_into().unwrap()) & 0x00ff_ffff;
+            return Ok(Some(QuickTimeValue::from_data(type_code, &payload[8..])));
+        }
+    }
+    Ok(None)
+}
+
+fn make_data_atom(value: &QuickTimeValue) -> Vec<u8> {
+    let (type_code, data) = value.to_data();
+    let mut payload = Vec::with_capacity(data.len() + 8);
+    payload.extend_from_slice(&type_code.to_be_bytes());
+    payload.extend_from_slice(&0u32.to_be_bytes()); // locale
+    payload.extend_from_slice(&data);
+    make_box(b"data", &payload)
+}
+
+/// Read a string from a full box (`mean` / `name` in freeform items)
+fn full_box_string(b: &RawBox<'_>) -> String {
+    let payload = b.payload();
+    String::from_utf8_lossy(payload.get(4..).unwrap_or(&[])).into_owned()
+}
+
+/// The mean/name of a `----` item
+fn freeform_key(item_payload: &[u8]) -> Result<Option<QuickTimeKey>> {
+    let mut mean = None;
+    let mut name = None;
+    for child in split_boxes(item_payload)? {
+        match &child.fourcc {
+            b"mean" => mean = Some(full_box_string(&child)),
+            b"name" => name = Some(full_box_string(&child)),
+            _ => {}
+        }
+    }
+    Ok(match (mean, name) {
+        (Some(mean), Some(name)) => Some(QuickTimeKey::Freeform { mean, name }),
+        _ => None,
+    })
+}
+
+fn make_item(key: &QuickTimeKey, value: &QuickTimeValue) -> Vec<u8> {
+    match key {
+        QuickTimeKey::Freeform { mean, name } => {
+            let mut payload = make_full_box(b"mean", mean.as_bytes());
+            payload.extend(make_full_box(b"name", name.as_bytes()));
+            payload.extend(make_data_atom(value));
+            make_box(b"----", &payload)
+        }
+        QuickTimeKey::Item(fourcc) => make_box(fourcc, &make_data_atom(value)),
+        // mdta and udta entries are serialized by their own containers
+        _ => unreachable!("not an ilst key"),
+    }
+}
+
+/// Parse QuickTime international text (`[u16 size][u16 language][text]...`)
+fn parse_intl_text(payload: &[u8]) -> Option<String> {
+    if payload.len() < 4 {
+        return None;
+    }
+    let len = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+    let text = payload.get(4..4 + len)?;
+    std::str::from_utf8(text).ok().map(str::to_string)
+}
+
+fn make_udta_atom(fourcc: &[u8; 4], value: &QuickTimeValue) -> Vec<u8> {
+    match value {
+        QuickTimeValue::Text(text) if fourcc[0] == 0xa9 => {
+            let mut payload = Vec::with_capacity(text.len() + 4);
+            payload.extend_from_slice(&(text.len().min(u16::MAX as usize) as u16).to_be_bytes());
+            payload.extend_from_slice(&LANGUAGE_UND.to_be_bytes());
+            payload.extend_from_slice(text.as_bytes());
+            make_box(fourcc, &payload)
+        }
+        other => make_box(fourcc, &other.to_data().1),
+    }
+}
+
+// ============================================================================
+// Reading
+// ============================================================================
+
+/// Parse entries from the (complete) `moov/udta` and `moov/meta` boxes
+pub(crate) fn parse(udta: Option<&[u8]>, meta: Option<&[u8]>) -> Result<QuickTimeMetadata> {
+    let mut entries = Vec::new();
+
+    if let Some(udta) = udta {
+        let udta = single_box(udta)?;
+        for child in split_boxes(udta.payload())? {
+            if &child.fourcc == b"meta" {
+                parse_itunes_meta(&child, &mut entries)?;
+                continue;
+            }
+            let value = if child.fourcc[0] == 0xa9 {
+                parse_intl_text(child.payload()).map(QuickTimeValue::Text)
+            } else {
+                None
+            };
+            let value = value.unwrap_or_else(|| QuickTimeValue::Data {
+                type_code: 0,
+                data: child.payload().to_vec(),
+            });
+            entries.push((QuickTimeKey::Udta(child.fourcc), value));
+        }
+    }
+
+    if let Some(meta) = meta {
+        let meta = single_box(meta)?;
+        let children = split_boxes(&meta.bytes[meta_children_offset(&meta)..])?;
+        let keys = children
+            .iter()
+            .find(|c| &c.fourcc == b"keys")
+            .map(|k| parse_keys(k.payload()))
+            .transpose()?
+            .unwrap_or_default();
+        if let Some(ilst) = children.iter().find(|c| &c.fourcc == b"ilst") {
+            for item in split_boxes(ilst.payload())? {
+                let index = u32::from_be_bytes(item.fourcc) as usize;
+                let Some((namespace, name)) = index.checked_sub(1).and_then(|i| keys.get(i)) else {
+                    continue;
+                };
+                if namespace != b"mdta" {
+                    continue;
+                }
+                if let Some(value) = parse_data_atom(item.payload())? {
+                    entries.push((QuickTimeKey::Mdta(name.clone()), value));
+                }
+            }
+        }
+    }
+
+    Ok(QuickTimeMetadata { entries })
+}
+
+fn single_box(bytes: &[u8]) -> Result<RawBox<'_>> {
+    split_boxes(bytes)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::InvalidFormat("Empty box".into()))
+}
+
+fn parse_itunes_meta(
+    meta: &RawBox<'_>,
+    entries: &mut Vec<(QuickTimeKey, QuickTimeValue)>,
+) -> Result<()> {
+    for child in split_boxes(&meta.bytes[meta_children_offset(meta)..])? {
+        if &child.fourcc != b"ilst" {
+            continue;
+        }
+        for item in split_boxes(child.payload())? {
+            let key = if &item.fourcc == b"----" {
+                match freeform_key(item.payload())? {
+                    Some(key) => key,
+                    None => continue,
+                }
+            } else {
+                QuickTimeKey::Item(item.fourcc)
+            };
+            if let Some(value) = parse_data_atom(item.payload())? {
+                entries.push((key, value));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse a `keys` payload into (namespace, name) pairs
+fn parse_keys(payload: &[u8]) -> Result<Vec<([u8; 4], String)>> {
+    if payload.len() < 8 {
+        return Err(Error::InvalidFormat("Truncated keys box".into()));
+    }
+    let count = u32::from_be_bytes(payload[4..8].try_into().unwrap()) as usize;
+    let mut keys = Vec::with_capacity(count.min(1024));
+    for entry in split_boxes(&payload[8..])?.into_iter().take(count) {
+        keys.push((
+            entry.fourcc,
+            String::from_utf8_lossy(entry.payload()).into_owned(),
+        ));
+    }
+    Ok(keys)
+}
+
+// ============================================================================
+// Writing
+// ============================================================================
+
+/// New `moov/udta` and `moov/meta` boxes; `None` means the box is absent
+type MoovChildren = (Option<Vec<u8>>, Option<Vec<u8>>);
+
+/// Apply edits to the (complete) `moov/udta` and `moov/meta` boxes
+///
+/// Returns the new boxes; `None` means the box is absent. Boxes are returned
+/// unchanged when no edit touches them.
+pub(crate) fn apply_edits(
+    udta: Option<&[u8]>,
+    meta: Option<&[u8]>,
+    edits: &[QuickTimeEdit],
+) -> Result<MoovChildren> {
+    let touches = |pred: fn(&QuickTimeKey) -> bool| {
+        edits.iter().any(|e| match e {
+            QuickTimeEdit::Set(key, _) | QuickTimeEdit::Remove(key) => pred(key),
+            QuickTimeEdit::RemoveLocation => true,
+        })
+    };
+    let udta_edited = touches(|k| !matches!(k, QuickTimeKey::Mdta(_)));
+    let meta_edited = touches(|k| matches!(k, QuickTimeKey::Mdta(_)));
+
+    let new_udta = if udta_edited {
+        rebuild_udta(udta, edits)?
+    } else {
+        udta.map(<[u8]>::to_vec)
+    };
+    let new_meta = if meta_edited {
+        rebuild_mdta_meta(meta, edits)?
+    } else {
+        meta.map(<[u8]>::to_vec)
+    };
+    Ok((new_udta, new_meta))
+}
+
+/// Entries of a container being rebuilt: the key (if recognized) and the atom bytes
+type Atoms = Vec<(Option<QuickTimeKey>, Vec<u8>)>;
+
+/// Apply edits to a list of atoms, using `make` to serialize new values
+fn edit_atoms(
+    atoms: &mut Atoms,
+    edits: &[QuickTimeEdit],
+    applies: impl Fn(&QuickTimeKey) -> bool,
+    make: impl Fn(&QuickTimeKey, &QuickTimeValue) -> Vec<u8>,
+) {
+    for edit in edits {
+        match edit {
+            QuickTimeEdit::Set(key, value) if applies(key) => {
+                let atom = make(key, value);
+                match atoms.iter().position(|(k, _)| k.as_ref() == Some(key)) {
+                    Some(i) => {
+                        // Replace the first value and drop any others
+                        atoms[i].1 = atom;
+                        let mut index = 0;
+                        atoms.retain(|(k, _)| {
+                            let keep = index <= i || k.as_ref() != Some(key);
+                            index += 1;
+                            keep
+                        });
+                    }
+                    None => atoms.push((Some(key.clone()), atom)),
+                }
+            }
+            QuickTimeEdit::Remove(key) if applies(key) => {
+                atoms.retain(|(k, _)| k.as_ref() != Some(key));
+            }
+            QuickTimeEdit::RemoveLocation => {
+                atoms.retain(|(k, _)| !k.as_ref().is_some_and(QuickTimeKey::is_location));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn rebuild_udta(udta: Option<&[u8]>, edits: &[QuickTimeEdit]) -> Result<Option<Vec<u8>>> {
+    let udta_box = udta.map(single_box).transpose()?;
+    // The iTunes meta box is the only atom without a key
+    let mut atoms: Atoms = Vec::new();
+    if let Some(udta_box) = &udta_box {
+        for child in split_boxes(udta_box.payload())? {
+            if &child.fourcc == b"meta" && !atoms.iter().any(|(k, _)| k.is_none()) {
+                atoms.push((None, child.bytes.to_vec()));
+            } else {
+                atoms.push((Some(QuickTimeKey::Udta(child.fourcc)), child.bytes.to_vec()));
+            }
+        }
+    }
+
+    edit_atoms(
+        &mut atoms,
+        edits,
+        |k| matches!(k, QuickTimeKey::Udta(_)),
+        |k, v| match k {
+            QuickTimeKey::Udta(fourcc) => make_udta_atom(fourcc, v),
+            _ => unreachable!(),
+        },
+    );
+
+    // iTunes items live in udta/meta/ilst
+    let itunes_edited = edits.iter().any(|e| match e {
+        QuickTimeEdit::Set(key, _) | QuickTimeEdit::Remove(key) => {
+            matches!(key, QuickTimeKey::Item(_) | QuickTimeKey::Freeform { .. })
+        }
+        QuickTimeEdit::RemoveLocation => false,
+    });
+    if itunes_edited {
+        let index = match atoms.iter().position(|(k, _)| k.is_none()) {
+            Some(i) => i,
+            None => {
+                atoms.push((None, Vec::new()));
+                atoms.len() - 1
+            }
+        };
+        let old = std::mem::take(&mut atoms[index].1);
+        atoms[index].1 = rebuild_itunes_meta((!old.is_empty()).then_some(old.as_slice()), edits)?;
+    }
+
+    if udta_box.is_none() && atoms.is_empty() {
+        return Ok(None);
+    }
+    let payload: Vec<u8> = atoms.into_iter().flat_map(|(_, bytes)| bytes).collect();
+    Ok(Some(make_box(b"udta", &payload)))
+}
+
+fn rebuild_itunes_meta(meta: Option<&[u8]>, edits: &[QuickTimeEdit]) -> Result<Vec<u8>> {
+    let meta_box = meta.map(single_box).transpose()?;
+    let (prefix, children) = match &meta_box {
+        Some(m) => {
+            let offset = meta_children_offset(m);
+            (
+                m.bytes[m.header_len..offset].to_vec(),
+                split_boxes(&m.bytes[offset..])?,
+            )
+        }
+        None => (vec![0u8; 4], Vec::new()),
+    };
+
+    let mut out_children: Vec<Vec<u8>> = Vec::new();
+    let mut has_hdlr = false;
+    let mut ilst_index = None;
+    let mut atoms: Atoms = Vec::new();
+    for child in &children {
+        match &child.fourcc {
+            b"hdlr" => has_hdlr = true,
+            b"ilst" if ilst_index.is_none() => {
+                ilst_index = Some(out_children.len());
+                for item in split_boxes(child.payload())? {
+                    let key = if &item.fourcc == b"----" {
+                        freeform_key(item.payload())?
+                    } else {
+                        Some(QuickTimeKey::Item(item.fourcc))
+                    };
+                    atoms.push((key, item.bytes.to_vec()));
+                }
+            }
+            _ => {}
+        }
+        out_children.push(child.bytes.to_vec());
+    }
+    if !has_hdlr {
+        out_children.insert(0, make_hdlr(b"mdir"));
+        ilst_index = ilst_index.map(|i| i + 1);
+    }
+
+    edit_atoms(
+        &mut atoms,
+        edits,
+        |k| matches!(k, QuickTimeKey::Item(_) | QuickTimeKey::Freeform { .. }),
+        make_item,
+    );
+
+    let ilst = make_box(
+        b"ilst",
+        &atoms.into_iter().flat_map(|(_, b)| b).collect::<Vec<_>>(),
+    );
+    match ilst_index {
+        Some(i) => out_children[i] = ilst,
+        None => {
+            // Keep any trailing padding (free) after the item list
+            let at = out_children
+                .iter()
+                .position(|c| c.get(4..8) == Some(b"free"))
+                .unwrap_or(out_children.len());
+            out_children.insert(at, ilst);
+        }
+    }
+
+    let mut payload = prefix;
+    for child in out_children {
+        payload.extend(child);
+    }
+    Ok(make_box(b"meta", &payload))
+}
+
+fn rebuild_mdta_meta(meta: Option<&[u8]>, edits: &[QuickTimeEdit]) -> Result<Option<Vec<u8>>> {
+    let meta_box = meta.map(single_box).transpose()?;
+    let (prefix, children) = match &meta_box {
+        Some(m) => {
+            let offset = meta_children_offset(m);
+            (
+                m.bytes[m.header_len..offset].to_vec(),
+                split_boxes(&m.bytes[offset..])?,
+            )
+        }
+        // QuickTime meta has no version/flags
+        None => (Vec::new(), Vec::new()),
+    };
+
+    let keys = children
+        .iter()
+        .find(|c| &c.fourcc == b"keys")
+        .map(|k| parse_keys(k.payload()))
+        .transpose()?
+        .unwrap_or_default();
+    let keys_flags = children
+        .iter()
+        .find(|c| &c.fourcc == b"keys")
+        .map(|k| k.payload()[0..4].to_vec())
+        .unwrap_or_else(|| vec![0u8; 4]);
+
+    // Items keyed by mdta name; items pointing at other namespaces are kept as-is
+    let mut atoms: Atoms = Vec::new();
+    let mut other_items: Vec<(usize, Vec<u8>)> = Vec::new();
+    if let Some(ilst) = children.iter().find(|c| &c.fourcc == b"ilst") {
+        for item in split_boxes(ilst.payload())? {
+            let index = u32::from_be_bytes(item.fourcc) as usize;
+            match index.checked_sub(1).and_then(|i| keys.get(i)) {
+                Some((ns, name)) if *ns == *b"mdta" => atoms.push((
+                    Some(QuickTimeKey::Mdta(name.clone())),
+                    item.payload().to_vec(),
+                )),
+                _ => other_items.push((index, item.payload().to_vec())),
+            }
+        }
+    }
+
+    edit_atoms(
+        &mut atoms,
+        edits,
+        |k| matches!(k, QuickTimeKey::Mdta(_)),
+        |_, v| make_data_atom(v),
+    );
+
+    if meta_box.is_none() && atoms.is_empty() {
+        return Ok(None);
+    }
+
+    // Rebuild keys: non-mdta keys keep their index, mdta keys are renumbered
+    // in item order after them
+    let mut new_keys: Vec<([u8; 4], String)> = Vec::new();
+    let mut items: Vec<(u32, Vec<u8>)> = Vec::new();
+    for (index, payload) in other_items {
+        if let Some(key) = index.checked_sub(1).and_then(|i| keys.get(i)) {
+            new_keys.push(key.clone());
+            items.push((new_keys.len() as u32, payload));
+        }
+    }
+    for (key, payload) in atoms {
+        let Some(QuickTimeKey::Mdta(name)) = key else {
+            continue;
+        };
+        let index = match new_keys
+            .iter()
+            .position(|(ns, n)| ns == b"mdta" && *n == name)
+        {
+            Some(i) => i + 1,
+            None => {
+                new_keys.push((*b"mdta", name));
+                new_keys.len()
+            }
+        };
+        items.push((index as u32, payload));
+    }
+
+    let mut keys_payload = keys_flags;
+    keys_payload.extend_from_slice(&(new_keys.len() as u32).to_be_bytes());
+    for (ns, name) in &new_keys {
+        keys_payload.extend_from_slice(&((name.len() + 8) as u32).to_be_bytes());
+        keys_payload.extend_from_slice(ns);
+        keys_payload.extend_from_slice(name.as_bytes());
+    }
+    let keys_box = make_box(b"keys", &keys_payload);
+
+    let mut ilst_payload = Vec::new();
+    for (index, payload) in items {
+        ilst_payload.extend(make_box(&index.to_be_bytes(), &payload));
+    }
+    let ilst_box = make_box(b"ilst", &ilst_payload);
+
+    let mut payload = prefix;
+    let mut wrote_keys = false;
+    let mut wrote_ilst = false;
+    if !children.iter().any(|c| &c.fourcc == b"hdlr") {
+        payload.extend(make_hdlr(b"mdta"));
+    }
+    for child in &children {
+        match &child.fourcc {
+            b"keys" if !wrote_keys => {
+                payload.extend_from_slice(&keys_box);
+                wrote_keys = true;
+            }
+            b"ilst" if !wrote_ilst => {
+                if !wrote_keys {
+                    payload.extend_from_slice(&keys_box);
+                    wrote_keys = true;
+                }
+                payload.extend_from_slice(&ilst_box);
+                wrote_ilst = true;
+            }
+            b"keys" | b"ilst" => {}
+            _ => payload.extend_from_slice(child.bytes),
+        }
+    }
+    if !wrote_keys {
+        payload.extend_from_slice(&keys_box);
+    }
+    if !wrote_ilst {
+        payload.extend_from_slice(&ilst_box);
+    }
+    Ok(Some(make_box(b"meta", &payload)))
+}
+
+/// Remove the `©xyz` atoms from the `udta` box of a track
+///
+/// Tracks carry no `mdta` keys or iTunes items, so this is all that
+/// [`QuickTimeEdit::RemoveLocation`] changes in them.
+pub(crate) fn strip_track_location(udta: &[u8]) -> Result<Vec<u8>> {
+    let udta_box = single_box(udta)?;
+    let children = split_boxes(udta_box.payload())?;
+    let mut payload = Vec::with_capacity(udta.len());
+    for child in &children {
+        if child.fourcc != UDTA_LOCATION {
+            payload.extend_from_slice(child.bytes);
+        }
+    }
+    // Keep a trailing terminator (four zero bytes in QuickTime files)
+    let consumed: usize = children.iter().map(|c| c.bytes.len()).sum();
+    payload.extend_from_slice(&udta_box.payload()[consumed..]);
+    remake_box(&udta_box, &payload)
+}
+
+/// A `trak` box with the location removed from its `udta`
+fn strip_trak_location(trak: &RawBox<'_>) -> Result<Vec<u8>> {
+    let mut payload = Vec::with_capacity(trak.bytes.len());
+    for child in split_boxes(trak.payload())? {
+        if &child.fourcc == b"udta" {
+            payload.extend(strip_track_location(child.bytes)?);
+        } else {
+            payload.extend_from_slice(child.bytes);
+        }
+    }
+    remake_box(trak, &payload)
+}
+
+/// Rebuild a box around a new payload, keeping its header length
+fn remake_box(original: &RawBox<'_>, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(payload.len() + original.header_len);
+    let size = (payload.len() + original.header_len) as u64;
+    if original.header_len == 16 {
+        out.extend_from_slice(&1u32.to_be_bytes());
+        out.extend_from_slice(&original.fourcc);
+        out.extend_from_slice(&size.to_be_bytes());
+    } else {
+        let size = u32::try_from(size).map_err(|_| {
+            Error::CapacityExceeded(format!(
+                "{} box too large",
+                String::from_utf8_lossy(&original.fourcc)
+            ))
+        })?;
+        out.extend_from_slice(&size.to_be_bytes());
+        out.extend_from_slice(&original.fourcc);
+    }
+    out.extend_from_slice(payload);
+    Ok(out)
+}
+
+/// Apply edits to a complete `moov` box
+///
+/// The `udta` and `meta` children are replaced in place, or appended when they
+/// are created. Removing the location also strips `©xyz` from the `udta` of
+/// every `trak`. Box headers keep their original length so that callers can
+/// predict the new layout from the sizes of the edited `udta` and `meta` boxes
+/// alone.
+pub(crate) fn edit_moov(moov: &[u8], edits: &[QuickTimeEdit]) -> Result<Vec<u8>> {
+    if edits.is_empty() {
+        return Ok(moov.to_vec());
+    }
+    let moov_box = single_box(moov)?;
+    let children = split_boxes(moov_box.payload())?;
+    let udta = children.iter().find(|b| &b.fourcc == b"udta");
+    let meta = children.iter().find(|b| &b.fourcc == b"meta");
+    let (new_udta, new_meta) = apply_edits(udta.map(|b| b.bytes), meta.map(|b| b.bytes), edits)?;
+    let remove_location = edits.contains(&QuickTimeEdit::RemoveLocation);
+
+    let mut payload = Vec::with_capacity(moov.len());
+    for child in &children {
+        if udta.is_some_and(|b| std::ptr::eq(b.bytes, child.bytes)) {
+            payload.extend(new_udta.iter().flatten());
+        } else if meta.is_some_and(|b| std::ptr::eq(b.bytes, child.bytes)) {
+            payload.extend(new_meta.iter().flatten());
+        } else if remove_location && &child.fourcc == b"trak" {
+            payload.extend(strip_trak_location(child)?);
+        } else {
+            payload.extend_from_slice(child.bytes);
+        }
+    }
+    if udta.is_none() {
+        payload.extend(new_udta.iter().flatten());
+    }
+    if meta.is_none() {
+        payload.extend(new_meta.iter().flatten());
+    }
+    remake_box(&moov_box, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(key: QuickTimeKey, text: &str) -> QuickTimeEdit {
+        QuickTimeEdit::Set(key, QuickTimeValue::Text(text.into()))
+    }
+
+    #[test]
+    fn test_create_and_parse() {
+        let edits = [
+            set(QuickTimeKey::TITLE, "Title"),
+            set(
+                QuickTimeKey::mdta(QUICKTIME_LOCATION_KEY),
+                "+37.3349-122.0090/",
+            ),
+            set(
+                QuickTimeKey::mdta(QUICKTIME_CREATION_DATE_KEY),
+                "2024-05-01T10:00:00Z",
+            ),
+            QuickTimeEdit::Set(QuickTimeKey::Item(*b"tmpo"), QuickTimeValue::Integer(120)),
+        ];
+        let (udta, meta) = apply_edits(None, None, &edits).unwrap();
+        let parsed = parse(udta.as_deref(), meta.as_deref()).unwrap();
+
+        assert_eq!(parsed.len(), 4);
+        assert_eq!(parsed.text(&QuickTimeKey::TITLE), Some("Title"));
+        assert_eq!(parsed.location(), Some("+37.3349-122.0090/"));
+        assert_eq!(parsed.creation_date(), Some("2024-05-01T10:00:00Z"));
+        assert_eq!(
+            parsed.get(&QuickTimeKey::Item(*b"tmpo")),
+            Some(&QuickTimeValue::Integer(120))
+        );
+    }
+
+    #[test]
+    fn test_replace_and_remove_location() {
+        let (udta, meta) = apply_edits(
+            None,
+            None,
+            &[
+                set(QuickTimeKey::Udta(UDTA_LOCATION), "+01.0000+002.0000/"),
+                set(
+                    QuickTimeKey::mdta(QUICKTIME_LOCATION_KEY),
+                    "+01.0000+002.0000/",
+                ),
+                set(QuickTimeKey::mdta("com.apple.quicktime.make"), "Apple"),
+            ],
+        )
+        .unwrap();
+
+        let (udta, meta) = apply_edits(
+            udta.as_deref(),
+            meta.as_deref(),
+            &[QuickTimeEdit::RemoveLocation],
+        )
+        .unwrap();
+        let parsed = parse(udta.as_deref(), meta.as_deref()).unwrap();
+        assert_eq!(parsed.location(), None);
+        assert_eq!(
+            parsed.text(&QuickTimeKey::mdta("com.apple.quicktime.make")),
+            Some("Apple")
+        );
+        assert_eq!(parsed.len(), 1);
+    }
+
+    #[test]
+    fn test_edit_moov_strips_track_location() {
+        let mut xyz = vec![0, 18, 0x15, 0xc7];
+        xyz.extend_from_slice(b"+01.0000+002.0000/");
+        let mut udta = make_box(&UDTA_LOCATION, &xyz);
+        udta.extend(make_box(b"name", b"track"));
+        udta.extend_from_slice(&[0, 0, 0, 0]);
+        let mdia = make_box(b"mdia", &[0u8; 16]);
+        let mut trak = mdia.clone();
+        trak.extend(make_box(b"udta", &udta));
+        let moov = make_box(b"moov", &make_box(b"trak", &trak));
+
+        let edited = edit_moov(&moov, &[QuickTimeEdit::RemoveLocation]).unwrap();
+        let moov_box = single_box(&edited).unwrap();
+        assert_eq!(moov_box.bytes.len(), edited.len());
+        let trak = split_boxes(moov_box.payload()).unwrap()[0];
+        assert_eq!(trak.bytes.len(), moov_box.payload().len());
+        let children = split_boxes(trak.payload()).unwrap();
+        assert_eq!(children[0].bytes, mdia.as_slice());
+
+        let mut expected = make_box(b"name", b"track");
+        expected.extend_from_slice(&[0, 0, 0, 0]);
+        assert_eq!(children[1].payload(), expected.as_slice());
+
+        // Other edits leave tracks alone
+        let titled = edit_moov(&moov, &[set(QuickTimeKey::TITLE, "Movie")]).unwrap();
+        assert!(titled.windows(4).any(|w| w == UDTA_LOCATION));
+    }
+
+    #[test]
+    fn test_edit_moov_appends_children() {
+        let mvhd = make_full_box(b"mvhd", &[0u8; 96]);
+        let moov = make_box(b"moov", &mvhd);
+
+        let edited = edit_moov(&moov, &[set(QuickTimeKey::TITLE, "Movie")]).unwrap();
+        let moov_box = single_box(&edited).unwrap();
+        assert_eq!(moov_box.bytes.len(), edited.len());
+        let children = split_boxes(moov_box.payload()).unwrap();
+        assert_eq!(children[0].bytes, mvhd.as_slice());
+        assert_eq!(&children[1].fourcc, b"udta");
+
+        let parsed = parse(Some(children[1].bytes), None).unwrap();
+        assert_eq!(parsed.text(&QuickTimeKey::TITLE), Some("Movie"));
+        assert_eq!(edit_moov(&moov, &[]).unwrap(), moov);
+    }
+}
@@ -23,30 +23,82 @@
 //! end of the RIFF chunk for maximum compatibility (per the c2pa-rs convention).
 //! XMP is stored in a `"XMP "` chunk, also appended before the C2PA chunk.
 //!
+//! # WebP Extended Format
+//!
+//! A simple WebP file holds a single `VP8 ` or `VP8L` chunk and may not carry
+//! metadata. When XMP or C2PA is written to one, it is converted to the
+//! extended format by synthesizing a `VP8X` chunk whose canvas size comes from
+//! the bitstream header. Extended files are written in the order the container
+//! spec requires (`VP8X`, `ICCP`, `ANIM`, image data and unknown chunks,
+//! `EXIF`, `XMP `), and the `VP8X` ICC/EXIF/XMP/animation flags are recomputed
+//! from the chunks actually written.
+//!
+//! # WAV Metadata
+//!
+//! The Broadcast WAV `bext` chunk, `iXML` and the `LIST/INFO` list are loaded
+//! during parse and can be rewritten through [`Updates`]. Edited chunks are
+//! replaced in place; new ones are inserted before the `data` chunk, so the
+//! RIFF size is computed for the final layout in the same streaming pass.
+//!
+//! # RF64 / BW64
+//!
+//! WAV files over 4 GB use an `RF64` (or `BW64`) signature with the RIFF size
+//! field set to `0xFFFFFFFF`; the real RIFF and `data` sizes, and those of any
+//! other oversized chunk, are held in a `ds64` chunk that must come first.
+//! Writes keep the `ds64` up to date, and upgrade a plain `RIFF` file to `RF64`
+//! when the output would exceed 4 GB. A leading `JUNK` placeholder is turned
+//! into the `ds64` chunk so that no other chunk moves.
+//!
+//! # AVI OpenDML
+//!
+//! AVI files over 1 GB continue in `RIFF AVIX` chunks after the main `RIFF AVI `
+//! chunk; each becomes an `AVIX` segment and is written after it unchanged.
+//! XMP and C2PA are appended to the main chunk, which moves the `AVIX` chunks,
+//! so the absolute offsets in `indx` super indexes and `ix##` standard indexes
+//! (and in `idx1`, for files that use absolute offsets) are rewritten to follow
+//! the data they point to. See [`avi_index`].
+//!
+//! The lists of an AVI file are recorded as segments too, with paths of list
+//! types: `AVI/hdrl` and its `AVI/hdrl/strl` and `AVI/hdrl/odml` lists,
+//! `AVI/movi`, and `AVIX/movi` for each extension chunk. They overlap the
+//! top-level chunk segments that hold them.
+//!
 //! # Security
 //!
-//! - Chunk sizes are capped at [`MAX_RIFF_CHUNK_ALLOC`] (256 MB) to prevent OOM attacks.
+//! - Chunks loaded into memory are capped at the segment size limit (256 MB by
+//!   default, see [`AssetBuilder`](crate::AssetBuilder)) to prevent OOM attacks.
 //! - File size is validated against the RIFF header's declared size.
 //! - All arithmetic uses checked or saturating operations.
 
+use super::avi_index::{self, AviIndex};
+use super::wav_meta::{self, BextChunk, ChunkChange, WavMetadata};
 use super::{ContainerIO, ContainerKind};
 use crate::{
+    diagnostic::{Diagnostic, ValidationReport},
     error::{Error, Result},
-    segment::{ByteRange, Segment, SegmentKind, MAX_SEGMENT_SIZE},
+    segment::{ByteRange, LazyData, Segment, SegmentKind},
     structure::Structure,
-    MediaType, Updates,
+    Candidate, Confidence, Limits, MediaType, Updates,
 };
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{Read, Seek, SeekFrom, Write};
 
-/// Maximum allocation for a single RIFF chunk (256 MB)
-///
-/// Prevents OOM attacks from maliciously crafted files claiming giant chunk sizes.
-const MAX_RIFF_CHUNK_ALLOC: u64 = 256 * 1024 * 1024;
-
 // Chunk IDs (4-byte FourCC codes, in spec-correct byte order)
 const C2PA_CHUNK_ID: &[u8; 4] = b"C2PA";
 const XMP_CHUNK_ID: &[u8; 4] = b"XMP ";
+const DS64_CHUNK_ID: &[u8; 4] = b"ds64";
+
+// Root signatures: plain RIFF, and the 64-bit RF64 (EBU Tech 3306) and BW64 (ITU-R BS.2088)
+const RIFF_SIGNATURES: [&[u8; 4]; 3] = [b"RIFF", b"RF64", b"BW64"];
+
+/// 32-bit size value meaning "see the ds64 chunk"
+const SIZE_IN_DS64: u64 = 0xFFFF_FFFF;
+
+/// Largest size a 32-bit RIFF size field can hold
+const MAX_RIFF_SIZE: u64 = 0xFFFF_FFFE;
+
+/// ds64 data without a table: RIFF size, data size, sample count (u64 each) + table length
+const DS64_MIN_DATA_SIZE: u64 = 28;
 
 // Top-level RIFF format codes (bytes 8–11 of file)
 const WEBP_FORMAT: &[u8; 4] = b"WEBP";
@@ -54,30 +106,49 @@ const WAVE_FORMAT: &[u8; 4] = b"WAVE";
 const AVI_FORMAT: &[u8; 4] = b"AVI ";
 
 // VP8X feature flags (LE uint32 at bytes 0–3 of the VP8X chunk data)
+const VP8X_ANIMATION_FLAG: u32 = 0x0002; // bit 1 = ANIM/ANMF chunks present
 const VP8X_XMP_FLAG: u32 = 0x0004; // bit 2 = XMP metadata present
+const VP8X_EXIF_FLAG: u32 = 0x0008; // bit 3 = EXIF metadata present
+const VP8X_ALPHA_FLAG: u32 = 0x0010; // bit 4 = alpha channel present
+const VP8X_ICC_FLAG: u32 = 0x0020; // bit 5 = ICCP color profile present
 
 // VP8X chunk data must be exactly 10 bytes
 const VP8X_DATA_SIZE: u64 = 10;
 
 /// RIFF container I/O implementation
-pub struct RiffIO;
+pub struct RiffIO {
+    limits: Limits,
+}
 
 impl RiffIO {
     /// Create a new RIFF I/O implementation
     pub fn new() -> Self {
-        Self
+        Self {
+            limits: Limits::default(),
+        }
+    }
+
+    /// Replace the limits enforced while parsing and reading
+    pub(crate) fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
     }
 
     /// Detect media type from the RIFF format code (bytes 8–11)
     fn detect_media_type(format: &[u8; 4]) -> MediaType {
+        Self::media_type_for_format(format).unwrap_or(MediaType::Wav) // conservative fallback
+    }
+
+    /// Media type a RIFF format code names, if it is one we know
+    fn media_type_for_format(format: &[u8; 4]) -> Option<MediaType> {
         if format == WEBP_FORMAT {
-            MediaType::WebP
+            Some(MediaType::WebP)
         } else if format == WAVE_FORMAT {
-            MediaType::Wav
+            Some(MediaType::Wav)
         } else if format == AVI_FORMAT {
-            MediaType::Avi
+            Some(MediaType::Avi)
         } else {
-            MediaType::Wav // conservative fallback
+            None
         }
     }
 
@@ -104,25 +175,460 @@ impl RiffIO {
         Ok(())
     }
 
+    /// Read up to `max` bytes of a top-level chunk's data
+    fn read_chunk_data<R: Read + Seek>(
+        source: &mut R,
+        segment: &Segment,
+        max: u64,
+    ) -> Result<Vec<u8>> {
+        let offset = segment.location().offset;
+        source.seek(SeekFrom::Start(offset + 4))?;
+        let size = (source.read_u32::<LittleEndian>()? as u64).min(max);
+        let mut data = vec![0u8; size as usize];
+        source.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// First top-level chunk with the given id
+    fn find_chunk<'a>(structure: &'a Structure, id: &[u8; 4]) -> Option<&'a Segment> {
+        structure
+            .segments()
+            .iter()
+            .find(|s| s.path.as_deref().map(str::as_bytes) == Some(&id[..]))
+    }
+
+    /// Parse the OpenDML `RIFF AVIX` chunks that follow the main `RIFF AVI `
+    /// chunk and the nested lists, and record the index offsets that writes
+    /// must keep valid
+    ///
+    /// Each `AVIX` chunk becomes one segment, as does each list (see
+    /// [`add_avi_lists`](Self::add_avi_lists)). Returns the end of the last
+    /// `AVIX` chunk.
+    fn parse_avi_extensions<R: Read + Seek>(
+        &self,
+        source: &mut R,
+        structure: &mut Structure,
+        riff_end: u64,
+    ) -> Result<u64> {
+        let file_len = source.seek(SeekFrom::End(0))?;
+        let mut offset = riff_end;
+        while offset + 12 <= file_len {
+            source.seek(SeekFrom::Start(offset))?;
+            let mut header = [0u8; 12];
+            source.read_exact(&mut header)?;
+            let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as u64;
+            let total = Self::chunk_on_disk(size);
+            if &header[0..4] != b"RIFF" || &header[8..12] != b"AVIX" || offset + total > file_len {
+                break;
+            }
+            structure.add_segment(Segment::new(
+                offset,
+                total,
+                SegmentKind::Other,
+                Some("AVIX".to_string()),
+            ));
+            offset += total;
+        }
+
+        Self::add_avi_lists(source, structure)?;
+        let list = |path: &str| {
+            structure
+                .segments()
+                .iter()
+                .find(|s| s.path.as_deref() == Some(path))
+                .map(|s| s.location().offset)
+        };
+        let idx1 = Self::find_chunk(structure, b"idx1").map(|s| s.location().offset);
+        let index = match AviIndex::scan(
+            source,
+            list("AVI/hdrl"),
+            list("AVI/movi"),
+            idx1,
+            file_len,
+            &self.limits,
+        ) {
+            // Without the index, offsets are left as they are on write
+            Err(error @ Error::InvalidSegment { .. }) => {
+                self.limits.recover(structure, "AVI/hdrl", error)?;
+                AviIndex::default()
+            }
+            index => index?,
+        };
+        log::debug!("parse: {} AVI index offset fields", index.fields.len());
+        structure.set_avi_index(index);
+        Ok(offset)
+    }
+
+    /// Add a segment for each AVI list, with its path of list types
+    ///
+    /// These are the top-level lists of the main chunk (`AVI/hdrl`, `AVI/movi`,
+    /// ...), the lists inside `hdrl` (`AVI/hdrl/strl`, `AVI/hdrl/odml`) and the
+    /// lists of each `AVIX` chunk (`AVIX/movi`). They overlap the top-level
+    /// chunk segments that hold them; `movi` is not descended into.
+    fn add_avi_lists<R: Read + Seek>(source: &mut R, structure: &mut Structure) -> Result<()> {
+        let mut lists = Vec::new();
+        for seg in structure.segments() {
+            let location = seg.location();
+            let end = location.offset + location.size;
+            match seg.path.as_deref() {
+                Some("LIST") => {
+                    source.seek(SeekFrom::Start(location.offset + 8))?;
+                    let mut list_type = [0u8; 4];
+                    source.read_exact(&mut list_type)?;
+                    let path = format!("AVI/{}", String::from_utf8_lossy(&list_type));
+                    lists.push((location, path.clone()));
+                    if &list_type == b"hdrl" {
+                        Self::list_children(source, location.offset + 12, end, &path, &mut lists)?;
+                    }
+                }
+                Some("AVIX") => {
+                    Self::list_children(source, location.offset + 12, end, "AVIX", &mut lists)?;
+                }
+                _ => {}
+            }
+        }
+        for (location, path) in lists {
+            structure.add_segment(Segment::new(
+                location.offset,
+                location.size,
+                SegmentKind::Other,
+                Some(path),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Collect the `LIST` chunks in `[start, end)` as `parent/list type`
+    fn list_children<R: Read + Seek>(
+        source: &mut R,
+        start: u64,
+        end: u64,
+        parent: &str,
+        lists: &mut Vec<(ByteRange, String)>,
+    ) -> Result<()> {
+        let mut offset = start;
+        while offset + 12 <= end {
+            source.seek(SeekFrom::Start(offset))?;
+            let mut header = [0u8; 12];
+            source.read_exact(&mut header)?;
+            let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as u64;
+            let total = Self::chunk_on_disk(size);
+            if offset + total > end {
+                break;
+            }
+            if &header[0..4] == b"LIST" {
+                lists.push((
+                    ByteRange::new(offset, total),
+                    format!("{}/{}", parent, String::from_utf8_lossy(&header[8..12])),
+                ));
+            }
+            offset += total;
+        }
+        Ok(())
+    }
+
+    /// Whether a segment is one of the nested AVI lists, which are written as
+    /// part of the chunk that holds them
+    fn is_avi_list(segment: &Segment) -> bool {
+        segment
+            .path
+            .as_deref()
+            .is_some_and(|p| p.starts_with("AVI/") || p.starts_with("AVIX/"))
+    }
+
+    /// Data of a chunk loaded during parse (without header or padding)
+    fn loaded_chunk_data(segment: &Segment) -> Option<&[u8]> {
+        match &segment.data {
+            LazyData::Loaded(chunk) if chunk.len() >= 8 => {
+                let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+                chunk.get(8..8 + size)
+            }
+            _ => None,
+        }
+    }
+
+    /// The WAV metadata chunk a segment holds: `bext`, `iXML` or `LIST/INFO`
+    fn wav_metadata_chunk(segment: &Segment) -> Option<&'static [u8; 4]> {
+        let data = Self::loaded_chunk_data(segment)?;
+        match segment.path.as_deref() {
+            Some("bext") => Some(b"bext"),
+            Some("iXML") => Some(b"iXML"),
+            Some("LIST") if data.starts_with(b"INFO") => Some(b"LIST"),
+            _ => None,
+        }
+    }
+
+    /// Read the `bext`, `iXML` and `LIST/INFO` chunks of a WAV file
+    pub(crate) fn read_wav_metadata(structure: &Structure) -> Result<Option<WavMetadata>> {
+        if structure.media_type != MediaType::Wav {
+            return Ok(None);
+        }
+        let mut metadata = WavMetadata::default();
+        for segment in structure.segments() {
+            let (Some(id), Some(data)) = (
+                Self::wav_metadata_chunk(segment),
+                Self::loaded_chunk_data(segment),
+            ) else {
+                continue;
+            };
+            match id {
+                b"bext" if metadata.bext.is_none() => {
+                    metadata.bext = Some(BextChunk::parse(data)?);
+                }
+                b"iXML" if metadata.ixml.is_none() => {
+                    metadata.ixml = Some(wav_meta::nul_terminated(data));
+                }
+                b"LIST" => metadata.info.extend(wav_meta::parse_info(data)?),
+                _ => {}
+            }
+        }
+        Ok(Some(metadata))
+    }
+
+    /// WebP canvas size from VP8X, or the frame size from the VP8/VP8L bitstream header;
+    /// frame count and total duration from the ANMF frames of an animation
+    fn webp_media_info<R: Read + Seek>(
+        structure: &Structure,
+        source: &mut R,
+        info: &mut crate::MediaInfo,
+    ) -> Result<()> {
+        let image = structure
+            .segments()
+            .iter()
+            .find(|s| s.is_type(SegmentKind::ImageData));
+        if let Some(path) = image.and_then(|s| s.path.as_deref()) {
+            info.codec = Some(path.trim_end().to_string());
+        }
+
+        let frames: Vec<&Segment> = structure
+            .segments()
+            .iter()
+            .filter(|s| s.path.as_deref() == Some("ANMF"))
+            .collect();
+        if !frames.is_empty() {
+            // X(3) + Y(3) + width - 1 (3) + height - 1 (3) + duration ms (3) + flags(1),
+            // then the frame's own chunks
+            let mut total_ms = 0u64;
+            for (i, frame) in frames.iter().enumerate() {
+                let data = Self::read_chunk_data(source, frame, 20)?;
+                if data.len() < 16 {
+                    return Err(Error::InvalidFormat("ANMF chunk too small".into()));
+                }
+                total_ms += u32::from_le_bytes([data[12], data[13], data[14], 0]) as u64;
+                if i == 0 && info.codec.is_none() && data.len() >= 20 {
+                    info.codec = Some(String::from_utf8_lossy(&data[16..20]).trim_end().into());
+                }
+            }
+            info.frame_count = Some(frames.len() as u32);
+            info.duration = Some(std::time::Duration::from_millis(total_ms));
+        }
+
+        if let Some(vp8x) = Self::find_chunk(structure, b"VP8X") {
+            // flags(1) + reserved(3) + canvas width - 1 (24 LE) + canvas height - 1 (24 LE)
+            let data = Self::read_chunk_data(source, vp8x, VP8X_DATA_SIZE)?;
+            if data.len() == VP8X_DATA_SIZE as usize {
+                let w = u32::from_le_bytes([data[4], data[5], data[6], 0]) + 1;
+                let h = u32::from_le_bytes([data[7], data[8], data[9], 0]) + 1;
+                info.width = Some(w);
+                info.height = Some(h);
+                return Ok(());
+            }
+        }
+
+        if let Some(image) = image {
+            if let Some((width, height, _)) = Self::bitstream_canvas(source, image)? {
+                info.width = Some(width);
+                info.height = Some(height);
+            }
+        }
+        Ok(())
+    }
+
+    /// WAV audio format from the `fmt ` chunk; duration from the `data` chunk size
+    fn wav_media_info<R: Read + Seek>(
+        structure: &Structure,
+        source: &mut R,
+        info: &mut crate::MediaInfo,
+    ) -> Result<()> {
+        let Some(fmt) = Self::find_chunk(structure, b"fmt ") else {
+            return Ok(());
+        };
+        // format tag(2) + channels(2) + sample rate(4) + byte rate(4) + block align(2)
+        // + bits per sample(2) [+ cbSize(2) + valid bits(2) + channel mask(4) + sub-format GUID]
+        let data = Self::read_chunk_data(source, fmt, 40)?;
+        if data.len() < 16 {
+            return Err(Error::InvalidFormat("WAV fmt chunk too small".into()));
+        }
+        let mut format_tag = u16::from_le_bytes([data[0], data[1]]);
+        if format_tag == 0xFFFE && data.len() >= 26 {
+            // WAVE_FORMAT_EXTENSIBLE: the real format is the first two bytes of the sub-format GUID
+            format_tag = u16::from_le_bytes([data[24], data[25]]);
+        }
+        let byte_rate = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+
+        info.channels = Some(u16::from_le_bytes([data[2], data[3]]));
+        info.sample_rate = Some(u32::from_le_bytes([data[4], data[5], data[6], data[7]]));
+        info.bit_depth = Some(u16::from_le_bytes([data[14], data[15]]));
+        info.codec = Some(match format_tag {
+            0x0001 => "pcm".to_string(),
+            0x0003 => "float".to_string(),
+            0x0006 => "alaw".to_string(),
+            0x0007 => "mulaw".to_string(),
+            0x0055 => "mp3".to_string(),
+            other => format!("0x{:04x}", other),
+        });
+
+        if let Some(audio) = Self::find_chunk(structure, b"data") {
+            source.seek(SeekFrom::Start(audio.location().offset + 4))?;
+            let data_size = source.read_u32::<LittleEndian>()? as u64;
+            info.duration = crate::media_info::duration_from_units(data_size, byte_rate);
+        }
+        Ok(())
+    }
+
+    /// AVI dimensions, frame count and duration from the `avih` main header in `LIST hdrl`
+    fn avi_media_info<R: Read + Seek>(
+        structure: &Structure,
+        source: &mut R,
+        info: &mut crate::MediaInfo,
+    ) -> Result<()> {
+        let Some(hdrl) = structure
+            .segments()
+            .iter()
+            .find(|s| s.path.as_deref() == Some("AVI/hdrl"))
+        else {
+            return Ok(());
+        };
+        // list type(4) + "avih"(4) + size(4) + 56-byte main header
+        let data = Self::read_chunk_data(source, hdrl, 4 + 8 + 56)?;
+        if data.len() < 4 + 8 + 40 || &data[4..8] != b"avih" {
+            return Ok(());
+        }
+        let avih = &data[12..];
+        let field = |i: usize| u32::from_le_bytes([avih[i], avih[i + 1], avih[i + 2], avih[i + 3]]);
+
+        let usec_per_frame = field(0);
+        let total_frames = field(16);
+        info.frame_count = Some(total_frames);
+        info.width = Some(field(32));
+        info.height = Some(field(36));
+        if usec_per_frame > 0 {
+            info.duration = crate::media_info::duration_from_units(
+                total_frames as u64 * usec_per_frame as u64,
+                1_000_000,
+            );
+        }
+        Ok(())
+    }
+
+    /// Capture date candidates from the `IDIT` chunks of `LIST hdrl` (AVI) and
+    /// the `ICRD` entries of `LIST INFO`, each with the id of its chunk
+    ///
+    /// All `IDIT` values come before all `ICRD` values, so callers can take the
+    /// first one that parses.
+    pub(crate) fn read_dates<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+    ) -> Result<Vec<([u8; 4], String)>> {
+        let mut idit = Vec::new();
+        let mut icrd = Vec::new();
+        for list in structure
+            .segments()
+            .iter()
+            .filter(|s| s.path.as_deref() == Some("LIST"))
+        {
+            source.seek(SeekFrom::Start(list.location().offset + 8))?;
+            let mut list_type = [0u8; 4];
+            source.read_exact(&mut list_type)?;
+            if &list_type != b"hdrl" && &list_type != b"INFO" {
+                continue;
+            }
+            let data = Self::read_chunk_data(source, list, self.limits.max_segment_size)?;
+
+            // Sub-chunks follow the 4-byte list type
+            let mut pos = 4;
+            while pos + 8 <= data.len() {
+                let id: [u8; 4] = data[pos..pos + 4].try_into().unwrap();
+                let size = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+                let Some(value) = data.get(pos + 8..pos + 8 + size) else {
+                    break;
+                };
+                let text = || {
+                    String::from_utf8_lossy(value)
+                        .trim_end_matches('\0')
+                        .trim()
+                        .to_string()
+                };
+                match &id {
+                    b"IDIT" => idit.push((id, text())),
+                    b"ICRD" => icrd.push((id, text())),
+                    _ => {}
+                }
+                pos += 8 + size + (size % 2);
+            }
+        }
+        idit.extend(icrd);
+        Ok(idit)
+    }
+
     /// Parse the RIFF file structure in a single sequential pass
     fn parse_impl<R: Read + Seek>(&self, source: &mut R) -> Result<Structure> {
+        let mut at = (0, None);
+        self.parse_chunks(source, &mut at).map_err(|e| match at {
+            (offset, Some(id)) => e.at(offset, format!("RIFF/{}", String::from_utf8_lossy(&id))),
+            (offset, None) => e.at(offset, "RIFF"),
+        })
+    }
+
+    /// Parse the chunks, keeping `at` on the offset and id of the chunk being parsed
+    fn parse_chunks<R: Read + Seek>(
+        &self,
+        source: &mut R,
+        at: &mut (u64, Option<[u8; 4]>),
+    ) -> Result<Structure> {
         source.seek(SeekFrom::Start(0))?;
 
         // Validate RIFF signature
         let mut sig = [0u8; 4];
         source.read_exact(&mut sig)?;
-        if &sig != b"RIFF" {
+        if !RIFF_SIGNATURES.contains(&&sig) {
             return Err(Error::InvalidFormat("Not a RIFF file".into()));
         }
 
         // Read declared data size (total file - 8 bytes for root chunk header)
-        let riff_data_size = source.read_u32::<LittleEndian>()? as u64;
-        let declared_end = riff_data_size.saturating_add(8);
+        let mut riff_data_size = source.read_u32::<LittleEndian>()? as u64;
 
         // Read format code (bytes 8–11) to determine media type
         let mut format = [0u8; 4];
         source.read_exact(&mut format)?;
 
+        // RF64/BW64 keep the real sizes in a leading ds64 chunk
+        let ds64 = if &sig == b"RIFF" {
+            None
+        } else {
+            let mut header = [0u8; 8];
+            source.read_exact(&mut header)?;
+            let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as u64;
+            if &header[0..4] != DS64_CHUNK_ID || size > self.limits.max_segment_size {
+                return Err(Error::InvalidSegment {
+                    offset: 12,
+                    reason: format!(
+                        "{} file must start with a ds64 chunk",
+                        String::from_utf8_lossy(&sig)
+                    ),
+                });
+            }
+            let mut data = vec![0u8; size as usize];
+            source.read_exact(&mut data)?;
+            let ds64 = Ds64::parse(&data)?;
+            if riff_data_size == SIZE_IN_DS64 {
+                riff_data_size = ds64.riff_size;
+            }
+            Some(ds64)
+        };
+        let declared_end = riff_data_size.saturating_add(8);
+
         let media_type = Self::detect_media_type(&format);
         let mut structure = Structure::new(ContainerKind::Riff, media_type);
 
@@ -130,6 +636,16 @@ impl RiffIO {
         let actual_end = {
             let pos = source.seek(SeekFrom::End(0))?;
             source.seek(SeekFrom::Start(12))?;
+            if declared_end > pos {
+                structure.add_diagnostic(Diagnostic::warning(
+                    4,
+                    "RIFF",
+                    format!(
+                        "RIFF size {} runs past the end of the file ({} bytes); writes correct it",
+                        riff_data_size, pos
+                    ),
+                ));
+            }
             pos.min(declared_end)
         };
 
@@ -151,6 +667,8 @@ impl RiffIO {
         // Walk direct child chunks sequentially
         let mut offset = 12u64;
         while offset + 8 <= actual_end {
+            self.limits.check_segment_count(&structure)?;
+
             // Read chunk ID
             let mut chunk_id = [0u8; 4];
             match source.read_exact(&mut chunk_id) {
@@ -158,10 +676,22 @@ impl RiffIO {
                 Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
                 Err(e) => return Err(e.into()),
             }
+            *at = (offset, Some(chunk_id));
 
-            // Read chunk data size (LE u32)
+            // Read chunk data size (LE u32, or 64-bit from ds64)
             let data_size = match source.read_u32::<LittleEndian>() {
-                Ok(s) => s as u64,
+                Ok(s) => match &ds64 {
+                    Some(ds64) if s as u64 == SIZE_IN_DS64 => ds64
+                        .chunk_size(&chunk_id)
+                        .ok_or_else(|| Error::InvalidSegment {
+                            offset,
+                            reason: format!(
+                                "chunk '{}' has no size in ds64",
+                                String::from_utf8_lossy(&chunk_id)
+                            ),
+                        })?,
+                    _ => s as u64,
+                },
                 Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
                 Err(e) => return Err(e.into()),
             };
@@ -201,22 +731,22 @@ impl RiffIO {
                     padded_data_size,
                     data_offset.saturating_add(padded_data_size)
                 );
+                structure.add_diagnostic(Diagnostic::warning(
+                    offset,
+                    format!("RIFF/{}", String::from_utf8_lossy(&chunk_id)),
+                    format!(
+                        "chunk runs past the end of the file ({} bytes declared); it and anything after it are dropped",
+                        data_size
+                    ),
+                ));
                 break;
             }
 
             match &chunk_id {
                 b"C2PA" => {
                     // Security: C2PA data is loaded entirely into RAM — cap it.
-                    if data_size > MAX_RIFF_CHUNK_ALLOC {
-                        return Err(Error::InvalidSegment {
-                            offset,
-                            reason: format!(
-                                "C2PA chunk too large: {} bytes (max {} MB)",
-                                data_size,
-                                MAX_RIFF_CHUNK_ALLOC / (1024 * 1024)
-                            ),
-                        });
-                    }
+                    self.limits
+                        .check_segment_size(offset, data_size, "C2PA chunk")?;
                     log::debug!(
                         "parse: found C2PA chunk data_offset={} data_size={}",
                         data_offset,
@@ -231,16 +761,8 @@ impl RiffIO {
                 }
                 b"XMP " => {
                     // Security: XMP data is loaded entirely into RAM — cap it.
-                    if data_size > MAX_RIFF_CHUNK_ALLOC {
-                        return Err(Error::InvalidSegment {
-                            offset,
-                            reason: format!(
-                                "XMP chunk too large: {} bytes (max {} MB)",
-                                data_size,
-                                MAX_RIFF_CHUNK_ALLOC / (1024 * 1024)
-                            ),
-                        });
-                    }
+                    self.limits
+                        .check_segment_size(offset, data_size, "XMP chunk")?;
                     log::debug!(
                         "parse: found XMP chunk data_offset={} data_size={}",
                         data_offset,
@@ -253,6 +775,16 @@ impl RiffIO {
                     )?);
                     source.seek(SeekFrom::Current(padded_data_size as i64))?;
                 }
+                b"EXIF" if media_type == MediaType::WebP => {
+                    // WebP EXIF chunk (raw TIFF data); the segment covers the whole chunk
+                    structure.add_segment(Segment::new(
+                        offset,
+                        chunk_total,
+                        SegmentKind::Exif,
+                        Some("EXIF".to_string()),
+                    ));
+                    source.seek(SeekFrom::Current(padded_data_size as i64))?;
+                }
                 b"VP8 " | b"VP8L" => {
                     // WebP image data (lossy VP8 or lossless VP8L)
                     let path = String::from_utf8_lossy(&chunk_id).into_owned();
@@ -264,6 +796,32 @@ impl RiffIO {
                     ));
                     source.seek(SeekFrom::Current(padded_data_size as i64))?;
                 }
+                b"bext" | b"iXML" | b"LIST" if media_type == MediaType::Wav => {
+                    // WAV descriptive metadata is small and rewritten in memory, so
+                    // load it; other LIST types (e.g. adtl) are only skipped.
+                    let mut list_type = [0u8; 4];
+                    if &chunk_id == b"LIST" && data_size >= 4 {
+                        source.read_exact(&mut list_type)?;
+                        source.seek(SeekFrom::Start(data_offset))?;
+                    }
+                    let path = String::from_utf8_lossy(&chunk_id).into_owned();
+                    let mut segment =
+                        Segment::new(offset, chunk_total, SegmentKind::Other, Some(path));
+                    if &chunk_id != b"LIST" || &list_type == b"INFO" {
+                        self.limits.check_segment_size(
+                            offset,
+                            data_size,
+                            &format!("{} chunk", String::from_utf8_lossy(&chunk_id)),
+                        )?;
+                        source.seek(SeekFrom::Start(offset))?;
+                        let mut chunk = vec![0u8; chunk_total as usize];
+                        source.read_exact(&mut chunk)?;
+                        segment = segment.with_data(LazyData::Loaded(chunk));
+                    } else {
+                        source.seek(SeekFrom::Current(padded_data_size as i64))?;
+                    }
+                    structure.add_segment(segment);
+                }
                 _ => {
                     // All other chunks (LIST/movi, idx1, JUNK, audio/video frames, etc.)
                     // are never loaded into memory — only seeked past during parse and
@@ -282,23 +840,140 @@ impl RiffIO {
             offset += chunk_total;
         }
 
+        let mut total_size = actual_end;
+        if media_type == MediaType::Avi {
+            *at = (actual_end, None);
+            total_size = self.parse_avi_extensions(source, &mut structure, actual_end)?;
+        }
+
         log::debug!(
             "parse: done, {} segments, total_size={}",
             structure.segments().len(),
-            actual_end
+            total_size
         );
-        structure.total_size = actual_end;
+        structure.total_size = total_size;
         Ok(structure)
     }
 
-    /// Returns true if the destination will contain XMP
-    fn has_xmp_output(source: &Structure, updates: &Updates) -> bool {
-        use crate::updates::MetadataUpdate;
-        match &updates.xmp {
-            MetadataUpdate::Set(_) => true,
-            MetadataUpdate::Keep => source.segments().iter().any(|s| s.is_xmp()),
-            MetadataUpdate::Remove => false,
+    /// Check the RIFF size, chunk padding and, for WebP, the `VP8X` flags
+    fn validate_impl<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        report: &mut ValidationReport,
+    ) -> Result<()> {
+        source.seek(SeekFrom::Start(0))?;
+        let mut sig = [0u8; 4];
+        source.read_exact(&mut sig)?;
+        let riff_size = source.read_u32::<LittleEndian>()? as u64;
+        let format = Self::read_format(source)?;
+        let end = Self::riff_end(structure);
+        if &sig == b"RIFF" && riff_size + 8 != end {
+            report.error(
+                4,
+                "RIFF",
+                format!(
+                    "RIFF size {} does not match the {} bytes of chunks",
+                    riff_size,
+                    end.saturating_sub(8)
+                ),
+            );
         }
+
+        // Walk the chunks, checking the pad byte after odd-sized ones
+        let mut chunks: Vec<(u64, [u8; 4])> = Vec::new();
+        let mut offset = 12u64;
+        while offset + 8 <= end {
+            source.seek(SeekFrom::Start(offset))?;
+            let mut chunk_id = [0u8; 4];
+            source.read_exact(&mut chunk_id)?;
+            let data_size = source.read_u32::<LittleEndian>()? as u64;
+            if chunk_id == [0u8; 4] {
+                offset += 4;
+                continue;
+            }
+            if data_size == SIZE_IN_DS64 && &sig != b"RIFF" {
+                // Sizes from ds64 were checked while parsing
+                break;
+            }
+            if offset + Self::chunk_on_disk(data_size) > end {
+                break;
+            }
+            if data_size % 2 == 1 {
+                source.seek(SeekFrom::Start(offset + 8 + data_size))?;
+                if source.read_u8()? != 0 {
+                    report.warning(
+                        offset + 8 + data_size,
+                        format!("RIFF/{}", String::from_utf8_lossy(&chunk_id)),
+                        "Padding byte after odd-sized chunk is not zero",
+                    );
+                }
+            }
+            chunks.push((offset, chunk_id));
+            offset += Self::chunk_on_disk(data_size);
+        }
+
+        if &format == WEBP_FORMAT {
+            Self::validate_vp8x(source, &chunks, report)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the `VP8X` flags agree with the chunks present
+    fn validate_vp8x<R: Read + Seek>(
+        source: &mut R,
+        chunks: &[(u64, [u8; 4])],
+        report: &mut ValidationReport,
+    ) -> Result<()> {
+        let find = |id: &[u8; 4]| chunks.iter().find(|(_, c)| c == id).map(|(o, _)| *o);
+        let Some(vp8x_offset) = find(b"VP8X") else {
+            for id in [b"ICCP", b"ANIM", b"ALPH", b"EXIF", XMP_CHUNK_ID] {
+                if let Some(offset) = find(id) {
+                    report.error(
+                        offset,
+                        format!("RIFF/{}", String::from_utf8_lossy(id)),
+                        "Chunk requires the extended format (VP8X)",
+                    );
+                }
+            }
+            return Ok(());
+        };
+        if chunks.first().map(|(o, _)| *o) != Some(vp8x_offset) {
+            report.error(vp8x_offset, "RIFF/VP8X", "VP8X must be the first chunk");
+        }
+
+        source.seek(SeekFrom::Start(vp8x_offset + 8))?;
+        let flags = source.read_u32::<LittleEndian>()?;
+        for (flag, id, name) in [
+            (VP8X_ICC_FLAG, b"ICCP", "ICC profile"),
+            (VP8X_ANIMATION_FLAG, b"ANIM", "animation"),
+            (VP8X_EXIF_FLAG, b"EXIF", "EXIF"),
+            (VP8X_XMP_FLAG, XMP_CHUNK_ID, "XMP"),
+        ] {
+            let present = find(id).is_some();
+            if present != (flags & flag != 0) {
+                report.error(
+                    vp8x_offset,
+                    "RIFF/VP8X",
+                    format!(
+                        "{} flag is {} but the {} chunk is {}",
+                        name,
+                        if present { "clear" } else { "set" },
+                        String::from_utf8_lossy(id),
+                        if present { "present" } else { "missing" }
+                    ),
+                );
+            }
+        }
+        // Lossless images carry alpha in the bitstream, so only ALPH needs the flag
+        if find(b"ALPH").is_some() && flags & VP8X_ALPHA_FLAG == 0 {
+            report.error(
+                vp8x_offset,
+                "RIFF/VP8X",
+                "alpha flag is clear but the ALPH chunk is present",
+            );
+        }
+        Ok(())
     }
 
     /// Read the 4-byte RIFF format code from a source stream
@@ -345,62 +1020,584 @@ impl RiffIO {
         Ok(())
     }
 
-    /// Write all "other" chunks from source, with optional VP8X flag patching
+    /// The chunks a write produces, in file order
     ///
-    /// VP8X is a WebP-specific chunk that declares optional feature flags.
-    /// When XMP is being added, bit 2 (0x0004) of the VP8X flags must be set.
-    fn write_other_chunks<R: Read + Seek, W: Write>(
-        source_structure: &Structure,
+    /// Non-WebP files keep their chunks in source order with XMP and C2PA
+    /// appended. WebP files are laid out in the order the container spec
+    /// requires, with a `VP8X` chunk whenever the output is not a bare
+    /// simple-format image.
+    fn output_chunks<'a>(
+        structure: &'a Structure,
+        updates: &Updates,
+    ) -> Result<Vec<OutputChunk<'a>>> {
+        use crate::updates::MetadataUpdate;
+
+        let xmp_size = match &updates.xmp {
+            MetadataUpdate::Set(xmp) => Some(xmp.len() as u64),
+            MetadataUpdate::Keep => structure
+                .xmp_index()
+                .map(|i| structure.segments()[i].location().size),
+            MetadataUpdate::Remove => None,
+        };
+        let c2pa_size = match &updates.jumbf {
+            MetadataUpdate::Set(jumbf) => Some(jumbf.len() as u64),
+            MetadataUpdate::Keep => structure
+                .c2pa_jumbf_index()
+                .map(|i| structure.segments()[i].location().size),
+            MetadataUpdate::Remove => None,
+        };
+
+        let is_avix = |s: &Segment| s.path.as_deref() == Some("AVIX");
+        let others: Vec<&Segment> = structure
+            .segments()
+            .iter()
+            .filter(|s| {
+                !s.is_type(SegmentKind::Header)
+                    && !s.is_xmp()
+                    && !s.is_jumbf()
+                    && !is_avix(s)
+                    && !Self::is_avi_list(s)
+            })
+            .collect();
+
+        let mut chunks = Vec::new();
+        if structure.media_type == MediaType::WebP {
+            let path_is = |seg: &Segment, id: &str| seg.path.as_deref() == Some(id);
+            let vp8x = others.iter().copied().find(|s| path_is(s, "VP8X"));
+            let image_only = others.len() == 1 && others[0].is_type(SegmentKind::ImageData);
+            if vp8x.is_some() || !image_only || xmp_size.is_some() || c2pa_size.is_some() {
+                chunks.push(OutputChunk::Vp8x(vp8x));
+            }
+            for id in ["ICCP", "ANIM"] {
+                chunks.extend(
+                    others
+                        .iter()
+                        .filter(|s| path_is(s, id))
+                        .map(|s| OutputChunk::Copy(s)),
+                );
+            }
+            chunks.extend(
+                others
+                    .iter()
+                    .filter(|s| {
+                        !s.is_exif() && !["VP8X", "ICCP", "ANIM"].iter().any(|id| path_is(s, id))
+                    })
+                    .map(|s| OutputChunk::Copy(s)),
+            );
+            chunks.extend(
+                others
+                    .iter()
+                    .filter(|s| s.is_exif())
+                    .map(|s| OutputChunk::Copy(s)),
+            );
+        } else if structure.media_type == MediaType::Wav && !updates.wav.is_empty() {
+            Self::wav_output_chunks(&others, updates, &mut chunks)?;
+        } else {
+            chunks.extend(others.iter().map(|s| OutputChunk::Copy(s)));
+        }
+
+        if let Some(size) = xmp_size {
+            chunks.push(OutputChunk::Xmp(size));
+        }
+        if let Some(size) = c2pa_size {
+            chunks.push(OutputChunk::C2pa(size));
+        }
+        if structure.media_type == MediaType::Wav {
+            Self::plan_ds64(&mut chunks);
+        }
+        // OpenDML extension chunks follow the main RIFF chunk
+        chunks.extend(
+            structure
+                .segments()
+                .iter()
+                .filter(|s| is_avix(s))
+                .map(OutputChunk::Copy),
+        );
+        Ok(chunks)
+    }
+
+    /// Use RF64 when the source already does or the output no longer fits
+    /// 32-bit sizes
+    ///
+    /// The `ds64` chunk replaces the source `ds64`, or a leading `JUNK`
+    /// placeholder large enough to hold it so that no other chunk moves.
+    /// Without either, a new `ds64` is inserted first.
+    fn plan_ds64(chunks: &mut Vec<OutputChunk<'_>>) {
+        let path_is = |chunk: &OutputChunk<'_>, id: &str| matches!(chunk, OutputChunk::Copy(s) if s.path.as_deref() == Some(id));
+        let total: u64 = 12 + chunks.iter().map(OutputChunk::size_on_disk).sum::<u64>();
+        let large_chunks = chunks
+            .iter()
+            .filter(|c| !path_is(c, "data") && c.size_on_disk() - 8 > MAX_RIFF_SIZE)
+            .count() as u64;
+        let existing = chunks.iter().position(|c| path_is(c, "ds64"));
+        if existing.is_none() && total - 8 <= MAX_RIFF_SIZE && large_chunks == 0 {
+            return;
+        }
+
+        let needed = DS64_MIN_DATA_SIZE + 12 * large_chunks;
+        let placeholder = existing.or_else(|| path_is(&chunks[0], "JUNK").then_some(0));
+        match placeholder {
+            Some(index) if chunks[index].size_on_disk() - 8 >= needed => {
+                let size = chunks[index].size_on_disk() - 8;
+                chunks[index] = OutputChunk::Ds64(size);
+            }
+            Some(index) if existing.is_some() => chunks[index] = OutputChunk::Ds64(needed),
+            _ => chunks.insert(0, OutputChunk::Ds64(needed)),
+        }
+    }
+
+    /// Data of the `ds64` chunk to write, with sizes from the output layout
+    fn ds64_data<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        chunks: &[OutputChunk<'_>],
+        size: u64,
+    ) -> Result<Vec<u8>> {
+        let source_ds64 = match Self::find_chunk(structure, DS64_CHUNK_ID) {
+            Some(seg) => Ds64::parse(&Self::read_chunk_data(
+                source,
+                seg,
+                self.limits.max_segment_size,
+            )?)?,
+            None => Ds64::default(),
+        };
+        let mut ds64 = Ds64 {
+            riff_size: 4 + chunks.iter().map(OutputChunk::size_on_disk).sum::<u64>(),
+            ..Default::default()
+        };
+        for chunk in chunks {
+            let OutputChunk::Copy(seg) = chunk else {
+                continue;
+            };
+            let id: [u8; 4] = match seg.path.as_deref().map(str::as_bytes) {
+                Some(path) if path.len() == 4 => path.try_into().unwrap(),
+                _ => continue,
+            };
+            // The padded on-disk size only differs from the real one for odd sizes
+            source.seek(SeekFrom::Start(seg.location().offset + 4))?;
+            let size = match source.read_u32::<LittleEndian>()? as u64 {
+                SIZE_IN_DS64 => source_ds64
+                    .chunk_size(&id)
+                    .unwrap_or(seg.location().size - 8),
+                size => size,
+            };
+            if &id == b"data" {
+                if ds64.data_size == 0 {
+                    ds64.data_size = size;
+                }
+            } else if size > MAX_RIFF_SIZE {
+                ds64.table.push((id, size));
+            }
+        }
+        ds64.sample_count = match Self::find_chunk(structure, b"fmt ") {
+            _ if source_ds64.sample_count != 0 => source_ds64.sample_count,
+            // blockAlign is at offset 12 of the fmt data
+            Some(fmt) => {
+                let fmt = Self::read_chunk_data(source, fmt, 16)?;
+                match fmt.get(12..14) {
+                    Some(block) if u16::from_le_bytes([block[0], block[1]]) != 0 => {
+                        ds64.data_size / u16::from_le_bytes([block[0], block[1]]) as u64
+                    }
+                    _ => 0,
+                }
+            }
+            None => 0,
+        };
+        Ok(ds64.to_bytes(size))
+    }
+
+    /// Output position of source position `pos`, for positions inside copied chunks
+    fn relocate(chunks: &[OutputChunk<'_>], pos: u64) -> u64 {
+        let mut offset = 12;
+        for chunk in chunks {
+            if let OutputChunk::Copy(seg) = chunk {
+                let location = seg.location();
+                if (location.offset..location.offset + location.size).contains(&pos) {
+                    return pos - location.offset + offset;
+                }
+            }
+            offset += chunk.size_on_disk();
+        }
+        pos
+    }
+
+    /// End of the main RIFF chunk, before any OpenDML `RIFF AVIX` chunks
+    fn riff_end(structure: &Structure) -> u64 {
+        Self::find_chunk(structure, b"AVIX")
+            .map(|s| s.location().offset)
+            .unwrap_or(structure.total_size)
+    }
+
+    /// Write the root chunk header: signature, size and format
+    ///
+    /// RF64 output keeps the source signature (`RF64` or `BW64`), and uses
+    /// `RF64` for a plain RIFF file upgraded because of its size.
+    fn write_header<R: Read + Seek, W: Write>(
         source: &mut R,
         writer: &mut W,
-        is_webp: bool,
-        adding_xmp: bool,
+        dest: &Structure,
     ) -> Result<()> {
-        let other_count = source_structure
-            .segments()
+        let format = Self::read_format(source)?;
+        if Self::find_chunk(dest, DS64_CHUNK_ID).is_some() {
+            source.seek(SeekFrom::Start(0))?;
+            let mut sig = [0u8; 4];
+            source.read_exact(&mut sig)?;
+            writer.write_all(if &sig == b"RIFF" { b"RF64" } else { &sig })?;
+            writer.write_u32::<LittleEndian>(SIZE_IN_DS64 as u32)?;
+        } else {
+            writer.write_all(b"RIFF")?;
+            writer.write_u32::<LittleEndian>((Self::riff_end(dest) - 8) as u32)?;
+        }
+        writer.write_all(&format)?;
+        Ok(())
+    }
+
+    /// WAV chunks in source order, with `bext`, `iXML` and `LIST/INFO`
+    /// replaced, removed or inserted before `data` as the edits require
+    fn wav_output_chunks<'a>(
+        others: &[&'a Segment],
+        updates: &Updates,
+        chunks: &mut Vec<OutputChunk<'a>>,
+    ) -> Result<()> {
+        let source_info = others
             .iter()
-            .filter(|s| !s.is_type(SegmentKind::Header) && !s.is_xmp() && !s.is_jumbf())
-            .count();
-        log::debug!("write_other_chunks: {} chunks to copy", other_count);
+            .find(|s| Self::wav_metadata_chunk(s) == Some(b"LIST"))
+            .and_then(|s| Self::loaded_chunk_data(s));
+        let changes = wav_meta::plan_changes(source_info, &updates.wav)?;
+        let change_for = |id: &[u8; 4]| match id {
+            b"bext" => &changes.bext,
+            b"iXML" => &changes.ixml,
+            _ => &changes.info,
+        };
 
-        for seg in source_structure.segments() {
-            if seg.is_type(SegmentKind::Header) || seg.is_xmp() || seg.is_jumbf() {
+        // Chunks new to the file go before the audio data, or at the end
+        let mut new_chunks: Vec<OutputChunk<'a>> = [b"bext", b"iXML", b"LIST"]
+            .into_iter()
+            .filter(|id| {
+                !others
+                    .iter()
+                    .any(|s| Self::wav_metadata_chunk(s) == Some(id))
+            })
+            .filter_map(|id| match change_for(id) {
+                ChunkChange::Set(data) => Some(OutputChunk::Data(*id, data.clone())),
+                _ => None,
+            })
+            .collect();
+
+        let mut written: Vec<&[u8; 4]> = Vec::new();
+        for seg in others {
+            if seg.path.as_deref() == Some("data") {
+                chunks.append(&mut new_chunks);
+            }
+            let Some(id) = Self::wav_metadata_chunk(seg) else {
+                chunks.push(OutputChunk::Copy(seg));
                 continue;
+            };
+            match change_for(id) {
+                ChunkChange::Keep => chunks.push(OutputChunk::Copy(seg)),
+                ChunkChange::Remove => {}
+                // A rewritten chunk replaces the first one; duplicates are dropped
+                ChunkChange::Set(data) if !written.contains(&id) => {
+                    written.push(id);
+                    chunks.push(OutputChunk::Data(*id, data.clone()));
+                }
+                ChunkChange::Set(_) => {}
             }
+        }
+        chunks.append(&mut new_chunks);
+        Ok(())
+    }
 
-            let location = seg.location();
-            log::debug!(
-                "write_other_chunks: copying '{}' offset={} size={}",
-                seg.path.as_deref().unwrap_or("?"),
-                location.offset,
-                location.size
-            );
+    /// Canvas size and alpha from a `VP8 ` or `VP8L` bitstream header
+    fn bitstream_canvas<R: Read + Seek>(
+        source: &mut R,
+        image: &Segment,
+    ) -> Result<Option<(u32, u32, bool)>> {
+        let data = Self::read_chunk_data(source, image, 10)?;
+        Ok(match image.path.as_deref() {
+            // frame tag(3) + start code 9d 01 2a + 14-bit width/height (LE, 2-bit scale)
+            Some("VP8 ") if data.len() >= 10 && data[3..6] == [0x9d, 0x01, 0x2a] => {
+                let w = u16::from_le_bytes([data[6], data[7]]) & 0x3fff;
+                let h = u16::from_le_bytes([data[8], data[9]]) & 0x3fff;
+                Some((w as u32, h as u32, false))
+            }
+            // signature 0x2f + packed 14-bit width - 1, height - 1 and the alpha hint
+            Some("VP8L") if data.len() >= 5 && data[0] == 0x2f => {
+                let bits = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+                Some((
+                    (bits & 0x3fff) + 1,
+                    ((bits >> 14) & 0x3fff) + 1,
+                    bits & (1 << 28) != 0,
+                ))
+            }
+            _ => None,
+        })
+    }
 
-            // Special case: VP8X chunk in WebP when XMP is being added
-            if is_webp
-                && adding_xmp
-                && seg.path.as_deref() == Some("VP8X")
-                && location.size == 8 + VP8X_DATA_SIZE
-            {
-                source.seek(SeekFrom::Start(location.offset))?;
-                let mut buf = vec![0u8; location.size as usize];
-                source.read_exact(&mut buf)?;
-
-                // Flags are a LE u32 at bytes 8–11 (after the 8-byte chunk header)
-                if let Ok(arr) = buf[8..12].try_into() {
-                    let flags = u32::from_le_bytes(arr) | VP8X_XMP_FLAG;
-                    buf[8..12].copy_from_slice(&flags.to_le_bytes());
+    /// Data of the `VP8X` chunk to write: the source chunk, or one synthesized
+    /// from the bitstream header, with feature flags matching `chunks`
+    fn vp8x_data<R: Read + Seek>(
+        structure: &Structure,
+        source: &mut R,
+        existing: Option<&Segment>,
+        chunks: &[OutputChunk<'_>],
+    ) -> Result<[u8; VP8X_DATA_SIZE as usize]> {
+        let mut data = [0u8; VP8X_DATA_SIZE as usize];
+        let mut flags = match existing {
+            Some(seg) => {
+                let existing = Self::read_chunk_data(source, seg, VP8X_DATA_SIZE)?;
+                if existing.len() != VP8X_DATA_SIZE as usize {
+                    return Err(Error::InvalidFormat("Invalid VP8X chunk size".into()));
+                }
+                data.copy_from_slice(&existing);
+                u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+            }
+            None => {
+                let image = structure
+                    .segments()
+                    .iter()
+                    .find(|s| s.is_type(SegmentKind::ImageData))
+                    .ok_or_else(|| Error::InvalidFormat("WebP has no image data".into()))?;
+                let (width, height, alpha) =
+                    Self::bitstream_canvas(source, image)?.ok_or_else(|| {
+                        Error::InvalidFormat("Cannot read WebP canvas size from bitstream".into())
+                    })?;
+                if width == 0 || height == 0 {
+                    return Err(Error::InvalidFormat("WebP canvas size is zero".into()));
                 }
-                writer.write_all(&buf)?;
+                data[4..7].copy_from_slice(&(width - 1).to_le_bytes()[..3]);
+                data[7..10].copy_from_slice(&(height - 1).to_le_bytes()[..3]);
+                if alpha {
+                    VP8X_ALPHA_FLAG
+                } else {
+                    0
+                }
+            }
+        };
+
+        let copied = |id: &str| {
+            chunks
+                .iter()
+                .any(|c| matches!(c, OutputChunk::Copy(s) if s.path.as_deref() == Some(id)))
+        };
+        let set = |flags: &mut u32, flag: u32, on: bool| {
+            if on {
+                *flags |= flag;
             } else {
-                source.seek(SeekFrom::Start(location.offset))?;
-                let copied = std::io::copy(&mut source.by_ref().take(location.size), writer)?;
-                log::debug!("write_other_chunks: copied {} bytes", copied);
+                *flags &= !flag;
+            }
+        };
+        set(&mut flags, VP8X_ICC_FLAG, copied("ICCP"));
+        set(&mut flags, VP8X_ANIMATION_FLAG, copied("ANIM"));
+        set(&mut flags, VP8X_EXIF_FLAG, copied("EXIF"));
+        set(
+            &mut flags,
+            VP8X_XMP_FLAG,
+            chunks.iter().any(|c| matches!(c, OutputChunk::Xmp(_))),
+        );
+        if copied("ALPH") {
+            flags |= VP8X_ALPHA_FLAG;
+        }
+        data[0..4].copy_from_slice(&flags.to_le_bytes());
+        Ok(data)
+    }
+
+    /// Write every chunk after the RIFF header
+    ///
+    /// `write_c2pa` writes the C2PA chunk, so that the processing path can
+    /// apply its exclusion.
+    fn write_chunks<R: Read + Seek, W: Write>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        writer: &mut W,
+        updates: &Updates,
+        mut write_c2pa: impl FnMut(&mut W, &[u8]) -> Result<()>,
+    ) -> Result<()> {
+        use crate::updates::MetadataUpdate;
+
+        let chunks = Self::output_chunks(structure, updates)?;
+        log::debug!("write_chunks: {} chunks to write", chunks.len());
+
+        for chunk in &chunks {
+            match chunk {
+                OutputChunk::Copy(seg) => {
+                    let location = seg.location();
+                    log::debug!(
+                        "write_chunks: copying '{}' offset={} size={}",
+                        seg.path.as_deref().unwrap_or("?"),
+                        location.offset,
+                        location.size
+                    );
+                    let end = location.offset + location.size;
+                    match structure
+                        .avi_index()
+                        .map(|i| i.fields_in(location.offset, end))
+                    {
+                        Some(fields) if !fields.is_empty() => {
+                            // Absolute index offsets follow the chunks they point into
+                            avi_index::copy_patched(
+                                source,
+                                writer,
+                                location.offset,
+                                location.size,
+                                fields,
+                                |value| Self::relocate(&chunks, value),
+                            )?;
+                        }
+                        _ => {
+                            source.seek(SeekFrom::Start(location.offset))?;
+                            std::io::copy(&mut source.by_ref().take(location.size), writer)?;
+                        }
+                    }
+                }
+                OutputChunk::Vp8x(existing) => {
+                    let data = Self::vp8x_data(structure, source, *existing, &chunks)?;
+                    Self::write_chunk(writer, b"VP8X", &data)?;
+                }
+                OutputChunk::Data(id, data) => Self::write_chunk(writer, id, data)?,
+                OutputChunk::Ds64(size) => {
+                    let data = self.ds64_data(structure, source, &chunks, *size)?;
+                    Self::write_chunk(writer, DS64_CHUNK_ID, &data)?;
+                }
+                OutputChunk::Xmp(_) => match &updates.xmp {
+                    MetadataUpdate::Set(xmp_data) => {
+                        Self::write_chunk(writer, XMP_CHUNK_ID, xmp_data)?
+                    }
+                    _ => {
+                        let xmp_data = self.read_metadata(structure, source, false)?;
+                        Self::write_chunk(writer, XMP_CHUNK_ID, &xmp_data)?;
+                    }
+                },
+                OutputChunk::C2pa(_) => match &updates.jumbf {
+                    MetadataUpdate::Set(jumbf_data) => write_c2pa(writer, jumbf_data)?,
+                    _ => {
+                        let jumbf_data = self.read_metadata(structure, source, true)?;
+                        write_c2pa(writer, &jumbf_data)?;
+                    }
+                },
             }
         }
-        log::debug!("write_other_chunks: done");
+        log::debug!("write_chunks: done");
         Ok(())
     }
+
+    /// Read the source XMP (or, with `c2pa`, the C2PA manifest) chunk data to copy it
+    fn read_metadata<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        c2pa: bool,
+    ) -> Result<Vec<u8>> {
+        let (index, name) = if c2pa {
+            (structure.c2pa_jumbf_index(), "C2PA")
+        } else {
+            (structure.xmp_index(), "XMP")
+        };
+        let index = index
+            .ok_or_else(|| Error::InvalidFormat(format!("{} chunk missing from source", name)))?;
+        let loc = structure.segments()[index].location();
+        self.limits
+            .check_segment_size(loc.offset, loc.size, &format!("{} chunk", name))?;
+        source.seek(SeekFrom::Start(loc.offset))?;
+        let mut data = vec![0u8; loc.size as usize];
+        source.read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// A chunk in the output of a write
+enum OutputChunk<'a> {
+    /// Copied unchanged from the source
+    Copy(&'a Segment),
+    /// WebP `VP8X`, patched from the source chunk or synthesized
+    Vp8x(Option<&'a Segment>),
+    /// XMP chunk with this data size
+    Xmp(u64),
+    /// C2PA chunk with this data size
+    C2pa(u64),
+    /// Chunk rebuilt in memory, with this id and data
+    Data([u8; 4], Vec<u8>),
+    /// RF64/BW64 `ds64` chunk with this data size
+    Ds64(u64),
+}
+
+impl OutputChunk<'_> {
+    /// Bytes the chunk takes in the output, including header and padding
+    fn size_on_disk(&self) -> u64 {
+        match self {
+            OutputChunk::Copy(seg) => seg.location().size,
+            OutputChunk::Vp8x(_) => RiffIO::chunk_on_disk(VP8X_DATA_SIZE),
+            OutputChunk::Xmp(size) | OutputChunk::C2pa(size) | OutputChunk::Ds64(size) => {
+                RiffIO::chunk_on_disk(*size)
+            }
+            OutputChunk::Data(_, data) => RiffIO::chunk_on_disk(data.len() as u64),
+        }
+    }
+}
+
+/// Contents of an RF64/BW64 `ds64` chunk
+#[derive(Debug, Default)]
+struct Ds64 {
+    riff_size: u64,
+    data_size: u64,
+    sample_count: u64,
+    /// 64-bit sizes of chunks other than `data`
+    table: Vec<([u8; 4], u64)>,
+}
+
+impl Ds64 {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if (data.len() as u64) < DS64_MIN_DATA_SIZE {
+            return Err(Error::InvalidFormat(format!(
+                "ds64 chunk too small: {} bytes",
+                data.len()
+            )));
+        }
+        let u64_at = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+        let table_len = u32::from_le_bytes(data[24..28].try_into().unwrap()) as usize;
+        let table = data[28..]
+            .chunks_exact(12)
+            .take(table_len)
+            .map(|entry| {
+                let id: [u8; 4] = entry[0..4].try_into().unwrap();
+                (id, u64::from_le_bytes(entry[4..12].try_into().unwrap()))
+            })
+            .collect();
+        Ok(Self {
+            riff_size: u64_at(0),
+            data_size: u64_at(8),
+            sample_count: u64_at(16),
+            table,
+        })
+    }
+
+    /// Serialize, zero-filling up to `size` bytes
+    fn to_bytes(&self, size: u64) -> Vec<u8> {
+        let mut out = Vec::with_capacity(size as usize);
+        out.extend_from_slice(&self.riff_size.to_le_bytes());
+        out.extend_from_slice(&self.data_size.to_le_bytes());
+        out.extend_from_slice(&self.sample_count.to_le_bytes());
+        out.extend_from_slice(&(self.table.len() as u32).to_le_bytes());
+        for (id, size) in &self.table {
+            out.extend_from_slice(id);
+            out.extend_from_slice(&size.to_le_bytes());
+        }
+        out.resize((size as usize).max(out.len()), 0);
+        out
+    }
+
+    /// 64-bit data size of a chunk whose 32-bit size field is [`SIZE_IN_DS64`]
+    fn chunk_size(&self, id: &[u8; 4]) -> Option<u64> {
+        if id == b"data" {
+            return Some(self.data_size);
+        }
+        self.table
+            .iter()
+            .find(|(k, _)| k == id)
+            .map(|(_, size)| *size)
+    }
 }
 
 impl Default for RiffIO {
@@ -437,14 +1634,32 @@ impl ContainerIO for RiffIO {
     }
 
     fn detect(header: &[u8]) -> Option<ContainerKind> {
-        // RIFF files begin with the literal "RIFF" FourCC
-        if header.len() >= 4 && &header[0..4] == b"RIFF" {
+        // RIFF files begin with the literal "RIFF" FourCC (or RF64/BW64 above 4 GB)
+        if header.len() >= 4 && RIFF_SIGNATURES.iter().any(|sig| header[0..4] == sig[..]) {
             Some(ContainerKind::Riff)
         } else {
             None
         }
     }
 
+    fn sniff(header: &[u8]) -> Vec<Candidate> {
+        if Self::detect(header).is_none() {
+            return Vec::new();
+        }
+        let known = header
+            .get(8..12)
+            .and_then(|format| Self::media_type_for_format(format.try_into().ok()?));
+        vec![match known {
+            Some(media_type) => Candidate::new(media_type, Confidence::High),
+            // Parsing falls back to WAV for unknown format codes
+            None => Candidate::new(MediaType::Wav, Confidence::Low),
+        }]
+    }
+
+    fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
     fn parse<R: Read + Seek>(&self, source: &mut R) -> Result<Structure> {
         self.parse_impl(source)
     }
@@ -461,16 +1676,8 @@ impl ContainerIO for RiffIO {
 
         let location = structure.segments()[idx].location();
 
-        if location.size > MAX_SEGMENT_SIZE {
-            return Err(Error::InvalidSegment {
-                offset: location.offset,
-                reason: format!(
-                    "XMP chunk too large: {} bytes (max {} MB)",
-                    location.size,
-                    MAX_SEGMENT_SIZE / (1024 * 1024)
-                ),
-            });
-        }
+        self.limits
+            .check_segment_size(location.offset, location.size, "XMP chunk")?;
 
         source.seek(SeekFrom::Start(location.offset))?;
         let mut data = vec![0u8; location.size as usize];
@@ -490,16 +1697,8 @@ impl ContainerIO for RiffIO {
 
         let location = structure.segments()[idx].location();
 
-        if location.size > MAX_SEGMENT_SIZE {
-            return Err(Error::InvalidSegment {
-                offset: location.offset,
-                reason: format!(
-                    "C2PA chunk too large: {} bytes (max {} MB)",
-                    location.size,
-                    MAX_SEGMENT_SIZE / (1024 * 1024)
-                ),
-            });
-        }
+        self.limits
+            .check_segment_size(location.offset, location.size, "C2PA chunk")?;
 
         source.seek(SeekFrom::Start(location.offset))?;
         let mut data = vec![0u8; location.size as usize];
@@ -512,7 +1711,7 @@ impl ContainerIO for RiffIO {
         source_structure: &Structure,
         updates: &Updates,
     ) -> Result<Structure> {
-        use crate::updates::MetadataUpdate;
+        crate::jumbf::reject_labeled_boxes(updates, ContainerKind::Riff)?;
 
         let mut dest = Structure::new(ContainerKind::Riff, source_structure.media_type);
 
@@ -524,151 +1723,122 @@ impl ContainerIO for RiffIO {
             Some("riff".to_string()),
         ));
         let mut offset = 12u64;
-
-        let is_webp = source_structure.media_type == MediaType::WebP;
-        let adding_xmp = Self::has_xmp_output(source_structure, updates);
-
-        // Copy all Other chunks in original order (C2PA and XMP will be appended at end)
-        for seg in source_structure.segments() {
-            if seg.is_type(SegmentKind::Header) || seg.is_xmp() || seg.is_jumbf() {
-                continue;
+        // Where each copied chunk went, for the AVI lists inside them
+        let mut copied = Vec::new();
+
+        for chunk in Self::output_chunks(source_structure, updates)? {
+            match chunk {
+                OutputChunk::Copy(seg) => {
+                    // Copied chunks keep their kind (ImageData for VP8/VP8L, Other for VP8X etc.)
+                    let size = seg.location().size; // already includes 8-byte header + padding
+                    dest.add_segment(Segment::new(offset, size, seg.kind, seg.path.clone()));
+                    copied.push((seg.location(), offset));
+                    offset += size;
+                }
+                OutputChunk::Vp8x(_) | OutputChunk::Ds64(_) => {
+                    let size = chunk.size_on_disk();
+                    let id = if matches!(chunk, OutputChunk::Vp8x(_)) {
+                        "VP8X"
+                    } else {
+                        "ds64"
+                    };
+                    dest.add_segment(Segment::new(
+                        offset,
+                        size,
+                        SegmentKind::Other,
+                        Some(id.to_string()),
+                    ));
+                    offset += size;
+                }
+                OutputChunk::Data(id, data) => {
+                    let mut chunk = Vec::with_capacity(data.len() + 9);
+                    Self::write_chunk(&mut chunk, &id, &data)?;
+                    let size = chunk.len() as u64;
+                    dest.add_segment(
+                        Segment::new(
+                            offset,
+                            size,
+                            SegmentKind::Other,
+                            Some(String::from_utf8_lossy(&id).into_owned()),
+                        )
+                        .with_data(LazyData::Loaded(chunk)),
+                    );
+                    offset += size;
+                }
+                OutputChunk::Xmp(size) => {
+                    // XMP segment records the data range (after the 8-byte chunk header)
+                    dest.add_segment(Segment::with_ranges(
+                        vec![ByteRange::new(offset + 8, size)],
+                        SegmentKind::Xmp,
+                        Some("XMP ".to_string()),
+                    )?);
+                    offset += Self::chunk_on_disk(size);
+                }
+                OutputChunk::C2pa(size) => {
+                    // Jumbf segment records the data range (after the 8-byte chunk header)
+                    dest.add_segment(Segment::with_ranges(
+                        vec![ByteRange::new(offset + 8, size)],
+                        SegmentKind::Jumbf,
+                        Some("C2PA".to_string()),
+                    )?);
+                    offset += Self::chunk_on_disk(size);
+                }
             }
-
-            let src_loc = seg.location();
-            let seg_size = src_loc.size; // already includes 8-byte header + padding
-
-            // VP8X same size - just content changes, not layout
-            // Preserve kind (ImageData for VP8/VP8L, Other for VP8X etc.)
-            dest.add_segment(Segment::new(offset, seg_size, seg.kind, seg.path.clone()));
-            offset += seg_size;
-
-            // Detect if VP8X is missing for WebP + XMP case
-            // (note: creation of VP8X requires VP8/VP8L dimension parsing - out of scope)
-            let _ = (is_webp, adding_xmp);
-        }
-
-        // Append XMP chunk at end (if requested)
-        let xmp_data_size: Option<u64> = match &updates.xmp {
-            MetadataUpdate::Set(xmp) => Some(xmp.len() as u64),
-            MetadataUpdate::Keep => source_structure
-                .xmp_index()
-                .map(|i| source_structure.segments()[i].location().size),
-            MetadataUpdate::Remove => None,
-        };
-
-        if let Some(sz) = xmp_data_size {
-            // XMP segment records the data range (after the 8-byte chunk header)
-            dest.add_segment(Segment::with_ranges(
-                vec![ByteRange::new(offset + 8, sz)],
-                SegmentKind::Xmp,
-                Some("XMP ".to_string()),
-            )?);
-            offset += Self::chunk_on_disk(sz);
         }
 
-        // Append C2PA chunk at very end (for maximum compatibility)
-        let c2pa_data_size: Option<u64> = match &updates.jumbf {
-            MetadataUpdate::Set(jumbf) => Some(jumbf.len() as u64),
-            MetadataUpdate::Keep => source_structure
-                .c2pa_jumbf_index()
-                .map(|i| source_structure.segments()[i].location().size),
-            MetadataUpdate::Remove => None,
-        };
-
-        if let Some(sz) = c2pa_data_size {
-            // Jumbf segment records the data range (after the 8-byte chunk header)
-            dest.add_segment(Segment::with_ranges(
-                vec![ByteRange::new(offset + 8, sz)],
-                SegmentKind::Jumbf,
-                Some("C2PA".to_string()),
-            )?);
-            offset += Self::chunk_on_disk(sz);
+        // AVI lists move with the chunk that holds them
+        for list in source_structure
+            .segments()
+            .iter()
+            .filter(|s| Self::is_avi_list(s))
+        {
+            let location = list.location();
+            if let Some((chunk, out)) = copied.iter().find(|(chunk, _)| {
+                location.offset >= chunk.offset
+                    && location.offset + location.size <= chunk.offset + chunk.size
+            }) {
+                dest.add_segment(Segment::new(
+                    out + (location.offset - chunk.offset),
+                    location.size,
+                    SegmentKind::Other,
+                    list.path.clone(),
+                ));
+            }
         }
 
         dest.total_size = offset;
+        let riff_end = Self::riff_end(&dest);
+        if riff_end - 8 > MAX_RIFF_SIZE && Self::find_chunk(&dest, DS64_CHUNK_ID).is_none() {
+            return Err(Error::InvalidFormat(format!(
+                "RIFF chunk of {} bytes exceeds the 4 GB limit",
+                riff_end
+            )));
+        }
         Ok(dest)
     }
 
-    fn write<R: Read + Seek, W: Write>(
+    fn write_stream<R: Read + Seek, W: Write>(
         &self,
         structure: &Structure,
         source: &mut R,
         writer: &mut W,
         updates: &Updates,
     ) -> Result<()> {
-        use crate::updates::MetadataUpdate;
-
         // Compute output structure to determine the correct RIFF size field
         let dest = self.calculate_updated_structure(structure, updates)?;
 
-        let format = Self::read_format(source)?;
-        let is_webp = &format == WEBP_FORMAT;
-        let adding_xmp = Self::has_xmp_output(structure, updates);
-
         log::debug!(
-            "write: format='{}' source_segments={} dest_total_size={} is_webp={} adding_xmp={}",
-            String::from_utf8_lossy(&format),
+            "write: source_segments={} dest_total_size={}",
             structure.segments().len(),
             dest.total_size,
-            is_webp,
-            adding_xmp
         );
 
         // RIFF root header: "RIFF" + (total - 8) + format
-        writer.write_all(b"RIFF")?;
-        writer.write_u32::<LittleEndian>((dest.total_size - 8) as u32)?;
-        writer.write_all(&format)?;
-
-        // Stream all non-metadata chunks in original order
-        Self::write_other_chunks(structure, source, writer, is_webp, adding_xmp)?;
-
-        // Append XMP
-        match &updates.xmp {
-            MetadataUpdate::Set(xmp_data) => {
-                Self::write_chunk(writer, XMP_CHUNK_ID, xmp_data)?;
-            }
-            MetadataUpdate::Keep => {
-                if let Some(idx) = structure.xmp_index() {
-                    let loc = structure.segments()[idx].location();
-                    if loc.size > MAX_SEGMENT_SIZE {
-                        return Err(Error::InvalidSegment {
-                            offset: loc.offset,
-                            reason: "XMP chunk too large to copy".into(),
-                        });
-                    }
-                    source.seek(SeekFrom::Start(loc.offset))?;
-                    let mut xmp_data = vec![0u8; loc.size as usize];
-                    source.read_exact(&mut xmp_data)?;
-                    Self::write_chunk(writer, XMP_CHUNK_ID, &xmp_data)?;
-                }
-            }
-            MetadataUpdate::Remove => {}
-        }
-
-        // Append C2PA manifest
-        match &updates.jumbf {
-            MetadataUpdate::Set(jumbf_data) => {
-                Self::write_chunk(writer, C2PA_CHUNK_ID, jumbf_data)?;
-            }
-            MetadataUpdate::Keep => {
-                if let Some(idx) = structure.c2pa_jumbf_index() {
-                    let loc = structure.segments()[idx].location();
-                    if loc.size > MAX_SEGMENT_SIZE {
-                        return Err(Error::InvalidSegment {
-                            offset: loc.offset,
-                            reason: "C2PA chunk too large to copy".into(),
-                        });
-                    }
-                    source.seek(SeekFrom::Start(loc.offset))?;
-                    let mut jumbf_data = vec![0u8; loc.size as usize];
-                    source.read_exact(&mut jumbf_data)?;
-                    Self::write_chunk(writer, C2PA_CHUNK_ID, &jumbf_data)?;
-                }
-            }
-            MetadataUpdate::Remove => {}
-        }
+        Self::write_header(source, writer, &dest)?;
 
-        Ok(())
+        self.write_chunks(structure, source, writer, updates, |w, data| {
+            Self::write_chunk(w, C2PA_CHUNK_ID, data)
+        })
     }
 
     fn write_with_processor<R: Read + Seek, W: Write, F>(
@@ -684,7 +1854,6 @@ impl ContainerIO for RiffIO {
     {
         use crate::processing_writer::ProcessingWriter;
         use crate::segment::ExclusionMode;
-        use crate::updates::MetadataUpdate;
 
         let exclude_segments = &updates.processing.exclude_segments;
         let exclusion_mode = updates.processing.exclusion_mode;
@@ -694,100 +1863,39 @@ impl ContainerIO for RiffIO {
         let mut pw = ProcessingWriter::new(writer, processor);
 
         let dest = self.calculate_updated_structure(structure, updates)?;
-        let format = Self::read_format(source)?;
-        let is_webp = &format == WEBP_FORMAT;
-        let adding_xmp = Self::has_xmp_output(structure, updates);
 
         // RIFF root header (always hashed – changes in total size are part of hash)
-        pw.write_all(b"RIFF")?;
-        pw.write_u32::<LittleEndian>((dest.total_size - 8) as u32)?;
-        pw.write_all(&format)?;
-
-        // Stream all non-metadata chunks through the processor
-        for seg in structure.segments() {
-            if seg.is_type(SegmentKind::Header) || seg.is_xmp() || seg.is_jumbf() {
-                continue;
-            }
-
-            let location = seg.location();
-
-            if is_webp
-                && adding_xmp
-                && seg.path.as_deref() == Some("VP8X")
-                && location.size == 8 + VP8X_DATA_SIZE
-            {
-                // VP8X: load, patch flags, write through processor
-                source.seek(SeekFrom::Start(location.offset))?;
-                let mut buf = vec![0u8; location.size as usize];
-                source.read_exact(&mut buf)?;
-                if let Ok(arr) = buf[8..12].try_into() {
-                    let flags = u32::from_le_bytes(arr) | VP8X_XMP_FLAG;
-                    buf[8..12].copy_from_slice(&flags.to_le_bytes());
-                }
-                pw.write_all(&buf)?;
-            } else {
-                source.seek(SeekFrom::Start(location.offset))?;
-                std::io::copy(&mut source.by_ref().take(location.size), &mut pw)?;
-            }
-        }
+        Self::write_header(source, &mut pw, &dest)?;
 
-        // XMP chunk – not excluded from hash (only C2PA is excluded per C2PA spec)
-        match &updates.xmp {
-            MetadataUpdate::Set(xmp_data) => {
-                Self::write_chunk(&mut pw, XMP_CHUNK_ID, xmp_data)?;
-            }
-            MetadataUpdate::Keep => {
-                if let Some(idx) = structure.xmp_index() {
-                    let loc = structure.segments()[idx].location();
-                    if loc.size > MAX_SEGMENT_SIZE {
-                        return Err(Error::InvalidSegment {
-                            offset: loc.offset,
-                            reason: "XMP chunk too large to copy".into(),
-                        });
-                    }
-                    source.seek(SeekFrom::Start(loc.offset))?;
-                    let mut xmp_data = vec![0u8; loc.size as usize];
-                    source.read_exact(&mut xmp_data)?;
-                    Self::write_chunk(&mut pw, XMP_CHUNK_ID, &xmp_data)?;
-                }
-            }
-            MetadataUpdate::Remove => {}
-        }
+        // Everything is hashed except the C2PA chunk, whose exclusion follows
+        // DataOnly or EntireSegment mode
+        self.write_chunks(structure, source, &mut pw, updates, |pw, data| {
+            Self::write_c2pa_chunk_with_exclusion(pw, data, should_exclude_jumbf, data_only)
+        })
+    }
 
-        // C2PA chunk – exclusion applied here per DataOnly or EntireSegment mode
-        match &updates.jumbf {
-            MetadataUpdate::Set(jumbf_data) => {
-                Self::write_c2pa_chunk_with_exclusion(
-                    &mut pw,
-                    jumbf_data,
-                    should_exclude_jumbf,
-                    data_only,
-                )?;
-            }
-            MetadataUpdate::Keep => {
-                if let Some(idx) = structure.c2pa_jumbf_index() {
-                    let loc = structure.segments()[idx].location();
-                    if loc.size > MAX_SEGMENT_SIZE {
-                        return Err(Error::InvalidSegment {
-                            offset: loc.offset,
-                            reason: "C2PA chunk too large to copy".into(),
-                        });
-                    }
-                    source.seek(SeekFrom::Start(loc.offset))?;
-                    let mut jumbf_data = vec![0u8; loc.size as usize];
-                    source.read_exact(&mut jumbf_data)?;
-                    Self::write_c2pa_chunk_with_exclusion(
-                        &mut pw,
-                        &jumbf_data,
-                        should_exclude_jumbf,
-                        data_only,
-                    )?;
-                }
-            }
-            MetadataUpdate::Remove => {}
+    fn read_media_info<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+    ) -> Result<crate::MediaInfo> {
+        let mut info = crate::MediaInfo::default();
+        match structure.media_type {
+            MediaType::WebP => Self::webp_media_info(structure, source, &mut info)?,
+            MediaType::Wav => Self::wav_media_info(structure, source, &mut info)?,
+            MediaType::Avi => Self::avi_media_info(structure, source, &mut info)?,
+            _ => {}
         }
+        Ok(info)
+    }
 
-        Ok(())
+    fn validate<R: Read + Seek>(
+        &self,
+        structure: &Structure,
+        source: &mut R,
+        report: &mut ValidationReport,
+    ) -> Result<()> {
+        self.validate_impl(structure, source, report)
     }
 
     fn exclusion_range_for_segment(structure: &Structure, kind: SegmentKind) -> Option<(u64, u64)> {
@@ -819,11 +1927,18 @@ impl ContainerIO for RiffIO {
     #[cfg(feature = "exif")]
     fn read_exif_info<R: Read + Seek>(
         &self,
-        _structure: &Structure,
-        _source: &mut R,
+        structure: &Structure,
+        source: &mut R,
     ) -> Result<Option<crate::tiff::ExifInfo>> {
-        // RIFF formats do not use standard EXIF IFD structure
-        Ok(None)
+        // Only WebP has an EXIF chunk; its segment covers the whole chunk
+        let Some(segment) = structure.segments().iter().find(|s| s.is_exif()) else {
+            return Ok(None);
+        };
+        let data = Self::read_chunk_data(source, segment, self.limits.max_segment_size)?;
+
+        // The spec stores raw TIFF data, but some writers keep the JPEG "Exif\0\0" prefix
+        let tiff = data.strip_prefix(b"Exif\0\0").unwrap_or(&data);
+        crate::tiff::parse_exif_info(tiff)
     }
 }
 
@@ -1097,4 +2212,547 @@ mod tests {
         // C2PA data should NOT appear in processed bytes count
         // (exact assertion requires tracking excluded bytes separately)
     }
+
+    #[test]
+    fn test_avi_media_info() {
+        // avih: 40000 us/frame, 250 frames, 640x480
+        let mut avih = vec![0u8; 56];
+        avih[0..4].copy_from_slice(&40_000u32.to_le_bytes());
+        avih[16..20].copy_from_slice(&250u32.to_le_bytes());
+        avih[32..36].copy_from_slice(&640u32.to_le_bytes());
+        avih[36..40].copy_from_slice(&480u32.to_le_bytes());
+        let mut hdrl = b"hdrl".to_vec();
+        hdrl.extend_from_slice(b"avih");
+        hdrl.extend_from_slice(&56u32.to_le_bytes());
+        hdrl.extend_from_slice(&avih);
+
+        let data = make_riff(AVI_FORMAT, &[(b"LIST", &hdrl), (b"idx1", b"")]);
+        let mut cursor = Cursor::new(data);
+        let handler = RiffIO::new();
+        let structure = handler.parse(&mut cursor).unwrap();
+        let info = handler.read_media_info(&structure, &mut cursor).unwrap();
+
+        assert_eq!((info.width, info.height), (Some(640), Some(480)));
+        assert_eq!(info.frame_count, Some(250));
+        assert_eq!(info.duration, Some(std::time::Duration::from_secs(10)));
+    }
+
+    /// VP8L bitstream header for a `width` x `height` image with the alpha hint set
+    fn vp8l_header(width: u32, height: u32) -> Vec<u8> {
+        let bits = (width - 1) | ((height - 1) << 14) | (1 << 28);
+        let mut data = vec![0x2f];
+        data.extend_from_slice(&bits.to_le_bytes());
+        data
+    }
+
+    /// Chunk ids of a RIFF file, in order
+    fn chunk_ids(data: &[u8]) -> Vec<String> {
+        let mut ids = Vec::new();
+        let mut pos = 12;
+        while pos + 8 <= data.len() {
+            ids.push(String::from_utf8_lossy(&data[pos..pos + 4]).into_owned());
+            let size = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            pos += 8 + size + size % 2;
+        }
+        ids
+    }
+
+    /// VP8X flags of a WebP file whose first chunk is VP8X
+    fn vp8x_flags(data: &[u8]) -> u32 {
+        assert_eq!(&data[12..16], b"VP8X");
+        u32::from_le_bytes(data[20..24].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_simple_webp_upgraded_to_extended_for_xmp() {
+        let data = make_riff(WEBP_FORMAT, &[(b"VP8L", &vp8l_header(100, 50))]);
+        let handler = RiffIO::new();
+        let mut cursor = Cursor::new(data);
+        let structure = handler.parse(&mut cursor).unwrap();
+
+        let updates = Updates::new().set_xmp(b"<x:xmpmeta/>".to_vec());
+        let mut output = Cursor::new(Vec::new());
+        handler
+            .write(&structure, &mut cursor, &mut output, &updates)
+            .unwrap();
+        let written = output.into_inner();
+
+        assert_eq!(chunk_ids(&written), vec!["VP8X", "VP8L", "XMP "]);
+        assert_eq!(vp8x_flags(&written), VP8X_XMP_FLAG | VP8X_ALPHA_FLAG);
+        // Canvas width - 1 and height - 1 as 24-bit LE values
+        assert_eq!(&written[24..30], &[99, 0, 0, 49, 0, 0]);
+        let predicted = handler
+            .calculate_updated_structure(&structure, &updates)
+            .unwrap();
+        assert_eq!(predicted.total_size, written.len() as u64);
+
+        // Removing the XMP keeps the extended format but clears the flag
+        let mut cursor = Cursor::new(written);
+        let structure = handler.parse(&mut cursor).unwrap();
+        let mut output = Cursor::new(Vec::new());
+        handler
+            .write(
+                &structure,
+                &mut cursor,
+                &mut output,
+                &Updates::new().remove_xmp(),
+            )
+            .unwrap();
+        let written = output.into_inner();
+        assert_eq!(chunk_ids(&written), vec!["VP8X", "VP8L"]);
+        assert_eq!(vp8x_flags(&written), VP8X_ALPHA_FLAG);
+    }
+
+    #[test]
+    fn test_extended_webp_chunks_reordered_and_flagged() {
+        let mut vp8x = vec![0u8; 10];
+        vp8x[4] = 99;
+        vp8x[7] = 49;
+        let exif = b"MM\x00\x2a\x00\x00\x00\x08\x00\x00";
+        let data = make_riff(
+            WEBP_FORMAT,
+            &[
+                (b"VP8X", &vp8x),
+                (b"EXIF", exif),
+                (b"VP8L", &vp8l_header(100, 50)),
+                (b"ICCP", b"profile"),
+            ],
+        );
+        let handler = RiffIO::new();
+        let mut cursor = Cursor::new(data);
+        let structure = handler.parse(&mut cursor).unwrap();
+        assert!(structure.segments().iter().any(|s| s.is_exif()));
+
+        let mut output = Cursor::new(Vec::new());
+        handler
+            .write(&structure, &mut cursor, &mut output, &Updates::new())
+            .unwrap();
+        let written = output.into_inner();
+        assert_eq!(chunk_ids(&written), vec!["VP8X", "ICCP", "VP8L", "EXIF"]);
+        assert_eq!(vp8x_flags(&written), VP8X_ICC_FLAG | VP8X_EXIF_FLAG);
+    }
+
+    #[test]
+    fn test_wav_metadata_replaced_and_inserted_before_data() {
+        let mut info = b"INFO".to_vec();
+        info.extend_from_slice(b"INAM\x04\x00\x00\x00Old\x00");
+        let data = make_riff(
+            WAVE_FORMAT,
+            &[
+                (b"fmt ", &[0u8; 16]),
+                (b"LIST", &info),
+                (b"data", &[0u8; 8]),
+            ],
+        );
+        let handler = RiffIO::new();
+        let mut cursor = Cursor::new(data);
+        let structure = handler.parse(&mut cursor).unwrap();
+        let metadata = RiffIO::read_wav_metadata(&structure).unwrap().unwrap();
+        assert_eq!(metadata.info(&WavMetadata::TITLE), Some("Old"));
+
+        let bext = BextChunk {
+            originator: "Recorder".into(),
+            ..Default::default()
+        };
+        let updates = Updates::new()
+            .set_bext(bext.clone())
+            .set_ixml("<BWFXML/>")
+            .set_riff_info(WavMetadata::TITLE, "New title");
+        let mut output = Cursor::new(Vec::new());
+        handler
+            .write(&structure, &mut cursor, &mut output, &updates)
+            .unwrap();
+        let written = output.into_inner();
+        assert_eq!(
+            chunk_ids(&written),
+            vec!["fmt ", "LIST", "bext", "iXML", "data"]
+        );
+        let riff_size = u32::from_le_bytes(written[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_size + 8, written.len());
+        let predicted = handler
+            .calculate_updated_structure(&structure, &updates)
+            .unwrap();
+        assert_eq!(predicted.total_size, written.len() as u64);
+
+        let structure = handler.parse(&mut Cursor::new(written)).unwrap();
+        let metadata = RiffIO::read_wav_metadata(&structure).unwrap().unwrap();
+        assert_eq!(metadata.bext, Some(bext));
+        assert_eq!(metadata.ixml.as_deref(), Some("<BWFXML/>"));
+        assert_eq!(metadata.info(&WavMetadata::TITLE), Some("New title"));
+    }
+
+    /// A source whose bytes are `head` followed by zeros up to `len`, so large
+    /// files can be tested without allocating them
+    struct ZeroTail {
+        head: Vec<u8>,
+        len: u64,
+        pos: u64,
+    }
+
+    impl Read for ZeroTail {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = (buf.len() as u64).min(self.len.saturating_sub(self.pos)) as usize;
+            let start = (self.pos as usize).min(self.head.len());
+            let from_head = (self.head.len() - start).min(n);
+            buf[..from_head].copy_from_slice(&self.head[start..start + from_head]);
+            buf[from_head..n].fill(0);
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for ZeroTail {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.pos = match pos {
+                SeekFrom::Start(p) => p,
+                SeekFrom::End(d) => (self.len as i64 + d) as u64,
+                SeekFrom::Current(d) => (self.pos as i64 + d) as u64,
+            };
+            Ok(self.pos)
+        }
+    }
+
+    /// A writer that keeps the first bytes written and counts the rest
+    #[derive(Default)]
+    struct HeadWriter {
+        head: Vec<u8>,
+        len: u64,
+    }
+
+    impl Write for HeadWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let keep = buf.len().min(256usize.saturating_sub(self.head.len()));
+            self.head.extend_from_slice(&buf[..keep]);
+            self.len += buf.len() as u64;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // `ContainerIO::write` requires a readable, seekable writer; RIFF writes only append
+    impl Read for HeadWriter {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Seek for HeadWriter {
+        fn seek(&mut self, _pos: SeekFrom) -> std::io::Result<u64> {
+            Ok(self.len)
+        }
+    }
+
+    /// WAV header with a 16-bit stereo fmt chunk (block align 4), then `chunks`
+    fn wav_fmt() -> Vec<u8> {
+        let mut fmt = vec![0u8; 16];
+        fmt[0..2].copy_from_slice(&1u16.to_le_bytes());
+        fmt[2..4].copy_from_slice(&2u16.to_le_bytes());
+        fmt[12..14].copy_from_slice(&4u16.to_le_bytes());
+        fmt
+    }
+
+    #[test]
+    fn test_rf64_parse_and_rewrite() {
+        let mut ds64 = vec![0u8; 28];
+        ds64[8..16].copy_from_slice(&8u64.to_le_bytes()); // data size
+        ds64[16..24].copy_from_slice(&2u64.to_le_bytes()); // sample count
+        let mut data = make_riff(
+            WAVE_FORMAT,
+            &[
+                (b"ds64", &ds64),
+                (b"fmt ", &wav_fmt()),
+                (b"data", &[0u8; 8]),
+            ],
+        );
+        let len = data.len() as u64;
+        data[0..4].copy_from_slice(b"BW64");
+        data[4..8].copy_from_slice(&[0xff; 4]);
+        data[20..28].copy_from_slice(&(len - 8).to_le_bytes()); // ds64 RIFF size
+        let data_header = data.len() - 12;
+        data[data_header..data_header + 4].copy_from_slice(&[0xff; 4]);
+
+        assert_eq!(RiffIO::detect(&data), Some(ContainerKind::Riff));
+        let handler = RiffIO::new();
+        let mut cursor = Cursor::new(data);
+        let structure = handler.parse(&mut cursor).unwrap();
+        assert_eq!(structure.media_type, MediaType::Wav);
+        let data_seg = RiffIO::find_chunk(&structure, b"data").unwrap();
+        assert_eq!(data_seg.location().size, 16);
+
+        let updates = Updates::new().set_xmp(b"<x:xmpmeta/>".to_vec());
+        let mut output = Cursor::new(Vec::new());
+        handler
+            .write(&structure, &mut cursor, &mut output, &updates)
+            .unwrap();
+        let written = output.into_inner();
+        assert_eq!(&written[0..8], b"BW64\xff\xff\xff\xff");
+        let ds64 = Ds64::parse(&written[20..48]).unwrap();
+        assert_eq!(ds64.riff_size, written.len() as u64 - 8);
+        assert_eq!((ds64.data_size, ds64.sample_count), (8, 2));
+
+        let structure = handler.parse(&mut Cursor::new(written)).unwrap();
+        let paths: Vec<_> = structure
+            .segments()
+            .iter()
+            .filter_map(|s| s.path.as_deref())
+            .collect();
+        assert_eq!(paths, vec!["riff", "ds64", "fmt ", "data", "XMP "]);
+    }
+
+    #[test]
+    fn test_wav_upgraded_to_rf64_over_4gb() {
+        // JUNK placeholder, fmt, and a data chunk just under the 4 GB limit
+        let data_size = MAX_RIFF_SIZE - 100;
+        let mut head = make_riff(WAVE_FORMAT, &[(b"JUNK", &[0u8; 28]), (b"fmt ", &wav_fmt())]);
+        head.extend_from_slice(b"data");
+        head.extend_from_slice(&(data_size as u32).to_le_bytes());
+        let len = head.len() as u64 + data_size;
+        head[4..8].copy_from_slice(&((len - 8) as u32).to_le_bytes());
+
+        let handler = RiffIO::new();
+        let mut source = ZeroTail { head, len, pos: 0 };
+        let structure = handler.parse(&mut source).unwrap();
+        let updates = Updates::new().set_jumbf(vec![0u8; 1024]);
+        let dest = handler
+            .calculate_updated_structure(&structure, &updates)
+            .unwrap();
+        // The ds64 takes the place of JUNK, so nothing else moves
+        assert_eq!(dest.segments()[1].path.as_deref(), Some("ds64"));
+        assert_eq!(dest.total_size, len + 1032);
+
+        let mut output = HeadWriter::default();
+        handler
+            .write(&structure, &mut source, &mut output, &updates)
+            .unwrap();
+        assert_eq!(output.len, dest.total_size);
+        assert_eq!(&output.head[0..16], b"RF64\xff\xff\xff\xffWAVEds64");
+        let ds64 = Ds64::parse(&output.head[20..48]).unwrap();
+        assert_eq!(ds64.riff_size, dest.total_size - 8);
+        assert_eq!(ds64.data_size, data_size);
+        assert_eq!(ds64.sample_count, data_size / 4);
+
+        // Without the upgrade a plain RIFF could not hold it
+        let avi = make_riff(AVI_FORMAT, &[]);
+        let mut structure = handler.parse(&mut Cursor::new(avi)).unwrap();
+        structure.add_segment(Segment::new(
+            12,
+            dest.total_size,
+            SegmentKind::Other,
+            Some("movi".into()),
+        ));
+        assert!(handler
+            .calculate_updated_structure(&structure, &Updates::new())
+            .is_err());
+    }
+
+    /// An OpenDML AVI: `RIFF AVI ` with an `indx` super index pointing at an
+    /// `ix00` standard index inside a following `RIFF AVIX` chunk
+    fn make_opendml_avi() -> (Vec<u8>, u64) {
+        let build = |avix: u64| {
+            let mut indx = vec![0u8; 24];
+            indx[0..2].copy_from_slice(&4u16.to_le_bytes()); // longs per entry
+            indx[4..8].copy_from_slice(&1u32.to_le_bytes()); // entries in use
+            indx[8..12].copy_from_slice(b"00dc");
+            indx.extend_from_slice(&(avix + 36).to_le_bytes()); // ix00 chunk
+            indx.extend_from_slice(&[0u8; 8]);
+            let mut strl = b"strl".to_vec();
+            for (id, data) in [(b"strh", &[0u8; 56][..]), (b"indx", &indx[..])] {
+                strl.extend_from_slice(id);
+                strl.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                strl.extend_from_slice(data);
+            }
+            let mut hdrl = b"hdrl".to_vec();
+            hdrl.extend_from_slice(b"avih\x38\x00\x00\x00");
+            hdrl.extend_from_slice(&[0u8; 56]);
+            hdrl.extend_from_slice(b"LIST");
+            hdrl.extend_from_slice(&(strl.len() as u32).to_le_bytes());
+            hdrl.extend_from_slice(&strl);
+            hdrl.extend_from_slice(b"LIST\x10\x00\x00\x00odmldmlh\x04\x00\x00\x00");
+            hdrl.extend_from_slice(&2u32.to_le_bytes()); // total frames
+            let mut idx1 = b"00dc".to_vec();
+            idx1.extend_from_slice(&[0x10, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0]); // movi-relative
+            make_riff(
+                AVI_FORMAT,
+                &[
+                    (b"LIST", &hdrl),
+                    (b"LIST", b"movi00dc\x04\x00\x00\x00abcd"),
+                    (b"idx1", &idx1),
+                ],
+            )
+        };
+        let avix = build(0).len() as u64;
+        let mut data = build(avix);
+
+        // RIFF AVIX > LIST movi > 00dc, ix00 (base offset = AVIX movi list type)
+        let mut movi = b"movi00dc\x04\x00\x00\x00efgh".to_vec();
+        movi.extend_from_slice(b"ix00\x20\x00\x00\x00");
+        movi.extend_from_slice(&[2, 0, 0, 1, 1, 0, 0, 0]);
+        movi.extend_from_slice(b"00dc");
+        movi.extend_from_slice(&(avix + 20).to_le_bytes());
+        movi.extend_from_slice(&[0, 0, 0, 0, 12, 0, 0, 0, 4, 0, 0, 0]);
+        let mut list = b"LIST".to_vec();
+        list.extend_from_slice(&(movi.len() as u32).to_le_bytes());
+        list.extend_from_slice(&movi);
+        data.extend_from_slice(b"RIFF");
+        data.extend_from_slice(&(list.len() as u32 + 4).to_le_bytes());
+        data.extend_from_slice(b"AVIX");
+        data.extend_from_slice(&list);
+        (data, avix)
+    }
+
+    #[test]
+    fn test_opendml_offsets_follow_appended_metadata() {
+        let (data, avix) = make_opendml_avi();
+        let handler = RiffIO::new();
+        let mut cursor = Cursor::new(data.clone());
+        let structure = handler.parse(&mut cursor).unwrap();
+        assert_eq!(structure.total_size, data.len() as u64);
+        assert_eq!(
+            RiffIO::find_chunk(&structure, b"AVIX").map(|s| s.location().offset),
+            Some(avix)
+        );
+        // indx entry and ix00 base offset; idx1 is movi-relative
+        let fields = structure.avi_index().unwrap().fields.clone();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[1].pos, avix + 36 + 8 + 12);
+
+        let updates = Updates::new()
+            .set_xmp(b"<x:xmpmeta/>".to_vec())
+            .set_jumbf(b"manifest".to_vec());
+        let mut output = Cursor::new(Vec::new());
+        handler
+            .write(&structure, &mut cursor, &mut output, &updates)
+            .unwrap();
+        let written = output.into_inner();
+        let delta = 8 + 12 + 8 + 8;
+        assert_eq!(written.len() as u64, data.len() as u64 + delta);
+        // The main RIFF grows; the AVIX chunk moves intact after it
+        let riff_size = u32::from_le_bytes(written[4..8].try_into().unwrap()) as u64;
+        assert_eq!(riff_size + 8, avix + delta);
+        assert_eq!(&written[(avix + delta) as usize..][..4], b"RIFF");
+
+        let read_u64 = |pos: u64| {
+            u64::from_le_bytes(written[pos as usize..pos as usize + 8].try_into().unwrap())
+        };
+        assert_eq!(read_u64(fields[0].pos), avix + 36 + delta);
+        assert_eq!(read_u64(fields[1].pos + delta), avix + 20 + delta);
+
+        let mut cursor = Cursor::new(written);
+        let reparsed = handler.parse(&mut cursor).unwrap();
+        assert!(reparsed.xmp_index().is_some());
+        assert_eq!(reparsed.avi_index().unwrap().fields.len(), 2);
+    }
+
+    #[test]
+    fn test_avi_lists_are_segments() {
+        let (data, avix) = make_opendml_avi();
+        let handler = RiffIO::new();
+        let mut cursor = Cursor::new(data);
+        let structure = handler.parse(&mut cursor).unwrap();
+
+        let lists = |s: &Structure| {
+            s.segments()
+                .iter()
+                .filter(|seg| RiffIO::is_avi_list(seg))
+                .map(|seg| (seg.path.clone().unwrap(), seg.location()))
+                .collect::<Vec<_>>()
+        };
+        let parsed = lists(&structure);
+        let paths: Vec<&str> = parsed.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "AVI/hdrl",
+                "AVI/hdrl/strl",
+                "AVI/hdrl/odml",
+                "AVI/movi",
+                "AVIX/movi"
+            ]
+        );
+        assert_eq!(parsed[2].1.size, 8 + 16);
+        assert_eq!(parsed[4].1.offset, avix + 12);
+
+        // Lists move with the chunks that hold them, and are not written twice
+        let updates = Updates::new().set_xmp(b"<x:xmpmeta/>".to_vec());
+        let expected = handler
+            .calculate_updated_structure(&structure, &updates)
+            .unwrap();
+        let mut output = Cursor::new(Vec::new());
+        handler
+            .write(&structure, &mut cursor, &mut output, &updates)
+            .unwrap();
+        let written = output.into_inner();
+        assert_eq!(expected.total_size, written.len() as u64);
+        let reparsed = handler.parse(&mut Cursor::new(written)).unwrap();
+        assert_eq!(lists(&expected), lists(&reparsed));
+        assert_eq!(lists(&reparsed)[4].1.offset, parsed[4].1.offset + 8 + 12);
+    }
+
+    #[test]
+    fn test_empty_hdrl_list() {
+        // LIST hdrl declaring size 0, then its list type anyway
+        let mut riff = make_riff(AVI_FORMAT, &[]);
+        riff.extend_from_slice(b"LIST\0\0\0\0hdrl");
+        let size = (riff.len() - 8) as u32;
+        riff[4..8].copy_from_slice(&size.to_le_bytes());
+
+        let result = RiffIO::new().parse(&mut Cursor::new(riff.clone()));
+        assert!(matches!(
+            result.unwrap_err().root(),
+            Error::InvalidSegment { .. }
+        ));
+
+        let lenient = Limits {
+            strict: false,
+            ..Limits::default()
+        };
+        let handler = RiffIO::new().with_limits(lenient);
+        let structure = handler.parse(&mut Cursor::new(riff)).unwrap();
+        assert!(structure.avi_index().unwrap().fields.is_empty());
+        assert_eq!(structure.diagnostics().len(), 1);
+    }
+
+    #[test]
+    fn test_absolute_idx1_offsets_follow_moved_movi() {
+        // XMP sits before movi, so removing it moves movi back
+        let mut riff = make_riff(
+            AVI_FORMAT,
+            &[
+                (b"LIST", b"hdrl"),
+                (b"XMP ", b"<x:xmpmeta/>"),
+                (b"LIST", b"movi00dc\x04\x00\x00\x00abcd"),
+            ],
+        );
+        let chunk_pos = (riff.len() - 12) as u32;
+        let mut idx1 = b"00dc\x10\x00\x00\x00".to_vec();
+        idx1.extend_from_slice(&chunk_pos.to_le_bytes());
+        idx1.extend_from_slice(&4u32.to_le_bytes());
+        riff.extend_from_slice(b"idx1\x10\x00\x00\x00");
+        riff.extend_from_slice(&idx1);
+        let size = (riff.len() - 8) as u32;
+        riff[4..8].copy_from_slice(&size.to_le_bytes());
+
+        let handler = RiffIO::new();
+        let mut cursor = Cursor::new(riff);
+        let structure = handler.parse(&mut cursor).unwrap();
+        assert_eq!(structure.avi_index().unwrap().fields.len(), 1);
+
+        let mut output = Cursor::new(Vec::new());
+        handler
+            .write(
+                &structure,
+                &mut cursor,
+                &mut output,
+                &Updates::new().remove_xmp(),
+            )
+            .unwrap();
+        let written = output.into_inner();
+        assert_eq!(chunk_ids(&written), vec!["LIST", "LIST", "idx1"]);
+        let offset = u32::from_le_bytes(written[written.len() - 8..][..4].try_into().unwrap());
+        assert_eq!(offset, chunk_pos - 20);
+        assert_eq!(&written[offset as usize..offset as usize + 4], b"00dc");
+    }
 }
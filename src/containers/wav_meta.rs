@@ -0,0 +1,387 @@
+//! Broadcast WAV (`bext`), `iXML` and `LIST/INFO` metadata
+//!
+//! WAV files used in production carry descriptive metadata in three chunks:
+//!
+//! - `bext`: the EBU Broadcast Wave Format extension (EBU Tech 3285), with
+//!   fixed-size ASCII fields, a sample-accurate time reference, a SMPTE UMID,
+//!   loudness values and a free-text coding history
+//! - `iXML`: an XML document used by field recorders
+//! - `LIST` of type `INFO`: short NUL-terminated strings keyed by four-character
+//!   ids (`INAM`, `IART`, `ICRD`, `ISFT`, ...)
+//!
+//! These chunks are small, so they are parsed and rebuilt in memory.
+//!
+//! Reference: EBU Tech 3285 v2; Microsoft RIFF "Multimedia Programming Interface
+//! and Data Specifications 1.0" (INFO list)
+
+use crate::error::{Error, Result};
+
+/// Size of the fixed part of a `bext` chunk, up to the coding history
+const BEXT_FIXED_SIZE: usize = 602;
+
+/// The EBU Broadcast Wave Format `bext` chunk
+///
+/// Text fields are ASCII and truncated to their fixed sizes on write. Loudness
+/// values are stored in hundredths (e.g. `-2300` for -23.00 LUFS) and are only
+/// meaningful when `version` is 2 or later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BextChunk {
+    /// Free description of the sound sequence (max 256 characters)
+    pub description: String,
+    /// Name of the originator (max 32 characters)
+    pub originator: String,
+    /// Unambiguous reference allocated by the originating organization (max 32 characters)
+    pub originator_reference: String,
+    /// Origination date, `yyyy:mm:dd` or `yyyy-mm-dd`
+    pub origination_date: String,
+    /// Origination time, `hh:mm:ss` or `hh-mm-ss`
+    pub origination_time: String,
+    /// Sample count since midnight of the first sample
+    pub time_reference: u64,
+    /// BWF version (0, 1 or 2)
+    pub version: u16,
+    /// SMPTE 330M UMID (all zeros when unused)
+    pub umid: [u8; 64],
+    /// Integrated loudness in LUFS × 100
+    pub loudness_value: i16,
+    /// Loudness range in LU × 100
+    pub loudness_range: i16,
+    /// Maximum true peak level in dBTP × 100
+    pub max_true_peak_level: i16,
+    /// Highest momentary loudness in LUFS × 100
+    pub max_momentary_loudness: i16,
+    /// Highest short-term loudness in LUFS × 100
+    pub max_short_term_loudness: i16,
+    /// Coding history, one line per processing step
+    pub coding_history: String,
+}
+
+impl Default for BextChunk {
+    fn default() -> Self {
+        Self {
+            description: String::new(),
+            originator: String::new(),
+            originator_reference: String::new(),
+            origination_date: String::new(),
+            origination_time: String::new(),
+            time_reference: 0,
+            version: 2,
+            umid: [0; 64],
+            loudness_value: 0,
+            loudness_range: 0,
+            max_true_peak_level: 0,
+            max_momentary_loudness: 0,
+            max_short_term_loudness: 0,
+            coding_history: String::new(),
+        }
+    }
+}
+
+impl BextChunk {
+    /// Parse `bext` chunk data (without the chunk header)
+    pub(crate) fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < BEXT_FIXED_SIZE {
+            return Err(Error::InvalidFormat(format!(
+                "bext chunk too small: {} bytes (min {})",
+                data.len(),
+                BEXT_FIXED_SIZE
+            )));
+        }
+        let u32_at = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        let i16_at = |at: usize| i16::from_le_bytes([data[at], data[at + 1]]);
+        let mut umid = [0u8; 64];
+        umid.copy_from_slice(&data[348..412]);
+        Ok(Self {
+            description: ascii_field(&data[0..256]),
+            originator: ascii_field(&data[256..288]),
+            originator_reference: ascii_field(&data[288..320]),
+            origination_date: ascii_field(&data[320..330]),
+            origination_time: ascii_field(&data[330..338]),
+            time_reference: u32_at(338) as u64 | (u32_at(342) as u64) << 32,
+            version: u16::from_le_bytes([data[346], data[347]]),
+            umid,
+            loudness_value: i16_at(412),
+            loudness_range: i16_at(414),
+            max_true_peak_level: i16_at(416),
+            max_momentary_loudness: i16_at(418),
+            max_short_term_loudness: i16_at(420),
+            // 180 reserved bytes follow the loudness fields
+            coding_history: nul_terminated(&data[BEXT_FIXED_SIZE..]),
+        })
+    }
+
+    /// Serialize to `bext` chunk data (without the chunk header)
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(BEXT_FIXED_SIZE + self.coding_history.len());
+        put_ascii(&mut out, &self.description, 256);
+        put_ascii(&mut out, &self.originator, 32);
+        put_ascii(&mut out, &self.originator_reference, 32);
+        put_ascii(&mut out, &self.origination_date, 10);
+        put_ascii(&mut out, &self.origination_time, 8);
+        out.extend_from_slice(&(self.time_reference as u32).to_le_bytes());
+        out.extend_from_slice(&((self.time_reference >> 32) as u32).to_le_bytes());
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&self.umid);
+        for value in [
+            self.loudness_value,
+            self.loudness_range,
+            self.max_true_peak_level,
+            self.max_momentary_loudness,
+            self.max_short_term_loudness,
+        ] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.resize(BEXT_FIXED_SIZE, 0);
+        out.extend_from_slice(self.coding_history.as_bytes());
+        out
+    }
+}
+
+/// WAV descriptive metadata read from the `bext`, `iXML` and `LIST/INFO` chunks
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WavMetadata {
+    /// Broadcast WAV extension
+    pub bext: Option<BextChunk>,
+    /// iXML document
+    pub ixml: Option<String>,
+    /// `LIST/INFO` entries in file order
+    pub info: Vec<([u8; 4], String)>,
+}
+
+impl WavMetadata {
+    /// `INFO` id for the title
+    pub const TITLE: [u8; 4] = *b"INAM";
+    /// `INFO` id for the artist
+    pub const ARTIST: [u8; 4] = *b"IART";
+    /// `INFO` id for the creation date
+    pub const CREATION_DATE: [u8; 4] = *b"ICRD";
+    /// `INFO` id for the software that created the file
+    pub const SOFTWARE: [u8; 4] = *b"ISFT";
+
+    /// The first `INFO` value with the given id
+    pub fn info(&self, id: &[u8; 4]) -> Option<&str> {
+        self.info
+            .iter()
+            .find(|(k, _)| k == id)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// True if none of the chunks are present
+    pub fn is_empty(&self) -> bool {
+        self.bext.is_none() && self.ixml.is_none() && self.info.is_empty()
+    }
+}
+
+/// A change to WAV metadata, applied in the order given to [`Updates`](crate::Updates)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum WavEdit {
+    SetBext(Box<BextChunk>),
+    RemoveBext,
+    SetIxml(String),
+    RemoveIxml,
+    /// Replace all values of an `INFO` id with one value
+    SetInfo([u8; 4], String),
+    RemoveInfo([u8; 4]),
+}
+
+/// What to write for one metadata chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ChunkChange {
+    /// Copy the source chunk unchanged (or write nothing if there is none)
+    Keep,
+    /// Drop the source chunk
+    Remove,
+    /// Write this chunk data (without the chunk header)
+    Set(Vec<u8>),
+}
+
+/// Changes to the `bext`, `iXML` and `LIST/INFO` chunks implied by a list of edits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WavChanges {
+    pub(crate) bext: ChunkChange,
+    pub(crate) ixml: ChunkChange,
+    /// The `LIST` chunk data, starting with the `INFO` list type
+    pub(crate) info: ChunkChange,
+}
+
+/// Work out the chunk changes for `edits`, given the source `LIST/INFO` data
+pub(crate) fn plan_changes(source_info: Option<&[u8]>, edits: &[WavEdit]) -> Result<WavChanges> {
+    let mut changes = WavChanges {
+        bext: ChunkChange::Keep,
+        ixml: ChunkChange::Keep,
+        info: ChunkChange::Keep,
+    };
+    let mut info: Option<Vec<([u8; 4], String)>> = None;
+
+    for edit in edits {
+        match edit {
+            WavEdit::SetBext(bext) => changes.bext = ChunkChange::Set(bext.to_bytes()),
+            WavEdit::RemoveBext => changes.bext = ChunkChange::Remove,
+            WavEdit::SetIxml(xml) => changes.ixml = ChunkChange::Set(xml.as_bytes().to_vec()),
+            WavEdit::RemoveIxml => changes.ixml = ChunkChange::Remove,
+            WavEdit::SetInfo(id, value) => {
+                let entries = info_entries(&mut info, source_info)?;
+                match entries.iter().position(|(k, _)| k == id) {
+                    Some(first) => {
+                        entries[first].1 = value.clone();
+                        let mut index = 0;
+                        entries.retain(|(k, _)| {
+                            index += 1;
+                            k != id || index - 1 == first
+                        });
+                    }
+                    None => entries.push((*id, value.clone())),
+                }
+            }
+            WavEdit::RemoveInfo(id) => {
+                info_entries(&mut info, source_info)?.retain(|(k, _)| k != id);
+            }
+        }
+    }
+
+    if let Some(entries) = info {
+        changes.info = if entries.is_empty() {
+            ChunkChange::Remove
+        } else {
+            ChunkChange::Set(info_to_bytes(&entries))
+        };
+    }
+    Ok(changes)
+}
+
+/// The edited `INFO` entries, parsed from the source on first use
+fn info_entries<'a>(
+    info: &'a mut Option<Vec<([u8; 4], String)>>,
+    source_info: Option<&[u8]>,
+) -> Result<&'a mut Vec<([u8; 4], String)>> {
+    if info.is_none() {
+        *info = Some(match source_info {
+            Some(data) => parse_info(data)?,
+            None => Vec::new(),
+        });
+    }
+    Ok(info.as_mut().unwrap())
+}
+
+/// Parse `LIST` chunk data of type `INFO` into (id, value) entries
+pub(crate) fn parse_info(data: &[u8]) -> Result<Vec<([u8; 4], String)>> {
+    if data.len() < 4 || &data[0..4] != b"INFO" {
+        return Err(Error::InvalidFormat("Not a LIST/INFO chunk".into()));
+    }
+    let mut entries = Vec::new();
+    let mut pos = 4;
+    while pos + 8 <= data.len() {
+        let id: [u8; 4] = data[pos..pos + 4].try_into().unwrap();
+        let size = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let value = data.get(pos + 8..pos + 8 + size).ok_or_else(|| {
+            Error::InvalidFormat(format!(
+                "INFO entry '{}' runs past the end of the list",
+                String::from_utf8_lossy(&id)
+            ))
+        })?;
+        entries.push((id, ascii_field(value)));
+        pos += 8 + size + size % 2;
+    }
+    Ok(entries)
+}
+
+/// Serialize `INFO` entries to `LIST` chunk data
+fn info_to_bytes(entries: &[([u8; 4], String)]) -> Vec<u8> {
+    let mut out = b"INFO".to_vec();
+    for (id, value) in entries {
+        // Values are NUL-terminated; odd sizes are padded
+        let size = value.len() + 1;
+        out.extend_from_slice(id);
+        out.extend_from_slice(&(size as u32).to_le_bytes());
+        out.extend_from_slice(value.as_bytes());
+        out.push(0);
+        if size % 2 == 1 {
+            out.push(0);
+        }
+    }
+    out
+}
+
+/// Text of a NUL-padded field, without trailing spaces
+fn ascii_field(bytes: &[u8]) -> String {
+    nul_terminated(bytes).trim_end().to_string()
+}
+
+/// Text up to the first NUL
+pub(crate) fn nul_terminated(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Append `value` as a NUL-padded field of `size` bytes
+fn put_ascii(out: &mut Vec<u8>, value: &str, size: usize) {
+    let bytes = value.as_bytes();
+    let len = bytes.len().min(size);
+    out.extend_from_slice(&bytes[..len]);
+    out.resize(out.len() + size - len, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bext_round_trip() {
+        let bext = BextChunk {
+            description: "Scene 4 take 2".into(),
+            originator: "Field Recorder".into(),
+            originator_reference: "REF123".into(),
+            origination_date: "2024-05-01".into(),
+            origination_time: "12:30:00".into(),
+            time_reference: 0x1_0000_0010,
+            loudness_value: -2300,
+            max_true_peak_level: -100,
+            coding_history: "A=PCM,F=48000,W=24,M=stereo\r\n".into(),
+            ..Default::default()
+        };
+        let bytes = bext.to_bytes();
+        assert_eq!(bytes.len(), BEXT_FIXED_SIZE + bext.coding_history.len());
+        assert_eq!(BextChunk::parse(&bytes).unwrap(), bext);
+
+        // Over-long fields are truncated to their fixed size
+        let long = BextChunk {
+            originator: "x".repeat(40),
+            ..Default::default()
+        };
+        let parsed = BextChunk::parse(&long.to_bytes()).unwrap();
+        assert_eq!(parsed.originator.len(), 32);
+    }
+
+    #[test]
+    fn test_info_edits() {
+        let source = info_to_bytes(&[
+            (*b"INAM", "Old".into()),
+            (*b"ISFT", "Tool".into()),
+            (*b"INAM", "Duplicate".into()),
+        ]);
+        let edits = [
+            WavEdit::SetInfo(*b"INAM", "New title".into()),
+            WavEdit::SetInfo(*b"IART", "Artist".into()),
+            WavEdit::RemoveInfo(*b"ISFT"),
+        ];
+        let changes = plan_changes(Some(&source), &edits).unwrap();
+        assert_eq!(changes.bext, ChunkChange::Keep);
+        let ChunkChange::Set(data) = changes.info else {
+            panic!("INFO should be rewritten");
+        };
+        assert_eq!(
+            parse_info(&data).unwrap(),
+            vec![
+                (*b"INAM", "New title".to_string()),
+                (*b"IART", "Artist".to_string())
+            ]
+        );
+
+        let cleared = plan_changes(
+            Some(&data),
+            &[WavEdit::RemoveInfo(*b"INAM"), WavEdit::RemoveInfo(*b"IART")],
+        )
+        .unwrap();
+        assert_eq!(cleared.info, ChunkChange::Remove);
+    }
+}
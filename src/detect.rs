@@ -0,0 +1,399 @@
+//! Media type detection from file content
+//!
+//! Sniffing looks at the first bytes of a file and reports which media types
+//! it could hold, how sure each guess is, and, for files this build cannot
+//! read, what they are. The result can be cross-checked against a file
+//! extension or MIME type, for example to reject uploads whose name lies
+//! about their content.
+
+use std::{
+    fmt,
+    io::{Read, Seek, SeekFrom},
+};
+
+use crate::{containers::sniff_media_types, MediaType, Result};
+
+/// Number of header bytes sniffing looks at
+///
+/// Shorter headers still work, but may give fewer or less confident candidates.
+pub const SNIFF_LEN: usize = 64;
+
+/// How sure a sniffed media type is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Confidence {
+    /// The container matches but the media type is a fallback guess
+    Low,
+    /// The header lists the media type as compatible, or the signature is partial
+    Medium,
+    /// The signature or primary brand names the media type
+    High,
+}
+
+/// A media type the sniffed content could be
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Candidate {
+    /// The media type
+    pub media_type: MediaType,
+    /// How sure the guess is
+    pub confidence: Confidence,
+}
+
+impl Candidate {
+    pub(crate) fn new(media_type: MediaType, confidence: Confidence) -> Self {
+        Self {
+            media_type,
+            confidence,
+        }
+    }
+}
+
+/// A format recognized from its signature that this build cannot read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KnownFormat {
+    /// Human readable name, e.g. "GIF image"
+    pub name: &'static str,
+    /// Primary MIME type
+    pub mime: &'static str,
+    /// File extensions (without dot), primary first
+    pub extensions: &'static [&'static str],
+    /// Cargo feature of this crate that adds support, if any
+    pub feature: Option<&'static str>,
+    /// Bytes that must appear at the given offsets
+    signature: &'static [(usize, &'static [u8])],
+}
+
+impl KnownFormat {
+    const fn new(
+        name: &'static str,
+        mime: &'static str,
+        extensions: &'static [&'static str],
+        signature: &'static [(usize, &'static [u8])],
+    ) -> Self {
+        Self {
+            name,
+            mime,
+            extensions,
+            feature: None,
+            signature,
+        }
+    }
+
+    #[cfg(any(
+        not(feature = "jpeg"),
+        not(feature = "png"),
+        not(feature = "bmff"),
+        not(feature = "riff")
+    ))]
+    const fn with_feature(mut self, feature: &'static str) -> Self {
+        self.feature = Some(feature);
+        self
+    }
+
+    fn matches(&self, header: &[u8]) -> bool {
+        self.signature
+            .iter()
+            .all(|(offset, bytes)| header.get(*offset..offset + bytes.len()) == Some(*bytes))
+    }
+}
+
+impl fmt::Display for KnownFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.mime)?;
+        if let Some(feature) = self.feature {
+            write!(f, "; enable the \"{}\" feature to read it", feature)?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats recognized but not read, including those of disabled features
+static KNOWN_FORMATS: &[KnownFormat] = &[
+    #[cfg(not(feature = "jpeg"))]
+    KnownFormat::new(
+        "JPEG image",
+        "image/jpeg",
+        &["jpg", "jpeg"],
+        &[(0, b"\xFF\xD8\xFF")],
+    )
+    .with_feature("jpeg"),
+    #[cfg(not(feature = "png"))]
+    KnownFormat::new(
+        "PNG image",
+        "image/png",
+        &["png"],
+        &[(0, b"\x89PNG\r\n\x1a\n")],
+    )
+    .with_feature("png"),
+    #[cfg(not(feature = "bmff"))]
+    KnownFormat::new(
+        "ISO BMFF file (HEIF, AVIF, MP4, MOV)",
+        "application/mp4",
+        &["mp4", "mov", "heic", "heif", "avif", "m4a", "m4v"],
+        &[(4, b"ftyp")],
+    )
+    .with_feature("bmff"),
+    #[cfg(not(feature = "riff"))]
+    KnownFormat::new(
+        "WebP image",
+        "image/webp",
+        &["webp"],
+        &[(0, b"RIFF"), (8, b"WEBP")],
+    )
+    .with_feature("riff"),
+    #[cfg(not(feature = "riff"))]
+    KnownFormat::new(
+        "WAV audio",
+        "audio/wav",
+        &["wav"],
+        &[(0, b"RIFF"), (8, b"WAVE")],
+    )
+    .with_feature("riff"),
+    #[cfg(not(feature = "riff"))]
+    KnownFormat::new(
+        "AVI video",
+        "video/avi",
+        &["avi"],
+        &[(0, b"RIFF"), (8, b"AVI ")],
+    )
+    .with_feature("riff"),
+    KnownFormat::new("GIF image", "image/gif", &["gif"], &[(0, b"GIF8")]),
+    KnownFormat::new(
+        "TIFF image",
+        "image/tiff",
+        &["tif", "tiff", "dng"],
+        &[(0, b"II*\0")],
+    ),
+    KnownFormat::new(
+        "TIFF image",
+        "image/tiff",
+        &["tif", "tiff", "dng"],
+        &[(0, b"MM\0*")],
+    ),
+    KnownFormat::new("JPEG XL image", "image/jxl", &["jxl"], &[(0, b"\xFF\x0A")]),
+    KnownFormat::new(
+        "JPEG XL image",
+        "image/jxl",
+        &["jxl"],
+        &[(0, b"\0\0\0\x0CJXL \r\n\x87\n")],
+    ),
+    KnownFormat::new(
+        "JPEG 2000 image",
+        "image/jp2",
+        &["jp2", "jpx"],
+        &[(0, b"\0\0\0\x0CjP  \r\n\x87\n")],
+    ),
+    KnownFormat::new(
+        "Photoshop document",
+        "image/vnd.adobe.photoshop",
+        &["psd"],
+        &[(0, b"8BPS")],
+    ),
+    KnownFormat::new("BMP image", "image/bmp", &["bmp"], &[(0, b"BM")]),
+    KnownFormat::new(
+        "PDF document",
+        "application/pdf",
+        &["pdf"],
+        &[(0, b"%PDF-")],
+    ),
+    KnownFormat::new("MP3 audio", "audio/mpeg", &["mp3"], &[(0, b"ID3")]),
+    KnownFormat::new("FLAC audio", "audio/flac", &["flac"], &[(0, b"fLaC")]),
+    KnownFormat::new(
+        "Ogg media",
+        "audio/ogg",
+        &["ogg", "oga", "ogv", "opus"],
+        &[(0, b"OggS")],
+    ),
+    KnownFormat::new(
+        "Matroska/WebM media",
+        "video/x-matroska",
+        &["mkv", "webm", "mka"],
+        &[(0, b"\x1A\x45\xDF\xA3")],
+    ),
+    KnownFormat::new(
+        "ZIP archive",
+        "application/zip",
+        &["zip"],
+        &[(0, b"PK\x03\x04")],
+    ),
+];
+
+/// Find the unsupported format `header` belongs to, if it is a known one
+pub(crate) fn known_format(header: &[u8]) -> Option<&'static KnownFormat> {
+    KNOWN_FORMATS.iter().find(|format| format.matches(header))
+}
+
+/// What sniffing found out about a file's content
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Detection {
+    /// Supported media types the content could be, most confident first
+    pub candidates: Vec<Candidate>,
+    /// The format of content this build cannot read, if recognized
+    pub unsupported: Option<KnownFormat>,
+}
+
+impl Detection {
+    /// The most likely media type, if the content is supported
+    pub fn best(&self) -> Option<MediaType> {
+        self.candidates.first().map(|c| c.media_type)
+    }
+
+    /// Whether the content is in a format this build can read
+    pub fn is_supported(&self) -> bool {
+        !self.candidates.is_empty()
+    }
+
+    /// Confidence that the content is `media_type`, if it could be
+    pub fn confidence(&self, media_type: MediaType) -> Option<Confidence> {
+        self.candidates
+            .iter()
+            .find(|c| c.media_type == media_type)
+            .map(|c| c.confidence)
+    }
+
+    /// Whether a file extension (with or without dot) agrees with the content
+    ///
+    /// Unknown extensions and unrecognized content never match.
+    pub fn matches_extension(&self, ext: &str) -> bool {
+        let ext = ext.trim_start_matches('.');
+        match MediaType::from_extension(ext) {
+            Some(media_type) => self.confidence(media_type).is_some(),
+            None => self
+                .unsupported
+                .is_some_and(|f| f.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext))),
+        }
+    }
+
+    /// Whether a MIME type agrees with the content
+    ///
+    /// Unknown MIME types and unrecognized content never match.
+    pub fn matches_mime(&self, mime: &str) -> bool {
+        match MediaType::from_mime(mime) {
+            Some(media_type) => self.confidence(media_type).is_some(),
+            None => {
+                let mime = mime.split(';').next().unwrap_or_default().trim();
+                self.unsupported
+                    .is_some_and(|f| f.mime.eq_ignore_ascii_case(mime))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.best(), &self.unsupported) {
+            (Some(media_type), _) => write!(f, "{}", media_type),
+            (None, Some(format)) => write!(f, "{}", format),
+            (None, None) => f.write_str("unrecognized content"),
+        }
+    }
+}
+
+/// Sniff the media type of a file from its first bytes
+///
+/// Pass at least [`SNIFF_LEN`] bytes when the file is that long.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "jpeg")]
+/// # {
+/// use asset_io::{sniff, Confidence, MediaType};
+///
+/// let detection = sniff(b"\xFF\xD8\xFF\xE0\x00\x10JFIF\x00");
+/// assert_eq!(detection.best(), Some(MediaType::Jpeg));
+/// assert_eq!(detection.confidence(MediaType::Jpeg), Some(Confidence::High));
+/// assert!(detection.matches_extension("jpg"));
+/// assert!(!detection.matches_extension("png"));
+/// # }
+/// ```
+pub fn sniff(header: &[u8]) -> Detection {
+    let mut candidates = sniff_media_types(header);
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.confidence));
+    let unsupported = if candidates.is_empty() {
+        known_format(header).copied()
+    } else {
+        None
+    };
+    Detection {
+        candidates,
+        unsupported,
+    }
+}
+
+/// Sniff the media type of a source, leaving it positioned at the start
+///
+/// # Example
+///
+/// ```no_run
+/// use asset_io::sniff_source;
+/// use std::fs::File;
+///
+/// # fn main() -> asset_io::Result<()> {
+/// let mut file = File::open("upload.jpg")?;
+/// let detection = sniff_source(&mut file)?;
+/// if !detection.matches_extension("jpg") {
+///     println!("upload.jpg is really {}", detection);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn sniff_source<R: Read + Seek>(source: &mut R) -> Result<Detection> {
+    source.seek(SeekFrom::Start(0))?;
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    source
+        .by_ref()
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut header)?;
+    source.seek(SeekFrom::Start(0))?;
+    Ok(sniff(&header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_formats() {
+        let detection = sniff(b"GIF89a\x01\x00\x01\x00");
+        assert!(!detection.is_supported());
+        assert_eq!(detection.unsupported.unwrap().mime, "image/gif");
+        assert!(detection.matches_extension(".GIF"));
+        assert!(detection.matches_mime("image/gif"));
+        assert!(!detection.matches_extension("jpg"));
+        assert_eq!(detection.to_string(), "GIF image (image/gif)");
+
+        let detection = sniff(b"\x00\x01\x02\x03");
+        assert_eq!(detection, Detection::default());
+        assert!(!detection.matches_extension("bin"));
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_bmff_brands() {
+        // ftyp with major brand heic, compatible brands mif1 and heic
+        let header = b"\0\0\0\x18ftypheic\0\0\0\0mif1heic";
+        let detection = sniff(header);
+        assert_eq!(detection.best(), Some(MediaType::Heic));
+        assert_eq!(
+            detection.confidence(MediaType::Heif),
+            Some(Confidence::Medium)
+        );
+        assert_eq!(detection.candidates.len(), 2);
+        assert!(detection.matches_extension("heif"));
+        assert!(!detection.matches_mime("video/mp4"));
+
+        // Unknown brands fall back to MP4 video, like parsing does
+        let detection = sniff(b"\0\0\0\x10ftypxxxx\0\0\0\0");
+        assert_eq!(
+            detection.candidates,
+            [Candidate::new(MediaType::Mp4Video, Confidence::Low)]
+        );
+    }
+
+    #[cfg(not(feature = "png"))]
+    #[test]
+    fn test_disabled_feature() {
+        let detection = sniff(b"\x89PNG\r\n\x1a\n\0\0\0\x0DIHDR");
+        assert_eq!(detection.unsupported.unwrap().feature, Some("png"));
+    }
+}
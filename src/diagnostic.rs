@@ -0,0 +1,156 @@
+//! Problems found while parsing an asset
+//!
+//! A strict parse fails on damage it cannot accept. A lenient parse (see
+//! [`AssetBuilder::strict`](crate::AssetBuilder::strict)) records the damage as a
+//! [`Diagnostic`] instead, recovers what it can and keeps going. Writing the asset
+//! then produces a repaired file: a missing JPEG EOI is appended, RIFF and PNG
+//! sizes are corrected and truncated chunks and boxes are dropped or clamped.
+
+use std::fmt;
+
+/// How serious a [`Diagnostic`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Unusual but valid enough to keep; recorded in every mode
+    Warning,
+    /// Damage that fails a strict parse; a lenient parse recovers from it
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// A problem found while parsing, and where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Offset of the problem from the start of the file
+    pub offset: u64,
+    /// How serious the problem is
+    pub severity: Severity,
+    /// Path of the segment, chunk or box in the container (e.g. `SOS`, `RIFF/LIST`, `moov/trak`)
+    pub path: String,
+    /// What is wrong and how it was handled
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Create a diagnostic
+    pub fn new(
+        severity: Severity,
+        offset: u64,
+        path: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            offset,
+            severity,
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Create a warning
+    pub fn warning(offset: u64, path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, offset, path, message)
+    }
+
+    /// Create an error
+    pub fn error(offset: u64, path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, offset, path, message)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at offset {} ({}): {}",
+            self.severity, self.offset, self.path, self.message
+        )
+    }
+}
+
+/// Findings of [`Asset::validate`](crate::Asset::validate)
+///
+/// Holds the problems recorded while parsing followed by those found by the
+/// container's conformance checks. An asset is valid when nothing of
+/// [`Severity::Error`] was found.
+///
+/// # Example
+///
+/// ```no_run
+/// use asset_io::Asset;
+///
+/// # fn main() -> asset_io::Result<()> {
+/// let mut asset = Asset::open("signed.png")?;
+/// let report = asset.validate()?;
+/// if !report.is_valid() {
+///     eprintln!("{}", report);
+///     std::process::exit(1);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Everything found, in the order it was found
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ValidationReport {
+    /// True if no errors were found (warnings are allowed)
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    /// The errors found
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+    }
+
+    /// The warnings found
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Warning)
+    }
+
+    /// Record an error
+    pub(crate) fn error(
+        &mut self,
+        offset: u64,
+        path: impl Into<String>,
+        message: impl Into<String>,
+    ) {
+        self.diagnostics
+            .push(Diagnostic::error(offset, path, message));
+    }
+
+    /// Record a warning
+    pub(crate) fn warning(
+        &mut self,
+        offset: u64,
+        path: impl Into<String>,
+        message: impl Into<String>,
+    ) {
+        self.diagnostics
+            .push(Diagnostic::warning(offset, path, message));
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
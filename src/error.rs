@@ -1,7 +1,8 @@
 //! Error types for jumbf-io
 
+use crate::{ContainerKind, KnownFormat, MediaType};
 use std::cell::RefCell;
-use std::io;
+use std::{fmt, io};
 
 /// Result type for jumbf-io operations
 pub type Result<T> = std::result::Result<T, Error>;
@@ -27,7 +28,17 @@ pub(crate) fn take_pending_processor_error() -> Option<Error> {
 }
 
 /// Errors that can occur during JUMBF I/O operations
+///
+/// Errors from parsing, reading or writing an asset carry an [`ErrorContext`]
+/// saying where they happened; use [`kind`](Error::kind) to handle them by
+/// category and [`root`](Error::root) to match on the underlying variant.
+///
+/// Such errors arrive as [`Error::WithContext`], so a `match` on the error
+/// itself no longer sees variants such as [`Error::Truncated`]: match on
+/// `error.root()` instead. [`Error::UserCanceled`] is never wrapped. New
+/// variants may be added, so matches need a wildcard arm.
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum Error {
     /// I/O error
     #[error("I/O error: {0}")]
@@ -41,20 +52,228 @@ pub enum Error {
     #[error("Invalid container: {0}")]
     InvalidFormat(String),
 
-    /// Unsupported file format
-    #[error("Unsupported format")]
-    UnsupportedFormat,
+    /// Unsupported file format, with what it is when recognized (see [`crate::sniff`])
+    #[error("Unsupported format{}", known_format_suffix(.0))]
+    UnsupportedFormat(Option<KnownFormat>),
 
     /// Invalid segment
     #[error("Invalid segment at offset {offset}: {reason}")]
     InvalidSegment { offset: u64, reason: String },
 
+    /// The data ends before a segment, chunk or box it declares
+    #[error("Truncated at offset {offset}: {reason}")]
+    Truncated { offset: u64, reason: String },
+
+    /// A parse limit (see [`AssetBuilder`](crate::AssetBuilder)) or built-in cap was exceeded
+    #[error("Limit exceeded at offset {offset}: {reason}")]
+    LimitExceeded { offset: u64, reason: String },
+
+    /// New data does not fit the space or size field available for it
+    #[error("Capacity exceeded: {0}")]
+    CapacityExceeded(String),
+
+    /// The operation is not supported for this asset
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+
+    /// Another error, raised after part of the output had been written
+    ///
+    /// The destination holds `written` bytes of incomplete output and should
+    /// be discarded.
+    #[error("Failed after writing {written} bytes: {source}")]
+    PartialOutput { written: u64, source: Box<Error> },
+
+    /// Another error, with where it happened
+    #[error("{source} ({context})")]
+    WithContext {
+        context: Box<ErrorContext>,
+        source: Box<Error>,
+    },
+
     /// XML parsing error (from quick-xml)
     #[cfg(feature = "xmp")]
     #[error("XML error: {0}")]
     Xml(#[from] quick_xml::Error),
 }
 
+/// Category of an [`Error`], for callers that handle failures by kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The data ends early (including unexpected end of file)
+    Truncated,
+    /// A parse limit or built-in cap was exceeded
+    LimitExceeded,
+    /// The data is malformed
+    Corrupt,
+    /// The format or operation is not supported
+    Unsupported,
+    /// New data does not fit the space available (e.g. an in-place update)
+    CapacityExceeded,
+    /// Reading or writing failed
+    Io,
+    /// A processor callback stopped the operation
+    Canceled,
+    /// The operation failed after part of the output was written
+    PartialOutput,
+}
+
+/// Where an [`Error`] happened
+///
+/// Fields are filled in as far as they are known: the container and media type
+/// by the asset, the path and offset by the container handler.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// Container being parsed or written
+    pub container: Option<ContainerKind>,
+    /// Media type of the asset, once known
+    pub media_type: Option<MediaType>,
+    /// Path of the segment, chunk or box (e.g. `APP1`, `RIFF/LIST`, `/moov/trak[1]/mdia`)
+    ///
+    /// BMFF paths number repeated boxes from zero, omitting `[0]`.
+    pub path: Option<String>,
+    /// Offset of that segment, chunk or box from the start of the file
+    pub offset: Option<u64>,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(container) = self.container {
+            parts.push(format!("container {:?}", container));
+        }
+        if let Some(media_type) = self.media_type {
+            parts.push(format!("media type {}", media_type));
+        }
+        if let Some(path) = &self.path {
+            parts.push(format!("path {}", path));
+        }
+        if let Some(offset) = self.offset {
+            parts.push(format!("offset {}", offset));
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+impl Error {
+    /// Category of this error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => ErrorKind::Truncated,
+            Error::Io(_) => ErrorKind::Io,
+            Error::UserCanceled => ErrorKind::Canceled,
+            Error::InvalidFormat(_) | Error::InvalidSegment { .. } => ErrorKind::Corrupt,
+            #[cfg(feature = "xmp")]
+            Error::Xml(_) => ErrorKind::Corrupt,
+            Error::UnsupportedFormat(_) | Error::Unsupported(_) => ErrorKind::Unsupported,
+            Error::Truncated { .. } => ErrorKind::Truncated,
+            Error::LimitExceeded { .. } => ErrorKind::LimitExceeded,
+            Error::CapacityExceeded(_) => ErrorKind::CapacityExceeded,
+            Error::PartialOutput { .. } => ErrorKind::PartialOutput,
+            Error::WithContext { source, .. } => source.kind(),
+        }
+    }
+
+    /// Where the error happened, if known
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without its context
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::WithContext { source, .. } => source.root(),
+            _ => self,
+        }
+    }
+
+    /// Offset from the start of the file where the error happened, if known
+    pub fn offset(&self) -> Option<u64> {
+        if let Some(offset) = self.context().and_then(|c| c.offset) {
+            return Some(offset);
+        }
+        match self.root() {
+            Error::InvalidSegment { offset, .. }
+            | Error::Truncated { offset, .. }
+            | Error::LimitExceeded { offset, .. } => Some(*offset),
+            Error::PartialOutput { source, .. } => source.offset(),
+            _ => None,
+        }
+    }
+
+    /// What went wrong, without the offset or context
+    pub(crate) fn reason(&self) -> String {
+        match self.root() {
+            Error::InvalidSegment { reason, .. }
+            | Error::Truncated { reason, .. }
+            | Error::LimitExceeded { reason, .. } => reason.clone(),
+            Error::PartialOutput { written, source } => {
+                format!("{} (after writing {} bytes)", source.reason(), written)
+            }
+            root => root.to_string(),
+        }
+    }
+
+    /// Record the segment, chunk or box being handled, unless a more specific
+    /// one is already recorded
+    pub(crate) fn at(self, offset: u64, path: impl Into<String>) -> Self {
+        self.with_context(|context| {
+            if context.path.is_none() {
+                context.path = Some(path.into());
+                context.offset = Some(offset);
+            }
+        })
+    }
+
+    /// Record the container and media type of the asset
+    pub(crate) fn in_container(
+        self,
+        container: ContainerKind,
+        media_type: Option<MediaType>,
+    ) -> Self {
+        self.with_context(|context| {
+            context.container.get_or_insert(container);
+            if context.media_type.is_none() {
+                context.media_type = media_type;
+            }
+        })
+    }
+
+    /// Update the context, adding one if needed
+    ///
+    /// Cancellation is returned unchanged so that callers can match it directly.
+    fn with_context(self, update: impl FnOnce(&mut ErrorContext)) -> Self {
+        match self {
+            Error::UserCanceled => self,
+            Error::WithContext {
+                mut context,
+                source,
+            } => {
+                update(&mut context);
+                Error::WithContext { context, source }
+            }
+            source => {
+                let mut context = Box::<ErrorContext>::default();
+                update(&mut context);
+                Error::WithContext {
+                    context,
+                    source: Box::new(source),
+                }
+            }
+        }
+    }
+}
+
+fn known_format_suffix(format: &Option<KnownFormat>) -> String {
+    format
+        .as_ref()
+        .map(|format| format!(": {}", format))
+        .unwrap_or_default()
+}
+
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
         if e.kind() == io::ErrorKind::Other && e.to_string() == PROCESSOR_IO_SENTINEL {
@@ -65,3 +284,79 @@ impl From<io::Error> for Error {
         Error::Io(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn truncated() -> Error {
+        Error::Truncated {
+            offset: 10,
+            reason: "Unexpected end of file".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_context_keeps_innermost_path() {
+        let error = truncated()
+            .at(8, "APP1")
+            .at(0, "SOI")
+            .in_container(ContainerKind::Jpeg, Some(MediaType::Jpeg));
+        assert_eq!(error.kind(), ErrorKind::Truncated);
+        assert_eq!(error.offset(), Some(8));
+        assert_eq!(error.reason(), "Unexpected end of file");
+        assert!(matches!(error.root(), Error::Truncated { offset: 10, .. }));
+
+        let context = error.context().unwrap();
+        assert_eq!(context.container, Some(ContainerKind::Jpeg));
+        assert_eq!(context.media_type, Some(MediaType::Jpeg));
+        assert_eq!(context.path.as_deref(), Some("APP1"));
+        assert_eq!(
+            error.to_string(),
+            "Truncated at offset 10: Unexpected end of file \
+             (container Jpeg, media type image/jpeg, path APP1, offset 8)"
+        );
+    }
+
+    #[test]
+    fn test_kind() {
+        let eof = io::Error::new(io::ErrorKind::UnexpectedEof, "eof");
+        assert_eq!(Error::from(eof).kind(), ErrorKind::Truncated);
+        assert_eq!(
+            Error::CapacityExceeded(String::new()).kind(),
+            ErrorKind::CapacityExceeded
+        );
+        assert_eq!(
+            Error::InvalidFormat(String::new()).kind(),
+            ErrorKind::Corrupt
+        );
+        assert_eq!(truncated().offset(), Some(10));
+        assert!(truncated().context().is_none());
+    }
+
+    #[test]
+    fn test_partial_output() {
+        let error = Error::PartialOutput {
+            written: 100,
+            source: Box::new(truncated()),
+        }
+        .at(0, "SOI");
+        assert_eq!(error.kind(), ErrorKind::PartialOutput);
+        assert_eq!(error.offset(), Some(0));
+        assert!(matches!(
+            error.root(),
+            Error::PartialOutput { written: 100, .. }
+        ));
+        assert_eq!(
+            error.reason(),
+            "Unexpected end of file (after writing 100 bytes)"
+        );
+    }
+
+    #[test]
+    fn test_cancel_is_not_wrapped() {
+        let error = Error::UserCanceled.at(0, "SOI");
+        assert!(matches!(error, Error::UserCanceled));
+        assert_eq!(error.kind(), ErrorKind::Canceled);
+    }
+}
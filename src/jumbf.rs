@@ -0,0 +1,661 @@
+//! JUMBF box parsing (ISO/IEC 19566-5)
+//!
+//! A JUMBF superbox (`jumb`) starts with a description box (`jumd`) followed by
+//! content boxes or nested superboxes:
+//!
+//! ```text
+//! superbox:  lbox(4) + "jumb"(4) [+ xlbox(8)]
+//! desc box:  lbox(4) + "jumd"(4) [+ xlbox(8)] + UUID(16) + toggles(1)
+//!            [+ label(n, NUL-terminated)] [+ id(4)] [+ hash(32)] [+ private box]
+//! content:   "json" | "cbor" | "bfdb" | "bidb" | "uuid" | ...
+//! ```
+//!
+//! [`JumbfSuperBox::parse`] reads a complete superbox into a navigable tree, which
+//! is enough to inspect C2PA manifest stores without a full C2PA implementation.
+//!
+//! JPEG files can carry any number of JUMBF boxes in APP11 segments (C2PA
+//! manifest stores next to JPEG 360 or privacy boxes, for example), so the
+//! description decides which one is the C2PA manifest store. The other containers
+//! have a dedicated place for the C2PA manifest store (PNG `caBX`, the BMFF C2PA
+//! `uuid` box or `c2pa` item, the RIFF `C2PA` chunk) and hold nothing else.
+
+use crate::{containers::ContainerKind, Updates};
+use crate::{
+    error::{Error, Result},
+    limits::DEFAULT_MAX_BOX_DEPTH,
+    segment::SegmentMetadata,
+    structure::Structure,
+};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Label of the C2PA manifest store superbox
+pub(crate) const C2PA_LABEL: &str = "c2pa";
+
+/// Content type of the C2PA manifest store superbox (`63327061-0011-0010-8000-00AA00389B71`)
+pub(crate) const C2PA_STORE_UUID: [u8; 16] = c2pa_uuid(*b"c2pa");
+
+/// Content type of a standard C2PA manifest
+const C2PA_MANIFEST_UUID: [u8; 16] = c2pa_uuid(*b"c2ma");
+
+/// Content type of a C2PA update manifest
+const C2PA_UPDATE_MANIFEST_UUID: [u8; 16] = c2pa_uuid(*b"c2um");
+
+/// Bytes read from the start of each JUMBF segment to find its description
+const DESCRIPTION_READ_SIZE: u64 = 512;
+
+// `jumd` toggle bits
+const REQUESTABLE: u8 = 0x01;
+const LABEL_PRESENT: u8 = 0x02;
+const ID_PRESENT: u8 = 0x04;
+const HASH_PRESENT: u8 = 0x08;
+const PRIVATE_PRESENT: u8 = 0x10;
+
+// `bfdb` toggle bits
+const FILE_NAME_PRESENT: u8 = 0x01;
+const EXTERNAL_REFERENCE: u8 = 0x02;
+
+/// A C2PA content type: the four-character code followed by the JUMBF UUID suffix
+const fn c2pa_uuid(code: [u8; 4]) -> [u8; 16] {
+    [
+        code[0], code[1], code[2], code[3], 0x00, 0x11, 0x00, 0x10, 0x80, 0x00, 0x00, 0xAA, 0x00,
+        0x38, 0x9B, 0x71,
+    ]
+}
+
+/// A JUMBF superbox embedded in an asset
+///
+/// Returned by [`Asset::jumbf_boxes`](crate::Asset::jumbf_boxes) and
+/// [`Structure::jumbf_boxes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumbfBox {
+    /// Index of the JUMBF segment in [`Structure::segments`]
+    pub segment_index: usize,
+    /// Label from the description box, if any
+    pub label: Option<String>,
+    /// Content type UUID from the description box
+    ///
+    /// `None` when the data does not start with a described JUMBF superbox
+    /// (such as a placeholder written before signing).
+    pub content_type: Option<[u8; 16]>,
+    /// Whether this is the C2PA manifest store, the box read by
+    /// [`Asset::jumbf`](crate::Asset::jumbf) and replaced by
+    /// [`Updates::set_jumbf`](crate::Updates::set_jumbf)
+    pub c2pa: bool,
+}
+
+/// A parsed JUMBF superbox (`jumb`)
+///
+/// # Example
+///
+/// ```no_run
+/// use asset_io::{Asset, JumbfSuperBox};
+///
+/// # fn main() -> asset_io::Result<()> {
+/// let mut asset = Asset::open("signed.jpg")?;
+/// if let Some(store) = asset.manifest_store()? {
+///     for manifest in store.manifests() {
+///         println!("manifest {:?}", manifest.label());
+///     }
+///     let claim = store
+///         .active_manifest()
+///         .and_then(|m| m.child("c2pa.claim"));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumbfSuperBox {
+    /// The description box
+    pub description: JumbfDescription,
+    /// Content boxes and nested superboxes, in file order
+    pub children: Vec<JumbfNode>,
+}
+
+/// A JUMBF description box (`jumd`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumbfDescription {
+    /// Content type UUID
+    pub content_type: [u8; 16],
+    /// Whether the box can be referenced by its label
+    pub requestable: bool,
+    /// Label
+    pub label: Option<String>,
+    /// Numeric ID
+    pub id: Option<u32>,
+    /// SHA-256 hash of the superbox contents
+    pub hash: Option<[u8; 32]>,
+    /// Private box, with application-specific data
+    pub private: Option<JumbfContent>,
+}
+
+/// A child of a JUMBF superbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JumbfNode {
+    /// A nested superbox
+    SuperBox(JumbfSuperBox),
+    /// A content box
+    Content(JumbfContent),
+}
+
+/// A JUMBF content box
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JumbfContent {
+    /// JSON (`json`)
+    Json(Vec<u8>),
+    /// CBOR (`cbor`)
+    Cbor(Vec<u8>),
+    /// Embedded file description (`bfdb`)
+    FileDescription {
+        /// Media type of the file
+        media_type: String,
+        /// File name
+        file_name: Option<String>,
+        /// Whether the `bidb` box holds a reference to the file instead of its data
+        external: bool,
+    },
+    /// Binary data (`bidb`)
+    BinaryData(Vec<u8>),
+    /// UUID box (`uuid`)
+    Uuid {
+        /// Box UUID
+        uuid: [u8; 16],
+        /// Box payload after the UUID
+        data: Vec<u8>,
+    },
+    /// Any other box
+    Other {
+        /// Box type
+        box_type: [u8; 4],
+        /// Box payload
+        data: Vec<u8>,
+    },
+}
+
+impl JumbfSuperBox {
+    /// Parse a complete JUMBF superbox
+    ///
+    /// Trailing data after the superbox is ignored.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        Self::parse_with_depth(data, DEFAULT_MAX_BOX_DEPTH)
+    }
+
+    /// Parse a complete JUMBF superbox nested at most `max_depth` levels deep
+    pub(crate) fn parse_with_depth(data: &[u8], max_depth: usize) -> Result<Self> {
+        let (box_type, body, _) = read_box(data, 0)?;
+        if box_type != *b"jumb" {
+            return Err(Error::InvalidFormat(format!(
+                "Expected JUMBF superbox, found '{}'",
+                String::from_utf8_lossy(&box_type)
+            )));
+        }
+        Self::parse_body(body, 0, max_depth)
+    }
+
+    fn parse_body(body: &[u8], depth: usize, max_depth: usize) -> Result<Self> {
+        if depth >= max_depth {
+            return Err(Error::InvalidFormat(
+                "JUMBF superboxes nested too deeply".into(),
+            ));
+        }
+        let (box_type, desc, mut pos) = read_box(body, 0)?;
+        if box_type != *b"jumd" {
+            return Err(Error::InvalidFormat(
+                "JUMBF superbox does not start with a description box".into(),
+            ));
+        }
+        let description = JumbfDescription::parse(desc, true)?;
+
+        let mut children = Vec::new();
+        while pos < body.len() {
+            let (box_type, child, next) = read_box(body, pos)?;
+            children.push(match &box_type {
+                b"jumb" => JumbfNode::SuperBox(Self::parse_body(child, depth + 1, max_depth)?),
+                _ => JumbfNode::Content(JumbfContent::parse(box_type, child)?),
+            });
+            pos = next;
+        }
+        Ok(Self {
+            description,
+            children,
+        })
+    }
+
+    /// The description label
+    pub fn label(&self) -> Option<&str> {
+        self.description.label.as_deref()
+    }
+
+    /// Nested superboxes, in file order
+    pub fn superboxes(&self) -> impl Iterator<Item = &JumbfSuperBox> {
+        self.children.iter().filter_map(|node| match node {
+            JumbfNode::SuperBox(superbox) => Some(superbox),
+            JumbfNode::Content(_) => None,
+        })
+    }
+
+    /// Content boxes, in file order
+    pub fn contents(&self) -> impl Iterator<Item = &JumbfContent> {
+        self.children.iter().filter_map(|node| match node {
+            JumbfNode::Content(content) => Some(content),
+            JumbfNode::SuperBox(_) => None,
+        })
+    }
+
+    /// The first nested superbox with the given label
+    pub fn child(&self, label: &str) -> Option<&JumbfSuperBox> {
+        self.superboxes().find(|b| b.label() == Some(label))
+    }
+
+    /// The superbox at a `/`-separated path of labels below this one
+    ///
+    /// Accepts JUMBF URIs such as `self#jumbf=/c2pa/<manifest>/c2pa.claim` when
+    /// called on the manifest store, whose own label is then skipped.
+    pub fn find(&self, path: &str) -> Option<&JumbfSuperBox> {
+        let path = path.strip_prefix("self#jumbf=").unwrap_or(path);
+        let mut labels = path.split('/').filter(|l| !l.is_empty()).peekable();
+        if path.starts_with('/') && labels.peek().copied() == self.label() {
+            labels.next();
+        }
+        labels.try_fold(self, |superbox, label| superbox.child(label))
+    }
+
+    /// Whether this is a C2PA manifest store
+    pub fn is_c2pa_store(&self) -> bool {
+        is_c2pa_description(self.label(), &self.description.content_type)
+    }
+
+    /// The C2PA manifests of a manifest store, oldest first
+    pub fn manifests(&self) -> impl Iterator<Item = &JumbfSuperBox> {
+        self.superboxes().filter(|b| {
+            matches!(
+                b.description.content_type,
+                C2PA_MANIFEST_UUID | C2PA_UPDATE_MANIFEST_UUID
+            )
+        })
+    }
+
+    /// The active manifest of a C2PA manifest store (the last one)
+    pub fn active_manifest(&self) -> Option<&JumbfSuperBox> {
+        self.manifests().last()
+    }
+}
+
+impl JumbfDescription {
+    /// Parse a `jumd` body
+    ///
+    /// Without `with_private` the private box is skipped, so a description cut
+    /// short after its fixed fields still parses.
+    fn parse(body: &[u8], with_private: bool) -> Result<Self> {
+        let truncated = || Error::InvalidFormat("JUMBF description box is truncated".into());
+        let content_type: [u8; 16] = body.get(..16).ok_or_else(truncated)?.try_into().unwrap();
+        let toggles = *body.get(16).ok_or_else(truncated)?;
+        let mut pos = 17;
+
+        let label =
+            if toggles & LABEL_PRESENT != 0 {
+                let text = body.get(pos..).ok_or_else(truncated)?;
+                let end = text.iter().position(|&b| b == 0).ok_or_else(truncated)?;
+                pos += end + 1;
+                Some(String::from_utf8(text[..end].to_vec()).map_err(|_| {
+                    Error::InvalidFormat("JUMBF description label is not UTF-8".into())
+                })?)
+            } else {
+                None
+            };
+        let id = if toggles & ID_PRESENT != 0 {
+            let bytes = body.get(pos..pos + 4).ok_or_else(truncated)?;
+            pos += 4;
+            Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+        } else {
+            None
+        };
+        let hash = if toggles & HASH_PRESENT != 0 {
+            let bytes = body.get(pos..pos + 32).ok_or_else(truncated)?;
+            pos += 32;
+            Some(bytes.try_into().unwrap())
+        } else {
+            None
+        };
+        let private = if with_private && toggles & PRIVATE_PRESENT != 0 {
+            let (box_type, data, _) = read_box(body, pos)?;
+            Some(JumbfContent::parse(box_type, data)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            content_type,
+            requestable: toggles & REQUESTABLE != 0,
+            label,
+            id,
+            hash,
+            private,
+        })
+    }
+}
+
+impl JumbfContent {
+    fn parse(box_type: [u8; 4], data: &[u8]) -> Result<Self> {
+        Ok(match &box_type {
+            b"json" => Self::Json(data.to_vec()),
+            b"cbor" => Self::Cbor(data.to_vec()),
+            b"bidb" => Self::BinaryData(data.to_vec()),
+            b"uuid" if data.len() >= 16 => Self::Uuid {
+                uuid: data[..16].try_into().unwrap(),
+                data: data[16..].to_vec(),
+            },
+            b"bfdb" if !data.is_empty() => {
+                let toggles = data[0];
+                let mut strings = data[1..].split(|&b| b == 0);
+                let mut next = || {
+                    strings
+                        .next()
+                        .map(|s| String::from_utf8_lossy(s).into_owned())
+                };
+                let media_type = next().unwrap_or_default();
+                let file_name = if toggles & FILE_NAME_PRESENT != 0 {
+                    next()
+                } else {
+                    None
+                };
+                Self::FileDescription {
+                    media_type,
+                    file_name,
+                    external: toggles & EXTERNAL_REFERENCE != 0,
+                }
+            }
+            _ => Self::Other {
+                box_type,
+                data: data.to_vec(),
+            },
+        })
+    }
+
+    /// The box type
+    pub fn box_type(&self) -> [u8; 4] {
+        match self {
+            Self::Json(_) => *b"json",
+            Self::Cbor(_) => *b"cbor",
+            Self::FileDescription { .. } => *b"bfdb",
+            Self::BinaryData(_) => *b"bidb",
+            Self::Uuid { .. } => *b"uuid",
+            Self::Other { box_type, .. } => *box_type,
+        }
+    }
+}
+
+/// Read the box at `pos`: its type, body, and the position after it
+///
+/// A size of 0 extends the box to the end of `data`.
+fn read_box(data: &[u8], pos: usize) -> Result<([u8; 4], &[u8], usize)> {
+    let invalid = |reason: &str| {
+        Error::InvalidFormat(format!("Invalid JUMBF box at offset {}: {}", pos, reason))
+    };
+    let header = data
+        .get(pos..pos + 8)
+        .ok_or_else(|| invalid("truncated header"))?;
+    let box_type: [u8; 4] = header[4..8].try_into().unwrap();
+    let (header_size, size) = match u32::from_be_bytes(header[0..4].try_into().unwrap()) {
+        0 => (8, (data.len() - pos) as u64),
+        1 => {
+            let large = data
+                .get(pos + 8..pos + 16)
+                .ok_or_else(|| invalid("truncated header"))?;
+            (16, u64::from_be_bytes(large.try_into().unwrap()))
+        }
+        size => (8, size as u64),
+    };
+    if size < header_size as u64 || size > (data.len() - pos) as u64 {
+        return Err(invalid(&format!("size {} out of bounds", size)));
+    }
+    let end = pos + size as usize;
+    Ok((box_type, &data[pos + header_size..end], end))
+}
+
+/// Read the label and content type of a JUMBF superbox
+///
+/// Only needs the start of the superbox. Returns `None` unless `data` starts
+/// with a `jumb` superbox whose first child is a well-formed `jumd` description box.
+pub(crate) fn describe(data: &[u8]) -> Option<(Option<String>, [u8; 16])> {
+    let superbox = head_body(data, b"jumb")?;
+    let desc = head_body(superbox, b"jumd")?;
+    let description = JumbfDescription::parse(desc, false).ok()?;
+    Some((description.label, description.content_type))
+}
+
+/// The body of the box at the start of `data`, if it has type `box_type`
+///
+/// The body is cut at the box size, or at the end of `data` when the box
+/// extends past it (only the start of a segment is read).
+fn head_body<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    if data.get(4..8)? != box_type {
+        return None;
+    }
+    let size = u32::from_be_bytes(data[0..4].try_into().ok()?) as u64;
+    let (header, size) = match size {
+        0 => (8, data.len() as u64),
+        1 => (16, u64::from_be_bytes(data.get(8..16)?.try_into().ok()?)),
+        size => (8, size),
+    };
+    if size < header as u64 {
+        return None;
+    }
+    let end = (size.min(data.len() as u64) as usize).max(header);
+    data.get(header..end)
+}
+
+/// Whether a description identifies the C2PA manifest store
+pub(crate) fn is_c2pa_description(label: Option<&str>, content_type: &[u8; 16]) -> bool {
+    label == Some(C2PA_LABEL) || *content_type == C2PA_STORE_UUID
+}
+
+/// Attach the description of every JUMBF segment as segment metadata
+///
+/// Called once after parsing. Segments whose data is not a described superbox
+/// are left without metadata.
+pub(crate) fn describe_segments<R: Read + Seek>(
+    structure: &mut Structure,
+    source: &mut R,
+) -> Result<()> {
+    for index in structure.jumbf_indices().to_vec() {
+        let segment = &structure.segments[index];
+        if segment.metadata.is_some() {
+            continue;
+        }
+        let Some(range) = segment.ranges.first() else {
+            continue;
+        };
+        let mut head = Vec::new();
+        source.seek(SeekFrom::Start(range.offset))?;
+        source
+            .by_ref()
+            .take(range.size.min(DESCRIPTION_READ_SIZE))
+            .read_to_end(&mut head)?;
+        if let Some((label, content_type)) = describe(&head) {
+            structure.segments[index].metadata = Some(SegmentMetadata::JumbfDescription {
+                label,
+                content_type,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Fail when `updates` adds or removes JUMBF boxes other than the C2PA manifest store
+///
+/// Used by containers that can only embed the C2PA manifest store.
+pub(crate) fn reject_labeled_boxes(updates: &Updates, container: ContainerKind) -> Result<()> {
+    match updates.jumbf_boxes.first() {
+        Some((label, _)) => Err(Error::Unsupported(format!(
+            "{:?} files cannot hold JUMBF boxes other than the C2PA manifest store (label '{}')",
+            container, label
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_box(box_type: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(box_type);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn superbox(content_type: [u8; 16], label: Option<&str>, children: &[Vec<u8>]) -> Vec<u8> {
+        let mut desc = content_type.to_vec();
+        match label {
+            Some(label) => {
+                desc.push(REQUESTABLE | LABEL_PRESENT);
+                desc.extend_from_slice(label.as_bytes());
+                desc.push(0);
+            }
+            None => desc.push(REQUESTABLE),
+        }
+        let mut body = make_box(b"jumd", &desc);
+        for child in children {
+            body.extend_from_slice(child);
+        }
+        make_box(b"jumb", &body)
+    }
+
+    /// A manifest store with two manifests, the second one active
+    fn manifest_store() -> Vec<u8> {
+        let manifest = |label: &str| {
+            let hash_data = superbox(
+                c2pa_uuid(*b"cbor"),
+                Some("c2pa.hash.data"),
+                &[make_box(b"cbor", &[0xA0])],
+            );
+            let assertions = superbox(c2pa_uuid(*b"c2as"), Some("c2pa.assertions"), &[hash_data]);
+            let claim = superbox(
+                c2pa_uuid(*b"c2cl"),
+                Some("c2pa.claim"),
+                &[make_box(b"json", b"{}")],
+            );
+            superbox(C2PA_MANIFEST_UUID, Some(label), &[assertions, claim])
+        };
+        superbox(
+            C2PA_STORE_UUID,
+            Some("c2pa"),
+            &[manifest("urn:uuid:first"), manifest("urn:uuid:second")],
+        )
+    }
+
+    #[test]
+    fn test_describe_c2pa_store() {
+        let data = manifest_store();
+        let (label, content_type) = describe(&data).unwrap();
+        assert_eq!(label.as_deref(), Some("c2pa"));
+        assert!(is_c2pa_description(label.as_deref(), &content_type));
+        // The start of the store is enough
+        assert_eq!(describe(&data[..60]).unwrap().0.as_deref(), Some("c2pa"));
+    }
+
+    #[test]
+    fn test_describe_other_boxes() {
+        let data = superbox([7; 16], Some("jpeg360"), &[]);
+        let (label, content_type) = describe(&data).unwrap();
+        assert_eq!(label.as_deref(), Some("jpeg360"));
+        assert!(!is_c2pa_description(label.as_deref(), &content_type));
+
+        let (label, content_type) = describe(&superbox([7; 16], None, &[])).unwrap();
+        assert_eq!(label, None);
+        assert_eq!(content_type, [7; 16]);
+
+        assert!(describe(b"placeholder bytes").is_none());
+        // Label without its terminator
+        let mut truncated = superbox([7; 16], Some("label"), &[]);
+        truncated.pop();
+        assert!(describe(&truncated).is_none());
+    }
+
+    #[test]
+    fn test_parse_manifest_store() {
+        let store = JumbfSuperBox::parse(&manifest_store()).unwrap();
+        assert!(store.is_c2pa_store());
+        assert!(store.description.requestable);
+        let labels: Vec<_> = store.manifests().map(|m| m.label().unwrap()).collect();
+        assert_eq!(labels, ["urn:uuid:first", "urn:uuid:second"]);
+        assert_eq!(
+            store.active_manifest().unwrap().label(),
+            Some("urn:uuid:second")
+        );
+
+        let hash = store
+            .find("self#jumbf=/c2pa/urn:uuid:second/c2pa.assertions/c2pa.hash.data")
+            .unwrap();
+        assert_eq!(
+            hash.contents().collect::<Vec<_>>(),
+            [&JumbfContent::Cbor(vec![0xA0])]
+        );
+        let claim = store.find("urn:uuid:first/c2pa.claim").unwrap();
+        assert_eq!(claim.contents().next().unwrap().box_type(), *b"json");
+        assert!(store.find("urn:uuid:first/missing").is_none());
+    }
+
+    #[test]
+    fn test_parse_optional_description_fields() {
+        let mut desc = [9u8; 16].to_vec();
+        desc.push(REQUESTABLE | LABEL_PRESENT | ID_PRESENT | HASH_PRESENT | PRIVATE_PRESENT);
+        desc.extend_from_slice(b"thumbnail\0");
+        desc.extend_from_slice(&42u32.to_be_bytes());
+        desc.extend_from_slice(&[0xAB; 32]);
+        desc.extend_from_slice(&make_box(b"dbox", b"private"));
+
+        let mut bfdb = vec![FILE_NAME_PRESENT];
+        bfdb.extend_from_slice(b"image/jpeg\0thumb.jpg\0");
+        let mut body = make_box(b"jumd", &desc);
+        body.extend_from_slice(&make_box(b"bfdb", &bfdb));
+        body.extend_from_slice(&make_box(b"bidb", &[0xFF, 0xD8]));
+        let mut uuid = [3u8; 16].to_vec();
+        uuid.extend_from_slice(b"payload");
+        body.extend_from_slice(&make_box(b"uuid", &uuid));
+
+        let superbox = JumbfSuperBox::parse(&make_box(b"jumb", &body)).unwrap();
+        let description = &superbox.description;
+        assert_eq!(description.label.as_deref(), Some("thumbnail"));
+        assert_eq!(description.id, Some(42));
+        assert_eq!(description.hash, Some([0xAB; 32]));
+        assert_eq!(
+            description.private,
+            Some(JumbfContent::Other {
+                box_type: *b"dbox",
+                data: b"private".to_vec()
+            })
+        );
+        let contents: Vec<_> = superbox.contents().cloned().collect();
+        assert_eq!(
+            contents,
+            [
+                JumbfContent::FileDescription {
+                    media_type: "image/jpeg".into(),
+                    file_name: Some("thumb.jpg".into()),
+                    external: false,
+                },
+                JumbfContent::BinaryData(vec![0xFF, 0xD8]),
+                JumbfContent::Uuid {
+                    uuid: [3; 16],
+                    data: b"payload".to_vec()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_boxes() {
+        let store = manifest_store();
+        assert!(JumbfSuperBox::parse(&store[..store.len() - 1]).is_err());
+        assert!(JumbfSuperBox::parse(&make_box(b"jumb", &make_box(b"json", b"{}"))).is_err());
+        assert!(JumbfSuperBox::parse(b"placeholder bytes").is_err());
+
+        let mut nested = superbox([1; 16], None, &[]);
+        for _ in 0..DEFAULT_MAX_BOX_DEPTH {
+            nested = superbox([1; 16], None, &[nested]);
+        }
+        assert!(JumbfSuperBox::parse(&nested).is_err());
+    }
+}
@@ -85,11 +85,23 @@
 //! ```
 
 mod asset;
+#[cfg(feature = "async")]
+mod async_asset;
+mod capture_time;
 mod containers;
+mod detect;
+mod diagnostic;
 mod error;
+mod jumbf;
+mod limits;
+mod media_info;
 mod media_type;
+#[cfg(feature = "parallel")]
+mod merkle;
 mod processing_writer;
+mod range_source;
 mod segment;
+mod stream;
 mod structure;
 mod thumbnail;
 #[cfg(feature = "exif")]
@@ -100,27 +112,52 @@ mod xmp;
 
 // Public exports
 pub use asset::{Asset, AssetBuilder};
+#[cfg(feature = "async")]
+pub use async_asset::{AsyncAsset, AsyncChunk};
+pub use capture_time::{CaptureTime, CaptureTimeSource};
 #[cfg(feature = "bmff")]
 pub use containers::bmff_io::{bmff_adjust_chunk_offsets, BmffFragment, BmffIO};
+#[cfg(all(feature = "bmff", feature = "parallel"))]
+pub use containers::bmff_io::{BmffMerkleMap, BmffMerkleMaps, MerkleBlocks};
+#[cfg(feature = "bmff")]
+pub use containers::bmff_tree::{BmffBox, BmffTree, BoxId};
+pub use containers::custom::{
+    register_container, unregister_container, CustomContainerIO, CustomFormat, ReadSeek,
+};
+#[cfg(feature = "bmff")]
+pub use containers::quicktime_meta::{
+    QuickTimeKey, QuickTimeMetadata, QuickTimeValue, QUICKTIME_CREATION_DATE_KEY,
+    QUICKTIME_LOCATION_KEY,
+};
+#[cfg(feature = "riff")]
+pub use containers::wav_meta::{BextChunk, WavMetadata};
 pub use containers::ContainerKind;
-pub use error::{Error, Result};
+pub use detect::{sniff, sniff_source, Candidate, Confidence, Detection, KnownFormat, SNIFF_LEN};
+pub use diagnostic::{Diagnostic, Severity, ValidationReport};
+pub use error::{Error, ErrorContext, ErrorKind, Result};
+pub use jumbf::{JumbfBox, JumbfContent, JumbfDescription, JumbfNode, JumbfSuperBox};
+pub use limits::Limits;
+pub use media_info::{MediaInfo, TrackInfo};
+pub use media_type::MediaType;
+#[cfg(feature = "parallel")]
+pub use merkle::{MerkleOddNode, MerkleTree};
 pub use processing_writer::{MdatChunk, ProcessChunk, ProcessChunkFn, ReadChunkFn, SimpleChunk};
+pub use range_source::{RangeReader, RangeSource};
 #[cfg(feature = "parallel")]
 pub use segment::merkle_root;
 pub use segment::{ByteRange, ChunkSpec, ExclusionMode, ProcessingChunk, Segment, SegmentKind};
+pub use stream::AssetStream;
 pub use structure::Structure;
 pub use thumbnail::{Thumbnail, ThumbnailKind};
 #[cfg(feature = "exif")]
 pub use tiff::ExifInfo;
-pub use updates::Updates;
+pub use updates::{MetadataUpdate, Updates};
 #[cfg(feature = "xmp")]
 pub use xmp::MiniXmp;
 
 // Internal re-exports
 pub(crate) use containers::{detect_container, get_handler, Handler};
-pub(crate) use media_type::MediaType;
 pub(crate) use segment::{ChunkedSegmentReader, SegmentMetadata};
-pub(crate) use updates::MetadataUpdate;
 
 // Test utilities - only compiled for tests or when explicitly enabled
 #[cfg(any(test, feature = "test-utils"))]
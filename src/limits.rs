@@ -0,0 +1,187 @@
+//! Per-asset parse limits
+//!
+//! Limits bound what an untrusted file can make the parser do: how much it
+//! allocates for a single segment, how deeply boxes nest and how many segments
+//! it records, and whether recoverable damage fails the parse. They are
+//! configured through [`AssetBuilder`](crate::AssetBuilder) and carried by each
+//! container handler.
+
+use crate::{
+    diagnostic::Diagnostic,
+    error::{Error, Result},
+    segment::MAX_SEGMENT_SIZE,
+    structure::Structure,
+};
+
+/// Default maximum nesting depth of BMFF and JUMBF boxes
+pub(crate) const DEFAULT_MAX_BOX_DEPTH: usize = 32;
+
+/// Default maximum number of segments in one asset
+pub(crate) const DEFAULT_MAX_SEGMENT_COUNT: usize = 1_000_000;
+
+/// Default maximum size of an XMP packet (100 MB)
+pub(crate) const DEFAULT_MAX_XMP_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Limits applied while parsing and reading an asset
+///
+/// Set through [`AssetBuilder`](crate::AssetBuilder); handlers registered with
+/// [`register_container`](crate::register_container) get them in
+/// [`CustomContainerIO::parse`](crate::CustomContainerIO::parse).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Largest segment or box read into memory at once
+    pub(crate) max_segment_size: u64,
+    /// Deepest nesting of BMFF boxes and JUMBF superboxes
+    pub(crate) max_box_depth: usize,
+    /// Most segments recorded in a structure
+    pub(crate) max_segment_count: usize,
+    /// Largest XMP packet, including JPEG extended XMP
+    pub(crate) max_xmp_size: u64,
+    /// Fail on recoverable damage instead of recording it as a diagnostic
+    pub(crate) strict: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_segment_size: MAX_SEGMENT_SIZE,
+            max_box_depth: DEFAULT_MAX_BOX_DEPTH,
+            max_segment_count: DEFAULT_MAX_SEGMENT_COUNT,
+            max_xmp_size: DEFAULT_MAX_XMP_SIZE,
+            strict: true,
+        }
+    }
+}
+
+impl Limits {
+    /// Largest segment or box read into memory at once
+    pub fn max_segment_size(&self) -> u64 {
+        self.max_segment_size
+    }
+
+    /// Deepest nesting of boxes
+    pub fn max_box_depth(&self) -> usize {
+        self.max_box_depth
+    }
+
+    /// Most segments recorded in a structure
+    pub fn max_segment_count(&self) -> usize {
+        self.max_segment_count
+    }
+
+    /// Largest XMP packet
+    pub fn max_xmp_size(&self) -> u64 {
+        self.max_xmp_size
+    }
+
+    /// Whether recoverable damage fails the parse
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Fail if reading `size` bytes at `offset` into memory exceeds the segment size limit
+    pub fn check_segment_size(&self, offset: u64, size: u64, what: &str) -> Result<()> {
+        if size > self.max_segment_size {
+            return Err(Error::LimitExceeded {
+                offset,
+                reason: format!(
+                    "{} too large: {} bytes (max {} bytes)",
+                    what, size, self.max_segment_size
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Fail once a structure holds more segments than allowed
+    pub fn check_segment_count(&self, structure: &Structure) -> Result<()> {
+        if structure.segments.len() > self.max_segment_count {
+            let offset = structure
+                .segments
+                .last()
+                .map(|segment| segment.location().offset)
+                .unwrap_or(0);
+            return Err(Error::LimitExceeded {
+                offset,
+                reason: format!("Too many segments (max {})", self.max_segment_count),
+            });
+        }
+        Ok(())
+    }
+
+    /// Fail with `error` when strict, otherwise record it on the structure
+    ///
+    /// Callers recover (truncate, clamp or skip) after this returns `Ok`.
+    pub fn recover(&self, structure: &mut Structure, path: &str, error: Error) -> Result<()> {
+        let offset = error.offset().unwrap_or(0);
+        if self.strict {
+            return Err(error.at(offset, path));
+        }
+        structure.add_diagnostic(Diagnostic::error(offset, path, error.reason()));
+        Ok(())
+    }
+
+    /// Size of the structure's XMP packet, if it exceeds the XMP size limit
+    pub(crate) fn oversized_xmp(&self, structure: &Structure) -> Option<(u64, u64)> {
+        let segment = &structure.segments[structure.xmp_index()?];
+        let size: u64 = segment.ranges.iter().map(|range| range.size).sum();
+        (size > self.max_xmp_size).then(|| (segment.location().offset, size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{containers::ContainerKind, segment::Segment, MediaType, SegmentKind};
+
+    #[test]
+    fn test_segment_limits() {
+        let limits = Limits {
+            max_segment_size: 100,
+            max_segment_count: 2,
+            max_xmp_size: 10,
+            ..Limits::default()
+        };
+        assert!(limits.check_segment_size(0, 100, "Chunk").is_ok());
+        let error = limits.check_segment_size(0, 101, "Chunk").unwrap_err();
+        assert_eq!(error.kind(), crate::ErrorKind::LimitExceeded);
+
+        let mut structure = Structure::new(ContainerKind::Jpeg, MediaType::Jpeg);
+        structure.add_segment(Segment::new(0, 2, SegmentKind::Header, None));
+        structure.add_segment(Segment::new(2, 20, SegmentKind::Xmp, None));
+        assert!(limits.check_segment_count(&structure).is_ok());
+        assert_eq!(limits.oversized_xmp(&structure), Some((2, 20)));
+
+        structure.add_segment(Segment::new(22, 2, SegmentKind::Other, None));
+        assert!(limits.check_segment_count(&structure).is_err());
+    }
+
+    fn truncated() -> Error {
+        Error::Truncated {
+            offset: 4,
+            reason: "Scan data cut short".into(),
+        }
+    }
+
+    #[test]
+    fn test_recover() {
+        let mut structure = Structure::new(ContainerKind::Jpeg, MediaType::Jpeg);
+        let strict = Limits::default();
+        let error = strict
+            .recover(&mut structure, "SOS", truncated())
+            .unwrap_err();
+        assert_eq!(error.kind(), crate::ErrorKind::Truncated);
+        assert_eq!(error.context().unwrap().path.as_deref(), Some("SOS"));
+        assert!(structure.diagnostics().is_empty());
+
+        let lenient = Limits {
+            strict: false,
+            ..Limits::default()
+        };
+        lenient.recover(&mut structure, "SOS", truncated()).unwrap();
+        let diagnostic = &structure.diagnostics()[0];
+        assert_eq!(diagnostic.severity, crate::Severity::Error);
+        assert_eq!((diagnostic.offset, diagnostic.path.as_str()), (4, "SOS"));
+        assert_eq!(diagnostic.message, "Scan data cut short");
+    }
+}
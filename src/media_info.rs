@@ -0,0 +1,121 @@
+//! Technical media properties
+//!
+//! [`MediaInfo`] collects the basic technical properties of an asset in one
+//! container-agnostic struct. Each container fills it from its own headers:
+//!
+//! - JPEG: SOFn frame header (dimensions, precision, components, progressive)
+//! - PNG: IHDR (dimensions, bit depth, color type, interlace)
+//! - WebP: VP8X canvas, or the VP8/VP8L frame header
+//! - HEIF/AVIF: `ispe` and `pixi` properties of the primary item
+//! - MP4/MOV: `mvhd`, and `tkhd`/`mdhd`/`hdlr`/`stsd` for each track
+//! - WAV: `fmt ` chunk (channels, sample rate, bits per sample)
+//! - AVI: `avih` main header (dimensions, frame count, duration)
+//!
+//! Fields a format does not record are left as `None`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use asset_io::Asset;
+//!
+//! # fn main() -> asset_io::Result<()> {
+//! let mut asset = Asset::open("video.mp4")?;
+//! let info = asset.media_info()?;
+//! if let (Some(w), Some(h)) = (info.width, info.height) {
+//!     println!("{}x{}", w, h);
+//! }
+//! for track in &info.tracks {
+//!     println!("track {}: {} {:?}", track.id, track.handler, track.codec);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+/// Technical properties of an asset's media
+///
+/// Use `Asset::media_info()` to obtain one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    /// Image or video width in pixels
+    pub width: Option<u32>,
+
+    /// Image or video height in pixels
+    pub height: Option<u32>,
+
+    /// Bits per sample (JPEG precision, PNG bit depth, HEIF `pixi`, WAV bits per sample)
+    pub bit_depth: Option<u16>,
+
+    /// Number of color components (JPEG, PNG, HEIF `pixi`)
+    pub components: Option<u8>,
+
+    /// PNG color type from IHDR
+    pub color_type: Option<u8>,
+
+    /// True for progressive JPEG or interlaced (Adam7) PNG
+    pub progressive: Option<bool>,
+
+    /// Duration of timed media
+    pub duration: Option<Duration>,
+
+    /// Movie timescale (units per second) from `mvhd`
+    pub timescale: Option<u32>,
+
+    /// Codec of the image or first track, e.g. `"avc1"`, `"hvc1"`, `"VP8L"` or `"pcm"`
+    pub codec: Option<String>,
+
+    /// Number of audio channels
+    pub channels: Option<u16>,
+
+    /// Audio sample rate in Hz
+    pub sample_rate: Option<u32>,
+
+    /// Number of video frames (AVI)
+    pub frame_count: Option<u32>,
+
+    /// Tracks of a movie, in file order
+    pub tracks: Vec<TrackInfo>,
+}
+
+/// Technical properties of one track in a movie
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackInfo {
+    /// Track id from `tkhd`
+    pub id: u32,
+
+    /// Handler type from `hdlr`, e.g. `"vide"` or `"soun"`
+    pub handler: String,
+
+    /// Format of the first sample description, e.g. `"avc1"` or `"mp4a"`
+    pub codec: Option<String>,
+
+    /// Track duration from `mdhd`
+    pub duration: Option<Duration>,
+
+    /// Media timescale (units per second) from `mdhd`
+    pub timescale: Option<u32>,
+
+    /// Presentation width in pixels from `tkhd` (visual tracks)
+    pub width: Option<u32>,
+
+    /// Presentation height in pixels from `tkhd` (visual tracks)
+    pub height: Option<u32>,
+
+    /// Number of audio channels (audio tracks)
+    pub channels: Option<u16>,
+
+    /// Audio sample rate in Hz (audio tracks)
+    pub sample_rate: Option<u32>,
+}
+
+/// Convert a count of `timescale` units to a [`Duration`]
+#[cfg(any(feature = "bmff", feature = "riff"))]
+pub(crate) fn duration_from_units(units: u64, timescale: u32) -> Option<Duration> {
+    if timescale == 0 {
+        return None;
+    }
+    let secs = units / timescale as u64;
+    let nanos = (units % timescale as u64) * 1_000_000_000 / timescale as u64;
+    Some(Duration::new(secs, nanos as u32))
+}
@@ -2,7 +2,7 @@
 //!
 //! This module defines specific media types that can be stored in various container formats.
 
-use crate::containers::ContainerKind;
+use crate::containers::{custom, custom::CustomFormat, ContainerKind};
 
 /// Specific media type - what the content represents
 ///
@@ -52,17 +52,21 @@ pub enum MediaType {
     #[cfg(feature = "riff")]
     /// AVI video (RIFF/AVI container)
     Avi,
+
+    /// Content of a container added at runtime with
+    /// [`register_container`](crate::register_container)
+    Custom(&'static CustomFormat),
 }
 
 impl MediaType {
     /// Get all media types that are available in this build
     ///
     /// Returns a slice of all `MediaType` variants that were compiled in
-    /// based on the enabled features.
+    /// based on the enabled features. Registered custom formats are not included.
     ///
     /// # Example
     ///
-    /// ```ignore
+    /// ```
     /// use asset_io::MediaType;
     ///
     /// let supported = MediaType::all();
@@ -102,7 +106,7 @@ impl MediaType {
     ///
     /// # Example
     ///
-    /// ```ignore
+    /// ```
     /// # #[cfg(feature = "jpeg")]
     /// # {
     /// use asset_io::MediaType;
@@ -126,6 +130,7 @@ impl MediaType {
             | MediaType::QuickTime => ContainerKind::Bmff,
             #[cfg(feature = "riff")]
             MediaType::WebP | MediaType::Wav | MediaType::Avi => ContainerKind::Riff,
+            MediaType::Custom(format) => ContainerKind::Custom(format),
         }
     }
 
@@ -133,7 +138,7 @@ impl MediaType {
     ///
     /// # Example
     ///
-    /// ```ignore
+    /// ```
     /// # #[cfg(feature = "jpeg")]
     /// # {
     /// use asset_io::MediaType;
@@ -166,6 +171,11 @@ impl MediaType {
             MediaType::Wav => "audio/wav",
             #[cfg(feature = "riff")]
             MediaType::Avi => "video/avi",
+            MediaType::Custom(format) => format
+                .mime_types
+                .first()
+                .copied()
+                .unwrap_or("application/octet-stream"),
         }
     }
 
@@ -173,7 +183,7 @@ impl MediaType {
     ///
     /// # Example
     ///
-    /// ```ignore
+    /// ```
     /// # #[cfg(feature = "jpeg")]
     /// # {
     /// use asset_io::MediaType;
@@ -206,8 +216,120 @@ impl MediaType {
             MediaType::Wav => "wav",
             #[cfg(feature = "riff")]
             MediaType::Avi => "avi",
+            MediaType::Custom(format) => format.extensions.first().copied().unwrap_or(""),
+        }
+    }
+
+    /// Get all file extensions for this media type (without dot), primary first
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            #[cfg(feature = "jpeg")]
+            MediaType::Jpeg => &["jpg", "jpeg", "jpe", "jfif"],
+            #[cfg(feature = "png")]
+            MediaType::Png => &["png"],
+            #[cfg(feature = "bmff")]
+            MediaType::Heic => &["heic"],
+            #[cfg(feature = "bmff")]
+            MediaType::Heif => &["heif"],
+            #[cfg(feature = "bmff")]
+            MediaType::Avif => &["avif"],
+            #[cfg(feature = "bmff")]
+            MediaType::Mp4Video => &["mp4", "m4v"],
+            #[cfg(feature = "bmff")]
+            MediaType::Mp4Audio => &["m4a"],
+            #[cfg(feature = "bmff")]
+            MediaType::QuickTime => &["mov"],
+            #[cfg(feature = "riff")]
+            MediaType::WebP => &["webp"],
+            #[cfg(feature = "riff")]
+            MediaType::Wav => &["wav"],
+            #[cfg(feature = "riff")]
+            MediaType::Avi => &["avi"],
+            MediaType::Custom(format) => format.extensions,
         }
     }
+
+    /// Get all MIME types for this media type, primary first
+    pub fn mime_types(self) -> &'static [&'static str] {
+        match self {
+            #[cfg(feature = "jpeg")]
+            MediaType::Jpeg => &["image/jpeg", "image/jpg"],
+            #[cfg(feature = "png")]
+            MediaType::Png => &["image/png"],
+            #[cfg(feature = "bmff")]
+            MediaType::Heic => &["image/heic"],
+            #[cfg(feature = "bmff")]
+            MediaType::Heif => &["image/heif"],
+            #[cfg(feature = "bmff")]
+            MediaType::Avif => &["image/avif"],
+            #[cfg(feature = "bmff")]
+            MediaType::Mp4Video => &["video/mp4", "video/x-m4v", "application/mp4"],
+            #[cfg(feature = "bmff")]
+            MediaType::Mp4Audio => &["audio/mp4"],
+            #[cfg(feature = "bmff")]
+            MediaType::QuickTime => &["video/quicktime"],
+            #[cfg(feature = "riff")]
+            MediaType::WebP => &["image/webp"],
+            #[cfg(feature = "riff")]
+            MediaType::Wav => &["audio/wav", "audio/wave", "audio/x-wav", "audio/vnd.wave"],
+            #[cfg(feature = "riff")]
+            MediaType::Avi => &[
+                "video/avi",
+                "video/msvideo",
+                "video/x-msvideo",
+                "application/x-troff-msvideo",
+            ],
+            MediaType::Custom(format) => format.mime_types,
+        }
+    }
+
+    /// Look up the media type for a file extension (with or without dot, any case)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "jpeg")]
+    /// # {
+    /// use asset_io::MediaType;
+    ///
+    /// assert_eq!(MediaType::from_extension(".JPEG"), Some(MediaType::Jpeg));
+    /// # }
+    /// ```
+    pub fn from_extension(ext: &str) -> Option<MediaType> {
+        let ext = ext.trim_start_matches('.');
+        Self::all()
+            .iter()
+            .copied()
+            .find(|m| m.extensions().iter().any(|e| e.eq_ignore_ascii_case(ext)))
+            .or_else(|| {
+                custom::find_format(|f| f.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+                    .map(MediaType::Custom)
+            })
+    }
+
+    /// Look up the media type for a MIME type (any case, parameters ignored)
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "jpeg")]
+    /// # {
+    /// use asset_io::MediaType;
+    ///
+    /// assert_eq!(MediaType::from_mime("image/jpeg"), Some(MediaType::Jpeg));
+    /// # }
+    /// ```
+    pub fn from_mime(mime: &str) -> Option<MediaType> {
+        let mime = mime.split(';').next().unwrap_or_default().trim();
+        Self::all()
+            .iter()
+            .copied()
+            .find(|m| m.mime_types().iter().any(|t| t.eq_ignore_ascii_case(mime)))
+            .or_else(|| {
+                custom::find_format(|f| f.mime_types.iter().any(|t| t.eq_ignore_ascii_case(mime)))
+                    .map(MediaType::Custom)
+            })
+    }
 }
 
 impl std::fmt::Display for MediaType {
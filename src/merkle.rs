@@ -0,0 +1,291 @@
+//! Merkle trees with inclusion proofs
+//!
+//! [`MerkleTree`] keeps every level of the tree so that inclusion proofs can be
+//! generated for individual leaves, e.g. to validate a single fMP4 segment
+//! against a C2PA BmffHash Merkle map without downloading the whole asset.
+//!
+//! # Example
+//!
+//! ```
+//! use asset_io::{MerkleOddNode, MerkleTree};
+//! use sha2::{Digest, Sha256};
+//!
+//! let leaves: Vec<[u8; 32]> = [b"a", b"b", b"c"]
+//!     .iter()
+//!     .map(|d| Sha256::digest(d).into())
+//!     .collect();
+//!
+//! let tree = MerkleTree::<Sha256>::new(&leaves, MerkleOddNode::Promote);
+//! let proof = tree.proof(2);
+//! assert!(tree.verify(&leaves[2], 2, &proof, &tree.root()));
+//! ```
+
+use std::marker::PhantomData;
+
+/// How a node without a sibling is carried to the next level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MerkleOddNode {
+    /// Hash the node with itself (what [`merkle_root()`](crate::merkle_root) does)
+    #[default]
+    Duplicate,
+    /// Move the node up unchanged, as defined for C2PA BmffHash Merkle maps
+    Promote,
+}
+
+/// A Merkle tree that keeps all of its levels
+///
+/// Level 0 holds the leaves and the last level holds the root. Interior
+/// nodes are `H(left || right)`; nodes without a sibling are handled
+/// according to [`MerkleOddNode`]. `H` must produce 32-byte digests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleTree<H> {
+    levels: Vec<Vec<[u8; 32]>>,
+    odd_node: MerkleOddNode,
+    _hash: PhantomData<H>,
+}
+
+impl<H> MerkleTree<H>
+where
+    H: sha2::Digest<OutputSize = sha2::digest::consts::U32> + Default,
+{
+    /// Build a tree from leaf hashes
+    pub fn new(leaves: &[[u8; 32]], odd_node: MerkleOddNode) -> Self {
+        let mut levels = vec![leaves.to_vec()];
+
+        while levels[levels.len() - 1].len() > 1 {
+            let current = &levels[levels.len() - 1];
+            let next = current
+                .chunks(2)
+                .map(|pair| match (pair, odd_node) {
+                    ([left, right], _) => hash_pair::<H>(left, right),
+                    ([node], MerkleOddNode::Duplicate) => hash_pair::<H>(node, node),
+                    ([node], MerkleOddNode::Promote) => *node,
+                    _ => unreachable!("chunks(2) yields one or two nodes"),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Self {
+            levels,
+            odd_node,
+            _hash: PhantomData,
+        }
+    }
+
+    /// The root hash (all zeros for an empty tree, the leaf itself for a single leaf)
+    pub fn root(&self) -> [u8; 32] {
+        self.levels[self.levels.len() - 1]
+            .first()
+            .copied()
+            .unwrap_or([0u8; 32])
+    }
+
+    /// The leaf hashes
+    pub fn leaves(&self) -> &[[u8; 32]] {
+        &self.levels[0]
+    }
+
+    /// Number of leaves
+    pub fn leaf_count(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// All levels, from the leaves (index 0) up to the root
+    pub fn levels(&self) -> &[Vec<[u8; 32]>] {
+        &self.levels
+    }
+
+    /// The odd-node handling this tree was built with
+    pub fn odd_node(&self) -> MerkleOddNode {
+        self.odd_node
+    }
+
+    /// Generate the inclusion proof for the leaf at `index`
+    ///
+    /// The proof lists sibling hashes from the leaf level upwards. Levels where
+    /// the node has no sibling contribute the node itself with
+    /// [`MerkleOddNode::Duplicate`] and nothing with [`MerkleOddNode::Promote`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than [`leaf_count()`](Self::leaf_count).
+    pub fn proof(&self, index: usize) -> Vec<[u8; 32]> {
+        assert!(
+            index < self.leaf_count(),
+            "leaf index {} out of range ({} leaves)",
+            index,
+            self.leaf_count()
+        );
+
+        let mut proof = Vec::with_capacity(self.levels.len());
+        let mut i = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = i ^ 1;
+            if sibling < level.len() {
+                proof.push(level[sibling]);
+            } else if self.odd_node == MerkleOddNode::Duplicate {
+                proof.push(level[i]);
+            }
+            i /= 2;
+        }
+        proof
+    }
+
+    /// Verify a proof for `leaf` at `index` against `root`, using this tree's shape
+    pub fn verify(
+        &self,
+        leaf: &[u8; 32],
+        index: usize,
+        proof: &[[u8; 32]],
+        root: &[u8; 32],
+    ) -> bool {
+        Self::verify_proof(leaf, index, self.leaf_count(), proof, root, self.odd_node)
+    }
+
+    /// Verify a proof without the tree, given only the number of leaves
+    ///
+    /// This is what a streaming validator uses: the leaf count and root come
+    /// from the Merkle map, the leaf hash from the fetched chunk.
+    pub fn verify_proof(
+        leaf: &[u8; 32],
+        index: usize,
+        leaf_count: usize,
+        proof: &[[u8; 32]],
+        root: &[u8; 32],
+        odd_node: MerkleOddNode,
+    ) -> bool {
+        if index >= leaf_count {
+            return false;
+        }
+
+        let mut hash = *leaf;
+        let mut proof = proof.iter();
+        let mut i = index;
+        let mut width = leaf_count;
+
+        while width > 1 {
+            if i % 2 == 1 {
+                match proof.next() {
+                    Some(left) => hash = hash_pair::<H>(left, &hash),
+                    None => return false,
+                }
+            } else if i + 1 < width {
+                match proof.next() {
+                    Some(right) => hash = hash_pair::<H>(&hash, right),
+                    None => return false,
+                }
+            } else if odd_node == MerkleOddNode::Duplicate {
+                match proof.next() {
+                    Some(copy) if *copy == hash => hash = hash_pair::<H>(&hash, &hash),
+                    _ => return false,
+                }
+            }
+            i /= 2;
+            width = width.div_ceil(2);
+        }
+
+        proof.next().is_none() && hash == *root
+    }
+}
+
+fn hash_pair<H>(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32]
+where
+    H: sha2::Digest<OutputSize = sha2::digest::consts::U32>,
+{
+    let mut hasher = H::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn leaves(n: usize) -> Vec<[u8; 32]> {
+        (0..n)
+            .map(|i| Sha256::digest((i as u32).to_be_bytes()).into())
+            .collect()
+    }
+
+    #[test]
+    fn test_duplicate_hashes_odd_node_with_itself() {
+        let leaves = leaves(3);
+        let tree = MerkleTree::<Sha256>::new(&leaves, MerkleOddNode::Duplicate);
+        let left = hash_pair::<Sha256>(&leaves[0], &leaves[1]);
+        let right = hash_pair::<Sha256>(&leaves[2], &leaves[2]);
+        assert_eq!(tree.root(), hash_pair::<Sha256>(&left, &right));
+
+        assert_eq!(
+            MerkleTree::<Sha256>::new(&[], MerkleOddNode::Duplicate).root(),
+            [0u8; 32]
+        );
+        assert_eq!(
+            MerkleTree::<Sha256>::new(&leaves[..1], MerkleOddNode::Duplicate).root(),
+            leaves[0]
+        );
+    }
+
+    #[test]
+    fn test_merkle_root_matches_tree_and_truncates_wide_digests() {
+        for n in 0..9 {
+            let leaves = leaves(n);
+            let tree = MerkleTree::<Sha256>::new(&leaves, MerkleOddNode::Duplicate);
+            assert_eq!(crate::merkle_root::<Sha256>(&leaves), tree.root());
+        }
+
+        let leaves = leaves(2);
+        let wide = sha2::Sha512::new()
+            .chain_update(leaves[0])
+            .chain_update(leaves[1])
+            .finalize();
+        assert_eq!(crate::merkle_root::<sha2::Sha512>(&leaves)[..], wide[..32]);
+    }
+
+    #[test]
+    fn test_promote_carries_odd_node_up() {
+        let leaves = leaves(3);
+        let tree = MerkleTree::<Sha256>::new(&leaves, MerkleOddNode::Promote);
+        let left = hash_pair::<Sha256>(&leaves[0], &leaves[1]);
+        assert_eq!(tree.levels()[1], vec![left, leaves[2]]);
+        assert_eq!(tree.root(), hash_pair::<Sha256>(&left, &leaves[2]));
+    }
+
+    #[test]
+    fn test_proofs_verify_for_every_leaf() {
+        for odd_node in [MerkleOddNode::Duplicate, MerkleOddNode::Promote] {
+            for n in 1..20 {
+                let leaves = leaves(n);
+                let tree = MerkleTree::<Sha256>::new(&leaves, odd_node);
+                let root = tree.root();
+                for (i, leaf) in leaves.iter().enumerate() {
+                    let proof = tree.proof(i);
+                    assert!(tree.verify(leaf, i, &proof, &root), "n={} i={}", n, i);
+                    assert!(MerkleTree::<Sha256>::verify_proof(
+                        leaf, i, n, &proof, &root, odd_node
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_rejects_tampering() {
+        let leaves = leaves(7);
+        let tree = MerkleTree::<Sha256>::new(&leaves, MerkleOddNode::Promote);
+        let root = tree.root();
+        let proof = tree.proof(4);
+
+        assert!(!tree.verify(&leaves[5], 4, &proof, &root));
+        assert!(!tree.verify(&leaves[4], 5, &proof, &root));
+        assert!(!tree.verify(&leaves[4], 4, &proof[1..], &root));
+        assert!(!tree.verify(&leaves[4], 4, &proof, &leaves[0]));
+        assert!(!tree.verify(&leaves[4], 9, &proof, &root));
+
+        let mut longer = proof.clone();
+        longer.push(root);
+        assert!(!tree.verify(&leaves[4], 4, &longer, &root));
+    }
+}
@@ -76,6 +76,12 @@ impl ProcessChunk for SegmentBoundary<'_> {
 }
 
 /// BMFF mdat box chunk with format-specific metadata.
+///
+/// Sent for mdat bytes past the first 16 when BmffHash V3 hashing is active
+/// (the JUMBF is excluded from processing); those bytes are then left out of the
+/// plain chunks. Otherwise the same bytes also arrive as plain chunks, and mdat
+/// boxes streamed past the segment size limit are only sent as `MdatChunk`s by
+/// `Asset::write_with_merkle_maps`.
 #[derive(Debug)]
 pub struct MdatChunk<'a> {
     /// Index of the mdat box (0, 1, 2, ...).
@@ -192,7 +198,6 @@ where
     /// # Arguments
     ///
     /// * `exclude` - If true, temporarily disable processing. If false, re-enable.
-    #[allow(dead_code)] // Used by container handlers when they override write_with_processor
     pub fn set_exclude_mode(&mut self, exclude: bool) {
         self.exclude_mode = exclude;
     }
@@ -0,0 +1,361 @@
+//! Random access to remote assets through range requests
+//!
+//! [`RangeSource`] is the minimal interface of a remote object store or HTTP
+//! server: the total size, and the bytes of a given range. [`RangeReader`]
+//! turns one into a `Read + Seek` source with a block cache and read-ahead, so
+//! [`Asset::from_source`](crate::Asset::from_source) only fetches the blocks
+//! the parser touches: headers, metadata segments and box trees.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Default size of a cached block (64 KB)
+const DEFAULT_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Default number of cached blocks (4 MB with the default block size)
+const DEFAULT_CACHE_BLOCKS: usize = 64;
+
+/// Default number of blocks fetched ahead of sequential reads
+const DEFAULT_READ_AHEAD: usize = 4;
+
+/// A source of byte ranges, e.g. an object store or an HTTP server
+///
+/// # Example
+///
+/// ```no_run
+/// use asset_io::RangeSource;
+/// use std::io;
+///
+/// /// Ranges of an in-memory object
+/// struct Object(Vec<u8>);
+///
+/// impl RangeSource for Object {
+///     fn size(&mut self) -> io::Result<u64> {
+///         Ok(self.0.len() as u64)
+///     }
+///
+///     fn fetch(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+///         let start = (offset as usize).min(self.0.len());
+///         let end = start.saturating_add(len).min(self.0.len());
+///         Ok(self.0[start..end].to_vec())
+///     }
+/// }
+/// ```
+pub trait RangeSource {
+    /// Total size of the resource in bytes
+    ///
+    /// Called once, on the first seek from the end or read.
+    fn size(&mut self) -> io::Result<u64>;
+
+    /// Fetch `len` bytes starting at `offset`
+    ///
+    /// May return fewer bytes only at the end of the resource.
+    fn fetch(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>>;
+}
+
+impl<S: RangeSource + ?Sized> RangeSource for Box<S> {
+    fn size(&mut self) -> io::Result<u64> {
+        (**self).size()
+    }
+
+    fn fetch(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        (**self).fetch(offset, len)
+    }
+}
+
+/// A `Read + Seek` adapter over a [`RangeSource`] with a block cache
+///
+/// Reads are served from fixed-size blocks. Missing blocks are fetched with
+/// one range request per run of consecutive blocks, and sequential reads
+/// fetch a few blocks ahead. The least recently used blocks are evicted once
+/// the cache is full.
+///
+/// [`bytes_fetched`](Self::bytes_fetched) and [`requests`](Self::requests)
+/// report the traffic so far.
+///
+/// # Example
+///
+/// ```no_run
+/// use asset_io::{Asset, RangeReader, RangeSource};
+///
+/// # fn example(source: impl RangeSource) -> asset_io::Result<()> {
+/// let mut asset = Asset::from_source(RangeReader::new(source))?;
+/// let manifest = asset.jumbf()?;
+///
+/// let reader = asset.source_mut();
+/// println!("fetched {} bytes in {} requests", reader.bytes_fetched(), reader.requests());
+/// # Ok(())
+/// # }
+/// ```
+pub struct RangeReader<S: RangeSource> {
+    source: S,
+    size: Option<u64>,
+    pos: u64,
+    block_size: usize,
+    cache_blocks: usize,
+    read_ahead: usize,
+    blocks: HashMap<u64, Vec<u8>>,
+    /// Cached block indices, least recently used first
+    lru: VecDeque<u64>,
+    /// Last block read, to detect sequential access
+    last_block: Option<u64>,
+    bytes_fetched: u64,
+    requests: u64,
+}
+
+impl<S: RangeSource> RangeReader<S> {
+    /// Create a reader over a range source with default cache settings
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            size: None,
+            pos: 0,
+            block_size: DEFAULT_BLOCK_SIZE,
+            cache_blocks: DEFAULT_CACHE_BLOCKS,
+            read_ahead: DEFAULT_READ_AHEAD,
+            blocks: HashMap::new(),
+            lru: VecDeque::new(),
+            last_block: None,
+            bytes_fetched: 0,
+            requests: 0,
+        }
+    }
+
+    /// Set the size of a cached block (default 64 KB)
+    ///
+    /// This is the smallest range fetched at a time.
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// Set how many blocks are cached (default 64)
+    pub fn with_cache_blocks(mut self, cache_blocks: usize) -> Self {
+        self.cache_blocks = cache_blocks.max(1);
+        self
+    }
+
+    /// Set how many blocks are fetched ahead of sequential reads (default 4)
+    pub fn with_read_ahead(mut self, read_ahead: usize) -> Self {
+        self.read_ahead = read_ahead;
+        self
+    }
+
+    /// Total bytes fetched from the source so far
+    pub fn bytes_fetched(&self) -> u64 {
+        self.bytes_fetched
+    }
+
+    /// Number of range requests made so far
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// Get a reference to the range source
+    pub fn get_ref(&self) -> &S {
+        &self.source
+    }
+
+    /// Unwrap the range source
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        match self.size {
+            Some(size) => Ok(size),
+            None => {
+                let size = self.source.size()?;
+                self.size = Some(size);
+                Ok(size)
+            }
+        }
+    }
+
+    /// Fetch the run of missing blocks starting at `first`, up to `last`
+    ///
+    /// Sequential access extends the run by the read-ahead.
+    fn fetch_run(&mut self, first: u64, last: u64) -> io::Result<()> {
+        let block_size = self.block_size as u64;
+        let block_count = self.size()?.div_ceil(block_size);
+        let mut end = last + 1;
+        if self.last_block.is_some_and(|b| b + 1 == first) {
+            end += self.read_ahead as u64;
+        }
+        // Every block of the run must fit in the cache
+        end = end.min(block_count).min(first + self.cache_blocks as u64);
+        if let Some(cached) = (first..end).find(|b| self.blocks.contains_key(b)) {
+            end = cached;
+        }
+
+        let offset = first * block_size;
+        let len = ((end - first) * block_size).min(self.size()? - offset) as usize;
+        let data = self.source.fetch(offset, len)?;
+        self.requests += 1;
+        self.bytes_fetched += data.len() as u64;
+        if data.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "Range source returned {} of {} bytes at {}",
+                    data.len(),
+                    len,
+                    offset
+                ),
+            ));
+        }
+
+        for (i, chunk) in data.chunks(self.block_size).enumerate() {
+            self.insert(first + i as u64, chunk.to_vec());
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, block: u64, data: Vec<u8>) {
+        while self.blocks.len() >= self.cache_blocks {
+            match self.lru.pop_front() {
+                Some(old) => {
+                    self.blocks.remove(&old);
+                }
+                None => break,
+            }
+        }
+        self.blocks.insert(block, data);
+        self.lru.push_back(block);
+    }
+
+    fn touch(&mut self, block: u64) {
+        if let Some(i) = self.lru.iter().rposition(|b| *b == block) {
+            self.lru.remove(i);
+            self.lru.push_back(block);
+        }
+    }
+}
+
+impl<S: RangeSource> Read for RangeReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.size()?;
+        if buf.is_empty() || self.pos >= size {
+            return Ok(0);
+        }
+        let block_size = self.block_size as u64;
+        let end = (self.pos + buf.len() as u64).min(size);
+        let last = (end - 1) / block_size;
+
+        let mut n = 0;
+        while self.pos < end {
+            let block = self.pos / block_size;
+            if !self.blocks.contains_key(&block) {
+                self.fetch_run(block, last)?;
+            }
+            self.touch(block);
+            let data = &self.blocks[&block];
+            let offset = (self.pos - block * block_size) as usize;
+            let count = (data.len() - offset).min((end - self.pos) as usize);
+            buf[n..n + count].copy_from_slice(&data[offset..offset + count]);
+            n += count;
+            self.pos += count as u64;
+            self.last_block = Some(block);
+        }
+        Ok(n)
+    }
+}
+
+impl<S: RangeSource> Seek for RangeReader<S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.size()?.checked_add_signed(delta),
+        };
+        self.pos = target.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative position")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serves ranges of a byte pattern, recording each request
+    struct Pattern {
+        size: u64,
+        fetched: Vec<(u64, usize)>,
+    }
+
+    impl RangeSource for Pattern {
+        fn size(&mut self) -> io::Result<u64> {
+            Ok(self.size)
+        }
+
+        fn fetch(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+            self.fetched.push((offset, len));
+            let end = (offset + len as u64).min(self.size);
+            Ok((offset..end).map(|i| i as u8).collect())
+        }
+    }
+
+    fn reader(size: u64) -> RangeReader<Pattern> {
+        RangeReader::new(Pattern {
+            size,
+            fetched: Vec::new(),
+        })
+        .with_block_size(16)
+        .with_cache_blocks(4)
+        .with_read_ahead(2)
+    }
+
+    #[test]
+    fn test_reads_through_cache() {
+        let mut reader = reader(1000);
+        let mut buf = [0u8; 40];
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert!(buf.iter().enumerate().all(|(i, b)| *b == (i + 10) as u8));
+        // Blocks 0..=3 in one request
+        assert_eq!(reader.get_ref().fetched, [(0, 64)]);
+
+        // Cached blocks aren't fetched again
+        reader.seek(SeekFrom::Start(20)).unwrap();
+        reader.read_exact(&mut buf[..8]).unwrap();
+        assert_eq!(reader.requests(), 1);
+        assert_eq!(reader.bytes_fetched(), 64);
+    }
+
+    #[test]
+    fn test_sequential_read_ahead() {
+        let mut reader = reader(1000);
+        let mut buf = [0u8; 16];
+        reader.read_exact(&mut buf).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        // Block 1 follows block 0, so blocks 1..=3 come in one request
+        assert_eq!(reader.get_ref().fetched, [(0, 16), (16, 48)]);
+        assert_eq!(buf[0], 48);
+    }
+
+    #[test]
+    fn test_eviction_and_end() {
+        let mut reader = reader(100);
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.seek(SeekFrom::End(-4)).unwrap(), 96);
+        assert_eq!(reader.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], [96, 97, 98, 99]);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+        for offset in [0, 20, 40, 60, 80] {
+            reader.seek(SeekFrom::Start(offset)).unwrap();
+            reader.read_exact(&mut buf[..1]).unwrap();
+        }
+        assert!(reader.blocks.len() <= 4);
+        // Block 6 was evicted and is fetched again
+        reader.seek(SeekFrom::Start(96)).unwrap();
+        reader.read_exact(&mut buf[..1]).unwrap();
+        assert_eq!(reader.get_ref().fetched.last(), Some(&(96, 4)));
+
+        assert!(reader.seek(SeekFrom::Current(-200)).is_err());
+    }
+}
@@ -32,7 +32,9 @@ impl ByteRange {
 /// Chunk size for streaming large segments (64KB)
 pub const DEFAULT_CHUNK_SIZE: usize = 65536;
 
-/// Maximum size for a single segment to prevent DOS attacks (256 MB)
+/// Default maximum size for a single segment to prevent DOS attacks (256 MB)
+///
+/// Set per asset with [`AssetBuilder::max_segment_size`](crate::AssetBuilder::max_segment_size).
 ///
 /// This prevents malicious files from requesting multi-GB allocations.
 /// Legitimate segments are typically much smaller:
@@ -148,6 +150,24 @@ pub enum SegmentMetadata {
     /// Embedded thumbnail location info (from EXIF or other metadata)
     #[cfg(feature = "exif")]
     Thumbnail(crate::thumbnail::EmbeddedThumbnailInfo),
+
+    /// Summary of a BMFF chunk offset table (`stco` or `co64`)
+    ///
+    /// Used to predict when 32-bit `stco` offsets overflow after a write.
+    ChunkOffsets {
+        /// Number of entries in the table
+        entry_count: u32,
+        /// Largest chunk offset in the table
+        max_offset: u64,
+    },
+
+    /// Description box (`jumd`) of a JUMBF superbox
+    JumbfDescription {
+        /// Label, if the description has one
+        label: Option<String>,
+        /// Content type UUID
+        content_type: [u8; 16],
+    },
 }
 
 impl SegmentMetadata {
@@ -159,7 +179,29 @@ impl SegmentMetadata {
                 chunk_offsets,
                 total_size,
             } => Some((guid.as_str(), chunk_offsets.as_slice(), *total_size)),
-            #[cfg(feature = "exif")]
+            _ => None,
+        }
+    }
+
+    /// Get the chunk offset table summary (entry count, largest offset) if this is
+    /// that variant
+    pub fn as_chunk_offsets(&self) -> Option<(u32, u64)> {
+        match self {
+            Self::ChunkOffsets {
+                entry_count,
+                max_offset,
+            } => Some((*entry_count, *max_offset)),
+            _ => None,
+        }
+    }
+
+    /// Get the JUMBF description (label, content type) if this is that variant
+    pub fn as_jumbf_description(&self) -> Option<(Option<&str>, &[u8; 16])> {
+        match self {
+            Self::JumbfDescription {
+                label,
+                content_type,
+            } => Some((label.as_deref(), content_type)),
             _ => None,
         }
     }
@@ -201,17 +243,24 @@ impl LazyData {
     }
 
     /// Load data from source at given location
-    pub fn load<R: Read>(&mut self, source: &mut R, location: ByteRange) -> Result<&[u8]> {
+    ///
+    /// Fails if the data is not loaded yet and is larger than `max_size` bytes,
+    /// e.g. [`Structure::max_segment_size`](crate::Structure::max_segment_size).
+    pub fn load<R: Read>(
+        &mut self,
+        source: &mut R,
+        location: ByteRange,
+        max_size: u64,
+    ) -> Result<&[u8]> {
         match self {
             Self::NotLoaded => {
                 // Validate segment size to prevent DOS attacks
-                if location.size > MAX_SEGMENT_SIZE {
-                    return Err(crate::Error::InvalidSegment {
+                if location.size > max_size {
+                    return Err(crate::Error::LimitExceeded {
                         offset: location.offset,
                         reason: format!(
-                            "Segment too large: {} bytes (max {} MB)",
-                            location.size,
-                            MAX_SEGMENT_SIZE / (1024 * 1024)
+                            "Segment too large: {} bytes (max {} bytes)",
+                            location.size, max_size
                         ),
                     });
                 }
@@ -465,39 +514,40 @@ impl Segment {
 
     /// Check if this JUMBF segment contains a C2PA manifest.
     ///
-    /// Returns `false` immediately for non-Jumbf segments. For Jumbf segments with
-    /// loaded data, inspects the JUMBF description box label (at byte offset 33) to
-    /// confirm the content type is `"c2pa"`.
+    /// Returns `false` immediately for non-Jumbf segments. Segments described at
+    /// parse time are identified by their description box label and content type.
+    /// Otherwise, for Jumbf segments with loaded data, the description box is
+    /// parsed from the data (see [`JumbfDescription`](crate::JumbfDescription)).
     ///
     /// Falls back to `true` when data is not yet loaded — a conservative assumption
     /// that avoids false negatives when segments haven't been read yet.
-    ///
-    /// JUMBF layout at the start of the payload:
-    /// ```text
-    /// superbox:  lbox(4) + "jumb"(4)
-    /// desc box:  lbox(4) + "jumd"(4) + UUID(16) + toggles(1) + label(n, NUL-terminated)
-    ///                                                            ^ offset 33
-    /// ```
     pub fn is_c2pa(&self) -> bool {
         if !self.is_jumbf() {
             return false;
         }
-        const LABEL_OFFSET: usize = 33;
-        const C2PA_LABEL: &[u8] = b"c2pa";
-        match &self.data {
-            LazyData::Loaded(bytes) if bytes.len() >= LABEL_OFFSET + C2PA_LABEL.len() => {
-                &bytes[LABEL_OFFSET..LABEL_OFFSET + C2PA_LABEL.len()] == C2PA_LABEL
-            }
+        if let Some((label, content_type)) = self.jumbf_description() {
+            return crate::jumbf::is_c2pa_description(label, content_type);
+        }
+        let description = match &self.data {
+            LazyData::Loaded(bytes) => crate::jumbf::describe(bytes),
             #[cfg(feature = "memory-mapped")]
-            LazyData::MemoryMapped { mmap, offset, size }
-                if *size >= LABEL_OFFSET + C2PA_LABEL.len() =>
-            {
-                let start = offset + LABEL_OFFSET;
-                &mmap[start..start + C2PA_LABEL.len()] == C2PA_LABEL
+            LazyData::MemoryMapped { mmap, offset, size } => {
+                crate::jumbf::describe(&mmap[*offset..*offset + *size])
             }
-            // Data not yet loaded — conservatively assume it is C2PA.
-            _ => true,
-        }
+            // Data not yet loaded — conservatively assume it is C2PA, like
+            // `Structure::c2pa_jumbf_index` does for undescribed placeholders.
+            _ => return true,
+        };
+        description.is_some_and(|(label, content_type)| {
+            crate::jumbf::is_c2pa_description(label.as_deref(), &content_type)
+        })
+    }
+
+    /// The JUMBF description (label, content type) found at parse time, if any
+    pub fn jumbf_description(&self) -> Option<(Option<&str>, &[u8; 16])> {
+        self.metadata
+            .as_ref()
+            .and_then(|m| m.as_jumbf_description())
     }
 
     /// Check if this is image data
@@ -705,7 +755,10 @@ impl ChunkSpec {
 ///
 /// # Returns
 ///
-/// The root hash of the Merkle tree
+/// The root hash of the Merkle tree. A node without a sibling is hashed with
+/// itself; use [`MerkleTree`](crate::MerkleTree) for other odd-node handling
+/// or for inclusion proofs. Digests wider than 32 bytes are truncated to their
+/// first 32 bytes; `H` must produce at least 32.
 ///
 /// # Example
 ///
@@ -730,9 +783,6 @@ where
     if leaves.is_empty() {
         return [0u8; 32];
     }
-    if leaves.len() == 1 {
-        return leaves[0];
-    }
 
     let mut current_level: Vec<[u8; 32]> = leaves.to_vec();
 
@@ -742,12 +792,8 @@ where
         for pair in current_level.chunks(2) {
             let mut hasher = H::new();
             hasher.update(pair[0]);
-            if pair.len() > 1 {
-                hasher.update(pair[1]);
-            } else {
-                // Odd number: hash with itself
-                hasher.update(pair[0]);
-            }
+            // Odd number: hash with itself
+            hasher.update(pair.get(1).unwrap_or(&pair[0]));
             let result = hasher.finalize();
             let mut hash = [0u8; 32];
             hash.copy_from_slice(&result[..32]);
@@ -0,0 +1,1216 @@
+//! Forward-only parsing and single-pass transforms for non-seekable streams
+//!
+//! [`AssetStream`] reads an asset from any [`Read`] (a pipe, a socket, stdin)
+//! without seeking back further than a bounded lookback window. It relies on
+//! the layout of sequential formats, where the metadata comes before the bulk
+//! media data: the JPEG scan, the PNG `IDAT` chunks, the BMFF `mdat` box, the
+//! WAV `data` chunk or the AVI `movi` list.
+//!
+//! - The *head*, everything before the media data, is buffered in memory.
+//! - The media data streams through; only the last `lookback` bytes are kept,
+//!   so the parsers can still step back a little.
+//! - Anything after the media data must fit in the lookback window.
+//!
+//! The container parsers and writers run unchanged, on sources in which the
+//! media data that has already gone by reads as zeros. A transform checks the
+//! writer's output against the bytes it has already sent, so it produces
+//! exactly the output of [`Asset::write`](crate::Asset::write) or fails; it
+//! also fails when XMP or JUMBF data lies in that media data.
+
+use crate::{
+    containers::ContainerKind,
+    detect_container,
+    error::{Error, Result},
+    get_handler,
+    segment::{LazyData, Segment, SegmentKind},
+    structure::Structure,
+    Asset, Handler, Updates, SNIFF_LEN,
+};
+use std::borrow::Cow;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+/// Default number of recently read bytes kept for seeking back (1 MB)
+const DEFAULT_LOOKBACK: usize = 1024 * 1024;
+
+/// Largest head (everything before the media data) buffered in memory
+const MAX_HEAD_SIZE: usize = 64 * 1024 * 1024;
+
+/// Bytes pulled from the stream at a time
+const PULL_SIZE: usize = 64 * 1024;
+
+/// An asset read from a forward-only stream
+///
+/// Supports JPEG, PNG, RIFF (WAV, AVI, WebP) and BMFF files whose metadata
+/// comes before `mdat`. A BMFF stream needs its length, set with
+/// [`with_len`](Self::with_len) (e.g. from an HTTP `Content-Length`); the
+/// other formats don't.
+///
+/// # Example
+///
+/// ```no_run
+/// use asset_io::{AssetStream, Updates};
+/// use std::io;
+///
+/// # fn main() -> asset_io::Result<()> {
+/// // Replace the XMP of a JPEG piped through stdin
+/// let updates = Updates::new().set_xmp(b"<new>metadata</new>".to_vec());
+/// AssetStream::new(io::stdin().lock()).transform(&mut io::stdout().lock(), &updates)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AssetStream<R: Read> {
+    reader: R,
+    len: Option<u64>,
+    lookback: usize,
+}
+
+impl<R: Read> AssetStream<R> {
+    /// Create a stream parser over a reader
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            len: None,
+            lookback: DEFAULT_LOOKBACK,
+        }
+    }
+
+    /// Set the total length of the stream
+    ///
+    /// Required for BMFF, whose parser needs the file size. RIFF streams use
+    /// the size declared in their header when this isn't set.
+    pub fn with_len(mut self, len: u64) -> Self {
+        self.len = Some(len);
+        self
+    }
+
+    /// Set how many recently read bytes are kept for seeking back (default 1 MB)
+    ///
+    /// Everything after the media data (e.g. a trailing JPEG EOI, PNG `IEND`
+    /// or a BMFF `moov` after `mdat`) must fit in this window.
+    pub fn with_lookback(mut self, lookback: usize) -> Self {
+        self.lookback = lookback;
+        self
+    }
+
+    /// Parse the stream, reporting each segment to `on_segment`
+    ///
+    /// Segments before the media data are reported as soon as they have been
+    /// read, with XMP, JUMBF and EXIF data already loaded. The remaining
+    /// segments are reported once the end of the stream is reached. Any `Err`
+    /// from the callback stops parsing and is returned here.
+    ///
+    /// Returns the same structure as [`Asset::structure`](crate::Asset::structure).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{AssetStream, SegmentKind};
+    /// use std::io;
+    ///
+    /// # fn main() -> asset_io::Result<()> {
+    /// AssetStream::new(io::stdin().lock()).parse(|segment| {
+    ///     if segment.kind == SegmentKind::Xmp {
+    ///         println!("XMP at {}", segment.location().offset);
+    ///     }
+    ///     Ok(())
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn parse<F>(mut self, mut on_segment: F) -> Result<Structure>
+    where
+        F: FnMut(&Segment) -> Result<()>,
+    {
+        let head = Head::read(&mut self.reader, self.len)?;
+        let handler = get_handler(head.container)?;
+
+        let Some(bulk) = &head.bulk else {
+            let structure = handler.parse(&mut Cursor::new(&head.bytes))?;
+            for segment in &structure.segments {
+                emit(segment, &head.bytes, &mut on_segment)?;
+            }
+            return Ok(structure);
+        };
+
+        let early = head.parse_early(&handler)?;
+        for segment in &early.segments {
+            if segment.span().offset < bulk.start {
+                emit(segment, &head.bytes, &mut on_segment)?;
+            }
+        }
+
+        let len = self.len.or(head.declared_len);
+        let mut reader = ForwardReader::new(&head.bytes, self.reader, self.lookback, len, None);
+        let structure = handler.parse(&mut reader)?;
+        if self.len.is_none() && head.declared_len.is_some() {
+            reader.expect_end(structure.total_size)?;
+        }
+        for segment in &structure.segments {
+            if segment.span().offset >= bulk.start {
+                on_segment(segment)?;
+            }
+        }
+        Ok(structure)
+    }
+
+    /// Apply updates while copying the stream to `writer` in a single pass
+    ///
+    /// The updated head is written as soon as it has been read; the media data
+    /// is copied through as it arrives, and whatever follows it is written at
+    /// the end. The output is identical to [`Asset::write`](crate::Asset::write).
+    ///
+    /// Updates that can't be applied in one pass fail: changes inside the
+    /// media data, XMP or JUMBF stored inside it (e.g. HEIF items in `mdat`),
+    /// or changes in size of RIFF chunks after the media data. When this is found before anything
+    /// has been written, the error is [`Error::Unsupported`]. Any failure after
+    /// output has been written, including these, is an
+    /// [`Error::PartialOutput`]; the destination is then incomplete.
+    ///
+    /// Returns the destination structure.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{AssetStream, Updates};
+    /// use std::fs::File;
+    ///
+    /// # fn main() -> asset_io::Result<()> {
+    /// let input = File::open("video.mp4")?;
+    /// let len = input.metadata()?.len();
+    /// let mut output = File::create("output.mp4")?;
+    ///
+    /// let updates = Updates::new().set_jumbf(vec![0u8; 1024]);
+    /// let structure = AssetStream::new(input)
+    ///     .with_len(len)
+    ///     .transform(&mut output, &updates)?;
+    /// println!("wrote {} bytes", structure.total_size);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn transform<W: Write>(mut self, writer: &mut W, updates: &Updates) -> Result<Structure> {
+        let head = Head::read(&mut self.reader, self.len)?;
+
+        let Some(bulk) = &head.bulk else {
+            let mut asset = Asset::from_source(Cursor::new(head.bytes))?;
+            return asset.write_stream(writer, updates);
+        };
+        head.check_transformable()?;
+        let handler = get_handler(head.container)?;
+
+        // Write the head as it will be once updated, then the media data read so far
+        let early = head.parse_early(&handler)?;
+        check_buffered(&early, head.bytes.len() as u64, u64::MAX)?;
+        let early_dest = handler.calculate_updated_structure(&early, updates)?;
+        let split = media_offset(&early, &early_dest, bulk.start)?;
+        let mut early_source = VirtualSource::new(head.early_parts());
+        let mut shadow = ShadowOutput::new(split, None);
+        handler.write(&early, &mut early_source, &mut shadow, updates)?;
+        if shadow.head.len() as u64 != split {
+            return Err(single_pass_error());
+        }
+
+        let mut out = CountingWriter {
+            inner: writer,
+            written: 0,
+        };
+        self.stream_through(&head, &handler, &shadow.head, &mut out, updates)
+            .map_err(|source| Error::PartialOutput {
+                written: out.written,
+                source: Box::new(source),
+            })
+    }
+
+    /// Write the updated head, then stream the media data and the trailer
+    fn stream_through<W: Write>(
+        self,
+        head: &Head,
+        handler: &Handler,
+        head_out: &[u8],
+        writer: &mut W,
+        updates: &Updates,
+    ) -> Result<Structure> {
+        let bulk = head.bulk.as_ref().expect("head has media data");
+        let split = head_out.len() as u64;
+        writer.write_all(head_out)?;
+        writer.write_all(&head.bytes[bulk.start as usize..])?;
+
+        // Stream the media data through, keeping only what follows it
+        let len = self.len.or(head.declared_len);
+        let mut reader =
+            ForwardReader::new(&head.bytes, self.reader, self.lookback, len, Some(writer));
+        let structure = handler.parse(&mut reader)?;
+        if self.len.is_none() && head.declared_len.is_some() {
+            reader.expect_end(structure.total_size)?;
+        }
+        reader.finish(structure.total_size)?;
+        let (tail_start, tail) = reader.into_tail();
+        check_buffered(&structure, head.bytes.len() as u64, tail_start)?;
+
+        // Write the whole asset again, keeping only what follows the media data
+        let dest = handler.calculate_updated_structure(&structure, updates)?;
+        if media_offset(&structure, &dest, bulk.start)? != split {
+            return Err(single_pass_error());
+        }
+        let mut source = VirtualSource::new(vec![
+            Cow::Borrowed(&head.bytes[..]).into(),
+            Part::Zeros(tail_start - head.bytes.len() as u64),
+            Cow::Borrowed(&tail[..]).into(),
+        ]);
+        let passthrough = Passthrough {
+            len: tail_start - bulk.start,
+            known: &head.bytes[bulk.start as usize..],
+        };
+        let mut shadow = ShadowOutput::new(split, Some(passthrough));
+        handler.write(&structure, &mut source, &mut shadow, updates)?;
+        if shadow.mismatch || shadow.head != head_out || shadow.end < split + passthrough.len {
+            return Err(single_pass_error());
+        }
+        writer.write_all(&shadow.trailer)?;
+        Ok(dest)
+    }
+}
+
+/// Counts the bytes written through it
+struct CountingWriter<'a, W> {
+    inner: &'a mut W,
+    written: u64,
+}
+
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Fail if XMP or JUMBF data lies in `[start, end)`, media data that has gone by
+///
+/// Only the head and the lookback window are kept, so such data (e.g. HEIF
+/// items stored in `mdat`) would be read as zeros.
+fn check_buffered(structure: &Structure, start: u64, end: u64) -> Result<()> {
+    for segment in &structure.segments {
+        if !matches!(segment.kind, SegmentKind::Xmp | SegmentKind::Jumbf) {
+            continue;
+        }
+        if let Some(range) = segment
+            .ranges
+            .iter()
+            .find(|r| r.offset < end && r.end_offset() > start)
+        {
+            return Err(Error::Unsupported(format!(
+                "{} data at offset {} is inside media data the stream has already passed",
+                segment.kind, range.offset
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn single_pass_error() -> Error {
+    Error::Unsupported(
+        "These updates change data after the start of the media data and can't be applied in a single pass"
+            .into(),
+    )
+}
+
+/// Report a head segment, with its metadata loaded from the buffered head
+fn emit<F>(segment: &Segment, bytes: &[u8], on_segment: &mut F) -> Result<()>
+where
+    F: FnMut(&Segment) -> Result<()>,
+{
+    if !matches!(
+        segment.kind,
+        SegmentKind::Xmp | SegmentKind::Jumbf | SegmentKind::Exif
+    ) || segment.data.get().is_some()
+    {
+        return on_segment(segment);
+    }
+    let mut data = Vec::new();
+    for range in &segment.ranges {
+        match bytes.get(range.offset as usize..range.end_offset() as usize) {
+            Some(part) => data.extend_from_slice(part),
+            None => return on_segment(segment),
+        }
+    }
+    on_segment(&segment.clone().with_data(LazyData::Loaded(data)))
+}
+
+/// Where the media data starting at source offset `bulk_start` lands in the output
+///
+/// The media segment is the first one at or after `bulk_start`, matched to the
+/// destination by kind, path and occurrence. Path indices (`mdat#1`) are
+/// ignored, as they change when boxes are inserted.
+fn media_offset(source: &Structure, dest: &Structure, bulk_start: u64) -> Result<u64> {
+    let (index, segment) = source
+        .segments
+        .iter()
+        .enumerate()
+        .find(|(_, s)| s.span().offset >= bulk_start)
+        .ok_or_else(|| Error::InvalidFormat("No media data segment".into()))?;
+    let gap = segment.span().offset - bulk_start;
+    let same = |s: &&Segment| s.kind == segment.kind && path_name(s) == path_name(segment);
+    let occurrence = source.segments[..index].iter().filter(same).count();
+    dest.segments
+        .iter()
+        .filter(same)
+        .nth(occurrence)
+        .and_then(|s| s.span().offset.checked_sub(gap))
+        .ok_or_else(single_pass_error)
+}
+
+fn path_name(segment: &Segment) -> Option<&str> {
+    let path = segment.path.as_deref()?;
+    path.split('#').next()
+}
+
+/// The start of a stream, read up to its media data
+struct Head {
+    container: ContainerKind,
+    /// Every byte read so far (may extend past the start of the media data)
+    bytes: Vec<u8>,
+    /// The media data, or `None` if the whole stream has been read
+    bulk: Option<Bulk>,
+    /// Stream length declared by a RIFF header
+    declared_len: Option<u64>,
+}
+
+/// Where the media data starts, and what stands in for it when parsing the head alone
+struct Bulk {
+    start: u64,
+    /// Header bytes of the media data box or chunk (kept from the stream)
+    header: usize,
+    /// Bytes standing in for the rest of the media data and what follows it
+    stand_in: Vec<Part<'static>>,
+}
+
+impl Head {
+    fn read<R: Read>(reader: &mut R, len: Option<u64>) -> Result<Self> {
+        let mut bytes = Vec::new();
+        fill(reader, &mut bytes, SNIFF_LEN)?;
+        let container = detect_container(&mut Cursor::new(&bytes))?;
+
+        let scan = match container {
+            #[cfg(feature = "jpeg")]
+            ContainerKind::Jpeg => scan_jpeg(reader, &mut bytes),
+            #[cfg(feature = "png")]
+            ContainerKind::Png => scan_png(reader, &mut bytes),
+            #[cfg(feature = "bmff")]
+            ContainerKind::Bmff => scan_bmff(reader, &mut bytes, len),
+            #[cfg(feature = "riff")]
+            ContainerKind::Riff => scan_riff(reader, &mut bytes),
+            // Registered containers are buffered whole
+            ContainerKind::Custom(_) => Ok(None),
+        };
+        let bulk = scan.map_err(|e| e.in_container(container, None))?;
+        if bulk.is_none() {
+            read_all(reader, &mut bytes).map_err(|e| e.in_container(container, None))?;
+        }
+        #[cfg(not(feature = "bmff"))]
+        let _ = len;
+
+        let declared_len = match &bytes[..] {
+            [b'R', b'I', b'F', b'F', a, b, c, d, ..] => {
+                Some(u32::from_le_bytes([*a, *b, *c, *d]) as u64 + 8)
+            }
+            _ => None,
+        };
+        Ok(Self {
+            container,
+            bytes,
+            bulk,
+            declared_len,
+        })
+    }
+
+    /// The head followed by a stand-in for the media data
+    fn early_parts(&self) -> Vec<Part<'_>> {
+        let bulk = self.bulk.as_ref().expect("head has media data");
+        let end = bulk.start as usize + bulk.header;
+        let mut parts = vec![Cow::Borrowed(&self.bytes[..end]).into()];
+        parts.extend(bulk.stand_in.iter().cloned());
+        parts
+    }
+
+    /// Parse the head followed by a stand-in for the media data
+    fn parse_early(&self, handler: &Handler) -> Result<Structure> {
+        handler.parse(&mut VirtualSource::new(self.early_parts()))
+    }
+
+    /// Reject layouts where the head depends on data after the media data
+    ///
+    /// The RF64 `ds64` sizes can't be known before the end of the stream.
+    fn check_transformable(&self) -> Result<()> {
+        #[cfg(feature = "riff")]
+        if self.container == ContainerKind::Riff && self.declared_len.is_none() {
+            return Err(Error::Unsupported(
+                "RF64 streams can't be transformed in a single pass".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Read until `bytes` holds `len` bytes; `false` if the stream ends first
+fn fill<R: Read>(reader: &mut R, bytes: &mut Vec<u8>, len: usize) -> Result<bool> {
+    if len > MAX_HEAD_SIZE {
+        return Err(head_too_large());
+    }
+    if bytes.len() >= len {
+        return Ok(true);
+    }
+    let want = len - bytes.len();
+    let got = reader.by_ref().take(want as u64).read_to_end(bytes)?;
+    Ok(got == want)
+}
+
+/// Read the rest of a stream that has no media data to stream through
+fn read_all<R: Read>(reader: &mut R, bytes: &mut Vec<u8>) -> Result<()> {
+    let limit = (MAX_HEAD_SIZE + 1).saturating_sub(bytes.len());
+    reader.by_ref().take(limit as u64).read_to_end(bytes)?;
+    if bytes.len() > MAX_HEAD_SIZE {
+        return Err(head_too_large());
+    }
+    Ok(())
+}
+
+fn head_too_large() -> Error {
+    Error::LimitExceeded {
+        offset: MAX_HEAD_SIZE as u64,
+        reason: format!(
+            "More than {} MB before the media data",
+            MAX_HEAD_SIZE / (1024 * 1024)
+        ),
+    }
+}
+
+/// Scan JPEG markers up to SOS
+#[cfg(feature = "jpeg")]
+fn scan_jpeg<R: Read>(reader: &mut R, bytes: &mut Vec<u8>) -> Result<Option<Bulk>> {
+    let mut pos = 2;
+    loop {
+        if !fill(reader, bytes, pos + 2)? {
+            return Ok(None);
+        }
+        if bytes[pos] != 0xFF {
+            return Err(Error::InvalidSegment {
+                offset: pos as u64,
+                reason: "Expected JPEG marker".into(),
+            });
+        }
+        match bytes[pos + 1] {
+            0xFF => pos += 1,
+            // SOS: an empty scan followed by EOI stands in for the image data
+            0xDA => {
+                return Ok(Some(Bulk {
+                    start: pos as u64,
+                    header: 0,
+                    stand_in: vec![Cow::Borrowed(&[0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xD9][..]).into()],
+                }))
+            }
+            0x01 | 0xD0..=0xD9 => pos += 2,
+            _ => {
+                if !fill(reader, bytes, pos + 4)? {
+                    return Ok(None);
+                }
+                pos += 2 + u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+            }
+        }
+    }
+}
+
+/// Scan PNG chunks up to the first IDAT
+#[cfg(feature = "png")]
+fn scan_png<R: Read>(reader: &mut R, bytes: &mut Vec<u8>) -> Result<Option<Bulk>> {
+    // An empty IDAT followed by IEND stands in for the image data
+    const STAND_IN: [u8; 24] = [
+        0, 0, 0, 0, b'I', b'D', b'A', b'T', 0x35, 0xAF, 0x06, 0x1E, //
+        0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82,
+    ];
+
+    let mut pos = 8;
+    loop {
+        if !fill(reader, bytes, pos + 8)? {
+            return Ok(None);
+        }
+        if &bytes[pos + 4..pos + 8] == b"IDAT" {
+            return Ok(Some(Bulk {
+                start: pos as u64,
+                header: 0,
+                stand_in: vec![Cow::Borrowed(&STAND_IN[..]).into()],
+            }));
+        }
+        let size = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        pos = pos.saturating_add(size).saturating_add(12);
+    }
+}
+
+/// Scan top-level BMFF boxes up to `mdat`
+#[cfg(feature = "bmff")]
+fn scan_bmff<R: Read>(
+    reader: &mut R,
+    bytes: &mut Vec<u8>,
+    len: Option<u64>,
+) -> Result<Option<Bulk>> {
+    let mut pos = 0;
+    loop {
+        if !fill(reader, bytes, pos + 8)? {
+            return Ok(None);
+        }
+        let mut header = 8;
+        let size = match u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) {
+            0 => None,
+            1 => {
+                if !fill(reader, bytes, pos + 16)? {
+                    return Ok(None);
+                }
+                header = 16;
+                Some(u64::from_be_bytes(
+                    bytes[pos + 8..pos + 16].try_into().unwrap(),
+                ))
+            }
+            size => Some(size as u64),
+        };
+        if &bytes[pos + 4..pos + 8] == b"mdat" {
+            // The mdat header followed by zeros stands in for the media data
+            let size = match size {
+                Some(size) => size,
+                None => len
+                    .and_then(|len| len.checked_sub(pos as u64))
+                    .ok_or_else(|| {
+                        Error::Unsupported(
+                            "mdat extends to the end of the stream; set its length with AssetStream::with_len".into(),
+                        )
+                    })?,
+            };
+            let payload = size
+                .checked_sub(header as u64)
+                .ok_or(Error::InvalidSegment {
+                    offset: pos as u64,
+                    reason: "Box size smaller than its header".into(),
+                })?;
+            return Ok(Some(Bulk {
+                start: pos as u64,
+                header,
+                stand_in: vec![Part::Zeros(payload)],
+            }));
+        }
+        match size {
+            Some(size) if size >= header as u64 => {
+                pos = pos.saturating_add(usize::try_from(size).unwrap_or(usize::MAX));
+            }
+            Some(_) => {
+                return Err(Error::InvalidSegment {
+                    offset: pos as u64,
+                    reason: "Box size smaller than its header".into(),
+                })
+            }
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Scan RIFF chunks up to `data`, `LIST movi` or an AVI `RIFF AVIX` extension
+#[cfg(feature = "riff")]
+fn scan_riff<R: Read>(reader: &mut R, bytes: &mut Vec<u8>) -> Result<Option<Bulk>> {
+    let mut pos = 12;
+    loop {
+        if !fill(reader, bytes, pos + 8)? {
+            return Ok(None);
+        }
+        let id = &bytes[pos..pos + 4];
+        let size = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as u64;
+        let padded = size + (size & 1);
+
+        let header = match id {
+            b"data" => 8,
+            b"LIST" => {
+                if !fill(reader, bytes, pos + 12)? {
+                    return Ok(None);
+                }
+                if &bytes[pos + 8..pos + 12] == b"movi" {
+                    12
+                } else {
+                    0
+                }
+            }
+            // Extensions follow the main chunk: nothing stands in for them
+            b"RIFF" => {
+                return Ok(Some(Bulk {
+                    start: pos as u64,
+                    header: 0,
+                    stand_in: Vec::new(),
+                }))
+            }
+            _ => 0,
+        };
+        if header > 0 {
+            // The chunk header followed by zeros stands in for the media data,
+            // and a JUNK chunk of the same size for any chunks after it, so
+            // that the RIFF size written in the head comes out the same
+            let end = pos as u64 + 8 + padded;
+            let declared_end = match &bytes[..4] {
+                b"RIFF" => u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as u64 + 8,
+                _ => end,
+            };
+            let mut stand_in = vec![Part::Zeros(padded.saturating_sub(header as u64 - 8))];
+            match declared_end.saturating_sub(end) {
+                0 => {}
+                trailer @ 1..=7 => stand_in.push(Part::Zeros(trailer)),
+                trailer => {
+                    let mut junk = b"JUNK".to_vec();
+                    junk.extend_from_slice(&((trailer - 8) as u32).to_le_bytes());
+                    stand_in.push(Part::Bytes(Cow::Owned(junk)));
+                    stand_in.push(Part::Zeros(trailer - 8));
+                }
+            }
+            return Ok(Some(Bulk {
+                start: pos as u64,
+                header,
+                stand_in,
+            }));
+        }
+        pos = pos.saturating_add(usize::try_from(padded).unwrap_or(usize::MAX) + 8);
+    }
+}
+
+/// A piece of a [`VirtualSource`]
+#[derive(Clone)]
+enum Part<'a> {
+    Bytes(Cow<'a, [u8]>),
+    Zeros(u64),
+}
+
+impl Part<'_> {
+    fn len(&self) -> u64 {
+        match self {
+            Part::Bytes(bytes) => bytes.len() as u64,
+            Part::Zeros(len) => *len,
+        }
+    }
+}
+
+impl<'a> From<Cow<'a, [u8]>> for Part<'a> {
+    fn from(bytes: Cow<'a, [u8]>) -> Self {
+        Part::Bytes(bytes)
+    }
+}
+
+/// A readable, seekable concatenation of byte slices and runs of zeros
+struct VirtualSource<'a> {
+    parts: Vec<Part<'a>>,
+    len: u64,
+    pos: u64,
+}
+
+impl<'a> VirtualSource<'a> {
+    fn new(parts: Vec<Part<'a>>) -> Self {
+        let len = parts.iter().map(Part::len).sum();
+        Self { parts, len, pos: 0 }
+    }
+}
+
+impl Read for VirtualSource<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut start = 0;
+        for part in &self.parts {
+            let end = start + part.len();
+            if self.pos < end {
+                let offset = self.pos - start;
+                let n = buf.len().min((end - self.pos) as usize);
+                match part {
+                    Part::Bytes(bytes) => {
+                        buf[..n].copy_from_slice(&bytes[offset as usize..offset as usize + n])
+                    }
+                    Part::Zeros(_) => buf[..n].fill(0),
+                }
+                self.pos += n as u64;
+                return Ok(n);
+            }
+            start = end;
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for VirtualSource<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = resolve(pos, self.pos, Some(self.len))?;
+        Ok(self.pos)
+    }
+}
+
+fn resolve(pos: SeekFrom, current: u64, len: Option<u64>) -> io::Result<u64> {
+    let target = match pos {
+        SeekFrom::Start(offset) => return Ok(offset),
+        SeekFrom::Current(delta) => current as i128 + delta as i128,
+        SeekFrom::End(delta) => {
+            let len = len.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Stream length unknown; set it with AssetStream::with_len",
+                )
+            })?;
+            len as i128 + delta as i128
+        }
+    };
+    u64::try_from(target)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative position"))
+}
+
+/// A `Read + Seek` view of a forward-only stream
+///
+/// The head is kept whole; after it, only the most recent `lookback` bytes
+/// stay available. Bytes leaving the window go to the tee, if any.
+struct ForwardReader<'a, R> {
+    head: &'a [u8],
+    inner: R,
+    /// Recently read bytes, starting at stream offset `window_start`
+    window: Vec<u8>,
+    window_start: u64,
+    pos: u64,
+    lookback: usize,
+    len: Option<u64>,
+    tee: Option<&'a mut dyn Write>,
+}
+
+impl<'a, R: Read> ForwardReader<'a, R> {
+    fn new(
+        head: &'a [u8],
+        inner: R,
+        lookback: usize,
+        len: Option<u64>,
+        tee: Option<&'a mut dyn Write>,
+    ) -> Self {
+        Self {
+            head,
+            inner,
+            window: Vec::new(),
+            window_start: head.len() as u64,
+            pos: 0,
+            lookback,
+            len,
+            tee,
+        }
+    }
+
+    fn window_end(&self) -> u64 {
+        self.window_start + self.window.len() as u64
+    }
+
+    /// Read up to `want` more bytes from the stream into the window
+    fn pull(&mut self, want: usize) -> io::Result<usize> {
+        (&mut self.inner)
+            .take(want as u64)
+            .read_to_end(&mut self.window)
+    }
+
+    /// Drop bytes that are out of the lookback window and behind the position
+    fn evict(&mut self) -> io::Result<()> {
+        let keep = self
+            .window_end()
+            .saturating_sub(self.lookback as u64)
+            .min(self.pos)
+            .max(self.window_start);
+        let n = (keep - self.window_start) as usize;
+        // Batched, so that the window isn't shifted on every read
+        if n >= self.lookback.max(PULL_SIZE) {
+            self.drop_front(n)?;
+        }
+        Ok(())
+    }
+
+    fn drop_front(&mut self, n: usize) -> io::Result<()> {
+        if let Some(tee) = &mut self.tee {
+            tee.write_all(&self.window[..n])?;
+        }
+        self.window.drain(..n);
+        self.window_start += n as u64;
+        Ok(())
+    }
+
+    /// Read the stream up to `end`, so the tee has seen everything before the window
+    fn finish(&mut self, end: u64) -> Result<()> {
+        while self.window_end() < end {
+            let want = (end - self.window_end()).min(PULL_SIZE as u64) as usize;
+            if self.pull(want)? == 0 {
+                return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            self.pos = self.window_end();
+            self.evict()?;
+        }
+        let keep = self.lookback.min(self.window.len());
+        let n = (self.window.len() - keep).min(end.saturating_sub(self.window_start) as usize);
+        self.drop_front(n)?;
+        Ok(())
+    }
+
+    /// Check that the stream ends at `end`
+    fn expect_end(&mut self, end: u64) -> Result<()> {
+        if self.window_end() > end || (self.window_end() == end && self.pull(1)? > 0) {
+            return Err(Error::Unsupported(
+                "Data after the RIFF chunk; set the stream length with AssetStream::with_len"
+                    .into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The stream offset of the window, and its bytes
+    fn into_tail(self) -> (u64, Vec<u8>) {
+        (self.window_start, self.window)
+    }
+}
+
+impl<R: Read> Read for ForwardReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pos < self.head.len() as u64 {
+            let start = self.pos as usize;
+            let n = buf.len().min(self.head.len() - start);
+            buf[..n].copy_from_slice(&self.head[start..start + n]);
+            self.pos += n as u64;
+            return Ok(n);
+        }
+        if self.pos < self.window_start {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Can't seek back to {} in a forward-only stream (lookback starts at {})",
+                    self.pos, self.window_start
+                ),
+            ));
+        }
+        while self.pos >= self.window_end() {
+            let want = (self.pos - self.window_end() + buf.len() as u64).min(PULL_SIZE as u64);
+            if self.pull(want as usize)? == 0 {
+                return Ok(0);
+            }
+            self.evict()?;
+        }
+        let offset = (self.pos - self.window_start) as usize;
+        let n = buf.len().min(self.window.len() - offset);
+        buf[..n].copy_from_slice(&self.window[offset..offset + n]);
+        self.pos += n as u64;
+        self.evict()?;
+        Ok(n)
+    }
+}
+
+impl<R: Read> Seek for ForwardReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = resolve(pos, self.pos, self.len)?;
+        if target >= self.head.len() as u64 && target < self.window_start {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Can't seek back to {} in a forward-only stream (lookback starts at {})",
+                    target, self.window_start
+                ),
+            ));
+        }
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+/// The media data a transform has already copied to its output
+#[derive(Clone, Copy)]
+struct Passthrough<'a> {
+    len: u64,
+    /// The leading bytes that were in the head; the rest read as zeros
+    known: &'a [u8],
+}
+
+impl Passthrough<'_> {
+    fn expected(&self, offset: u64) -> u8 {
+        self.known.get(offset as usize).copied().unwrap_or(0)
+    }
+}
+
+/// Output of a container writer, checked against what a transform has sent
+///
+/// Bytes before `split` are kept in `head`. With a passthrough, the writer
+/// must reproduce the copied media data after `split`, and everything after
+/// that is kept in `trailer`; without one, everything after `split` is dropped.
+struct ShadowOutput<'a> {
+    split: u64,
+    passthrough: Option<Passthrough<'a>>,
+    head: Vec<u8>,
+    trailer: Vec<u8>,
+    mismatch: bool,
+    pos: u64,
+    end: u64,
+}
+
+impl<'a> ShadowOutput<'a> {
+    fn new(split: u64, passthrough: Option<Passthrough<'a>>) -> Self {
+        Self {
+            split,
+            passthrough,
+            head: Vec::new(),
+            trailer: Vec::new(),
+            mismatch: false,
+            pos: 0,
+            end: 0,
+        }
+    }
+
+    fn trailer_start(&self) -> u64 {
+        self.split + self.passthrough.map_or(u64::MAX - self.split, |p| p.len)
+    }
+}
+
+/// Write `data` into `buf` at `offset`, growing it as needed
+fn put(buf: &mut Vec<u8>, offset: usize, data: &[u8]) {
+    if buf.len() < offset + data.len() {
+        buf.resize(offset + data.len(), 0);
+    }
+    buf[offset..offset + data.len()].copy_from_slice(data);
+}
+
+impl Write for ShadowOutput<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = self.pos;
+        let end = start + buf.len() as u64;
+        let trailer_start = self.trailer_start();
+
+        if start < self.split {
+            let n = (end.min(self.split) - start) as usize;
+            put(&mut self.head, start as usize, &buf[..n]);
+        }
+        if let Some(passthrough) = self.passthrough {
+            for pos in start.max(self.split)..end.min(trailer_start) {
+                if buf[(pos - start) as usize] != passthrough.expected(pos - self.split) {
+                    self.mismatch = true;
+                }
+            }
+            if end > trailer_start {
+                let from = start.max(trailer_start);
+                put(
+                    &mut self.trailer,
+                    (from - trailer_start) as usize,
+                    &buf[(from - start) as usize..],
+                );
+            }
+        }
+        self.pos = end;
+        self.end = self.end.max(end);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for ShadowOutput<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.end.saturating_sub(self.pos) as usize);
+        let trailer_start = self.trailer_start();
+        for (i, byte) in buf[..n].iter_mut().enumerate() {
+            let pos = self.pos + i as u64;
+            *byte = if pos < self.split {
+                self.head.get(pos as usize).copied().unwrap_or(0)
+            } else if pos < trailer_start {
+                self.passthrough.map_or(0, |p| p.expected(pos - self.split))
+            } else {
+                self.trailer
+                    .get((pos - trailer_start) as usize)
+                    .copied()
+                    .unwrap_or(0)
+            };
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for ShadowOutput<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = resolve(pos, self.pos, Some(self.end))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reader that hands out at most a few bytes per call
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(7);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_forward_reader_seeks() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(300_000).collect();
+        let head = &data[..100];
+        let mut reader = ForwardReader::new(head, Trickle(&data[100..]), 1000, None, None);
+
+        let mut buf = [0u8; 4];
+        reader.seek(SeekFrom::Start(98)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[98..102]);
+
+        // Forward seeks skip data; the head and the lookback window stay readable
+        reader.seek(SeekFrom::Start(200_000)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[200_000..200_004]);
+        reader.seek(SeekFrom::Current(-500)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[199_504..199_508]);
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data[10..14]);
+
+        // Further back than the lookback window fails
+        let err = reader.seek(SeekFrom::Start(150_000)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        let err = reader.seek(SeekFrom::End(0)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        // Reading past the end returns EOF
+        reader.seek(SeekFrom::Start(400_000)).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_forward_reader_tee() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(200_000).collect();
+        let mut out = Vec::new();
+        let mut reader = ForwardReader::new(
+            &data[..10],
+            Trickle(&data[10..]),
+            1000,
+            None,
+            Some(&mut out),
+        );
+        reader.seek(SeekFrom::Start(150_000)).unwrap();
+        reader.finish(200_000).unwrap();
+        let (start, tail) = reader.into_tail();
+        assert_eq!(start, 199_000);
+        assert_eq!(tail, data[199_000..]);
+        assert_eq!(out, data[10..199_000]);
+    }
+
+    #[test]
+    fn test_shadow_output() {
+        let known = [1u8, 2];
+        let mut out = ShadowOutput::new(
+            3,
+            Some(Passthrough {
+                len: 4,
+                known: &known,
+            }),
+        );
+        out.write_all(&[9, 9, 9, 1, 2, 0, 0, 5, 6]).unwrap();
+        out.seek(SeekFrom::Start(1)).unwrap();
+        out.write_all(&[8]).unwrap();
+        assert_eq!(out.head, [9, 8, 9]);
+        assert_eq!(out.trailer, [5, 6]);
+        assert!(!out.mismatch);
+
+        out.seek(SeekFrom::Start(4)).unwrap();
+        out.write_all(&[7]).unwrap();
+        assert!(out.mismatch);
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_transform_rejects_xmp_item_in_passed_mdat() {
+        fn bmff_box(fourcc: &[u8; 4], body: &[u8]) -> Vec<u8> {
+            let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+            data.extend_from_slice(fourcc);
+            data.extend_from_slice(body);
+            data
+        }
+        let xmp = b"<x:xmpmeta/>";
+        let padding = 200_000;
+
+        // meta with one XMP item stored in mdat (iloc construction method 0)
+        let meta = |xmp_offset: u32| {
+            let mut hdlr = vec![0u8; 8];
+            hdlr.extend_from_slice(b"pict");
+            hdlr.extend_from_slice(&[0u8; 13]);
+            let mut infe = vec![2, 0, 0, 0, 0, 1, 0, 0];
+            infe.extend_from_slice(b"mime\0application/rdf+xml\0");
+            let mut iinf = vec![0, 0, 0, 0, 0, 1];
+            iinf.extend(bmff_box(b"infe", &infe));
+            let mut iloc = vec![0, 0, 0, 0, 0x44, 0x00, 0, 1, 0, 1, 0, 0, 0, 1];
+            iloc.extend_from_slice(&xmp_offset.to_be_bytes());
+            iloc.extend_from_slice(&(xmp.len() as u32).to_be_bytes());
+            let mut body = vec![0u8; 4];
+            body.extend(bmff_box(b"hdlr", &hdlr));
+            body.extend(bmff_box(b"iinf", &iinf));
+            body.extend(bmff_box(b"iloc", &iloc));
+            bmff_box(b"meta", &body)
+        };
+        let mut data = bmff_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+        let xmp_offset = data.len() + meta(0).len() + 8 + padding;
+        data.extend(meta(xmp_offset as u32));
+        let mut payload = vec![0u8; padding];
+        payload.extend_from_slice(xmp);
+        data.extend(bmff_box(b"mdat", &payload));
+
+        let mut asset = Asset::from_source(Cursor::new(&data)).unwrap();
+        assert_eq!(asset.xmp().unwrap().as_deref(), Some(&xmp[..]));
+
+        let error = AssetStream::new(&data[..])
+            .with_len(data.len() as u64)
+            .transform(&mut Vec::new(), &Updates::new())
+            .unwrap_err();
+        assert!(matches!(error, Error::Unsupported(_)), "{error}");
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_scan_jpeg() {
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xFF, 0xDA, 0x00, 0x02,
+        ];
+        let mut bytes = Vec::new();
+        let bulk = scan_jpeg(&mut Trickle(&jpeg), &mut bytes).unwrap().unwrap();
+        assert_eq!(bulk.start, 9);
+
+        let mut bytes = Vec::new();
+        assert!(scan_jpeg(&mut Trickle(&jpeg[..8]), &mut bytes)
+            .unwrap()
+            .is_none());
+    }
+
+    #[cfg(feature = "riff")]
+    #[test]
+    fn test_scan_riff() {
+        let mut wav = b"RIFF\x1a\0\0\0WAVEfmt \x02\0\0\0abdata\x04\0\0\0".to_vec();
+        wav.extend_from_slice(&[1, 2, 3, 4]);
+        let mut bytes = Vec::new();
+        let bulk = scan_riff(&mut Trickle(&wav), &mut bytes).unwrap().unwrap();
+        assert_eq!(bulk.start, 22);
+        assert_eq!(bulk.header, 8);
+        assert_eq!(bulk.stand_in.iter().map(Part::len).sum::<u64>(), 4);
+
+        // Chunks after the media data are stood in for by a JUNK chunk of the same size
+        wav[4] += 12;
+        wav.extend_from_slice(b"LIST\x04\0\0\0INFO");
+        let mut bytes = Vec::new();
+        let bulk = scan_riff(&mut Trickle(&wav), &mut bytes).unwrap().unwrap();
+        let mut source = VirtualSource::new(bulk.stand_in);
+        let mut stand_in = Vec::new();
+        source.read_to_end(&mut stand_in).unwrap();
+        assert_eq!(stand_in, b"\0\0\0\0JUNK\x04\0\0\0\0\0\0\0");
+    }
+}
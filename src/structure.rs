@@ -2,10 +2,10 @@
 
 use crate::{
     containers::ContainerKind,
+    diagnostic::Diagnostic,
     error::Result,
-    segment::{
-        ByteRange, ChunkedSegmentReader, Segment, SegmentKind, DEFAULT_CHUNK_SIZE, MAX_SEGMENT_SIZE,
-    },
+    limits::Limits,
+    segment::{ByteRange, ChunkedSegmentReader, Segment, SegmentKind, DEFAULT_CHUNK_SIZE},
     MediaType,
 };
 use std::io::{Read, Seek, SeekFrom, Take};
@@ -15,7 +15,7 @@ use std::io::{Read, Seek, SeekFrom, Take};
 /// This structure works with any `Read + Seek` source (files, buffers, streams).
 /// It contains the parsed segment layout and provides efficient access methods
 /// for metadata extraction and hashing operations.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Structure {
     /// All segments in the asset
     pub segments: Vec<Segment>,
@@ -35,9 +35,26 @@ pub struct Structure {
     /// Quick lookup: indices of JUMBF segments
     jumbf_indices: Vec<usize>,
 
+    /// Index of the C2PA manifest store, when known from a write
+    c2pa_jumbf: Option<usize>,
+
     /// Memory-mapped file data (optional, for zero-copy access)
     #[cfg(feature = "memory-mapped")]
     mmap: Option<std::sync::Arc<memmap2::Mmap>>,
+
+    /// BMFF box hierarchy (set when parsing BMFF files)
+    #[cfg(feature = "bmff")]
+    bmff_tree: Option<crate::BmffTree>,
+
+    /// AVI absolute index offsets (set when parsing AVI files)
+    #[cfg(feature = "riff")]
+    avi_index: Option<crate::containers::avi_index::AviIndex>,
+
+    /// Problems found while parsing
+    diagnostics: Vec<Diagnostic>,
+
+    /// Limits of the asset this structure describes
+    limits: Limits,
 }
 
 impl Structure {
@@ -50,11 +67,69 @@ impl Structure {
             total_size: 0,
             xmp_index: None,
             jumbf_indices: Vec::new(),
+            c2pa_jumbf: None,
             #[cfg(feature = "memory-mapped")]
             mmap: None,
+            #[cfg(feature = "bmff")]
+            bmff_tree: None,
+            #[cfg(feature = "riff")]
+            avi_index: None,
+            diagnostics: Vec::new(),
+            limits: Limits::default(),
         }
     }
 
+    /// The BMFF box hierarchy, for structures parsed from BMFF files
+    ///
+    /// Structures returned by writes describe segments only and have no tree.
+    #[cfg(feature = "bmff")]
+    pub fn bmff_tree(&self) -> Option<&crate::BmffTree> {
+        self.bmff_tree.as_ref()
+    }
+
+    /// Attach the BMFF box hierarchy
+    #[cfg(feature = "bmff")]
+    pub(crate) fn set_bmff_tree(&mut self, tree: crate::BmffTree) {
+        self.bmff_tree = Some(tree);
+    }
+
+    /// The AVI index offsets that writes must keep valid
+    #[cfg(feature = "riff")]
+    pub(crate) fn avi_index(&self) -> Option<&crate::containers::avi_index::AviIndex> {
+        self.avi_index.as_ref()
+    }
+
+    /// Attach the AVI index offsets
+    #[cfg(feature = "riff")]
+    pub(crate) fn set_avi_index(&mut self, index: crate::containers::avi_index::AviIndex) {
+        self.avi_index = Some(index);
+    }
+
+    /// Problems found while parsing, in the order they were found
+    ///
+    /// A strict parse only records warnings; a lenient parse also records the
+    /// damage it recovered from (see [`Diagnostic`]).
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Largest segment or range read into memory at once
+    ///
+    /// Set with [`AssetBuilder::max_segment_size`](crate::AssetBuilder::max_segment_size).
+    pub fn max_segment_size(&self) -> u64 {
+        self.limits.max_segment_size
+    }
+
+    /// Apply the limits of the asset to later reads
+    pub(crate) fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Record a problem found while parsing
+    pub(crate) fn add_diagnostic(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
     /// Attach memory-mapped data to this structure (zero-copy access)
     #[cfg(feature = "memory-mapped")]
     pub fn with_mmap(mut self, mmap: memmap2::Mmap) -> Self {
@@ -139,11 +214,81 @@ impl Structure {
         &self.jumbf_indices
     }
 
-    /// Get the first C2PA JUMBF index (most common case for C2PA workflows)
+    /// Get the index of the C2PA manifest store
+    ///
+    /// JPEG files can hold several JUMBF boxes. The manifest store is the first
+    /// one described as C2PA (label `c2pa` or the C2PA content type), or else the
+    /// first one without a label, such as a placeholder written before signing.
+    /// Other containers only hold the C2PA manifest store.
     pub fn c2pa_jumbf_index(&self) -> Option<usize> {
-        // For now, return the first JUMBF segment
-        // TODO: Check segment metadata to identify C2PA-specific JUMBF
-        self.jumbf_indices.first().copied()
+        if self.c2pa_jumbf.is_some() {
+            return self.c2pa_jumbf;
+        }
+        #[cfg(feature = "jpeg")]
+        let jpeg = self.container == ContainerKind::Jpeg;
+        #[cfg(not(feature = "jpeg"))]
+        let jpeg = false;
+        if !jpeg {
+            return self.jumbf_indices.first().copied();
+        }
+        let described = |i: &usize| self.segments[*i].jumbf_description();
+        self.jumbf_indices
+            .iter()
+            .find(|i| described(i).is_some_and(|(l, t)| crate::jumbf::is_c2pa_description(l, t)))
+            .or_else(|| {
+                self.jumbf_indices
+                    .iter()
+                    .find(|i| described(i).and_then(|(label, _)| label).is_none())
+            })
+            .copied()
+    }
+
+    /// Record which JUMBF segment a write produced as the C2PA manifest store
+    #[cfg(feature = "jpeg")]
+    pub(crate) fn set_c2pa_jumbf_index(&mut self, index: usize) {
+        self.c2pa_jumbf = Some(index);
+    }
+
+    /// Get the index of the JUMBF segment with the given description label
+    ///
+    /// The label `"c2pa"` always selects the C2PA manifest store
+    /// (see [`c2pa_jumbf_index`](Self::c2pa_jumbf_index)).
+    pub fn jumbf_index_by_label(&self, label: &str) -> Option<usize> {
+        if label == crate::jumbf::C2PA_LABEL {
+            return self.c2pa_jumbf_index();
+        }
+        self.jumbf_indices.iter().copied().find(|&i| {
+            self.segments[i]
+                .jumbf_description()
+                .is_some_and(|(l, _)| l == Some(label))
+        })
+    }
+
+    /// List every JUMBF box with its label and content type
+    pub fn jumbf_boxes(&self) -> Vec<crate::JumbfBox> {
+        let c2pa = self.c2pa_jumbf_index();
+        self.jumbf_indices
+            .iter()
+            .map(|&i| {
+                let description = self.segments[i].jumbf_description();
+                crate::JumbfBox {
+                    segment_index: i,
+                    label: description.and_then(|(l, _)| l).map(str::to_string),
+                    content_type: description.map(|(_, t)| *t),
+                    c2pa: c2pa == Some(i),
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the segment at `index` is one of the excluded `kinds`
+    ///
+    /// For [`SegmentKind::Jumbf`] only the C2PA manifest store is excluded; other
+    /// JUMBF boxes are hashed like any other data.
+    pub(crate) fn is_excluded(&self, index: usize, kinds: &[SegmentKind]) -> bool {
+        let segment = &self.segments[index];
+        kinds.iter().any(|kind| segment.is_type(*kind))
+            && (!segment.is_jumbf() || self.c2pa_jumbf_index() == Some(index))
     }
 
     /// Get reference to segments (for format-specific handlers)
@@ -186,17 +331,16 @@ impl Structure {
     /// Read a specific byte range from the file
     ///
     /// This is useful for data hash models that need to hash arbitrary ranges.
-    /// Returns an error if the range exceeds MAX_SEGMENT_SIZE (256MB).
+    /// Returns an error if the range exceeds [`max_segment_size`](Self::max_segment_size).
     /// Use `read_range_chunked` for streaming access to larger ranges.
     pub fn read_range<R: Read + Seek>(&self, source: &mut R, range: ByteRange) -> Result<Vec<u8>> {
         // Validate size to prevent memory exhaustion attacks
-        if range.size > MAX_SEGMENT_SIZE {
-            return Err(crate::Error::InvalidSegment {
+        if range.size > self.limits.max_segment_size {
+            return Err(crate::Error::LimitExceeded {
                 offset: range.offset,
                 reason: format!(
-                    "Range too large: {} bytes (max {} MB). Use read_range_chunked for large ranges.",
-                    range.size,
-                    MAX_SEGMENT_SIZE / (1024 * 1024)
+                    "Range too large: {} bytes (max {} bytes). Use read_range_chunked for large ranges.",
+                    range.size, self.limits.max_segment_size
                 ),
             });
         }
@@ -232,6 +376,7 @@ impl Structure {
     /// - **PNG**: Segment location + 4 bytes (data + CRC)
     /// - **RIFF**: Segment location with padded size (data + alignment padding)
     /// - **BMFF JUMBF**: Full UUID box range (not just JUMBF data), per BmffHash spec
+    /// - **Custom**: The registered handler's [`DataOnly`](crate::ExclusionMode::DataOnly) range
     ///
     /// Returns `None` if the segment kind is not found in this structure.
     pub fn exclusion_range_for_segment(&self, kind: SegmentKind) -> Option<(u64, u64)> {
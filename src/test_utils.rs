@@ -188,6 +188,26 @@ pub fn list_fixtures() -> Result<Vec<String>> {
     Ok(fixtures)
 }
 
+/// Load the named fixtures whose container this build can read
+///
+/// Fixtures of containers behind a disabled feature are left out; a fixture
+/// that cannot be loaded is an error.
+pub fn readable_fixtures<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<(&'a str, Vec<u8>)>> {
+    let mut fixtures = Vec::new();
+    for name in names {
+        let data = fixture_bytes(name)?;
+        let feature_disabled = crate::sniff(&data)
+            .unsupported
+            .is_some_and(|format| format.feature.is_some());
+        if !feature_disabled {
+            fixtures.push((name, data));
+        }
+    }
+    Ok(fixtures)
+}
+
 /// Check if a fixture is embedded
 pub fn is_embedded(fixture_name: &str) -> bool {
     get_registry().contains_key(fixture_name)
@@ -34,6 +34,7 @@ mod tags {
     pub const F_NUMBER: u16 = 0x829D;
     pub const ISO_SPEED: u16 = 0x8827;
     pub const DATE_TIME_ORIGINAL: u16 = 0x9003;
+    pub const OFFSET_TIME_ORIGINAL: u16 = 0x9011;
     pub const FOCAL_LENGTH: u16 = 0x920A;
 
     // IFD1 (thumbnail) tags
@@ -102,6 +103,8 @@ pub struct ExifInfo {
     pub date_time: Option<String>,
     /// Original capture date/time (from EXIF sub-IFD)
     pub date_time_original: Option<String>,
+    /// UTC offset of `date_time_original` (format: "+HH:MM"), from EXIF 2.31 OffsetTimeOriginal
+    pub offset_time_original: Option<String>,
     /// Artist/photographer name
     pub artist: Option<String>,
     /// Copyright notice
@@ -284,11 +287,11 @@ fn parse_exif_from_ifd(
         }
     }
 
-    // Parse EXIF sub-IFD for DateTimeOriginal if present
+    // Parse EXIF sub-IFD for DateTimeOriginal and OffsetTimeOriginal if present
     if let Some(exif_offset) = exif_ifd_offset {
-        if let Some(dt_orig) = parse_exif_subifd_datetime(data, exif_offset, byte_order) {
-            info.date_time_original = Some(dt_orig);
-        }
+        let (dt_orig, offset_orig) = parse_exif_subifd_datetime(data, exif_offset, byte_order);
+        info.date_time_original = dt_orig;
+        info.offset_time_original = offset_orig;
     }
 
     Ok(Some(info))
@@ -330,21 +333,31 @@ fn read_ascii_tag(
         .filter(|s| !s.is_empty())
 }
 
-/// Parse EXIF sub-IFD to get DateTimeOriginal
-fn parse_exif_subifd_datetime(data: &[u8], offset: u32, byte_order: ByteOrder) -> Option<String> {
+/// Parse EXIF sub-IFD to get DateTimeOriginal and OffsetTimeOriginal
+fn parse_exif_subifd_datetime(
+    data: &[u8],
+    offset: u32,
+    byte_order: ByteOrder,
+) -> (Option<String>, Option<String>) {
+    let mut date_time = None;
+    let mut offset_time = None;
     if offset as usize >= data.len() {
-        return None;
+        return (date_time, offset_time);
     }
 
     let mut cursor = Cursor::new(data);
-    cursor.seek(SeekFrom::Start(offset as u64)).ok()?;
+    if cursor.seek(SeekFrom::Start(offset as u64)).is_err() {
+        return (date_time, offset_time);
+    }
 
     let mut count_bytes = [0u8; 2];
-    cursor.read_exact(&mut count_bytes).ok()?;
+    if cursor.read_exact(&mut count_bytes).is_err() {
+        return (date_time, offset_time);
+    }
     let tag_count = byte_order.read_u16(&count_bytes);
 
     if tag_count > MAX_IFD_TAGS {
-        return None;
+        return (date_time, offset_time);
     }
 
     for _ in 0..tag_count {
@@ -358,12 +371,18 @@ fn parse_exif_subifd_datetime(data: &[u8], offset: u32, byte_order: ByteOrder) -
         let count = byte_order.read_u32(&tag_bytes[4..8]);
         let value_or_offset = byte_order.read_u32(&tag_bytes[8..12]);
 
-        if tag_id == tags::DATE_TIME_ORIGINAL {
-            return read_ascii_tag(data, tag_type, count, value_or_offset, byte_order);
+        match tag_id {
+            tags::DATE_TIME_ORIGINAL => {
+                date_time = read_ascii_tag(data, tag_type, count, value_or_offset, byte_order)
+            }
+            tags::OFFSET_TIME_ORIGINAL => {
+                offset_time = read_ascii_tag(data, tag_type, count, value_or_offset, byte_order)
+            }
+            _ => {}
         }
     }
 
-    None
+    (date_time, offset_time)
 }
 
 /// Parse an IFD and return the offset to the next IFD (if any)
@@ -1,6 +1,10 @@
 //! Updates and processing options for asset modifications
 
-use crate::segment::{ExclusionMode, SegmentKind, DEFAULT_CHUNK_SIZE};
+#[cfg(feature = "bmff")]
+use crate::containers::quicktime_meta::{QuickTimeEdit, QuickTimeKey, QuickTimeValue};
+#[cfg(feature = "riff")]
+use crate::containers::wav_meta::{BextChunk, WavEdit};
+use crate::segment::{ExclusionMode, SegmentKind};
 
 /// Options controlling how data is processed during read or write operations
 ///
@@ -19,22 +23,30 @@ pub(crate) struct ProcessingOptions {
 
     /// How to handle exclusions (default: EntireSegment)
     pub(crate) exclusion_mode: ExclusionMode,
+
+    /// Send streamed mdat data as mdat chunks even when it stays in the main
+    /// hash (set while building Merkle maps)
+    #[cfg(feature = "bmff")]
+    pub(crate) mdat_chunks: bool,
     // Future: include_segments for explicit inclusion
 }
 
 impl ProcessingOptions {
-    /// Get the effective chunk size (uses DEFAULT_CHUNK_SIZE if not set)
-    pub(crate) fn effective_chunk_size(&self) -> usize {
-        self.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE)
+    /// Get the effective chunk size (uses the asset's `default` if not set)
+    pub(crate) fn effective_chunk_size(&self, default: usize) -> usize {
+        self.chunk_size.unwrap_or(default)
     }
 }
 
-/// Metadata update strategy (internal)
+/// Metadata update strategy
 ///
 /// Specifies how to handle a particular type of metadata when writing an asset.
-/// By default, all metadata is kept unchanged.
-#[derive(Debug, Clone, Default)]
-pub(crate) enum MetadataUpdate {
+/// By default, all metadata is kept unchanged. Set through the `Updates`
+/// builder; handlers registered with
+/// [`register_container`](crate::register_container) read it back with
+/// [`Updates::xmp`] and [`Updates::jumbf`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MetadataUpdate {
     /// Keep existing metadata (default)
     #[default]
     Keep,
@@ -73,14 +85,29 @@ pub(crate) enum MetadataUpdate {
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Updates {
     /// XMP data update strategy (use builder methods to modify)
     pub(crate) xmp: MetadataUpdate,
 
-    /// JUMBF data update strategy (use builder methods to modify)
+    /// C2PA manifest store update strategy (use builder methods to modify)
     pub(crate) jumbf: MetadataUpdate,
 
+    /// Updates to other JUMBF boxes, by description label (one entry per label)
+    pub(crate) jumbf_boxes: Vec<(String, MetadataUpdate)>,
+
+    /// QuickTime / MP4 descriptive metadata edits, applied in order
+    #[cfg(feature = "bmff")]
+    pub(crate) quicktime: Vec<QuickTimeEdit>,
+
+    /// Move `moov` in front of the first `mdat` (BMFF "faststart")
+    #[cfg(feature = "bmff")]
+    pub(crate) faststart: bool,
+
+    /// WAV `bext`, `iXML` and `LIST/INFO` edits, applied in order
+    #[cfg(feature = "riff")]
+    pub(crate) wav: Vec<WavEdit>,
+
     /// Processing options (chunk size, exclusions, etc.)
     /// Used by both read_with_processing() and write_with_processing()
     pub(crate) processing: ProcessingOptions,
@@ -94,6 +121,16 @@ impl Updates {
         Self::default()
     }
 
+    /// What to do with the XMP packet
+    pub fn xmp(&self) -> &MetadataUpdate {
+        &self.xmp
+    }
+
+    /// What to do with the C2PA manifest store JUMBF
+    pub fn jumbf(&self) -> &MetadataUpdate {
+        &self.jumbf
+    }
+
     /// Set XMP metadata to a new value
     ///
     /// # Example
@@ -137,7 +174,9 @@ impl Updates {
         self
     }
 
-    /// Set JUMBF metadata to a new value
+    /// Set the C2PA manifest store JUMBF to a new value
+    ///
+    /// Other JUMBF boxes in the file are kept (see [`set_jumbf_box`](Self::set_jumbf_box)).
     ///
     /// # Example
     ///
@@ -152,7 +191,7 @@ impl Updates {
         self
     }
 
-    /// Remove JUMBF metadata
+    /// Remove the C2PA manifest store JUMBF
     ///
     /// # Example
     ///
@@ -180,6 +219,209 @@ impl Updates {
         self
     }
 
+    /// Add or replace the JUMBF box with the given description label
+    ///
+    /// Only JPEG files can hold JUMBF boxes other than the C2PA manifest store;
+    /// writing other formats fails with [`Error::Unsupported`](crate::Error::Unsupported).
+    /// The label `"c2pa"` is the same as [`set_jumbf`](Self::set_jumbf).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::Updates;
+    ///
+    /// let jpeg360 = std::fs::read("jpeg360.jumbf")?;
+    /// let updates = Updates::new().set_jumbf_box("jpeg360", jpeg360);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn set_jumbf_box(self, label: impl Into<String>, jumbf: Vec<u8>) -> Self {
+        self.update_jumbf_box(label.into(), MetadataUpdate::Set(jumbf))
+    }
+
+    /// Remove the JUMBF box with the given description label
+    ///
+    /// As with [`set_jumbf_box`](Self::set_jumbf_box), writing formats other
+    /// than JPEG fails. The label `"c2pa"` is the same as
+    /// [`remove_jumbf`](Self::remove_jumbf).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::Updates;
+    ///
+    /// let updates = Updates::new().remove_jumbf_box("jpeg360");
+    /// ```
+    pub fn remove_jumbf_box(self, label: impl Into<String>) -> Self {
+        self.update_jumbf_box(label.into(), MetadataUpdate::Remove)
+    }
+
+    fn update_jumbf_box(mut self, label: String, update: MetadataUpdate) -> Self {
+        if label == crate::jumbf::C2PA_LABEL {
+            self.jumbf = update;
+        } else {
+            self.jumbf_boxes.retain(|(l, _)| *l != label);
+            self.jumbf_boxes.push((label, update));
+        }
+        self
+    }
+
+    /// The update for the JUMBF box with the given label (other than `"c2pa"`)
+    #[cfg(feature = "jpeg")]
+    pub(crate) fn jumbf_box_update(&self, label: &str) -> Option<&MetadataUpdate> {
+        self.jumbf_boxes
+            .iter()
+            .find(|(l, _)| l == label)
+            .map(|(_, update)| update)
+    }
+
+    /// Set a QuickTime / MP4 metadata entry, replacing any existing values
+    ///
+    /// Entries are stored in `moov/udta` or `moov/meta` depending on the key.
+    /// Ignored for files without a `moov` box (e.g. HEIF images).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{QuickTimeKey, QuickTimeValue, Updates};
+    ///
+    /// let updates = Updates::new()
+    ///     .set_quicktime(QuickTimeKey::TITLE, QuickTimeValue::Text("Holiday".into()));
+    /// ```
+    #[cfg(feature = "bmff")]
+    pub fn set_quicktime(mut self, key: QuickTimeKey, value: QuickTimeValue) -> Self {
+        self.quicktime.push(QuickTimeEdit::Set(key, value));
+        self
+    }
+
+    /// Remove all values of a QuickTime / MP4 metadata entry
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{QuickTimeKey, Updates};
+    ///
+    /// let updates = Updates::new().remove_quicktime(QuickTimeKey::COVER);
+    /// ```
+    #[cfg(feature = "bmff")]
+    pub fn remove_quicktime(mut self, key: QuickTimeKey) -> Self {
+        self.quicktime.push(QuickTimeEdit::Remove(key));
+        self
+    }
+
+    /// Remove all QuickTime location metadata
+    ///
+    /// This strips the `com.apple.quicktime.location.*` `mdta` keys and the
+    /// `©xyz` user data atoms of the movie and of each track.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::Updates;
+    ///
+    /// let updates = Updates::new().remove_quicktime_location();
+    /// ```
+    #[cfg(feature = "bmff")]
+    pub fn remove_quicktime_location(mut self) -> Self {
+        self.quicktime.push(QuickTimeEdit::RemoveLocation);
+        self
+    }
+
+    /// Move the `moov` box in front of the media data
+    ///
+    /// Files that end with `moov` cannot start playing until fully downloaded.
+    /// With this option the write places `moov` right before the first `mdat` and
+    /// adjusts the `stco`/`co64` chunk offsets accordingly. Files that already have
+    /// `moov` first are unaffected.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::Updates;
+    ///
+    /// let updates = Updates::new()
+    ///     .set_jumbf(b"manifest".to_vec())
+    ///     .faststart();
+    /// ```
+    #[cfg(feature = "bmff")]
+    pub fn faststart(mut self) -> Self {
+        self.faststart = true;
+        self
+    }
+
+    /// Set the Broadcast WAV `bext` chunk, replacing any existing one
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{BextChunk, Updates};
+    ///
+    /// let updates = Updates::new().set_bext(BextChunk {
+    ///     originator: "Recorder".into(),
+    ///     origination_date: "2024-05-01".into(),
+    ///     ..Default::default()
+    /// });
+    /// ```
+    #[cfg(feature = "riff")]
+    pub fn set_bext(mut self, bext: BextChunk) -> Self {
+        self.wav.push(WavEdit::SetBext(Box::new(bext)));
+        self
+    }
+
+    /// Remove the Broadcast WAV `bext` chunk
+    #[cfg(feature = "riff")]
+    pub fn remove_bext(mut self) -> Self {
+        self.wav.push(WavEdit::RemoveBext);
+        self
+    }
+
+    /// Set the WAV `iXML` chunk, replacing any existing one
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::Updates;
+    ///
+    /// let updates = Updates::new().set_ixml("<BWFXML>...</BWFXML>");
+    /// ```
+    #[cfg(feature = "riff")]
+    pub fn set_ixml(mut self, ixml: impl Into<String>) -> Self {
+        self.wav.push(WavEdit::SetIxml(ixml.into()));
+        self
+    }
+
+    /// Remove the WAV `iXML` chunk
+    #[cfg(feature = "riff")]
+    pub fn remove_ixml(mut self) -> Self {
+        self.wav.push(WavEdit::RemoveIxml);
+        self
+    }
+
+    /// Set a WAV `LIST/INFO` entry, replacing any existing values
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use asset_io::{Updates, WavMetadata};
+    ///
+    /// let updates = Updates::new()
+    ///     .set_riff_info(WavMetadata::TITLE, "Interview")
+    ///     .set_riff_info(WavMetadata::SOFTWARE, "asset-io");
+    /// ```
+    #[cfg(feature = "riff")]
+    pub fn set_riff_info(mut self, id: [u8; 4], value: impl Into<String>) -> Self {
+        self.wav.push(WavEdit::SetInfo(id, value.into()));
+        self
+    }
+
+    /// Remove all values of a WAV `LIST/INFO` entry
+    ///
+    /// The `LIST/INFO` chunk is dropped when its last entry is removed.
+    #[cfg(feature = "riff")]
+    pub fn remove_riff_info(mut self, id: [u8; 4]) -> Self {
+        self.wav.push(WavEdit::RemoveInfo(id));
+        self
+    }
+
     /// Create updates that keep all existing metadata (no changes)
     ///
     /// This is an alias for `Updates::new()` or `Updates::default()`.
@@ -121,7 +121,7 @@ mod fixture_tests {
         // Verify data is valid
         let (data, format) = registry.get(FIREFLY_TRAIN).unwrap();
         assert_eq!(*format, "image/jpeg");
-        assert!(&data[0..2] == &[0xFF, 0xD8], "Should be valid JPEG");
+        assert!(data[0..2] == [0xFF, 0xD8], "Should be valid JPEG");
     }
 
     #[test]
@@ -149,7 +149,7 @@ mod fixture_tests {
             let path = fixture_path("capture.jpg");
             if path.exists() {
                 let data = std::fs::read(&path).expect("Should read extended fixture");
-                assert!(&data[0..2] == &[0xFF, 0xD8], "Should be valid JPEG");
+                assert!(data[0..2] == [0xFF, 0xD8], "Should be valid JPEG");
                 println!("Successfully loaded extended fixture: capture.jpg");
             }
         } else {
@@ -501,7 +501,8 @@ mod streaming_tests {
             let input_path = fixture_path(fixture);
             let output_path = format!("/tmp/test_streaming_{}.{}", name, ext);
 
-            let mut asset = Asset::open(&input_path).expect(&format!("Failed to open {}", name));
+            let mut asset =
+                Asset::open(&input_path).unwrap_or_else(|_| panic!("Failed to open {}", name));
 
             // Create placeholder JUMBF (using valid JUMBF structure)
             let placeholder = create_test_jumbf();
@@ -526,12 +527,12 @@ mod streaming_tests {
                     &updates,
                     &mut |_chunk: &dyn asset_io::ProcessChunk| Ok(()),
                 )
-                .expect(&format!("write_with_processing failed for {}", name));
+                .unwrap_or_else(|_| panic!("write_with_processing failed for {}", name));
 
             // Check JUMBF segment exists in structure
             let jumbf_idx = structure
                 .c2pa_jumbf_index()
-                .expect(&format!("No JUMBF in structure for {}", name));
+                .unwrap_or_else(|| panic!("No JUMBF in structure for {}", name));
             let jumbf_seg = &structure.segments[jumbf_idx];
             println!(
                 "  JUMBF segment: offset=0x{:x}, size={}",
@@ -552,19 +553,19 @@ mod streaming_tests {
 
             structure
                 .update_segment(&mut output_file, SegmentKind::Jumbf, final_jumbf.clone())
-                .expect(&format!("update_segment failed for {}", name));
+                .unwrap_or_else(|_| panic!("update_segment failed for {}", name));
 
             // Flush and close
             drop(output_file);
 
             // Verify output is valid and JUMBF was updated
-            let mut verify_asset =
-                Asset::open(&output_path).expect(&format!("Failed to reopen output for {}", name));
+            let mut verify_asset = Asset::open(&output_path)
+                .unwrap_or_else(|_| panic!("Failed to reopen output for {}", name));
 
             let result_jumbf = verify_asset
                 .jumbf()
-                .expect(&format!("Failed to extract JUMBF from {}", name))
-                .expect(&format!("JUMBF not found in output {}", name));
+                .unwrap_or_else(|_| panic!("Failed to extract JUMBF from {}", name))
+                .unwrap_or_else(|| panic!("JUMBF not found in output {}", name));
 
             // Verify we can read back JUMBF data of the correct size
             assert_eq!(
@@ -685,7 +686,7 @@ mod streaming_tests {
                     let base = format!(
                         "{}_{}_{}_{}",
                         format_name,
-                        fixture.replace('/', "_").replace('.', "_"),
+                        fixture.replace(['/', '.'], "_"),
                         xmp_name.to_lowercase(),
                         jumbf_name.to_lowercase()
                     );
@@ -710,15 +711,19 @@ mod streaming_tests {
                         _ => unreachable!(),
                     };
 
-                    let mut asset = Asset::open(&input_path).expect(&format!(
-                        "Failed to open {} for {} x {} test",
-                        fixture, xmp_name, jumbf_name
-                    ));
+                    let mut asset = Asset::open(&input_path).unwrap_or_else(|_| {
+                        panic!(
+                            "Failed to open {} for {} x {} test",
+                            fixture, xmp_name, jumbf_name
+                        )
+                    });
 
-                    asset.write_to(&output, &updates).expect(&format!(
-                        "Write failed for {} x {} on {}",
-                        xmp_name, jumbf_name, format_name
-                    ));
+                    asset.write_to(&output, &updates).unwrap_or_else(|_| {
+                        panic!(
+                            "Write failed for {} x {} on {}",
+                            xmp_name, jumbf_name, format_name
+                        )
+                    });
 
                     // Verify output parses and metadata state matches expectations
                     let mut verify = Asset::open(&output).expect("Failed to reopen output");
@@ -773,3 +778,2385 @@ mod streaming_tests {
         }
     }
 }
+
+#[cfg(all(test, feature = "bmff", feature = "parallel"))]
+mod merkle_tests {
+    use asset_io::{
+        test_utils::*, Asset, AssetBuilder, ExclusionMode, MerkleBlocks, MerkleOddNode, MerkleTree,
+        SegmentKind, Updates,
+    };
+    use sha2::{Digest, Sha256};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_bmff_merkle_maps_match_between_read_and_write() {
+        let block_size = 4096u64;
+        let mut asset = Asset::open(fixture_path(SAMPLE1_HEIC)).unwrap();
+
+        let updates = Updates::new()
+            .set_jumbf(vec![0u8; 1024])
+            .exclude_from_processing(vec![SegmentKind::Jumbf], ExclusionMode::DataOnly);
+
+        let mut output = Cursor::new(Vec::new());
+        let mut hasher = Sha256::new();
+        let (_, written) = asset
+            .write_with_merkle_maps::<_, Sha256, _>(
+                &mut output,
+                &updates,
+                MerkleBlocks::Fixed(block_size),
+                &mut |chunk: &dyn asset_io::ProcessChunk| {
+                    if chunk.id().is_none() {
+                        hasher.update(chunk.data());
+                    }
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert!(
+            !written.maps.is_empty(),
+            "sample1.heic should have mdat data"
+        );
+
+        // Re-reading the output must produce the same maps and init hash
+        let mut reread = Asset::from_source(Cursor::new(output.into_inner())).unwrap();
+        let read = reread
+            .bmff_merkle_maps::<Sha256>(MerkleBlocks::Fixed(block_size))
+            .unwrap();
+        assert_eq!(read, written);
+
+        for map in &read.maps {
+            assert_eq!(map.count, map.leaves.len());
+            assert_eq!(map.fixed_block_size, Some(block_size));
+            let tree = MerkleTree::<Sha256>::new(&map.leaves, MerkleOddNode::Promote);
+            assert_eq!(map.root, tree.root());
+        }
+
+        // Replacing the manifest with a signed one of the same size changes neither
+        let updates = Updates::new().set_jumbf(vec![1u8; 1024]);
+        let mut output = Cursor::new(Vec::new());
+        reread.write(&mut output, &updates).unwrap();
+        let mut resigned = Asset::from_source(output).unwrap();
+        let maps = resigned
+            .bmff_merkle_maps::<Sha256>(MerkleBlocks::Fixed(block_size))
+            .unwrap();
+        assert_eq!(maps.init_hash, read.init_hash);
+        assert_eq!(maps.maps, read.maps);
+    }
+
+    #[test]
+    fn test_bmff_merkle_maps_by_media_chunk() {
+        let mut asset = Asset::open(fixture_path("sample1.m4a")).unwrap();
+        let updates = Updates::new()
+            .set_jumbf(vec![0u8; 1024])
+            .exclude_from_processing(vec![SegmentKind::Jumbf], ExclusionMode::DataOnly);
+
+        let mut output = Cursor::new(Vec::new());
+        let (_, written) = asset
+            .write_with_merkle_maps::<_, Sha256, _>(
+                &mut output,
+                &updates,
+                MerkleBlocks::Chunks,
+                &mut |_| Ok(()),
+            )
+            .unwrap();
+
+        // Chunk offsets moved with the mdat, so re-reading finds the same blocks
+        let mut reread = Asset::from_source(output).unwrap();
+        let read = reread
+            .bmff_merkle_maps::<Sha256>(MerkleBlocks::Chunks)
+            .unwrap();
+        assert_eq!(read, written);
+
+        // Leaves cover each mdat past its first 16 bytes
+        let mdats: Vec<u64> = reread
+            .structure()
+            .bmff_tree()
+            .unwrap()
+            .top_level()
+            .filter(|b| b.box_type() == "mdat")
+            .map(|b| b.size())
+            .collect();
+        assert_eq!(written.maps.len(), mdats.len());
+        for map in &written.maps {
+            let sizes = map.variable_block_sizes.as_ref().unwrap();
+            assert!(map.count > 1);
+            assert_eq!(sizes.len(), map.count);
+            assert_eq!(sizes.iter().sum::<u64>(), mdats[map.local_id] - 16);
+        }
+    }
+
+    #[test]
+    fn test_streamed_mdat_chunks_only_for_merkle_maps() {
+        // A small segment limit sends the mdat box down the streaming path
+        let open = || {
+            AssetBuilder::new()
+                .max_segment_size(64 * 1024)
+                .open(fixture_path(SAMPLE1_HEIC))
+                .unwrap()
+        };
+        // Without a JUMBF exclusion the mdat stays in the main hash
+        let updates = Updates::new().set_jumbf(vec![0u8; 1024]);
+
+        let mut output = Cursor::new(Vec::new());
+        let mut plain = Vec::new();
+        let mut mdat_chunks = 0;
+        open()
+            .write_with_processing(
+                &mut output,
+                &updates,
+                &mut |chunk: &dyn asset_io::ProcessChunk| {
+                    match chunk.id() {
+                        Some(_) => mdat_chunks += 1,
+                        None => plain.extend_from_slice(chunk.data()),
+                    }
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(mdat_chunks, 0);
+        // Everything after the leading ftyp and manifest boxes is hashed exactly once
+        assert!(output.into_inner().ends_with(&plain));
+
+        let mut output = Cursor::new(Vec::new());
+        let (_, written) = open()
+            .write_with_merkle_maps::<_, Sha256, _>(
+                &mut output,
+                &updates,
+                MerkleBlocks::Fixed(4096),
+                &mut |_| Ok(()),
+            )
+            .unwrap();
+        assert!(!written.maps.is_empty());
+        let mut reread = Asset::from_source(output).unwrap();
+        assert_eq!(
+            reread
+                .bmff_merkle_maps::<Sha256>(MerkleBlocks::Fixed(4096))
+                .unwrap(),
+            written
+        );
+    }
+
+    #[test]
+    fn test_bmff_merkle_maps_rejects_other_formats() {
+        let mut asset = Asset::open(fixture_path(FIREFLY_TRAIN)).unwrap();
+        assert!(asset
+            .bmff_merkle_maps::<Sha256>(MerkleBlocks::Fixed(4096))
+            .is_err());
+    }
+}
+
+#[cfg(all(test, feature = "bmff"))]
+mod heif_item_tests {
+    use asset_io::{test_utils::*, Asset, AssetBuilder, Error, SegmentKind, Updates};
+    use std::io::Cursor;
+
+    const XMP: &[u8] = br#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF/></x:xmpmeta>"#;
+
+    fn paths(asset: &Asset<Cursor<Vec<u8>>>, kind: SegmentKind) -> Vec<String> {
+        asset
+            .structure()
+            .segments()
+            .iter()
+            .filter(|s| s.kind == kind)
+            .filter_map(|s| s.path.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_heif_xmp_written_as_item() {
+        let input = std::fs::read(fixture_path(SAMPLE1_HEIC)).unwrap();
+        let mut asset = Asset::from_source(Cursor::new(input.clone())).unwrap();
+
+        let mut output = Cursor::new(Vec::new());
+        let expected = asset
+            .write(&mut output, &Updates::new().set_xmp(XMP.to_vec()))
+            .unwrap();
+
+        let mut written = Asset::from_source(Cursor::new(output.into_inner())).unwrap();
+        assert_eq!(written.xmp().unwrap().as_deref(), Some(XMP));
+        assert_eq!(paths(&written, SegmentKind::Xmp), vec!["meta/item/xmp"]);
+
+        // The predicted structure matches the file that was written
+        let actual = written.structure();
+        assert_eq!(expected.total_size, actual.total_size);
+        let xmp_ranges =
+            |s: &asset_io::Structure| s.segments()[s.xmp_index().unwrap()].ranges.clone();
+        assert_eq!(xmp_ranges(&expected), xmp_ranges(actual));
+
+        // Items that live in mdat (here the thumbnail) still resolve to the same bytes
+        #[cfg(feature = "exif")]
+        {
+            let mut source = Asset::from_source(Cursor::new(input)).unwrap();
+            let thumb = source.read_embedded_thumbnail().unwrap().unwrap();
+            let moved = written.read_embedded_thumbnail().unwrap().unwrap();
+            assert_eq!(thumb.data, moved.data);
+        }
+
+        // Replacing and removing the item
+        let mut output = Cursor::new(Vec::new());
+        written
+            .write(&mut output, &Updates::new().set_xmp(b"<x/>".to_vec()))
+            .unwrap();
+        let mut replaced = Asset::from_source(Cursor::new(output.into_inner())).unwrap();
+        assert_eq!(replaced.xmp().unwrap().as_deref(), Some(&b"<x/>"[..]));
+        assert_eq!(paths(&replaced, SegmentKind::Xmp).len(), 1);
+
+        let mut output = Cursor::new(Vec::new());
+        replaced
+            .write(&mut output, &Updates::new().remove_xmp())
+            .unwrap();
+        let mut removed = Asset::from_source(Cursor::new(output.into_inner())).unwrap();
+        assert_eq!(removed.xmp().unwrap(), None);
+    }
+
+    fn bmff_box(fourcc: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(fourcc);
+        data.extend_from_slice(body);
+        data
+    }
+
+    /// HEIF whose XMP item lives in mdat (iloc construction method 0)
+    fn heif_with_xmp_in_mdat(xmp: &[u8]) -> Vec<u8> {
+        let meta = |xmp_offset: u32| {
+            let mut hdlr = vec![0u8; 8];
+            hdlr.extend_from_slice(b"pict");
+            hdlr.extend_from_slice(&[0u8; 13]);
+            let mut infe = vec![2, 0, 0, 0, 0, 1, 0, 0];
+            infe.extend_from_slice(b"mime\0application/rdf+xml\0");
+            let mut iinf = vec![0, 0, 0, 0, 0, 1];
+            iinf.extend(bmff_box(b"infe", &infe));
+            let mut iloc = vec![0, 0, 0, 0, 0x44, 0x00, 0, 1, 0, 1, 0, 0, 0, 1];
+            iloc.extend_from_slice(&xmp_offset.to_be_bytes());
+            iloc.extend_from_slice(&(xmp.len() as u32).to_be_bytes());
+            let mut body = vec![0u8; 4];
+            body.extend(bmff_box(b"hdlr", &hdlr));
+            body.extend(bmff_box(b"iinf", &iinf));
+            body.extend(bmff_box(b"iloc", &iloc));
+            bmff_box(b"meta", &body)
+        };
+        let mut data = bmff_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+        let xmp_offset = data.len() + meta(0).len() + 8;
+        data.extend(meta(xmp_offset as u32));
+        data.extend(bmff_box(b"mdat", xmp));
+        data
+    }
+
+    #[test]
+    fn test_heif_xmp_item_in_mdat_replaced() {
+        let input = heif_with_xmp_in_mdat(b"<x:xmpmeta>old</x:xmpmeta>");
+        let mut asset = Asset::from_source(Cursor::new(input)).unwrap();
+        assert_eq!(paths(&asset, SegmentKind::Xmp), vec!["meta/item/xmp"]);
+
+        let mut output = Cursor::new(Vec::new());
+        let expected = asset
+            .write(&mut output, &Updates::new().set_xmp(XMP.to_vec()))
+            .unwrap();
+        let mut written = Asset::from_source(Cursor::new(output.into_inner())).unwrap();
+        assert_eq!(written.xmp().unwrap().as_deref(), Some(XMP));
+        assert_eq!(paths(&written, SegmentKind::Xmp), vec!["meta/item/xmp"]);
+        assert_eq!(expected.total_size, written.structure().total_size);
+
+        // Removing it leaves no XMP item behind
+        let mut output = Cursor::new(Vec::new());
+        written
+            .write(&mut output, &Updates::new().remove_xmp())
+            .unwrap();
+        let mut removed = Asset::from_source(Cursor::new(output.into_inner())).unwrap();
+        assert_eq!(removed.xmp().unwrap(), None);
+        assert!(paths(&removed, SegmentKind::Xmp).is_empty());
+    }
+
+    #[test]
+    fn test_heif_meta_over_segment_limit() {
+        let input = heif_with_xmp_in_mdat(XMP);
+        let builder = || AssetBuilder::new().max_segment_size(64);
+
+        let error = builder()
+            .from_source(Cursor::new(input.clone()))
+            .err()
+            .unwrap();
+        assert!(
+            matches!(error.root(), Error::LimitExceeded { .. }),
+            "{error}"
+        );
+
+        let asset = builder()
+            .strict(false)
+            .from_source(Cursor::new(input))
+            .unwrap();
+        assert!(paths(&asset, SegmentKind::Xmp).is_empty());
+        assert_eq!(asset.diagnostics().len(), 1);
+    }
+}
+
+#[cfg(all(test, feature = "bmff"))]
+mod quicktime_tests {
+    use asset_io::{
+        test_utils::*, Asset, QuickTimeKey, QuickTimeValue, Updates, QUICKTIME_LOCATION_KEY,
+    };
+    use std::io::Cursor;
+
+    /// First chunk offset of the first stco box
+    fn first_chunk_offset(data: &[u8]) -> usize {
+        let stco = data.windows(4).position(|w| w == b"stco").unwrap();
+        u32::from_be_bytes(data[stco + 12..stco + 16].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn test_quicktime_metadata_round_trip() {
+        let input = std::fs::read(fixture_path("sample1.m4a")).unwrap();
+        let mut asset = Asset::from_source(Cursor::new(input.clone())).unwrap();
+        let metadata = asset.quicktime_metadata().unwrap().unwrap();
+        assert!(metadata.text(&QuickTimeKey::TITLE).is_some());
+        assert_eq!(metadata.location(), None);
+
+        let location = "+48.8577+002.2950/";
+        let updates = Updates::new()
+            .set_quicktime(
+                QuickTimeKey::TITLE,
+                QuickTimeValue::Text("New title".into()),
+            )
+            .set_quicktime(
+                QuickTimeKey::mdta(QUICKTIME_LOCATION_KEY),
+                QuickTimeValue::Text(location.into()),
+            );
+        let mut output = Cursor::new(Vec::new());
+        let expected = asset.write(&mut output, &updates).unwrap();
+        let output = output.into_inner();
+        assert_eq!(expected.total_size, output.len() as u64);
+
+        // Chunk offsets follow the media data as moov grows
+        let chunk = |data: &[u8]| {
+            let offset = first_chunk_offset(data);
+            data[offset..offset + 64].to_vec()
+        };
+        assert_ne!(first_chunk_offset(&input), first_chunk_offset(&output));
+        assert_eq!(chunk(&input), chunk(&output));
+
+        let mut written = Asset::from_source(Cursor::new(output)).unwrap();
+        let metadata = written.quicktime_metadata().unwrap().unwrap();
+        assert_eq!(metadata.text(&QuickTimeKey::TITLE), Some("New title"));
+        assert_eq!(metadata.location(), Some(location));
+
+        let moov = |s: &asset_io::Structure| {
+            s.segments()
+                .iter()
+                .filter(|seg| seg.path.as_deref().is_some_and(|p| p.starts_with("moov")))
+                .map(|seg| (seg.path.clone(), seg.ranges.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(moov(&expected), moov(written.structure()));
+
+        // Stripping the location
+        let mut output = Cursor::new(Vec::new());
+        written
+            .write(&mut output, &Updates::new().remove_quicktime_location())
+            .unwrap();
+        let output = output.into_inner();
+        assert_eq!(chunk(&input), chunk(&output));
+        let mut stripped = Asset::from_source(Cursor::new(output)).unwrap();
+        let metadata = stripped.quicktime_metadata().unwrap().unwrap();
+        assert_eq!(metadata.location(), None);
+        assert_eq!(metadata.text(&QuickTimeKey::TITLE), Some("New title"));
+    }
+
+    fn mp4_box(fourcc: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(fourcc);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// ftyp, mdat, moov with a single chunk pointing into mdat
+    fn moov_last_mp4(chunk: &[u8]) -> Vec<u8> {
+        let mut ftyp = b"isom".to_vec();
+        ftyp.extend_from_slice(&[0, 0, 2, 0]);
+        ftyp.extend_from_slice(b"isommp41");
+        let mut file = mp4_box(b"ftyp", &ftyp);
+        let chunk_offset = file.len() as u32 + 8;
+        file.extend(mp4_box(b"mdat", chunk));
+
+        let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stco.extend_from_slice(&chunk_offset.to_be_bytes());
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stco", &stco));
+        let trak = mp4_box(b"trak", &mp4_box(b"mdia", &mp4_box(b"minf", &stbl)));
+        let mut moov = mp4_box(b"mvhd", &[0u8; 100]);
+        moov.extend(trak);
+        file.extend(mp4_box(b"moov", &moov));
+        file
+    }
+
+    /// ftyp, moov with two tracks, mdat; the first track has a `©xyz` location
+    fn track_location_mp4(chunk: &[u8]) -> Vec<u8> {
+        let mut ftyp = b"isom".to_vec();
+        ftyp.extend_from_slice(&[0, 0, 2, 0]);
+        ftyp.extend_from_slice(b"isommp41");
+
+        let build = |chunk_offset: u32| {
+            let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            stco.extend_from_slice(&chunk_offset.to_be_bytes());
+            let mdia = mp4_box(
+                b"mdia",
+                &mp4_box(b"minf", &mp4_box(b"stbl", &mp4_box(b"stco", &stco))),
+            );
+            let mut xyz = vec![0, 18, 0x15, 0xc7];
+            xyz.extend_from_slice(b"+48.8577+002.2950/");
+            let mut located = mdia.clone();
+            located.extend(mp4_box(b"udta", &mp4_box(b"\xa9xyz", &xyz)));
+
+            let mut file = mp4_box(b"ftyp", &ftyp);
+            let mut moov = mp4_box(b"mvhd", &[0u8; 100]);
+            moov.extend(mp4_box(b"trak", &located));
+            moov.extend(mp4_box(b"trak", &mdia));
+            file.extend(mp4_box(b"moov", &moov));
+            file
+        };
+        let mut file = build(0);
+        file = build(file.len() as u32 + 8);
+        file.extend(mp4_box(b"mdat", chunk));
+        file
+    }
+
+    #[test]
+    fn test_remove_location_from_tracks() {
+        let chunk = b"media chunk data";
+        let input = track_location_mp4(chunk);
+        let mut asset = Asset::from_source(Cursor::new(input.clone())).unwrap();
+
+        let mut output = Cursor::new(Vec::new());
+        let expected = asset
+            .write(&mut output, &Updates::new().remove_quicktime_location())
+            .unwrap();
+        let output = output.into_inner();
+        assert!(!output.windows(4).any(|w| w == b"\xa9xyz"));
+        assert_eq!(output.len(), input.len() - 30);
+
+        // Both tracks' chunk offsets follow the media data
+        let stco: Vec<usize> = output
+            .windows(4)
+            .enumerate()
+            .filter(|(_, w)| *w == b"stco")
+            .map(|(i, _)| u32::from_be_bytes(output[i + 12..i + 16].try_into().unwrap()) as usize)
+            .collect();
+        assert_eq!(stco.len(), 2);
+        for offset in stco {
+            assert_eq!(&output[offset..offset + chunk.len()], chunk);
+        }
+
+        // The predicted layout matches the written file
+        let written = Asset::from_source(Cursor::new(output.clone())).unwrap();
+        let layout = |s: &asset_io::Structure| {
+            s.segments()
+                .iter()
+                .map(|seg| (seg.path.clone(), seg.ranges.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(expected.total_size, output.len() as u64);
+        assert_eq!(layout(&expected), layout(written.structure()));
+    }
+
+    #[test]
+    fn test_faststart_moves_moov_before_mdat() {
+        let chunk = b"media chunk data";
+        let input = moov_last_mp4(chunk);
+        let mut asset = Asset::from_source(Cursor::new(input)).unwrap();
+
+        let updates = Updates::new()
+            .set_jumbf(b"jumbf".to_vec())
+            .set_quicktime(QuickTimeKey::TITLE, QuickTimeValue::Text("Fast".into()))
+            .faststart();
+        let mut output = Cursor::new(Vec::new());
+        let expected = asset.write(&mut output, &updates).unwrap();
+        let output = output.into_inner();
+
+        // moov now precedes mdat and the chunk offset follows the data
+        let moov = output.windows(4).position(|w| w == b"moov").unwrap();
+        let mdat = output.windows(4).position(|w| w == b"mdat").unwrap();
+        assert!(moov < mdat);
+        let offset = first_chunk_offset(&output);
+        assert_eq!(&output[offset..offset + chunk.len()], chunk);
+
+        // The predicted layout matches the written file
+        let written = Asset::from_source(Cursor::new(output.clone())).unwrap();
+        let layout = |s: &asset_io::Structure| {
+            let mut layout: Vec<_> = s
+                .segments()
+                .iter()
+                .map(|seg| {
+                    let ranges: Vec<_> = seg.ranges.iter().map(|r| (r.offset, r.size)).collect();
+                    (seg.kind.as_str(), ranges)
+                })
+                .collect();
+            layout.sort();
+            layout
+        };
+        assert_eq!(expected.total_size, output.len() as u64);
+        assert_eq!(layout(&expected), layout(written.structure()));
+
+        // Files that already start with moov are left in place
+        let mut again = Cursor::new(Vec::new());
+        Asset::from_source(Cursor::new(output.clone()))
+            .unwrap()
+            .write(&mut again, &Updates::new().faststart())
+            .unwrap();
+        assert_eq!(again.into_inner(), output);
+    }
+}
+
+#[cfg(all(test, feature = "bmff"))]
+mod bmff_tree_tests {
+    use asset_io::{test_utils::*, Asset};
+
+    #[test]
+    fn test_bmff_tree_navigation() {
+        let mut asset = Asset::open(fixture_path("sample1.m4a")).unwrap();
+        let tree = asset.structure().bmff_tree().unwrap().clone();
+
+        let top: Vec<&str> = tree.top_level().map(|b| b.box_type()).collect();
+        assert_eq!(top[0], "ftyp");
+        assert!(top.contains(&"moov") && top.contains(&"mdat"));
+
+        // Track handler type, read lazily
+        let hdlr = tree.find("/moov/trak/mdia/hdlr").unwrap();
+        assert_eq!(hdlr.version(), Some(0));
+        let payload = asset.read_bmff_payload(hdlr).unwrap();
+        assert_eq!(&payload[4..8], b"soun");
+
+        // Parent/child navigation agrees with paths
+        let mdia = tree.parent(hdlr).unwrap();
+        assert_eq!(mdia.path(), "/moov/trak/mdia");
+        assert_eq!(tree.child(mdia, "hdlr").unwrap().id(), hdlr.id());
+        for child in tree.children(mdia) {
+            assert_eq!(
+                child.path(),
+                format!("/moov/trak/mdia/{}", child.box_type())
+            );
+            assert!(child.offset() >= mdia.offset() + mdia.header_size());
+        }
+
+        // Top-level boxes cover the whole file
+        let covered: u64 = tree.top_level().map(|b| b.size()).sum();
+        assert_eq!(covered, asset.structure().total_size);
+    }
+}
+
+#[cfg(test)]
+mod media_info_tests {
+    use asset_io::{test_utils::*, Asset};
+    use std::time::Duration;
+
+    #[test]
+    fn test_jpeg_media_info() {
+        let mut asset = Asset::open(fixture_path(FIREFLY_TRAIN)).unwrap();
+        let info = asset.media_info().unwrap();
+        assert_eq!((info.width, info.height), (Some(672), Some(384)));
+        assert_eq!(info.bit_depth, Some(8));
+        assert_eq!(info.components, Some(3));
+        assert_eq!(info.progressive, Some(false));
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_png_media_info() {
+        let mut asset = Asset::open(fixture_path("sample1.png")).unwrap();
+        let info = asset.media_info().unwrap();
+        assert_eq!((info.width, info.height), (Some(1024), Some(683)));
+        assert_eq!(info.color_type, Some(2));
+        assert_eq!(info.components, Some(3));
+    }
+
+    #[cfg(feature = "riff")]
+    #[test]
+    fn test_riff_media_info() {
+        let mut webp = Asset::open(fixture_path("sample1.webp")).unwrap();
+        let info = webp.media_info().unwrap();
+        assert_eq!((info.width, info.height), (Some(1050), Some(700)));
+        assert_eq!(info.codec.as_deref(), Some("VP8"));
+
+        let mut wav = Asset::open(fixture_path("sample1.wav")).unwrap();
+        let info = wav.media_info().unwrap();
+        assert_eq!(info.channels, Some(2));
+        assert_eq!(info.sample_rate, Some(8000));
+        assert_eq!(info.bit_depth, Some(16));
+        assert_eq!(info.duration.unwrap().as_secs(), 33);
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_bmff_media_info() {
+        let mut heic = Asset::open(fixture_path("sample1.heic")).unwrap();
+        let info = heic.media_info().unwrap();
+        assert_eq!((info.width, info.height), (Some(1440), Some(960)));
+        assert_eq!(info.codec.as_deref(), Some("hvc1"));
+
+        let mut avif = Asset::open(fixture_path("sample1.avif")).unwrap();
+        let info = avif.media_info().unwrap();
+        assert_eq!(info.codec.as_deref(), Some("av01"));
+        assert_eq!(info.bit_depth, Some(10));
+
+        let mut m4a = Asset::open(fixture_path("sample1.m4a")).unwrap();
+        let info = m4a.media_info().unwrap();
+        assert_eq!(info.timescale, Some(44100));
+        assert!(info.duration.unwrap() > Duration::from_secs(122));
+        assert_eq!(info.tracks.len(), 1);
+        let track = &info.tracks[0];
+        assert_eq!((track.id, track.handler.as_str()), (1, "soun"));
+        assert_eq!(track.codec.as_deref(), Some("mp4a"));
+        assert_eq!((track.channels, track.sample_rate), (Some(2), Some(44100)));
+    }
+}
+
+#[cfg(test)]
+mod capture_time_tests {
+    #[allow(unused_imports)]
+    use asset_io::{test_utils::*, Asset, CaptureTimeSource, Updates};
+    #[allow(unused_imports)]
+    use std::io::Cursor;
+
+    #[cfg(feature = "exif")]
+    #[test]
+    fn test_capture_time_from_exif() {
+        let mut asset = Asset::open(fixture_path(P1000708)).unwrap();
+        let time = asset.capture_time().unwrap().unwrap();
+        assert_eq!(time.source, CaptureTimeSource::ExifDateTimeOriginal);
+        assert_eq!(time.to_string(), "2019-06-04T10:34:34");
+        assert!(!time.has_timezone());
+    }
+
+    #[cfg(feature = "xmp")]
+    #[test]
+    fn test_capture_time_from_xmp() {
+        let mut asset = Asset::open(fixture_path(FIREFLY_TRAIN)).unwrap();
+        let xmp = br#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:CreateDate="2022-03-01T08:15:00+01:00"/></rdf:RDF></x:xmpmeta>"#;
+        let mut output = Cursor::new(Vec::new());
+        asset
+            .write(&mut output, &Updates::new().set_xmp(xmp.to_vec()))
+            .unwrap();
+
+        let mut written = Asset::from_source(output).unwrap();
+        let time = written.capture_time().unwrap().unwrap();
+        assert_eq!(time.source, CaptureTimeSource::XmpCreateDate);
+        assert_eq!(time.utc_offset_minutes, Some(60));
+        assert_eq!(time.to_string(), "2022-03-01T08:15:00+01:00");
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_capture_time_from_mvhd() {
+        let mut asset = Asset::open(fixture_path("sample1.m4a")).unwrap();
+        let time = asset.capture_time().unwrap().unwrap();
+        assert_eq!(time.source, CaptureTimeSource::MvhdCreationTime);
+        assert!(time.has_timezone());
+        assert_eq!(time.to_string(), "2024-10-01T15:40:19Z");
+    }
+
+    /// A WAV file holding one LIST chunk per `(list type, sub-chunk id, value)`
+    #[cfg(feature = "riff")]
+    fn wav_with_lists(lists: &[(&[u8; 4], &[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for (list_type, id, value) in lists {
+            let mut list = list_type.to_vec();
+            list.extend_from_slice(*id);
+            list.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            list.extend_from_slice(value);
+            if value.len() % 2 == 1 {
+                list.push(0);
+            }
+            body.extend_from_slice(b"LIST");
+            body.extend_from_slice(&(list.len() as u32).to_le_bytes());
+            body.extend_from_slice(&list);
+        }
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&body);
+        wav
+    }
+
+    #[cfg(feature = "riff")]
+    #[test]
+    fn test_capture_time_from_riff_info() {
+        let wav = wav_with_lists(&[(b"INFO", b"ICRD", b"2020-02-29\0")]);
+        let mut asset = Asset::from_source(Cursor::new(wav)).unwrap();
+        let time = asset.capture_time().unwrap().unwrap();
+        assert_eq!(time.source, CaptureTimeSource::RiffIcrd);
+        assert_eq!((time.year, time.month, time.day), (2020, 2, 29));
+        assert!(!time.has_timezone());
+    }
+
+    #[cfg(feature = "riff")]
+    #[test]
+    fn test_capture_time_falls_back_past_bad_idit() {
+        let wav = wav_with_lists(&[
+            (b"hdrl", b"IDIT", b"not a date\0"),
+            (b"INFO", b"ICRD", b"2021-07-04\0"),
+        ]);
+        let mut asset = Asset::from_source(Cursor::new(wav)).unwrap();
+        let time = asset.capture_time().unwrap().unwrap();
+        assert_eq!(time.source, CaptureTimeSource::RiffIcrd);
+        assert_eq!((time.year, time.month, time.day), (2021, 7, 4));
+    }
+}
+
+#[cfg(all(test, feature = "riff"))]
+mod webp_tests {
+    use asset_io::{test_utils::*, Asset, Updates};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_simple_webp_gains_vp8x_with_metadata() {
+        let mut asset = Asset::open(fixture_path("sample1.webp")).unwrap();
+        let xmp = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec();
+        let updates = Updates::new()
+            .set_xmp(xmp.clone())
+            .set_jumbf(b"manifest".to_vec());
+        let mut output = Cursor::new(Vec::new());
+        asset.write(&mut output, &updates).unwrap();
+
+        let data = output.get_ref().clone();
+        assert_eq!(&data[12..16], b"VP8X");
+        // XMP flag set; no ICC, EXIF or animation
+        assert_eq!(data[20], 0x04);
+
+        let mut written = Asset::from_source(output).unwrap();
+        assert_eq!(written.xmp().unwrap(), Some(xmp));
+        assert_eq!(written.jumbf().unwrap(), Some(b"manifest".to_vec()));
+        let info = written.media_info().unwrap();
+        assert_eq!((info.width, info.height), (Some(1050), Some(700)));
+    }
+}
+
+#[cfg(all(test, feature = "riff"))]
+mod wav_metadata_tests {
+    use asset_io::{test_utils::*, Asset, BextChunk, MediaType, Updates, WavMetadata};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wav_metadata_round_trip() {
+        let mut asset = Asset::open(fixture_path("sample1.wav")).unwrap();
+        let original_len = std::fs::metadata(fixture_path("sample1.wav"))
+            .unwrap()
+            .len();
+        let bext = BextChunk {
+            description: "Interview".into(),
+            originator: "asset-io".into(),
+            origination_date: "2024-05-01".into(),
+            origination_time: "12:30:00".into(),
+            time_reference: 48_000 * 3600,
+            loudness_value: -2300,
+            coding_history: "A=PCM,F=8000,W=16,M=stereo\r\n".into(),
+            ..Default::default()
+        };
+        let updates = Updates::new()
+            .set_bext(bext.clone())
+            .set_ixml("<BWFXML><PROJECT>Demo</PROJECT></BWFXML>")
+            .set_riff_info(WavMetadata::ARTIST, "Someone")
+            .set_riff_info(WavMetadata::SOFTWARE, "asset-io")
+            .set_jumbf(b"manifest".to_vec());
+        let mut output = Cursor::new(Vec::new());
+        asset.write(&mut output, &updates).unwrap();
+
+        let data = output.get_ref().clone();
+        let riff_size = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        assert_eq!(riff_size + 8, data.len());
+        assert!(data.len() as u64 > original_len);
+
+        let mut written = Asset::from_source(output).unwrap();
+        let metadata = written.wav_metadata().unwrap().unwrap();
+        assert_eq!(metadata.bext, Some(bext));
+        assert_eq!(
+            metadata.ixml.as_deref(),
+            Some("<BWFXML><PROJECT>Demo</PROJECT></BWFXML>")
+        );
+        assert_eq!(metadata.info(&WavMetadata::ARTIST), Some("Someone"));
+        assert_eq!(metadata.info(&WavMetadata::SOFTWARE), Some("asset-io"));
+        assert_eq!(written.jumbf().unwrap(), Some(b"manifest".to_vec()));
+        assert_eq!(written.media_info().unwrap().sample_rate, Some(8000));
+
+        // Removing everything drops the chunks again
+        let updates = Updates::new()
+            .remove_bext()
+            .remove_ixml()
+            .remove_riff_info(WavMetadata::ARTIST)
+            .remove_riff_info(WavMetadata::SOFTWARE);
+        let mut output = Cursor::new(Vec::new());
+        written.write(&mut output, &updates).unwrap();
+        let mut stripped = Asset::from_source(output).unwrap();
+        let metadata = stripped.wav_metadata().unwrap().unwrap();
+        assert!(metadata.bext.is_none() && metadata.ixml.is_none());
+        assert!(metadata.info(&WavMetadata::ARTIST).is_none());
+    }
+
+    /// RF64 file with a 16-bit stereo 8 kHz fmt chunk and `samples` as its data
+    fn rf64(samples: &[u8]) -> Vec<u8> {
+        let mut fmt = Vec::new();
+        fmt.extend(1u16.to_le_bytes()); // PCM
+        fmt.extend(2u16.to_le_bytes());
+        fmt.extend(8000u32.to_le_bytes());
+        fmt.extend(32000u32.to_le_bytes());
+        fmt.extend(4u16.to_le_bytes());
+        fmt.extend(16u16.to_le_bytes());
+
+        let riff_size = 4 + (8 + 28) + (8 + 16) + 8 + samples.len() as u64;
+        let mut data = b"RF64\xff\xff\xff\xffWAVEds64\x1c\0\0\0".to_vec();
+        data.extend(riff_size.to_le_bytes());
+        data.extend((samples.len() as u64).to_le_bytes());
+        data.extend((samples.len() as u64 / 4).to_le_bytes());
+        data.extend(0u32.to_le_bytes()); // no table entries
+        data.extend(b"fmt \x10\0\0\0");
+        data.extend(fmt);
+        data.extend(b"data\xff\xff\xff\xff");
+        data.extend(samples);
+        data
+    }
+
+    #[test]
+    fn test_rf64_round_trip() {
+        let samples: Vec<u8> = (0..64).collect();
+        let mut asset = Asset::from_source(Cursor::new(rf64(&samples))).unwrap();
+        assert_eq!(asset.media_type(), MediaType::Wav);
+        assert_eq!(asset.media_info().unwrap().sample_rate, Some(8000));
+
+        let updates = Updates::new()
+            .set_xmp(b"<x:xmpmeta/>".to_vec())
+            .set_jumbf(b"manifest".to_vec());
+        let mut output = Cursor::new(Vec::new());
+        asset.write(&mut output, &updates).unwrap();
+
+        // Still RF64, with the real sizes in ds64
+        let data = output.get_ref().clone();
+        assert_eq!(&data[0..12], b"RF64\xff\xff\xff\xffWAVE");
+        let riff_size = u64::from_le_bytes(data[20..28].try_into().unwrap());
+        assert_eq!(riff_size + 8, data.len() as u64);
+        let data_size = u64::from_le_bytes(data[28..36].try_into().unwrap());
+        assert_eq!(data_size, samples.len() as u64);
+
+        let mut written = Asset::from_source(output).unwrap();
+        assert_eq!(written.xmp().unwrap(), Some(b"<x:xmpmeta/>".to_vec()));
+        assert_eq!(written.jumbf().unwrap(), Some(b"manifest".to_vec()));
+        assert_eq!(written.media_info().unwrap().sample_rate, Some(8000));
+        assert!(data.windows(samples.len()).any(|w| w == samples));
+
+        // Removing the metadata leaves the original file
+        let updates = Updates::new().remove_xmp().remove_jumbf();
+        let mut output = Cursor::new(Vec::new());
+        written.write(&mut output, &updates).unwrap();
+        assert_eq!(output.into_inner(), rf64(&samples));
+    }
+}
+
+#[cfg(test)]
+mod jumbf_box_tests {
+    use asset_io::{test_utils::*, Asset, Error, ExclusionMode, SegmentKind, Updates};
+    use std::io::Cursor;
+
+    /// A JUMBF superbox with a labeled description box and `payload_size` bytes of content
+    fn labeled_jumbf(label: &str, payload_size: usize) -> Vec<u8> {
+        let mut jumd = Vec::new();
+        jumd.extend_from_slice(&((8 + 16 + 1 + label.len() + 1) as u32).to_be_bytes());
+        jumd.extend_from_slice(b"jumd");
+        jumd.extend_from_slice(&[0x42; 16]); // Content type
+        jumd.push(0x03); // Requestable, label present
+        jumd.extend_from_slice(label.as_bytes());
+        jumd.push(0);
+
+        let mut content = Vec::new();
+        content.extend_from_slice(&((8 + payload_size) as u32).to_be_bytes());
+        content.extend_from_slice(b"bidb");
+        content.extend((0..payload_size).map(|i| (i % 251) as u8));
+
+        let mut jumbf = Vec::new();
+        jumbf.extend_from_slice(&((8 + jumd.len() + content.len()) as u32).to_be_bytes());
+        jumbf.extend_from_slice(b"jumb");
+        jumbf.extend_from_slice(&jumd);
+        jumbf.extend_from_slice(&content);
+        jumbf
+    }
+
+    #[test]
+    fn test_manifest_store_tree() {
+        let mut asset = Asset::open(fixture_path(FIREFLY_TRAIN)).unwrap();
+        let store = asset.manifest_store().unwrap().expect("manifest store");
+        assert!(store.is_c2pa_store());
+        let active = store.active_manifest().expect("active manifest");
+        assert_eq!(active.label(), Some("de8eabe5-2d21-49df-80ad-e65b282309f7"));
+        assert!(active.child("c2pa.claim").is_some() || active.child("c2pa.claim.v2").is_some());
+        assert_eq!(
+            asset.active_manifest_label().unwrap().as_deref(),
+            active.label()
+        );
+        assert!(
+            asset.structure().segments()[asset.structure().c2pa_jumbf_index().unwrap()].is_c2pa()
+        );
+    }
+
+    #[test]
+    fn test_other_jumbf_survives_c2pa_signing() {
+        let mut asset = Asset::open(fixture_path(FIREFLY_TRAIN)).unwrap();
+        let boxes = asset.jumbf_boxes();
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].label.as_deref(), Some("c2pa"));
+        assert!(boxes[0].c2pa);
+        let manifest = asset.jumbf().unwrap().unwrap();
+
+        // Add a box spanning several APP11 segments next to the manifest store
+        let jpeg360 = labeled_jumbf("jpeg360", 100_000);
+        let updates = Updates::new().set_jumbf_box("jpeg360", jpeg360.clone());
+        let mut output = Cursor::new(Vec::new());
+        asset.write(&mut output, &updates).unwrap();
+        let mut with_box = Asset::from_source(output).unwrap();
+
+        let labels: Vec<_> = with_box
+            .jumbf_boxes()
+            .into_iter()
+            .map(|b| (b.label, b.c2pa))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                (Some("c2pa".to_string()), true),
+                (Some("jpeg360".to_string()), false)
+            ]
+        );
+        assert_eq!(with_box.jumbf().unwrap(), Some(manifest));
+        assert_eq!(
+            with_box.jumbf_box("jpeg360").unwrap(),
+            Some(jpeg360.clone())
+        );
+        assert!(with_box.jumbf_box("privacy").unwrap().is_none());
+
+        // Signing replaces only the manifest store; the other box is hashed
+        let placeholder = labeled_jumbf("c2pa", 2000);
+        let updates = Updates::new()
+            .set_jumbf(placeholder.clone())
+            .exclude_from_processing(vec![SegmentKind::Jumbf], ExclusionMode::DataOnly);
+        let mut output = Cursor::new(Vec::new());
+        let mut hashed = Vec::new();
+        let structure = with_box
+            .write_with_processing(
+                &mut output,
+                &updates,
+                &mut |chunk: &dyn asset_io::ProcessChunk| {
+                    hashed.extend_from_slice(chunk.data());
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert!(hashed.windows(16).any(|w| w == &jpeg360[..16]));
+        assert!(!hashed.windows(16).any(|w| w == &placeholder[..16]));
+
+        let c2pa_index = structure.c2pa_jumbf_index().unwrap();
+        assert_eq!(structure.jumbf_index_by_label("c2pa"), Some(c2pa_index));
+        let mut signed = placeholder.clone();
+        signed[100..104].copy_from_slice(b"sign");
+        structure
+            .update_segment(&mut output, SegmentKind::Jumbf, signed.clone())
+            .unwrap();
+
+        output.set_position(0);
+        let mut signed_asset = Asset::from_source(output).unwrap();
+        assert!(signed_asset.jumbf().unwrap() == Some(signed));
+        assert_eq!(signed_asset.jumbf_box("jpeg360").unwrap(), Some(jpeg360));
+
+        // Removing by label leaves the manifest store alone
+        let mut output = Cursor::new(Vec::new());
+        signed_asset
+            .write(&mut output, &Updates::new().remove_jumbf_box("jpeg360"))
+            .unwrap();
+        let stripped = Asset::from_source(output).unwrap();
+        assert_eq!(stripped.jumbf_boxes().len(), 1);
+        assert!(stripped.jumbf_boxes()[0].c2pa);
+    }
+
+    #[test]
+    fn test_jumbf_box_label_must_match_data() {
+        let mut asset = Asset::open(fixture_path(P1000708)).unwrap();
+        let updates = Updates::new().set_jumbf_box("privacy", labeled_jumbf("jpeg360", 10));
+        let mut output = Cursor::new(Vec::new());
+        assert!(asset.write(&mut output, &updates).is_err());
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_png_holds_only_the_manifest_store() {
+        let mut asset = Asset::open(fixture_path(SAMPLE1_PNG)).unwrap();
+        let updates = Updates::new().set_jumbf_box("jpeg360", labeled_jumbf("jpeg360", 10));
+        let mut output = Cursor::new(Vec::new());
+        let error = asset.write(&mut output, &updates).unwrap_err();
+        assert!(matches!(error.root(), Error::Unsupported(_)));
+
+        // Removing a labeled box fails the same way
+        let updates = Updates::new().remove_jumbf_box("jpeg360");
+        let error = asset.write(&mut output, &updates).unwrap_err();
+        assert!(matches!(error.root(), Error::Unsupported(_)));
+
+        // The "c2pa" label addresses the manifest store everywhere
+        let updates = Updates::new().set_jumbf_box("c2pa", b"manifest".to_vec());
+        let mut output = Cursor::new(Vec::new());
+        asset.write(&mut output, &updates).unwrap();
+        let mut written = Asset::from_source(output).unwrap();
+        assert_eq!(
+            written.jumbf_box("c2pa").unwrap(),
+            Some(b"manifest".to_vec())
+        );
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod async_tests {
+    use asset_io::{
+        test_utils::*, Asset, AsyncAsset, ExclusionMode, ProcessChunk, SegmentKind, Updates,
+    };
+    use std::io::Cursor;
+
+    /// Chunk id, large size flag, boundary segment and data
+    type Context = (Option<usize>, Option<bool>, Option<String>, Vec<u8>);
+
+    /// Processor output with the data of consecutive chunks of the same context joined
+    #[derive(Debug, Default, PartialEq)]
+    struct Processed(Vec<Context>);
+
+    impl Processed {
+        fn add(&mut self, chunk: &dyn ProcessChunk) {
+            let (id, large_size) = (chunk.id(), chunk.large_size());
+            let segment = chunk.segment().map(|segment| format!("{segment:?}"));
+            match self.0.last_mut() {
+                Some(last)
+                    if segment.is_none()
+                        && last.2.is_none()
+                        && (last.0, last.1) == (id, large_size) =>
+                {
+                    last.3.extend_from_slice(chunk.data())
+                }
+                _ => self
+                    .0
+                    .push((id, large_size, segment, chunk.data().to_vec())),
+            }
+        }
+    }
+
+    /// The committed fixtures plus larger media files, which exercise copying
+    /// media data during async writes
+    fn async_fixtures() -> Vec<(&'static str, Vec<u8>)> {
+        let mut names = list_all_fixtures();
+        names.extend(["sample1.avif", "sample1.heif", "sample1.m4a", "sample1.wav"]);
+        readable_fixtures(names).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_async_matches_sync() {
+        for (name, data) in async_fixtures() {
+            let mut sync = Asset::from_source(Cursor::new(data.clone())).unwrap();
+            let mut asset = AsyncAsset::from_source(Cursor::new(data)).await.unwrap();
+            assert_eq!(
+                format!("{:?}", asset.structure()),
+                format!("{:?}", sync.structure()),
+                "{name}"
+            );
+            assert_eq!(asset.xmp().await.unwrap(), sync.xmp().unwrap(), "{name}");
+            assert_eq!(
+                asset.jumbf().await.unwrap(),
+                sync.jumbf().unwrap(),
+                "{name}"
+            );
+
+            let updates = Updates::new()
+                .set_jumbf(b"placeholder".to_vec())
+                .exclude_from_processing(vec![SegmentKind::Jumbf], ExclusionMode::DataOnly);
+
+            let mut sync_chunks = Vec::new();
+            sync.read_with_processing(&updates, &mut |chunk| {
+                sync_chunks.push(chunk.to_vec());
+                Ok(())
+            })
+            .unwrap();
+            let mut chunks = Vec::new();
+            asset
+                .read_with_processing(&updates, |chunk| {
+                    chunks.push(chunk.into_data());
+                    async { Ok(()) }
+                })
+                .await
+                .unwrap();
+            assert!(chunks == sync_chunks, "{name}");
+
+            let mut sync_output = Cursor::new(Vec::new());
+            let sync_structure = sync.write(&mut sync_output, &updates).unwrap();
+            let (structure, output) = asset
+                .write(Cursor::new(Vec::new()), &updates)
+                .await
+                .unwrap();
+            assert_eq!(
+                format!("{structure:?}"),
+                format!("{sync_structure:?}"),
+                "{name}"
+            );
+            assert!(output.into_inner() == sync_output.into_inner(), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_write_to_file() {
+        let mut asset = AsyncAsset::open(fixture_path(FIREFLY_TRAIN)).await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.jpg");
+        let updates = Updates::new().set_xmp(b"<x:xmpmeta/>".to_vec());
+        asset.write_to(&path, &updates).await.unwrap();
+
+        let sync_path = dir.path().join("sync.jpg");
+        let mut sync = Asset::open(fixture_path(FIREFLY_TRAIN)).unwrap();
+        sync.write_to(&sync_path, &updates).unwrap();
+        assert!(std::fs::read(&path).unwrap() == std::fs::read(&sync_path).unwrap());
+
+        let mut written = AsyncAsset::open(&path).await.unwrap();
+        assert_eq!(written.xmp().await.unwrap(), Some(b"<x:xmpmeta/>".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_async_write_stream() {
+        let mut asset = AsyncAsset::open(fixture_path(P1000708)).await.unwrap();
+        let updates = Updates::new().set_jumbf(b"placeholder".to_vec());
+        let (structure, output) = asset.write_stream(Vec::new(), &updates).await.unwrap();
+
+        let mut sync = Asset::open(fixture_path(P1000708)).unwrap();
+        let mut expected = Cursor::new(Vec::new());
+        sync.write(&mut expected, &updates).unwrap();
+        assert!(output == expected.into_inner());
+        assert_eq!(structure.total_size, output.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_async_processor_error_stops_read() {
+        let mut asset = AsyncAsset::open(fixture_path(P1000708)).await.unwrap();
+        let mut calls = 0;
+        let result = asset
+            .read_with_processing(&Updates::new().with_chunk_size(1024), |_| {
+                calls += 1;
+                async { Err(asset_io::Error::UserCanceled) }
+            })
+            .await;
+        assert!(matches!(result, Err(asset_io::Error::UserCanceled)));
+        assert_eq!(calls, 1);
+        // The asset is still usable
+        assert!(asset.xmp().await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_async_write_with_processing_matches_sync() {
+        let updates = Updates::new()
+            .set_jumbf(vec![7u8; 5000])
+            .exclude_from_processing(vec![SegmentKind::Jumbf], ExclusionMode::DataOnly);
+        for (name, data) in async_fixtures() {
+            let mut sync = Asset::from_source(Cursor::new(data.clone())).unwrap();
+            let mut expected = Processed::default();
+            let mut sync_output = Cursor::new(Vec::new());
+            let sync_structure = sync
+                .write_with_processing(&mut sync_output, &updates, &mut |chunk| {
+                    expected.add(chunk);
+                    Ok(())
+                })
+                .unwrap();
+
+            let mut asset = AsyncAsset::from_source(Cursor::new(data)).await.unwrap();
+            let mut processed = Processed::default();
+            let (structure, output) = asset
+                .write_with_processing(Cursor::new(Vec::new()), &updates, |chunk| {
+                    processed.add(&chunk);
+                    async { Ok(()) }
+                })
+                .await
+                .unwrap();
+            assert!(processed == expected, "{name}");
+            assert!(output.into_inner() == sync_output.into_inner(), "{name}");
+            assert_eq!(
+                format!("{structure:?}"),
+                format!("{sync_structure:?}"),
+                "{name}"
+            );
+        }
+    }
+
+    #[cfg(feature = "bmff")]
+    #[tokio::test]
+    async fn test_async_mdat_chunks() {
+        let mut asset = AsyncAsset::open(fixture_path("sample1.m4a")).await.unwrap();
+        let updates = Updates::new()
+            .set_jumbf(vec![0u8; 1024])
+            .exclude_from_processing(vec![SegmentKind::Jumbf], ExclusionMode::DataOnly);
+        // Operations run on any task
+        let mdat_bytes = tokio::spawn(async move {
+            let mut mdat_bytes = 0;
+            asset
+                .write_with_processing(Cursor::new(Vec::new()), &updates, |chunk| {
+                    if chunk.id() == Some(0) {
+                        mdat_bytes += chunk.data().len();
+                    }
+                    async { Ok(()) }
+                })
+                .await
+                .unwrap();
+            mdat_bytes
+        })
+        .await
+        .unwrap();
+        assert!(mdat_bytes > 0);
+    }
+
+    #[tokio::test]
+    async fn test_async_cancelled_operation_keeps_asset() {
+        use std::future::{poll_fn, Future};
+        use std::task::Poll;
+
+        let mut asset = AsyncAsset::open(fixture_path(P1000708)).await.unwrap();
+        let updates = Updates::new().set_jumbf(b"placeholder".to_vec());
+        {
+            // Start a write into a pipe nobody reads, then drop it mid-flight
+            let (pipe, _reader) = tokio::io::duplex(64);
+            let mut write = Box::pin(asset.write_stream(pipe, &updates));
+            poll_fn(|cx| {
+                assert!(write.as_mut().poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+        }
+        assert!(asset.xmp().await.unwrap().is_some());
+        let (structure, output) = asset
+            .write(Cursor::new(Vec::new()), &updates)
+            .await
+            .unwrap();
+        assert_eq!(structure.total_size, output.into_inner().len() as u64);
+    }
+}
+
+#[cfg(test)]
+mod write_stream_tests {
+    use asset_io::{test_utils::*, Asset, Updates};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write_stream_matches_write() {
+        let updates = [
+            Updates::new(),
+            Updates::new().set_xmp(b"<x:xmpmeta/>".to_vec()),
+            Updates::new().set_jumbf(vec![7u8; 5000]).remove_xmp(),
+        ];
+        for (name, data) in readable_fixtures(list_all_fixtures()).unwrap() {
+            let mut asset = Asset::from_source(Cursor::new(data)).unwrap();
+            for updates in &updates {
+                let mut expected = Cursor::new(Vec::new());
+                let expected_structure = asset.write(&mut expected, updates).unwrap();
+
+                // A Vec is Write but not Seek
+                let mut output: Vec<u8> = Vec::new();
+                let structure = asset.write_stream(&mut output, updates).unwrap();
+                assert!(output == expected.into_inner(), "{name}");
+                assert_eq!(
+                    format!("{structure:?}"),
+                    format!("{expected_structure:?}"),
+                    "{name}"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod range_source_tests {
+    use asset_io::{test_utils::*, Asset, RangeReader, RangeSource};
+    use std::io::{self, Cursor};
+
+    /// An in-process stand-in for a server answering range requests
+    ///
+    /// Serves `head` followed by zeros up to `size`, so that very large objects
+    /// cost no memory.
+    struct LocalRangeServer {
+        head: Vec<u8>,
+        size: u64,
+    }
+
+    impl RangeSource for LocalRangeServer {
+        fn size(&mut self) -> io::Result<u64> {
+            Ok(self.size)
+        }
+
+        fn fetch(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+            let end = (offset + len as u64).min(self.size);
+            Ok((offset..end)
+                .map(|i| self.head.get(i as usize).copied().unwrap_or(0))
+                .collect())
+        }
+    }
+
+    fn serve(data: Vec<u8>) -> RangeReader<LocalRangeServer> {
+        let size = data.len() as u64;
+        RangeReader::new(LocalRangeServer { head: data, size })
+    }
+
+    #[test]
+    fn test_range_reader_matches_cursor() {
+        for (name, data) in readable_fixtures(list_all_fixtures()).unwrap() {
+            let mut expected = Asset::from_source(Cursor::new(data.clone())).unwrap();
+            let mut asset =
+                Asset::from_source(serve(data).with_block_size(4096).with_cache_blocks(8)).unwrap();
+            assert_eq!(
+                format!("{:?}", asset.structure()),
+                format!("{:?}", expected.structure()),
+                "{name}"
+            );
+            assert_eq!(asset.xmp().unwrap(), expected.xmp().unwrap(), "{name}");
+            assert_eq!(asset.jumbf().unwrap(), expected.jumbf().unwrap(), "{name}");
+
+            let updates = asset_io::Updates::new().set_jumbf(vec![1, 2, 3]);
+            let mut output = Vec::new();
+            let mut expected_output = Vec::new();
+            asset.write_stream(&mut output, &updates).unwrap();
+            expected
+                .write_stream(&mut expected_output, &updates)
+                .unwrap();
+            assert!(output == expected_output, "{name}");
+        }
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_large_mp4_manifest_costs_kilobytes() {
+        const SIZE: u64 = 20 * 1024 * 1024 * 1024;
+
+        // Sign a small MP4, then grow its trailing mdat to 20 GB
+        let mut asset = Asset::open(fixture_path("sample1.m4a")).unwrap();
+        let manifest = vec![0x5A; 40_000];
+        let mut signed = Cursor::new(Vec::new());
+        let structure = asset
+            .write(
+                &mut signed,
+                &asset_io::Updates::new().set_jumbf(manifest.clone()),
+            )
+            .unwrap();
+        let mut head = signed.into_inner();
+        let mdat = structure
+            .segments()
+            .iter()
+            .find(|s| s.is_type(asset_io::SegmentKind::ImageData))
+            .unwrap()
+            .location()
+            .offset
+            - 8;
+        head.truncate(mdat as usize);
+        head.extend_from_slice(&1u32.to_be_bytes());
+        head.extend_from_slice(b"mdat");
+        head.extend_from_slice(&(SIZE - mdat).to_be_bytes());
+
+        let server = LocalRangeServer { head, size: SIZE };
+        let mut asset = Asset::from_source(RangeReader::new(server)).unwrap();
+        assert_eq!(asset.structure().total_size, SIZE);
+        assert_eq!(asset.jumbf().unwrap(), Some(manifest));
+
+        let reader = asset.source_mut();
+        assert!(
+            reader.bytes_fetched() < 512 * 1024,
+            "fetched {} bytes",
+            reader.bytes_fetched()
+        );
+    }
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use asset_io::{test_utils::*, Asset, AssetStream, Updates};
+    use std::io::{Cursor, Read};
+
+    /// A forward-only reader handing out a few KB at a time
+    struct Pipe<'a>(&'a [u8]);
+
+    impl Read for Pipe<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(3000);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    /// Registered fixtures plus the ones only available on disk
+    fn stream_fixtures() -> Vec<(&'static str, Vec<u8>)> {
+        let on_disk = ["sample1.wav", "sample1.avif", "sample1.heif", "sample1.m4a"];
+        readable_fixtures(list_all_fixtures().into_iter().chain(on_disk)).unwrap()
+    }
+
+    #[test]
+    fn test_stream_parse_matches_asset() {
+        for (name, data) in stream_fixtures() {
+            let mut asset = Asset::from_source(Cursor::new(data.clone())).unwrap();
+            let mut reported = Vec::new();
+            let structure = AssetStream::new(Pipe(&data))
+                .with_len(data.len() as u64)
+                .with_lookback(4096)
+                .parse(|segment| {
+                    reported.push(segment.clone());
+                    Ok(())
+                })
+                .unwrap();
+            assert_eq!(
+                format!("{structure:?}"),
+                format!("{:?}", asset.structure()),
+                "{name}"
+            );
+            assert_eq!(reported.len(), structure.segments().len(), "{name}");
+
+            // XMP before the media data is reported with its data
+            if let Some(xmp) = reported.iter().find(|s| s.is_xmp() && s.ranges.len() == 1) {
+                if let Some(data) = xmp.data.get() {
+                    assert_eq!(Some(data.to_vec()), asset.xmp().unwrap(), "{name}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_transform_matches_asset() {
+        let updates = [
+            Updates::new(),
+            Updates::new().set_xmp(b"<x:xmpmeta/>".to_vec()),
+            Updates::new().set_jumbf(vec![7u8; 5000]),
+            Updates::new().remove_xmp(),
+        ];
+        for (name, data) in stream_fixtures() {
+            let mut asset = Asset::from_source(Cursor::new(data.clone())).unwrap();
+            for updates in &updates {
+                let mut expected = Cursor::new(Vec::new());
+                let expected_structure = asset.write(&mut expected, updates).unwrap();
+
+                let mut output = Vec::new();
+                let result = AssetStream::new(Pipe(&data))
+                    .with_len(data.len() as u64)
+                    .with_lookback(4096)
+                    .transform(&mut output, updates);
+                let structure = match result {
+                    Ok(structure) => structure,
+                    // The HEIF fixture keeps its XMP inside mdat
+                    Err(asset_io::Error::Unsupported(_)) if name == "sample1.heif" => continue,
+                    Err(e) => panic!("{name}: {e}"),
+                };
+                assert!(output == expected.get_ref()[..], "{name}");
+                assert_eq!(
+                    format!("{structure:?}"),
+                    format!("{expected_structure:?}"),
+                    "{name}"
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_stream_transform_reports_partial_output() {
+        let data = fixture_bytes(FIREFLY_TRAIN).unwrap();
+        let truncated = &data[..data.len() * 3 / 4];
+        let mut output = Vec::new();
+        let error = AssetStream::new(Pipe(truncated))
+            .with_len(data.len() as u64)
+            .transform(&mut output, &Updates::new())
+            .unwrap_err();
+        assert_eq!(error.kind(), asset_io::ErrorKind::PartialOutput);
+        let asset_io::Error::PartialOutput { written, source } = error.root() else {
+            panic!("{error}");
+        };
+        assert_eq!(*written, output.len() as u64);
+        assert!(*written > 0);
+        assert_eq!(source.kind(), asset_io::ErrorKind::Truncated);
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_stream_bmff_needs_len() {
+        let data = fixture_bytes(SAMPLE1_HEIC).unwrap();
+        let result = AssetStream::new(Pipe(&data)).parse(|_| Ok(()));
+        assert!(matches!(result.unwrap_err().root(), asset_io::Error::Io(_)));
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_stream_callback_error_stops_parse() {
+        let data = fixture_bytes(FIREFLY_TRAIN).unwrap();
+        let mut calls = 0;
+        let result = AssetStream::new(Pipe(&data)).parse(|_| {
+            calls += 1;
+            Err(asset_io::Error::UserCanceled)
+        });
+        assert!(matches!(result, Err(asset_io::Error::UserCanceled)));
+        assert_eq!(calls, 1);
+    }
+}
+
+#[cfg(test)]
+mod builder_tests {
+    use asset_io::{test_utils::*, Asset, AssetBuilder, ContainerKind, Updates};
+    use std::io::Cursor;
+
+    fn source(name: &str) -> Cursor<Vec<u8>> {
+        Cursor::new(fixture_bytes(name).unwrap())
+    }
+
+    #[test]
+    fn test_default_builder_matches_asset() {
+        let asset = Asset::from_source(source(FIREFLY_TRAIN)).unwrap();
+        let built = AssetBuilder::new()
+            .from_source(source(FIREFLY_TRAIN))
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", built.structure()),
+            format!("{:?}", asset.structure())
+        );
+
+        let opened = AssetBuilder::new()
+            .open(fixture_path(FIREFLY_TRAIN))
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", opened.structure().segments),
+            format!("{:?}", asset.structure().segments)
+        );
+    }
+
+    #[test]
+    fn test_segment_limits() {
+        let count = Asset::from_source(source(FIREFLY_TRAIN))
+            .unwrap()
+            .structure()
+            .segments
+            .len();
+        assert!(AssetBuilder::new()
+            .max_segment_count(count)
+            .from_source(source(FIREFLY_TRAIN))
+            .is_ok());
+        assert!(AssetBuilder::new()
+            .max_segment_count(count - 1)
+            .from_source(source(FIREFLY_TRAIN))
+            .is_err());
+
+        // JUMBF is only loaded on demand, so the size limit fails the read
+        let mut asset = AssetBuilder::new()
+            .max_segment_size(1024)
+            .from_source(source(FIREFLY_TRAIN))
+            .unwrap();
+        assert!(asset.jumbf().is_err());
+        assert!(asset.xmp().unwrap().is_some());
+
+        // Range reads on the structure follow the same limit
+        let structure = asset.structure();
+        assert_eq!(structure.max_segment_size(), 1024);
+        let mut data = source(FIREFLY_TRAIN);
+        assert!(structure
+            .read_range(&mut data, asset_io::ByteRange::new(0, 1024))
+            .is_ok());
+        assert!(structure
+            .read_range(&mut data, asset_io::ByteRange::new(0, 1025))
+            .is_err());
+    }
+
+    #[test]
+    fn test_xmp_limit_strict_and_lenient() {
+        let builder = AssetBuilder::new().max_xmp_size(100);
+        assert!(builder.clone().from_source(source(P1000708)).is_err());
+
+        let mut asset = builder.strict(false).from_source(source(P1000708)).unwrap();
+        assert_eq!(asset.xmp().unwrap(), None);
+
+        // The oversized packet is still copied on write
+        let mut output = Cursor::new(Vec::new());
+        asset.write(&mut output, &Updates::new()).unwrap();
+        let mut written = Asset::from_source(output).unwrap();
+        assert!(written.xmp().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_forced_container() {
+        let asset = AssetBuilder::new()
+            .container(ContainerKind::Jpeg)
+            .from_source(source(DESIGNER))
+            .unwrap();
+        assert_eq!(asset.container(), ContainerKind::Jpeg);
+
+        // The forced media type must belong to the forced container
+        let media_type = asset.media_type();
+        assert!(AssetBuilder::new()
+            .media_type(media_type)
+            .from_source(source(P1000708))
+            .is_ok());
+        #[cfg(feature = "png")]
+        {
+            assert!(AssetBuilder::new()
+                .container(ContainerKind::Png)
+                .from_source(source(DESIGNER))
+                .is_err());
+            assert!(AssetBuilder::new()
+                .container(ContainerKind::Png)
+                .media_type(media_type)
+                .from_source(source(DESIGNER))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_chunk_size_default() {
+        let updates = Updates::new();
+        let asset = Asset::from_source(source(P1000708)).unwrap();
+        let larger = AssetBuilder::new()
+            .chunk_size(256 * 1024)
+            .from_source(source(P1000708))
+            .unwrap();
+        assert!(larger.chunk_specs(&updates).len() < asset.chunk_specs(&updates).len());
+
+        // A chunk size in the updates takes precedence
+        let updates = updates.with_chunk_size(64 * 1024);
+        assert_eq!(
+            larger.chunk_specs(&updates).len(),
+            asset.chunk_specs(&updates).len()
+        );
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_box_depth_limit() {
+        assert!(AssetBuilder::new()
+            .from_source(source(SAMPLE1_HEIC))
+            .is_ok());
+        assert!(AssetBuilder::new()
+            .max_box_depth(1)
+            .from_source(source(SAMPLE1_HEIC))
+            .is_err());
+    }
+}
+
+#[cfg(test)]
+mod diagnostics_tests {
+    use asset_io::{test_utils::*, Asset, AssetBuilder, Severity, Updates};
+    use std::io::Cursor;
+
+    /// A fixture cut to `len` bytes
+    fn truncated(name: &str, len: usize) -> Vec<u8> {
+        let mut data = fixture_bytes(name).unwrap();
+        data.truncate(len);
+        data
+    }
+
+    /// Open `data` leniently, write it unchanged and return the output
+    fn repair(data: Vec<u8>) -> Vec<u8> {
+        let mut asset = AssetBuilder::new()
+            .strict(false)
+            .from_source(Cursor::new(data))
+            .unwrap();
+        let mut output = Cursor::new(Vec::new());
+        asset.write(&mut output, &Updates::new()).unwrap();
+        output.into_inner()
+    }
+
+    #[test]
+    fn test_jpeg_missing_eoi() {
+        let len = fixture_bytes(P1000708).unwrap().len() - 1000;
+        let data = truncated(P1000708, len);
+        assert!(Asset::from_source(Cursor::new(data.clone())).is_err());
+
+        let mut asset = AssetBuilder::new()
+            .strict(false)
+            .from_source(Cursor::new(data.clone()))
+            .unwrap();
+        let diagnostics = asset.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].path, "EOI");
+        assert_eq!(diagnostics[0].offset, len as u64);
+        assert!(asset.xmp().unwrap().is_some());
+
+        // The repaired file gets its EOI back and parses strictly
+        let repaired = repair(data);
+        assert_eq!(repaired.len(), len + 2);
+        assert_eq!(repaired[len..], [0xFF, 0xD9]);
+        let asset = Asset::from_source(Cursor::new(repaired)).unwrap();
+        assert!(asset.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_jpeg_truncated_segment() {
+        let data = truncated(DESIGNER, 300);
+        assert!(Asset::from_source(Cursor::new(data.clone())).is_err());
+
+        let asset = AssetBuilder::new()
+            .strict(false)
+            .from_source(Cursor::new(data.clone()))
+            .unwrap();
+        assert_eq!(asset.diagnostics()[0].severity, Severity::Error);
+
+        assert!(Asset::from_source(Cursor::new(repair(data))).is_ok());
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_png_truncated_chunk() {
+        let len = fixture_bytes(SAMPLE1_PNG).unwrap().len() / 2;
+        let data = truncated(SAMPLE1_PNG, len);
+        assert!(Asset::from_source(Cursor::new(data.clone())).is_err());
+
+        let asset = AssetBuilder::new()
+            .strict(false)
+            .from_source(Cursor::new(data.clone()))
+            .unwrap();
+        let paths: Vec<_> = asset
+            .diagnostics()
+            .iter()
+            .map(|d| d.path.as_str())
+            .collect();
+        assert_eq!(paths, ["IDAT", "IEND"]);
+
+        // The truncated chunk is dropped and IEND appended
+        let repaired = repair(data);
+        assert_eq!(repaired[repaired.len() - 8..][..4], *b"IEND");
+        let asset = Asset::from_source(Cursor::new(repaired)).unwrap();
+        assert!(asset.diagnostics().is_empty());
+    }
+
+    #[cfg(feature = "riff")]
+    #[test]
+    fn test_riff_truncated_chunk() {
+        // A trailing JUNK chunk cut short, with a RIFF size that counts all of it
+        let mut data = fixture_bytes(SAMPLE1_WEBP).unwrap();
+        let len = data.len();
+        data.extend_from_slice(b"JUNK");
+        data.extend_from_slice(&100u32.to_le_bytes());
+        data.extend_from_slice(&[0; 10]);
+        data[4..8].copy_from_slice(&((len + 100) as u32).to_le_bytes());
+
+        // RIFF tolerates truncation in every mode, with warnings
+        let asset = Asset::from_source(Cursor::new(data.clone())).unwrap();
+        let paths: Vec<_> = asset
+            .diagnostics()
+            .iter()
+            .map(|d| d.path.as_str())
+            .collect();
+        assert_eq!(paths, ["RIFF", "RIFF/JUNK"]);
+        assert!(asset
+            .diagnostics()
+            .iter()
+            .all(|d| d.severity == Severity::Warning));
+
+        // Writes drop the chunk and correct the RIFF size
+        let repaired = repair(data);
+        assert_eq!(repaired.len(), len);
+        let riff_size = u32::from_le_bytes(repaired[4..8].try_into().unwrap());
+        assert_eq!(riff_size as usize + 8, len);
+        let asset = Asset::from_source(Cursor::new(repaired)).unwrap();
+        assert!(asset.diagnostics().is_empty());
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_bmff_bad_box_size() {
+        let mut data = fixture_bytes(SAMPLE1_HEIC).unwrap();
+        let len = data.len();
+        data.extend_from_slice(&[0, 0, 0, 4, b'f', b'r', b'e', b'e']);
+        assert!(Asset::from_source(Cursor::new(data.clone())).is_err());
+
+        let asset = AssetBuilder::new()
+            .strict(false)
+            .from_source(Cursor::new(data.clone()))
+            .unwrap();
+        let diagnostic = &asset.diagnostics()[0];
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(
+            (diagnostic.offset, diagnostic.path.as_str()),
+            (len as u64, "/free")
+        );
+
+        // The damaged box is dropped on write
+        let repaired = repair(data);
+        assert_eq!(repaired.len(), len);
+        let asset = Asset::from_source(Cursor::new(repaired)).unwrap();
+        assert!(asset.diagnostics().is_empty());
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_bmff_box_past_end() {
+        let mut data = fixture_bytes(SAMPLE1_HEIC).unwrap();
+        let len = data.len();
+        data.extend_from_slice(&[0, 0, 0, 100, b'f', b'r', b'e', b'e', 0, 0]);
+        let error = Asset::from_source(Cursor::new(data.clone())).err().unwrap();
+        assert!(error.to_string().contains("runs past its parent"));
+        assert!(!error.to_string().contains("dropped"));
+
+        let asset = AssetBuilder::new()
+            .strict(false)
+            .from_source(Cursor::new(data.clone()))
+            .unwrap();
+        let diagnostic = &asset.diagnostics()[0];
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(
+            (diagnostic.offset, diagnostic.path.as_str()),
+            (len as u64, "/free")
+        );
+        assert!(diagnostic
+            .message
+            .ends_with("it and anything after it are dropped"));
+
+        // The box is dropped on write
+        assert_eq!(repair(data).len(), len);
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_bmff_unterminated_c2pa_purpose() {
+        const C2PA_UUID: [u8; 16] = [
+            0xd8, 0xfe, 0xc3, 0xd6, 0x1b, 0x0e, 0x48, 0x3c, 0x92, 0x97, 0x58, 0x28, 0x87, 0x7e,
+            0xc4, 0x81,
+        ];
+        let mut data = fixture_bytes(SAMPLE1_HEIC).unwrap();
+        let len = data.len();
+        data.extend_from_slice(&[0, 0, 0, 36, b'u', b'u', b'i', b'd']);
+        data.extend_from_slice(&C2PA_UUID);
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(b"manifest");
+        assert!(Asset::from_source(Cursor::new(data.clone())).is_err());
+
+        let mut asset = AssetBuilder::new()
+            .strict(false)
+            .from_source(Cursor::new(data))
+            .unwrap();
+        let diagnostic = &asset.diagnostics()[0];
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(
+            (diagnostic.offset, diagnostic.path.as_str()),
+            (len as u64, "uuid/c2pa")
+        );
+        assert_eq!(asset.jumbf().unwrap(), None);
+    }
+}
+
+#[cfg(test)]
+mod validate_tests {
+    use asset_io::{test_utils::*, Asset, Updates, ValidationReport};
+    use std::io::Cursor;
+
+    /// Validate `data` after a strict parse
+    fn validate(data: Vec<u8>) -> ValidationReport {
+        let mut asset = Asset::from_source(Cursor::new(data)).unwrap();
+        asset.validate().unwrap()
+    }
+
+    /// Paths of the errors in `report`
+    fn error_paths(report: &ValidationReport) -> Vec<&str> {
+        report.errors().map(|d| d.path.as_str()).collect()
+    }
+
+    /// Fixtures of every enabled container
+    fn fixtures() -> Vec<&'static str> {
+        let mut fixtures = vec![FIREFLY_TRAIN, P1000708, DESIGNER];
+        #[cfg(feature = "png")]
+        fixtures.extend([SAMPLE1_PNG, GREEN_CAT_PNG]);
+        #[cfg(feature = "riff")]
+        fixtures.extend([SAMPLE1_WEBP, "sample1.wav"]);
+        #[cfg(feature = "bmff")]
+        fixtures.extend([SAMPLE1_HEIC, "sample1.heif", "sample1.avif", "sample1.m4a"]);
+        fixtures
+    }
+
+    #[test]
+    fn test_fixtures_are_valid() {
+        for name in fixtures() {
+            let report = validate(std::fs::read(fixture_path(name)).unwrap());
+            assert!(report.is_valid(), "{}: {}", name, report);
+        }
+    }
+
+    #[test]
+    fn test_written_assets_are_valid() {
+        let updates = Updates::new()
+            .set_xmp(b"<x:xmpmeta/>".to_vec())
+            .set_jumbf(vec![0u8; 1000]);
+        for name in fixtures() {
+            let mut asset = Asset::open(fixture_path(name)).unwrap();
+            let mut output = Cursor::new(Vec::new());
+            asset.write(&mut output, &updates).unwrap();
+            let report = validate(output.into_inner());
+            assert!(report.is_valid(), "{}: {}", name, report);
+        }
+    }
+
+    #[test]
+    fn test_jpeg_duplicate_xmp() {
+        let mut data = fixture_bytes(P1000708).unwrap();
+        let signature = b"http://ns.adobe.com/xap/1.0/\0";
+        let start = data
+            .windows(signature.len())
+            .position(|w| w == signature)
+            .unwrap()
+            - 4;
+        let len = u16::from_be_bytes([data[start + 2], data[start + 3]]) as usize + 2;
+        let app1 = data[start..start + len].to_vec();
+        data.splice(start + len..start + len, app1);
+
+        let report = validate(data);
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(errors.len(), 1);
+        // Reported at the start of the second packet
+        let packet = start + len + 4 + signature.len();
+        assert_eq!(errors[0].offset, packet as u64);
+        assert_eq!(errors[0].message, "Duplicate XMP packet");
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_png_crc_mismatch() {
+        let mut data = fixture_bytes(SAMPLE1_PNG).unwrap();
+        // First byte of the IHDR width
+        data[16] ^= 0x01;
+
+        let report = validate(data);
+        assert_eq!(error_paths(&report), ["IHDR"]);
+        assert!(report.errors().next().unwrap().message.contains("CRC"));
+    }
+
+    #[cfg(feature = "riff")]
+    #[test]
+    fn test_webp_vp8x_flags() {
+        // Adding XMP makes the output use the extended format
+        let mut asset =
+            Asset::from_source(Cursor::new(fixture_bytes(SAMPLE1_WEBP).unwrap())).unwrap();
+        let mut output = Cursor::new(Vec::new());
+        let updates = Updates::new().set_xmp(b"<x:xmpmeta/>".to_vec());
+        asset.write(&mut output, &updates).unwrap();
+        let mut data = output.into_inner();
+        assert_eq!(&data[12..16], b"VP8X");
+        assert!(validate(data.clone()).is_valid());
+
+        // Clear the XMP flag
+        data[20] &= !0x04;
+        let report = validate(data);
+        assert_eq!(error_paths(&report), ["RIFF/VP8X"]);
+        assert!(report
+            .errors()
+            .next()
+            .unwrap()
+            .message
+            .starts_with("XMP flag"));
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_bmff_chunk_offset_outside_mdat() {
+        let mut data = std::fs::read(fixture_path("sample1.m4a")).unwrap();
+        let asset = Asset::from_source(Cursor::new(data.clone())).unwrap();
+        let tree = asset.structure().bmff_tree().unwrap();
+        let stco = tree
+            .iter()
+            .find(|b| b.box_type() == "stco")
+            .expect("stco box");
+        let path = stco.path().to_string();
+        // Point the first chunk at the ftyp box
+        let entry = stco.payload_offset() as usize + 4;
+        data[entry..entry + 4].copy_from_slice(&0u32.to_be_bytes());
+
+        let report = validate(data);
+        assert_eq!(error_paths(&report), [path.as_str()]);
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use asset_io::{test_utils::*, Asset, AssetBuilder, ContainerKind, ErrorKind, SegmentKind};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_truncated_jpeg_context() {
+        let mut data = fixture_bytes(P1000708).unwrap();
+        let len = data.len() - 1000;
+        data.truncate(len);
+
+        let error = Asset::from_source(Cursor::new(data)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Truncated);
+        assert_eq!(error.offset(), Some(len as u64));
+        let context = error.context().expect("error context");
+        assert_eq!(context.container, Some(ContainerKind::Jpeg));
+        assert_eq!(context.path.as_deref(), Some("EOI"));
+    }
+
+    #[test]
+    fn test_segment_size_limit() {
+        let error = AssetBuilder::new()
+            .max_xmp_size(16)
+            .from_source(Cursor::new(fixture_bytes(FIREFLY_TRAIN).unwrap()))
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::LimitExceeded);
+        let context = error.context().expect("error context");
+        assert_eq!(context.container, Some(ContainerKind::Jpeg));
+        assert_eq!(context.path.as_deref(), Some("XMP"));
+    }
+
+    #[test]
+    fn test_in_place_capacity() {
+        let mut asset =
+            Asset::from_source(Cursor::new(fixture_bytes(FIREFLY_TRAIN).unwrap())).unwrap();
+        let capacity = asset.structure().segments[asset.structure().c2pa_jumbf_index().unwrap()]
+            .ranges
+            .iter()
+            .map(|r| r.size as usize)
+            .sum::<usize>();
+        let error = asset
+            .update_segment_in_place(SegmentKind::Jumbf, vec![0; capacity + 1])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::CapacityExceeded);
+
+        let error = asset
+            .update_segment_in_place(SegmentKind::Exif, Vec::new())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+    }
+
+    #[cfg(feature = "bmff")]
+    #[test]
+    fn test_bmff_box_path() {
+        let error = AssetBuilder::new()
+            .max_box_depth(2)
+            .from_source(Cursor::new(fixture_bytes("sample1.m4a").unwrap()))
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::LimitExceeded);
+        let context = error.context().expect("error context");
+        assert_eq!(context.container, Some(ContainerKind::Bmff));
+        let path = context.path.as_deref().unwrap();
+        assert!(path.starts_with("/moov/"), "{}", path);
+    }
+}
+
+#[cfg(test)]
+mod detect_tests {
+    use asset_io::{sniff, sniff_source, test_utils::*, Asset, Confidence, ErrorKind, MediaType};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_fixtures_sniff_as_parsed() {
+        let mut fixtures = vec![FIREFLY_TRAIN, P1000708];
+        #[cfg(feature = "png")]
+        fixtures.push(SAMPLE1_PNG);
+        #[cfg(feature = "riff")]
+        fixtures.extend([SAMPLE1_WEBP, "sample1.wav"]);
+        #[cfg(feature = "bmff")]
+        fixtures.extend([SAMPLE1_HEIC, "sample1.heif", "sample1.avif", "sample1.m4a"]);
+
+        for name in fixtures {
+            let mut source = Cursor::new(fixture_bytes(name).unwrap());
+            let detection = sniff_source(&mut source).unwrap();
+            assert_eq!(source.position(), 0);
+
+            let media_type = Asset::from_source(source).unwrap().media_type();
+            assert_eq!(detection.best(), Some(media_type), "{}", name);
+            assert_eq!(
+                detection.confidence(media_type),
+                Some(Confidence::High),
+                "{}",
+                name
+            );
+            let ext = name.rsplit('.').next().unwrap();
+            assert!(detection.matches_extension(ext), "{}", name);
+            assert!(detection.matches_mime(media_type.to_mime()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_extension_lies() {
+        let detection = sniff(&fixture_bytes(FIREFLY_TRAIN).unwrap());
+        assert_eq!(detection.best(), Some(MediaType::Jpeg));
+        assert!(detection.matches_extension("JPEG"));
+        assert!(detection.matches_mime("image/jpeg; charset=binary"));
+        assert!(!detection.matches_extension("png"));
+        assert!(!detection.matches_extension("gif"));
+        assert!(!detection.matches_mime("video/mp4"));
+    }
+
+    #[test]
+    fn test_known_unsupported_format() {
+        let gif = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\xff\xff\xff\x00\x00\x00;".to_vec();
+        let detection = sniff(&gif);
+        assert!(!detection.is_supported());
+        assert_eq!(detection.unsupported.unwrap().name, "GIF image");
+
+        let error = Asset::from_source(Cursor::new(gif)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Unsupported);
+        assert!(error.to_string().contains("GIF image"), "{}", error);
+        let asset_io::Error::UnsupportedFormat(Some(format)) = error.root() else {
+            panic!("{error}");
+        };
+        assert_eq!(format.mime, "image/gif");
+
+        let error = Asset::from_source(Cursor::new(vec![0u8; 32]))
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.root(),
+            asset_io::Error::UnsupportedFormat(None)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod custom_container_tests {
+    use asset_io::{
+        register_container, sniff, Asset, AssetBuilder, Confidence, ContainerKind,
+        CustomContainerIO, CustomFormat, Error, ExclusionMode, Limits, MediaType, MetadataUpdate,
+        ReadSeek, Result, Segment, SegmentKind, Structure, Updates,
+    };
+    use std::io::{Cursor, SeekFrom, Write};
+
+    static ACME: CustomFormat = CustomFormat::new("acme", &["application/x-acme"], &["acme"]);
+
+    /// "ACME", then length-prefixed XMP and JUMBF, then the payload
+    struct AcmeIO;
+
+    impl AcmeIO {
+        fn structure(xmp_len: u64, jumbf_len: u64, total_size: u64) -> Structure {
+            let mut structure =
+                Structure::new(ContainerKind::Custom(&ACME), MediaType::Custom(&ACME));
+            if xmp_len > 0 {
+                structure.add_segment(Segment::new(8, xmp_len, SegmentKind::Xmp, None));
+            }
+            if jumbf_len > 0 {
+                let offset = 12 + xmp_len;
+                structure.add_segment(Segment::new(offset, jumbf_len, SegmentKind::Jumbf, None));
+            }
+            structure.total_size = total_size;
+            structure
+        }
+
+        fn len(structure: &Structure, kind: SegmentKind) -> u64 {
+            structure
+                .segments()
+                .iter()
+                .find(|s| s.kind == kind)
+                .map_or(0, |s| s.location().size)
+        }
+
+        fn resolve(
+            update: &MetadataUpdate,
+            structure: &Structure,
+            source: &mut dyn ReadSeek,
+            kind: SegmentKind,
+        ) -> Result<Vec<u8>> {
+            Ok(match update {
+                MetadataUpdate::Keep => {
+                    match structure.segments().iter().find(|s| s.kind == kind) {
+                        Some(segment) => {
+                            structure.read_range(&mut &mut *source, segment.location())?
+                        }
+                        None => Vec::new(),
+                    }
+                }
+                MetadataUpdate::Remove => Vec::new(),
+                MetadataUpdate::Set(data) => data.clone(),
+            })
+        }
+    }
+
+    impl CustomContainerIO for AcmeIO {
+        fn format(&self) -> &'static CustomFormat {
+            &ACME
+        }
+
+        fn detect(&self, header: &[u8]) -> bool {
+            header.starts_with(b"ACME")
+        }
+
+        fn parse(&self, source: &mut dyn ReadSeek, limits: &Limits) -> Result<Structure> {
+            let mut header = [0u8; 8];
+            source.seek(SeekFrom::Start(0))?;
+            source.read_exact(&mut header)?;
+            let xmp_len = u32::from_be_bytes(header[4..8].try_into().unwrap()) as u64;
+            limits.check_segment_size(8, xmp_len, "ACME XMP")?;
+            let mut len = [0u8; 4];
+            source.seek(SeekFrom::Start(8 + xmp_len))?;
+            source.read_exact(&mut len)?;
+            let jumbf_len = u32::from_be_bytes(len) as u64;
+            let total_size = source.seek(SeekFrom::End(0))?;
+            Ok(Self::structure(xmp_len, jumbf_len, total_size))
+        }
+
+        fn write(
+            &self,
+            structure: &Structure,
+            source: &mut dyn ReadSeek,
+            writer: &mut dyn Write,
+            updates: &Updates,
+        ) -> Result<()> {
+            let xmp = Self::resolve(updates.xmp(), structure, source, SegmentKind::Xmp)?;
+            let jumbf = Self::resolve(updates.jumbf(), structure, source, SegmentKind::Jumbf)?;
+            writer.write_all(b"ACME")?;
+            writer.write_all(&(xmp.len() as u32).to_be_bytes())?;
+            writer.write_all(&xmp)?;
+            writer.write_all(&(jumbf.len() as u32).to_be_bytes())?;
+            writer.write_all(&jumbf)?;
+            let payload = 16
+                + Self::len(structure, SegmentKind::Xmp)
+                + Self::len(structure, SegmentKind::Jumbf)
+                - 4;
+            source.seek(SeekFrom::Start(payload))?;
+            std::io::copy(source, writer)?;
+            Ok(())
+        }
+
+        fn calculate_updated_structure(
+            &self,
+            source_structure: &Structure,
+            updates: &Updates,
+        ) -> Result<Structure> {
+            let updated_len = |update: &MetadataUpdate, kind| match update {
+                MetadataUpdate::Keep => Self::len(source_structure, kind),
+                MetadataUpdate::Remove => 0,
+                MetadataUpdate::Set(data) => data.len() as u64,
+            };
+            let xmp_len = updated_len(updates.xmp(), SegmentKind::Xmp);
+            let jumbf_len = updated_len(updates.jumbf(), SegmentKind::Jumbf);
+            let payload = source_structure.total_size
+                - Self::len(source_structure, SegmentKind::Xmp)
+                - Self::len(source_structure, SegmentKind::Jumbf);
+            Ok(Self::structure(
+                xmp_len,
+                jumbf_len,
+                payload + xmp_len + jumbf_len,
+            ))
+        }
+
+        fn exclusion_range_for_segment(
+            &self,
+            structure: &Structure,
+            kind: SegmentKind,
+            mode: ExclusionMode,
+        ) -> Option<(u64, u64)> {
+            let segment = structure.segments().iter().find(|s| s.kind == kind)?;
+            let location = segment.location();
+            Some(match mode {
+                ExclusionMode::DataOnly => (location.offset, location.size),
+                // Include the length prefix
+                ExclusionMode::EntireSegment => (location.offset - 4, location.size + 4),
+            })
+        }
+    }
+
+    fn acme(xmp: &[u8], jumbf: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut data = b"ACME".to_vec();
+        data.extend((xmp.len() as u32).to_be_bytes());
+        data.extend(xmp);
+        data.extend((jumbf.len() as u32).to_be_bytes());
+        data.extend(jumbf);
+        data.extend(payload);
+        data
+    }
+
+    #[test]
+    fn test_custom_container_read() {
+        register_container(AcmeIO);
+        let data = acme(b"<x:xmpmeta/>", b"manifest", b"pixels");
+
+        let detection = sniff(&data);
+        assert_eq!(detection.best(), Some(MediaType::Custom(&ACME)));
+        assert_eq!(
+            detection.confidence(MediaType::Custom(&ACME)),
+            Some(Confidence::High)
+        );
+        assert!(detection.matches_extension("acme"));
+        assert_eq!(MediaType::Custom(&ACME).to_mime(), "application/x-acme");
+
+        let mut asset = Asset::from_source(Cursor::new(data)).unwrap();
+        assert_eq!(asset.container(), ContainerKind::Custom(&ACME));
+        assert_eq!(asset.xmp().unwrap().unwrap(), b"<x:xmpmeta/>");
+        assert_eq!(asset.jumbf().unwrap().unwrap(), b"manifest");
+        assert_eq!(
+            asset
+                .structure()
+                .exclusion_range_for_segment(SegmentKind::Jumbf),
+            Some((24, 8))
+        );
+    }
+
+    #[test]
+    fn test_custom_container_write() {
+        register_container(AcmeIO);
+        let mut asset =
+            Asset::from_source(Cursor::new(acme(b"<x:xmpmeta/>", b"", b"pixels"))).unwrap();
+
+        let updates = Updates::new()
+            .set_jumbf(b"new manifest".to_vec())
+            .remove_xmp();
+        let mut output = Cursor::new(Vec::new());
+        let mut processed = Vec::new();
+        let structure = asset
+            .write_with_processing(
+                &mut output,
+                &updates,
+                &mut |chunk: &dyn asset_io::ProcessChunk| {
+                    processed.extend_from_slice(chunk.data());
+                    Ok(())
+                },
+            )
+            .unwrap();
+        let output = output.into_inner();
+        assert_eq!(output, acme(b"", b"new manifest", b"pixels"));
+        assert_eq!(processed, output);
+        assert_eq!(structure.total_size, output.len() as u64);
+
+        let mut written = Asset::from_source(Cursor::new(output)).unwrap();
+        assert_eq!(written.xmp().unwrap(), None);
+        assert_eq!(written.jumbf().unwrap().unwrap(), b"new manifest");
+
+        // Only the C2PA manifest store has a place in the format
+        let updates = Updates::new().set_jumbf_box("other", b"box".to_vec());
+        assert!(asset.write(&mut Cursor::new(Vec::new()), &updates).is_err());
+    }
+
+    #[test]
+    fn test_custom_container_write_excludes_jumbf() {
+        register_container(AcmeIO);
+        let mut asset =
+            Asset::from_source(Cursor::new(acme(b"<x:xmpmeta/>", b"", b"pixels"))).unwrap();
+
+        // DataOnly hashes the length prefix, EntireSegment leaves it out too
+        let jumbf_offset = 8 + 12 + 4;
+        for (mode, excluded_from) in [
+            (ExclusionMode::DataOnly, jumbf_offset),
+            (ExclusionMode::EntireSegment, jumbf_offset - 4),
+        ] {
+            let updates = Updates::new()
+                .set_jumbf(b"placeholder".to_vec())
+                .exclude_from_processing(vec![SegmentKind::Jumbf], mode);
+            let mut output = Cursor::new(Vec::new());
+            let mut processed = Vec::new();
+            asset
+                .write_with_processing(
+                    &mut output,
+                    &updates,
+                    &mut |chunk: &dyn asset_io::ProcessChunk| {
+                        processed.extend_from_slice(chunk.data());
+                        Ok(())
+                    },
+                )
+                .unwrap();
+            let output = output.into_inner();
+            assert_eq!(output, acme(b"<x:xmpmeta/>", b"placeholder", b"pixels"));
+
+            let mut expected = output[..excluded_from].to_vec();
+            expected.extend_from_slice(&output[jumbf_offset + b"placeholder".len()..]);
+            assert_eq!(processed, expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_custom_container_parse_gets_limits() {
+        register_container(AcmeIO);
+        let data = acme(b"<x:xmpmeta/>", b"manifest", b"pixels");
+        let error = AssetBuilder::new()
+            .max_segment_size(8)
+            .from_source(Cursor::new(data))
+            .err()
+            .unwrap();
+        assert!(matches!(error.root(), Error::LimitExceeded { .. }));
+    }
+}
@@ -3,6 +3,9 @@
 //! These tests verify that safety limits and checks are in place.
 //! Comprehensive testing should be done with fuzzing (cargo-fuzz).
 
+// These tests intentionally document compile-time limits with plain assertions.
+#![allow(clippy::assertions_on_constants)]
+
 #[cfg(feature = "memory-mapped")]
 use asset_io::ByteRange;
 